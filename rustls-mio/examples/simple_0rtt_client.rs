@@ -4,13 +4,12 @@ use std::io::{stdout, Read, Write};
 use std::net::TcpStream;
 
 use rustls;
-use webpki;
 use webpki_roots;
 use env_logger;
 
 fn start_session(config: &Arc<rustls::ClientConfig>, domain_name: &str) {
-    let dns_name = webpki::DNSNameRef::try_from_ascii_str(domain_name).unwrap();
-    let mut sess = rustls::ClientSession::new(config, dns_name);
+    let server_name = rustls::ServerName::try_from_ascii_str(domain_name).unwrap();
+    let mut sess = rustls::ClientSession::new(config, server_name);
     let mut sock = TcpStream::connect(format!("{}:443", domain_name)).unwrap();
     sock.set_nodelay(true).unwrap();
     let request = format!(
@@ -4,7 +4,6 @@ use std::net::TcpStream;
 use std::io::{Read, Write, stdout};
 
 use rustls;
-use webpki;
 use webpki_roots;
 
 use rustls::Session;
@@ -13,8 +12,8 @@ fn main() {
     let mut config = rustls::ClientConfig::new();
     config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
 
-    let dns_name = webpki::DNSNameRef::try_from_ascii_str("google.com").unwrap();
-    let mut sess = rustls::ClientSession::new(&Arc::new(config), dns_name);
+    let server_name = rustls::ServerName::try_from_ascii_str("google.com").unwrap();
+    let mut sess = rustls::ClientSession::new(&Arc::new(config), server_name);
     let mut sock = TcpStream::connect("google.com:443").unwrap();
     let mut tls = rustls::Stream::new(&mut sess, &mut sock);
     tls.write(concat!("GET / HTTP/1.1\r\n",
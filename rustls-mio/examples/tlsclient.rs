@@ -19,7 +19,6 @@ extern crate serde_derive;
 use docopt::Docopt;
 
 use rustls;
-use webpki;
 use webpki_roots;
 use ct_logs;
 
@@ -80,12 +79,12 @@ impl io::Read for TlsClient {
 }
 
 impl TlsClient {
-    fn new(sock: TcpStream, hostname: webpki::DNSNameRef<'_>, cfg: Arc<rustls::ClientConfig>) -> TlsClient {
+    fn new(sock: TcpStream, server_name: rustls::ServerName, cfg: Arc<rustls::ClientConfig>) -> TlsClient {
         TlsClient {
             socket: sock,
             closing: false,
             clean_closure: false,
-            tls_session: rustls::ClientSession::new(&cfg, hostname),
+            tls_session: rustls::ClientSession::new(&cfg, server_name),
         }
     }
 
@@ -427,16 +426,15 @@ fn load_key_and_cert(config: &mut rustls::ClientConfig, keyfile: &str, certsfile
 #[cfg(feature = "dangerous_configuration")]
 mod danger {
     use super::rustls;
-    use webpki;
-
     pub struct NoCertificateVerification {}
 
     impl rustls::ServerCertVerifier for NoCertificateVerification {
         fn verify_server_cert(&self,
                               _roots: &rustls::RootCertStore,
                               _presented_certs: &[rustls::Certificate],
-                              _dns_name: webpki::DNSNameRef<'_>,
-                              _ocsp: &[u8]) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+                              _server_name: &rustls::ServerName,
+                              _ocsp: &[u8],
+                              _now: webpki::Time) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
             Ok(rustls::ServerCertVerified::assertion())
         }
     }
@@ -539,8 +537,8 @@ fn main() {
     let config = make_config(&args);
 
     let sock = TcpStream::connect(&addr).unwrap();
-    let dns_name = webpki::DNSNameRef::try_from_ascii_str(&args.arg_hostname).unwrap();
-    let mut tlsclient = TlsClient::new(sock, dns_name, config);
+    let server_name = rustls::ServerName::try_from_ascii_str(&args.arg_hostname).unwrap();
+    let mut tlsclient = TlsClient::new(sock, server_name, config);
 
     if args.flag_http {
         let httpreq = format!("GET / HTTP/1.0\r\nHost: {}\r\nConnection: \
@@ -0,0 +1,835 @@
+//! # rustls-pkcs11
+//! `SigningKey` implementations for rustls, backed by private keys
+//! held on a PKCS#11 token: an HSM, a smartcard, or SoftHSM.
+//!
+//! The private key never leaves the token.  rustls asks for a
+//! signature during each handshake where it's needed, and this
+//! crate makes it with `C_Sign`, using the mechanism which matches
+//! the `SignatureScheme` negotiated with the peer.
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! # fn chain() -> Vec<rustls::Certificate> { vec![] }
+//! let module = rustls_pkcs11::Module::load("/usr/lib/softhsm/libsofthsm2.so")
+//!     .unwrap();
+//! let key = rustls_pkcs11::Pkcs11SigningKey::new(&module, 0, Some("1234"), "my-key")
+//!     .unwrap();
+//!
+//! let mut config = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+//! config.set_single_cert_with_signing_key(chain(), Box::new(key));
+//! ```
+//!
+//! RSA (PKCS#1 and PSS), ECDSA (on P-256 and P-384) and Ed25519
+//! keys are supported.  Modules are loaded with `dlopen`, so this
+//! crate is only available on unix-like platforms.
+
+use rustls::sign::{SigningKey, Signer};
+use rustls::internal::msgs::enums::SignatureAlgorithm;
+use rustls::{SignatureScheme, TLSError};
+use ring::digest;
+
+use std::ffi::CString;
+use std::mem;
+use std::os::raw::{c_char, c_ulong, c_void};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+const CKR_OK: c_ulong = 0x0;
+const CKR_USER_ALREADY_LOGGED_IN: c_ulong = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: c_ulong = 0x191;
+
+const CKF_RW_SESSION: c_ulong = 0x2;
+const CKF_SERIAL_SESSION: c_ulong = 0x4;
+const CKF_OS_LOCKING_OK: c_ulong = 0x2;
+const CKU_USER: c_ulong = 1;
+
+const CKA_CLASS: c_ulong = 0x0;
+const CKA_LABEL: c_ulong = 0x3;
+const CKA_KEY_TYPE: c_ulong = 0x100;
+const CKA_EC_PARAMS: c_ulong = 0x180;
+
+const CKO_PRIVATE_KEY: c_ulong = 0x3;
+
+const CKK_RSA: c_ulong = 0x0;
+const CKK_EC: c_ulong = 0x3;
+const CKK_EC_EDWARDS: c_ulong = 0x40;
+
+const CKM_RSA_PKCS: c_ulong = 0x1;
+const CKM_RSA_PKCS_PSS: c_ulong = 0xd;
+const CKM_ECDSA: c_ulong = 0x1041;
+const CKM_EDDSA: c_ulong = 0x1057;
+const CKM_SHA256: c_ulong = 0x250;
+const CKM_SHA384: c_ulong = 0x260;
+const CKM_SHA512: c_ulong = 0x270;
+
+const CKG_MGF1_SHA256: c_ulong = 0x2;
+const CKG_MGF1_SHA384: c_ulong = 0x3;
+const CKG_MGF1_SHA512: c_ulong = 0x4;
+
+/// DER-encoded `CKA_EC_PARAMS` for the curves we support: each is
+/// a namedCurve OID, or for Ed25519 also the PrintableString form
+/// which some tokens use.
+const PRIME256V1: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const SECP384R1: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22];
+const ED25519_OID: &[u8] = &[0x06, 0x03, 0x2b, 0x65, 0x70];
+const ED25519_NAME: &[u8] = b"\x13\x0cedwards25519";
+
+/// DER-encoded DigestInfo prefixes for RSA PKCS#1 signatures.  The
+/// token pads, but doesn't hash or encode, for `CKM_RSA_PKCS`.
+const SHA256_DIGESTINFO: &[u8] = &[0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01,
+                                   0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00, 0x04, 0x20];
+const SHA384_DIGESTINFO: &[u8] = &[0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01,
+                                   0x65, 0x03, 0x04, 0x02, 0x02, 0x05, 0x00, 0x04, 0x30];
+const SHA512_DIGESTINFO: &[u8] = &[0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01,
+                                   0x65, 0x03, 0x04, 0x02, 0x03, 0x05, 0x00, 0x04, 0x40];
+
+static ALL_RSA_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA512,
+    SignatureScheme::RSA_PKCS1_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
+
+#[repr(C)]
+struct Attribute {
+    typ: c_ulong,
+    value: *mut c_void,
+    value_len: c_ulong,
+}
+
+#[repr(C)]
+struct Mechanism {
+    mechanism: c_ulong,
+    parameter: *const c_void,
+    parameter_len: c_ulong,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct RsaPssParams {
+    hash_alg: c_ulong,
+    mgf: c_ulong,
+    salt_len: c_ulong,
+}
+
+#[repr(C)]
+struct InitializeArgs {
+    create_mutex: *const c_void,
+    destroy_mutex: *const c_void,
+    lock_mutex: *const c_void,
+    unlock_mutex: *const c_void,
+    flags: c_ulong,
+    reserved: *mut c_void,
+}
+
+type GetFunctionListFn = unsafe extern "C" fn(*mut *const FunctionList) -> c_ulong;
+type InitializeFn = unsafe extern "C" fn(*mut c_void) -> c_ulong;
+type FinalizeFn = unsafe extern "C" fn(*mut c_void) -> c_ulong;
+type OpenSessionFn = unsafe extern "C" fn(c_ulong, c_ulong, *mut c_void, *const c_void,
+                                          *mut c_ulong) -> c_ulong;
+type CloseSessionFn = unsafe extern "C" fn(c_ulong) -> c_ulong;
+type LoginFn = unsafe extern "C" fn(c_ulong, c_ulong, *const u8, c_ulong) -> c_ulong;
+type GetAttributeValueFn = unsafe extern "C" fn(c_ulong, c_ulong, *mut Attribute,
+                                                c_ulong) -> c_ulong;
+type FindObjectsInitFn = unsafe extern "C" fn(c_ulong, *const Attribute, c_ulong) -> c_ulong;
+type FindObjectsFn = unsafe extern "C" fn(c_ulong, *mut c_ulong, c_ulong,
+                                          *mut c_ulong) -> c_ulong;
+type FindObjectsFinalFn = unsafe extern "C" fn(c_ulong) -> c_ulong;
+type SignInitFn = unsafe extern "C" fn(c_ulong, *const Mechanism, c_ulong) -> c_ulong;
+type SignFn = unsafe extern "C" fn(c_ulong, *const u8, c_ulong, *mut u8,
+                                   *mut c_ulong) -> c_ulong;
+
+/// A prefix of `CK_FUNCTION_LIST`, up to `C_Sign`.  The functions we
+/// don't call are left as opaque pointers.
+#[repr(C)]
+struct FunctionList {
+    version: [u8; 2],
+    initialize: InitializeFn,
+    finalize: FinalizeFn,
+    _get_info_to_set_pin: [*const c_void; 10],
+    open_session: OpenSessionFn,
+    close_session: CloseSessionFn,
+    _close_all_sessions_to_set_operation_state: [*const c_void; 4],
+    login: LoginFn,
+    _logout_to_get_object_size: [*const c_void; 5],
+    get_attribute_value: GetAttributeValueFn,
+    _set_attribute_value: *const c_void,
+    find_objects_init: FindObjectsInitFn,
+    find_objects: FindObjectsFn,
+    find_objects_final: FindObjectsFinalFn,
+    _encrypt_init_to_digest_final: [*const c_void; 13],
+    sign_init: SignInitFn,
+    sign: SignFn,
+}
+
+fn error(function: &str, rv: c_ulong) -> TLSError {
+    TLSError::General(format!("PKCS#11 {} failed: 0x{:x}", function, rv))
+}
+
+fn check(function: &str, rv: c_ulong) -> Result<(), TLSError> {
+    if rv == CKR_OK {
+        Ok(())
+    } else {
+        Err(error(function, rv))
+    }
+}
+
+/// A loaded and initialized PKCS#11 module.
+pub struct Module {
+    library: *mut c_void,
+    functions: *const FunctionList,
+    finalize: bool,
+}
+
+// We initialize modules with CKF_OS_LOCKING_OK, so they may be
+// called from any thread.
+unsafe impl Send for Module {}
+unsafe impl Sync for Module {}
+
+impl Module {
+    /// Load the PKCS#11 module at `path` (for example,
+    /// `libsofthsm2.so`), and initialize it.
+    pub fn load(path: &str) -> Result<Arc<Module>, TLSError> {
+        let c_path = CString::new(path)
+            .map_err(|_| TLSError::General("invalid PKCS#11 module path".to_string()))?;
+
+        let library = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if library.is_null() {
+            return Err(TLSError::General(format!("cannot load PKCS#11 module {}", path)));
+        }
+
+        let symbol = unsafe {
+            libc::dlsym(library, b"C_GetFunctionList\0".as_ptr() as *const c_char)
+        };
+        if symbol.is_null() {
+            unsafe { libc::dlclose(library) };
+            return Err(TLSError::General(format!("{} is not a PKCS#11 module", path)));
+        }
+
+        let get_function_list: GetFunctionListFn = unsafe { mem::transmute(symbol) };
+        let mut functions = ptr::null();
+        let rv = unsafe { get_function_list(&mut functions) };
+        if rv != CKR_OK || functions.is_null() {
+            unsafe { libc::dlclose(library) };
+            return Err(error("C_GetFunctionList", rv));
+        }
+
+        Module::initialize(library, functions)
+            .map(Arc::new)
+    }
+
+    fn initialize(library: *mut c_void,
+                  functions: *const FunctionList) -> Result<Module, TLSError> {
+        let mut args = InitializeArgs {
+            create_mutex: ptr::null(),
+            destroy_mutex: ptr::null(),
+            lock_mutex: ptr::null(),
+            unlock_mutex: ptr::null(),
+            flags: CKF_OS_LOCKING_OK,
+            reserved: ptr::null_mut(),
+        };
+
+        let rv = unsafe {
+            ((*functions).initialize)(&mut args as *mut InitializeArgs as *mut c_void)
+        };
+
+        match rv {
+            CKR_OK => Ok(Module { library, functions, finalize: true }),
+
+            // Someone else in this process is using the module: leave
+            // them to finalize it.
+            CKR_CRYPTOKI_ALREADY_INITIALIZED => Ok(Module { library, functions, finalize: false }),
+
+            _ => {
+                if !library.is_null() {
+                    unsafe { libc::dlclose(library) };
+                }
+                Err(error("C_Initialize", rv))
+            }
+        }
+    }
+
+    fn functions(&self) -> &FunctionList {
+        unsafe { &*self.functions }
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        if self.finalize {
+            unsafe { (self.functions().finalize)(ptr::null_mut()) };
+        }
+
+        if !self.library.is_null() {
+            unsafe { libc::dlclose(self.library) };
+        }
+    }
+}
+
+/// A session on a token.  A PKCS#11 session can only do one
+/// operation at a time, so signatures are serialised.
+struct Session {
+    module: Arc<Module>,
+    handle: Mutex<c_ulong>,
+}
+
+impl Session {
+    fn open(module: &Arc<Module>, slot: u64, pin: Option<&str>) -> Result<Session, TLSError> {
+        let f = module.functions();
+        let mut handle = 0;
+        check("C_OpenSession", unsafe {
+            (f.open_session)(slot as c_ulong, CKF_SERIAL_SESSION | CKF_RW_SESSION,
+                             ptr::null_mut(), ptr::null(), &mut handle)
+        })?;
+
+        let session = Session { module: module.clone(), handle: Mutex::new(handle) };
+
+        if let Some(pin) = pin {
+            // Logging in applies to all our sessions on the token.
+            let rv = unsafe { (f.login)(handle, CKU_USER, pin.as_ptr(), pin.len() as c_ulong) };
+            if rv != CKR_USER_ALREADY_LOGGED_IN {
+                check("C_Login", rv)?;
+            }
+        }
+
+        Ok(session)
+    }
+
+    fn find_private_key(&self, label: &str) -> Result<c_ulong, TLSError> {
+        let f = self.module.functions();
+        let handle = self.handle.lock().unwrap();
+
+        let mut class = CKO_PRIVATE_KEY;
+        let template = [
+            Attribute {
+                typ: CKA_CLASS,
+                value: &mut class as *mut c_ulong as *mut c_void,
+                value_len: mem::size_of::<c_ulong>() as c_ulong,
+            },
+            Attribute {
+                typ: CKA_LABEL,
+                value: label.as_ptr() as *mut c_void,
+                value_len: label.len() as c_ulong,
+            },
+        ];
+
+        let mut objects = [0; 2];
+        let mut count = 0;
+        unsafe {
+            check("C_FindObjectsInit",
+                  (f.find_objects_init)(*handle, template.as_ptr(), template.len() as c_ulong))?;
+            let rv = (f.find_objects)(*handle, objects.as_mut_ptr(), objects.len() as c_ulong,
+                                      &mut count);
+            check("C_FindObjectsFinal", (f.find_objects_final)(*handle))?;
+            check("C_FindObjects", rv)?;
+        }
+
+        match count {
+            1 => Ok(objects[0]),
+            0 => Err(TLSError::General(format!("no PKCS#11 private key labelled {:?}", label))),
+            _ => Err(TLSError::General(format!("several PKCS#11 private keys labelled {:?}", label))),
+        }
+    }
+
+    fn get_attribute(&self, object: c_ulong, typ: c_ulong) -> Result<Vec<u8>, TLSError> {
+        let f = self.module.functions();
+        let handle = self.handle.lock().unwrap();
+
+        let mut attr = Attribute { typ, value: ptr::null_mut(), value_len: 0 };
+        check("C_GetAttributeValue", unsafe {
+            (f.get_attribute_value)(*handle, object, &mut attr, 1)
+        })?;
+
+        let mut value = vec![0u8; attr.value_len as usize];
+        attr.value = value.as_mut_ptr() as *mut c_void;
+        check("C_GetAttributeValue", unsafe {
+            (f.get_attribute_value)(*handle, object, &mut attr, 1)
+        })?;
+
+        value.truncate(attr.value_len as usize);
+        Ok(value)
+    }
+
+    fn get_ulong_attribute(&self, object: c_ulong, typ: c_ulong) -> Result<c_ulong, TLSError> {
+        let value = self.get_attribute(object, typ)?;
+        if value.len() != mem::size_of::<c_ulong>() {
+            return Err(TLSError::General("PKCS#11 attribute has wrong length".to_string()));
+        }
+
+        let mut bytes = [0u8; mem::size_of::<c_ulong>()];
+        bytes.copy_from_slice(&value);
+        Ok(c_ulong::from_ne_bytes(bytes))
+    }
+
+    fn sign(&self,
+            key: c_ulong,
+            mechanism: c_ulong,
+            pss: Option<RsaPssParams>,
+            data: &[u8]) -> Result<Vec<u8>, TLSError> {
+        let f = self.module.functions();
+        let handle = self.handle.lock().unwrap();
+
+        let mech = match pss {
+            Some(ref params) => Mechanism {
+                mechanism,
+                parameter: params as *const RsaPssParams as *const c_void,
+                parameter_len: mem::size_of::<RsaPssParams>() as c_ulong,
+            },
+            None => Mechanism { mechanism, parameter: ptr::null(), parameter_len: 0 },
+        };
+
+        unsafe {
+            check("C_SignInit", (f.sign_init)(*handle, &mech, key))?;
+
+            // The first call only finds the length.
+            let mut len = 0;
+            check("C_Sign", (f.sign)(*handle, data.as_ptr(), data.len() as c_ulong,
+                                     ptr::null_mut(), &mut len))?;
+
+            let mut sig = vec![0u8; len as usize];
+            check("C_Sign", (f.sign)(*handle, data.as_ptr(), data.len() as c_ulong,
+                                     sig.as_mut_ptr(), &mut len))?;
+            sig.truncate(len as usize);
+            Ok(sig)
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let handle = *self.handle.lock().unwrap();
+        unsafe { (self.module.functions().close_session)(handle) };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum KeyKind {
+    RSA,
+    ECDSA(SignatureScheme),
+    Ed25519,
+}
+
+/// A `SigningKey` for a private key on a PKCS#11 token.
+pub struct Pkcs11SigningKey {
+    session: Arc<Session>,
+    key: c_ulong,
+    kind: KeyKind,
+}
+
+impl Pkcs11SigningKey {
+    /// Open a session on `slot` of `module`, log in with `pin` if
+    /// given, and use the private key labelled `label`.
+    ///
+    /// This fails if there isn't exactly one such key, or if it's of
+    /// a type we don't support.
+    pub fn new(module: &Arc<Module>,
+               slot: u64,
+               pin: Option<&str>,
+               label: &str) -> Result<Pkcs11SigningKey, TLSError> {
+        let session = Session::open(module, slot, pin)?;
+        let key = session.find_private_key(label)?;
+
+        let kind = match session.get_ulong_attribute(key, CKA_KEY_TYPE)? {
+            CKK_RSA => KeyKind::RSA,
+            CKK_EC => match session.get_attribute(key, CKA_EC_PARAMS)?.as_slice() {
+                PRIME256V1 => KeyKind::ECDSA(SignatureScheme::ECDSA_NISTP256_SHA256),
+                SECP384R1 => KeyKind::ECDSA(SignatureScheme::ECDSA_NISTP384_SHA384),
+                _ => return Err(TLSError::General("unsupported PKCS#11 EC key curve".to_string())),
+            },
+            CKK_EC_EDWARDS => match session.get_attribute(key, CKA_EC_PARAMS)?.as_slice() {
+                ED25519_OID | ED25519_NAME => KeyKind::Ed25519,
+                _ => return Err(TLSError::General("unsupported PKCS#11 EdDSA key curve".to_string())),
+            },
+            typ => return Err(TLSError::General(format!("unsupported PKCS#11 key type 0x{:x}", typ))),
+        };
+
+        Ok(Pkcs11SigningKey { session: Arc::new(session), key, kind })
+    }
+
+    fn signer(&self, scheme: SignatureScheme) -> Box<dyn Signer> {
+        Box::new(Pkcs11Signer { session: self.session.clone(), key: self.key, scheme })
+    }
+}
+
+impl SigningKey for Pkcs11SigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        match self.kind {
+            KeyKind::RSA => ALL_RSA_SCHEMES
+                .iter()
+                .find(|scheme| offered.contains(scheme))
+                .map(|scheme| self.signer(*scheme)),
+            KeyKind::ECDSA(scheme) if offered.contains(&scheme) => Some(self.signer(scheme)),
+            KeyKind::Ed25519 if offered.contains(&SignatureScheme::ED25519) =>
+                Some(self.signer(SignatureScheme::ED25519)),
+            _ => None,
+        }
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        match self.kind {
+            KeyKind::RSA => SignatureAlgorithm::RSA,
+            KeyKind::ECDSA(_) => SignatureAlgorithm::ECDSA,
+            KeyKind::Ed25519 => SignatureAlgorithm::ED25519,
+        }
+    }
+}
+
+struct Pkcs11Signer {
+    session: Arc<Session>,
+    key: c_ulong,
+    scheme: SignatureScheme,
+}
+
+fn pkcs1(prefix: &[u8], alg: &'static digest::Algorithm, message: &[u8]) -> Vec<u8> {
+    let mut digest_info = prefix.to_vec();
+    digest_info.extend_from_slice(digest::digest(alg, message).as_ref());
+    digest_info
+}
+
+fn pss(hash_alg: c_ulong, mgf: c_ulong, alg: &'static digest::Algorithm) -> Option<RsaPssParams> {
+    Some(RsaPssParams { hash_alg, mgf, salt_len: alg.output_len as c_ulong })
+}
+
+fn hash(alg: &'static digest::Algorithm, message: &[u8]) -> Vec<u8> {
+    digest::digest(alg, message).as_ref().to_vec()
+}
+
+impl Signer for Pkcs11Signer {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, TLSError> {
+        use ring::digest::{SHA256, SHA384, SHA512};
+
+        let (mechanism, params, data) = match self.scheme {
+            SignatureScheme::RSA_PKCS1_SHA256 =>
+                (CKM_RSA_PKCS, None, pkcs1(SHA256_DIGESTINFO, &SHA256, message)),
+            SignatureScheme::RSA_PKCS1_SHA384 =>
+                (CKM_RSA_PKCS, None, pkcs1(SHA384_DIGESTINFO, &SHA384, message)),
+            SignatureScheme::RSA_PKCS1_SHA512 =>
+                (CKM_RSA_PKCS, None, pkcs1(SHA512_DIGESTINFO, &SHA512, message)),
+            SignatureScheme::RSA_PSS_SHA256 =>
+                (CKM_RSA_PKCS_PSS, pss(CKM_SHA256, CKG_MGF1_SHA256, &SHA256), hash(&SHA256, message)),
+            SignatureScheme::RSA_PSS_SHA384 =>
+                (CKM_RSA_PKCS_PSS, pss(CKM_SHA384, CKG_MGF1_SHA384, &SHA384), hash(&SHA384, message)),
+            SignatureScheme::RSA_PSS_SHA512 =>
+                (CKM_RSA_PKCS_PSS, pss(CKM_SHA512, CKG_MGF1_SHA512, &SHA512), hash(&SHA512, message)),
+            SignatureScheme::ECDSA_NISTP256_SHA256 =>
+                (CKM_ECDSA, None, hash(&SHA256, message)),
+            SignatureScheme::ECDSA_NISTP384_SHA384 =>
+                (CKM_ECDSA, None, hash(&SHA384, message)),
+            SignatureScheme::ED25519 =>
+                (CKM_EDDSA, None, message.to_vec()),
+            _ => unreachable!(),
+        };
+
+        let sig = self.session.sign(self.key, mechanism, params, &data)?;
+
+        if mechanism == CKM_ECDSA {
+            ecdsa_sig_to_der(&sig)
+        } else {
+            Ok(sig)
+        }
+    }
+
+    fn get_scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+/// Tokens give ECDSA signatures as r || s; TLS wants an
+/// Ecdsa-Sig-Value: `SEQUENCE { r INTEGER, s INTEGER }`.
+fn ecdsa_sig_to_der(sig: &[u8]) -> Result<Vec<u8>, TLSError> {
+    if sig.is_empty() || sig.len() % 2 != 0 {
+        return Err(TLSError::General("PKCS#11 ECDSA signature has wrong length".to_string()));
+    }
+
+    let (r, s) = sig.split_at(sig.len() / 2);
+    let mut body = Vec::new();
+    der_integer(&mut body, r);
+    der_integer(&mut body, s);
+
+    let mut der = vec![0x30];
+    der_length(&mut der, body.len());
+    der.extend_from_slice(&body);
+    Ok(der)
+}
+
+fn der_integer(out: &mut Vec<u8>, unsigned: &[u8]) {
+    let first = unsigned.iter()
+        .position(|b| *b != 0)
+        .unwrap_or(unsigned.len() - 1);
+    let value = &unsigned[first..];
+    let pad = value[0] & 0x80 != 0;
+
+    out.push(0x02);
+    der_length(out, value.len() + pad as usize);
+    if pad {
+        out.push(0x00);
+    }
+    out.extend_from_slice(value);
+}
+
+fn der_length(out: &mut Vec<u8>, len: usize) {
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        assert!(len <= 0xff);
+        out.push(0x81);
+        out.push(len as u8);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::slice;
+
+    #[test]
+    fn test_ecdsa_sig_to_der() {
+        let mut sig = vec![0x80; 32];
+        sig.extend_from_slice(&[0x00; 32]);
+        sig[63] = 0x01;
+        let der = ecdsa_sig_to_der(&sig).unwrap();
+        assert_eq!(&der[..5], &[0x30, 0x26, 0x02, 0x21, 0x00]);
+        assert_eq!(&der[5..37], &[0x80; 32][..]);
+        assert_eq!(&der[37..], &[0x02, 0x01, 0x01]);
+
+        let sig = [0x00, 0x00, 0x7f, 0x01, 0x00, 0xff];
+        assert_eq!(ecdsa_sig_to_der(&sig).unwrap(),
+                   vec![0x30, 0x08, 0x02, 0x01, 0x7f, 0x02, 0x03, 0x01, 0x00, 0xff]);
+
+        let mut sig = vec![0xff; 96];
+        sig.extend_from_slice(&[0xff; 96]);
+        let der = ecdsa_sig_to_der(&sig).unwrap();
+        assert_eq!(&der[..6], &[0x30, 0x81, 0xc6, 0x02, 0x61, 0x00]);
+
+        assert!(ecdsa_sig_to_der(&[]).is_err());
+        assert!(ecdsa_sig_to_der(&[0x01, 0x02, 0x03]).is_err());
+    }
+
+    #[test]
+    fn test_load_missing_module() {
+        assert!(Module::load("/nonexistent/libpkcs11.so").is_err());
+        assert!(Module::load("bad\0path").is_err());
+    }
+
+    // A PKCS#11 module with three keys, which records what it's
+    // asked to sign.
+    struct MockToken {
+        found: Vec<c_ulong>,
+        signed: Vec<(c_ulong, Option<RsaPssParams>, Vec<u8>)>,
+        logged_in: bool,
+    }
+
+    static MOCK: Mutex<MockToken> = Mutex::new(MockToken {
+        found: Vec::new(),
+        signed: Vec::new(),
+        logged_in: false,
+    });
+
+    static MOCK_USERS: Mutex<()> = Mutex::new(());
+
+    const MOCK_RSA: c_ulong = 1;
+    const MOCK_ECDSA: c_ulong = 2;
+    const MOCK_ED25519: c_ulong = 3;
+
+    unsafe extern "C" fn mock_initialize(_args: *mut c_void) -> c_ulong { CKR_OK }
+    unsafe extern "C" fn mock_finalize(_reserved: *mut c_void) -> c_ulong { CKR_OK }
+
+    unsafe extern "C" fn mock_open_session(_slot: c_ulong, _flags: c_ulong,
+                                           _app: *mut c_void, _notify: *const c_void,
+                                           session: *mut c_ulong) -> c_ulong {
+        *session = 42;
+        CKR_OK
+    }
+
+    unsafe extern "C" fn mock_close_session(_session: c_ulong) -> c_ulong { CKR_OK }
+
+    unsafe extern "C" fn mock_login(_session: c_ulong, _user: c_ulong,
+                                    pin: *const u8, pin_len: c_ulong) -> c_ulong {
+        if slice::from_raw_parts(pin, pin_len as usize) != b"1234" {
+            return 0xa0; // CKR_PIN_INCORRECT
+        }
+        MOCK.lock().unwrap().logged_in = true;
+        CKR_OK
+    }
+
+    unsafe extern "C" fn mock_get_attribute_value(_session: c_ulong, object: c_ulong,
+                                                  template: *mut Attribute,
+                                                  count: c_ulong) -> c_ulong {
+        assert_eq!(count, 1);
+        let attr = &mut *template;
+        let key_type: c_ulong = match object {
+            MOCK_RSA => CKK_RSA,
+            MOCK_ECDSA => CKK_EC,
+            _ => CKK_EC_EDWARDS,
+        };
+        let key_type = key_type.to_ne_bytes();
+        let value: &[u8] = match (attr.typ, object) {
+            (CKA_KEY_TYPE, _) => &key_type,
+            (CKA_EC_PARAMS, MOCK_ECDSA) => PRIME256V1,
+            (CKA_EC_PARAMS, MOCK_ED25519) => ED25519_NAME,
+            _ => return 0x12, // CKR_ATTRIBUTE_TYPE_INVALID
+        };
+
+        if !attr.value.is_null() {
+            assert!(attr.value_len as usize >= value.len());
+            ptr::copy_nonoverlapping(value.as_ptr(), attr.value as *mut u8, value.len());
+        }
+        attr.value_len = value.len() as c_ulong;
+        CKR_OK
+    }
+
+    unsafe extern "C" fn mock_find_objects_init(_session: c_ulong, template: *const Attribute,
+                                                count: c_ulong) -> c_ulong {
+        let template = slice::from_raw_parts(template, count as usize);
+        let label = template.iter()
+            .find(|attr| attr.typ == CKA_LABEL)
+            .map(|attr| slice::from_raw_parts(attr.value as *const u8, attr.value_len as usize))
+            .unwrap();
+
+        let mut mock = MOCK.lock().unwrap();
+        if !mock.logged_in {
+            mock.found = vec![];
+            return CKR_OK;
+        }
+
+        mock.found = match label {
+            b"rsa" => vec![MOCK_RSA],
+            b"ecdsa" => vec![MOCK_ECDSA],
+            b"ed25519" => vec![MOCK_ED25519],
+            b"dupe" => vec![MOCK_RSA, MOCK_ECDSA],
+            _ => vec![],
+        };
+        CKR_OK
+    }
+
+    unsafe extern "C" fn mock_find_objects(_session: c_ulong, objects: *mut c_ulong,
+                                           max: c_ulong, count: *mut c_ulong) -> c_ulong {
+        let found = &MOCK.lock().unwrap().found;
+        let n = found.len().min(max as usize);
+        ptr::copy_nonoverlapping(found.as_ptr(), objects, n);
+        *count = n as c_ulong;
+        CKR_OK
+    }
+
+    unsafe extern "C" fn mock_find_objects_final(_session: c_ulong) -> c_ulong { CKR_OK }
+
+    unsafe extern "C" fn mock_sign_init(_session: c_ulong, mechanism: *const Mechanism,
+                                        _key: c_ulong) -> c_ulong {
+        let mech = &*mechanism;
+        let params = if mech.parameter.is_null() {
+            None
+        } else {
+            assert_eq!(mech.parameter_len as usize, mem::size_of::<RsaPssParams>());
+            Some(*(mech.parameter as *const RsaPssParams))
+        };
+        MOCK.lock().unwrap().signed.push((mech.mechanism, params, vec![]));
+        CKR_OK
+    }
+
+    unsafe extern "C" fn mock_sign(_session: c_ulong, data: *const u8, data_len: c_ulong,
+                                   sig: *mut u8, sig_len: *mut c_ulong) -> c_ulong {
+        let data = slice::from_raw_parts(data, data_len as usize);
+        let mut mock = MOCK.lock().unwrap();
+        let last = mock.signed.last_mut().unwrap();
+
+        // ECDSA signatures are r || s; others just echo the input.
+        let output = if last.0 == CKM_ECDSA { vec![0x01; 64] } else { data.to_vec() };
+
+        if !sig.is_null() {
+            ptr::copy_nonoverlapping(output.as_ptr(), sig, output.len());
+            last.2 = data.to_vec();
+        }
+        *sig_len = output.len() as c_ulong;
+        CKR_OK
+    }
+
+    fn mock_module() -> Arc<Module> {
+        let functions = Box::new(FunctionList {
+            version: [2, 40],
+            initialize: mock_initialize,
+            finalize: mock_finalize,
+            _get_info_to_set_pin: [ptr::null(); 10],
+            open_session: mock_open_session,
+            close_session: mock_close_session,
+            _close_all_sessions_to_set_operation_state: [ptr::null(); 4],
+            login: mock_login,
+            _logout_to_get_object_size: [ptr::null(); 5],
+            get_attribute_value: mock_get_attribute_value,
+            _set_attribute_value: ptr::null(),
+            find_objects_init: mock_find_objects_init,
+            find_objects: mock_find_objects,
+            find_objects_final: mock_find_objects_final,
+            _encrypt_init_to_digest_final: [ptr::null(); 13],
+            sign_init: mock_sign_init,
+            sign: mock_sign,
+        });
+
+        Arc::new(Module::initialize(ptr::null_mut(), Box::leak(functions)).unwrap())
+    }
+
+    fn last_signed() -> (c_ulong, Option<RsaPssParams>, Vec<u8>) {
+        MOCK.lock().unwrap().signed.last().unwrap().clone()
+    }
+
+    #[test]
+    fn test_find_keys() {
+        let _guard = MOCK_USERS.lock().unwrap();
+        let module = mock_module();
+
+        assert!(Pkcs11SigningKey::new(&module, 0, Some("0000"), "rsa").is_err());
+        MOCK.lock().unwrap().logged_in = false;
+        assert!(Pkcs11SigningKey::new(&module, 0, None, "rsa").is_err());
+        assert!(Pkcs11SigningKey::new(&module, 0, Some("1234"), "missing").is_err());
+        assert!(Pkcs11SigningKey::new(&module, 0, Some("1234"), "dupe").is_err());
+
+        let rsa = Pkcs11SigningKey::new(&module, 0, Some("1234"), "rsa").unwrap();
+        assert_eq!(rsa.kind, KeyKind::RSA);
+        assert_eq!(rsa.algorithm(), SignatureAlgorithm::RSA);
+
+        let ecdsa = Pkcs11SigningKey::new(&module, 0, None, "ecdsa").unwrap();
+        assert_eq!(ecdsa.kind, KeyKind::ECDSA(SignatureScheme::ECDSA_NISTP256_SHA256));
+        assert_eq!(ecdsa.algorithm(), SignatureAlgorithm::ECDSA);
+
+        let ed25519 = Pkcs11SigningKey::new(&module, 0, None, "ed25519").unwrap();
+        assert_eq!(ed25519.kind, KeyKind::Ed25519);
+        assert_eq!(ed25519.algorithm(), SignatureAlgorithm::ED25519);
+    }
+
+    #[test]
+    fn test_sign_mechanisms() {
+        let _guard = MOCK_USERS.lock().unwrap();
+        let module = mock_module();
+        let message = b"hello";
+
+        let rsa = Pkcs11SigningKey::new(&module, 0, Some("1234"), "rsa").unwrap();
+        assert!(rsa.choose_scheme(&[SignatureScheme::ED25519]).is_none());
+
+        let signer = rsa.choose_scheme(&[SignatureScheme::RSA_PKCS1_SHA256,
+                                          SignatureScheme::RSA_PSS_SHA384]).unwrap();
+        assert_eq!(signer.get_scheme(), SignatureScheme::RSA_PSS_SHA384);
+        assert_eq!(signer.sign(message).unwrap(), hash(&digest::SHA384, message));
+        assert_eq!(last_signed(),
+                   (CKM_RSA_PKCS_PSS,
+                    Some(RsaPssParams { hash_alg: CKM_SHA384, mgf: CKG_MGF1_SHA384, salt_len: 48 }),
+                    hash(&digest::SHA384, message)));
+
+        let signer = rsa.choose_scheme(&[SignatureScheme::RSA_PKCS1_SHA256]).unwrap();
+        let digest_info = signer.sign(message).unwrap();
+        assert_eq!(digest_info.len(), 19 + 32);
+        assert_eq!(&digest_info[..19], SHA256_DIGESTINFO);
+        assert_eq!(last_signed().0, CKM_RSA_PKCS);
+
+        let ecdsa = Pkcs11SigningKey::new(&module, 0, None, "ecdsa").unwrap();
+        assert!(ecdsa.choose_scheme(&[SignatureScheme::ECDSA_NISTP384_SHA384]).is_none());
+        let signer = ecdsa.choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256]).unwrap();
+        assert_eq!(signer.sign(message).unwrap(), ecdsa_sig_to_der(&[0x01; 64]).unwrap());
+        assert_eq!(last_signed(), (CKM_ECDSA, None, hash(&digest::SHA256, message)));
+
+        let ed25519 = Pkcs11SigningKey::new(&module, 0, None, "ed25519").unwrap();
+        let signer = ed25519.choose_scheme(&[SignatureScheme::ED25519]).unwrap();
+        assert_eq!(signer.sign(message).unwrap(), message.to_vec());
+        assert_eq!(last_signed(), (CKM_EDDSA, None, message.to_vec()));
+    }
+}
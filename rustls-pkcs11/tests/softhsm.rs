@@ -0,0 +1,189 @@
+// Handshakes signed by keys on a real SoftHSM v2 token.
+//
+// This needs SoftHSM 2.6 or later (for Ed25519): set SOFTHSM2_MODULE
+// to the path of libsofthsm2.so, and have softhsm2-util on the PATH
+// (or name it with SOFTHSM2_UTIL).  Without SOFTHSM2_MODULE this test
+// does nothing.
+//
+// The test makes its own token in a temporary directory, and imports
+// test-ca's end-entity keys into it.
+
+use rustls::sign::{SigningKey, Signer};
+use rustls::internal::msgs::enums::SignatureAlgorithm;
+use rustls::internal::pemfile;
+use rustls::{ClientConfig, ClientSession, NoClientAuth, ProtocolVersion, ServerConfig,
+             ServerName, ServerSession, Session, SignatureScheme};
+use rustls_pkcs11::{Module, Pkcs11SigningKey};
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::{self, Command};
+use std::sync::{Arc, Mutex};
+
+const PIN: &str = "1234";
+const SO_PIN: &str = "5678";
+const TOKEN_LABEL: &str = "rustls-pkcs11-test";
+
+fn test_ca(kt: &str, part: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../test-ca")
+        .join(kt)
+        .join(part)
+}
+
+fn softhsm2_util(args: &[&str]) -> String {
+    let util = env::var("SOFTHSM2_UTIL").unwrap_or_else(|_| "softhsm2-util".to_string());
+    let output = Command::new(&util)
+        .args(args)
+        .output()
+        .unwrap_or_else(|err| panic!("cannot run {}: {}", util, err));
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(output.status.success(),
+            "{} {:?} failed: {}{}", util, args, stdout, String::from_utf8_lossy(&output.stderr));
+    stdout
+}
+
+/// A SoftHSM configuration and token directory, removed on drop.
+struct Token {
+    dir: PathBuf,
+    slot: u64,
+}
+
+impl Token {
+    fn new() -> Token {
+        let dir = env::temp_dir().join(format!("rustls-pkcs11-softhsm-{}", process::id()));
+        let tokens = dir.join("tokens");
+        fs::create_dir_all(&tokens).unwrap();
+
+        let conf = dir.join("softhsm2.conf");
+        fs::write(&conf,
+                  format!("directories.tokendir = {}\nobjectstore.backend = file\nlog.level = ERROR\n",
+                          tokens.display()))
+            .unwrap();
+        env::set_var("SOFTHSM2_CONF", &conf);
+
+        let out = softhsm2_util(&["--init-token", "--free", "--label", TOKEN_LABEL,
+                                  "--so-pin", SO_PIN, "--pin", PIN]);
+        let slot = out.split("reassigned to slot ")
+            .nth(1)
+            .and_then(|rest| rest.trim().parse().ok())
+            .unwrap_or_else(|| panic!("cannot find slot in {:?}", out));
+
+        Token { dir, slot }
+    }
+
+    fn import(&self, kt: &str, id: &str) {
+        let key = test_ca(kt, "end.key");
+        softhsm2_util(&["--import", key.to_str().unwrap(), "--token", TOKEN_LABEL,
+                        "--label", kt, "--id", id, "--pin", PIN]);
+    }
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Remembers which scheme the handshake used the key with.
+struct RecordingKey {
+    key: Box<dyn SigningKey>,
+    chosen: Arc<Mutex<Option<SignatureScheme>>>,
+}
+
+impl SigningKey for RecordingKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        let signer = self.key.choose_scheme(offered)?;
+        *self.chosen.lock().unwrap() = Some(signer.get_scheme());
+        Some(signer)
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        self.key.algorithm()
+    }
+}
+
+fn transfer(from: &mut dyn Session, to: &mut dyn Session) {
+    let mut buf = Vec::new();
+    while from.wants_write() {
+        from.write_tls(&mut buf).unwrap();
+    }
+
+    let mut rd = &buf[..];
+    while !rd.is_empty() {
+        to.read_tls(&mut rd).unwrap();
+        to.process_new_packets().unwrap();
+    }
+}
+
+fn handshake_with(module: &Arc<Module>, slot: u64, kt: &str,
+                  version: ProtocolVersion) -> SignatureScheme {
+    let key = Pkcs11SigningKey::new(module, slot, Some(PIN), kt).unwrap();
+    let chosen = Arc::new(Mutex::new(None));
+
+    let chain = pemfile::certs(&mut io::BufReader::new(
+            fs::File::open(test_ca(kt, "end.fullchain")).unwrap()))
+        .unwrap();
+    let mut server_config = ServerConfig::new(NoClientAuth::new());
+    server_config.set_single_cert_with_signing_key(
+        chain, Box::new(RecordingKey { key: Box::new(key), chosen: chosen.clone() }));
+
+    let mut client_config = ClientConfig::new();
+    client_config.root_store
+        .add_pem_file(&mut io::BufReader::new(fs::File::open(test_ca(kt, "ca.cert")).unwrap()))
+        .unwrap();
+    client_config.versions = vec![version];
+
+    let mut client = ClientSession::new(&Arc::new(client_config),
+                                        ServerName::try_from_ascii_str("testserver.com").unwrap());
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    while client.is_handshaking() || server.is_handshaking() {
+        transfer(&mut client, &mut server);
+        transfer(&mut server, &mut client);
+    }
+
+    client.write_all(b"hello").unwrap();
+    transfer(&mut client, &mut server);
+    let mut received = [0u8; 5];
+    server.read_exact(&mut received).unwrap();
+    assert_eq!(&received, b"hello");
+
+    let scheme = chosen.lock().unwrap().take();
+    scheme.expect("key wasn't used")
+}
+
+#[test]
+fn softhsm_handshakes() {
+    let module_path = match env::var("SOFTHSM2_MODULE") {
+        Ok(path) => path,
+        Err(_) => {
+            println!("SOFTHSM2_MODULE is unset; skipping");
+            return;
+        }
+    };
+
+    let token = Token::new();
+    token.import("rsa", "01");
+    token.import("ecdsa", "02");
+    token.import("eddsa", "03");
+
+    let module = Module::load(&module_path).unwrap();
+
+    for version in &[ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2] {
+        let rsa = handshake_with(&module, token.slot, "rsa", *version);
+        assert!(match rsa {
+            SignatureScheme::RSA_PSS_SHA256 |
+            SignatureScheme::RSA_PSS_SHA384 |
+            SignatureScheme::RSA_PSS_SHA512 => true,
+            _ => false,
+        }, "rsa key used with {:?}", rsa);
+
+        assert_eq!(handshake_with(&module, token.slot, "ecdsa", *version),
+                   SignatureScheme::ECDSA_NISTP256_SHA256);
+        assert_eq!(handshake_with(&module, token.slot, "eddsa", *version),
+                   SignatureScheme::ED25519);
+    }
+}
@@ -257,7 +257,7 @@ fn bench_handshake(version: rustls::ProtocolVersion,
 
     for _ in 0..rounds {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
-        let mut client = ClientSession::new(&client_config, dns_name);
+        let mut client = ClientSession::new(&client_config, dns_name.into());
         let mut server = ServerSession::new(&server_config);
 
         server_time += time(|| {
@@ -327,7 +327,7 @@ fn bench_bulk(version: rustls::ProtocolVersion, suite: &'static rustls::Supporte
     }
 
     let dns_name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
-    let mut client = ClientSession::new(&client_config, dns_name);
+    let mut client = ClientSession::new(&client_config, dns_name.into());
     let mut server = ServerSession::new(&server_config);
 
     do_handshake(&mut client, &mut server);
@@ -391,7 +391,7 @@ fn bench_memory(version: rustls::ProtocolVersion,
     for _i in 0..session_count {
         servers.push(ServerSession::new(&server_config));
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
-        clients.push(ClientSession::new(&client_config, dns_name));
+        clients.push(ClientSession::new(&client_config, dns_name.into()));
     }
 
     for _step in 0..5 {
@@ -57,6 +57,7 @@ struct Options {
     min_version: Option<ProtocolVersion>,
     max_version: Option<ProtocolVersion>,
     server_ocsp_response: Vec<u8>,
+    fail_ocsp_callback: bool,
     server_sct_list: Vec<u8>,
     use_signing_scheme: u16,
     expect_curve: u16,
@@ -101,6 +102,7 @@ impl Options {
             min_version: None,
             max_version: None,
             server_ocsp_response: vec![],
+            fail_ocsp_callback: false,
             server_sct_list: vec![],
             use_signing_scheme: 0,
             expect_curve: 0,
@@ -177,19 +179,29 @@ impl rustls::ClientCertVerifier for DummyClientAuth {
     }
 
     fn verify_client_cert(&self,
-                          _certs: &[rustls::Certificate]) -> Result<rustls::ClientCertVerified, rustls::TLSError> {
+                          _certs: &[rustls::Certificate],
+                          _now: webpki::Time) -> Result<rustls::ClientCertVerified, rustls::TLSError> {
         Ok(rustls::ClientCertVerified::assertion())
     }
 }
 
-struct DummyServerAuth {}
+struct DummyServerAuth {
+    fail_ocsp: bool,
+}
 
 impl rustls::ServerCertVerifier for DummyServerAuth {
     fn verify_server_cert(&self,
                           _roots: &rustls::RootCertStore,
                           _certs: &[rustls::Certificate],
-                          _hostname: webpki::DNSNameRef<'_>,
-                          _ocsp: &[u8]) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+                          _server_name: &rustls::ServerName,
+                          _ocsp: &[u8],
+                          _now: webpki::Time) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        // This stands in for bogo's OCSP callback, which is called whether
+        // or not a response was stapled.
+        if self.fail_ocsp {
+            return Err(rustls::TLSError::InvalidOCSPResponse("rejected by callback".to_string()));
+        }
+
         Ok(rustls::ServerCertVerified::assertion())
     }
 }
@@ -259,6 +271,7 @@ fn lookup_scheme(scheme: u16) -> rustls::SignatureScheme {
         0x0804 => rustls::SignatureScheme::RSA_PSS_SHA256,
         0x0805 => rustls::SignatureScheme::RSA_PSS_SHA384,
         0x0806 => rustls::SignatureScheme::RSA_PSS_SHA512,
+        0x0807 => rustls::SignatureScheme::ED25519,
         _ => {
             println_err!("Unsupported signature scheme {:04x}", scheme);
             process::exit(BOGO_NACK);
@@ -371,7 +384,9 @@ fn make_client_cfg(opts: &Options) -> Arc<rustls::ClientConfig> {
     }
 
     cfg.dangerous()
-        .set_certificate_verifier(Arc::new(DummyServerAuth {}));
+        .set_certificate_verifier(Arc::new(DummyServerAuth {
+            fail_ocsp: opts.fail_ocsp_callback,
+        }));
 
     if !opts.protocols.is_empty() {
         cfg.set_protocols(&opts.protocols
@@ -455,6 +470,7 @@ fn handle_err(err: rustls::TLSError) -> ! {
         TLSError::PeerSentOversizedRecord => {
             quit(":DATA_LENGTH_TOO_LONG:")
         }
+        TLSError::InvalidOCSPResponse(_) => quit(":OCSP_CB_ERROR:"),
         _ => {
             println_err!("unhandled error: {:?}", err);
             quit(":FIXME:")
@@ -722,7 +738,6 @@ fn main() {
 
             "-expect-secure-renegotiation" |
             "-expect-no-session-id" |
-            "-enable-ed25519" |
             "-expect-session-id" => {
                 println!("not checking {}; NYI", arg);
             }
@@ -752,6 +767,9 @@ fn main() {
                 opts.server_ocsp_response = base64::decode(args.remove(0).as_bytes())
                     .expect("invalid base64");
             }
+            "-fail-ocsp-callback" => {
+                opts.fail_ocsp_callback = true;
+            }
             "-signed-cert-timestamps" => {
                 opts.server_sct_list = base64::decode(args.remove(0).as_bytes())
                     .expect("invalid base64");
@@ -815,6 +833,7 @@ fn main() {
 
             // defaults:
             "-enable-all-curves" |
+            "-enable-ed25519" |
             "-renegotiate-ignore" |
             "-no-tls11" |
             "-no-tls1" |
@@ -826,6 +845,7 @@ fn main() {
             "-expect-extended-master-secret" |
             "-expect-ticket-renewal" |
             "-enable-ocsp-stapling" |
+            "-use-ocsp-callback" |
             // internal openssl details:
             "-async" |
             "-implicit-handshake" |
@@ -923,14 +943,14 @@ fn main() {
             };
             ClientOrServer::Server(s)
         } else {
-            let dns_name =
-                webpki::DNSNameRef::try_from_ascii_str(&opts.host_name).unwrap();
+            let server_name =
+                rustls::ServerName::try_from_ascii_str(&opts.host_name).unwrap();
             let c = if opts.quic_transport_params.is_empty() {
                 rustls::ClientSession::new(ccfg.as_ref().unwrap(),
-                                           dns_name)
+                                           server_name)
             } else {
                 rustls::ClientSession::new_quic(ccfg.as_ref().unwrap(),
-                                                dns_name,
+                                                server_name,
                                                 opts.quic_transport_params.clone())
             };
             ClientOrServer::Client(c)
@@ -5,7 +5,6 @@
 //
 
 
-use webpki;
 use webpki_roots;
 
 use std::io::{Read, Write, BufReader};
@@ -15,7 +14,7 @@ use std::fs::File;
 use std::error::Error;
 use std::process;
 use std::env;
-use rustls::{ClientConfig, ClientSession, Session, TLSError};
+use rustls::{ClientConfig, ClientSession, ServerName, Session, TLSError};
 
 enum Verdict {
     Accept,
@@ -44,9 +43,9 @@ fn parse_args(args: &[String]) -> Result<(String, u16, ClientConfig), Box<dyn Er
 }
 
 fn communicate(host: String, port: u16, config: ClientConfig) -> Result<Verdict, Box<dyn Error>> {
-    let dns_name = webpki::DNSNameRef::try_from_ascii_str(&host).unwrap();
+    let server_name = ServerName::try_from_ascii_str(&host).unwrap();
     let rc_config = Arc::new(config);
-    let mut client = ClientSession::new(&rc_config, dns_name);
+    let mut client = ClientSession::new(&rc_config, server_name);
     let mut stream = TcpStream::connect((&*host, port))?;
 
     client.write_all(b"GET / HTTP/1.0\r\nConnection: close\r\nContent-Length: 0\r\n\r\n")?;
@@ -0,0 +1,148 @@
+// AES-128 in CCM mode (RFC3610, NIST SP800-38C), for the TLS1.3
+// AES-CCM ciphersuites.
+//
+// ring offers neither CCM nor the raw AES block cipher, so this
+// wraps the RustCrypto `ccm` and `aes` crates.  It is only built
+// with the `aes_ccm` feature.
+
+use crate::crypto::{AeadKey, NONCE_LEN};
+use crate::error::TLSError;
+
+use aes::Aes128;
+use ccm::aead::{AeadInPlace, KeyInit};
+use ccm::aead::generic_array::typenum::Unsigned;
+use ccm::consts::{U8, U12, U16};
+use ccm::Ccm;
+
+/// AES-128-CCM, with a 16 byte tag.
+pub(crate) type Aes128Ccm = CcmKey<Ccm<Aes128, U16, U12>>;
+
+/// AES-128-CCM-8, with an 8 byte tag.
+pub(crate) type Aes128Ccm8 = CcmKey<Ccm<Aes128, U8, U12>>;
+
+/// An AES-CCM key; see `Aes128Ccm` and `Aes128Ccm8`.
+pub(crate) struct CcmKey<C> {
+    ccm: C,
+}
+
+impl<C: KeyInit> CcmKey<C> {
+    /// Make a key.  `key` must be 16 bytes.
+    pub(crate) fn new(key: &[u8]) -> CcmKey<C> {
+        CcmKey {
+            ccm: C::new(key.into()),
+        }
+    }
+}
+
+impl<C: AeadInPlace + Send + Sync> AeadKey for CcmKey<C> {
+    fn seal_in_place_separate_tag(&self,
+                                  nonce: [u8; NONCE_LEN],
+                                  aad: &[u8],
+                                  in_out: &mut [u8]) -> Result<Vec<u8>, TLSError> {
+        self.ccm
+            .encrypt_in_place_detached((&nonce[..]).into(), aad, in_out)
+            .map(|tag| tag.to_vec())
+            .map_err(|_| TLSError::General("encrypt failed".to_string()))
+    }
+
+    fn open_in_place(&self,
+                     nonce: [u8; NONCE_LEN],
+                     aad: &[u8],
+                     in_out: &mut [u8]) -> Result<usize, TLSError> {
+        let plain_len = in_out.len()
+            .checked_sub(C::TagSize::USIZE)
+            .ok_or(TLSError::DecryptError)?;
+        let (plain, tag) = in_out.split_at_mut(plain_len);
+
+        if self.ccm
+            .decrypt_in_place_detached((&nonce[..]).into(),
+                                       aad,
+                                       plain,
+                                       (&tag[..]).into())
+            .is_err() {
+            // Don't leave anything behind that looks like plaintext.
+            for b in plain.iter_mut() {
+                *b = 0;
+            }
+            return Err(TLSError::DecryptError);
+        }
+
+        Ok(plain_len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Aes128Ccm, Aes128Ccm8};
+    use crate::crypto::AeadKey;
+    use crate::error::TLSError;
+
+    fn check_seal_open(key: &dyn AeadKey,
+                       nonce: [u8; 12],
+                       aad: &[u8],
+                       plain: &[u8],
+                       expected: &[u8]) {
+        let mut buf = plain.to_vec();
+        let tag = key.seal_in_place_separate_tag(nonce, aad, &mut buf).unwrap();
+        buf.extend_from_slice(&tag);
+        assert_eq!(buf, expected.to_vec());
+
+        assert_eq!(key.open_in_place(nonce, aad, &mut buf), Ok(plain.len()));
+        assert_eq!(&buf[..plain.len()], plain);
+    }
+
+    #[test]
+    fn test_ccm_sp800_38c() {
+        // Example 3 from NIST SP800-38C, which has a 12 byte nonce
+        // and an 8 byte tag.
+        check_seal_open(&Aes128Ccm8::new(&hex!("404142434445464748494a4b4c4d4e4f")),
+                        hex!("101112131415161718191a1b"),
+                        &hex!("000102030405060708090a0b0c0d0e0f10111213"),
+                        &hex!("202122232425262728292a2b2c2d2e2f3031323334353637"),
+                        &hex!("e3b201a9f5b71a7a9b1ceaeccd97e70b6176aad9a4428aa5
+                               484392fbc1b09951"));
+    }
+
+    #[test]
+    fn test_ccm_cavp_16_byte_tag() {
+        // From the NIST CAVP CCM "DVPT" vectors, for Nlen = 12
+        // and Tlen = 16.
+        let key = Aes128Ccm::new(&hex!("005e8f4d8e0cbf4e1ceeb5d87a275848"));
+
+        check_seal_open(&key,
+                        hex!("0ec3ac452b547b9062aac8fa"),
+                        &hex!("2f1821aa57e5278ffd33c17d46615b77363149dbc98470413f6543a6b749f2ca"),
+                        &hex!("b6f345204526439daf84998f380dcfb4b4167c959c04ff65"),
+                        &hex!("9575e16f35da3c88a19c26a7b762044f4d7bbbafeff05d75
+                               4829e2a7752fa3a14890972884b511d8"));
+        check_seal_open(&key,
+                        hex!("472711261a9262bef077c0b7"),
+                        &hex!("17c87889a2652636bcf712d111c86b9d68d64d18d531928030a5ec97c59931a4"),
+                        &hex!("9d63df773b3799e361c5328d44bbb12f4154747ecf7cc667"),
+                        &hex!("53323b82d7a754d82cebf0d4bc930ef06d11e162c5c027c4
+                               715a641834bbb75bb6572ca5a45c3183"));
+    }
+
+    #[test]
+    fn test_ccm_rejects_forgery() {
+        let key = Aes128Ccm::new(&[0x42; 16]);
+        let nonce = [0x24; 12];
+
+        let mut buf = b"hello world".to_vec();
+        let tag = key.seal_in_place_separate_tag(nonce, b"aad", &mut buf).unwrap();
+        buf.extend_from_slice(&tag);
+
+        let mut wrong_aad = buf.clone();
+        assert_eq!(key.open_in_place(nonce, b"bad", &mut wrong_aad),
+                   Err(TLSError::DecryptError));
+        assert!(wrong_aad[..11].iter().all(|b| *b == 0));
+
+        let mut flipped = buf.clone();
+        flipped[0] ^= 1;
+        assert_eq!(key.open_in_place(nonce, b"aad", &mut flipped),
+                   Err(TLSError::DecryptError));
+
+        assert_eq!(key.open_in_place(nonce, b"aad", &mut buf[..15]),
+                   Err(TLSError::DecryptError));
+    }
+}
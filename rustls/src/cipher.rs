@@ -1,4 +1,3 @@
-use ring::{aead, hkdf};
 use std::io::Write;
 use crate::msgs::codec;
 use crate::msgs::codec::Codec;
@@ -8,27 +7,61 @@ use crate::msgs::fragmenter::MAX_FRAGMENT_LEN;
 use crate::error::TLSError;
 use crate::session::SessionSecrets;
 use crate::suites::{SupportedCipherSuite, BulkAlgorithm};
-use crate::key_schedule::{derive_traffic_key, derive_traffic_iv};
+use crate::key_schedule::{derive_traffic_key, derive_traffic_iv, Prk};
+use crate::key_schedule::hkdf_expand;
+use crate::crypto::{CryptoProvider, Aead, AeadKey, NONCE_LEN};
+#[cfg(feature = "aes_ccm")]
+use crate::ccm;
 use std::convert::TryInto;
+use std::fmt;
 
 /// Objects with this trait can decrypt TLS messages.
 pub trait MessageDecrypter : Send + Sync {
+    /// Decrypt `m`, the `seq`th record received under this key.
+    /// Fail with `TLSError::DecryptError` if it isn't authentic.
     fn decrypt(&self, m: Message, seq: u64) -> Result<Message, TLSError>;
 }
 
 /// Objects with this trait can encrypt TLS messages.
 pub trait MessageEncrypter : Send + Sync {
+    /// Encrypt `m`, the `seq`th record sent under this key.
     fn encrypt(&self, m: BorrowMessage, seq: u64) -> Result<Message, TLSError>;
 }
 
+/// Makes the `MessageEncrypter`s and `MessageDecrypter`s which
+/// protect records for a ciphersuite.
+///
+/// Implement this to define a ciphersuite rustls doesn't support
+/// itself: name it in the `cipher_factory` of your own
+/// `SupportedCipherSuite`, and list that suite in `ClientConfig::ciphersuites`
+/// or `ServerConfig::ciphersuites`.
+pub trait MessageCipherFactory : Send + Sync + fmt::Debug {
+    /// Make a TLS1.2 decrypter.  `key` is the peer's write key of
+    /// `enc_key_len` bytes, and `iv` the peer's `fixed_iv_len` bytes
+    /// of IV.
+    fn new_tls12_decrypter(&self, key: &[u8], iv: &[u8]) -> Box<dyn MessageDecrypter>;
+
+    /// Make a TLS1.2 encrypter.  `key` and `iv` are our write key and
+    /// IV, as for `new_tls12_decrypter`.  `extra` is the `explicit_nonce_len`
+    /// bytes which follow in the key block, for use in making nonces.
+    fn new_tls12_encrypter(&self, key: &[u8], iv: &[u8], extra: &[u8]) -> Box<dyn MessageEncrypter>;
+
+    /// Make a TLS1.3 decrypter.  `key` is a traffic key of `enc_key_len`
+    /// bytes, and `iv` the 12 byte traffic IV.
+    fn new_tls13_decrypter(&self, key: &[u8], iv: &[u8]) -> Box<dyn MessageDecrypter>;
+
+    /// Make a TLS1.3 encrypter, as for `new_tls13_decrypter`.
+    fn new_tls13_encrypter(&self, key: &[u8], iv: &[u8]) -> Box<dyn MessageEncrypter>;
+}
+
 impl dyn MessageEncrypter {
-    pub fn invalid() -> Box<dyn MessageEncrypter> {
+    pub(crate) fn invalid() -> Box<dyn MessageEncrypter> {
         Box::new(InvalidMessageEncrypter {})
     }
 }
 
 impl dyn MessageDecrypter {
-    pub fn invalid() -> Box<dyn MessageDecrypter> {
+    pub(crate) fn invalid() -> Box<dyn MessageDecrypter> {
         Box::new(InvalidMessageDecrypter {})
     }
 }
@@ -39,20 +72,23 @@ const TLS12_AAD_SIZE: usize = 8 + 1 + 2 + 2;
 fn make_tls12_aad(seq: u64,
                   typ: ContentType,
                   vers: ProtocolVersion,
-                  len: usize) -> ring::aead::Aad<[u8; TLS12_AAD_SIZE]> {
+                  len: usize) -> [u8; TLS12_AAD_SIZE] {
     let mut out = [0; TLS12_AAD_SIZE];
     codec::put_u64(seq, &mut out[0..]);
     out[8] = typ.get_u8();
     codec::put_u16(vers.get_u16(), &mut out[9..]);
     codec::put_u16(len as u16, &mut out[11..]);
-    ring::aead::Aad::from(out)
+    out
 }
 
 /// Make a `MessageCipherPair` based on the given supported ciphersuite `scs`,
-/// and the session's `secrets`.
-pub fn new_tls12(scs: &'static SupportedCipherSuite,
+/// and the session's `secrets`, using `provider`'s implementation or
+/// the suite's own `cipher_factory`.
+/// This fails if `provider` can't do the suite's AEAD after all.
+pub fn new_tls12(provider: &dyn CryptoProvider,
+                 scs: &'static SupportedCipherSuite,
                  secrets: &SessionSecrets)
-                 -> MessageCipherPair {
+                 -> Result<MessageCipherPair, TLSError> {
     // Make a key block, and chop it up.
     // nb. we don't implement any ciphersuites with nonzero mac_key_len.
     let key_block = secrets.make_key_block(scs.key_block_len());
@@ -78,9 +114,16 @@ pub fn new_tls12(scs: &'static SupportedCipherSuite,
         (client_write_key, client_write_iv)
     };
 
-    let aead_alg = scs.get_aead_alg();
+    if let Some(factory) = scs.cipher_factory {
+        offs += scs.fixed_iv_len;
+        let extra = &key_block[offs..offs + scs.explicit_nonce_len];
+        return Ok((factory.new_tls12_decrypter(read_key, read_iv),
+                   factory.new_tls12_encrypter(write_key, write_iv, extra)));
+    }
 
-    match scs.bulk {
+    let aead_alg = provider.suite_aead(scs)?;
+
+    Ok(match scs.bulk {
         BulkAlgorithm::AES_128_GCM |
         BulkAlgorithm::AES_256_GCM => {
             // The GCM nonce is constructed from a 32-bit 'salt' derived
@@ -118,34 +161,57 @@ pub fn new_tls12(scs: &'static SupportedCipherSuite,
                                                             write_key,
                                                             write_iv)))
         }
-    }
+
+        // TLS1.2 needs to know how a suite builds its nonces, which
+        // a provider's AEAD alone doesn't say.
+        BulkAlgorithm::Custom => {
+            return Err(TLSError::General("TLS1.2 ciphersuite has no cipher_factory".to_string()));
+        }
+    })
 }
 
-pub fn new_tls13_read(scs: &'static SupportedCipherSuite,
-                      secret: &hkdf::Prk) -> Box<dyn MessageDecrypter> {
-    let key = derive_traffic_key(secret, scs.get_aead_alg());
+/// Make a TLS1.3 decrypter for `scs` from the traffic secret `secret`.
+/// Like `new_tls12`, this fails if `provider` lacks the suite's AEAD.
+pub fn new_tls13_read(provider: &dyn CryptoProvider,
+                      scs: &'static SupportedCipherSuite,
+                      secret: &Prk) -> Result<Box<dyn MessageDecrypter>, TLSError> {
+    if let Some(factory) = scs.cipher_factory {
+        let key = hkdf_expand(secret, scs.enc_key_len, b"key", &[]);
+        return Ok(factory.new_tls13_decrypter(&key, derive_traffic_iv(secret).value()));
+    }
+
+    let aead_alg = provider.suite_aead(scs)?;
+    let key = derive_traffic_key(secret, aead_alg);
     let iv = derive_traffic_iv(secret);
 
-    Box::new(TLS13MessageDecrypter::new(key, iv))
+    Ok(Box::new(TLS13MessageDecrypter::new(key, aead_alg.tag_len(), iv)))
 }
 
-pub fn new_tls13_write(scs: &'static SupportedCipherSuite,
-                       secret: &hkdf::Prk) -> Box<dyn MessageEncrypter> {
-    let key = derive_traffic_key(secret, scs.get_aead_alg());
+/// Make a TLS1.3 encrypter, as for `new_tls13_read`.
+pub fn new_tls13_write(provider: &dyn CryptoProvider,
+                       scs: &'static SupportedCipherSuite,
+                       secret: &Prk) -> Result<Box<dyn MessageEncrypter>, TLSError> {
+    if let Some(factory) = scs.cipher_factory {
+        let key = hkdf_expand(secret, scs.enc_key_len, b"key", &[]);
+        return Ok(factory.new_tls13_encrypter(&key, derive_traffic_iv(secret).value()));
+    }
+
+    let aead_alg = provider.suite_aead(scs)?;
+    let key = derive_traffic_key(secret, aead_alg);
     let iv = derive_traffic_iv(secret);
 
-    Box::new(TLS13MessageEncrypter::new(key, iv))
+    Ok(Box::new(TLS13MessageEncrypter::new(key, aead_alg.tag_len(), iv)))
 }
 
 /// A `MessageEncrypter` for AES-GCM AEAD ciphersuites. TLS 1.2 only.
 pub struct GCMMessageEncrypter {
-    enc_key: aead::LessSafeKey,
+    enc_key: Box<dyn AeadKey>,
     iv: Iv,
 }
 
 /// A `MessageDecrypter` for AES-GCM AEAD ciphersuites.  TLS1.2 only.
 pub struct GCMMessageDecrypter {
-    dec_key: aead::LessSafeKey,
+    dec_key: Box<dyn AeadKey>,
     dec_salt: [u8; 4],
 }
 
@@ -166,22 +232,21 @@ impl MessageDecrypter for GCMMessageDecrypter {
             let mut nonce = [0u8; 12];
             nonce.as_mut().write_all(&self.dec_salt).unwrap();
             nonce[4..].as_mut().write_all(&buf[..8]).unwrap();
-            aead::Nonce::assume_unique_for_key(nonce)
+            nonce
         };
 
         let aad = make_tls12_aad(seq, msg.typ, msg.version, buf.len() - GCM_OVERHEAD);
 
-        let plain_len = self.dec_key.open_within(nonce,
-                                                 aad,
-                                                 &mut buf,
-                                                 GCM_EXPLICIT_NONCE_LEN..)
-            .map_err(|_| TLSError::DecryptError)?
-            .len();
+        let plain_len = self.dec_key.open_in_place(nonce,
+                                                   &aad,
+                                                   &mut buf[GCM_EXPLICIT_NONCE_LEN..])
+            .map_err(|_| TLSError::DecryptError)?;
 
         if plain_len > MAX_FRAGMENT_LEN {
             return Err(TLSError::PeerSentOversizedRecord);
         }
 
+        buf.drain(..GCM_EXPLICIT_NONCE_LEN);
         buf.truncate(plain_len);
 
         Ok(Message {
@@ -197,13 +262,12 @@ impl MessageEncrypter for GCMMessageEncrypter {
         let nonce = make_tls13_nonce(&self.iv, seq);
         let aad = make_tls12_aad(seq, msg.typ, msg.version, msg.payload.len());
 
-        let total_len = msg.payload.len() + self.enc_key.algorithm().tag_len();
-        let mut payload = Vec::with_capacity(GCM_EXPLICIT_NONCE_LEN + total_len);
-        payload.extend_from_slice(&nonce.as_ref()[4..]);
+        let mut payload = Vec::with_capacity(GCM_OVERHEAD + msg.payload.len());
+        payload.extend_from_slice(&nonce[4..]);
         payload.extend_from_slice(&msg.payload);
 
-        self.enc_key.seal_in_place_separate_tag(nonce, aad, &mut payload[GCM_EXPLICIT_NONCE_LEN..])
-            .map(|tag| payload.extend(tag.as_ref()))
+        self.enc_key.seal_in_place_separate_tag(nonce, &aad, &mut payload[GCM_EXPLICIT_NONCE_LEN..])
+            .map(|tag| payload.extend(tag))
             .map_err(|_| TLSError::General("encrypt failed".to_string()))?;
 
         Ok(Message {
@@ -215,25 +279,21 @@ impl MessageEncrypter for GCMMessageEncrypter {
 }
 
 impl GCMMessageEncrypter {
-    fn new(alg: &'static aead::Algorithm, enc_key: &[u8], iv: Iv)
+    fn new(alg: &dyn Aead, enc_key: &[u8], iv: Iv)
            -> GCMMessageEncrypter {
-        let key = aead::UnboundKey::new(alg, enc_key)
-            .unwrap();
         GCMMessageEncrypter {
-            enc_key: aead::LessSafeKey::new(key),
+            enc_key: alg.new_key(enc_key),
             iv,
         }
     }
 }
 
 impl GCMMessageDecrypter {
-    fn new(alg: &'static aead::Algorithm,
+    fn new(alg: &dyn Aead,
            dec_key: &[u8],
            dec_iv: &[u8]) -> GCMMessageDecrypter {
-        let key = aead::UnboundKey::new(alg, dec_key)
-            .unwrap();
         let mut ret = GCMMessageDecrypter {
-            dec_key: aead::LessSafeKey::new(key),
+            dec_key: alg.new_key(dec_key),
             dec_salt: [0u8; 4],
         };
 
@@ -244,37 +304,25 @@ impl GCMMessageDecrypter {
 }
 
 /// A TLS 1.3 write or read IV.
-pub(crate) struct Iv([u8; ring::aead::NONCE_LEN]);
+pub(crate) struct Iv([u8; NONCE_LEN]);
 
 impl Iv {
-    pub(crate) fn new(value: [u8; ring::aead::NONCE_LEN]) -> Self {
+    pub(crate) fn new(value: [u8; NONCE_LEN]) -> Self {
         Self(value)
     }
 
-    pub(crate) fn value(&self) -> &[u8; 12] { &self.0 }
-}
-
-pub(crate) struct IvLen;
-
-impl hkdf::KeyType for IvLen {
-    fn len(&self) -> usize { aead::NONCE_LEN }
-}
-
-impl From<hkdf::Okm<'_, IvLen>> for Iv {
-    fn from(okm: hkdf::Okm<IvLen>) -> Self {
-        let mut r = Iv(Default::default());
-        okm.fill(&mut r.0[..]).unwrap();
-        r
-    }
+    pub(crate) fn value(&self) -> &[u8; NONCE_LEN] { &self.0 }
 }
 
 struct TLS13MessageEncrypter {
-    enc_key: aead::LessSafeKey,
+    enc_key: Box<dyn AeadKey>,
+    tag_len: usize,
     iv: Iv,
 }
 
 struct TLS13MessageDecrypter {
-    dec_key: aead::LessSafeKey,
+    dec_key: Box<dyn AeadKey>,
+    tag_len: usize,
     iv: Iv,
 }
 
@@ -290,30 +338,30 @@ fn unpad_tls13(v: &mut Vec<u8>) -> ContentType {
     }
 }
 
-fn make_tls13_nonce(iv: &Iv, seq: u64) -> ring::aead::Nonce {
-    let mut nonce = [0u8; ring::aead::NONCE_LEN];
+fn make_tls13_nonce(iv: &Iv, seq: u64) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
     codec::put_u64(seq, &mut nonce[4..]);
 
     nonce.iter_mut().zip(iv.0.iter()).for_each(|(nonce, iv)| {
         *nonce ^= *iv;
     });
 
-    aead::Nonce::assume_unique_for_key(nonce)
+    nonce
 }
 
-fn make_tls13_aad(len: usize) -> ring::aead::Aad<[u8; 1 + 2 + 2]>{
-    ring::aead::Aad::from([
+fn make_tls13_aad(len: usize) -> [u8; 1 + 2 + 2] {
+    [
         0x17, // ContentType::ApplicationData
         0x3, // ProtocolVersion (major)
         0x3, // ProtocolVersion (minor)
         (len >> 8) as u8,
         len as u8,
-    ])
+    ]
 }
 
 impl MessageEncrypter for TLS13MessageEncrypter {
     fn encrypt(&self, msg: BorrowMessage, seq: u64) -> Result<Message, TLSError> {
-        let total_len = msg.payload.len() + 1 + self.enc_key.algorithm().tag_len();
+        let total_len = msg.payload.len() + 1 + self.tag_len;
         let mut buf = Vec::with_capacity(total_len);
         buf.extend_from_slice(&msg.payload);
         msg.typ.encode(&mut buf);
@@ -321,7 +369,7 @@ impl MessageEncrypter for TLS13MessageEncrypter {
         let nonce = make_tls13_nonce(&self.iv, seq);
         let aad = make_tls13_aad(total_len);
 
-        self.enc_key.seal_in_place_append_tag(nonce, aad, &mut buf)
+        self.enc_key.seal_in_place_append_tag(nonce, &aad, &mut buf)
             .map_err(|_| TLSError::General("encrypt failed".to_string()))?;
 
         Ok(Message {
@@ -338,15 +386,14 @@ impl MessageDecrypter for TLS13MessageDecrypter {
             .ok_or(TLSError::DecryptError)?;
         let mut buf = payload.0;
 
-        if buf.len() < self.dec_key.algorithm().tag_len() {
+        if buf.len() < self.tag_len {
             return Err(TLSError::DecryptError);
         }
 
         let nonce = make_tls13_nonce(&self.iv, seq);
         let aad = make_tls13_aad(buf.len());
-        let plain_len = self.dec_key.open_in_place(nonce, aad, &mut buf)
-            .map_err(|_| TLSError::DecryptError)?
-            .len();
+        let plain_len = self.dec_key.open_in_place(nonce, &aad, &mut buf)
+            .map_err(|_| TLSError::DecryptError)?;
 
         buf.truncate(plain_len);
 
@@ -373,28 +420,75 @@ impl MessageDecrypter for TLS13MessageDecrypter {
 }
 
 impl TLS13MessageEncrypter {
-    fn new(key: aead::UnboundKey, enc_iv: Iv) -> TLS13MessageEncrypter {
+    fn new(key: Box<dyn AeadKey>, tag_len: usize, enc_iv: Iv) -> TLS13MessageEncrypter {
         TLS13MessageEncrypter {
-            enc_key: aead::LessSafeKey::new(key),
+            enc_key: key,
+            tag_len,
             iv: enc_iv,
         }
     }
 }
 
 impl TLS13MessageDecrypter {
-    fn new(key: aead::UnboundKey, dec_iv: Iv) -> TLS13MessageDecrypter {
+    fn new(key: Box<dyn AeadKey>, tag_len: usize, dec_iv: Iv) -> TLS13MessageDecrypter {
         TLS13MessageDecrypter {
-            dec_key: aead::LessSafeKey::new(key),
+            dec_key: key,
+            tag_len,
             iv: dec_iv,
         }
     }
 }
 
+/// Makes records for the TLS1.3 AES-128-CCM and AES-128-CCM-8
+/// ciphersuites, which differ only in tag length.
+#[cfg(feature = "aes_ccm")]
+#[derive(Debug)]
+pub(crate) struct CCMCipherFactory {
+    tag_len: usize,
+    new_key: fn(&[u8]) -> Box<dyn AeadKey>,
+}
+
+#[cfg(feature = "aes_ccm")]
+pub(crate) static AES_128_CCM: CCMCipherFactory = CCMCipherFactory {
+    tag_len: 16,
+    new_key: |key| Box::new(ccm::Aes128Ccm::new(key)),
+};
+#[cfg(feature = "aes_ccm")]
+pub(crate) static AES_128_CCM_8: CCMCipherFactory = CCMCipherFactory {
+    tag_len: 8,
+    new_key: |key| Box::new(ccm::Aes128Ccm8::new(key)),
+};
+
+#[cfg(feature = "aes_ccm")]
+impl MessageCipherFactory for CCMCipherFactory {
+    // The AES-CCM ciphersuites are TLS1.3 only, so these are never
+    // asked for; refuse every record if they are.
+    fn new_tls12_decrypter(&self, _key: &[u8], _iv: &[u8]) -> Box<dyn MessageDecrypter> {
+        MessageDecrypter::invalid()
+    }
+
+    fn new_tls12_encrypter(&self, _key: &[u8], _iv: &[u8], _extra: &[u8]) -> Box<dyn MessageEncrypter> {
+        MessageEncrypter::invalid()
+    }
+
+    fn new_tls13_decrypter(&self, key: &[u8], iv: &[u8]) -> Box<dyn MessageDecrypter> {
+        Box::new(TLS13MessageDecrypter::new((self.new_key)(key),
+                                            self.tag_len,
+                                            Iv::new(iv.try_into().unwrap())))
+    }
+
+    fn new_tls13_encrypter(&self, key: &[u8], iv: &[u8]) -> Box<dyn MessageEncrypter> {
+        Box::new(TLS13MessageEncrypter::new((self.new_key)(key),
+                                            self.tag_len,
+                                            Iv::new(iv.try_into().unwrap())))
+    }
+}
+
 /// The RFC7905/RFC7539 ChaCha20Poly1305 construction.
 /// This implementation does the AAD construction required in TLS1.2.
 /// TLS1.3 uses `TLS13MessageEncrypter`.
 pub struct ChaCha20Poly1305MessageEncrypter {
-    enc_key: aead::LessSafeKey,
+    enc_key: Box<dyn AeadKey>,
     enc_offset: Iv,
 }
 
@@ -402,31 +496,27 @@ pub struct ChaCha20Poly1305MessageEncrypter {
 /// This implementation does the AAD construction required in TLS1.2.
 /// TLS1.3 uses `TLS13MessageDecrypter`.
 pub struct ChaCha20Poly1305MessageDecrypter {
-    dec_key: aead::LessSafeKey,
+    dec_key: Box<dyn AeadKey>,
     dec_offset: Iv,
 }
 
 impl ChaCha20Poly1305MessageEncrypter {
-    fn new(alg: &'static aead::Algorithm,
+    fn new(alg: &dyn Aead,
            enc_key: &[u8],
            enc_iv: Iv) -> ChaCha20Poly1305MessageEncrypter {
-        let key = aead::UnboundKey::new(alg, enc_key)
-            .unwrap();
         ChaCha20Poly1305MessageEncrypter {
-            enc_key: aead::LessSafeKey::new(key),
+            enc_key: alg.new_key(enc_key),
             enc_offset: enc_iv,
         }
     }
 }
 
 impl ChaCha20Poly1305MessageDecrypter {
-    fn new(alg: &'static aead::Algorithm,
+    fn new(alg: &dyn Aead,
            dec_key: &[u8],
            dec_iv: Iv) -> ChaCha20Poly1305MessageDecrypter {
-        let key = aead::UnboundKey::new(alg, dec_key)
-            .unwrap();
         ChaCha20Poly1305MessageDecrypter {
-            dec_key: aead::LessSafeKey::new(key),
+            dec_key: alg.new_key(dec_key),
             dec_offset: dec_iv,
         }
     }
@@ -447,9 +537,8 @@ impl MessageDecrypter for ChaCha20Poly1305MessageDecrypter {
         let nonce = make_tls13_nonce(&self.dec_offset, seq);
         let aad = make_tls12_aad(seq, msg.typ, msg.version, buf.len() - CHACHAPOLY1305_OVERHEAD);
 
-        let plain_len = self.dec_key.open_in_place(nonce, aad, &mut buf)
-            .map_err(|_| TLSError::DecryptError)?
-            .len();
+        let plain_len = self.dec_key.open_in_place(nonce, &aad, &mut buf)
+            .map_err(|_| TLSError::DecryptError)?;
 
         if plain_len > MAX_FRAGMENT_LEN {
             return Err(TLSError::PeerSentOversizedRecord);
@@ -470,11 +559,11 @@ impl MessageEncrypter for ChaCha20Poly1305MessageEncrypter {
         let nonce = make_tls13_nonce(&self.enc_offset, seq);
         let aad = make_tls12_aad(seq, msg.typ, msg.version, msg.payload.len());
 
-        let total_len = msg.payload.len() + self.enc_key.algorithm().tag_len();
+        let total_len = msg.payload.len() + CHACHAPOLY1305_OVERHEAD;
         let mut buf = Vec::with_capacity(total_len);
         buf.extend_from_slice(&msg.payload);
 
-        self.enc_key.seal_in_place_append_tag(nonce, aad, &mut buf)
+        self.enc_key.seal_in_place_append_tag(nonce, &aad, &mut buf)
             .map_err(|_| TLSError::General("encrypt failed".to_string()))?;
 
         Ok(Message {
@@ -502,3 +591,76 @@ impl MessageDecrypter for InvalidMessageDecrypter {
         Err(TLSError::DecryptError)
     }
 }
+
+#[cfg(all(test, feature = "aes_ccm"))]
+mod test {
+    use super::{new_tls13_read, new_tls13_write};
+    use crate::crypto::{CryptoProvider, HashAlgorithm};
+    use crate::crypto::ring::RING;
+    use crate::error::TLSError;
+    use crate::key_schedule::Prk;
+    use crate::msgs::enums::{ContentType, ProtocolVersion};
+    use crate::msgs::message::{BorrowMessage, Message, MessagePayload};
+    use crate::suites::{SupportedCipherSuite, TLS13_AES_128_CCM_SHA256,
+                        TLS13_AES_128_CCM_8_SHA256};
+
+    /// Protect a record with the server handshake traffic secret
+    /// from RFC8448 section 3, and check we get `expected` and
+    /// can get it back again.  There are no published TLS1.3 CCM
+    /// records, so `expected` was computed separately with Python's
+    /// `cryptography` package; ccm.rs tests the AEAD itself against
+    /// the NIST vectors.
+    fn check_tls13_record(scs: &'static SupportedCipherSuite, expected: &[u8]) {
+        let hmac = RING.hmac(HashAlgorithm::SHA256).unwrap();
+        let secret = Prk::new_less_safe(hmac, &hex!("b67b7d690cc16c4e75e54213cb2d37b4
+                                                     e9c912bcded9105d42befd59d391ad38"));
+        let plain = b"hello constrained world";
+
+        let enc = new_tls13_write(&RING, scs, &secret).unwrap();
+        let record = enc.encrypt(BorrowMessage {
+            typ: ContentType::Handshake,
+            version: ProtocolVersion::TLSv1_3,
+            payload: plain,
+        }, 1).unwrap();
+        let mut record = record;
+        assert_eq!(record.typ, ContentType::ApplicationData);
+        assert_eq!(record.take_opaque_payload().unwrap().0, expected.to_vec());
+
+        let dec = new_tls13_read(&RING, scs, &secret).unwrap();
+        let mut got = dec.decrypt(opaque(expected.to_vec()), 1).unwrap();
+        assert_eq!(got.typ, ContentType::Handshake);
+        assert_eq!(got.take_opaque_payload().unwrap().0, plain.to_vec());
+
+        // wrong sequence number
+        assert_eq!(dec.decrypt(opaque(expected.to_vec()), 2).err(),
+                   Some(TLSError::DecryptError));
+
+        // damaged tag
+        let mut damaged = expected.to_vec();
+        *damaged.last_mut().unwrap() ^= 1;
+        assert_eq!(dec.decrypt(opaque(damaged), 1).err(),
+                   Some(TLSError::DecryptError));
+    }
+
+    fn opaque(payload: Vec<u8>) -> Message {
+        Message {
+            typ: ContentType::ApplicationData,
+            version: ProtocolVersion::TLSv1_2,
+            payload: MessagePayload::new_opaque(payload),
+        }
+    }
+
+    #[test]
+    fn test_tls13_aes_128_ccm() {
+        check_tls13_record(&TLS13_AES_128_CCM_SHA256,
+                           &hex!("90d7ffc977c28b3402bf3d1136dd5a033b1e2c52c8e97e5d
+                                  18cda306e67675be399a85e48555e9df"));
+    }
+
+    #[test]
+    fn test_tls13_aes_128_ccm_8() {
+        check_tls13_record(&TLS13_AES_128_CCM_8_SHA256,
+                           &hex!("90d7ffc977c28b3402bf3d1136dd5a033b1e2c52c8e97e5d
+                                  a9a532b39e1397eb"));
+    }
+}
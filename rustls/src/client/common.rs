@@ -7,15 +7,19 @@ use crate::msgs::handshake::ClientExtension;
 use crate::msgs::persist;
 use crate::msgs::enums::ExtensionType;
 use crate::msgs::enums::NamedGroup;
+use crate::msgs::enums::CertificateType;
 use crate::session::SessionRandoms;
+use crate::crypto::CryptoProvider;
 use crate::hash_hs;
 use crate::sign;
-use crate::suites;
+use crate::kx;
+use crate::server_name::ServerName;
 #[cfg(feature = "logging")]
 use crate::log::trace;
-use webpki;
 
 use std::mem;
+use crate::ech::{ECHHandshakeData, ECHClientState};
+#[cfg(feature = "esni")]
 use crate::esni::ESNIHandshakeData;
 
 pub struct ServerCertDetails {
@@ -52,6 +56,13 @@ impl ServerKXDetails {
     }
 }
 
+/// The ways we can hide the server name from observers.
+pub enum EncryptedHello {
+    ECH(ECHHandshakeData),
+    #[cfg(feature = "esni")]
+    ESNI(ESNIHandshakeData),
+}
+
 pub struct HandshakeDetails {
     pub resuming_session: Option<persist::ClientSessionValue>,
     pub transcript: hash_hs::HandshakeHash,
@@ -60,25 +71,49 @@ pub struct HandshakeDetails {
     pub using_ems: bool,
     pub session_id: SessionID,
     pub sent_tls13_fake_ccs: bool,
-    pub dns_name: webpki::DNSName,
+    pub server_name: ServerName,
+    pub server_cert_type: CertificateType,
+    pub client_cert_type: CertificateType,
+    pub ech: Option<ECHClientState>,
+    #[cfg(feature = "esni")]
     pub esni: Option<ESNIHandshakeData>,
+    #[cfg(feature = "esni")]
+    pub esni_nonce: Option<[u8; 16]>,
     pub extra_exts: Vec<ClientExtension>,
 }
 
 impl HandshakeDetails {
-    pub fn new(host_name: webpki::DNSName,
-               esni: Option<ESNIHandshakeData>,
+    pub fn new(provider: &dyn CryptoProvider,
+               server_name: ServerName,
+               encrypted_hello: Option<EncryptedHello>,
                extra_exts: Vec<ClientExtension>) -> HandshakeDetails {
+        let mut ech = None;
+        #[cfg(feature = "esni")]
+        let mut esni = None;
+
+        match encrypted_hello {
+            Some(EncryptedHello::ECH(data)) => ech = Some(ECHClientState::new(provider, data)),
+            #[cfg(feature = "esni")]
+            Some(EncryptedHello::ESNI(data)) => esni = Some(data),
+            None => {}
+        }
+
         HandshakeDetails {
             resuming_session: None,
             transcript: hash_hs::HandshakeHash::new(),
             hash_at_client_recvd_server_hello: Vec::new(),
-            randoms: SessionRandoms::for_client(),
+            randoms: SessionRandoms::for_client(provider),
             using_ems: false,
             session_id: SessionID::empty(),
             sent_tls13_fake_ccs: false,
-            dns_name: host_name,
+            server_name,
+            server_cert_type: CertificateType::X509,
+            client_cert_type: CertificateType::X509,
+            ech,
+            #[cfg(feature = "esni")]
             esni,
+            #[cfg(feature = "esni")]
+            esni_nonce: None,
             extra_exts,
         }
     }
@@ -86,7 +121,7 @@ impl HandshakeDetails {
 
 pub struct ClientHelloDetails {
     pub sent_extensions: Vec<ExtensionType>,
-    pub offered_key_shares: Vec<suites::KeyExchange>,
+    pub offered_key_shares: Vec<kx::KeyExchange>,
 }
 
 impl ClientHelloDetails {
@@ -103,14 +138,14 @@ impl ClientHelloDetails {
             .any(|share| share.group == group)
     }
 
-    pub fn find_key_share(&mut self, group: NamedGroup) -> Option<suites::KeyExchange> {
+    pub fn find_key_share(&mut self, group: NamedGroup) -> Option<kx::KeyExchange> {
         self.offered_key_shares.iter()
             .position(|s| s.group == group)
             .map(|idx| self.offered_key_shares.remove(idx))
     }
 
     pub fn find_key_share_and_discard_others(&mut self, group: NamedGroup)
-            -> Option<suites::KeyExchange> {
+            -> Option<kx::KeyExchange> {
         match self.find_key_share(group) {
             Some(group) => {
                 self.offered_key_shares.clear();
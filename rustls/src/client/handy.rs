@@ -1,10 +1,22 @@
 use crate::msgs::enums::SignatureScheme;
+use crate::msgs::base::{PayloadU16, PayloadU24};
+use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::persist;
+use crate::time_provider::{TimeProvider, SystemTimeProvider};
 use crate::sign;
 use crate::key;
 use crate::client;
+#[cfg(feature = "logging")]
+use crate::log::debug;
 
+use std::cmp;
 use std::collections;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 /// An implementor of `StoresClientSessions` which does nothing.
 pub struct NoClientSessionStorage {}
@@ -63,6 +75,193 @@ impl client::StoresClientSessions for ClientSessionMemoryCache {
     }
 }
 
+/// An implementor of `StoresClientSessions` that keeps sessions
+/// in a file, so short-lived processes can resume sessions (and
+/// send early data) from earlier ones.
+///
+/// Any number of processes can share the file.  Updates are made
+/// under an advisory lock, by writing a new file and renaming it
+/// into place, so readers never see a partly-written file.  On unix
+/// the file is only readable by its owner, since anyone who can read
+/// it can resume our sessions.
+///
+/// Sessions whose tickets have expired are dropped whenever the
+/// file is updated, and it holds at most a limited number of
+/// entries, dropping the oldest first.
+pub struct ClientSessionFileCache {
+    path: PathBuf,
+    max_entries: usize,
+    time_provider: Arc<dyn TimeProvider>,
+}
+
+/// How long to wait for another process to finish updating a
+/// `ClientSessionFileCache`, before giving up on storing a session.
+const FILE_CACHE_LOCK_ATTEMPTS: u32 = 100;
+const FILE_CACHE_LOCK_DELAY: Duration = Duration::from_millis(10);
+
+impl ClientSessionFileCache {
+    /// Make a new ClientSessionFileCache keeping sessions in the
+    /// file at `path`, which is created when the first session is
+    /// stored.  `size` is the maximum number of stored sessions; a
+    /// `size` of zero is treated as one.
+    ///
+    /// Files at `path` with `.lock` and `.tmp` appended are used
+    /// while updating it.  The `.lock` file is left in place.
+    pub fn new<P: AsRef<Path>>(path: P, size: usize) -> Arc<ClientSessionFileCache> {
+        ClientSessionFileCache::new_with_time_provider(path, size, Arc::new(SystemTimeProvider))
+    }
+
+    /// Like `new`, but sessions are expired according to the time
+    /// from `time_provider`.
+    pub fn new_with_time_provider<P: AsRef<Path>>(path: P,
+                                                  size: usize,
+                                                  time_provider: Arc<dyn TimeProvider>)
+                                                  -> Arc<ClientSessionFileCache> {
+        Arc::new(ClientSessionFileCache {
+            path: path.as_ref().to_path_buf(),
+            max_entries: cmp::max(size, 1),
+            time_provider,
+        })
+    }
+
+    /// `path`, with `suffix` appended to its file name.
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(suffix);
+        PathBuf::from(path)
+    }
+
+    /// Read the stored entries, oldest first.  A missing or
+    /// unreadable file holds nothing.
+    fn read_entries(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Vec::new(),
+            #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+            Err(err) => {
+                debug!("Cannot read session cache {:?}: {}", self.path, err);
+                return Vec::new();
+            }
+        };
+
+        let mut r = Reader::init(&bytes);
+        let mut entries = Vec::new();
+        while r.any_left() {
+            match (PayloadU16::read(&mut r), PayloadU24::read(&mut r)) {
+                (Some(key), Some(value)) => entries.push((key.0, value.0)),
+                _ => {
+                    debug!("Session cache {:?} is corrupt", self.path);
+                    return Vec::new();
+                }
+            }
+        }
+
+        entries
+    }
+
+    fn write_entries(&self, entries: Vec<(Vec<u8>, Vec<u8>)>) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        for (key, value) in entries {
+            PayloadU16::new(key).encode(&mut bytes);
+            PayloadU24::new(value).encode(&mut bytes);
+        }
+
+        // We hold the lock, so nobody else is using this.  It's
+        // removed first so it gets our permissions, not those of
+        // any leftover file.
+        let tmp = self.sibling(".tmp");
+        let _ = fs::remove_file(&tmp);
+        let mut file = private_file_options().create_new(true).open(&tmp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    fn lock(&self) -> io::Result<FileCacheLock> {
+        let file = private_file_options()
+            .create(true)
+            .open(self.sibling(".lock"))?;
+
+        for _ in 0..FILE_CACHE_LOCK_ATTEMPTS {
+            match file.try_lock() {
+                Ok(()) => return Ok(FileCacheLock(file)),
+                Err(fs::TryLockError::WouldBlock) => thread::sleep(FILE_CACHE_LOCK_DELAY),
+                Err(fs::TryLockError::Error(err)) => return Err(err),
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::TimedOut, "session cache is locked"))
+    }
+}
+
+/// Options for opening a file for writing which, if created, only
+/// its owner can read.
+fn private_file_options() -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+
+    #[cfg(unix)] {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options
+}
+
+/// Whether `value` is a session whose ticket has expired.  Other
+/// things (like key exchange hints) never expire, and nor does
+/// anything if we don't know the time.
+fn is_expired(value: &[u8], now: Option<u64>) -> bool {
+    match (persist::ClientSessionValue::read_bytes(value), now) {
+        (Some(session), Some(now)) => session.has_expired(now),
+        _ => false,
+    }
+}
+
+/// Exclusive permission to update a `ClientSessionFileCache`,
+/// until dropped.  This is an advisory lock on its `.lock` file
+/// (`flock` or `LockFileEx`), so the OS releases it if we die.
+struct FileCacheLock(fs::File);
+
+impl Drop for FileCacheLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+impl client::StoresClientSessions for ClientSessionFileCache {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        let result = self.lock().and_then(|_lock| {
+            let now = self.time_provider.now();
+            let mut entries = self.read_entries();
+            entries.retain(|(k, v)| *k != key && !is_expired(v, now));
+            entries.push((key, value));
+
+            let excess = entries.len().saturating_sub(self.max_entries);
+            entries.drain(..excess);
+            self.write_entries(entries)
+        });
+
+        match result {
+            Ok(()) => true,
+            #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+            Err(err) => {
+                debug!("Cannot update session cache {:?}: {}", self.path, err);
+                false
+            }
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let now = self.time_provider.now();
+        self.read_entries()
+            .into_iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+            .filter(|v| !is_expired(v, now))
+    }
+}
+
 pub struct FailResolveClientCert {}
 
 impl client::ResolvesClientCert for FailResolveClientCert {
@@ -87,6 +286,18 @@ impl AlwaysResolvesClientCert {
             .expect("Invalid private key");
         AlwaysResolvesClientCert(sign::CertifiedKey::new(chain, Arc::new(key)))
     }
+
+    pub fn new_raw_public_key(spki: Vec<u8>,
+                              priv_key: &key::PrivateKey) -> AlwaysResolvesClientCert {
+        let key = sign::any_supported_type(priv_key)
+            .expect("Invalid private key");
+        AlwaysResolvesClientCert(sign::CertifiedKey::new_raw_public_key(spki, Arc::new(key)))
+    }
+
+    pub fn new_with_signing_key(chain: Vec<key::Certificate>,
+                                key: Box<dyn sign::SigningKey>) -> AlwaysResolvesClientCert {
+        AlwaysResolvesClientCert(sign::CertifiedKey::new(chain, Arc::new(key)))
+    }
 }
 
 impl client::ResolvesClientCert for AlwaysResolvesClientCert {
@@ -162,4 +373,192 @@ mod test {
 
         assert_eq!(count, 4);
     }
+
+    struct FixedTime(Mutex<u64>);
+
+    impl TimeProvider for FixedTime {
+        fn now(&self) -> Option<u64> {
+            Some(*self.0.lock().unwrap())
+        }
+    }
+
+    fn session(epoch: u64, lifetime: u32) -> Vec<u8> {
+        use crate::msgs::enums::{CipherSuite, ProtocolVersion};
+        use crate::msgs::handshake::SessionID;
+
+        let mut value = persist::ClientSessionValue::new(ProtocolVersion::TLSv1_3,
+                                                         CipherSuite::TLS13_AES_128_GCM_SHA256,
+                                                         &SessionID::empty(),
+                                                         vec![0x01; 32],
+                                                         vec![0x02; 32]);
+        value.set_times(epoch, lifetime, 0);
+        value.get_encoding()
+    }
+
+    #[test]
+    fn test_clientsessionfilecache_persists_put() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sessions");
+        let c = ClientSessionFileCache::new(&path, 4);
+        assert_eq!(c.get(&[0x01]), None);
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x02]));
+
+        // Another process sees it.
+        let c = ClientSessionFileCache::new(&path, 4);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x02]));
+        assert!(!dir.path().join("sessions.tmp").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_clientsessionfilecache_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sessions");
+
+        // A leftover temporary file doesn't lend us its permissions.
+        let tmp = dir.path().join("sessions.tmp");
+        fs::write(&tmp, b"").unwrap();
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let c = ClientSessionFileCache::new(&path, 4);
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        let mode = |p| fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(&dir.path().join("sessions.lock")), 0o600);
+    }
+
+    #[test]
+    fn test_clientsessionfilecache_stores_one_when_size_is_zero() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let c = ClientSessionFileCache::new(dir.path().join("sessions"), 0);
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x02]));
+        assert_eq!(c.put(vec![0x03], vec![0x04]), true);
+        assert_eq!(c.get(&[0x01]), None);
+        assert_eq!(c.get(&[0x03]), Some(vec![0x04]));
+    }
+
+    #[test]
+    fn test_clientsessionfilecache_overwrites_put() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let c = ClientSessionFileCache::new(dir.path().join("sessions"), 4);
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.put(vec![0x03], vec![0x04]), true);
+        assert_eq!(c.put(vec![0x01], vec![0x05; 0x10000]), true);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x05; 0x10000]));
+        assert_eq!(c.get(&[0x03]), Some(vec![0x04]));
+    }
+
+    #[test]
+    fn test_clientsessionfilecache_drops_oldest_to_maintain_size_invariant() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let c = ClientSessionFileCache::new(dir.path().join("sessions"), 4);
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.put(vec![0x03], vec![0x04]), true);
+        assert_eq!(c.put(vec![0x05], vec![0x06]), true);
+        assert_eq!(c.put(vec![0x07], vec![0x08]), true);
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.put(vec![0x09], vec![0x0a]), true);
+
+        assert_eq!(c.get(&[0x03]), None);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x02]));
+        assert_eq!(c.get(&[0x05]), Some(vec![0x06]));
+        assert_eq!(c.get(&[0x07]), Some(vec![0x08]));
+        assert_eq!(c.get(&[0x09]), Some(vec![0x0a]));
+    }
+
+    #[test]
+    fn test_clientsessionfilecache_prunes_expired_sessions() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sessions");
+        let time = Arc::new(FixedTime(Mutex::new(1000)));
+        let c = ClientSessionFileCache::new_with_time_provider(&path, 4, time.clone());
+        assert_eq!(c.put(vec![0x01], session(1000, 100)), true);
+        assert_eq!(c.put(vec![0x02], session(1000, 200)), true);
+        assert_eq!(c.put(vec![0x03], vec![0x04]), true);
+
+        *time.0.lock().unwrap() = 1150;
+        assert_eq!(c.get(&[0x01]), None);
+        assert_eq!(c.get(&[0x02]), Some(session(1000, 200)));
+        assert!(fs::read(&path).unwrap().len() > 2 * session(1000, 100).len());
+
+        assert_eq!(c.put(vec![0x05], vec![0x06]), true);
+        assert!(fs::read(&path).unwrap().len() < 2 * session(1000, 100).len());
+        assert_eq!(c.get(&[0x02]), Some(session(1000, 200)));
+        assert_eq!(c.get(&[0x03]), Some(vec![0x04]));
+    }
+
+    #[test]
+    fn test_clientsessionfilecache_waits_for_lock() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let c = ClientSessionFileCache::new(dir.path().join("sessions"), 4);
+        let lock = c.lock().unwrap();
+
+        let writer = {
+            let c = c.clone();
+            thread::spawn(move || c.put(vec![0x01], vec![0x02]))
+        };
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(c.get(&[0x01]), None);
+
+        drop(lock);
+        assert_eq!(writer.join().unwrap(), true);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x02]));
+    }
+
+    #[test]
+    fn test_clientsessionfilecache_gives_up_on_held_lock() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let c = ClientSessionFileCache::new(dir.path().join("sessions"), 4);
+        let _lock = c.lock().unwrap();
+        assert_eq!(c.put(vec![0x01], vec![0x02]), false);
+        assert_eq!(c.get(&[0x01]), None);
+    }
+
+    #[test]
+    fn test_clientsessionfilecache_ignores_unlocked_lock_file() {
+        // eg. left by a process which died while updating the file.
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::write(dir.path().join("sessions.lock"), b"").unwrap();
+
+        let c = ClientSessionFileCache::new(dir.path().join("sessions"), 4);
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x02]));
+    }
+
+    #[test]
+    fn test_clientsessionfilecache_serialises_concurrent_puts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sessions");
+
+        let writers = (0..8u8)
+            .map(|i| {
+                let c = ClientSessionFileCache::new(&path, 16);
+                thread::spawn(move || c.put(vec![i], vec![i; 100]))
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            assert_eq!(writer.join().unwrap(), true);
+        }
+
+        let c = ClientSessionFileCache::new(&path, 16);
+        for i in 0..8u8 {
+            assert_eq!(c.get(&[i]), Some(vec![i; 100]));
+        }
+    }
+
+    #[test]
+    fn test_clientsessionfilecache_ignores_corrupt_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sessions");
+        fs::write(&path, b"\x00\x01\x01\x00").unwrap();
+
+        let c = ClientSessionFileCache::new(&path, 4);
+        assert_eq!(c.get(&[0x01]), None);
+        assert_eq!(c.put(vec![0x01], vec![0x02]), true);
+        assert_eq!(c.get(&[0x01]), Some(vec![0x02]));
+    }
 }
@@ -4,38 +4,50 @@ use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::base::Payload;
 use crate::msgs::handshake::{HandshakePayload, HandshakeMessagePayload, ClientHelloPayload};
 use crate::msgs::handshake::{SessionID, Random};
-use crate::msgs::handshake::{ClientExtension, HasServerExtensions};
+use crate::msgs::handshake::{ClientExtension, HasServerExtensions, ECHClientHello};
 use crate::msgs::handshake::{ECPointFormatList, SupportedPointFormats};
 use crate::msgs::handshake::{ProtocolNameList, ConvertProtocolNameList};
 use crate::msgs::handshake::HelloRetryRequest;
+use crate::msgs::handshake::DigitallySignedStruct;
 use crate::msgs::handshake::{CertificateStatusRequest, SCTList};
-use crate::msgs::enums::{PSKKeyExchangeMode, ECPointFormat};
-use crate::msgs::codec::{Codec, Reader, encode_vec_u16};
+use crate::msgs::enums::{PSKKeyExchangeMode, ECPointFormat, CertificateType};
+use crate::msgs::codec::{Codec, Reader};
+#[cfg(feature = "esni")]
+use crate::msgs::codec::encode_vec_u16;
 use crate::msgs::persist;
 use crate::client::ClientSessionImpl;
 use crate::session::SessionSecrets;
 use crate::key_schedule::SecretKind;
 use crate::cipher;
+use crate::ech;
 use crate::suites;
+use crate::kx;
 use crate::verify;
+use crate::ct;
+use crate::sign;
+use crate::server_name::ServerName;
 use crate::rand;
-use crate::ticketer;
+use crate::time_provider::webpki_now;
+use crate::crypto::CryptoProvider;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
 use crate::error::TLSError;
-use crate::handshake::check_handshake_message;
+use crate::handshake::{check_handshake_message, check_message};
 #[cfg(feature = "quic")]
 use crate::{
     msgs::base::PayloadU16,
-    session::Protocol
+    session::Protocol,
+    quic,
 };
 
 use crate::client::common::{ServerCertDetails, HandshakeDetails};
 use crate::client::common::{ClientHelloDetails, ReceivedTicketDetails};
+use crate::client::common::EncryptedHello;
 use crate::client::{tls12, tls13};
 
+use ring::constant_time;
 use webpki;
-use crate::esni::ESNIHandshakeData;
+use std::mem;
 
 macro_rules! extract_handshake(
   ( $m:expr, $t:path ) => (
@@ -68,6 +80,74 @@ pub type NextStateOrError = Result<NextState, TLSError>;
 pub trait State {
     fn check_message(&self, m: &Message) -> CheckResult;
     fn handle(self: Box<Self>, sess: &mut ClientSessionImpl, m: Message) -> NextStateOrError;
+
+    /// The signature this state is waiting for, if any.  No messages
+    /// are processed until it arrives.
+    fn pending_signature(&self) -> Option<&sign::PendingSignature> {
+        None
+    }
+
+    /// Continue with `sig`, made for `pending_signature()`.
+    fn resume_with_signature(self: Box<Self>,
+                             _sess: &mut ClientSessionImpl,
+                             _sig: Vec<u8>) -> NextStateOrError {
+        unreachable!("no signature pending")
+    }
+}
+
+type ResumeWithSignature =
+    Box<dyn FnOnce(&mut ClientSessionImpl, DigitallySignedStruct) -> NextStateOrError + Send + Sync>;
+
+/// The handshake is paused until the application supplies a
+/// signature: see `sign::Signer::start_sign`.
+pub struct ExpectSignature {
+    pending: sign::PendingSignature,
+    resume: ResumeWithSignature,
+}
+
+impl ExpectSignature {
+    /// Sign `message` with `signer`, then continue the handshake
+    /// with `resume`: either now, or once the signature arrives.
+    pub fn sign<F>(sess: &mut ClientSessionImpl,
+                   signer: &dyn sign::Signer,
+                   message: Vec<u8>,
+                   resume: F) -> NextStateOrError
+        where F: FnOnce(&mut ClientSessionImpl, DigitallySignedStruct) -> NextStateOrError + Send + Sync + 'static
+    {
+        let scheme = signer.get_scheme();
+
+        match signer.start_sign(&message)? {
+            Some(sig) => resume(sess, DigitallySignedStruct::new(scheme, sig)),
+            None => {
+                debug!("Waiting for {:?} signature", scheme);
+                Ok(Box::new(ExpectSignature {
+                    pending: sign::PendingSignature::new(message, scheme),
+                    resume: Box::new(resume),
+                }))
+            }
+        }
+    }
+}
+
+impl State for ExpectSignature {
+    fn check_message(&self, m: &Message) -> CheckResult {
+        check_message(m, &[], &[])
+    }
+
+    fn handle(self: Box<Self>, _sess: &mut ClientSessionImpl, _m: Message) -> NextStateOrError {
+        unreachable!("no messages are processed while a signature is pending")
+    }
+
+    fn pending_signature(&self) -> Option<&sign::PendingSignature> {
+        Some(&self.pending)
+    }
+
+    fn resume_with_signature(self: Box<Self>,
+                             sess: &mut ClientSessionImpl,
+                             sig: Vec<u8>) -> NextStateOrError {
+        let dss = DigitallySignedStruct::new(self.pending.scheme(), sig);
+        (self.resume)(sess, dss)
+    }
 }
 
 pub fn illegal_param(sess: &mut ClientSessionImpl, why: &str) -> TLSError {
@@ -83,22 +163,24 @@ pub fn check_aligned_handshake(sess: &mut ClientSessionImpl) -> Result<(), TLSEr
     }
 }
 
-fn find_session(sess: &mut ClientSessionImpl, dns_name: webpki::DNSNameRef)
+/// Find a cached session for `server_name` which hasn't expired
+/// by `now`.
+fn find_session(sess: &mut ClientSessionImpl, server_name: &ServerName, now: u64)
                 -> Option<persist::ClientSessionValue> {
-    let key = persist::ClientSessionKey::session_for_dns_name(dns_name);
+    let key = persist::ClientSessionKey::session_for_server_name(server_name);
     let key_buf = key.get_encoding();
 
     let maybe_value = sess.config.session_persistence.get(&key_buf);
 
     if maybe_value.is_none() {
-        debug!("No cached session for {:?}", dns_name);
+        debug!("No cached session for {:?}", server_name);
         return None;
     }
 
     let value = maybe_value.unwrap();
     let mut reader = Reader::init(&value[..]);
     if let Some(result) = persist::ClientSessionValue::read(&mut reader) {
-        if result.has_expired(ticketer::timebase()) {
+        if result.has_expired(now) {
             None
         } else {
             #[cfg(feature = "quic")] {
@@ -114,17 +196,18 @@ fn find_session(sess: &mut ClientSessionImpl, dns_name: webpki::DNSNameRef)
     }
 }
 
-fn random_sessionid() -> SessionID {
+fn random_sessionid(provider: &dyn CryptoProvider) -> SessionID {
     let mut random_id = [0u8; 32];
-    rand::fill_random(&mut random_id);
+    rand::fill_random(provider, &mut random_id);
     SessionID::new(&random_id)
 }
 
 /// If we have a ticket, we use the sessionid as a signal that we're
 /// doing an abbreviated handshake.  See section 3.4 in RFC5077.
-fn random_sessionid_for_ticket(csv: &mut persist::ClientSessionValue) {
+fn random_sessionid_for_ticket(provider: &dyn CryptoProvider,
+                               csv: &mut persist::ClientSessionValue) {
     if !csv.ticket.0.is_empty() {
-        csv.session_id = random_sessionid();
+        csv.session_id = random_sessionid(provider);
     }
 }
 
@@ -133,17 +216,22 @@ struct InitialState {
 }
 
 impl InitialState {
-    fn new(host_name: webpki::DNSName,
-           esni: Option<ESNIHandshakeData>,
+    fn new(provider: &dyn CryptoProvider,
+           server_name: ServerName,
+           encrypted_hello: Option<EncryptedHello>,
            extra_exts: Vec<ClientExtension>) -> InitialState {
         InitialState {
-            handshake: HandshakeDetails::new(host_name, esni, extra_exts),
+            handshake: HandshakeDetails::new(provider, server_name, encrypted_hello, extra_exts),
         }
     }
 
-    fn emit_initial_client_hello(mut self, sess: &mut ClientSessionImpl) -> NextState {
+    fn emit_initial_client_hello(mut self, sess: &mut ClientSessionImpl) -> NextStateOrError {
         if sess.config.client_auth_cert_resolver.has_certs() {
             self.handshake.transcript.set_client_auth_enabled();
+
+            if let Some(ref mut ech) = self.handshake.ech {
+                ech.outer_transcript.set_client_auth_enabled();
+            }
         }
         let hello_details = ClientHelloDetails::new();
         emit_client_hello_for_retry(sess, self.handshake, hello_details, None)
@@ -151,10 +239,10 @@ impl InitialState {
 }
 
 
-pub fn start_handshake(sess: &mut ClientSessionImpl, host_name: webpki::DNSName,
-                       esni: Option<ESNIHandshakeData>,
-                       extra_exts: Vec<ClientExtension>) -> NextState {
-    InitialState::new(host_name, esni, extra_exts)
+pub fn start_handshake(sess: &mut ClientSessionImpl, server_name: ServerName,
+                       encrypted_hello: Option<EncryptedHello>,
+                       extra_exts: Vec<ClientExtension>) -> NextStateOrError {
+    InitialState::new(sess.config.crypto_provider, server_name, encrypted_hello, extra_exts)
         .emit_initial_client_hello(sess)
 }
 
@@ -182,23 +270,64 @@ pub fn compatible_suite(sess: &ClientSessionImpl,
     }
 }
 
+/// Encrypt our SNI for this ClientHello.  This is bound to the hello's
+/// key shares, so must be redone for each ClientHello we send.
+///
+/// If we can't, we fail rather than send our SNI in the clear.
+#[cfg(feature = "esni")]
+fn push_esni_extension(provider: &dyn CryptoProvider,
+                       handshake: &mut HandshakeDetails,
+                       exts: &mut Vec<ClientExtension>) -> Result<(), TLSError> {
+    handshake.esni_nonce = None;
+
+    let keyshare_entries = exts.iter()
+        .filter_map(|ext| match ext {
+            ClientExtension::KeyShare(ks) => Some(ks.clone()),
+            _ => None,
+        })
+        .next();
+
+    let unavailable = |why: &str| TLSError::ESNIUnavailable(why.to_string());
+    let esni = handshake.esni.as_ref()
+        .ok_or_else(|| unavailable("no ESNI record"))?;
+    let dns_name = handshake.server_name.dns_name()
+        .ok_or_else(|| unavailable("server is named by IP address"))?;
+    let ks = keyshare_entries
+        .ok_or_else(|| unavailable("ESNI needs TLS1.3"))?;
+
+    let mut ks_bytes = Vec::new();
+    encode_vec_u16(&mut ks_bytes, &ks);
+    let mut nonce = [0u8; 16];
+    rand::fill_random(provider, &mut nonce);
+
+    let ext = ClientExtension::make_esni(dns_name, esni, ks_bytes,
+                                         &handshake.randoms, nonce)
+        .map_err(|err| TLSError::ESNIUnavailable(err.to_string()))?;
+    exts.push(ext);
+    handshake.esni_nonce = Some(nonce);
+    Ok(())
+}
+
 fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
                                mut handshake: HandshakeDetails,
                                mut hello: ClientHelloDetails,
-                               retryreq: Option<&HelloRetryRequest>) -> NextState {
-    // Do we have a SessionID or ticket cached for this host?
-    handshake.resuming_session = find_session(sess, handshake.dns_name.as_ref());
+                               retryreq: Option<&HelloRetryRequest>) -> NextStateOrError {
+    // Do we have a SessionID or ticket cached for this host?  We
+    // can't tell if it's expired without knowing the time.
+    let now = sess.config.time_provider.now();
+    handshake.resuming_session = now
+        .and_then(|now| find_session(sess, &handshake.server_name, now));
     let (session_id, ticket, resume_version) = if handshake.resuming_session.is_some() {
         let resuming = handshake.resuming_session.as_mut().unwrap();
         if resuming.version == ProtocolVersion::TLSv1_2 {
-            random_sessionid_for_ticket(resuming);
+            random_sessionid_for_ticket(sess.config.crypto_provider, resuming);
         }
         debug!("Resuming session");
         (resuming.session_id, resuming.ticket.0.clone(), resuming.version)
     } else {
         debug!("Not resuming any session");
         if handshake.session_id.is_empty() {
-            handshake.session_id = random_sessionid();
+            handshake.session_id = random_sessionid(sess.config.crypto_provider);
         }
         (handshake.session_id, Vec::new(), ProtocolVersion::Unknown(0))
     };
@@ -220,38 +349,33 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
         exts.push(ClientExtension::SupportedVersions(supported_versions));
     }
 
-    let keyshare_entries = if support_tls13 {
+    if support_tls13 {
         let ks = tls13::choose_kx_groups(sess,  &mut hello, &mut handshake, retryreq);
-        let ret = ks.clone();
         exts.push(ClientExtension::KeyShare(ks));
-        Some(ret)
-    } else {
-        None
-    };
-
-    if sess.config.enable_sni && sess.config.encrypt_sni {
-        if let Some(esni) = &handshake.esni {
-            if let Some(ks) = &keyshare_entries {
-                let mut ks_bytes=  Vec::new();
-                encode_vec_u16(&mut ks_bytes, ks);
-                let esni_ext = ClientExtension::make_esni(handshake.dns_name.as_ref(), esni, ks_bytes, &handshake.randoms);
-                if let Some(ext) = esni_ext {
-                    exts.push(ext);
-                }
+    }
 
-                // TODO: what if ESNI fails?
-            }
+    #[cfg(feature = "esni")]
+    let encrypt_sni = sess.config.enable_sni && sess.config.encrypt_sni;
+    #[cfg(not(feature = "esni"))]
+    let encrypt_sni = false;
+
+    if encrypt_sni {
+        #[cfg(feature = "esni")]
+        push_esni_extension(sess.config.crypto_provider, &mut handshake, &mut exts)?;
+    } else if sess.config.enable_sni || handshake.ech.is_some() {
+        // With ECH, this is the ClientHelloInner: it always names the
+        // real server, and only the server can read it.
+        //
+        // RFC 6066 doesn't allow IP addresses in SNI, so for those we
+        // send nothing.
+        if let Some(dns_name) = handshake.server_name.dns_name() {
+            exts.push(ClientExtension::make_sni(dns_name));
         }
-
-        // TODO: what if ESNI is configured but there's no ESNI record?
-
-    } else if sess.config.enable_sni {
-        exts.push(ClientExtension::make_sni(handshake.dns_name.as_ref()));
     }
 
     exts.push(ClientExtension::ECPointFormats(ECPointFormatList::supported()));
-    exts.push(ClientExtension::NamedGroups(suites::KeyExchange::supported_groups().to_vec()));
-    exts.push(ClientExtension::SignatureAlgorithms(verify::supported_verify_schemes().to_vec()));
+    exts.push(ClientExtension::NamedGroups(kx::kx_group_names(&sess.config.kx_groups)));
+    exts.push(ClientExtension::SignatureAlgorithms(sess.config.crypto_provider.supported_verify_schemes()));
     exts.push(ClientExtension::ExtendedMasterSecretRequest);
     exts.push(ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()));
 
@@ -259,6 +383,14 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
         exts.push(ClientExtension::SignedCertificateTimestampRequest);
     }
 
+    if sess.config.raw_public_key_verifier.is_some() {
+        exts.push(ClientExtension::ServerCertificateTypes(vec![ CertificateType::RawPublicKey ]));
+    }
+
+    if sess.config.client_auth_raw_public_key {
+        exts.push(ClientExtension::ClientCertificateTypes(vec![ CertificateType::RawPublicKey ]));
+    }
+
     if let Some(cookie) = retryreq.and_then(HelloRetryRequest::get_cookie) {
         exts.push(ClientExtension::Cookie(cookie.clone()));
     }
@@ -279,24 +411,31 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
         )));
     }
 
+    if handshake.ech.is_some() {
+        exts.push(ClientExtension::EncryptedClientHello(ECHClientHello::Inner));
+    }
+
     // Extra extensions must be placed before the PSK extension
     exts.extend(handshake.extra_exts.iter().cloned());
 
-    let fill_in_binder = if support_tls13 && sess.config.enable_tickets &&
-                            resume_version == ProtocolVersion::TLSv1_3 &&
-                            !ticket.is_empty() {
-        tls13::prepare_resumption(sess, ticket, &handshake, &mut exts,
-                                  retryreq.is_some())
-    } else if sess.config.enable_tickets {
-        // If we have a ticket, include it.  Otherwise, request one.
-        if ticket.is_empty() {
-            exts.push(ClientExtension::SessionTicketRequest);
-        } else {
-            exts.push(ClientExtension::SessionTicketOffer(Payload::new(ticket)));
+    let fill_in_binder = match now {
+        // A session was only found if we know the time.
+        Some(now) if support_tls13 && sess.config.enable_tickets &&
+                     resume_version == ProtocolVersion::TLSv1_3 &&
+                     !ticket.is_empty() => {
+            tls13::prepare_resumption(sess, ticket, &handshake, &mut exts,
+                                      retryreq.is_some(), now)
         }
-        false
-    } else {
-        false
+        _ if sess.config.enable_tickets => {
+            // If we have a ticket, include it.  Otherwise, request one.
+            if ticket.is_empty() {
+                exts.push(ClientExtension::SessionTicketRequest);
+            } else {
+                exts.push(ClientExtension::SessionTicketOffer(Payload::new(ticket)));
+            }
+            false
+        }
+        _ => false,
     };
 
     // Note what extensions we sent.
@@ -317,19 +456,40 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
     };
 
     if fill_in_binder {
-        tls13::fill_in_psk_binder(sess, &mut handshake, &mut chp);
+        tls13::fill_in_psk_binder(sess, &mut handshake, &mut chp)?;
     }
 
+    // "This value MUST be set to 0x0303 for all records generated
+    //  by a TLS 1.3 implementation other than an initial ClientHello
+    //  (i.e., one not generated after a HelloRetryRequest)"
+    let version = if retryreq.is_some() {
+        ProtocolVersion::TLSv1_2
+    } else {
+        ProtocolVersion::TLSv1_0
+    };
+
+    // With ECH, what we've built so far is the ClientHelloInner.  We
+    // send it encrypted inside a ClientHelloOuter, and keep a transcript
+    // of each until the server tells us which it used.
+    let outer = handshake.ech.as_mut().map(|ech| {
+        let inner = match chp.payload {
+            HandshakePayload::ClientHello(ref inner) => inner,
+            _ => unreachable!(),
+        };
+
+        Message {
+            typ: ContentType::Handshake,
+            version,
+            payload: MessagePayload::Handshake(HandshakeMessagePayload {
+                typ: HandshakeType::ClientHello,
+                payload: HandshakePayload::ClientHello(ech.make_outer_hello(inner)),
+            }),
+        }
+    });
+
     let ch = Message {
         typ: ContentType::Handshake,
-        // "This value MUST be set to 0x0303 for all records generated
-        //  by a TLS 1.3 implementation other than an initial ClientHello
-        //  (i.e., one not generated after a HelloRetryRequest)"
-        version: if retryreq.is_some() {
-            ProtocolVersion::TLSv1_2
-        } else {
-            ProtocolVersion::TLSv1_0
-        },
+        version,
         payload: MessagePayload::Handshake(chp),
     };
 
@@ -339,9 +499,26 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
         tls13::emit_fake_ccs(&mut handshake, sess);
     }
 
-    trace!("Sending ClientHello {:#?}", ch);
+    let ch = match outer {
+        Some(outer) => {
+            trace!("Encrypting ClientHello {:#?}", ch);
+            let ech = handshake.ech.as_mut().unwrap();
+
+            if ech.is_rejected() {
+                handshake.transcript.add_message(&outer);
+            } else {
+                handshake.transcript.add_message(&ch);
+                ech.outer_transcript.add_message(&outer);
+            }
+            outer
+        }
+        None => {
+            handshake.transcript.add_message(&ch);
+            ch
+        }
+    };
 
-    handshake.transcript.add_message(&ch);
+    trace!("Sending ClientHello {:#?}", ch);
     sess.common.send_msg(ch, false);
 
     // Calculate the hash of ClientHello and use it to derive EarlyTrafficSecret
@@ -354,7 +531,7 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
             .as_ref()
             .and_then(|resume| sess.find_cipher_suite(resume.cipher_suite)).unwrap();
 
-        let client_hello_hash = handshake.transcript.get_hash_given(resuming_suite.get_hash(), &[]);
+        let client_hello_hash = handshake.transcript.get_hash_given(sess.config.crypto_provider.suite_hash(resuming_suite)?, &[]);
         let client_early_traffic_secret = sess.common
             .get_key_schedule()
             .derive_logged_secret(SecretKind::ClientEarlyTrafficSecret, &client_hello_hash,
@@ -362,11 +539,11 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
                                   &handshake.randoms.client);
         // Set early data encryption key
         sess.common
-            .set_message_encrypter(cipher::new_tls13_write(resuming_suite, &client_early_traffic_secret));
+            .set_message_encrypter(cipher::new_tls13_write(sess.config.crypto_provider, resuming_suite, &client_early_traffic_secret)?);
 
         #[cfg(feature = "quic")]
         {
-            sess.common.quic.early_secret = Some(client_early_traffic_secret);
+            sess.common.quic.early_secret = Some(quic::ring_prk(resuming_suite, &client_early_traffic_secret));
         }
 
         // Now the client can send encrypted early data
@@ -383,9 +560,9 @@ fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
     };
 
     if support_tls13 && retryreq.is_none() {
-        Box::new(ExpectServerHelloOrHelloRetryRequest(next))
+        Ok(Box::new(ExpectServerHelloOrHelloRetryRequest(next)))
     } else {
-        Box::new(next)
+        Ok(Box::new(next))
     }
 }
 
@@ -406,6 +583,98 @@ pub fn sct_list_is_invalid(scts: &SCTList) -> bool {
         scts.iter().any(|sct| sct.0.is_empty())
 }
 
+/// Note which certificate types (RFC7250) the server chose.  We only
+/// ever offer raw public keys, so that's the only thing it can choose;
+/// if it says nothing, we're both using X.509 certificates.
+pub fn process_certificate_types(sess: &mut ClientSessionImpl,
+                                 handshake: &mut HandshakeDetails,
+                                 exts: &dyn HasServerExtensions) -> Result<(), TLSError> {
+    if let Some(typ) = exts.get_server_certificate_type() {
+        if typ != CertificateType::RawPublicKey {
+            return Err(illegal_param(sess, "server chose non-offered server certificate type"));
+        }
+        handshake.server_cert_type = typ;
+    }
+
+    if let Some(typ) = exts.get_client_certificate_type() {
+        if typ != CertificateType::RawPublicKey {
+            return Err(illegal_param(sess, "server chose non-offered client certificate type"));
+        }
+        handshake.client_cert_type = typ;
+    }
+
+    debug!("Certificate types: server {:?}, client {:?}",
+           handshake.server_cert_type, handshake.client_cert_type);
+    Ok(())
+}
+
+/// Authenticate the server's certificate chain, or its raw public
+/// key if that's what we negotiated.
+pub fn verify_server_cert(sess: &mut ClientSessionImpl,
+                          handshake: &HandshakeDetails,
+                          server_cert: &ServerCertDetails)
+                          -> Result<verify::ServerCertVerified, TLSError> {
+    if server_cert.cert_chain.is_empty() {
+        return Err(TLSError::NoCertificatesPresented);
+    }
+
+    let result = match (handshake.server_cert_type, &sess.config.raw_public_key_verifier) {
+        (CertificateType::RawPublicKey, Some(verifier)) => {
+            verifier.verify_server_raw_public_key(&server_cert.cert_chain[0].0,
+                                                  &handshake.server_name)
+        }
+        (CertificateType::X509, None) => {
+            webpki_now(&*sess.config.time_provider)
+                .and_then(|now| {
+                    sess.config
+                        .get_verifier()
+                        .verify_server_cert(&sess.config.root_store,
+                                            &server_cert.cert_chain,
+                                            &handshake.server_name,
+                                            &server_cert.ocsp_response,
+                                            now)
+                })
+        }
+        _ => {
+            sess.common.send_fatal_alert(AlertDescription::UnsupportedCertificate);
+            return Err(TLSError::PeerIncompatibleError("server does not support raw public keys"
+                .to_string()));
+        }
+    };
+
+    result.map_err(|err| send_cert_error_alert(sess, err))
+}
+
+/// Check the SCTs for the server's (already verified) certificate,
+/// against our CT policy if we have one.
+pub fn verify_scts(sess: &ClientSessionImpl,
+                   handshake: &HandshakeDetails,
+                   server_cert: &ServerCertDetails) -> Result<(), TLSError> {
+    let logs = match sess.config.ct_logs {
+        Some(logs) => logs,
+        None => return Ok(()),
+    };
+
+    match (&sess.config.ct_policy, server_cert.scts.as_ref()) {
+        (Some(policy), _) if handshake.server_cert_type == CertificateType::X509 => {
+            ct::check_policy(policy,
+                             logs,
+                             &server_cert.cert_chain,
+                             &sess.config.root_store,
+                             server_cert.scts.as_ref(),
+                             &server_cert.ocsp_response,
+                             &*sess.config.time_provider)
+        }
+        (_, Some(scts)) => {
+            verify::verify_scts(&server_cert.cert_chain[0],
+                                scts,
+                                logs,
+                                &*sess.config.time_provider)
+        }
+        (_, None) => Ok(()),
+    }
+}
+
 impl ExpectServerHello {
     fn into_expect_tls13_encrypted_extensions(self) -> NextState {
         Box::new(tls13::ExpectEncryptedExtensions {
@@ -472,6 +741,13 @@ impl State for ExpectServerHello {
                 sess.common.negotiated_version = Some(TLSv1_3);
             }
             TLSv1_2 if sess.config.supports_version(TLSv1_2) => {
+                if self.handshake.ech.is_some() {
+                    // ECH needs TLS1.3, so the server didn't read our
+                    // ClientHelloInner.
+                    sess.common.send_fatal_alert(AlertDescription::ECHRequired);
+                    return Err(TLSError::ECHRejected(None));
+                }
+
                 if sess.early_data.is_enabled() && sess.common.early_traffic {
                     // The client must fail with a dedicated error code if the server
                     // responds with TLS 1.2 when offering 0-RTT.
@@ -540,8 +816,12 @@ impl State for ExpectServerHello {
             return Err(illegal_param(sess, "server chose unusable ciphersuite for version"));
         }
 
+        if sess.common.is_tls13() {
+            process_ech_acceptance(sess, &mut self.handshake, &mut self.hello, &m)?;
+        }
+
         // Start our handshake hash, and input the server-hello.
-        let starting_hash = sess.common.get_suite_assert().get_hash();
+        let starting_hash = sess.config.crypto_provider.suite_hash(sess.common.get_suite_assert())?;
         self.handshake.transcript.start_hash(starting_hash);
         self.handshake.transcript.add_message(&m);
 
@@ -617,12 +897,12 @@ impl State for ExpectServerHello {
                 }
 
                 let secrets = SessionSecrets::new_resume(&self.handshake.randoms,
-                                                         scs.unwrap().get_hash(),
+                                                         sess.config.crypto_provider.suite_hmac(scs.unwrap())?,
                                                          &resuming.master_secret.0);
                 sess.config.key_log.log("CLIENT_RANDOM",
                                         &secrets.randoms.client,
                                         &secrets.master_secret);
-                sess.common.start_encryption_tls12(secrets);
+                sess.common.start_encryption_tls12(secrets)?;
             }
         }
 
@@ -638,6 +918,7 @@ impl State for ExpectServerHello {
                 Ok(self.into_expect_tls12_ccs_resume(certv, sigv))
             }
         } else {
+            process_certificate_types(sess, &mut self.handshake, server_hello)?;
             Ok(self.into_expect_tls12_certificate())
         }
     }
@@ -665,7 +946,7 @@ impl ExpectServerHelloOrHelloRetryRequest {
 
         // Or asks for us to retry on an unsupported group.
         if let Some(group) = req_group {
-            if !suites::KeyExchange::supported_groups().contains(&group) {
+            if kx::find_kx_group(group, &sess.config.kx_groups).is_none() {
                 return Err(illegal_param(sess, "server requested hrr with bad group"));
             }
         }
@@ -715,8 +996,17 @@ impl ExpectServerHelloOrHelloRetryRequest {
         sess.common.set_suite(cs);
 
         // This is the draft19 change where the transcript became a tree
-        self.0.handshake.transcript.start_hash(cs.get_hash());
+        self.0.handshake.transcript.start_hash(sess.config.crypto_provider.suite_hash(cs)?);
         self.0.handshake.transcript.rollup_for_hrr();
+
+        if self.0.handshake.ech.is_some() {
+            process_hrr_ech_acceptance(sess, &mut self.0.handshake, &mut self.0.hello, &m)?;
+        } else if hrr.get_ech_confirmation().is_some() {
+            sess.common.send_fatal_alert(AlertDescription::UnsupportedExtension);
+            return Err(TLSError::PeerMisbehavedError("server sent unsolicited ech in hrr"
+                                                     .to_string()));
+        }
+
         self.0.handshake.transcript.add_message(&m);
 
         // Early data is not alllowed after HelloRetryrequest
@@ -724,10 +1014,10 @@ impl ExpectServerHelloOrHelloRetryRequest {
             sess.early_data.rejected();
         }
 
-        Ok(emit_client_hello_for_retry(sess,
-                                       self.0.handshake,
-                                       self.0.hello,
-                                       Some(hrr)))
+        emit_client_hello_for_retry(sess,
+                                    self.0.handshake,
+                                    self.0.hello,
+                                    Some(hrr))
     }
 }
 
@@ -747,6 +1037,111 @@ impl State for ExpectServerHelloOrHelloRetryRequest {
     }
 }
 
+/// The server accepted our ClientHelloInner if it signals so
+/// in the ServerHello random.  If it didn't, we carry on with the
+/// ClientHelloOuter.
+fn process_ech_acceptance(sess: &mut ClientSessionImpl,
+                          handshake: &mut HandshakeDetails,
+                          hello: &mut ClientHelloDetails,
+                          m: &Message) -> Result<(), TLSError> {
+    let accepted_in_hrr = match handshake.ech {
+        Some(ref ech) if !ech.is_rejected() => ech.accepted == Some(true),
+        _ => return Ok(()),
+    };
+
+    let (server_hello, random) = match m.payload {
+        MessagePayload::Handshake(ref hmp) => match hmp.payload {
+            HandshakePayload::ServerHello(ref sh) => (hmp, &sh.random),
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    };
+
+    let suite = sess.common.get_suite_assert();
+    let transcript_hash = handshake.transcript
+        .get_hash_given(sess.config.crypto_provider.suite_hash(suite)?,
+                        &server_hello.get_encoding_for_ech_confirmation());
+    let confirmation = ech::compute_confirmation(sess.config.crypto_provider,
+                                                 suite,
+                                                 &handshake.randoms.client,
+                                                 &transcript_hash,
+                                                 ech::ACCEPT_CONFIRMATION_LABEL)?;
+
+    let mut server_random = [0u8; 32];
+    random.write_slice(&mut server_random);
+
+    if constant_time::verify_slices_are_equal(&server_random[24..], &confirmation).is_ok() {
+        debug!("Server accepted ECH");
+        handshake.ech.as_mut().unwrap().accepted = Some(true);
+    } else if accepted_in_hrr {
+        return Err(illegal_param(sess, "server rejected ech after accepting it in hrr"));
+    } else {
+        reject_ech(handshake, hello);
+    }
+
+    Ok(())
+}
+
+/// As `process_ech_acceptance`, but a HelloRetryRequest carries its
+/// signal in an extension.  `handshake.transcript` must be rolled up
+/// already.
+fn process_hrr_ech_acceptance(sess: &mut ClientSessionImpl,
+                              handshake: &mut HandshakeDetails,
+                              hello: &mut ClientHelloDetails,
+                              m: &Message) -> Result<(), TLSError> {
+    let (hrr_message, hrr) = match m.payload {
+        MessagePayload::Handshake(ref hmp) => match hmp.payload {
+            HandshakePayload::HelloRetryRequest(ref hrr) => (hmp, hrr),
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    };
+
+    let suite = sess.common.get_suite_assert();
+    {
+        let ech = handshake.ech.as_mut().unwrap();
+        ech.outer_transcript.start_hash(sess.config.crypto_provider.suite_hash(suite)?);
+        ech.outer_transcript.rollup_for_hrr();
+    }
+
+    let transcript_hash = handshake.transcript
+        .get_hash_given(sess.config.crypto_provider.suite_hash(suite)?,
+                        &hrr_message.get_encoding_for_ech_confirmation());
+    let confirmation = ech::compute_confirmation(sess.config.crypto_provider,
+                                                 suite,
+                                                 &handshake.randoms.client,
+                                                 &transcript_hash,
+                                                 ech::HRR_ACCEPT_CONFIRMATION_LABEL)?;
+
+    let accepted = hrr.get_ech_confirmation()
+        .map(|theirs| constant_time::verify_slices_are_equal(theirs, &confirmation).is_ok())
+        .unwrap_or(false);
+
+    if accepted {
+        debug!("Server accepted ECH in HelloRetryRequest");
+        handshake.ech.as_mut().unwrap().accepted = Some(true);
+    } else {
+        reject_ech(handshake, hello);
+    }
+
+    Ok(())
+}
+
+/// Switch to the ClientHelloOuter.  From here on we're talking to the
+/// public name, without resumption; see `tls13::ExpectFinished` for
+/// how the handshake ends.
+fn reject_ech(handshake: &mut HandshakeDetails, hello: &mut ClientHelloDetails) {
+    debug!("Server rejected ECH");
+    if let Some(ref mut ech) = handshake.ech {
+        ech.accepted = Some(false);
+        mem::swap(&mut handshake.transcript, &mut ech.outer_transcript);
+        handshake.randoms.client = ech.outer_random;
+        handshake.server_name = ech.get_public_name().into();
+        hello.sent_extensions = ech.outer_extensions.clone();
+    }
+    handshake.resuming_session = None;
+}
+
 pub fn send_cert_error_alert(sess: &mut ClientSessionImpl, err: TLSError) -> TLSError {
     match err {
         TLSError::WebPKIError(webpki::Error::BadDER) => {
@@ -755,6 +1150,12 @@ pub fn send_cert_error_alert(sess: &mut ClientSessionImpl, err: TLSError) -> TLS
         TLSError::PeerMisbehavedError(_) => {
             sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
         }
+        TLSError::CertificateRevoked => {
+            sess.common.send_fatal_alert(AlertDescription::CertificateRevoked);
+        }
+        TLSError::InvalidOCSPResponse(_) => {
+            sess.common.send_fatal_alert(AlertDescription::BadCertificateStatusResponse);
+        }
         _ => {
             sess.common.send_fatal_alert(AlertDescription::BadCertificate);
         }
@@ -2,17 +2,26 @@ use crate::msgs::enums::CipherSuite;
 use crate::msgs::enums::{AlertDescription, HandshakeType};
 use crate::session::{Session, SessionCommon};
 use crate::keylog::{KeyLog, NoKeyLog};
+use crate::time_provider::{TimeProvider, SystemTimeProvider};
 use crate::suites::{SupportedCipherSuite, ALL_CIPHERSUITES};
+use crate::kx::SupportedKxGroup;
+use crate::crypto::{self, CryptoProvider};
 use crate::msgs::handshake::CertificatePayload;
 use crate::msgs::enums::SignatureScheme;
 use crate::msgs::enums::{ContentType, ProtocolVersion};
 use crate::msgs::handshake::ClientExtension;
 use crate::msgs::message::Message;
 use crate::verify;
+use crate::ct;
+use crate::ocsp::OCSPPolicy;
+use crate::crl::{CRLStore, RevocationPolicy};
 use crate::anchors;
 use crate::sign;
 use crate::error::TLSError;
 use crate::key;
+use crate::server_name::ServerName;
+use crate::ech::ECHHandshakeData;
+#[cfg(feature = "esni")]
 use crate::esni::ESNIHandshakeData;
 use crate::vecbuf::WriteV;
 #[cfg(feature = "logging")]
@@ -33,6 +42,8 @@ mod tls13;
 mod common;
 pub mod handy;
 
+pub(crate) use crate::client::common::EncryptedHello;
+
 /// A trait for the ability to store client session data.
 /// The keys and values are opaque.
 ///
@@ -87,6 +98,19 @@ pub struct ClientConfig {
     /// List of ciphersuites, in preference order.
     pub ciphersuites: Vec<&'static SupportedCipherSuite>,
 
+    /// List of supported key exchange groups, in preference order.
+    /// The first is the one we guess the server will use, when we
+    /// have no better information.
+    ///
+    /// The default is the `default_kx_groups()` of `crypto_provider`.
+    pub kx_groups: Vec<&'static dyn SupportedKxGroup>,
+
+    /// Where to get our cryptography.  Ciphersuites this doesn't
+    /// support are not offered.
+    ///
+    /// The default is `crypto::ring::RING`.
+    pub crypto_provider: &'static dyn CryptoProvider,
+
     /// Collection of root certificates.
     pub root_store: anchors::RootCertStore,
 
@@ -119,6 +143,11 @@ pub struct ClientConfig {
     /// checking is disabled.
     pub ct_logs: Option<&'static [&'static sct::Log<'static>]>,
 
+    /// What we require of the SCTs from `ct_logs`.  If None, any
+    /// SCTs the server sends in the TLS extension are checked, but
+    /// none are required.
+    ct_policy: Option<ct::CTPolicy>,
+
     /// Whether to send the Server Name Indication (SNI) extension
     /// during the client handshake.
     ///
@@ -128,16 +157,44 @@ pub struct ClientConfig {
     /// If true, encrypt the SNI. Only used if `enable_sni` is true, in which case
     /// the clear text SNI will not be sent.
     ///
+    /// If the SNI can't be encrypted -- there's no ESNI record (see
+    /// `ClientSession::new_with_esni`), the server is named by IP address,
+    /// TLS1.3 is disabled, or the record can't be used -- the handshake
+    /// isn't started: nothing is sent, and `process_new_packets` fails
+    /// with `TLSError::ESNIUnavailable`.  Call it before waiting for the
+    /// server, which will never reply.
+    ///
     /// The default is false.
+    #[cfg(feature = "esni")]
     pub encrypt_sni: bool,
 
     /// How to verify the server certificate chain.
     verifier: Arc<dyn verify::ServerCertVerifier>,
 
+    /// If set, we ask the server for a raw public key (RFC7250)
+    /// instead of a certificate chain, and verify it with this.
+    raw_public_key_verifier: Option<Arc<dyn verify::ServerRawPublicKeyVerifier>>,
+
+    /// Whether to offer a raw public key (RFC7250) for client
+    /// auth instead of a certificate chain.  If true,
+    /// `client_auth_cert_resolver` should resolve keys made with
+    /// `CertifiedKey::new_raw_public_key`.
+    ///
+    /// The default is false.
+    pub client_auth_raw_public_key: bool,
+
     /// How to output key material for debugging.  The default
     /// does nothing.
     pub key_log: Arc<dyn KeyLog>,
 
+    /// Where to get the current time, for verifying server
+    /// certificates and expiring tickets.  To check ESNI records
+    /// against the same time, pass this to
+    /// `esni::create_esni_handshake_with_clock`.
+    ///
+    /// The default is `SystemTimeProvider`.
+    pub time_provider: Arc<dyn TimeProvider>,
+
     /// Whether to send data on the first flight ("early data") in
     /// TLS 1.3 handshakes.
     ///
@@ -156,8 +213,21 @@ impl ClientConfig {
     /// The default session persistence provider stores up to 32
     /// items in memory.
     pub fn new() -> ClientConfig {
+        ClientConfig::with_crypto_provider(&crypto::ring::RING)
+    }
+
+    /// Make a `ClientConfig` like `new()`, but which gets its
+    /// cryptography from `provider`.  The ciphersuites are those
+    /// `provider` supports, and the key exchange groups are its
+    /// default ones.
+    pub fn with_crypto_provider(provider: &'static dyn CryptoProvider) -> ClientConfig {
         ClientConfig {
-            ciphersuites: ALL_CIPHERSUITES.to_vec(),
+            ciphersuites: ALL_CIPHERSUITES.iter()
+                .filter(|scs| provider.supports_suite(scs))
+                .cloned()
+                .collect(),
+            kx_groups: provider.default_kx_groups(),
+            crypto_provider: provider,
             root_store: anchors::RootCertStore::empty(),
             alpn_protocols: Vec::new(),
             session_persistence: handy::ClientSessionMemoryCache::new(32),
@@ -166,10 +236,15 @@ impl ClientConfig {
             enable_tickets: true,
             versions: vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2],
             ct_logs: None,
+            ct_policy: None,
             enable_sni: true,
+            #[cfg(feature = "esni")]
             encrypt_sni: false,
             verifier: Arc::new(verify::WebPKIVerifier::new()),
+            raw_public_key_verifier: None,
+            client_auth_raw_public_key: false,
             key_log: Arc::new(NoKeyLog {}),
+            time_provider: Arc::new(SystemTimeProvider),
             enable_early_data: false,
         }
     }
@@ -182,11 +257,55 @@ impl ClientConfig {
         self.versions.contains(&v) && self.ciphersuites.iter().any(|cs| cs.usable_for_version(v))
     }
 
+    /// Verify server certificates in the default way, checking stapled
+    /// OCSP responses according to `policy`.  The default is
+    /// `OCSPPolicy::SoftFail`.
+    ///
+    /// This replaces the current verifier, including any set using
+    /// `dangerous()` or `set_crls`.
+    pub fn set_ocsp_policy(&mut self, policy: OCSPPolicy) {
+        self.verifier = Arc::new(verify::WebPKIVerifier::new_with_ocsp_policy(policy));
+    }
+
+    /// After verifying server certificates as before, check them
+    /// against `crls` according to `policy`.
+    pub fn set_crls(&mut self, crls: CRLStore, policy: RevocationPolicy) {
+        let inner = self.verifier.clone();
+        self.verifier = Arc::new(verify::RevocationCheckingServerVerifier::new(inner, crls, policy));
+    }
+
+    /// Verify server certificates with `verifier`, which checks
+    /// pinned hostnames' keys against their pins.
+    ///
+    /// Like `set_ocsp_policy`, this replaces the current verifier,
+    /// so call `set_crls` afterwards if you want both.
+    pub fn set_pinning_verifier(&mut self, verifier: verify::PinningVerifier) {
+        self.verifier = Arc::new(verifier);
+    }
+
+    /// Require server certificates to have SCTs from `logs`, as
+    /// `policy` says.  This sets `ct_logs`.
+    pub fn set_ct_policy(&mut self,
+                         logs: &'static [&'static sct::Log<'static>],
+                         policy: ct::CTPolicy) {
+        self.ct_logs = Some(logs);
+        self.ct_policy = Some(policy);
+    }
+
     #[doc(hidden)]
     pub fn get_verifier(&self) -> &dyn verify::ServerCertVerifier {
         self.verifier.as_ref()
     }
 
+    /// Require servers to authenticate with a raw public key
+    /// (RFC7250) rather than a certificate chain, and verify
+    /// their keys with `verifier`.  Servers which can't are
+    /// refused.
+    pub fn set_server_raw_public_key_verifier(&mut self,
+                                              verifier: Arc<dyn verify::ServerRawPublicKeyVerifier>) {
+        self.raw_public_key_verifier = Some(verifier);
+    }
+
     /// Set the ALPN protocol list to the given protocol names.
     /// Overwrites any existing configured protocols.
     /// The first element in the `protocols` list is the most
@@ -230,6 +349,32 @@ impl ClientConfig {
         self.client_auth_cert_resolver = Arc::new(resolver);
     }
 
+    /// Sets a single raw public key (RFC7250) for client authentication,
+    /// and offers it instead of a certificate chain.
+    ///
+    /// `spki` is the DER-encoded SubjectPublicKeyInfo of `key_der`,
+    /// a DER-encoded RSA, ECDSA or Ed25519 private key.
+    pub fn set_single_client_raw_public_key(&mut self,
+                                            spki: Vec<u8>,
+                                            key_der: key::PrivateKey) {
+        let resolver = handy::AlwaysResolvesClientCert::new_raw_public_key(spki, &key_der);
+        self.client_auth_cert_resolver = Arc::new(resolver);
+        self.client_auth_raw_public_key = true;
+    }
+
+    /// Sets a single client authentication certificate chain, and a
+    /// `SigningKey` for its private key.  This is like
+    /// `set_single_client_cert`, for keys which rustls can't load
+    /// itself: for example, ones held in an HSM.
+    ///
+    /// `cert_chain` is a vector of DER-encoded certificates.
+    pub fn set_single_client_cert_with_signing_key(&mut self,
+                                                   cert_chain: Vec<key::Certificate>,
+                                                   key: Box<dyn sign::SigningKey>) {
+        let resolver = handy::AlwaysResolvesClientCert::new_with_signing_key(cert_chain, key);
+        self.client_auth_cert_resolver = Arc::new(resolver);
+    }
+
     /// Access configuration options whose use is dangerous and requires
     /// extra care.
     #[cfg(feature = "dangerous_configuration")]
@@ -396,7 +541,7 @@ impl ClientSessionImpl {
         ClientSessionImpl {
             config: config.clone(),
             alpn_protocol: None,
-            common: SessionCommon::new(config.mtu, true),
+            common: SessionCommon::new(config.mtu, true, config.crypto_provider),
             error: None,
             state: None,
             server_cert_chain: Vec::new(),
@@ -405,15 +550,22 @@ impl ClientSessionImpl {
         }
     }
 
-    pub fn start_handshake(&mut self, hostname: webpki::DNSName, esni: Option<ESNIHandshakeData>, extra_exts: Vec<ClientExtension>) {
-        self.state = Some(hs::start_handshake(self, hostname, esni, extra_exts));
+    pub fn start_handshake(&mut self, server_name: ServerName,
+                           encrypted_hello: Option<EncryptedHello>,
+                           extra_exts: Vec<ClientExtension>) {
+        match hs::start_handshake(self, server_name, encrypted_hello, extra_exts) {
+            Ok(state) => self.state = Some(state),
+            Err(err) => self.error = Some(err),
+        }
     }
 
     pub fn get_cipher_suites(&self) -> Vec<CipherSuite> {
         let mut ret = Vec::new();
 
         for cs in &self.config.ciphersuites {
-            ret.push(cs.suite);
+            if self.config.crypto_provider.supports_suite(cs) {
+                ret.push(cs.suite);
+            }
         }
 
         // We don't do renegotation at all, in fact.
@@ -424,7 +576,7 @@ impl ClientSessionImpl {
 
     pub fn find_cipher_suite(&self, suite: CipherSuite) -> Option<&'static SupportedCipherSuite> {
         for scs in &self.config.ciphersuites {
-            if scs.suite == suite {
+            if scs.suite == suite && self.config.crypto_provider.supports_suite(scs) {
                 return Some(scs);
             }
         }
@@ -496,8 +648,11 @@ impl ClientSessionImpl {
     }
 
     pub fn process_new_handshake_messages(&mut self) -> Result<(), TLSError> {
-        while let Some(msg) = self.common.handshake_joiner.frames.pop_front() {
-            self.process_main_protocol(msg)?;
+        while self.get_pending_signature().is_none() {
+            match self.common.handshake_joiner.frames.pop_front() {
+                Some(msg) => self.process_main_protocol(msg)?,
+                None => break,
+            }
         }
 
         Ok(())
@@ -545,7 +700,12 @@ impl ClientSessionImpl {
             return Err(TLSError::CorruptMessage);
         }
 
-        while let Some(msg) = self.common.message_deframer.frames.pop_front() {
+        while self.get_pending_signature().is_none() {
+            let msg = match self.common.message_deframer.frames.pop_front() {
+                Some(msg) => msg,
+                None => break,
+            };
+
             match self.process_msg(msg) {
                 Ok(_) => {}
                 Err(err) => {
@@ -558,6 +718,35 @@ impl ClientSessionImpl {
         Ok(())
     }
 
+    pub fn get_pending_signature(&self) -> Option<&sign::PendingSignature> {
+        self.state.as_ref()
+            .and_then(|st| st.pending_signature())
+    }
+
+    pub fn complete_signature(&mut self, signature: Vec<u8>) -> Result<(), TLSError> {
+        if let Some(ref err) = self.error {
+            return Err(err.clone());
+        }
+
+        if self.get_pending_signature().is_none() {
+            return Err(TLSError::General("no signature is pending".to_string()));
+        }
+
+        let st = self.state.take().unwrap();
+        let result = st.resume_with_signature(self, signature)
+            .and_then(|st| {
+                self.state = Some(st);
+                self.process_new_handshake_messages()
+            });
+
+        if let Err(err) = result {
+            self.error = Some(err.clone());
+            return Err(err);
+        }
+
+        self.process_new_packets()
+    }
+
     pub fn get_peer_certificates(&self) -> Option<Vec<key::Certificate>> {
         if self.server_cert_chain.is_empty() {
             return None;
@@ -600,11 +789,11 @@ pub struct ClientSession {
 
 impl ClientSession {
     /// Make a new ClientSession.  `config` controls how
-    /// we behave in the TLS protocol, `hostname` is the
-    /// hostname of who we want to talk to.
-    pub fn new(config: &Arc<ClientConfig>, hostname: webpki::DNSNameRef) -> ClientSession {
+    /// we behave in the TLS protocol, `server_name` is the
+    /// DNS name or IP address of who we want to talk to.
+    pub fn new(config: &Arc<ClientConfig>, server_name: ServerName) -> ClientSession {
         let mut imp = ClientSessionImpl::new(config);
-        imp.start_handshake(hostname.into(), None,vec![]);
+        imp.start_handshake(server_name, None, vec![]);
         ClientSession { imp }
     }
 
@@ -612,9 +801,23 @@ impl ClientSession {
     /// we behave in the TLS protocol, `hostname` is the
     /// hostname of who we want to talk to, and esni_keys are used to
     /// encrypt the hostname in the ClientHello.
+    #[cfg(feature = "esni")]
     pub fn new_with_esni(config: &Arc<ClientConfig>, hostname: webpki::DNSNameRef, esni: ESNIHandshakeData) -> ClientSession {
         let mut imp = ClientSessionImpl::new(config);
-        imp.start_handshake(hostname.into(), Some(esni), vec![]);
+        imp.start_handshake(hostname.into(), Some(EncryptedHello::ESNI(esni)), vec![]);
+        ClientSession { imp }
+    }
+
+    /// Make a new ClientSession which hides `hostname` using
+    /// Encrypted Client Hello.  `ech` comes from the server's
+    /// ECHConfigList, via `ech::create_ech_handshake`.
+    ///
+    /// ECH requires TLS1.3.  Observers see the config's public name
+    /// instead of `hostname`.  If the server rejects ECH, the handshake
+    /// fails with `TLSError::ECHRejected`.
+    pub fn new_with_ech(config: &Arc<ClientConfig>, hostname: webpki::DNSNameRef, ech: ECHHandshakeData) -> ClientSession {
+        let mut imp = ClientSessionImpl::new(config);
+        imp.start_handshake(hostname.into(), Some(EncryptedHello::ECH(ech)), vec![]);
         ClientSession { imp }
     }
 
@@ -715,6 +918,13 @@ impl Session for ClientSession {
         self.imp.get_negotiated_ciphersuite().or(self.imp.resumption_ciphersuite)
     }
 
+    fn get_pending_signature(&self) -> Option<&sign::PendingSignature> {
+        self.imp.get_pending_signature()
+    }
+
+    fn complete_signature(&mut self, signature: Vec<u8>) -> Result<(), TLSError> {
+        self.imp.complete_signature(signature)
+    }
 }
 
 impl io::Read for ClientSession {
@@ -1,20 +1,20 @@
 use crate::msgs::enums::{ContentType, HandshakeType};
 use crate::msgs::enums::{ProtocolVersion, AlertDescription};
 use crate::msgs::message::{Message, MessagePayload};
-use crate::msgs::base::{Payload, PayloadU8};
+use crate::msgs::base::{Payload, PayloadU8, PayloadU16, PayloadU24};
 use crate::msgs::handshake::{HandshakePayload, HandshakeMessagePayload};
 use crate::msgs::handshake::DecomposedSignatureScheme;
-use crate::msgs::handshake::ServerKeyExchangePayload;
+use crate::msgs::handshake::{ServerKeyExchangePayload, KeyExchangeAlgorithm};
 use crate::msgs::handshake::DigitallySignedStruct;
-use crate::msgs::enums::ClientCertificateType;
+use crate::msgs::enums::{ClientCertificateType, CertificateType};
 use crate::msgs::codec::Codec;
 use crate::msgs::persist;
 use crate::msgs::ccs::ChangeCipherSpecPayload;
 use crate::client::ClientSessionImpl;
 use crate::session::SessionSecrets;
-use crate::suites;
+use crate::kx;
+use crate::key;
 use crate::verify;
-use crate::ticketer;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace, warn};
 use crate::error::TLSError;
@@ -57,11 +57,20 @@ impl hs::State for ExpectCertificate {
         check_handshake_message(m, &[HandshakeType::Certificate])
     }
 
-    fn handle(mut self: Box<Self>, _sess: &mut ClientSessionImpl, m: Message) -> hs::NextStateOrError {
-        let cert_chain = extract_handshake!(m, HandshakePayload::Certificate).unwrap();
-        self.handshake.transcript.add_message(&m);
+    fn handle(mut self: Box<Self>, sess: &mut ClientSessionImpl, m: Message) -> hs::NextStateOrError {
+        let cert_chain = match self.handshake.server_cert_type {
+            CertificateType::RawPublicKey => extract_handshake!(m, HandshakePayload::RawPublicKey)
+                .map(|spki| vec![ key::Certificate(spki.0.clone()) ]),
+            _ => extract_handshake!(m, HandshakePayload::Certificate).cloned(),
+        };
+
+        if cert_chain.is_none() {
+            sess.common.send_fatal_alert(AlertDescription::DecodeError);
+            return Err(TLSError::CorruptMessagePayload(ContentType::Handshake));
+        }
 
-        self.server_cert.cert_chain = cert_chain.clone();
+        self.handshake.transcript.add_message(&m);
+        self.server_cert.cert_chain = cert_chain.unwrap();
 
         if self.may_send_cert_status {
             Ok(self.into_expect_certificate_status_or_server_kx())
@@ -183,8 +192,14 @@ impl hs::State for ExpectServerKX {
 
         #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
         {
-            if let ServerKeyExchangePayload::ECDHE(ecdhe) = decoded_kx {
-                debug!("ECDHE curve is {:?}", ecdhe.params.curve_params);
+            match decoded_kx {
+                ServerKeyExchangePayload::ECDHE(ecdhe) => {
+                    debug!("ECDHE curve is {:?}", ecdhe.params.curve_params);
+                }
+                ServerKeyExchangePayload::DHE(dhe) => {
+                    debug!("DHE modulus is {} bytes", dhe.params.dh_p.0.len());
+                }
+                _ => {}
             }
         }
 
@@ -197,12 +212,21 @@ fn emit_certificate(handshake: &mut HandshakeDetails,
                     sess: &mut ClientSessionImpl) {
     let chosen_cert = client_auth.cert.take();
 
+    // A raw public key goes on its own, but we say we have none
+    // with an empty certificate list either way.
+    let payload = match chosen_cert {
+        Some(mut cert) if handshake.client_cert_type == CertificateType::RawPublicKey => {
+            HandshakePayload::RawPublicKey(PayloadU24::new(cert.remove(0).0))
+        }
+        chosen_cert => HandshakePayload::Certificate(chosen_cert.unwrap_or_else(Vec::new)),
+    };
+
     let cert = Message {
         typ: ContentType::Handshake,
         version: ProtocolVersion::TLSv1_2,
         payload: MessagePayload::Handshake(HandshakeMessagePayload {
             typ: HandshakeType::Certificate,
-            payload: HandshakePayload::Certificate(chosen_cert.unwrap_or_else(Vec::new)),
+            payload,
         }),
     };
 
@@ -212,10 +236,13 @@ fn emit_certificate(handshake: &mut HandshakeDetails,
 
 fn emit_clientkx(handshake: &mut HandshakeDetails,
                  sess: &mut ClientSessionImpl,
-                 kxd: &suites::KeyExchangeResult) {
+                 kxd: &kx::KeyExchangeResult) {
     let mut buf = Vec::new();
-    let ecpoint = PayloadU8::new(Vec::from(kxd.pubkey.as_ref()));
-    ecpoint.encode(&mut buf);
+    if sess.common.get_suite_assert().kx == KeyExchangeAlgorithm::DHE {
+        PayloadU16::new(kxd.pubkey.clone()).encode(&mut buf);
+    } else {
+        PayloadU8::new(kxd.pubkey.clone()).encode(&mut buf);
+    }
     let pubkey = Payload::new(buf);
 
     let ckx = Message {
@@ -232,20 +259,8 @@ fn emit_clientkx(handshake: &mut HandshakeDetails,
 }
 
 fn emit_certverify(handshake: &mut HandshakeDetails,
-                   client_auth: &mut ClientAuthDetails,
-                   sess: &mut ClientSessionImpl) -> Result<(), TLSError> {
-    if client_auth.signer.is_none() {
-        trace!("Not sending CertificateVerify, no key");
-        handshake.transcript.abandon_client_auth();
-        return Ok(());
-    }
-
-    let message = handshake.transcript.take_handshake_buf();
-    let signer = client_auth.signer.take().unwrap();
-    let scheme = signer.get_scheme();
-    let sig = signer.sign(&message)?;
-    let body = DigitallySignedStruct::new(scheme, sig);
-
+                   body: DigitallySignedStruct,
+                   sess: &mut ClientSessionImpl) {
     let m = Message {
         typ: ContentType::Handshake,
         version: ProtocolVersion::TLSv1_2,
@@ -257,7 +272,6 @@ fn emit_certverify(handshake: &mut HandshakeDetails,
 
     handshake.transcript.add_message(&m);
     sess.common.send_msg(m, false);
-    Ok(())
 }
 
 fn emit_ccs(sess: &mut ClientSessionImpl) {
@@ -341,8 +355,10 @@ impl hs::State for ExpectCertificateRequest {
             .iter()
             .map(|p| p.0.as_slice())
             .collect::<Vec<&[u8]>>();
-        let maybe_certkey =
-            sess.config.client_auth_cert_resolver.resolve(&canames, &certreq.sigschemes);
+        let raw_public_key = self.handshake.client_cert_type == CertificateType::RawPublicKey;
+        let maybe_certkey = sess.config.client_auth_cert_resolver
+            .resolve(&canames, &certreq.sigschemes)
+            .filter(|certkey| certkey.raw_public_key == raw_public_key);
 
         if let Some(mut certkey) = maybe_certkey {
             debug!("Attempting client auth");
@@ -434,6 +450,42 @@ impl ExpectServerDone {
             sig_verified: sigv,
         })
     }
+
+    fn finish(mut self,
+              sess: &mut ClientSessionImpl,
+              kxd: kx::KeyExchangeResult,
+              handshake_hash: Vec<u8>,
+              certv: verify::ServerCertVerified,
+              sigv: verify::HandshakeSignatureValid) -> hs::NextStateOrError {
+        // 5d.
+        emit_ccs(sess);
+
+        // 5e. Now commit secrets.
+        let hmac = sess.config.crypto_provider.suite_hmac(sess.common.get_suite_assert())?;
+        let secrets = if self.handshake.using_ems {
+            SessionSecrets::new_ems(&self.handshake.randoms,
+                                    &handshake_hash,
+                                    hmac,
+                                    &kxd.premaster_secret)
+        } else {
+            SessionSecrets::new(&self.handshake.randoms,
+                                hmac,
+                                &kxd.premaster_secret)
+        };
+        sess.config.key_log.log("CLIENT_RANDOM",
+                                &secrets.randoms.client,
+                                &secrets.master_secret);
+        sess.common.start_encryption_tls12(secrets)?;
+
+        // 6.
+        emit_finished(&mut self.handshake, sess);
+
+        if self.must_issue_new_ticket {
+            Ok(self.into_expect_new_ticket(certv, sigv))
+        } else {
+            Ok(self.into_expect_ccs(certv, sigv))
+        }
+    }
 }
 
 impl hs::State for ExpectServerDone {
@@ -446,7 +498,7 @@ impl hs::State for ExpectServerDone {
         st.handshake.transcript.add_message(&m);
 
         debug!("Server cert is {:?}", st.server_cert.cert_chain);
-        debug!("Server DNS name is {:?}", st.handshake.dns_name);
+        debug!("Server name is {:?}", st.handshake.server_name);
 
         // 1. Verify the cert chain.
         // 2. Verify any SCTs provided with the certificate.
@@ -461,27 +513,10 @@ impl hs::State for ExpectServerDone {
         // 6. emit a Finished, our first encrypted message under the new keys.
 
         // 1.
-        if st.server_cert.cert_chain.is_empty() {
-            return Err(TLSError::NoCertificatesPresented);
-        }
-
-        let certv = sess.config
-            .get_verifier()
-            .verify_server_cert(&sess.config.root_store,
-                                &st.server_cert.cert_chain,
-                                st.handshake.dns_name.as_ref(),
-                                &st.server_cert.ocsp_response)
-            .map_err(|err| hs::send_cert_error_alert(sess, err))?;
+        let certv = hs::verify_server_cert(sess, &st.handshake, &st.server_cert)?;
 
         // 2. Verify any included SCTs.
-        match (st.server_cert.scts.as_ref(), sess.config.ct_logs) {
-            (Some(scts), Some(logs)) => {
-                verify::verify_scts(&st.server_cert.cert_chain[0],
-                                    scts,
-                                    logs)?;
-            }
-            (_, _) => {}
-        }
+        hs::verify_scts(sess, &st.handshake, &st.server_cert)?;
 
         // 3.
         // Build up the contents of the signed message.
@@ -495,15 +530,17 @@ impl hs::State for ExpectServerDone {
             // Check the signature is compatible with the ciphersuite.
             let sig = &st.server_kx.kx_sig;
             let scs = sess.common.get_suite_assert();
-            if scs.sign != sig.scheme.sign() {
+            if !scs.usable_for_sigalg(sig.scheme.sign()) {
                 let error_message =
                     format!("peer signed kx with wrong algorithm (got {:?} expect {:?})",
                                       sig.scheme.sign(), scs.sign);
                 return Err(TLSError::PeerMisbehavedError(error_message));
             }
 
-            verify::verify_signed_struct(&message,
+            verify::verify_signed_struct(sess.config.crypto_provider,
+                                         &message,
                                          &st.server_cert.cert_chain[0],
+                                         st.handshake.server_cert_type,
                                          sig)
                 .map_err(|err| hs::send_cert_error_alert(sess, err))?
         };
@@ -518,7 +555,7 @@ impl hs::State for ExpectServerDone {
 
         // 5a.
         let kxd = sess.common.get_suite_assert()
-            .do_client_kx(&st.server_kx.kx_params)
+            .do_client_kx(&st.server_kx.kx_params, &sess.config.kx_groups)
             .ok_or_else(|| TLSError::PeerMisbehavedError("key exchange failed".to_string()))?;
 
         // 5b.
@@ -527,40 +564,21 @@ impl hs::State for ExpectServerDone {
         let handshake_hash = st.handshake.transcript.get_current_hash();
 
         // 5c.
-        if st.client_auth.is_some() {
-            emit_certverify(&mut st.handshake,
-                            st.client_auth.as_mut().unwrap(),
-                            sess)?;
-        }
-
-        // 5d.
-        emit_ccs(sess);
-
-        // 5e. Now commit secrets.
-        let hashalg = sess.common.get_suite_assert().get_hash();
-        let secrets = if st.handshake.using_ems {
-            SessionSecrets::new_ems(&st.handshake.randoms,
-                                    &handshake_hash,
-                                    hashalg,
-                                    &kxd.premaster_secret)
-        } else {
-            SessionSecrets::new(&st.handshake.randoms,
-                                hashalg,
-                                &kxd.premaster_secret)
-        };
-        sess.config.key_log.log("CLIENT_RANDOM",
-                                &secrets.randoms.client,
-                                &secrets.master_secret);
-        sess.common.start_encryption_tls12(secrets);
-
-        // 6.
-        emit_finished(&mut st.handshake, sess);
+        if let Some(ref mut client_auth) = st.client_auth {
+            if let Some(signer) = client_auth.signer.take() {
+                let message = st.handshake.transcript.take_handshake_buf();
+                return hs::ExpectSignature::sign(sess, &*signer, message, move |sess, dss| {
+                    let mut st = st;
+                    emit_certverify(&mut st.handshake, dss, sess);
+                    st.finish(sess, kxd, handshake_hash, certv, sigv)
+                });
+            }
 
-        if st.must_issue_new_ticket {
-            Ok(st.into_expect_new_ticket(certv, sigv))
-        } else {
-            Ok(st.into_expect_ccs(certv, sigv))
+            trace!("Not sending CertificateVerify, no key");
+            st.handshake.transcript.abandon_client_auth();
         }
+
+        st.finish(sess, kxd, handshake_hash, certv, sigv)
     }
 }
 
@@ -657,7 +675,7 @@ fn save_session(handshake: &mut HandshakeDetails,
         return;
     }
 
-    let key = persist::ClientSessionKey::session_for_dns_name(handshake.dns_name.as_ref());
+    let key = persist::ClientSessionKey::session_for_server_name(&handshake.server_name);
 
     let scs = sess.common.get_suite_assert();
     let master_secret = sess.common.secrets.as_ref().unwrap().get_master_secret();
@@ -667,7 +685,15 @@ fn save_session(handshake: &mut HandshakeDetails,
                                                      &handshake.session_id,
                                                      ticket,
                                                      master_secret);
-    value.set_times(ticketer::timebase(),
+    let now = match sess.config.time_provider.now() {
+        Some(now) => now,
+        None => {
+            debug!("Session not saved: current time unknown");
+            return;
+        }
+    };
+
+    value.set_times(now,
                     recvd_ticket.new_ticket_lifetime,
                     0);
     if handshake.using_ems {
@@ -1,5 +1,5 @@
 use crate::msgs::enums::{ContentType, HandshakeType, ExtensionType, SignatureScheme};
-use crate::msgs::enums::{ProtocolVersion, AlertDescription, NamedGroup};
+use crate::msgs::enums::{ProtocolVersion, AlertDescription, NamedGroup, CertificateType};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::base::{Payload, PayloadU8};
 use crate::msgs::handshake::{HandshakePayload, HandshakeMessagePayload};
@@ -17,9 +17,9 @@ use crate::key_schedule::{SecretKind, KeySchedule};
 use crate::cipher;
 use crate::hash_hs;
 use crate::verify;
+use crate::server_name::ServerName;
 use crate::sign;
-use crate::suites;
-use crate::ticketer;
+use crate::kx;
 #[cfg(feature = "logging")]
 use crate::log::{debug, warn};
 use crate::error::TLSError;
@@ -36,7 +36,6 @@ use crate::client::common::{ClientHelloDetails, ClientAuthDetails};
 use crate::client::hs;
 
 use ring::constant_time;
-use webpki;
 
 // Extensions we expect in plaintext in the ServerHello.
 static ALLOWED_PLAINTEXT_EXTS: &'static [ExtensionType] = &[
@@ -67,16 +66,16 @@ pub fn validate_server_hello(sess: &mut ClientSessionImpl,
     Ok(())
 }
 
-fn find_kx_hint(sess: &mut ClientSessionImpl, dns_name: webpki::DNSNameRef) -> Option<NamedGroup> {
-    let key = persist::ClientSessionKey::hint_for_dns_name(dns_name);
+fn find_kx_hint(sess: &mut ClientSessionImpl, server_name: &ServerName) -> Option<NamedGroup> {
+    let key = persist::ClientSessionKey::hint_for_server_name(server_name);
     let key_buf = key.get_encoding();
 
     let maybe_value = sess.config.session_persistence.get(&key_buf);
     maybe_value.and_then(|enc| NamedGroup::read_bytes(&enc))
 }
 
-fn save_kx_hint(sess: &mut ClientSessionImpl, dns_name: webpki::DNSNameRef, group: NamedGroup) {
-    let key = persist::ClientSessionKey::hint_for_dns_name(dns_name);
+fn save_kx_hint(sess: &mut ClientSessionImpl, server_name: &ServerName, group: NamedGroup) {
+    let key = persist::ClientSessionKey::hint_for_server_name(server_name);
 
     sess.config.session_persistence.put(key.get_encoding(), group.get_encoding());
 }
@@ -89,27 +88,30 @@ pub fn choose_kx_groups(sess: &mut ClientSessionImpl,
     // - if we've been asked via HelloRetryRequest for a specific
     //   one, do that.
     // - if not, we might have a hint of what the server supports
-    // - if not, send just X25519.
+    // - if not, send just our most preferred group.
     //
     let groups = retryreq.and_then(HelloRetryRequest::get_requested_key_share_group)
-        .or_else(|| find_kx_hint(sess, handshake.dns_name.as_ref()))
-        .or_else(|| Some(NamedGroup::X25519))
-        .map(|grp| vec![ grp ])
-        .unwrap();
+        .or_else(|| find_kx_hint(sess, &handshake.server_name))
+        .and_then(|group| kx::find_kx_group(group, &sess.config.kx_groups))
+        .or_else(|| sess.config.kx_groups.first().cloned())
+        .map(|skxg| vec![ skxg ])
+        .unwrap_or_else(Vec::new);
 
     let mut key_shares = vec![];
 
-    for group in groups {
+    for skxg in groups {
+        let group = skxg.name();
+
         // in reply to HelloRetryRequest, we must not alter any existing key
         // shares
         if let Some(already_offered_share) = hello.find_key_share(group) {
-            key_shares.push(KeyShareEntry::new(group, already_offered_share.pubkey.as_ref()));
+            key_shares.push(KeyShareEntry::new(group, &already_offered_share.pubkey));
             hello.offered_key_shares.push(already_offered_share);
             continue;
         }
 
-        if let Some(key_share) = suites::KeyExchange::start_ecdhe(group) {
-            key_shares.push(KeyShareEntry::new(group, key_share.pubkey.as_ref()));
+        if let Some(key_share) = kx::KeyExchange::start(skxg) {
+            key_shares.push(KeyShareEntry::new(group, &key_share.pubkey));
             hello.offered_key_shares.push(key_share);
         }
     }
@@ -121,12 +123,11 @@ pub fn choose_kx_groups(sess: &mut ClientSessionImpl,
 /// data dependency on the message they are contained within.
 pub fn fill_in_psk_binder(sess: &mut ClientSessionImpl,
                           handshake: &mut HandshakeDetails,
-                          hmp: &mut HandshakeMessagePayload) {
+                          hmp: &mut HandshakeMessagePayload) -> Result<(), TLSError> {
     // We need to know the hash function of the suite we're trying to resume into.
     let resuming = handshake.resuming_session.as_ref().unwrap();
     let suite = sess.find_cipher_suite(resuming.cipher_suite).unwrap();
-    let hkdf_alg = suite.hkdf_algorithm;
-    let suite_hash = suite.get_hash();
+    let suite_hash = sess.config.crypto_provider.suite_hash(suite)?;
 
     // The binder is calculated over the clienthello, but doesn't include itself or its
     // length, or the length of its container.
@@ -140,14 +141,15 @@ pub fn fill_in_psk_binder(sess: &mut ClientSessionImpl,
 
     // Run a fake key_schedule to simulate what the server will do if it choses
     // to resume.
-    let key_schedule = KeySchedule::new(hkdf_alg, &resuming.master_secret.0);
-    let base_key = key_schedule.derive(hkdf_alg, SecretKind::ResumptionPSKBinderKey, &empty_hash);
+    let key_schedule = KeySchedule::new(sess.config.crypto_provider, suite, &resuming.master_secret.0)?;
+    let base_key = key_schedule.derive(SecretKind::ResumptionPSKBinderKey, &empty_hash);
     let real_binder = key_schedule.sign_verify_data(&base_key, &handshake_hash);
 
     if let HandshakePayload::ClientHello(ref mut ch) = hmp.payload {
         ch.set_psk_binder(real_binder);
     };
     sess.common.set_key_schedule(key_schedule);
+    Ok(())
 }
 
 pub fn start_handshake_traffic(sess: &mut ClientSessionImpl,
@@ -183,7 +185,7 @@ pub fn start_handshake_traffic(sess: &mut ClientSessionImpl,
         // Discard the early data key schedule.
         sess.early_data.rejected();
         sess.common.early_traffic = false;
-        sess.common.set_key_schedule(KeySchedule::new_with_empty_secret(suite.hkdf_algorithm));
+        sess.common.set_key_schedule(KeySchedule::new_with_empty_secret(sess.config.crypto_provider, suite)?);
         handshake.resuming_session.take();
     }
 
@@ -199,7 +201,7 @@ pub fn start_handshake_traffic(sess: &mut ClientSessionImpl,
         .ok_or_else(|| TLSError::PeerMisbehavedError("key exchange failed"
                                                      .to_string()))?;
 
-    save_kx_hint(sess, handshake.dns_name.as_ref(), their_key_share.group);
+    save_kx_hint(sess, &handshake.server_name, their_key_share.group);
     sess.common.get_mut_key_schedule().input_secret(&shared.premaster_secret);
 
     hs::check_aligned_handshake(sess)?;
@@ -214,7 +216,7 @@ pub fn start_handshake_traffic(sess: &mut ClientSessionImpl,
                                   &handshake.hash_at_client_recvd_server_hello,
                                   &*sess.config.key_log,
                                   &handshake.randoms.client);
-        sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+        sess.common.set_message_encrypter(cipher::new_tls13_write(sess.config.crypto_provider, suite, &write_key)?);
         sess.common.get_mut_key_schedule().current_client_traffic_secret = Some(write_key);
     }
 
@@ -223,7 +225,7 @@ pub fn start_handshake_traffic(sess: &mut ClientSessionImpl,
                               &handshake.hash_at_client_recvd_server_hello,
                               &*sess.config.key_log,
                               &handshake.randoms.client);
-    sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
+    sess.common.set_message_decrypter(cipher::new_tls13_read(sess.config.crypto_provider, suite, &read_key)?);
     sess.common.get_mut_key_schedule().current_server_traffic_secret = Some(read_key);
 
     #[cfg(feature = "quic")] {
@@ -231,16 +233,15 @@ pub fn start_handshake_traffic(sess: &mut ClientSessionImpl,
         let client = if sess.early_data.is_enabled() {
             // Traffic secret wasn't computed and stored above, so do it here.
             sess.common.get_key_schedule().derive(
-                sess.common.get_key_schedule().algorithm(),
                 SecretKind::ClientHandshakeTrafficSecret,
                 &handshake.hash_at_client_recvd_server_hello)
         } else {
             key_schedule.current_client_traffic_secret.clone().unwrap()
         };
-        sess.common.quic.hs_secrets = Some(quic::Secrets {
-            client,
-            server: key_schedule.current_server_traffic_secret.clone().unwrap(),
-        });
+        sess.common.quic.hs_secrets = Some(quic::Secrets::new(
+            suite,
+            &client,
+            key_schedule.current_server_traffic_secret.as_ref().unwrap()));
     }
 
     Ok(())
@@ -250,12 +251,20 @@ pub fn prepare_resumption(sess: &mut ClientSessionImpl,
                           ticket: Vec<u8>,
                           handshake: &HandshakeDetails,
                           exts: &mut Vec<ClientExtension>,
-                          doing_retry: bool) -> bool {
+                          doing_retry: bool,
+                          now: u64) -> bool {
     let resuming_suite = handshake.resuming_session
         .as_ref()
         .and_then(|resume| sess.find_cipher_suite(resume.cipher_suite));
 
     if hs::compatible_suite(sess, resuming_suite) {
+        // The binder is as long as the suite's hash; if we can't
+        // have that, don't try to resume.
+        let binder_len = match sess.config.crypto_provider.suite_hash(resuming_suite.unwrap()) {
+            Ok(hash) => hash.output_len(),
+            Err(_) => return false,
+        };
+
         sess.resumption_ciphersuite = resuming_suite;
         // The EarlyData extension MUST be supplied together with the
         // PreSharedKey extension.
@@ -263,7 +272,10 @@ pub fn prepare_resumption(sess: &mut ClientSessionImpl,
             .resuming_session
             .as_ref()
             .map_or(0, |resume| resume.max_early_data_size);
-        if sess.config.enable_early_data && max_early_data_size > 0 && !doing_retry {
+        // The ClientHelloOuter can't offer early data, so neither can
+        // we when using ECH.
+        if sess.config.enable_early_data && max_early_data_size > 0 && !doing_retry &&
+            handshake.ech.is_none() {
             sess.early_data.enable(max_early_data_size as usize);
             exts.push(ClientExtension::EarlyData);
         }
@@ -273,14 +285,13 @@ pub fn prepare_resumption(sess: &mut ClientSessionImpl,
         //
         // Include an empty binder. It gets filled in below because it depends on
         // the message it's contained in (!!!).
-        let (obfuscated_ticket_age, suite) = {
+        let obfuscated_ticket_age = {
             let resuming = handshake.resuming_session
                 .as_ref()
                 .unwrap();
-            (resuming.get_obfuscated_ticket_age(ticketer::timebase()), resuming.cipher_suite)
+            resuming.get_obfuscated_ticket_age(now)
         };
 
-        let binder_len = sess.find_cipher_suite(suite).unwrap().get_hash().output_len;
         let binder = vec![0u8; binder_len];
 
         let psk_identity = PresharedKeyIdentity::new(ticket, obfuscated_ticket_age);
@@ -337,6 +348,29 @@ fn validate_encrypted_extensions(sess: &mut ClientSessionImpl,
     Ok(())
 }
 
+/// A server that decrypted our ESNI proves it by echoing our nonce.
+/// One that didn't (eg. because it doesn't support ESNI) sends
+/// nothing, and we refuse to continue: we'd be talking to someone
+/// who never saw the name we asked for.
+#[cfg(feature = "esni")]
+fn validate_esni_nonce(sess: &mut ClientSessionImpl,
+                       handshake: &HandshakeDetails,
+                       exts: &EncryptedExtensions) -> Result<(), TLSError> {
+    match (handshake.esni_nonce, exts.get_esni_nonce()) {
+        (Some(ref ours), Some(theirs))
+            if constant_time::verify_slices_are_equal(ours, theirs).is_ok() => Ok(()),
+        (None, None) => Ok(()),
+        (Some(_), None) => {
+            sess.common.send_fatal_alert(AlertDescription::MissingExtension);
+            Err(TLSError::ESNINonceMissing)
+        }
+        (_, Some(_)) => {
+            sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+            Err(TLSError::ESNINonceMismatch)
+        }
+    }
+}
+
 pub struct ExpectEncryptedExtensions {
     pub handshake: HandshakeDetails,
     pub server_cert: ServerCertDetails,
@@ -375,6 +409,18 @@ impl hs::State for ExpectEncryptedExtensions {
 
         validate_encrypted_extensions(sess, &self.hello, exts)?;
         hs::process_alpn_protocol(sess, exts.get_alpn_protocol())?;
+        hs::process_certificate_types(sess, &mut self.handshake, exts)?;
+
+        #[cfg(feature = "esni")]
+        validate_esni_nonce(sess, &self.handshake, exts)?;
+
+        // Keep any retry configs until we've authenticated the server.
+        if let Some(ref mut ech) = self.handshake.ech {
+            if ech.is_rejected() {
+                ech.retry_configs = exts.get_ech_retry_configs()
+                    .map(|configs| configs.get_encoding());
+            }
+        }
 
         #[cfg(feature = "quic")] {
             // QUIC transport parameters
@@ -403,7 +449,7 @@ impl hs::State for ExpectEncryptedExtensions {
                         &self.handshake.hash_at_client_recvd_server_hello,
                         &*sess.config.key_log,
                         &self.handshake.randoms.client);
-                sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+                sess.common.set_message_encrypter(cipher::new_tls13_write(sess.config.crypto_provider, suite, &write_key)?);
                 sess.common.get_mut_key_schedule()
                     .current_client_traffic_secret = Some(write_key);
             }
@@ -537,22 +583,6 @@ impl ExpectCertificateVerify {
     }
 }
 
-fn send_cert_error_alert(sess: &mut ClientSessionImpl, err: TLSError) -> TLSError {
-    match err {
-        TLSError::WebPKIError(webpki::Error::BadDER) => {
-            sess.common.send_fatal_alert(AlertDescription::DecodeError);
-        }
-        TLSError::PeerMisbehavedError(_) => {
-            sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
-        }
-        _ => {
-            sess.common.send_fatal_alert(AlertDescription::BadCertificate);
-        }
-    };
-
-    err
-}
-
 impl hs::State for ExpectCertificateVerify {
     fn check_message(&self, m: &Message) -> Result<(), TLSError> {
         check_handshake_message(m, &[HandshakeType::CertificateVerify])
@@ -564,35 +594,20 @@ impl hs::State for ExpectCertificateVerify {
         debug!("Server cert is {:?}", self.server_cert.cert_chain);
 
         // 1. Verify the certificate chain.
-        if self.server_cert.cert_chain.is_empty() {
-            return Err(TLSError::NoCertificatesPresented);
-        }
-
-        let certv = sess.config
-            .get_verifier()
-            .verify_server_cert(&sess.config.root_store,
-                                &self.server_cert.cert_chain,
-                                self.handshake.dns_name.as_ref(),
-                                &self.server_cert.ocsp_response)
-            .map_err(|err| send_cert_error_alert(sess, err))?;
+        let certv = hs::verify_server_cert(sess, &self.handshake, &self.server_cert)?;
 
         // 2. Verify their signature on the handshake.
         let handshake_hash = self.handshake.transcript.get_current_hash();
-        let sigv = verify::verify_tls13(&self.server_cert.cert_chain[0],
+        let sigv = verify::verify_tls13(sess.config.crypto_provider,
+                                        &self.server_cert.cert_chain[0],
+                                        self.handshake.server_cert_type,
                                         cert_verify,
                                         &handshake_hash,
                                         b"TLS 1.3, server CertificateVerify\x00")
-            .map_err(|err| send_cert_error_alert(sess, err))?;
+            .map_err(|err| hs::send_cert_error_alert(sess, err))?;
 
         // 3. Verify any included SCTs.
-        match (self.server_cert.scts.as_ref(), sess.config.ct_logs) {
-            (Some(scts), Some(logs)) => {
-                verify::verify_scts(&self.server_cert.cert_chain[0],
-                                    scts,
-                                    logs)?;
-            }
-            (_, _) => {}
-        }
+        hs::verify_scts(sess, &self.handshake, &self.server_cert)?;
 
         sess.server_cert_chain = self.server_cert.take_chain();
         self.handshake.transcript.add_message(&m);
@@ -659,8 +674,17 @@ impl hs::State for ExpectCertificateRequest {
             .iter()
             .map(|p| p.0.as_slice())
             .collect::<Vec<&[u8]>>();
-        let maybe_certkey =
-            sess.config.client_auth_cert_resolver.resolve(&canames, &compat_sigschemes);
+        // If the server rejected ECH, it doesn't get to know who we are.
+        let ech_rejected = self.handshake.ech
+            .as_ref()
+            .map_or(false, |ech| ech.is_rejected());
+        let maybe_certkey = if ech_rejected {
+            None
+        } else {
+            let raw_public_key = self.handshake.client_cert_type == CertificateType::RawPublicKey;
+            sess.config.client_auth_cert_resolver.resolve(&canames, &compat_sigschemes)
+                .filter(|certkey| certkey.raw_public_key == raw_public_key)
+        };
 
         let mut client_auth = ClientAuthDetails::new();
         if let Some(mut certkey) = maybe_certkey {
@@ -707,24 +731,17 @@ fn emit_certificate_tls13(handshake: &mut HandshakeDetails,
     sess.common.send_msg(m, true);
 }
 
-fn emit_certverify_tls13(handshake: &mut HandshakeDetails,
-                         client_auth: &mut ClientAuthDetails,
-                         sess: &mut ClientSessionImpl) -> Result<(), TLSError> {
-    if client_auth.signer.is_none() {
-        debug!("Skipping certverify message (no client scheme/key)");
-        return Ok(());
-    }
-
+fn certverify_message_tls13(handshake: &HandshakeDetails) -> Vec<u8> {
     let mut message = Vec::new();
     message.resize(64, 0x20u8);
     message.extend_from_slice(b"TLS 1.3, client CertificateVerify\x00");
     message.extend_from_slice(&handshake.transcript.get_current_hash());
+    message
+}
 
-    let signer = client_auth.signer.take().unwrap();
-    let scheme = signer.get_scheme();
-    let sig = signer.sign(&message)?;
-    let dss = DigitallySignedStruct::new(scheme, sig);
-
+fn emit_certverify_tls13(handshake: &mut HandshakeDetails,
+                         dss: DigitallySignedStruct,
+                         sess: &mut ClientSessionImpl) {
     let m = Message {
         typ: ContentType::Handshake,
         version: ProtocolVersion::TLSv1_3,
@@ -736,7 +753,6 @@ fn emit_certverify_tls13(handshake: &mut HandshakeDetails,
 
     handshake.transcript.add_message(&m);
     sess.common.send_msg(m, true);
-    Ok(())
 }
 
 fn emit_finished_tls13(handshake: &mut HandshakeDetails,
@@ -797,6 +813,43 @@ impl ExpectFinished {
             _fin_verified: fin,
         }
     }
+
+    fn finish(mut self,
+              sess: &mut ClientSessionImpl,
+              fin: verify::FinishedMessageVerified,
+              handshake_hash: Vec<u8>) -> hs::NextStateOrError {
+        emit_finished_tls13(&mut self.handshake, sess);
+
+        /* Now move to our application traffic keys. */
+        let suite = sess.common.get_suite_assert();
+        let write_key = sess.common
+            .get_key_schedule()
+            .derive_logged_secret(SecretKind::ClientApplicationTrafficSecret,
+                                  &handshake_hash,
+                                  &*sess.config.key_log,
+                                  &self.handshake.randoms.client);
+        sess.common.set_message_encrypter(cipher::new_tls13_write(sess.config.crypto_provider, suite, &write_key)?);
+        sess.common
+            .get_mut_key_schedule()
+            .current_client_traffic_secret = Some(write_key);
+
+        sess.common.we_now_encrypting();
+        sess.common.start_traffic();
+
+        let st = self.into_expect_traffic(fin);
+        #[cfg(feature = "quic")] {
+            if sess.common.protocol == Protocol::Quic {
+                let key_schedule = sess.common.key_schedule.as_ref().unwrap();
+                sess.common.quic.traffic_secrets = Some(quic::Secrets::new(
+                    sess.common.get_suite_assert(),
+                    key_schedule.current_client_traffic_secret.as_ref().unwrap(),
+                    key_schedule.current_server_traffic_secret.as_ref().unwrap()));
+                return Ok(Box::new(ExpectQUICTraffic(st)));
+            }
+        }
+
+        Ok(Box::new(st))
+    }
 }
 
 impl hs::State for ExpectFinished {
@@ -820,6 +873,16 @@ impl hs::State for ExpectFinished {
                     })
             .map(|_| verify::FinishedMessageVerified::assertion())?;
 
+        // If the server rejected ECH, we have now authenticated it as the
+        // public name.  That's not who we wanted to talk to, but it means
+        // we can trust its retry configs.
+        if let Some(ref mut ech) = st.handshake.ech {
+            if ech.is_rejected() {
+                sess.common.send_fatal_alert(AlertDescription::ECHRequired);
+                return Err(TLSError::ECHRejected(ech.retry_configs.take()));
+            }
+        }
+
         let suite = sess.common.get_suite_assert();
         let maybe_write_key = if sess.common.early_traffic {
             /* Derive the client-to-server encryption key before key schedule update */
@@ -849,7 +912,7 @@ impl hs::State for ExpectFinished {
                 &handshake_hash,
                 &*sess.config.key_log,
                 &st.handshake.randoms.client);
-        sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
+        sess.common.set_message_decrypter(cipher::new_tls13_read(sess.config.crypto_provider, suite, &read_key)?);
         sess.common
             .get_mut_key_schedule()
             .current_server_traffic_secret = Some(read_key);
@@ -870,52 +933,29 @@ impl hs::State for ExpectFinished {
             emit_end_of_early_data_tls13(&mut st.handshake, sess);
             sess.common.early_traffic = false;
             sess.early_data.finished();
-            sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+            sess.common.set_message_encrypter(cipher::new_tls13_write(sess.config.crypto_provider, suite, &write_key)?);
             sess.common.get_mut_key_schedule().current_client_traffic_secret = Some(write_key);
         }
 
         /* Send our authentication/finished messages.  These are still encrypted
          * with our handshake keys. */
-        if st.client_auth.is_some() {
-            emit_certificate_tls13(&mut st.handshake,
-                                   st.client_auth.as_mut().unwrap(),
-                                   sess);
-            emit_certverify_tls13(&mut st.handshake,
-                                  st.client_auth.as_mut().unwrap(),
-                                  sess)?;
-        }
-
-        emit_finished_tls13(&mut st.handshake, sess);
-
-        /* Now move to our application traffic keys. */
         hs::check_aligned_handshake(sess)?;
-        let write_key = sess.common
-            .get_key_schedule()
-            .derive_logged_secret(SecretKind::ClientApplicationTrafficSecret,
-                                  &handshake_hash,
-                                  &*sess.config.key_log,
-                                  &st.handshake.randoms.client);
-        sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
-        sess.common
-            .get_mut_key_schedule()
-            .current_client_traffic_secret = Some(write_key);
-
-        sess.common.we_now_encrypting();
-        sess.common.start_traffic();
-
-        let st = st.into_expect_traffic(fin);
-        #[cfg(feature = "quic")] {
-            if sess.common.protocol == Protocol::Quic {
-                let key_schedule = sess.common.key_schedule.as_ref().unwrap();
-                sess.common.quic.traffic_secrets = Some(quic::Secrets {
-                    client: key_schedule.current_client_traffic_secret.clone().unwrap(),
-                    server: key_schedule.current_server_traffic_secret.clone().unwrap(),
+        if let Some(ref mut client_auth) = st.client_auth {
+            emit_certificate_tls13(&mut st.handshake, client_auth, sess);
+
+            if let Some(signer) = client_auth.signer.take() {
+                let message = certverify_message_tls13(&st.handshake);
+                return hs::ExpectSignature::sign(sess, &*signer, message, move |sess, dss| {
+                    let mut st = st;
+                    emit_certverify_tls13(&mut st.handshake, dss, sess);
+                    st.finish(sess, fin, handshake_hash)
                 });
-                return Ok(Box::new(ExpectQUICTraffic(st)));
             }
+
+            debug!("Skipping certverify message (no client scheme/key)");
         }
 
-        Ok(Box::new(st))
+        st.finish(sess, fin, handshake_hash)
     }
 }
 
@@ -935,7 +975,7 @@ impl ExpectTraffic {
         let handshake_hash = self.handshake.transcript.get_current_hash();
         let key_schedule = sess.common.get_key_schedule();
         let resumption_master_secret =
-            key_schedule.derive(key_schedule.algorithm(), SecretKind::ResumptionMasterSecret, &handshake_hash);
+            key_schedule.derive(SecretKind::ResumptionMasterSecret, &handshake_hash);
         let secret = sess.common
             .get_key_schedule()
             .derive_ticket_psk(&resumption_master_secret, &nst.nonce.0);
@@ -945,7 +985,15 @@ impl ExpectTraffic {
                                                          &SessionID::empty(),
                                                          nst.ticket.0.clone(),
                                                          secret);
-        value.set_times(ticketer::timebase(),
+        let now = match sess.config.time_provider.now() {
+            Some(now) => now,
+            None => {
+                debug!("Ticket not saved: current time unknown");
+                return Ok(());
+            }
+        };
+
+        value.set_times(now,
                         nst.lifetime,
                         nst.age_add);
 
@@ -960,7 +1008,7 @@ impl ExpectTraffic {
             }
         }
 
-        let key = persist::ClientSessionKey::session_for_dns_name(self.handshake.dns_name.as_ref());
+        let key = persist::ClientSessionKey::session_for_server_name(&self.handshake.server_name);
         #[allow(unused_mut)]
         let mut ticket = value.get_encoding();
 
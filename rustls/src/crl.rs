@@ -0,0 +1,423 @@
+// Certificate revocation lists (RFC 5280 section 5).
+//
+// As with OCSP, webpki doesn't deal with CRLs, so we parse them
+// ourselves.  We support complete CRLs issued directly by the issuer
+// of the certificates they cover: indirect, partitioned and delta
+// CRLs all announce themselves with critical extensions, which we
+// refuse to load.
+
+use ring::io::der;
+use webpki;
+
+use crate::ocsp::CertStatus;
+use crate::pemfile;
+use crate::x509;
+#[cfg(feature = "logging")]
+use crate::log::{debug, trace};
+use std::io;
+
+/// A parsed certificate revocation list.
+///
+/// The list's signature can only be checked once we know which
+/// certificate it is being used for, so this happens when it is used.
+#[derive(Debug, Clone)]
+pub struct CertRevocationList {
+    der: Vec<u8>,
+    issuer: Vec<u8>,
+    this_update: u64,
+    next_update: Option<u64>,
+    revoked_serials: Vec<Vec<u8>>,
+}
+
+const TAG_INTEGER: u8 = der::Tag::Integer as u8;
+const TAG_SEQUENCE: u8 = der::Tag::Sequence as u8;
+const TAG_OID: u8 = der::Tag::OID as u8;
+const TAG_BOOLEAN: u8 = der::Tag::Boolean as u8;
+const TAG_OCTET_STRING: u8 = der::Tag::OctetString as u8;
+const TAG_CONTEXT_0: u8 = der::Tag::ContextSpecificConstructed0 as u8;
+
+/// Does the contents of `extensions` contain any extension marked
+/// critical?
+fn has_critical_extension(mut extensions: &[u8]) -> Result<bool, webpki::Error> {
+    while !extensions.is_empty() {
+        let mut extension = x509::read_der(&mut extensions, TAG_SEQUENCE)
+            .ok_or(webpki::Error::BadDER)?;
+        x509::read_der(&mut extension, TAG_OID)
+            .ok_or(webpki::Error::BadDER)?;
+        if x509::read_der(&mut extension, TAG_BOOLEAN) == Some(&[0xff]) {
+            return Ok(true);
+        }
+        x509::read_der(&mut extension, TAG_OCTET_STRING)
+            .ok_or(webpki::Error::BadDER)?;
+    }
+
+    Ok(false)
+}
+
+impl CertRevocationList {
+    /// Parse a DER-encoded CRL.
+    ///
+    /// This fails with `webpki::Error::UnsupportedCriticalExtension`
+    /// for delta CRLs and other kinds we don't support.
+    pub fn from_der(crl: &[u8]) -> Result<CertRevocationList, webpki::Error> {
+        Self::parse(crl)
+            .ok_or(webpki::Error::BadDER)?
+    }
+
+    fn parse(crl: &[u8]) -> Option<Result<CertRevocationList, webpki::Error>> {
+        let mut input = crl;
+        let mut outer = x509::read_der(&mut input, TAG_SEQUENCE)?;
+        if !input.is_empty() {
+            return None;
+        }
+
+        let mut tbs = x509::read_der(&mut outer, TAG_SEQUENCE)?;
+        x509::read_der(&mut outer, TAG_SEQUENCE)?;
+        x509::read_bit_string(&mut outer)?;
+
+        x509::read_der(&mut tbs, TAG_INTEGER);
+        x509::read_der(&mut tbs, TAG_SEQUENCE)?;
+        let issuer = x509::read_der_value(&mut tbs, TAG_SEQUENCE)?;
+        let this_update = x509::read_time(&mut tbs)?;
+        let next_update = x509::read_optional_time(&mut tbs)?;
+
+        let mut revoked_serials = Vec::new();
+        if let Some(mut revoked) = x509::read_der(&mut tbs, TAG_SEQUENCE) {
+            while !revoked.is_empty() {
+                let mut entry = x509::read_der(&mut revoked, TAG_SEQUENCE)?;
+                revoked_serials.push(x509::read_der(&mut entry, TAG_INTEGER)?.to_vec());
+                x509::read_time(&mut entry)?;
+
+                if let Some(extensions) = x509::read_der(&mut entry, TAG_SEQUENCE) {
+                    match has_critical_extension(extensions) {
+                        Ok(false) => {}
+                        Ok(true) => return Some(Err(webpki::Error::UnsupportedCriticalExtension)),
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+            }
+        }
+
+        if let Some(mut extensions) = x509::read_der(&mut tbs, TAG_CONTEXT_0) {
+            let extensions = x509::read_der(&mut extensions, TAG_SEQUENCE)?;
+            match has_critical_extension(extensions) {
+                Ok(false) => {}
+                Ok(true) => return Some(Err(webpki::Error::UnsupportedCriticalExtension)),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+
+        if !tbs.is_empty() {
+            return None;
+        }
+
+        revoked_serials.sort();
+
+        Some(Ok(CertRevocationList {
+            der: crl.to_vec(),
+            issuer: issuer.to_vec(),
+            this_update,
+            next_update,
+            revoked_serials,
+        }))
+    }
+
+    /// Is this CRL authentic, and issued by the holder of
+    /// `issuer_name` and `issuer_spki`?
+    fn is_issued_by(&self, issuer_name: &[u8], issuer_spki: &[u8]) -> bool {
+        if self.issuer != issuer_name {
+            return false;
+        }
+
+        let mut input = &self.der[..];
+        let verified = x509::read_der(&mut input, TAG_SEQUENCE)
+            .and_then(|mut outer| {
+                let tbs = x509::read_der_value(&mut outer, TAG_SEQUENCE)?;
+                let alg = x509::read_der(&mut outer, TAG_SEQUENCE)?;
+                let signature = x509::read_bit_string(&mut outer)?;
+                Some(x509::verify_signed_data(issuer_spki, alg, tbs, signature))
+            });
+
+        verified == Some(Ok(()))
+    }
+
+    fn is_current(&self, now: webpki::Time) -> bool {
+        let time = webpki::Time::from_seconds_since_unix_epoch;
+        now >= time(self.this_update) &&
+            self.next_update.map_or(true, |next_update| now <= time(next_update))
+    }
+
+    fn is_revoked(&self, serial: &[u8]) -> bool {
+        self.revoked_serials
+            .binary_search_by(|revoked| revoked.as_slice().cmp(serial))
+            .is_ok()
+    }
+}
+
+/// A container for CRLs.
+#[derive(Debug, Clone)]
+pub struct CRLStore {
+    /// The list of CRLs.
+    pub crls: Vec<CertRevocationList>,
+}
+
+impl CRLStore {
+    /// Make a new, empty `CRLStore`.
+    pub fn empty() -> CRLStore {
+        CRLStore { crls: Vec::new() }
+    }
+
+    /// Return true if there are no CRLs.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Say how many CRLs are in the container.
+    pub fn len(&self) -> usize {
+        self.crls.len()
+    }
+
+    /// Add a single DER-encoded CRL to the store.
+    pub fn add(&mut self, der: &[u8]) -> Result<(), webpki::Error> {
+        self.crls.push(CertRevocationList::from_der(der)?);
+        Ok(())
+    }
+
+    /// Parse a PEM file and add all `X509 CRL` sections to the store.
+    ///
+    /// Returns the number of CRLs added, and the number which were
+    /// extracted from the PEM but couldn't be used.
+    pub fn add_pem_file(&mut self, rd: &mut dyn io::BufRead) -> Result<(usize, usize), ()> {
+        let ders = pemfile::crls(rd).map_err(|_| ())?;
+        let mut valid_count = 0;
+        let mut invalid_count = 0;
+
+        for der in ders {
+            #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+            match self.add(&der) {
+                Ok(_) => valid_count += 1,
+                Err(err) => {
+                    trace!("invalid crl der {:?}", der);
+                    debug!("crl parsing failed: {:?}", err);
+                    invalid_count += 1
+                }
+            }
+        }
+
+        Ok((valid_count, invalid_count))
+    }
+
+    /// What do our CRLs say about `cert`, issued by the holder of
+    /// `issuer_name` and `issuer_spki`?
+    pub(crate) fn status(&self,
+                         cert: &x509::CertInfo,
+                         issuer_name: &[u8],
+                         issuer_spki: &[u8],
+                         now: webpki::Time,
+                         allow_expired_crls: bool) -> CertStatus {
+        let mut status = CertStatus::Unknown;
+
+        for crl in &self.crls {
+            if !crl.is_issued_by(issuer_name, issuer_spki) {
+                continue;
+            }
+
+            if !allow_expired_crls && !crl.is_current(now) {
+                debug!("ignoring out of date crl");
+                continue;
+            }
+
+            if crl.is_revoked(cert.serial) {
+                return CertStatus::Revoked;
+            }
+            status = CertStatus::Good;
+        }
+
+        status
+    }
+}
+
+/// Which certificates a revocation-checking verifier looks up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RevocationCheckDepth {
+    /// Only the end-entity certificate.
+    EndEntity,
+
+    /// The end-entity certificate and all intermediates.  Trust
+    /// anchors can't be revoked.
+    Chain,
+}
+
+/// What a revocation-checking verifier does with a certificate for
+/// which it has no usable CRL.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnknownStatusPolicy {
+    /// Accept the certificate.
+    Allow,
+
+    /// Reject the certificate with `TLSError::UnknownRevocationStatus`.
+    Deny,
+}
+
+/// How a revocation-checking verifier uses its CRLs.
+#[derive(Debug, Clone)]
+pub struct RevocationPolicy {
+    /// Which certificates to check.  The default is
+    /// `RevocationCheckDepth::Chain`.
+    pub depth: RevocationCheckDepth,
+
+    /// What to do with certificates we have no CRL for.  The default
+    /// is `UnknownStatusPolicy::Deny`.
+    pub unknown_status: UnknownStatusPolicy,
+
+    /// Whether to use CRLs past their `nextUpdate` time.  If this is
+    /// false, such CRLs are treated as missing.  The default is false.
+    pub allow_expired_crls: bool,
+}
+
+impl RevocationPolicy {
+    /// Make a `RevocationPolicy` with the defaults.
+    pub fn new() -> RevocationPolicy {
+        RevocationPolicy {
+            depth: RevocationCheckDepth::Chain,
+            unknown_status: UnknownStatusPolicy::Deny,
+            allow_expired_crls: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::key;
+    use crate::anchors::RootCertStore;
+    use crate::error::TLSError;
+    use crate::verify::check_revocation;
+
+    const JUNE_2ND: u64 = 1_591_056_000;
+
+    fn chain() -> Vec<key::Certificate> {
+        vec![
+            key::Certificate(include_bytes!("testdata/crl-end.der").to_vec()),
+            key::Certificate(include_bytes!("testdata/crl-inter.der").to_vec()),
+        ]
+    }
+
+    fn chain2() -> Vec<key::Certificate> {
+        vec![
+            key::Certificate(include_bytes!("testdata/crl-end2.der").to_vec()),
+            key::Certificate(include_bytes!("testdata/crl-inter.der").to_vec()),
+        ]
+    }
+
+    fn roots() -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        roots.add(&key::Certificate(include_bytes!("testdata/crl-ca.der").to_vec()))
+            .unwrap();
+        roots
+    }
+
+    fn store(ders: &[&[u8]]) -> CRLStore {
+        let mut store = CRLStore::empty();
+        for der in ders {
+            store.add(der).unwrap();
+        }
+        store
+    }
+
+    fn check(store: &CRLStore,
+             policy: &RevocationPolicy,
+             chain: &[key::Certificate],
+             now: u64) -> Result<(), TLSError> {
+        check_revocation(store, policy, &roots(), chain,
+                         webpki::Time::from_seconds_since_unix_epoch(now))
+    }
+
+    const REVOKES_END: &[u8] = include_bytes!("testdata/crl-inter-revokes-end.der");
+    const REVOKES_INTER: &[u8] = include_bytes!("testdata/crl-ca-revokes-inter.der");
+    const INTER_EMPTY: &[u8] = include_bytes!("testdata/crl-inter-empty.der");
+    const INTER_FORGED: &[u8] = include_bytes!("testdata/crl-inter-forged.der");
+
+    #[test]
+    fn test_end_entity_revocation() {
+        let mut policy = RevocationPolicy::new();
+        policy.depth = RevocationCheckDepth::EndEntity;
+        let crls = store(&[REVOKES_END]);
+
+        assert_eq!(check(&crls, &policy, &chain(), JUNE_2ND),
+                   Err(TLSError::CertificateRevoked));
+        assert_eq!(check(&crls, &policy, &chain2(), JUNE_2ND), Ok(()));
+    }
+
+    #[test]
+    fn test_chain_revocation() {
+        let mut policy = RevocationPolicy::new();
+        let crls = store(&[INTER_EMPTY, REVOKES_INTER]);
+        assert_eq!(check(&crls, &policy, &chain2(), JUNE_2ND),
+                   Err(TLSError::CertificateRevoked));
+
+        policy.depth = RevocationCheckDepth::EndEntity;
+        assert_eq!(check(&crls, &policy, &chain2(), JUNE_2ND), Ok(()));
+    }
+
+    #[test]
+    fn test_unknown_status() {
+        let mut policy = RevocationPolicy::new();
+        let crls = store(&[INTER_EMPTY]);
+        assert_eq!(check(&crls, &policy, &chain2(), JUNE_2ND),
+                   Err(TLSError::UnknownRevocationStatus));
+
+        policy.unknown_status = UnknownStatusPolicy::Allow;
+        assert_eq!(check(&crls, &policy, &chain2(), JUNE_2ND), Ok(()));
+    }
+
+    #[test]
+    fn test_expired_crls() {
+        let mut policy = RevocationPolicy::new();
+        policy.depth = RevocationCheckDepth::EndEntity;
+        let crls = store(&[REVOKES_END]);
+        let august = JUNE_2ND + 60 * 24 * 60 * 60;
+
+        assert_eq!(check(&crls, &policy, &chain(), august),
+                   Err(TLSError::UnknownRevocationStatus));
+
+        policy.allow_expired_crls = true;
+        assert_eq!(check(&crls, &policy, &chain(), august),
+                   Err(TLSError::CertificateRevoked));
+    }
+
+    #[test]
+    fn test_forged_crl_is_ignored() {
+        let mut policy = RevocationPolicy::new();
+        policy.depth = RevocationCheckDepth::EndEntity;
+        let crls = store(&[INTER_FORGED]);
+
+        assert_eq!(check(&crls, &policy, &chain2(), JUNE_2ND),
+                   Err(TLSError::UnknownRevocationStatus));
+    }
+
+    #[test]
+    fn test_loading() {
+        let mut crls = CRLStore::empty();
+        assert_eq!(crls.add(include_bytes!("testdata/crl-inter-delta.der")),
+                   Err(webpki::Error::UnsupportedCriticalExtension));
+        assert_eq!(crls.add(b"\x30\x00"), Err(webpki::Error::BadDER));
+
+        // nextUpdate is optional, but if it's there it must be valid.
+        let mut bad_next_update = INTER_EMPTY.to_vec();
+        assert_eq!(&bad_next_update[69..71], &[0x17, 0x0d]);
+        bad_next_update[83] = b'X';
+        assert_eq!(crls.add(&bad_next_update), Err(webpki::Error::BadDER));
+        assert!(crls.is_empty());
+
+        let pem = include_bytes!("testdata/crl-ca-empty.pem");
+        crls.add(&pemfile::crls(&mut &pem[..]).unwrap()[0]).unwrap();
+        assert_eq!(crls.add_pem_file(&mut &pem[..]), Ok((1, 0)));
+        assert_eq!(crls.len(), 2);
+
+        let crl = CertRevocationList::from_der(REVOKES_END).unwrap();
+        assert!(crl.is_revoked(&[0x20, 0x00]));
+        assert!(crl.is_revoked(&[0x1f, 0xff]));
+        assert!(!crl.is_revoked(&[0x20, 0x01]));
+    }
+}
@@ -0,0 +1,248 @@
+use crate::error::TLSError;
+use crate::key::Certificate;
+use crate::kx::SupportedKxGroup;
+use crate::msgs::enums::SignatureScheme;
+use crate::suites::{BulkAlgorithm, SupportedCipherSuite};
+
+use std::fmt;
+
+pub use crate::msgs::enums::HashAlgorithm;
+
+/// The default provider, implemented using *ring*.
+pub mod ring;
+
+/// The length of the nonce taken by every `Aead`.
+pub const NONCE_LEN: usize = 12;
+
+/// A source of the cryptography used by TLS sessions: random
+/// material, hashing, HMAC (and so the PRF and key schedule), bulk
+/// encryption, the default choice of key exchange groups, and
+/// verifying the peer's handshake signatures.
+///
+/// Everything else uses *ring* directly, whichever provider is
+/// configured:
+///
+/// - certificate chain validation (see `ServerCertVerifier` and
+///   `ClientCertVerifier`).
+/// - signing with our own keys (see the `sign` module; implement
+///   `sign::SigningKey` to use something else).
+/// - the key exchange groups rustls implements.  `RingKxGroup` uses
+///   *ring*'s key agreement and random number generator; the FFDHE and
+///   post-quantum hybrid groups get their private keys from *ring*'s
+///   random number generator.  A provider can return its own groups
+///   from `default_kx_groups` instead.
+/// - session ticket encryption by `Ticketer` and `AEADTicketer`
+///   (implement `ProducesTickets` to use something else).
+/// - ESNI and ECH, including their HPKE.
+///
+/// Choose one with `ClientConfig::with_crypto_provider` or
+/// `ServerConfig::with_crypto_provider`.  The default is
+/// `crypto::ring::RING`.
+///
+/// Algorithms a provider doesn't support are reported with
+/// `None`; ciphersuites which need them are then not negotiated.
+pub trait CryptoProvider : Send + Sync + fmt::Debug {
+    /// Fill the whole of `bytes` with cryptographically secure
+    /// random material.
+    fn fill_random(&self, bytes: &mut [u8]) -> Result<(), GetRandomFailed>;
+
+    /// The hash function `alg`.
+    fn hash(&self, alg: HashAlgorithm) -> Option<&'static dyn Hash>;
+
+    /// HMAC using the hash function `alg`.  TLS1.3's HKDF
+    /// is built on this.
+    fn hmac(&self, alg: HashAlgorithm) -> Option<&'static dyn Hmac>;
+
+    /// The AEAD `alg`, for bulk encryption.
+    fn aead(&self, alg: BulkAlgorithm) -> Option<&'static dyn Aead>;
+
+    /// The key exchange groups to use by default, in preference
+    /// order.
+    fn default_kx_groups(&self) -> Vec<&'static dyn SupportedKxGroup>;
+
+    /// The signature schemes which `verify_tls12_signature` and
+    /// `verify_tls13_signature` can check, in preference order.
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme>;
+
+    /// Verify `sig`, made by the key in `cert` over `message` using
+    /// `scheme`, in a TLS1.2 handshake.  For ECDSA, the curve is
+    /// that of the key rather than being fixed by `scheme`.
+    ///
+    /// `cert` has already been authenticated.
+    fn verify_tls12_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              scheme: SignatureScheme,
+                              sig: &[u8]) -> Result<(), TLSError>;
+
+    /// Verify `sig`, made by the key in `cert` over `message` using
+    /// `scheme`, in a TLS1.3 handshake.
+    ///
+    /// `cert` has already been authenticated.
+    fn verify_tls13_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              scheme: SignatureScheme,
+                              sig: &[u8]) -> Result<(), TLSError>;
+
+    /// Like `verify_tls12_signature`, but for a raw public key
+    /// (RFC7250): `spki` is a DER-encoded SubjectPublicKeyInfo.
+    ///
+    /// The default implementation fails, so providers which don't
+    /// override this can't be used with raw public keys.
+    fn verify_tls12_raw_signature(&self,
+                                  _message: &[u8],
+                                  _spki: &[u8],
+                                  _scheme: SignatureScheme,
+                                  _sig: &[u8]) -> Result<(), TLSError> {
+        Err(TLSError::General("raw public keys not supported by crypto provider".to_string()))
+    }
+
+    /// Like `verify_tls13_signature`, but for a raw public key
+    /// (RFC7250): `spki` is a DER-encoded SubjectPublicKeyInfo.
+    ///
+    /// The default implementation fails, like
+    /// `verify_tls12_raw_signature`.
+    fn verify_tls13_raw_signature(&self,
+                                  _message: &[u8],
+                                  _spki: &[u8],
+                                  _scheme: SignatureScheme,
+                                  _sig: &[u8]) -> Result<(), TLSError> {
+        Err(TLSError::General("raw public keys not supported by crypto provider".to_string()))
+    }
+
+    /// Whether this provider has all the algorithms needed
+    /// to use `suite`.  Suites with a `cipher_factory` don't
+    /// need the provider's AEAD.
+    fn supports_suite(&self, suite: &SupportedCipherSuite) -> bool {
+        self.hash(suite.hash).is_some() &&
+            self.hmac(suite.hash).is_some() &&
+            (suite.cipher_factory.is_some() || self.aead(suite.bulk).is_some())
+    }
+}
+
+impl<'a> dyn CryptoProvider + 'a {
+    /// The hash function for `suite`.  We only negotiate suites
+    /// which `supports_suite`, but a provider isn't bound to give
+    /// the same answers every time, so this can still fail.
+    pub(crate) fn suite_hash(&self, suite: &SupportedCipherSuite)
+                             -> Result<&'static dyn Hash, TLSError> {
+        self.hash(suite.hash)
+            .ok_or_else(unsupported_suite)
+    }
+
+    /// The HMAC for `suite`.
+    pub(crate) fn suite_hmac(&self, suite: &SupportedCipherSuite)
+                             -> Result<&'static dyn Hmac, TLSError> {
+        self.hmac(suite.hash)
+            .ok_or_else(unsupported_suite)
+    }
+
+    /// The AEAD for `suite`.
+    pub(crate) fn suite_aead(&self, suite: &SupportedCipherSuite)
+                             -> Result<&'static dyn Aead, TLSError> {
+        self.aead(suite.bulk)
+            .ok_or_else(unsupported_suite)
+    }
+}
+
+fn unsupported_suite() -> TLSError {
+    TLSError::General("ciphersuite not supported by crypto provider".to_string())
+}
+
+/// A `CryptoProvider` couldn't make random material.
+#[derive(Debug, PartialEq)]
+pub struct GetRandomFailed;
+
+/// A hash function.
+pub trait Hash : Send + Sync {
+    /// Which hash function this is.
+    fn algorithm(&self) -> HashAlgorithm;
+
+    /// The length of the output, in bytes.
+    fn output_len(&self) -> usize;
+
+    /// Start an incremental hash.
+    fn start(&self) -> Box<dyn HashContext>;
+
+    /// Hash `data` in one go.
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        let mut ctx = self.start();
+        ctx.update(data);
+        ctx.finish()
+    }
+}
+
+/// An incremental hash computation.
+pub trait HashContext : Send + Sync {
+    /// Add `data` to the input.
+    fn update(&mut self, data: &[u8]);
+
+    /// A copy of this computation, so the hash of the input
+    /// so far can be had without ending it.
+    fn fork(&self) -> Box<dyn HashContext>;
+
+    /// End the computation, returning the hash.
+    fn finish(self: Box<Self>) -> Vec<u8>;
+}
+
+/// HMAC using a particular hash function.
+pub trait Hmac : Send + Sync {
+    /// The length of the output (and of the underlying hash),
+    /// in bytes.
+    fn output_len(&self) -> usize;
+
+    /// Prepare to sign with `key`, which may be any length.
+    fn with_key(&self, key: &[u8]) -> Box<dyn HmacKey>;
+}
+
+/// An HMAC key, ready for use.
+pub trait HmacKey : Send + Sync {
+    /// The HMAC of the concatenation of `data`.
+    fn sign(&self, data: &[&[u8]]) -> Vec<u8>;
+}
+
+/// An AEAD algorithm, with a nonce of `NONCE_LEN` bytes.
+pub trait Aead : Send + Sync {
+    /// The length of keys, in bytes.
+    fn key_len(&self) -> usize;
+
+    /// The length of the authentication tag, in bytes.
+    fn tag_len(&self) -> usize;
+
+    /// Prepare to use `key`, which is `key_len()` bytes long.
+    fn new_key(&self, key: &[u8]) -> Box<dyn AeadKey>;
+}
+
+/// An AEAD key, ready for use.
+pub trait AeadKey : Send + Sync {
+    /// Encrypt `in_out` in place, authenticating it and `aad`.
+    /// Returns the authentication tag.
+    fn seal_in_place_separate_tag(&self,
+                                  nonce: [u8; NONCE_LEN],
+                                  aad: &[u8],
+                                  in_out: &mut [u8]) -> Result<Vec<u8>, TLSError>;
+
+    /// Authenticate and decrypt `in_out` in place, which is the
+    /// ciphertext followed by the tag.  Returns the length of the
+    /// plaintext, which is left at the start of `in_out`.
+    ///
+    /// Fails with `TLSError::DecryptError` if `in_out` or `aad`
+    /// aren't authentic.
+    fn open_in_place(&self,
+                     nonce: [u8; NONCE_LEN],
+                     aad: &[u8],
+                     in_out: &mut [u8]) -> Result<usize, TLSError>;
+}
+
+impl dyn AeadKey {
+    /// Encrypt `in_out` in place, and append the tag.
+    pub(crate) fn seal_in_place_append_tag(&self,
+                                           nonce: [u8; NONCE_LEN],
+                                           aad: &[u8],
+                                           in_out: &mut Vec<u8>) -> Result<(), TLSError> {
+        let tag = self.seal_in_place_separate_tag(nonce, aad, in_out)?;
+        in_out.extend_from_slice(&tag);
+        Ok(())
+    }
+}
@@ -0,0 +1,425 @@
+use crate::crypto::{self, CryptoProvider, GetRandomFailed, HashAlgorithm};
+use crate::error::TLSError;
+use crate::key::Certificate;
+use crate::kx::{self, SupportedKxGroup};
+use crate::msgs::enums::SignatureScheme;
+use crate::suites::BulkAlgorithm;
+
+use ring::{aead, digest, hmac};
+use ring::rand::{SecureRandom, SystemRandom};
+use webpki;
+
+/// A `CryptoProvider` which uses *ring* for everything.
+///
+/// Signatures are verified using *webpki*.
+#[derive(Debug)]
+pub struct RingProvider;
+
+/// The *ring* provider.  This is the default.
+pub static RING: RingProvider = RingProvider;
+
+impl CryptoProvider for RingProvider {
+    fn fill_random(&self, bytes: &mut [u8]) -> Result<(), GetRandomFailed> {
+        SystemRandom::new()
+            .fill(bytes)
+            .map_err(|_| GetRandomFailed)
+    }
+
+    fn hash(&self, alg: HashAlgorithm) -> Option<&'static dyn crypto::Hash> {
+        match alg {
+            HashAlgorithm::SHA256 => Some(&SHA256),
+            HashAlgorithm::SHA384 => Some(&SHA384),
+            HashAlgorithm::SHA512 => Some(&SHA512),
+            _ => None,
+        }
+    }
+
+    fn hmac(&self, alg: HashAlgorithm) -> Option<&'static dyn crypto::Hmac> {
+        match alg {
+            HashAlgorithm::SHA256 => Some(&HMAC_SHA256),
+            HashAlgorithm::SHA384 => Some(&HMAC_SHA384),
+            HashAlgorithm::SHA512 => Some(&HMAC_SHA512),
+            _ => None,
+        }
+    }
+
+    fn aead(&self, alg: BulkAlgorithm) -> Option<&'static dyn crypto::Aead> {
+        match alg {
+            BulkAlgorithm::AES_128_GCM => Some(&AES_128_GCM),
+            BulkAlgorithm::AES_256_GCM => Some(&AES_256_GCM),
+            BulkAlgorithm::CHACHA20_POLY1305 => Some(&CHACHA20_POLY1305),
+            BulkAlgorithm::Custom => None,
+        }
+    }
+
+    fn default_kx_groups(&self) -> Vec<&'static dyn SupportedKxGroup> {
+        kx::ALL_KX_GROUPS.to_vec()
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+
+            SignatureScheme::ED25519,
+
+            SignatureScheme::RSA_PSS_SHA512,
+            SignatureScheme::RSA_PSS_SHA384,
+            SignatureScheme::RSA_PSS_SHA256,
+
+            SignatureScheme::RSA_PKCS1_SHA512,
+            SignatureScheme::RSA_PKCS1_SHA384,
+            SignatureScheme::RSA_PKCS1_SHA256,
+        ]
+    }
+
+    fn verify_tls12_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              scheme: SignatureScheme,
+                              sig: &[u8]) -> Result<(), TLSError> {
+        let possible_algs = convert_scheme(scheme)?;
+        let cert = webpki::EndEntityCert::from(&cert.0)
+            .map_err(TLSError::WebPKIError)?;
+
+        verify_sig_using_any_alg(&cert, possible_algs, message, sig)
+            .map_err(TLSError::WebPKIError)
+    }
+
+    fn verify_tls13_signature(&self,
+                              message: &[u8],
+                              cert: &Certificate,
+                              scheme: SignatureScheme,
+                              sig: &[u8]) -> Result<(), TLSError> {
+        let alg = convert_alg_tls13(scheme)?;
+        let cert = webpki::EndEntityCert::from(&cert.0)
+            .map_err(TLSError::WebPKIError)?;
+
+        cert.verify_signature(alg, message, sig)
+            .map_err(TLSError::WebPKIError)
+    }
+
+    fn verify_tls12_raw_signature(&self,
+                                  message: &[u8],
+                                  spki: &[u8],
+                                  scheme: SignatureScheme,
+                                  sig: &[u8]) -> Result<(), TLSError> {
+        verify_raw_signature(message, spki, scheme, sig, false)
+    }
+
+    fn verify_tls13_raw_signature(&self,
+                                  message: &[u8],
+                                  spki: &[u8],
+                                  scheme: SignatureScheme,
+                                  sig: &[u8]) -> Result<(), TLSError> {
+        verify_raw_signature(message, spki, scheme, sig, true)
+    }
+}
+
+struct RingHash(HashAlgorithm, &'static digest::Algorithm);
+
+static SHA256: RingHash = RingHash(HashAlgorithm::SHA256, &digest::SHA256);
+static SHA384: RingHash = RingHash(HashAlgorithm::SHA384, &digest::SHA384);
+static SHA512: RingHash = RingHash(HashAlgorithm::SHA512, &digest::SHA512);
+
+impl crypto::Hash for RingHash {
+    fn algorithm(&self) -> HashAlgorithm {
+        self.0
+    }
+
+    fn output_len(&self) -> usize {
+        self.1.output_len
+    }
+
+    fn start(&self) -> Box<dyn crypto::HashContext> {
+        Box::new(RingHashContext(digest::Context::new(self.1)))
+    }
+}
+
+struct RingHashContext(digest::Context);
+
+impl crypto::HashContext for RingHashContext {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn fork(&self) -> Box<dyn crypto::HashContext> {
+        Box::new(RingHashContext(self.0.clone()))
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        self.0.finish().as_ref().to_vec()
+    }
+}
+
+struct RingHmac(hmac::Algorithm);
+
+static HMAC_SHA256: RingHmac = RingHmac(hmac::HMAC_SHA256);
+static HMAC_SHA384: RingHmac = RingHmac(hmac::HMAC_SHA384);
+static HMAC_SHA512: RingHmac = RingHmac(hmac::HMAC_SHA512);
+
+impl crypto::Hmac for RingHmac {
+    fn output_len(&self) -> usize {
+        self.0.digest_algorithm().output_len
+    }
+
+    fn with_key(&self, key: &[u8]) -> Box<dyn crypto::HmacKey> {
+        Box::new(RingHmacKey(hmac::Key::new(self.0, key)))
+    }
+}
+
+struct RingHmacKey(hmac::Key);
+
+impl crypto::HmacKey for RingHmacKey {
+    fn sign(&self, data: &[&[u8]]) -> Vec<u8> {
+        let mut ctx = hmac::Context::with_key(&self.0);
+        for d in data {
+            ctx.update(d);
+        }
+        ctx.sign().as_ref().to_vec()
+    }
+}
+
+struct RingAead(&'static aead::Algorithm);
+
+static AES_128_GCM: RingAead = RingAead(&aead::AES_128_GCM);
+static AES_256_GCM: RingAead = RingAead(&aead::AES_256_GCM);
+static CHACHA20_POLY1305: RingAead = RingAead(&aead::CHACHA20_POLY1305);
+
+impl crypto::Aead for RingAead {
+    fn key_len(&self) -> usize {
+        self.0.key_len()
+    }
+
+    fn tag_len(&self) -> usize {
+        self.0.tag_len()
+    }
+
+    fn new_key(&self, key: &[u8]) -> Box<dyn crypto::AeadKey> {
+        let key = aead::UnboundKey::new(self.0, key)
+            .unwrap();
+        Box::new(RingAeadKey(aead::LessSafeKey::new(key)))
+    }
+}
+
+struct RingAeadKey(aead::LessSafeKey);
+
+impl crypto::AeadKey for RingAeadKey {
+    fn seal_in_place_separate_tag(&self,
+                                  nonce: [u8; crypto::NONCE_LEN],
+                                  aad: &[u8],
+                                  in_out: &mut [u8]) -> Result<Vec<u8>, TLSError> {
+        self.0.seal_in_place_separate_tag(aead::Nonce::assume_unique_for_key(nonce),
+                                          aead::Aad::from(aad),
+                                          in_out)
+            .map(|tag| tag.as_ref().to_vec())
+            .map_err(|_| TLSError::General("encrypt failed".to_string()))
+    }
+
+    fn open_in_place(&self,
+                     nonce: [u8; crypto::NONCE_LEN],
+                     aad: &[u8],
+                     in_out: &mut [u8]) -> Result<usize, TLSError> {
+        self.0.open_in_place(aead::Nonce::assume_unique_for_key(nonce),
+                             aead::Aad::from(aad),
+                             in_out)
+            .map(|plain| plain.len())
+            .map_err(|_| TLSError::DecryptError)
+    }
+}
+
+type SignatureAlgorithms = &'static [&'static webpki::SignatureAlgorithm];
+
+static ECDSA_SHA256: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA256,
+                                             &webpki::ECDSA_P384_SHA256];
+static ECDSA_SHA384: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA384,
+                                             &webpki::ECDSA_P384_SHA384];
+
+static ED25519: SignatureAlgorithms = &[&webpki::ED25519];
+
+static RSA_SHA256: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA256];
+static RSA_SHA384: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA384];
+static RSA_SHA512: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA512];
+static RSA_PSS_SHA256: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY];
+static RSA_PSS_SHA384: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY];
+static RSA_PSS_SHA512: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY];
+
+fn convert_scheme(scheme: SignatureScheme) -> Result<SignatureAlgorithms, TLSError> {
+    match scheme {
+        // nb. for TLS1.2 the curve is not fixed by SignatureScheme.
+        SignatureScheme::ECDSA_NISTP256_SHA256 => Ok(ECDSA_SHA256),
+        SignatureScheme::ECDSA_NISTP384_SHA384 => Ok(ECDSA_SHA384),
+
+        SignatureScheme::ED25519 => Ok(ED25519),
+
+        SignatureScheme::RSA_PKCS1_SHA256 => Ok(RSA_SHA256),
+        SignatureScheme::RSA_PKCS1_SHA384 => Ok(RSA_SHA384),
+        SignatureScheme::RSA_PKCS1_SHA512 => Ok(RSA_SHA512),
+
+        SignatureScheme::RSA_PSS_SHA256 => Ok(RSA_PSS_SHA256),
+        SignatureScheme::RSA_PSS_SHA384 => Ok(RSA_PSS_SHA384),
+        SignatureScheme::RSA_PSS_SHA512 => Ok(RSA_PSS_SHA512),
+
+        _ => {
+            let error_msg = format!("received unadvertised sig scheme {:?}", scheme);
+            Err(TLSError::PeerMisbehavedError(error_msg))
+        }
+    }
+}
+
+fn verify_sig_using_any_alg(cert: &webpki::EndEntityCert,
+                            algs: SignatureAlgorithms,
+                            message: &[u8],
+                            sig: &[u8])
+                            -> Result<(), webpki::Error> {
+    // TLS doesn't itself give us enough info to map to a single webpki::SignatureAlgorithm.
+    // Therefore, convert_algs maps to several and we try them all.
+    for alg in algs {
+        match cert.verify_signature(alg, message, sig) {
+            Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey) => continue,
+            res => return res,
+        }
+    }
+
+    Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey)
+}
+
+fn convert_alg_tls13(scheme: SignatureScheme)
+                     -> Result<&'static webpki::SignatureAlgorithm, TLSError> {
+    use crate::msgs::enums::SignatureScheme::*;
+
+    match scheme {
+        ECDSA_NISTP256_SHA256 => Ok(&webpki::ECDSA_P256_SHA256),
+        ECDSA_NISTP384_SHA384 => Ok(&webpki::ECDSA_P384_SHA384),
+        ED25519 => Ok(&webpki::ED25519),
+        RSA_PSS_SHA256 => Ok(&webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY),
+        RSA_PSS_SHA384 => Ok(&webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY),
+        RSA_PSS_SHA512 => Ok(&webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY),
+        _ => {
+            let error_msg = format!("received unsupported sig scheme {:?}", scheme);
+            Err(TLSError::PeerMisbehavedError(error_msg))
+        }
+    }
+}
+
+/// Verify `sig` using the key in the DER-encoded SubjectPublicKeyInfo
+/// `spki`.  This allows the same algorithms as `convert_scheme` (for
+/// TLS1.2) and `convert_alg_tls13` (for TLS1.3), but we have to choose
+/// the *ring* algorithm ourselves, without webpki's help.
+fn verify_raw_signature(message: &[u8],
+                        spki: &[u8],
+                        scheme: SignatureScheme,
+                        sig: &[u8],
+                        tls13: bool) -> Result<(), TLSError> {
+    use crate::msgs::enums::SignatureScheme::*;
+    use crate::x509::{self, PublicKeyType};
+    use ring::signature;
+
+    let (key_type, public_key) = x509::parse_spki(spki)
+        .ok_or(TLSError::WebPKIError(webpki::Error::BadDER))?;
+
+    let alg: &'static dyn signature::VerificationAlgorithm = match (scheme, key_type) {
+        // nb. for TLS1.2 the curve is not fixed by SignatureScheme.
+        (ECDSA_NISTP256_SHA256, PublicKeyType::ECDSAP256) => &signature::ECDSA_P256_SHA256_ASN1,
+        (ECDSA_NISTP256_SHA256, PublicKeyType::ECDSAP384) if !tls13 => &signature::ECDSA_P384_SHA256_ASN1,
+        (ECDSA_NISTP384_SHA384, PublicKeyType::ECDSAP384) => &signature::ECDSA_P384_SHA384_ASN1,
+        (ECDSA_NISTP384_SHA384, PublicKeyType::ECDSAP256) if !tls13 => &signature::ECDSA_P256_SHA384_ASN1,
+
+        (ED25519, PublicKeyType::Ed25519) => &signature::ED25519,
+
+        (RSA_PKCS1_SHA256, PublicKeyType::RSA) if !tls13 => &signature::RSA_PKCS1_2048_8192_SHA256,
+        (RSA_PKCS1_SHA384, PublicKeyType::RSA) if !tls13 => &signature::RSA_PKCS1_2048_8192_SHA384,
+        (RSA_PKCS1_SHA512, PublicKeyType::RSA) if !tls13 => &signature::RSA_PKCS1_2048_8192_SHA512,
+
+        (RSA_PSS_SHA256, PublicKeyType::RSA) => &signature::RSA_PSS_2048_8192_SHA256,
+        (RSA_PSS_SHA384, PublicKeyType::RSA) => &signature::RSA_PSS_2048_8192_SHA384,
+        (RSA_PSS_SHA512, PublicKeyType::RSA) => &signature::RSA_PSS_2048_8192_SHA512,
+
+        _ => {
+            return Err(TLSError::WebPKIError(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey));
+        }
+    };
+
+    signature::UnparsedPublicKey::new(alg, public_key)
+        .verify(message, sig)
+        .map_err(|_| TLSError::WebPKIError(webpki::Error::InvalidSignatureForPublicKey))
+}
+
+#[cfg(test)]
+mod test {
+    use super::RING;
+    use crate::crypto::{CryptoProvider, HashAlgorithm, NONCE_LEN};
+    use crate::suites::{BulkAlgorithm, ALL_CIPHERSUITES};
+
+    #[test]
+    fn supports_all_ciphersuites() {
+        for suite in ALL_CIPHERSUITES.iter() {
+            assert!(RING.supports_suite(suite));
+        }
+    }
+
+    #[test]
+    fn hash_fork() {
+        let sha256 = RING.hash(HashAlgorithm::SHA256).unwrap();
+        assert_eq!(sha256.output_len(), 32);
+
+        let mut ctx = sha256.start();
+        ctx.update(b"hello");
+        let fork = ctx.fork();
+        ctx.update(b"world");
+
+        assert_eq!(fork.finish(), sha256.hash(b"hello"));
+        assert_eq!(ctx.finish(), sha256.hash(b"helloworld"));
+    }
+
+    #[test]
+    fn hmac_sign_concatenates() {
+        let hmac = RING.hmac(HashAlgorithm::SHA384).unwrap();
+        let key = hmac.with_key(b"key");
+        assert_eq!(key.sign(&[b"hello", b"", b"world"]),
+                   key.sign(&[b"helloworld"]));
+        assert_eq!(hmac.output_len(), 48);
+    }
+
+    #[test]
+    fn aead_roundtrip() {
+        let aead = RING.aead(BulkAlgorithm::CHACHA20_POLY1305).unwrap();
+        let key = aead.new_key(&vec![0x11; aead.key_len()]);
+        let nonce = [0x22; NONCE_LEN];
+
+        let mut buf = b"hello".to_vec();
+        let tag = key.seal_in_place_separate_tag(nonce, b"aad", &mut buf).unwrap();
+        assert_eq!(tag.len(), aead.tag_len());
+        buf.extend_from_slice(&tag);
+
+        let mut bad = buf.clone();
+        assert!(key.open_in_place(nonce, b"bad", &mut bad).is_err());
+
+        let len = key.open_in_place(nonce, b"aad", &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"hello");
+    }
+
+    #[test]
+    fn raw_signature() {
+        use crate::msgs::enums::SignatureScheme;
+        use ring::signature::{Ed25519KeyPair, KeyPair};
+
+        let key = Ed25519KeyPair::from_seed_unchecked(&[0x33; 32]).unwrap();
+        let mut spki = vec![0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70,
+                            0x03, 0x21, 0x00];
+        spki.extend_from_slice(key.public_key().as_ref());
+        let sig = key.sign(b"hello");
+
+        assert!(RING.verify_tls12_raw_signature(b"hello", &spki, SignatureScheme::ED25519,
+                                                sig.as_ref()).is_ok());
+        assert!(RING.verify_tls13_raw_signature(b"hello", &spki, SignatureScheme::ED25519,
+                                                sig.as_ref()).is_ok());
+        assert!(RING.verify_tls13_raw_signature(b"world", &spki, SignatureScheme::ED25519,
+                                                sig.as_ref()).is_err());
+        assert!(RING.verify_tls13_raw_signature(b"hello", &spki,
+                                                SignatureScheme::ECDSA_NISTP256_SHA256,
+                                                sig.as_ref()).is_err());
+        assert!(RING.verify_tls13_raw_signature(b"hello", &spki[1..], SignatureScheme::ED25519,
+                                                sig.as_ref()).is_err());
+    }
+}
@@ -0,0 +1,372 @@
+// Certificate Transparency (RFC 6962) policy.
+//
+// The sct crate checks SCTs issued for a certificate, which is what
+// servers send in the TLS extension or in OCSP responses.  SCTs
+// embedded in a certificate were issued for its precertificate
+// instead, which the sct crate doesn't deal with, so we check those
+// ourselves.
+
+use ring::digest;
+use ring::io::der;
+use ring::signature;
+use sct;
+use webpki;
+
+use crate::anchors::RootCertStore;
+use crate::error::TLSError;
+use crate::key::Certificate;
+use crate::msgs::base::PayloadU16;
+use crate::msgs::codec::{self, Codec, Reader};
+use crate::msgs::enums::SignatureScheme;
+use crate::msgs::handshake::SCTList;
+use crate::ocsp;
+use crate::time_provider::TimeProvider;
+use crate::verify;
+use crate::x509::{self, CertInfo};
+#[cfg(feature = "logging")]
+use crate::log::debug;
+
+/// The extension carrying SCTs embedded in a certificate.
+const OID_SCT_LIST: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x02];
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// What a client requires of the SCTs (signed certificate timestamps)
+/// for a server's certificate, beyond their being valid.
+///
+/// SCTs count once per log, however many times and ways they're
+/// delivered.  SCTs from logs we don't know are ignored.
+#[derive(Debug, Clone)]
+pub struct CTPolicy {
+    /// How many SCTs a certificate needs, by the length of its
+    /// validity period.  A certificate valid for at most `.0` days
+    /// needs `.1` SCTs, using the first entry which fits; ones which
+    /// fit no entry are refused.
+    ///
+    /// The default follows Chrome: 2 SCTs for certificates valid
+    /// for under 15 months, 3 for up to 27 months, 4 for up to 39
+    /// months and 5 for longer.
+    pub scts_by_lifetime: Vec<(u64, usize)>,
+
+    /// How many different log operators the SCTs must come from.
+    /// The default is 2.
+    pub min_operators: usize,
+
+    /// Whether to count SCTs embedded in the certificate.  The
+    /// default is true.
+    pub accept_embedded: bool,
+
+    /// Whether to count SCTs in the stapled OCSP response.  The
+    /// default is true.
+    pub accept_ocsp: bool,
+
+    /// Whether to count SCTs sent in the TLS extension.  The
+    /// default is true.
+    pub accept_tls_extension: bool,
+}
+
+impl CTPolicy {
+    /// Make a `CTPolicy` with the defaults.
+    pub fn new() -> CTPolicy {
+        CTPolicy {
+            scts_by_lifetime: vec![ (456, 2), (821, 3), (1187, 4), (u64::max_value(), 5) ],
+            min_operators: 2,
+            accept_embedded: true,
+            accept_ocsp: true,
+            accept_tls_extension: true,
+        }
+    }
+
+    fn scts_required(&self, cert: &CertInfo) -> Option<usize> {
+        let lifetime = cert.not_after.saturating_sub(cert.not_before) / SECONDS_PER_DAY;
+        self.scts_by_lifetime
+            .iter()
+            .find(|&&(days, _)| lifetime <= days)
+            .map(|&(_, scts)| scts)
+    }
+}
+
+/// An SCT, after its version.
+struct SCT {
+    log_id: Vec<u8>,
+    timestamp: u64,
+    extensions: PayloadU16,
+    scheme: SignatureScheme,
+    signature: PayloadU16,
+}
+
+impl SCT {
+    fn parse(sct: &[u8]) -> Result<SCT, sct::Error> {
+        let mut r = Reader::init(sct);
+        match u8::read(&mut r) {
+            Some(0) => {}
+            Some(_) => return Err(sct::Error::UnsupportedSCTVersion),
+            None => return Err(sct::Error::MalformedSCT),
+        }
+
+        SCT::read(&mut r)
+            .filter(|_| !r.any_left())
+            .ok_or(sct::Error::MalformedSCT)
+    }
+
+    fn read(r: &mut Reader) -> Option<SCT> {
+        Some(SCT {
+            log_id: r.take(32)?.to_vec(),
+            timestamp: u64::read(r)?,
+            extensions: PayloadU16::read(r)?,
+            scheme: SignatureScheme::read(r)?,
+            signature: PayloadU16::read(r)?,
+        })
+    }
+}
+
+/// What a log signs when it issues an SCT for a precertificate.
+struct PrecertEntry {
+    issuer_key_hash: digest::Digest,
+    tbs: Vec<u8>,
+}
+
+impl PrecertEntry {
+    /// Recover the precertificate of `cert`, whose issuer is among
+    /// `intermediates` or `roots`.
+    fn new(cert: &CertInfo,
+           intermediates: &[Certificate],
+           roots: &RootCertStore) -> Option<PrecertEntry> {
+        let (_, issuer_spki) = verify::find_issuer(cert, intermediates, roots)?;
+        Some(PrecertEntry {
+            issuer_key_hash: digest::digest(&digest::SHA256, &issuer_spki),
+            tbs: cert.tbs_without_extension(OID_SCT_LIST)?,
+        })
+    }
+
+    /// Like `sct::verify_sct`, for an SCT issued for this
+    /// precertificate.
+    fn verify_sct(&self, sct: &[u8], now: u64, logs: &[&sct::Log]) -> Result<usize, sct::Error> {
+        let sct = SCT::parse(sct)?;
+        let log = logs.iter()
+            .position(|log| log.id[..] == sct.log_id[..])
+            .ok_or(sct::Error::UnknownLog)?;
+
+        let alg: &dyn signature::VerificationAlgorithm = match sct.scheme {
+            SignatureScheme::ECDSA_NISTP256_SHA256 => &signature::ECDSA_P256_SHA256_ASN1,
+            SignatureScheme::ECDSA_NISTP384_SHA384 => &signature::ECDSA_P384_SHA384_ASN1,
+            SignatureScheme::RSA_PKCS1_SHA256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA384 => &signature::RSA_PKCS1_2048_8192_SHA384,
+            _ => return Err(sct::Error::InvalidSignature),
+        };
+
+        let mut message = Vec::new();
+        0u8.encode(&mut message); // v1
+        0u8.encode(&mut message); // certificate_timestamp
+        sct.timestamp.encode(&mut message);
+        1u16.encode(&mut message); // precert_entry
+        message.extend_from_slice(self.issuer_key_hash.as_ref());
+        codec::u24(self.tbs.len() as u32).encode(&mut message);
+        message.extend_from_slice(&self.tbs);
+        sct.extensions.encode(&mut message);
+
+        signature::UnparsedPublicKey::new(alg, logs[log].key)
+            .verify(&message, &sct.signature.0)
+            .map_err(|_| sct::Error::InvalidSignature)?;
+
+        if sct.timestamp > now {
+            return Err(sct::Error::TimestampInFuture);
+        }
+
+        Ok(log)
+    }
+}
+
+/// Note the log which issued an SCT, if it's valid.  Like
+/// `verify::verify_scts`, SCTs we can't check are ignored, and bad
+/// ones are fatal.
+fn add_valid_sct(valid: &mut Vec<usize>,
+                 result: Result<usize, sct::Error>) -> Result<(), TLSError> {
+    match result {
+        Ok(log) => {
+            if !valid.contains(&log) {
+                valid.push(log);
+            }
+            Ok(())
+        }
+        Err(e) if e.should_be_fatal() => Err(TLSError::InvalidSCT(e)),
+        #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+        Err(e) => {
+            debug!("SCT ignored because {:?}", e);
+            Ok(())
+        }
+    }
+}
+
+/// Check the SCTs for the end-entity certificate in the (already
+/// validated) `presented_certs` against `policy`.  `tls_scts` are
+/// those sent in the TLS extension, if any, and `logs` are the logs
+/// we trust.
+pub(crate) fn check_policy(policy: &CTPolicy,
+                           logs: &[&sct::Log],
+                           presented_certs: &[Certificate],
+                           roots: &RootCertStore,
+                           tls_scts: Option<&SCTList>,
+                           ocsp_response: &[u8],
+                           time_provider: &dyn TimeProvider) -> Result<(), TLSError> {
+    let end_entity = &presented_certs[0];
+    let cert = CertInfo::parse(&end_entity.0)
+        .ok_or(TLSError::WebPKIError(webpki::Error::BadDER))?;
+    let now = verify::unix_time_millis(time_provider)?;
+    let mut valid = Vec::new();
+
+    if policy.accept_tls_extension {
+        for sct in tls_scts.into_iter().flatten() {
+            add_valid_sct(&mut valid, sct::verify_sct(&end_entity.0, &sct.0, now, logs))?;
+        }
+    }
+
+    if policy.accept_ocsp {
+        for list in ocsp::sct_lists(ocsp_response) {
+            let scts = SCTList::read_bytes(list)
+                .ok_or(TLSError::InvalidSCT(sct::Error::MalformedSCT))?;
+            for sct in &scts {
+                add_valid_sct(&mut valid, sct::verify_sct(&end_entity.0, &sct.0, now, logs))?;
+            }
+        }
+    }
+
+    if policy.accept_embedded {
+        if let Some(mut ext) = cert.extension(OID_SCT_LIST) {
+            let scts = x509::read_der(&mut ext, der::Tag::OctetString as u8)
+                .and_then(SCTList::read_bytes)
+                .ok_or(TLSError::InvalidSCT(sct::Error::MalformedSCT))?;
+
+            match PrecertEntry::new(&cert, &presented_certs[1..], roots) {
+                Some(entry) => {
+                    for sct in &scts {
+                        add_valid_sct(&mut valid, entry.verify_sct(&sct.0, now, logs))?;
+                    }
+                }
+                None => debug!("Embedded SCTs ignored: cannot recover precertificate"),
+            }
+        }
+    }
+
+    let required = policy.scts_required(&cert)
+        .ok_or_else(|| TLSError::CTPolicyNotMet("certificate lifetime too long".to_string()))?;
+
+    let mut operators = valid.iter()
+        .map(|&log| logs[log].operated_by)
+        .collect::<Vec<&str>>();
+    operators.sort();
+    operators.dedup();
+
+    if valid.len() < required || operators.len() < policy.min_operators {
+        return Err(TLSError::CTPolicyNotMet(
+            format!("{} SCTs from {} log operators, but {} from {} are required",
+                    valid.len(), operators.len(), required, policy.min_operators)));
+    }
+
+    debug!("{} SCTs from {} log operators meet CT policy", valid.len(), operators.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    static GOOGLE_PILOT: sct::Log = sct::Log {
+        description: "Google 'Pilot' log",
+        url: "ct.googleapis.com/pilot/",
+        operated_by: "Google",
+        key: include_bytes!("testdata/google-pilot-pubkey.bin"),
+        id: [164, 185, 9, 144, 180, 24, 88, 20, 135, 187, 19, 162, 204, 103, 112, 10,
+             60, 53, 152, 4, 249, 27, 223, 184, 227, 119, 205, 14, 200, 13, 220, 16],
+        max_merge_delay: 86400,
+    };
+
+    static SYMANTEC_LOG: sct::Log = sct::Log {
+        description: "Symantec log",
+        url: "ct.ws.symantec.com/",
+        operated_by: "Symantec",
+        key: include_bytes!("testdata/symantec-log-pubkey.bin"),
+        id: [221, 235, 29, 43, 122, 13, 79, 166, 32, 139, 129, 173, 129, 104, 112, 126,
+             46, 142, 157, 1, 213, 92, 136, 141, 61, 17, 196, 205, 182, 236, 190, 204],
+        max_merge_delay: 86400,
+    };
+
+    struct FixedTime(u64);
+
+    impl TimeProvider for FixedTime {
+        fn now(&self) -> Option<u64> {
+            Some(self.0)
+        }
+    }
+
+    // wapo's certificate embeds SCTs from both these logs, and two
+    // others.  It's valid for 27 months, less a few days.
+    fn wapo() -> Vec<Certificate> {
+        vec![
+            Certificate(include_bytes!("testdata/cert-wapo.0.der").to_vec()),
+            Certificate(include_bytes!("testdata/cert-wapo.1.der").to_vec()),
+        ]
+    }
+
+    fn check(policy: &CTPolicy, logs: &[&sct::Log], chain: &[Certificate]) -> Result<(), TLSError> {
+        check_policy(policy, logs, chain, &RootCertStore::empty(), None, &[],
+                     &FixedTime(1_500_000_000))
+    }
+
+    fn not_met(scts: usize, operators: usize, required: usize, min_operators: usize)
+               -> Result<(), TLSError> {
+        Err(TLSError::CTPolicyNotMet(
+            format!("{} SCTs from {} log operators, but {} from {} are required",
+                    scts, operators, required, min_operators)))
+    }
+
+    #[test]
+    fn test_embedded_scts() {
+        let logs = [&GOOGLE_PILOT, &SYMANTEC_LOG];
+        let mut policy = CTPolicy::new();
+        assert_eq!(check(&policy, &logs, &wapo()), not_met(2, 2, 3, 2));
+
+        policy.scts_by_lifetime = vec![ (1000, 2) ];
+        assert_eq!(check(&policy, &logs, &wapo()), Ok(()));
+        assert_eq!(check(&policy, &logs[..1], &wapo()), not_met(1, 1, 2, 2));
+
+        policy.min_operators = 1;
+        assert_eq!(check(&policy, &[&GOOGLE_PILOT, &GOOGLE_PILOT], &wapo()),
+                   not_met(1, 1, 2, 1));
+
+        policy.accept_embedded = false;
+        assert_eq!(check(&policy, &logs, &wapo()), not_met(0, 0, 2, 1));
+        policy.accept_embedded = true;
+
+        policy.scts_by_lifetime = vec![ (365, 2) ];
+        assert_eq!(check(&policy, &logs, &wapo()),
+                   Err(TLSError::CTPolicyNotMet("certificate lifetime too long".to_string())));
+    }
+
+    #[test]
+    fn test_embedded_scts_need_issuer() {
+        let mut policy = CTPolicy::new();
+        policy.scts_by_lifetime = vec![ (u64::max_value(), 1) ];
+        policy.min_operators = 1;
+
+        let logs = [&GOOGLE_PILOT];
+        assert_eq!(check(&policy, &logs, &wapo()), Ok(()));
+        assert_eq!(check(&policy, &logs, &wapo()[..1]), not_met(0, 0, 1, 1));
+
+        // github's certificate is from a different issuer.
+        let github = Certificate(include_bytes!("testdata/cert-github.1.der").to_vec());
+        assert_eq!(check(&policy, &logs, &[wapo()[0].clone(), github]), not_met(0, 0, 1, 1));
+    }
+
+    #[test]
+    fn test_embedded_scts_from_the_future() {
+        let mut policy = CTPolicy::new();
+        policy.scts_by_lifetime = vec![ (u64::max_value(), 1) ];
+        policy.min_operators = 1;
+
+        // The SCTs were issued on 2016-10-17.
+        assert_eq!(check_policy(&policy, &[&GOOGLE_PILOT], &wapo(), &RootCertStore::empty(),
+                                None, &[], &FixedTime(1_476_000_000)),
+                   Err(TLSError::InvalidSCT(sct::Error::TimestampInFuture)));
+    }
+}
@@ -0,0 +1,539 @@
+use crate::msgs::handshake::{ClientHelloPayload, ClientExtension, ECHClientHello};
+use crate::msgs::handshake::{ECHClientHelloOuter, ECHConfig, ECHConfigContents, ECHConfigList};
+use crate::msgs::handshake::{HpkeKeyConfig, HpkeSymmetricCipherSuite, SessionID, Random};
+use crate::msgs::handshake::ConvertServerNameList;
+use crate::msgs::enums::{ExtensionType, HpkeKem, HpkeKdf, HpkeAead};
+use crate::msgs::base::{PayloadU8, PayloadU16};
+use crate::msgs::codec::{Codec, Reader};
+use crate::hpke::{self, HpkeSuite, HpkeContext};
+use crate::key_schedule::{hkdf_expand, Prk};
+use crate::crypto::CryptoProvider;
+use crate::hash_hs::HandshakeHash;
+use crate::suites::SupportedCipherSuite;
+use crate::error::TLSError;
+use crate::rand;
+use crate::x25519;
+
+use webpki;
+
+use std::fmt;
+
+/// The symmetric suites we offer in configs we make, in order of
+/// preference.
+static SUPPORTED_SYMMETRIC_SUITES: &[HpkeSymmetricCipherSuite] = &[
+    HpkeSymmetricCipherSuite { kdf: HpkeKdf::HKDF_SHA256, aead: HpkeAead::AES_128_GCM },
+    HpkeSymmetricCipherSuite { kdf: HpkeKdf::HKDF_SHA256, aead: HpkeAead::CHACHA20_POLY_1305 },
+    HpkeSymmetricCipherSuite { kdf: HpkeKdf::HKDF_SHA256, aead: HpkeAead::AES_256_GCM },
+];
+
+/// The longest name we pad to, unless told otherwise.
+const DEFAULT_MAXIMUM_NAME_LENGTH: u8 = 64;
+
+/// All the AEADs we can use have 16 byte tags.
+const AEAD_TAG_LEN: usize = 16;
+
+/// Data chosen from an ECHConfigList for a client session.
+#[derive(Clone, Debug)]
+pub struct ECHHandshakeData {
+    config: ECHConfigContents,
+    suite: HpkeSymmetricCipherSuite,
+    public_name: webpki::DNSName,
+    info: Vec<u8>,
+}
+
+impl ECHHandshakeData {
+    /// The name the server authenticates as if it rejects ECH.
+    pub fn get_public_name(&self) -> webpki::DNSNameRef {
+        self.public_name.as_ref()
+    }
+
+    fn hpke_suite(&self) -> HpkeSuite {
+        hpke_suite(self.config.key_config.kem, self.suite)
+    }
+}
+
+/// Choose the first config we support from `config_list_bytes`, an
+/// encoded ECHConfigList.  This is what is published in DNS, or sent
+/// by a server as retry configs (see `TLSError::ECHRejected`).
+///
+/// Returns None if there are no configs we can use.
+pub fn create_ech_handshake(config_list_bytes: &[u8]) -> Option<ECHHandshakeData> {
+    let configs = ECHConfigList::read(&mut Reader::init(config_list_bytes))?;
+
+    configs.iter()
+        .filter_map(|config| match *config {
+            ECHConfig::V1(ref contents) => config_to_handshake_data(contents),
+            _ => None,
+        })
+        .nth(0)
+}
+
+fn config_to_handshake_data(config: &ECHConfigContents) -> Option<ECHHandshakeData> {
+    if config.has_mandatory_extension() ||
+        config.key_config.kem != HpkeKem::DHKEM_X25519_HKDF_SHA256 {
+        return None;
+    }
+
+    let suite = config.key_config.cipher_suites
+        .iter()
+        .find(|suite| hpke_suite(config.key_config.kem, **suite).is_supported())
+        .cloned()?;
+
+    let public_name = webpki::DNSNameRef::try_from_ascii(&config.public_name.0)
+        .ok()?
+        .to_owned();
+
+    let data = ECHHandshakeData {
+        config: config.clone(),
+        suite,
+        public_name,
+        info: make_info(config),
+    };
+
+    // This rejects bad public keys, so we can't fail later.
+    hpke::setup_base_sender(data.hpke_suite(), &config.key_config.public_key.0, &data.info)?;
+    Some(data)
+}
+
+fn hpke_suite(kem: HpkeKem, suite: HpkeSymmetricCipherSuite) -> HpkeSuite {
+    HpkeSuite {
+        kem,
+        kdf: suite.kdf,
+        aead: suite.aead,
+    }
+}
+
+/// The HPKE info string is a label and the ECHConfig.
+fn make_info(config: &ECHConfigContents) -> Vec<u8> {
+    let mut info = b"tls ech\x00".to_vec();
+    ECHConfig::V1(config.clone()).encode(&mut info);
+    info
+}
+
+/// The acceptance signal is derived from the ClientHelloInner random
+/// and the transcript, so only a server which decrypted the
+/// ClientHelloInner can make it.
+pub(crate) fn compute_confirmation(provider: &dyn CryptoProvider,
+                                   suite: &SupportedCipherSuite,
+                                   inner_random: &[u8; 32],
+                                   transcript_hash: &[u8],
+                                   label: &[u8]) -> Result<[u8; 8], TLSError> {
+    let zeroes = vec![0u8; provider.suite_hash(suite)?.output_len()];
+    let prk = Prk::extract(provider.suite_hmac(suite)?, &zeroes, inner_random);
+    let confirmation = hkdf_expand(&prk, 8, label, transcript_hash);
+
+    let mut ret = [0u8; 8];
+    ret.copy_from_slice(&confirmation);
+    Ok(ret)
+}
+
+pub(crate) const ACCEPT_CONFIRMATION_LABEL: &[u8] = b"ech accept confirmation";
+pub(crate) const HRR_ACCEPT_CONFIRMATION_LABEL: &[u8] = b"hrr ech accept confirmation";
+
+/// Encode a ClientHelloInner, omitting its session id and padding it
+/// so its length doesn't reveal the server name.
+fn encode_inner_hello(inner: &ClientHelloPayload, maximum_name_length: u8) -> Vec<u8> {
+    let mut inner = inner.clone();
+    inner.session_id = SessionID::empty();
+
+    let mut encoded = inner.get_encoding();
+
+    let maximum_name_length = usize::from(maximum_name_length);
+    let mut padding = match inner.get_sni_extension().and_then(|sni| sni.get_hostname()) {
+        Some(name) => {
+            let name: &str = name.into();
+            maximum_name_length.saturating_sub(name.len())
+        }
+        None => maximum_name_length + 9,
+    };
+
+    let len = encoded.len() + padding;
+    padding += (32 - len % 32) % 32;
+
+    encoded.resize(encoded.len() + padding, 0);
+    encoded
+}
+
+/// Decode a ClientHelloInner, checking its padding.
+fn decode_inner_hello(encoded: &[u8], outer: &ClientHelloPayload) -> Option<ClientHelloPayload> {
+    let mut rd = Reader::init(encoded);
+    let mut inner = ClientHelloPayload::read(&mut rd)?;
+
+    if !inner.session_id.is_empty() || rd.rest().iter().any(|byte| *byte != 0) {
+        return None;
+    }
+
+    inner.session_id = outer.session_id;
+    Some(inner)
+}
+
+/// Make the AAD for a ClientHelloOuter: its encoding, but with the
+/// encrypted payload replaced by zeroes.
+fn make_outer_aad(outer: &ClientHelloPayload) -> Vec<u8> {
+    let mut outer = outer.clone();
+
+    for ext in outer.extensions.iter_mut() {
+        if let ClientExtension::EncryptedClientHello(ECHClientHello::Outer(ref mut ech)) = *ext {
+            ech.payload = PayloadU16::new(vec![0u8; ech.payload.0.len()]);
+        }
+    }
+
+    outer.get_encoding()
+}
+
+/// A client's ECH state over one handshake.
+pub(crate) struct ECHClientState {
+    data: ECHHandshakeData,
+    context: Option<HpkeContext>,
+    pub(crate) outer_random: [u8; 32],
+    pub(crate) outer_transcript: HandshakeHash,
+    pub(crate) outer_extensions: Vec<ExtensionType>,
+    pub(crate) accepted: Option<bool>,
+    pub(crate) retry_configs: Option<Vec<u8>>,
+}
+
+impl ECHClientState {
+    pub(crate) fn new(provider: &dyn CryptoProvider, data: ECHHandshakeData) -> ECHClientState {
+        let mut outer_random = [0u8; 32];
+        rand::fill_random(provider, &mut outer_random);
+
+        ECHClientState {
+            data,
+            context: None,
+            outer_random,
+            outer_transcript: HandshakeHash::new(),
+            outer_extensions: Vec::new(),
+            accepted: None,
+            retry_configs: None,
+        }
+    }
+
+    pub(crate) fn is_rejected(&self) -> bool {
+        self.accepted == Some(false)
+    }
+
+    pub(crate) fn get_public_name(&self) -> webpki::DNSNameRef {
+        self.data.get_public_name()
+    }
+
+    /// Make the ClientHelloOuter carrying `inner`.  The outer hello
+    /// names the public name, and offers neither resumption nor early
+    /// data.
+    pub(crate) fn make_outer_hello(&mut self, inner: &ClientHelloPayload) -> ClientHelloPayload {
+        let mut exts = vec![ ClientExtension::make_sni(self.get_public_name()) ];
+        exts.extend(inner.extensions
+                    .iter()
+                    .filter(|ext| match ext.get_type() {
+                        ExtensionType::ServerName |
+                            ExtensionType::PreSharedKey |
+                            ExtensionType::EarlyData |
+                            ExtensionType::EncryptedClientHello => false,
+                        _ => true,
+                    })
+                    .cloned());
+
+        // Only the first hello carries our encapsulated key.  After
+        // a HelloRetryRequest we carry on with the same context.
+        let enc = if self.context.is_none() {
+            // create_ech_handshake checked this key.
+            let (enc, context) = hpke::setup_base_sender(self.data.hpke_suite(),
+                                                         &self.data.config.key_config.public_key.0,
+                                                         &self.data.info)
+                .unwrap();
+            self.context = Some(context);
+            enc
+        } else {
+            Vec::new()
+        };
+
+        let encoded_inner = encode_inner_hello(inner, self.data.config.maximum_name_length);
+
+        exts.push(ClientExtension::EncryptedClientHello(ECHClientHello::Outer(ECHClientHelloOuter {
+            cipher_suite: self.data.suite,
+            config_id: self.data.config.key_config.config_id,
+            enc: PayloadU16::new(enc),
+            payload: PayloadU16::new(vec![0u8; encoded_inner.len() + AEAD_TAG_LEN]),
+        })));
+
+        let mut outer = ClientHelloPayload {
+            client_version: inner.client_version,
+            random: Random::from_slice(&self.outer_random),
+            session_id: inner.session_id,
+            cipher_suites: inner.cipher_suites.clone(),
+            compression_methods: inner.compression_methods.clone(),
+            extensions: exts,
+        };
+
+        let aad = make_outer_aad(&outer);
+        let payload = self.context
+            .as_mut()
+            .unwrap()
+            .seal(&aad, &encoded_inner);
+
+        if let Some(ClientExtension::EncryptedClientHello(ECHClientHello::Outer(ref mut ech))) =
+            outer.extensions.last_mut() {
+            ech.payload = PayloadU16::new(payload);
+        }
+
+        self.outer_extensions = outer.extensions
+            .iter()
+            .map(ClientExtension::get_type)
+            .collect();
+        outer
+    }
+}
+
+/// An ECHConfig and its private key.  A server holds one of these
+/// per config it is willing to accept.
+#[derive(Clone)]
+pub struct ECHServerKeys {
+    config: ECHConfigContents,
+    private_key: [u8; x25519::X25519_LEN],
+}
+
+impl ECHServerKeys {
+    /// Make a new config with a fresh X25519 key.  `public_name` is
+    /// the name clients put in their ClientHelloOuter, and which we
+    /// must be able to authenticate as if we reject ECH.
+    pub fn generate(config_id: u8, public_name: &str) -> Result<ECHServerKeys, TLSError> {
+        if webpki::DNSNameRef::try_from_ascii_str(public_name).is_err() {
+            return Err(TLSError::InvalidDNSName(public_name.to_string()));
+        }
+
+        let private_key = hpke::generate_private_key();
+
+        Ok(ECHServerKeys {
+            config: ECHConfigContents {
+                key_config: HpkeKeyConfig {
+                    config_id,
+                    kem: HpkeKem::DHKEM_X25519_HKDF_SHA256,
+                    public_key: PayloadU16::new(x25519::x25519_base(&private_key).to_vec()),
+                    cipher_suites: SUPPORTED_SYMMETRIC_SUITES.to_vec(),
+                },
+                maximum_name_length: DEFAULT_MAXIMUM_NAME_LENGTH,
+                public_name: PayloadU8::new(public_name.as_bytes().to_vec()),
+                extensions: PayloadU16::empty(),
+            },
+            private_key,
+        })
+    }
+
+    /// Load an encoded ECHConfig and its private key, as saved
+    /// from `get_config` and `get_private_key`.
+    pub fn new(config_bytes: &[u8], private_key: &[u8]) -> Result<ECHServerKeys, TLSError> {
+        let config = match ECHConfig::read_bytes(config_bytes) {
+            Some(ECHConfig::V1(config)) => config,
+            _ => return Err(TLSError::General("invalid ECH config".to_string())),
+        };
+
+        if config.key_config.kem != HpkeKem::DHKEM_X25519_HKDF_SHA256 ||
+            private_key.len() != x25519::X25519_LEN {
+            return Err(TLSError::General("unsupported ECH key".to_string()));
+        }
+
+        let mut key = [0u8; x25519::X25519_LEN];
+        key.copy_from_slice(private_key);
+
+        if x25519::x25519_base(&key)[..] != config.key_config.public_key.0[..] {
+            return Err(TLSError::General("ECH private key does not match config".to_string()));
+        }
+
+        Ok(ECHServerKeys {
+            config,
+            private_key: key,
+        })
+    }
+
+    /// The encoded ECHConfig.
+    pub fn get_config(&self) -> Vec<u8> {
+        ECHConfig::V1(self.config.clone()).get_encoding()
+    }
+
+    /// The config's identifier.
+    pub fn get_config_id(&self) -> u8 {
+        self.config.key_config.config_id
+    }
+
+    /// The raw X25519 private key.
+    pub fn get_private_key(&self) -> Vec<u8> {
+        self.private_key.to_vec()
+    }
+}
+
+impl fmt::Debug for ECHServerKeys {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ECHServerKeys")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// Encode the configs of all of `keys` as an ECHConfigList, which
+/// is what clients need to use them.
+pub fn make_config_list(keys: &[ECHServerKeys]) -> Vec<u8> {
+    make_retry_configs(keys).get_encoding()
+}
+
+pub(crate) fn make_retry_configs(keys: &[ECHServerKeys]) -> ECHConfigList {
+    keys.iter()
+        .map(|key| ECHConfig::V1(key.config.clone()))
+        .collect()
+}
+
+/// Decrypt the ClientHelloInner carried in `outer`.  `context` is the
+/// HPKE context from the first ClientHello, if we've sent a
+/// HelloRetryRequest after accepting ECH.
+///
+/// Returns Ok(None) if `outer` was made for a config we don't know,
+/// in which case we continue with the outer hello.
+pub(crate) fn decrypt_client_hello(keys: &[ECHServerKeys],
+                                   outer: &ClientHelloPayload,
+                                   context: Option<HpkeContext>)
+                                   -> Result<Option<(ClientHelloPayload, HpkeContext)>, TLSError> {
+    let ech = match outer.get_ech_extension() {
+        Some(ECHClientHello::Outer(ech)) => ech,
+        Some(ECHClientHello::Inner) => {
+            return Err(TLSError::PeerMisbehavedError("inner ECH extension in ClientHelloOuter"
+                                                     .to_string()));
+        }
+        None => return Ok(None),
+    };
+
+    let mut context = match context {
+        Some(_) if !ech.enc.0.is_empty() => {
+            return Err(TLSError::PeerMisbehavedError("ECH enc changed after HelloRetryRequest"
+                                                     .to_string()));
+        }
+        Some(context) => context,
+        None => {
+            let key = keys.iter()
+                .find(|key| key.config.key_config.config_id == ech.config_id &&
+                            key.config.key_config.cipher_suites.contains(&ech.cipher_suite));
+            let key = match key {
+                Some(key) => key,
+                None => return Ok(None),
+            };
+
+            let suite = hpke_suite(key.config.key_config.kem, ech.cipher_suite);
+            match hpke::setup_base_receiver(suite, &ech.enc.0, &key.private_key,
+                                            &make_info(&key.config)) {
+                Some(context) => context,
+                None => return Err(TLSError::DecryptError),
+            }
+        }
+    };
+
+    let encoded_inner = context.open(&make_outer_aad(outer), &ech.payload.0)
+        .ok_or(TLSError::DecryptError)?;
+
+    let inner = decode_inner_hello(&encoded_inner, outer)
+        .ok_or_else(|| TLSError::PeerMisbehavedError("invalid ClientHelloInner".to_string()))?;
+
+    match inner.get_ech_extension() {
+        Some(ECHClientHello::Inner) => Ok(Some((inner, context))),
+        _ => Err(TLSError::PeerMisbehavedError("ClientHelloInner lacks ECH extension"
+                                               .to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msgs::enums::{ProtocolVersion, CipherSuite, Compression};
+    use crate::crypto::ring::RING;
+
+    fn sample_inner_hello() -> ClientHelloPayload {
+        let dns_name = webpki::DNSNameRef::try_from_ascii_str("secret.example.com").unwrap();
+
+        ClientHelloPayload {
+            client_version: ProtocolVersion::TLSv1_2,
+            random: Random::from_slice(&[1u8; 32]),
+            session_id: SessionID::new(&[2u8; 32]),
+            cipher_suites: vec![ CipherSuite::TLS13_AES_128_GCM_SHA256 ],
+            compression_methods: vec![ Compression::Null ],
+            extensions: vec![
+                ClientExtension::make_sni(dns_name),
+                ClientExtension::EncryptedClientHello(ECHClientHello::Inner),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_client_hello_roundtrip() {
+        let keys = ECHServerKeys::generate(7, "public.example.com").unwrap();
+        let data = create_ech_handshake(&make_config_list(&[keys.clone()])).unwrap();
+        assert_eq!("public.example.com", <&str>::from(data.get_public_name()));
+
+        let inner = sample_inner_hello();
+        let mut state = ECHClientState::new(&RING, data);
+        let outer = state.make_outer_hello(&inner);
+
+        let outer_sni = outer.get_sni_extension().unwrap().get_hostname().unwrap();
+        assert_eq!("public.example.com", <&str>::from(outer_sni));
+        assert_eq!(outer.session_id, inner.session_id);
+
+        let (decrypted, _) = decrypt_client_hello(&[keys], &outer, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(decrypted.get_encoding(), inner.get_encoding());
+    }
+
+    #[test]
+    fn test_inner_hello_is_padded() {
+        let short = sample_inner_hello();
+        let mut long = sample_inner_hello();
+        let name = webpki::DNSNameRef::try_from_ascii_str("a-much-longer-secret-name.example.com")
+            .unwrap();
+        long.extensions[0] = ClientExtension::make_sni(name);
+
+        let short_len = encode_inner_hello(&short, 64).len();
+        assert_eq!(short_len % 32, 0);
+        assert_eq!(short_len, encode_inner_hello(&long, 64).len());
+    }
+
+    #[test]
+    fn test_unknown_config_is_ignored() {
+        let keys = ECHServerKeys::generate(1, "public.example.com").unwrap();
+        let other = ECHServerKeys::generate(2, "public.example.com").unwrap();
+        let data = create_ech_handshake(&make_config_list(&[other])).unwrap();
+
+        let outer = ECHClientState::new(&RING, data).make_outer_hello(&sample_inner_hello());
+        assert!(decrypt_client_hello(&[keys], &outer, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_tampered_outer_hello_fails() {
+        let keys = ECHServerKeys::generate(1, "public.example.com").unwrap();
+        let data = create_ech_handshake(&make_config_list(&[keys.clone()])).unwrap();
+
+        let mut outer = ECHClientState::new(&RING, data).make_outer_hello(&sample_inner_hello());
+        outer.cipher_suites.push(CipherSuite::TLS13_AES_256_GCM_SHA384);
+        assert_eq!(decrypt_client_hello(&[keys], &outer, None).err(),
+                   Some(TLSError::DecryptError));
+    }
+
+    #[test]
+    fn test_server_keys_roundtrip() {
+        let keys = ECHServerKeys::generate(3, "public.example.com").unwrap();
+        let loaded = ECHServerKeys::new(&keys.get_config(), &keys.get_private_key()).unwrap();
+        assert_eq!(loaded.get_config(), keys.get_config());
+        assert_eq!(loaded.get_config_id(), 3);
+
+        let other = ECHServerKeys::generate(3, "public.example.com").unwrap();
+        assert!(ECHServerKeys::new(&keys.get_config(), &other.get_private_key()).is_err());
+        assert!(ECHServerKeys::generate(3, "not a name").is_err());
+    }
+
+    #[test]
+    fn test_skips_unsupported_configs() {
+        let mut keys = ECHServerKeys::generate(1, "public.example.com").unwrap();
+        keys.config.extensions = PayloadU16::new(vec![0xfe, 0x00, 0x00, 0x00]);
+        assert!(create_ech_handshake(&make_config_list(&[keys.clone()])).is_none());
+
+        keys.config.extensions = PayloadU16::new(vec![0x7e, 0x00, 0x00, 0x00]);
+        assert!(create_ech_handshake(&make_config_list(&[keys.clone()])).is_some());
+
+        keys.config.key_config.kem = HpkeKem::DHKEM_P256_HKDF_SHA256;
+        assert!(create_ech_handshake(&make_config_list(&[keys])).is_none());
+    }
+}
@@ -76,6 +76,52 @@ pub enum TLSError {
 
     /// An incoming connection did not support any known application protocol.
     NoApplicationProtocol,
+
+    /// The server rejected our encrypted ClientHello.  The handshake
+    /// was completed with its public name, so any retry configs it
+    /// sent (an encoded ECHConfigList) are authentic and can be used
+    /// to try again.
+    ECHRejected(Option<Vec<u8>>),
+
+    /// The server echoed an ESNI nonce other than the one we
+    /// encrypted with our SNI.
+    ESNINonceMismatch,
+
+    /// We sent ESNI, and the server didn't echo its nonce: it
+    /// didn't decrypt our SNI.
+    ESNINonceMissing,
+
+    /// We were configured to encrypt our SNI, but couldn't, so
+    /// didn't start the handshake.  The string says why.
+    ESNIUnavailable(String),
+
+    /// A stapled OCSP response was needed, but was missing or
+    /// couldn't be verified.  The string says why.
+    InvalidOCSPResponse(String),
+
+    /// The presented certificate has been revoked.
+    CertificateRevoked,
+
+    /// We have no up-to-date revocation information for the presented
+    /// certificate, and our policy requires it.
+    UnknownRevocationStatus,
+
+    /// The server's hostname is pinned, and no key in its
+    /// certificate chain matches the pins.
+    CertificatePinMismatch,
+
+    /// The peer authenticated with a raw public key (RFC7250)
+    /// which we don't trust.
+    UnknownRawPublicKey,
+
+    /// The client's certificate is valid, but doesn't name anyone
+    /// our policy allows.
+    ClientIdentityNotAllowed,
+
+    /// The server's certificate doesn't have the valid SCTs our
+    /// certificate transparency policy requires.  The string says
+    /// what was missing.
+    CTPolicyNotMet(String),
 }
 
 fn join<T: fmt::Debug>(items: &[T]) -> String {
@@ -106,14 +152,28 @@ impl fmt::Display for TLSError {
                 write!(f, "{} of type {:?}", self.description(), typ)
             }
             TLSError::PeerIncompatibleError(ref why) |
-            TLSError::PeerMisbehavedError(ref why) => write!(f, "{}: {}", self.description(), why),
+            TLSError::PeerMisbehavedError(ref why) |
+            TLSError::InvalidOCSPResponse(ref why) |
+            TLSError::ESNIUnavailable(ref why) |
+            TLSError::CTPolicyNotMet(ref why) => write!(f, "{}: {}", self.description(), why),
             TLSError::AlertReceived(ref alert) => write!(f, "{}: {:?}", self.description(), alert),
             TLSError::WebPKIError(ref err) => write!(f, "{}: {:?}", self.description(), err),
             TLSError::CorruptMessage |
             TLSError::NoCertificatesPresented |
             TLSError::DecryptError |
             TLSError::PeerSentOversizedRecord |
+            TLSError::ESNINonceMismatch |
+            TLSError::ESNINonceMissing |
+            TLSError::CertificateRevoked |
+            TLSError::UnknownRevocationStatus |
+            TLSError::CertificatePinMismatch |
+            TLSError::UnknownRawPublicKey |
+            TLSError::ClientIdentityNotAllowed |
             TLSError::HandshakeNotComplete => write!(f, "{}", self.description()),
+            TLSError::ECHRejected(ref retry_configs) => {
+                write!(f, "{} ({} retry configs)", self.description(),
+                       if retry_configs.is_some() { "with" } else { "without" })
+            }
             _ => write!(f, "{}: {:?}", self.description(), self),
         }
     }
@@ -141,6 +201,17 @@ impl Error for TLSError {
             TLSError::HandshakeNotComplete => "handshake not complete",
             TLSError::PeerSentOversizedRecord => "peer sent excess record size",
             TLSError::NoApplicationProtocol => "peer doesn't support any known protocol",
+            TLSError::ECHRejected(_) => "server rejected encrypted client hello",
+            TLSError::ESNINonceMismatch => "server sent wrong ESNI nonce",
+            TLSError::ESNINonceMissing => "server didn't echo ESNI nonce",
+            TLSError::ESNIUnavailable(_) => "cannot encrypt SNI",
+            TLSError::InvalidOCSPResponse(_) => "invalid OCSP response",
+            TLSError::CertificateRevoked => "certificate revoked",
+            TLSError::UnknownRevocationStatus => "certificate revocation status unknown",
+            TLSError::CertificatePinMismatch => "certificate chain doesn't match pinned keys",
+            TLSError::UnknownRawPublicKey => "peer's raw public key is not trusted",
+            TLSError::ClientIdentityNotAllowed => "client certificate names no allowed identity",
+            TLSError::CTPolicyNotMet(_) => "certificate transparency policy not met",
         }
     }
 }
@@ -177,7 +248,19 @@ mod tests {
                        TLSError::InvalidDNSName("dns something".to_string()),
                        TLSError::HandshakeNotComplete,
                        TLSError::PeerSentOversizedRecord,
-                       TLSError::NoApplicationProtocol];
+                       TLSError::NoApplicationProtocol,
+                       TLSError::ECHRejected(None),
+                       TLSError::ECHRejected(Some(vec![0, 0])),
+                       TLSError::ESNINonceMismatch,
+                       TLSError::ESNINonceMissing,
+                       TLSError::ESNIUnavailable("no ESNI record".to_string()),
+                       TLSError::InvalidOCSPResponse("stale".to_string()),
+                       TLSError::CertificateRevoked,
+                       TLSError::UnknownRevocationStatus,
+                       TLSError::CertificatePinMismatch,
+                       TLSError::UnknownRawPublicKey,
+                       TLSError::ClientIdentityNotAllowed,
+                       TLSError::CTPolicyNotMet("no SCTs".to_string())];
 
         for err in all {
             println!("{:?}:", err);
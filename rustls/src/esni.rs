@@ -2,21 +2,33 @@ use crate::client::ClientConfig;
 use crate::msgs::handshake::{ESNIRecord, KeyShareEntry, ServerNamePayload, ServerName, ClientEncryptedSNI, ESNIContents, Random, PaddedServerNameList, ClientESNIInner};
 use crate::msgs::enums::ServerNameType;
 use crate::msgs::enums::ProtocolVersion;
-use crate::suites::{KeyExchange, TLS13_CIPHERSUITES, choose_ciphersuite_preferring_server};
-use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::enums::{NamedGroup, ESNIVersion};
+use crate::suites::{TLS13_CIPHERSUITES, choose_ciphersuite_preferring_server};
+use crate::kx::{KeyExchange, ALL_KX_GROUPS, find_kx_group};
+use crate::msgs::codec::{Codec, Reader, encode_vec_u16, read_vec_u16};
 use crate::rand;
+use crate::crypto::{CryptoProvider, Aead, AeadKey, Hash, Hmac, NONCE_LEN};
+use crate::crypto::ring::RING;
+use crate::time_provider::{TimeProvider, SystemTimeProvider};
 
-use std::time::{SystemTime, UNIX_EPOCH};
-
-use ring::{digest, hkdf};
+use ring::digest;
 use webpki;
 use crate::SupportedCipherSuite;
-use crate::msgs::base::PayloadU16;
-use ring::hkdf::Prk;
-use crate::cipher::{Iv, IvLen};
-use ring::aead::UnboundKey;
+use crate::msgs::base::{PayloadU8, PayloadU16};
+use crate::cipher::Iv;
 use crate::session::SessionRandoms;
-use crate::key_schedule::hkdf_expand;
+use crate::key_schedule::{hkdf_expand, Prk};
+use crate::error::TLSError;
+use crate::x25519;
+use crate::p256;
+
+use std::fmt;
+use std::error::Error;
+
+/// ESNI records are handled apart from any config, so their
+/// cryptography always comes from *ring*, whatever the session's
+/// `CryptoProvider` is.
+static PROVIDER: &dyn CryptoProvider = &RING;
 
 /// Data calculated for a client session from a DNS ESNI record.
 #[derive(Clone, Debug)]
@@ -43,92 +55,167 @@ pub fn create_esni_config() -> ClientConfig {
     config
 }
 
-/// Creates a `ClientConfig` with defaults suitable for ESNI extension support.
-/// This creates a config that supports TLS 1.3 only.
-pub fn create_esni_handshake(record_bytes: &Vec<u8>) -> Option<ESNIHandshakeData> {
-    let record = ESNIRecord::read(&mut Reader::init(&record_bytes))?;
+/// The reasons an ESNI record can't be used, or the SNI
+/// can't be encrypted with it.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ESNIError {
+    /// The record couldn't be decoded.
+    InvalidRecord,
+
+    /// The record's checksum doesn't match its contents.
+    InvalidChecksum,
+
+    /// We failed to figure out what time it currently is.
+    FailedToGetCurrentTime,
+
+    /// The record's `not_before` time is in the future.
+    NotYetValid,
+
+    /// The record's `not_after` time is in the past.
+    Expired,
+
+    /// The record has no key share in a group we support.
+    UnsupportedGroup,
+
+    /// The record has no cipher suite we support.
+    NoSharedCipherSuite,
+
+    /// The record's key share couldn't be used for key exchange.
+    KeyExchangeFailed,
+
+    /// Encrypting the SNI failed.
+    EncryptionFailed,
+}
+
+impl fmt::Display for ESNIError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+impl Error for ESNIError {
+    fn description(&self) -> &str {
+        match *self {
+            ESNIError::InvalidRecord => "invalid ESNI record",
+            ESNIError::InvalidChecksum => "ESNI record checksum is invalid",
+            ESNIError::FailedToGetCurrentTime => "failed to get current time",
+            ESNIError::NotYetValid => "ESNI record is not yet valid",
+            ESNIError::Expired => "ESNI record has expired",
+            ESNIError::UnsupportedGroup => "ESNI record has no supported key share",
+            ESNIError::NoSharedCipherSuite => "ESNI record has no supported cipher suite",
+            ESNIError::KeyExchangeFailed => "ESNI key exchange failed",
+            ESNIError::EncryptionFailed => "ESNI encryption failed",
+        }
+    }
+}
+
+/// Prepare to encrypt the SNI using a DNS ESNI record.  `record_bytes`
+/// is the encoded ESNIKeys structure, after base64 decoding.
+///
+/// The record's validity period is checked against the system time.
+pub fn create_esni_handshake(record_bytes: &Vec<u8>) -> Result<ESNIHandshakeData, ESNIError> {
+    create_esni_handshake_with_clock(record_bytes, &SystemTimeProvider)
+}
+
+/// Like `create_esni_handshake`, but the record's validity period
+/// is checked against `clock`: usually the `ClientConfig`'s
+/// `time_provider`.
+pub fn create_esni_handshake_with_clock(record_bytes: &Vec<u8>,
+                                        clock: &dyn TimeProvider) -> Result<ESNIHandshakeData, ESNIError> {
+    let record = ESNIRecord::read(&mut Reader::init(&record_bytes))
+        .ok_or(ESNIError::InvalidRecord)?;
+
+    if !record.is_checksum_valid() {
+        return Err(ESNIError::InvalidChecksum);
+    }
+
     // Check whether the record is still valid
-    let now = now()?;
+    let now = clock.now()
+        .ok_or(ESNIError::FailedToGetCurrentTime)?;
+
+    if now < record.not_before {
+        return Err(ESNIError::NotYetValid);
+    }
 
-    if now < record.not_before || now > record.not_after {
-        return None
+    if now > record.not_after {
+        return Err(ESNIError::Expired);
     }
 
     record_to_handshake_data(&record, record_bytes)
 }
 
-fn record_to_handshake_data(record: &ESNIRecord, record_bytes: &Vec<u8>) -> Option<ESNIHandshakeData> {
-    let peer_share = match KeyExchange::supported_groups()
+fn record_to_handshake_data(record: &ESNIRecord,
+                            record_bytes: &Vec<u8>) -> Result<ESNIHandshakeData, ESNIError> {
+    let peer_share = ALL_KX_GROUPS
         .iter()
-        .flat_map(|group| {
-            record.keys.iter().find(|key| { key.group == *group })
+        .flat_map(|skxg| {
+            record.keys.iter().find(|key| { key.group == skxg.name() })
         }).nth(0)
-        .cloned() {
-        Some(entry) => entry,
-        None => return None,
-    };
+        .cloned()
+        .ok_or(ESNIError::UnsupportedGroup)?;
 
-    let cipher_suite= match
+    let cipher_suite =
         choose_ciphersuite_preferring_server(record.cipher_suites.as_slice(),
-                                             &TLS13_CIPHERSUITES) {
-        Some(entry) => entry,
-        None => return None,
-    };
+                                             &TLS13_CIPHERSUITES)
+        .ok_or(ESNIError::NoSharedCipherSuite)?;
 
-    Some(ESNIHandshakeData {
+    let hash = PROVIDER.suite_hash(cipher_suite)
+        .map_err(|_| ESNIError::NoSharedCipherSuite)?;
+
+    Ok(ESNIHandshakeData {
         peer_share,
         cipher_suite,
         padded_length: record.padded_length,
-        record_digest: record_digest(cipher_suite.get_hash(), record_bytes),
+        record_digest: record_digest(hash, record_bytes),
     })
 }
 
-fn now() -> Option<u64> {
-    let start = SystemTime::now();
-    match start.duration_since(UNIX_EPOCH)  {
-        Err(_e) => None,
-        Ok(since_the_epoch) => Some(since_the_epoch.as_secs())
-    }
+/// The hash, HMAC and AEAD of `suite`.
+fn suite_algorithms(suite: &SupportedCipherSuite)
+                    -> Result<(&'static dyn Hash, &'static dyn Hmac, &'static dyn Aead), TLSError> {
+    Ok((PROVIDER.suite_hash(suite)?, PROVIDER.suite_hmac(suite)?, PROVIDER.suite_aead(suite)?))
 }
 
-fn record_digest(algorithm: &'static ring::digest::Algorithm, bytes: &[u8]) -> Vec<u8> {
-    digest::digest(algorithm, bytes).as_ref().to_vec()
+fn record_digest(hash: &dyn Hash, bytes: &[u8]) -> Vec<u8> {
+    hash.hash(bytes)
 }
 
-/// Compute the encrypted SNI
+/// Compute the encrypted SNI.  `key_share_bytes` are the encoded key
+/// shares of the ClientHello carrying it, and `nonce` is what the server
+/// should echo back if it decrypts it.
 pub fn compute_esni(dns_name: webpki::DNSNameRef,
                     hs_data: &ESNIHandshakeData,
                     key_share_bytes: Vec<u8>,
-                    randoms: &SessionRandoms) -> Option<ClientEncryptedSNI> {
-    let mut nonce = [0u8; 16];
-    rand::fill_random(&mut nonce);
+                    randoms: &SessionRandoms,
+                    nonce: [u8; 16]) -> Result<ClientEncryptedSNI, ESNIError> {
     let mut sni_bytes = compute_client_esni_inner(dns_name, hs_data.padded_length, nonce);
     let mut peer_bytes = Vec::new();
     hs_data.peer_share.clone().encode(&mut peer_bytes);
 
-    let key_exchange = match KeyExchange::start_ecdhe(hs_data.peer_share.group) {
-        Some(ke) => ke,
-        None => return None,
-    };
-    let exchange_result = key_exchange.complete(&hs_data.peer_share.payload.0)?;
-    let contents_bytes = compute_esni_content(&hs_data, &exchange_result.pubkey.clone().as_ref().to_vec(), randoms.client);
-    let hash = esni_hash(&contents_bytes, hs_data.cipher_suite.get_hash());
+    let key_exchange = find_kx_group(hs_data.peer_share.group, &ALL_KX_GROUPS)
+        .and_then(KeyExchange::start)
+        .ok_or(ESNIError::UnsupportedGroup)?;
+    let exchange_result = key_exchange.complete(&hs_data.peer_share.payload.0)
+        .ok_or(ESNIError::KeyExchangeFailed)?;
+    let contents_bytes = compute_esni_content(&hs_data, &exchange_result.pubkey, randoms.client);
+    let (suite_hash, suite_hmac, suite_aead) = suite_algorithms(hs_data.cipher_suite)
+        .map_err(|_| ESNIError::NoSharedCipherSuite)?;
+    let hash = esni_hash(&contents_bytes, suite_hash);
 
-    let zx = zx(hs_data.cipher_suite.hkdf_algorithm, &exchange_result.premaster_secret);
-    let key = hkdf_expand(&zx, hs_data.cipher_suite.get_aead_alg(), b"esni key", &hash);
-    let iv: Iv = hkdf_expand(&zx, IvLen, b"esni iv", &hash);
-    let aad = ring::aead::Aad::from(key_share_bytes.to_vec());
+    let zx = zx(suite_hmac, &exchange_result.premaster_secret);
+    let key = esni_key(&zx, suite_aead, &hash);
+    let iv = esni_iv(&zx, &hash);
 
-    match encrypt(key, iv, aad, &mut sni_bytes) {
+    match encrypt(key, iv, &key_share_bytes, &mut sni_bytes) {
         Some(bytes) => {
-            Some (ClientEncryptedSNI {
+            Ok(ClientEncryptedSNI {
                 suite: hs_data.cipher_suite.suite,
-                key_share_entry: KeyShareEntry::new(hs_data.peer_share.group, exchange_result.pubkey.as_ref()),
+                key_share_entry: KeyShareEntry::new(hs_data.peer_share.group, &exchange_result.pubkey),
                 record_digest: PayloadU16(hs_data.record_digest.clone()),
                 encrypted_sni: PayloadU16(bytes),
             })
         },
-        _ => None
+        _ => Err(ESNIError::EncryptionFailed)
     }
 }
 
@@ -166,34 +253,532 @@ fn compute_client_esni_inner(dns_name: webpki::DNSNameRef, length: u16, nonce: [
     sni_bytes
 }
 
-fn esni_hash(encoded_esni_contents: &Vec<u8>, algorithm: &'static ring::digest::Algorithm) -> Vec<u8> {
-    let digest = digest::digest(algorithm, &encoded_esni_contents);
-    digest.as_ref().to_vec()
+fn esni_hash(encoded_esni_contents: &Vec<u8>, hash: &dyn Hash) -> Vec<u8> {
+    hash.hash(encoded_esni_contents)
 }
 
-fn zx(algorithm: ring::hkdf::Algorithm, secret: &Vec<u8>) -> Prk {
-    let salt = hkdf::Salt::new(algorithm, &[]);
-    salt.extract(secret)
+fn zx(hmac: &'static dyn Hmac, secret: &Vec<u8>) -> Prk {
+    Prk::extract(hmac, &[], secret)
 }
 
-fn encrypt(unbound: UnboundKey, iv: Iv, aad: ring::aead::Aad<Vec<u8>>, sni_bytes: &mut Vec<u8>) -> Option<Vec<u8>> {
-    let lsk = ring::aead::LessSafeKey::new(unbound);
-    match lsk.seal_in_place_append_tag(ring::aead::Nonce::assume_unique_for_key(*iv.value()),
-                                       aad,
-                                       sni_bytes) {
+fn esni_key(zx: &Prk, aead: &dyn Aead, hash: &[u8]) -> Box<dyn AeadKey> {
+    aead.new_key(&hkdf_expand(zx, aead.key_len(), b"esni key", hash))
+}
+
+fn esni_iv(zx: &Prk, hash: &[u8]) -> Iv {
+    let mut iv = [0u8; NONCE_LEN];
+    iv.copy_from_slice(&hkdf_expand(zx, NONCE_LEN, b"esni iv", hash));
+    Iv::new(iv)
+}
+
+fn encrypt(key: Box<dyn AeadKey>, iv: Iv, aad: &[u8], sni_bytes: &mut Vec<u8>) -> Option<Vec<u8>> {
+    match key.seal_in_place_append_tag(*iv.value(), aad, sni_bytes) {
         Ok(_) => Some(sni_bytes.clone()),
         _ => None
     }
 }
 
+/// A private key matching one of the key shares published
+/// in an ESNI DNS record.  X25519 and secp256r1 are supported.
+#[derive(Clone)]
+pub struct ESNIPrivateKey {
+    group: NamedGroup,
+    scalar: [u8; x25519::X25519_LEN],
+}
+
+impl ESNIPrivateKey {
+    /// Make an X25519 private key from its 32 raw bytes.
+    /// Returns None if `bytes` is the wrong length.
+    pub fn new_x25519(bytes: &[u8]) -> Option<ESNIPrivateKey> {
+        if bytes.len() != x25519::X25519_LEN {
+            return None;
+        }
+
+        let mut scalar = [0u8; x25519::X25519_LEN];
+        scalar.copy_from_slice(bytes);
+        Some(ESNIPrivateKey {
+            group: NamedGroup::X25519,
+            scalar,
+        })
+    }
+
+    /// Make a secp256r1 private key from its 32-byte big-endian
+    /// scalar.  Returns None if `bytes` is the wrong length, or not
+    /// a valid scalar.
+    pub fn new_p256(bytes: &[u8]) -> Option<ESNIPrivateKey> {
+        if bytes.len() != p256::P256_SCALAR_LEN {
+            return None;
+        }
+
+        let mut scalar = [0u8; p256::P256_SCALAR_LEN];
+        scalar.copy_from_slice(bytes);
+        if !p256::is_valid_scalar(&scalar) {
+            return None;
+        }
+
+        Some(ESNIPrivateKey {
+            group: NamedGroup::secp256r1,
+            scalar,
+        })
+    }
+
+    /// Generate a new random private key in `group`.
+    /// X25519 and secp256r1 are supported.
+    pub fn generate(group: NamedGroup) -> Result<ESNIPrivateKey, TLSError> {
+        let mut scalar = [0u8; x25519::X25519_LEN];
+
+        match group {
+            NamedGroup::X25519 => {
+                rand::fill_random(&RING, &mut scalar);
+            }
+            NamedGroup::secp256r1 => {
+                // Rejection sampling: all but a tiny fraction of
+                // random values are in range first time.
+                rand::fill_random(&RING, &mut scalar);
+                while !p256::is_valid_scalar(&scalar) {
+                    rand::fill_random(&RING, &mut scalar);
+                }
+            }
+            _ => {
+                return Err(TLSError::General(format!("unsupported ESNI key group {:?}", group)));
+            }
+        }
+
+        Ok(ESNIPrivateKey {
+            group,
+            scalar,
+        })
+    }
+
+    /// The group this key is for.
+    pub fn get_group(&self) -> NamedGroup {
+        self.group
+    }
+
+    /// The public key, as it appears in the record's key share.
+    pub fn get_public_key(&self) -> Vec<u8> {
+        match self.group {
+            NamedGroup::secp256r1 => p256::public_key(&self.scalar)
+                .expect("ESNI secp256r1 key was checked when made"),
+            _ => x25519::x25519_base(&self.scalar).to_vec(),
+        }
+    }
+
+    fn agree(&self, peer: &[u8]) -> Option<Vec<u8>> {
+        match self.group {
+            NamedGroup::secp256r1 => p256::agree(&self.scalar, peer),
+            _ => x25519::agree(&self.scalar, peer),
+        }.map(|secret| secret.to_vec())
+    }
+}
+
+impl Codec for ESNIPrivateKey {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.group.encode(bytes);
+        PayloadU8::new(self.scalar.to_vec()).encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<ESNIPrivateKey> {
+        let group = NamedGroup::read(r)?;
+        let scalar = PayloadU8::read(r)?;
+
+        match group {
+            NamedGroup::X25519 => ESNIPrivateKey::new_x25519(&scalar.0),
+            NamedGroup::secp256r1 => ESNIPrivateKey::new_p256(&scalar.0),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for ESNIPrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ESNIPrivateKey")
+            .field("group", &self.group)
+            .finish()
+    }
+}
+
+/// A published ESNI DNS record, and the private keys for
+/// its key shares.  A server holds one of these per record
+/// it is willing to accept.
+#[derive(Clone, Debug)]
+pub struct ESNIServerKeys {
+    record_bytes: Vec<u8>,
+    record: ESNIRecord,
+    private_keys: Vec<ESNIPrivateKey>,
+}
+
+impl ESNIServerKeys {
+    /// Make a new `ESNIServerKeys`.  `record_bytes` is the encoded
+    /// ESNIKeys structure, exactly as published in DNS (after base64
+    /// decoding).  Every key in `private_keys` must match one of the
+    /// record's key shares.
+    pub fn new(record_bytes: &[u8],
+               private_keys: Vec<ESNIPrivateKey>) -> Result<ESNIServerKeys, TLSError> {
+        let record = ESNIRecord::read(&mut Reader::init(record_bytes))
+            .ok_or_else(|| TLSError::General("invalid ESNI record".to_string()))?;
+
+        if !record.is_checksum_valid() {
+            return Err(TLSError::General("ESNI record checksum is invalid".to_string()));
+        }
+
+        if private_keys.is_empty() {
+            return Err(TLSError::General("no ESNI private keys".to_string()));
+        }
+
+        for key in &private_keys {
+            let public_key = key.get_public_key();
+            if !record.keys.iter().any(|share| share.group == key.group && share.payload.0 == public_key) {
+                return Err(TLSError::General("ESNI private key not in record".to_string()));
+            }
+        }
+
+        Ok(ESNIServerKeys {
+            record_bytes: record_bytes.to_vec(),
+            record,
+            private_keys,
+        })
+    }
+
+    fn find_private_key(&self, group: NamedGroup) -> Option<&ESNIPrivateKey> {
+        self.private_keys
+            .iter()
+            .find(|key| key.group == group)
+    }
+}
+
+/// Makes a new ESNI DNS record, with fresh private keys.
+///
+/// The defaults are one X25519 key share, TLS13_AES_128_GCM_SHA256 and
+/// names padded to 260 bytes.
+#[derive(Clone, Debug)]
+pub struct ESNIKeysBuilder {
+    groups: Vec<NamedGroup>,
+    cipher_suites: Vec<&'static SupportedCipherSuite>,
+    padded_length: u16,
+    not_before: u64,
+    not_after: u64,
+}
+
+impl ESNIKeysBuilder {
+    /// Start making a record which is valid from `not_before` until
+    /// `not_after`, both in seconds since the UNIX epoch.
+    pub fn new(not_before: u64, not_after: u64) -> ESNIKeysBuilder {
+        ESNIKeysBuilder {
+            groups: vec![ NamedGroup::X25519 ],
+            cipher_suites: vec![ &crate::suites::TLS13_AES_128_GCM_SHA256 ],
+            padded_length: 260,
+            not_before,
+            not_after,
+        }
+    }
+
+    /// Generate a key share in each of `groups`.
+    pub fn groups(mut self, groups: &[NamedGroup]) -> ESNIKeysBuilder {
+        self.groups = groups.to_vec();
+        self
+    }
+
+    /// Offer `cipher_suites`, which must all be TLS1.3 suites.
+    pub fn cipher_suites(mut self, cipher_suites: &[&'static SupportedCipherSuite]) -> ESNIKeysBuilder {
+        self.cipher_suites = cipher_suites.to_vec();
+        self
+    }
+
+    /// Have clients pad their server names to `padded_length` bytes.
+    pub fn padded_length(mut self, padded_length: u16) -> ESNIKeysBuilder {
+        self.padded_length = padded_length;
+        self
+    }
+
+    /// Generate the private keys and make the record.
+    pub fn build(self) -> Result<ESNIKeys, TLSError> {
+        if self.groups.is_empty() {
+            return Err(TLSError::General("ESNI record needs a key share".to_string()));
+        }
+
+        if self.cipher_suites.is_empty() ||
+            self.cipher_suites.iter().any(|scs| !scs.usable_for_version(ProtocolVersion::TLSv1_3)) {
+            return Err(TLSError::General("ESNI record needs TLS1.3 cipher suites".to_string()));
+        }
+
+        if self.not_before > self.not_after {
+            return Err(TLSError::General("ESNI record expires before it is valid".to_string()));
+        }
+
+        let mut private_keys = Vec::new();
+        for group in &self.groups {
+            private_keys.push(ESNIPrivateKey::generate(*group)?);
+        }
+
+        let record = ESNIRecord {
+            version: ESNIVersion::V1,
+            checksum: vec![0u8; 4],
+            checksum_valid: true,
+            keys: private_keys.iter()
+                .map(|key| KeyShareEntry::new(key.group, &key.get_public_key()))
+                .collect(),
+            cipher_suites: self.cipher_suites.iter()
+                .map(|scs| scs.suite)
+                .collect(),
+            padded_length: self.padded_length,
+            not_before: self.not_before,
+            not_after: self.not_after,
+            extensions: PayloadU16::empty(),
+        };
+
+        // The checksum covers the record with a zero checksum.
+        let mut record_bytes = record.get_encoding();
+        let checksum = digest::digest(&digest::SHA256, &record_bytes);
+        record_bytes[2..6].copy_from_slice(&checksum.as_ref()[..4]);
+
+        Ok(ESNIKeys {
+            record_bytes,
+            private_keys,
+        })
+    }
+}
+
+/// A generated ESNI DNS record and its private keys.
+///
+/// These can be saved with `to_bytes` and loaded again with
+/// `from_bytes`, so the same keys can be used until the record
+/// is rotated.
+#[derive(Clone, Debug)]
+pub struct ESNIKeys {
+    record_bytes: Vec<u8>,
+    private_keys: Vec<ESNIPrivateKey>,
+}
+
+impl ESNIKeys {
+    /// The encoded ESNIKeys record.  This is what
+    /// `create_esni_handshake` expects.
+    pub fn get_record_bytes(&self) -> &[u8] {
+        &self.record_bytes
+    }
+
+    /// The record, base64 encoded for publishing as
+    /// a DNS TXT record.
+    pub fn get_txt_record(&self) -> String {
+        base64::encode(&self.record_bytes)
+    }
+
+    /// The private keys for the record's key shares.
+    pub fn get_private_keys(&self) -> &[ESNIPrivateKey] {
+        &self.private_keys
+    }
+
+    /// Make the `ESNIServerKeys` for use in a `ServerConfig`.
+    pub fn to_server_keys(&self) -> Result<ESNIServerKeys, TLSError> {
+        ESNIServerKeys::new(&self.record_bytes, self.private_keys.clone())
+    }
+
+    /// Serialise the record and private keys, for storage.
+    ///
+    /// The result contains private key material, so treat it
+    /// as **highly sensitive data**.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        PayloadU16::new(self.record_bytes.clone()).encode(&mut bytes);
+        encode_vec_u16(&mut bytes, &self.private_keys);
+        bytes
+    }
+
+    /// Load keys previously saved with `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ESNIKeys, TLSError> {
+        let mut rd = Reader::init(bytes);
+        let record_bytes = PayloadU16::read(&mut rd);
+        let private_keys = read_vec_u16::<ESNIPrivateKey>(&mut rd);
+
+        match (record_bytes, private_keys) {
+            (Some(record_bytes), Some(private_keys)) if !rd.any_left() => {
+                // Check the keys belong to the record.
+                ESNIServerKeys::new(&record_bytes.0, private_keys.clone())?;
+
+                Ok(ESNIKeys {
+                    record_bytes: record_bytes.0,
+                    private_keys,
+                })
+            }
+            _ => Err(TLSError::General("invalid saved ESNI keys".to_string())),
+        }
+    }
+}
+
+/// Decrypt a client's encrypted SNI using our configured `keys`.
+///
+/// `key_share_bytes` is the encoded body of the ClientHello's key_share
+/// extension, which is the AAD.  Returns Ok(None) if the extension was
+/// made for a record we don't know, in which case it should be ignored.
+pub(crate) fn decrypt_esni(keys: &[ESNIServerKeys],
+                           esni: &ClientEncryptedSNI,
+                           key_share_bytes: &[u8],
+                           client_random: &[u8; 32]) -> Result<Option<ClientESNIInner>, TLSError> {
+    let cipher_suite = match TLS13_CIPHERSUITES.iter().find(|scs| scs.suite == esni.suite) {
+        Some(scs) => *scs,
+        None => return Ok(None),
+    };
+
+    let (suite_hash, suite_hmac, suite_aead) = suite_algorithms(cipher_suite)?;
+    let server_keys = match keys.iter()
+        .find(|k| {
+            k.record.cipher_suites.contains(&cipher_suite.suite) &&
+                record_digest(suite_hash, &k.record_bytes) == esni.record_digest.0
+        }) {
+        Some(k) => k,
+        None => return Ok(None),
+    };
+
+    let private_key = server_keys.find_private_key(esni.key_share_entry.group)
+        .ok_or_else(|| TLSError::PeerMisbehavedError("ESNI key share in unknown group".to_string()))?;
+    let shared_secret = private_key.agree(&esni.key_share_entry.payload.0)
+        .ok_or_else(|| TLSError::PeerMisbehavedError("invalid ESNI key share".to_string()))?;
+
+    let contents = ESNIContents {
+        record_digest: esni.record_digest.clone(),
+        esni_key_share: esni.key_share_entry.clone(),
+        client_hello_random: Random::from_slice(client_random),
+    };
+    let hash = esni_hash(&contents.get_encoding(), suite_hash);
+
+    let zx = zx(suite_hmac, &shared_secret);
+    let key = esni_key(&zx, suite_aead, &hash);
+    let iv = esni_iv(&zx, &hash);
+
+    let plain = decrypt(key, iv, key_share_bytes, &esni.encrypted_sni.0)
+        .ok_or(TLSError::DecryptError)?;
+
+    let mut rd = Reader::init(&plain);
+    let inner = ClientESNIInner::read(&mut rd)
+        .ok_or_else(|| TLSError::PeerMisbehavedError("invalid ESNI plaintext".to_string()))?;
+
+    if inner.real_sni.padded_length != server_keys.record.padded_length {
+        return Err(TLSError::PeerMisbehavedError("ESNI padded to wrong length".to_string()));
+    }
+
+    Ok(Some(inner))
+}
+
+fn decrypt(key: Box<dyn AeadKey>, iv: Iv, aad: &[u8], encrypted: &[u8]) -> Option<Vec<u8>> {
+    let mut buf = encrypted.to_vec();
+    let plain_len = key.open_in_place(*iv.value(), aad, &mut buf).ok()?;
+    buf.truncate(plain_len);
+    Some(buf)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::key_schedule::hkdf_expand;
-    use crate::cipher::{Iv, IvLen};
+    use super::PROVIDER;
+    use crate::crypto::HashAlgorithm;
+    use crate::suites::TLS13_AES_128_GCM_SHA256;
     use crate::msgs::handshake::ESNIRecord;
-    use crate::msgs::codec::{Codec, Reader};
+    use crate::msgs::codec::{Codec, Reader, encode_vec_u16};
+    use crate::msgs::enums::{NamedGroup, CipherSuite};
+    use crate::msgs::base::PayloadU16;
     use webpki::DNSNameRef;
 
+    #[test]
+    fn test_build_esni_keys() {
+        let keys = super::ESNIKeysBuilder::new(1000, 2000)
+            .padded_length(128)
+            .build()
+            .unwrap();
+
+        let record = ESNIRecord::read(&mut Reader::init(keys.get_record_bytes())).unwrap();
+        assert!(record.is_checksum_valid());
+        assert_eq!(record.keys.len(), 1);
+        assert_eq!(record.keys[0].group, NamedGroup::X25519);
+        assert_eq!(record.keys[0].payload.0, keys.get_private_keys()[0].get_public_key());
+        assert_eq!(record.cipher_suites, vec![ CipherSuite::TLS13_AES_128_GCM_SHA256 ]);
+        assert_eq!(record.padded_length, 128);
+        assert_eq!(record.not_before, 1000);
+        assert_eq!(record.not_after, 2000);
+        assert_eq!(record.get_encoding(), keys.get_record_bytes());
+
+        assert_eq!(base64::decode(&keys.get_txt_record()).unwrap(), keys.get_record_bytes());
+        assert!(super::record_to_handshake_data(&record, &keys.get_record_bytes().to_vec()).is_ok());
+        assert!(keys.to_server_keys().is_ok());
+    }
+
+    #[test]
+    fn test_build_esni_keys_rejects_bad_params() {
+        use crate::suites::TLS13_CHACHA20_POLY1305_SHA256;
+        use crate::suites::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256;
+
+        let builder = super::ESNIKeysBuilder::new(0, 1);
+        assert!(builder.clone().groups(&[]).build().is_err());
+        assert!(builder.clone().groups(&[ NamedGroup::secp384r1 ]).build().is_err());
+        assert!(builder.clone().cipher_suites(&[]).build().is_err());
+        assert!(builder.clone()
+                .cipher_suites(&[ &TLS13_CHACHA20_POLY1305_SHA256,
+                                  &TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256 ])
+                .build()
+                .is_err());
+        assert!(super::ESNIKeysBuilder::new(1, 0).build().is_err());
+    }
+
+    #[test]
+    fn test_esni_keys_roundtrip() {
+        let keys = super::ESNIKeysBuilder::new(0, 1)
+            .groups(&[ NamedGroup::X25519, NamedGroup::secp256r1 ])
+            .build()
+            .unwrap();
+        let bytes = keys.to_bytes();
+        let loaded = super::ESNIKeys::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.get_record_bytes(), keys.get_record_bytes());
+        assert_eq!(loaded.get_private_keys().len(), 2);
+        for (loaded_key, key) in loaded.get_private_keys().iter().zip(keys.get_private_keys()) {
+            assert_eq!(loaded_key.get_group(), key.get_group());
+            assert_eq!(loaded_key.get_public_key(), key.get_public_key());
+        }
+        assert_eq!(loaded.get_private_keys()[1].get_group(), NamedGroup::secp256r1);
+        assert_eq!(loaded.get_private_keys()[1].get_public_key().len(), 65);
+        assert_eq!(loaded.to_bytes(), bytes);
+
+        assert!(super::ESNIKeys::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(super::ESNIKeys::from_bytes(&[]).is_err());
+
+        let other = super::ESNIKeysBuilder::new(0, 1).build().unwrap();
+        let mut mixed = Vec::new();
+        PayloadU16::new(keys.get_record_bytes().to_vec()).encode(&mut mixed);
+        encode_vec_u16(&mut mixed, other.get_private_keys());
+        assert!(super::ESNIKeys::from_bytes(&mixed).is_err());
+    }
+
+    struct FixedClock(Option<u64>);
+
+    impl crate::time_provider::TimeProvider for FixedClock {
+        fn now(&self) -> Option<u64> {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_create_esni_handshake_errors() {
+        use super::{ESNIError, create_esni_handshake_with_clock};
+
+        let keys = super::ESNIKeysBuilder::new(1000, 2000).build().unwrap();
+        let record = keys.get_record_bytes().to_vec();
+
+        assert!(create_esni_handshake_with_clock(&record, &FixedClock(Some(1000))).is_ok());
+        assert!(create_esni_handshake_with_clock(&record, &FixedClock(Some(2000))).is_ok());
+        assert_eq!(create_esni_handshake_with_clock(&record, &FixedClock(Some(999))).unwrap_err(),
+                   ESNIError::NotYetValid);
+        assert_eq!(create_esni_handshake_with_clock(&record, &FixedClock(Some(2001))).unwrap_err(),
+                   ESNIError::Expired);
+        assert_eq!(create_esni_handshake_with_clock(&record, &FixedClock(None)).unwrap_err(),
+                   ESNIError::FailedToGetCurrentTime);
+
+        let mut bad_checksum = record.clone();
+        bad_checksum[2] ^= 1;
+        assert_eq!(create_esni_handshake_with_clock(&bad_checksum, &FixedClock(Some(1500))).unwrap_err(),
+                   ESNIError::InvalidChecksum);
+
+        let truncated = record[..record.len() - 1].to_vec();
+        assert_eq!(create_esni_handshake_with_clock(&truncated, &FixedClock(Some(1500))).unwrap_err(),
+                   ESNIError::InvalidRecord);
+    }
+
     #[test]
     fn test_compute_esni_content() {
         let esni_keys = hex!("
@@ -246,7 +831,7 @@ mod tests {
             0c e3 8c 2c 67 21 8e bf ab 88 90 04 49 cc 23 92
         ");
 
-        let result = super::record_digest(&ring::digest::SHA256, &esni_keys);
+        let result = super::record_digest(PROVIDER.hash(HashAlgorithm::SHA256).unwrap(), &esni_keys);
         assert!(crate::msgs::handshake::slice_eq(&expected, &result));
     }
 
@@ -299,7 +884,7 @@ mod tests {
             94 94 b2 6e e6 55 08 0e 47 21 6a b2 3b 7d 25 f7
         ");
 
-        let result = super::esni_hash(&esni_bytes.to_vec(), &ring::digest::SHA256);
+        let result = super::esni_hash(&esni_bytes.to_vec(), PROVIDER.hash(HashAlgorithm::SHA256).unwrap());
         assert!(crate::msgs::handshake::slice_eq(&expected, &result));
     }
 
@@ -372,17 +957,15 @@ mod tests {
             33 15 75 24
         ");
 
-        let hkdf_alg = crate::suites::TLS13_AES_128_GCM_SHA256.hkdf_algorithm;
-        let zx = super::zx(hkdf_alg, &z_bytes.to_vec());
-        let aead_alg = crate::suites::TLS13_AES_128_GCM_SHA256.get_aead_alg();
-        let key = hkdf_expand(&zx, aead_alg, b"esni key", hash.as_ref());
+        let suite = &TLS13_AES_128_GCM_SHA256;
+        let zx = super::zx(PROVIDER.suite_hmac(suite).unwrap(), &z_bytes.to_vec());
+        let key = super::esni_key(&zx, PROVIDER.suite_aead(suite).unwrap(), hash.as_ref());
 
-        let iv: Iv = hkdf_expand(&zx, IvLen, b"esni iv", hash.as_ref());
+        let iv = super::esni_iv(&zx, hash.as_ref());
         assert!(crate::msgs::handshake::slice_eq(&expected_iv, iv.value()));
 
-        let aad = ring::aead::Aad::from(aad_bytes.to_vec());
         let mut sni_bytes = Vec::from(plain_text.to_vec());
-        let encrypted = super::encrypt(key, iv, aad, &mut sni_bytes).unwrap();
+        let encrypted = super::encrypt(key, iv, &aad_bytes, &mut sni_bytes).unwrap();
 
         assert!(crate::msgs::handshake::slice_eq(&expected, &encrypted));
     }
@@ -447,12 +1030,10 @@ mod tests {
             62 ee 41 8a
          ");
 
-        let alg= crate::suites::TLS13_AES_128_GCM_SHA256.get_aead_alg();
-        let key = ring::aead::UnboundKey::new(alg, &key_bytes).unwrap();
+        let key = PROVIDER.suite_aead(&TLS13_AES_128_GCM_SHA256).unwrap().new_key(&key_bytes);
         let iv = crate::cipher::Iv::new(iv_bytes);
-        let aad = ring::aead::Aad::from(aad_bytes.to_vec());
         let mut sni_bytes = Vec::from(plain_text.to_vec());
-        let encrypted = super::encrypt(key, iv, aad, &mut sni_bytes).unwrap();
+        let encrypted = super::encrypt(key, iv, &aad_bytes, &mut sni_bytes).unwrap();
         assert_eq!(expected.len(), encrypted.len());
         assert!(crate::msgs::handshake::slice_eq(&expected, encrypted.as_slice()));
     }
@@ -1,4 +1,4 @@
-use ring::digest;
+use crate::crypto::{Hash, HashContext};
 use std::mem;
 use crate::msgs::codec::Codec;
 use crate::msgs::message::{Message, MessagePayload};
@@ -15,10 +15,10 @@ use crate::log::warn;
 /// This is disabled in cases where client auth is not possible.
 pub struct HandshakeHash {
     /// None before we know what hash function we're using
-    alg: Option<&'static digest::Algorithm>,
+    alg: Option<&'static dyn Hash>,
 
     /// None before we know what hash function we're using
-    ctx: Option<digest::Context>,
+    ctx: Option<Box<dyn HashContext>>,
 
     /// true if we need to keep all messages
     client_auth_enabled: bool,
@@ -52,11 +52,11 @@ impl HandshakeHash {
     }
 
     /// We now know what hash function the verify_data will use.
-    pub fn start_hash(&mut self, alg: &'static digest::Algorithm) -> bool {
+    pub fn start_hash(&mut self, alg: &'static dyn Hash) -> bool {
         match self.alg {
             None => {},
             Some(started) => {
-                if started != alg {
+                if started.algorithm() != alg.algorithm() {
                     // hash type is changing
                     warn!("altered hash to HandshakeHash::start_hash");
                     return false;
@@ -68,7 +68,7 @@ impl HandshakeHash {
         self.alg = Some(alg);
         debug_assert!(self.ctx.is_none());
 
-        let mut ctx = digest::Context::new(alg);
+        let mut ctx = alg.start();
         ctx.update(&self.buffer);
         self.ctx = Some(ctx);
 
@@ -106,20 +106,17 @@ impl HandshakeHash {
 
     /// Get the hash value if we were to hash `extra` too,
     /// using hash function `hash`.
-    pub fn get_hash_given(&self, hash: &'static digest::Algorithm, extra: &[u8]) -> Vec<u8> {
+    pub fn get_hash_given(&self, hash: &'static dyn Hash, extra: &[u8]) -> Vec<u8> {
         let mut ctx = if self.ctx.is_none() {
-            let mut ctx = digest::Context::new(hash);
+            let mut ctx = hash.start();
             ctx.update(&self.buffer);
             ctx
         } else {
-            self.ctx.as_ref().unwrap().clone()
+            self.ctx.as_ref().unwrap().fork()
         };
 
         ctx.update(extra);
-        let hash = ctx.finish();
-        let mut ret = Vec::new();
-        ret.extend_from_slice(hash.as_ref());
-        ret
+        ctx.finish()
     }
 
     /// Take the current hash value, and encapsulate it in a
@@ -129,16 +126,13 @@ impl HandshakeHash {
         let old_hash = self.ctx.take().unwrap().finish();
         let old_handshake_hash_msg = HandshakeMessagePayload::build_handshake_hash(old_hash.as_ref());
 
-        self.ctx = Some(digest::Context::new(self.alg.unwrap()));
+        self.ctx = Some(self.alg.unwrap().start());
         self.update_raw(&old_handshake_hash_msg.get_encoding());
     }
 
     /// Get the current hash value.
     pub fn get_current_hash(&self) -> Vec<u8> {
-        let hash = self.ctx.as_ref().unwrap().clone().finish();
-        let mut ret = Vec::new();
-        ret.extend_from_slice(hash.as_ref());
-        ret
+        self.ctx.as_ref().unwrap().fork().finish()
     }
 
     /// Takes this object's buffer containing all handshake messages
@@ -153,14 +147,15 @@ impl HandshakeHash {
 #[cfg(test)]
 mod test {
     use super::HandshakeHash;
-    use ring::digest;
+    use crate::crypto::{CryptoProvider, HashAlgorithm};
+    use crate::crypto::ring::RING;
 
     #[test]
     fn hashes_correctly() {
         let mut hh = HandshakeHash::new();
         hh.update_raw(b"hello");
         assert_eq!(hh.buffer.len(), 5);
-        hh.start_hash(&digest::SHA256);
+        hh.start_hash(RING.hash(HashAlgorithm::SHA256).unwrap());
         assert_eq!(hh.buffer.len(), 0);
         hh.update_raw(b"world");
         let h = hh.get_current_hash();
@@ -176,7 +171,7 @@ mod test {
         hh.set_client_auth_enabled();
         hh.update_raw(b"hello");
         assert_eq!(hh.buffer.len(), 5);
-        hh.start_hash(&digest::SHA256);
+        hh.start_hash(RING.hash(HashAlgorithm::SHA256).unwrap());
         assert_eq!(hh.buffer.len(), 5);
         hh.update_raw(b"world");
         assert_eq!(hh.buffer.len(), 10);
@@ -195,7 +190,7 @@ mod test {
         hh.set_client_auth_enabled();
         hh.update_raw(b"hello");
         assert_eq!(hh.buffer.len(), 5);
-        hh.start_hash(&digest::SHA256);
+        hh.start_hash(RING.hash(HashAlgorithm::SHA256).unwrap());
         assert_eq!(hh.buffer.len(), 5);
         hh.abandon_client_auth();
         assert_eq!(hh.buffer.len(), 0);
@@ -0,0 +1,302 @@
+// Hybrid Public Key Encryption (RFC9180), base mode only.
+//
+// This supports DHKEM(X25519, HKDF-SHA256) with HKDF-SHA256 and
+// any of the AEADs ring offers, which is what ECH needs.  The
+// primitives always come from the ring `CryptoProvider`, not the
+// session's: see the `CryptoProvider` docs.  The sender's ephemeral
+// key is one of ring's; only the receiver's long-lived key needs
+// `x25519`.
+
+use crate::msgs::enums::{HpkeKem, HpkeKdf, HpkeAead};
+use crate::key_schedule::Prk;
+use crate::rand;
+use crate::crypto::{CryptoProvider, Aead, AeadKey, Hmac, HashAlgorithm, NONCE_LEN};
+use crate::crypto::ring::RING;
+use crate::kx::{self, SupportedKxGroup};
+use crate::suites::BulkAlgorithm;
+use crate::x25519;
+
+const HPKE_VERSION: &[u8] = b"HPKE-v1";
+const MODE_BASE: u8 = 0x00;
+
+/// A choice of KEM, KDF and AEAD.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HpkeSuite {
+    pub kem: HpkeKem,
+    pub kdf: HpkeKdf,
+    pub aead: HpkeAead,
+}
+
+impl HpkeSuite {
+    /// Do we support this suite?
+    pub fn is_supported(&self) -> bool {
+        self.kem == HpkeKem::DHKEM_X25519_HKDF_SHA256 &&
+            self.kdf == HpkeKdf::HKDF_SHA256 &&
+            self.aead_algorithm().is_some()
+    }
+
+    fn aead_algorithm(&self) -> Option<&'static dyn Aead> {
+        match self.aead {
+            HpkeAead::AES_128_GCM => RING.aead(BulkAlgorithm::AES_128_GCM),
+            HpkeAead::AES_256_GCM => RING.aead(BulkAlgorithm::AES_256_GCM),
+            HpkeAead::CHACHA20_POLY_1305 => RING.aead(BulkAlgorithm::CHACHA20_POLY1305),
+            _ => None,
+        }
+    }
+
+    fn suite_id(&self) -> Vec<u8> {
+        let mut id = b"HPKE".to_vec();
+        id.extend_from_slice(&self.kem.get_u16().to_be_bytes());
+        id.extend_from_slice(&self.kdf.get_u16().to_be_bytes());
+        id.extend_from_slice(&self.aead.get_u16().to_be_bytes());
+        id
+    }
+}
+
+fn kem_suite_id(kem: HpkeKem) -> Vec<u8> {
+    let mut id = b"KEM".to_vec();
+    id.extend_from_slice(&kem.get_u16().to_be_bytes());
+    id
+}
+
+fn hkdf_sha256() -> &'static dyn Hmac {
+    RING.hmac(HashAlgorithm::SHA256).unwrap()
+}
+
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> Vec<u8> {
+    // HKDF-Extract is HMAC keyed with the salt.
+    hkdf_sha256()
+        .with_key(salt)
+        .sign(&[HPKE_VERSION, suite_id, label, ikm])
+}
+
+fn labeled_expand(suite_id: &[u8], prk: &[u8], label: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+    let prk = Prk::new_less_safe(hkdf_sha256(), prk);
+    let output_len = (len as u16).to_be_bytes();
+    let info = &[&output_len[..], HPKE_VERSION, suite_id, label, info];
+    let mut okm = vec![0u8; len];
+    prk.expand(info, &mut okm)
+        .unwrap();
+    okm
+}
+
+fn extract_and_expand(kem: HpkeKem, dh: &[u8], enc: &[u8], pk_r: &[u8]) -> Vec<u8> {
+    let suite_id = kem_suite_id(kem);
+    let eae_prk = labeled_extract(&suite_id, &[], b"eae_prk", dh);
+
+    let mut kem_context = enc.to_vec();
+    kem_context.extend_from_slice(pk_r);
+    labeled_expand(&suite_id, &eae_prk, b"shared_secret", &kem_context, 32)
+}
+
+/// An HPKE encryption context, as made by `setup_base_sender`
+/// or `setup_base_receiver`.
+pub struct HpkeContext {
+    key: Box<dyn AeadKey>,
+    base_nonce: [u8; NONCE_LEN],
+    seq: u64,
+}
+
+impl HpkeContext {
+    fn new(suite: HpkeSuite, shared_secret: &[u8], info: &[u8]) -> HpkeContext {
+        let suite_id = suite.suite_id();
+        let alg = suite.aead_algorithm().unwrap();
+
+        let psk_id_hash = labeled_extract(&suite_id, &[], b"psk_id_hash", &[]);
+        let info_hash = labeled_extract(&suite_id, &[], b"info_hash", info);
+        let mut context = vec![ MODE_BASE ];
+        context.extend_from_slice(&psk_id_hash);
+        context.extend_from_slice(&info_hash);
+
+        let secret = labeled_extract(&suite_id, shared_secret, b"secret", &[]);
+        let key = labeled_expand(&suite_id, &secret, b"key", &context, alg.key_len());
+        let nonce = labeled_expand(&suite_id, &secret, b"base_nonce", &context, NONCE_LEN);
+
+        let mut base_nonce = [0u8; NONCE_LEN];
+        base_nonce.copy_from_slice(&nonce);
+
+        HpkeContext {
+            key: alg.new_key(&key),
+            base_nonce,
+            seq: 0,
+        }
+    }
+
+    fn current_nonce(&self) -> [u8; NONCE_LEN] {
+        let mut nonce = self.base_nonce;
+        for (n, s) in nonce[NONCE_LEN - 8..].iter_mut().zip(self.seq.to_be_bytes().iter()) {
+            *n ^= *s;
+        }
+        nonce
+    }
+
+    /// Encrypt `plaintext`, authenticating `aad`.
+    pub fn seal(&mut self, aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let nonce = self.current_nonce();
+        let mut buf = plaintext.to_vec();
+        self.key.seal_in_place_append_tag(nonce, aad, &mut buf)
+            .unwrap();
+        self.seq += 1;
+        buf
+    }
+
+    /// Decrypt `ciphertext`, authenticating `aad`.  Returns None if
+    /// that fails, in which case the sequence number doesn't move on
+    /// (RFC 9180 section 5.2), so the next genuine message still opens.
+    pub fn open(&mut self, aad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let nonce = self.current_nonce();
+        let mut buf = ciphertext.to_vec();
+        let len = self.key.open_in_place(nonce, aad, &mut buf)
+            .ok()?;
+        self.seq += 1;
+        buf.truncate(len);
+        Some(buf)
+    }
+}
+
+/// Make a new X25519 private key, for a receiver to keep.
+pub fn generate_private_key() -> [u8; x25519::X25519_LEN] {
+    let mut sk = [0u8; x25519::X25519_LEN];
+    rand::fill_random(&RING, &mut sk);
+    sk
+}
+
+/// Set up a context for encrypting to `pk_r`.  Returns the
+/// encapsulated key to send to the receiver, and the context.
+pub fn setup_base_sender(suite: HpkeSuite,
+                         pk_r: &[u8],
+                         info: &[u8]) -> Option<(Vec<u8>, HpkeContext)> {
+    if !suite.is_supported() {
+        return None;
+    }
+
+    let sk_e = kx::X25519.start()?;
+    let enc = sk_e.pub_key().to_vec();
+    let dh = sk_e.complete(pk_r)?;
+    Some(sender_context(suite, pk_r, info, &dh, enc))
+}
+
+/// The sender's half of `setup_base_sender`, once the ephemeral key
+/// `enc` has agreed `dh` with `pk_r`.
+fn sender_context(suite: HpkeSuite,
+                  pk_r: &[u8],
+                  info: &[u8],
+                  dh: &[u8],
+                  enc: Vec<u8>) -> (Vec<u8>, HpkeContext) {
+    let shared_secret = extract_and_expand(suite.kem, dh, &enc, pk_r);
+    (enc, HpkeContext::new(suite, &shared_secret, info))
+}
+
+/// Set up a context for decrypting with our private key `sk_r`,
+/// given the sender's encapsulated key `enc`.
+pub fn setup_base_receiver(suite: HpkeSuite,
+                           enc: &[u8],
+                           sk_r: &[u8; x25519::X25519_LEN],
+                           info: &[u8]) -> Option<HpkeContext> {
+    if !suite.is_supported() {
+        return None;
+    }
+
+    let dh = x25519::agree(sk_r, enc)?;
+    let pk_r = x25519::x25519_base(sk_r);
+    let shared_secret = extract_and_expand(suite.kem, &dh, enc, &pk_r);
+    Some(HpkeContext::new(suite, &shared_secret, info))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn suite(aead: HpkeAead) -> HpkeSuite {
+        HpkeSuite {
+            kem: HpkeKem::DHKEM_X25519_HKDF_SHA256,
+            kdf: HpkeKdf::HKDF_SHA256,
+            aead,
+        }
+    }
+
+    // RFC9180 appendix A.1.1.
+    #[test]
+    fn rfc9180_x25519_sha256_aes128gcm() {
+        let info = hex!("4f6465206f6e2061204772656369616e2055726e");
+        let sk_e = hex!("52c4a758a802cd8b936eceea314432798d5baf2d7e9235dc084ab1b9cfa2f736");
+        let pk_r = hex!("3948cfe0ad1ddb695d780e59077195da6c56506b027329794ab02bca80815c4d");
+        let sk_r = hex!("4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8");
+        let expected_enc = hex!("37fda3567bdbd628e88668c3c8d7e97d1d1253b6d4ea6d44c150f741f1bf4431");
+        let aad = hex!("436f756e742d30");
+        let pt = hex!("4265617574792069732074727574682c20747275746820626561757479");
+        let expected_ct = hex!("f938558b5d72f1a23810b4be2ab4f84331acc02fc97babc53a52ae8218a355a9
+                                6d8770ac83d07bea87e13c512a");
+
+        assert_eq!(&x25519::x25519_base(&sk_r), &pk_r);
+
+        // ring can't import the fixed ephemeral key, so do its part
+        // of `setup_base_sender` by hand.
+        let dh = x25519::agree(&sk_e, &pk_r).unwrap();
+        let (enc, mut sender) = sender_context(suite(HpkeAead::AES_128_GCM), &pk_r, &info,
+                                               &dh, x25519::x25519_base(&sk_e).to_vec());
+        assert_eq!(&enc[..], &expected_enc[..]);
+        assert_eq!(sender.seal(&aad, &pt), expected_ct.to_vec());
+
+        let mut receiver = setup_base_receiver(suite(HpkeAead::AES_128_GCM),
+                                               &enc, &sk_r, &info).unwrap();
+        assert_eq!(receiver.open(&aad, &expected_ct).unwrap(), pt.to_vec());
+    }
+
+    #[test]
+    fn sequence_numbers_advance() {
+        for aead in &[ HpkeAead::AES_128_GCM, HpkeAead::AES_256_GCM, HpkeAead::CHACHA20_POLY_1305 ] {
+            let sk_r = generate_private_key();
+            let pk_r = x25519::x25519_base(&sk_r);
+
+            let (enc, mut sender) = setup_base_sender(suite(*aead), &pk_r, b"info").unwrap();
+            let mut receiver = setup_base_receiver(suite(*aead), &enc, &sk_r, b"info").unwrap();
+
+            let first = sender.seal(b"aad", b"hello");
+            let second = sender.seal(b"aad", b"hello");
+            assert_ne!(first, second);
+
+            assert_eq!(receiver.open(b"aad", &first).unwrap(), b"hello".to_vec());
+            assert_eq!(receiver.open(b"aad", &second).unwrap(), b"hello".to_vec());
+            assert!(receiver.open(b"aad", &first).is_none());
+
+            // Failures (replays, forgeries) don't use up a sequence
+            // number.
+            let mut forged = sender.seal(b"aad", b"hello");
+            forged[0] ^= 1;
+            assert!(receiver.open(b"aad", &forged).is_none());
+            assert!(receiver.open(b"other aad", &second).is_none());
+
+            let third = sender.seal(b"aad", b"world");
+            assert!(receiver.open(b"aad", &third).is_none());
+            forged[0] ^= 1;
+            assert_eq!(receiver.open(b"aad", &forged).unwrap(), b"hello".to_vec());
+            assert_eq!(receiver.open(b"aad", &third).unwrap(), b"world".to_vec());
+        }
+    }
+
+    #[test]
+    fn rejects_bad_inputs() {
+        let sk_r = generate_private_key();
+        let pk_r = x25519::x25519_base(&sk_r);
+
+        let (enc, mut sender) = setup_base_sender(suite(HpkeAead::AES_128_GCM), &pk_r, b"info").unwrap();
+        let ct = sender.seal(b"aad", b"hello");
+
+        let mut wrong_info = setup_base_receiver(suite(HpkeAead::AES_128_GCM), &enc, &sk_r, b"other").unwrap();
+        assert!(wrong_info.open(b"aad", &ct).is_none());
+
+        let mut wrong_aad = setup_base_receiver(suite(HpkeAead::AES_128_GCM), &enc, &sk_r, b"info").unwrap();
+        assert!(wrong_aad.open(b"other", &ct).is_none());
+
+        assert!(setup_base_receiver(suite(HpkeAead::EXPORT_ONLY), &enc, &sk_r, b"info").is_none());
+        assert!(setup_base_sender(suite(HpkeAead::AES_128_GCM), &[0u8; 32], b"info").is_none());
+
+        let p256 = HpkeSuite {
+            kem: HpkeKem::DHKEM_P256_HKDF_SHA256,
+            kdf: HpkeKdf::HKDF_SHA256,
+            aead: HpkeAead::AES_128_GCM,
+        };
+        assert!(setup_base_sender(p256, &pk_r, b"info").is_none());
+    }
+}
@@ -0,0 +1,220 @@
+// Who a peer's certificate says they are, and policies about which
+// identities we accept from clients.
+//
+// webpki establishes that a certificate chains to a trust anchor;
+// these are for the questions that come after that.
+
+use std::net::IpAddr;
+use std::str;
+
+use crate::error::TLSError;
+use crate::key::Certificate;
+use crate::pinning::{hash_spki, SPKIHash};
+use crate::x509;
+
+/// The id-kp-clientAuth extended key usage.
+const OID_CLIENT_AUTH: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02];
+
+/// A subjectAltName of a kind we understand.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubjectAltName {
+    /// A dNSName.
+    DNSName(String),
+
+    /// An rfc822Name: an email address.
+    Email(String),
+
+    /// A uniformResourceIdentifier.
+    URI(String),
+
+    /// An iPAddress.
+    IPAddress(IpAddr),
+}
+
+impl SubjectAltName {
+    fn from_general_name(tag: u8, name: &[u8]) -> Option<SubjectAltName> {
+        let text = || str::from_utf8(name).ok().map(str::to_string);
+
+        match tag {
+            x509::TAG_DNS_NAME => text().map(SubjectAltName::DNSName),
+            x509::TAG_RFC822_NAME => text().map(SubjectAltName::Email),
+            x509::TAG_URI => text().map(SubjectAltName::URI),
+            x509::TAG_IP_ADDRESS if name.len() == 4 => {
+                let mut octets = [0u8; 4];
+                octets.copy_from_slice(name);
+                Some(SubjectAltName::IPAddress(IpAddr::from(octets)))
+            }
+            x509::TAG_IP_ADDRESS if name.len() == 16 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(name);
+                Some(SubjectAltName::IPAddress(IpAddr::from(octets)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The identity in a peer's end-entity certificate.
+///
+/// This is only as trustworthy as the verifier which accepted the
+/// certificate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerIdentity {
+    /// The DER-encoded subject Name.
+    pub subject: Vec<u8>,
+
+    /// The subject's commonName, if it has one which is valid UTF-8.
+    pub common_name: Option<String>,
+
+    /// The subjectAltNames we understand.  Others are left out.
+    pub subject_alt_names: Vec<SubjectAltName>,
+
+    /// The SHA-256 hash of the certificate's SubjectPublicKeyInfo,
+    /// as used for pinning.
+    pub spki_hash: SPKIHash,
+}
+
+impl PeerIdentity {
+    /// Extract the identity from the end-entity certificate `cert`.
+    pub fn from_certificate(cert: &Certificate) -> Result<PeerIdentity, TLSError> {
+        let info = x509::CertInfo::parse(&cert.0)
+            .ok_or(TLSError::WebPKIError(webpki::Error::BadDER))?;
+        Ok(PeerIdentity::from_cert_info(&info))
+    }
+
+    fn from_cert_info(info: &x509::CertInfo) -> PeerIdentity {
+        PeerIdentity {
+            subject: info.subject.to_vec(),
+            common_name: info.common_name()
+                .and_then(|cn| str::from_utf8(cn).ok())
+                .map(str::to_string),
+            subject_alt_names: info.subject_alt_names()
+                .into_iter()
+                .filter_map(|(tag, name)| SubjectAltName::from_general_name(tag, name))
+                .collect(),
+            spki_hash: hash_spki(info.spki),
+        }
+    }
+
+    /// Is this identity called `name`?  That's if it's the commonName
+    /// or a DNS name, ignoring ASCII case, or exactly an email address
+    /// or URI.
+    pub fn has_name(&self, name: &str) -> bool {
+        if self.common_name.as_ref()
+            .map_or(false, |cn| cn.eq_ignore_ascii_case(name)) {
+            return true;
+        }
+
+        self.subject_alt_names
+            .iter()
+            .any(|san| match *san {
+                SubjectAltName::DNSName(ref dns) => dns.eq_ignore_ascii_case(name),
+                SubjectAltName::Email(ref email) => email == name,
+                SubjectAltName::URI(ref uri) => uri == name,
+                SubjectAltName::IPAddress(_) => false,
+            })
+    }
+}
+
+/// Which client certificates we accept, beyond their chaining to a
+/// trust anchor.
+///
+/// The default accepts any client.
+#[derive(Debug, Clone, Default)]
+pub struct ClientIdentityPolicy {
+    require_client_auth_eku: bool,
+    allowed_names: Vec<String>,
+}
+
+impl ClientIdentityPolicy {
+    /// Make a new `ClientIdentityPolicy` which accepts any client.
+    pub fn new() -> ClientIdentityPolicy {
+        ClientIdentityPolicy::default()
+    }
+
+    /// Require client certificates to carry the clientAuth extended
+    /// key usage.  Otherwise certificates with no extended key usage
+    /// extension at all are accepted too.
+    pub fn require_client_auth_eku(&mut self) -> &mut ClientIdentityPolicy {
+        self.require_client_auth_eku = true;
+        self
+    }
+
+    /// Accept clients called `name`: see `PeerIdentity::has_name`.
+    /// Once any name is allowed, clients with none of the allowed
+    /// names are refused.
+    pub fn allow_name(&mut self, name: &str) -> &mut ClientIdentityPolicy {
+        self.allowed_names.push(name.to_string());
+        self
+    }
+
+    /// Check the end-entity certificate `cert` against this policy.
+    pub(crate) fn check(&self, cert: &Certificate) -> Result<(), TLSError> {
+        let info = x509::CertInfo::parse(&cert.0)
+            .ok_or(TLSError::WebPKIError(webpki::Error::BadDER))?;
+
+        if self.require_client_auth_eku && !info.has_eku(OID_CLIENT_AUTH) {
+            return Err(TLSError::WebPKIError(webpki::Error::RequiredEKUNotFound));
+        }
+
+        if self.allowed_names.is_empty() {
+            return Ok(());
+        }
+
+        let identity = PeerIdentity::from_cert_info(&info);
+        if self.allowed_names.iter().any(|name| identity.has_name(name)) {
+            Ok(())
+        } else {
+            Err(TLSError::ClientIdentityNotAllowed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_peer_identity() {
+        let cert = Certificate(include_bytes!("testdata/cert-ip-san.der").to_vec());
+        let identity = PeerIdentity::from_certificate(&cert).unwrap();
+        assert_eq!(identity.common_name, Some("198.51.100.1".to_string()));
+        assert_eq!(identity.subject_alt_names,
+                   vec![ SubjectAltName::DNSName("example.com".to_string()),
+                         SubjectAltName::IPAddress("198.51.100.1".parse().unwrap()),
+                         SubjectAltName::IPAddress("2001:db8::1".parse().unwrap()),
+                         SubjectAltName::Email("a@example.com".to_string()) ]);
+        assert_eq!(identity.spki_hash, crate::pinning::PinSet::spki_hash(&cert).unwrap());
+
+        assert!(identity.has_name("EXAMPLE.com"));
+        assert!(identity.has_name("a@example.com"));
+        assert!(!identity.has_name("A@example.com"));
+        assert!(!identity.has_name("2001:db8::1"));
+
+        assert!(PeerIdentity::from_certificate(&Certificate(vec![0x30, 0x00])).is_err());
+    }
+
+    #[test]
+    fn test_policy() {
+        let cert = Certificate(include_bytes!("testdata/cert-github.0.der").to_vec());
+        assert_eq!(ClientIdentityPolicy::new().check(&cert), Ok(()));
+        assert_eq!(ClientIdentityPolicy::new()
+                       .require_client_auth_eku()
+                       .allow_name("example.com")
+                       .allow_name("www.github.com")
+                       .check(&cert),
+                   Ok(()));
+        assert_eq!(ClientIdentityPolicy::new()
+                       .allow_name("example.com")
+                       .check(&cert),
+                   Err(TLSError::ClientIdentityNotAllowed));
+
+        // This one has no extended key usage extension.
+        let cert = Certificate(include_bytes!("testdata/ocsp-end.der").to_vec());
+        assert_eq!(ClientIdentityPolicy::new().check(&cert), Ok(()));
+        assert_eq!(ClientIdentityPolicy::new()
+                       .require_client_auth_eku()
+                       .check(&cert),
+                   Err(TLSError::WebPKIError(webpki::Error::RequiredEKUNotFound)));
+    }
+}
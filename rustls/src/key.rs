@@ -7,6 +7,9 @@ use std::fmt;
 ///
 /// `rustls::pemfile::pkcs8_private_keys` or `rustls::pemfile::rsa_private_keys`
 /// could be used to extract private keys from a PEM file in these formats.
+/// `rustls::pemfile::ec_private_keys` and
+/// `rustls::pemfile::encrypted_pkcs8_private_keys` convert SEC1 and
+/// encrypted keys into PKCS#8.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct PrivateKey(pub Vec<u8>);
 
@@ -1,9 +1,9 @@
 /// Key schedule maintenance for TLS1.3
 
-use ring::{aead, hkdf::{self, KeyType as _}, hmac, digest};
+use crate::crypto::{CryptoProvider, Aead, AeadKey, Hash, Hmac, NONCE_LEN};
 use crate::error::TLSError;
-use crate::cipher::{Iv, IvLen};
-use crate::msgs::base::PayloadU8;
+use crate::cipher::Iv;
+use crate::suites::SupportedCipherSuite;
 use crate::KeyLog;
 
 /// The kinds of secret we can extract from `KeySchedule`.
@@ -49,88 +49,134 @@ impl SecretKind {
     }
 }
 
+/// A pseudorandom key, from which HKDF-Expand derives further
+/// keying material.
+#[derive(Clone)]
+pub struct Prk {
+    hmac: &'static dyn Hmac,
+    secret: Vec<u8>,
+}
+
+impl Prk {
+    /// HKDF-Extract: make a `Prk` from `salt` and `secret`.
+    pub(crate) fn extract(hmac: &'static dyn Hmac, salt: &[u8], secret: &[u8]) -> Prk {
+        Prk {
+            hmac,
+            secret: hmac.with_key(salt).sign(&[secret]),
+        }
+    }
+
+    /// Use `secret` directly as a `Prk`.  It must already be
+    /// uniformly random, such as a TLS1.3 traffic secret.
+    pub(crate) fn new_less_safe(hmac: &'static dyn Hmac, secret: &[u8]) -> Prk {
+        Prk {
+            hmac,
+            secret: secret.to_vec(),
+        }
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// HKDF-Expand: fill `out` with keying material derived
+    /// from this key and the concatenation of `info`.  This
+    /// fails if `out` is longer than 255 hash outputs.
+    pub(crate) fn expand(&self, info: &[&[u8]], out: &mut [u8]) -> Result<(), ()> {
+        let hash_len = self.hmac.output_len();
+        if out.len() > 255 * hash_len {
+            return Err(());
+        }
+
+        let key = self.hmac.with_key(&self.secret);
+        let mut t = Vec::new();
+
+        for (i, chunk) in out.chunks_mut(hash_len).enumerate() {
+            let counter = [i as u8 + 1];
+            let mut input = vec![&t[..]];
+            input.extend_from_slice(info);
+            input.push(&counter[..]);
+
+            t = key.sign(&input);
+            chunk.copy_from_slice(&t[..chunk.len()]);
+        }
+
+        Ok(())
+    }
+}
+
 /// This is the TLS1.3 key schedule.  It stores the current secret,
 /// the type of hash, plus the two current traffic keys which form their
 /// own lineage of keys over successive key updates.
 pub struct KeySchedule {
-    current: hkdf::Prk,
-    algorithm: ring::hkdf::Algorithm,
-    pub current_client_traffic_secret: Option<hkdf::Prk>,
-    pub current_server_traffic_secret: Option<hkdf::Prk>,
-    pub current_exporter_secret: Option<hkdf::Prk>,
+    current: Prk,
+    hash: &'static dyn Hash,
+    pub current_client_traffic_secret: Option<Prk>,
+    pub current_server_traffic_secret: Option<Prk>,
+    pub current_exporter_secret: Option<Prk>,
 }
 
 impl KeySchedule {
-    pub fn new(algorithm: hkdf::Algorithm, secret: &[u8]) -> KeySchedule {
-        let zeroes = [0u8; digest::MAX_OUTPUT_LEN];
-        let zeroes = &zeroes[..algorithm.len()];
-        let salt = hkdf::Salt::new(algorithm, &zeroes);
-        KeySchedule {
-            current: salt.extract(secret),
-            algorithm,
+    pub fn new(provider: &dyn CryptoProvider,
+               suite: &SupportedCipherSuite,
+               secret: &[u8]) -> Result<KeySchedule, TLSError> {
+        let hash = provider.suite_hash(suite)?;
+        let zeroes = vec![0u8; hash.output_len()];
+        Ok(KeySchedule {
+            current: Prk::extract(provider.suite_hmac(suite)?, &zeroes, secret),
+            hash,
             current_server_traffic_secret: None,
             current_client_traffic_secret: None,
             current_exporter_secret: None,
-        }
+        })
     }
 
-    #[inline]
-    pub fn algorithm(&self) -> hkdf::Algorithm { self.algorithm }
-
-    pub fn new_with_empty_secret(algorithm: hkdf::Algorithm) -> KeySchedule {
-        let zeroes = [0u8; digest::MAX_OUTPUT_LEN];
-        Self::new(algorithm, &zeroes[..algorithm.len()])
+    pub fn new_with_empty_secret(provider: &dyn CryptoProvider,
+                                 suite: &SupportedCipherSuite) -> Result<KeySchedule, TLSError> {
+        let zeroes = vec![0u8; provider.suite_hash(suite)?.output_len()];
+        Self::new(provider, suite, &zeroes)
     }
 
     /// Input the empty secret.
     pub fn input_empty(&mut self) {
-        let zeroes = [0u8; digest::MAX_OUTPUT_LEN];
-        self.input_secret(&zeroes[..self.algorithm.len()]);
+        let zeroes = vec![0u8; self.hash.output_len()];
+        self.input_secret(&zeroes);
     }
 
     /// Input the given secret.
     pub fn input_secret(&mut self, secret: &[u8]) {
-        let salt: hkdf::Salt = self.derive_for_empty_hash(SecretKind::DerivedSecret);
-        self.current = salt.extract(secret);
+        let salt = self.derive_for_empty_hash(SecretKind::DerivedSecret);
+        self.current = Prk::extract(self.current.hmac, salt.as_bytes(), secret);
     }
 
     /// Derive a secret of given `kind`, using current handshake hash `hs_hash`.
-    pub fn derive<T, L>(&self, key_type: L, kind: SecretKind, hs_hash: &[u8]) -> T
-        where
-            T: for <'a> From<hkdf::Okm<'a, L>>,
-            L: hkdf::KeyType,
-    {
-        hkdf_expand(&self.current, key_type, kind.to_bytes(), hs_hash)
+    pub fn derive(&self, kind: SecretKind, hs_hash: &[u8]) -> Prk {
+        hkdf_expand_prk(&self.current, kind.to_bytes(), hs_hash)
     }
 
     pub fn derive_logged_secret(&self, kind: SecretKind, hs_hash: &[u8],
                                 key_log: &dyn KeyLog, client_random: &[u8; 32])
-        -> hkdf::Prk
+        -> Prk
     {
         let log_label = kind.log_label().expect("not a loggable secret");
+        let secret = self.derive(kind, hs_hash);
         if key_log.will_log(log_label) {
-            let secret = self.derive::<PayloadU8, _>(PayloadU8Len(self.algorithm.len()), kind, hs_hash)
-                .into_inner();
-            key_log.log(log_label, client_random, &secret);
+            key_log.log(log_label, client_random, secret.as_bytes());
         }
-        self.derive(self.algorithm, kind, hs_hash)
+        secret
     }
 
     /// Derive a secret of given `kind` using the hash of the empty string
     /// for the handshake hash.  Useful only for
     /// `SecretKind::ResumptionPSKBinderKey` and
     /// `SecretKind::DerivedSecret`.
-    pub fn derive_for_empty_hash<T>(&self, kind: SecretKind) -> T
-        where
-            T: for <'a> From<hkdf::Okm<'a, hkdf::Algorithm>>
-    {
-        let digest_alg = self.algorithm.hmac_algorithm().digest_algorithm();
-        let empty_hash = digest::digest(digest_alg, &[]);
-        self.derive(self.algorithm, kind, empty_hash.as_ref())
+    pub fn derive_for_empty_hash(&self, kind: SecretKind) -> Prk {
+        let empty_hash = self.hash.hash(&[]);
+        self.derive(kind, &empty_hash)
     }
 
     /// Return the current traffic secret, of given `kind`.
-    fn current_traffic_secret(&self, kind: SecretKind) -> &hkdf::Prk {
+    fn current_traffic_secret(&self, kind: SecretKind) -> &Prk {
         match kind {
             SecretKind::ServerHandshakeTrafficSecret |
             SecretKind::ServerApplicationTrafficSecret =>
@@ -152,26 +198,24 @@ impl KeySchedule {
 
     /// Sign the finished message consisting of `hs_hash` using the key material
     /// `base_key`.
-    pub fn sign_verify_data(&self, base_key: &hkdf::Prk, hs_hash: &[u8]) -> Vec<u8> {
-        let hmac_alg = self.algorithm.hmac_algorithm();
-        let hmac_key = hkdf_expand(base_key, hmac_alg, b"finished", &[]);
-        hmac::sign(&hmac_key, hs_hash)
-            .as_ref()
-            .to_vec()
+    pub fn sign_verify_data(&self, base_key: &Prk, hs_hash: &[u8]) -> Vec<u8> {
+        let hmac_key = hkdf_expand(base_key, self.hash.output_len(), b"finished", &[]);
+        base_key.hmac
+            .with_key(&hmac_key)
+            .sign(&[hs_hash])
     }
 
     /// Derive the next application traffic secret of given `kind`, returning
     /// it.
-    pub fn derive_next(&self, kind: SecretKind) -> hkdf::Prk {
+    pub fn derive_next(&self, kind: SecretKind) -> Prk {
         let base_key = self.current_traffic_secret(kind);
-        hkdf_expand(&base_key, self.algorithm, b"traffic upd", &[])
+        hkdf_expand_prk(&base_key, b"traffic upd", &[])
     }
 
     /// Derive the PSK to use given a resumption_master_secret and
     /// ticket_nonce.
-    pub fn derive_ticket_psk(&self, rms: &hkdf::Prk, nonce: &[u8]) -> Vec<u8> {
-        let payload: PayloadU8 = hkdf_expand(rms, PayloadU8Len(self.algorithm.len()), b"resumption", nonce);
-        payload.into_inner()
+    pub fn derive_ticket_psk(&self, rms: &Prk, nonce: &[u8]) -> Vec<u8> {
+        hkdf_expand(rms, self.hash.output_len(), b"resumption", nonce)
     }
 
     pub fn export_keying_material(&self, out: &mut [u8],
@@ -179,76 +223,91 @@ impl KeySchedule {
                                   context: Option<&[u8]>) -> Result<(), TLSError> {
         let current_exporter_secret =
             self.current_exporter_secret.as_ref().ok_or(TLSError::HandshakeNotComplete)?;
-        let digest_alg = self.algorithm.hmac_algorithm().digest_algorithm();
 
-        let h_empty = digest::digest(digest_alg, &[]);
-        let secret: hkdf::Prk =
-            hkdf_expand(current_exporter_secret, self.algorithm, label, h_empty.as_ref());
+        let h_empty = self.hash.hash(&[]);
+        let secret = hkdf_expand_prk(current_exporter_secret, label, &h_empty);
 
-        let h_context = digest::digest(digest_alg, context.unwrap_or(&[]));
+        let h_context = self.hash.hash(context.unwrap_or(&[]));
 
         // TODO: Test what happens when this fails
-        hkdf_expand_info(&secret, PayloadU8Len(out.len()), b"exporter", h_context.as_ref(),
-                         |okm| okm.fill(out))
+        hkdf_expand_info(&secret, out, b"exporter", &h_context)
             .map_err(|_| TLSError::General("exporting too much".to_string()))
     }
 }
 
-pub(crate) fn hkdf_expand<T, L>(secret: &hkdf::Prk, key_type: L, label: &[u8], context: &[u8]) -> T
-    where
-        T: for <'a> From<hkdf::Okm<'a, L>>,
-        L: hkdf::KeyType,
-{
-    hkdf_expand_info(secret, key_type, label, context, |okm| okm.into())
+/// HKDF-Expand-Label from RFC8446, producing `len` bytes.
+pub(crate) fn hkdf_expand(secret: &Prk, len: usize, label: &[u8], context: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; len];
+    hkdf_expand_info(secret, &mut out, label, context).unwrap();
+    out
+}
+
+/// HKDF-Expand-Label, producing a new secret of the hash's length.
+pub(crate) fn hkdf_expand_prk(secret: &Prk, label: &[u8], context: &[u8]) -> Prk {
+    let okm = hkdf_expand(secret, secret.hmac.output_len(), label, context);
+    Prk::new_less_safe(secret.hmac, &okm)
 }
 
-fn hkdf_expand_info<F, T, L>(secret: &hkdf::Prk, key_type: L, label: &[u8], context: &[u8], f: F)
-        -> T
-    where
-        F: for<'b> FnOnce(hkdf::Okm<'b, L>) -> T,
-        L: hkdf::KeyType
-{
+fn hkdf_expand_info(secret: &Prk, out: &mut [u8], label: &[u8], context: &[u8])
+                    -> Result<(), ()> {
     const LABEL_PREFIX: &[u8] = b"tls13 ";
 
-    let output_len = u16::to_be_bytes(key_type.len() as u16);
+    let output_len = u16::to_be_bytes(out.len() as u16);
     let label_len = u8::to_be_bytes((LABEL_PREFIX.len() + label.len()) as u8);
     let context_len = u8::to_be_bytes(context.len() as u8);
 
     let info = &[&output_len[..], &label_len[..], LABEL_PREFIX, label, &context_len[..], context];
-    let okm = secret.expand(info, key_type).unwrap();
-
-    f(okm)
+    secret.expand(info, out)
 }
 
-pub(crate) struct PayloadU8Len(pub(crate) usize);
-impl hkdf::KeyType for PayloadU8Len {
-    fn len(&self) -> usize { self.0 }
+pub fn derive_traffic_key(secret: &Prk, aead: &dyn Aead) -> Box<dyn AeadKey> {
+    aead.new_key(&hkdf_expand(secret, aead.key_len(), b"key", &[]))
 }
 
-impl From<hkdf::Okm<'_, PayloadU8Len>> for PayloadU8 {
-    fn from(okm: hkdf::Okm<PayloadU8Len>) -> Self {
-        let mut r = vec![0u8;okm.len().0];
-        okm.fill(&mut r[..]).unwrap();
-        PayloadU8::new(r)
-    }
-}
-
-pub fn derive_traffic_key(secret: &hkdf::Prk, aead_algorithm: &'static aead::Algorithm)
-    -> aead::UnboundKey
-{
-    hkdf_expand(secret, aead_algorithm, b"key", &[])
-}
-
-pub(crate) fn derive_traffic_iv(secret: &hkdf::Prk) -> Iv {
-    hkdf_expand(secret, IvLen, b"iv", &[])
+pub(crate) fn derive_traffic_iv(secret: &Prk) -> Iv {
+    let mut iv = [0u8; NONCE_LEN];
+    hkdf_expand_info(secret, &mut iv, b"iv", &[]).unwrap();
+    Iv::new(iv)
 }
 
 #[cfg(test)]
 mod test {
-    use super::{KeySchedule, SecretKind, derive_traffic_key, derive_traffic_iv};
-    use ring::{aead, hkdf};
+    use super::{KeySchedule, Prk, SecretKind, derive_traffic_key, derive_traffic_iv};
+    use crate::crypto::{CryptoProvider, AeadKey, HashAlgorithm, NONCE_LEN};
+    use crate::crypto::ring::RING;
+    use crate::suites::{BulkAlgorithm, TLS13_AES_128_GCM_SHA256};
     use crate::KeyLog;
 
+    #[test]
+    fn test_hkdf_rfc5869() {
+        let hmac = RING.hmac(HashAlgorithm::SHA256).unwrap();
+
+        // Test case 1
+        let prk = Prk::extract(hmac, &hex!("000102030405060708090a0b0c"), &[0x0b; 22]);
+        assert_eq!(prk.as_bytes(),
+                   &hex!("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5")[..]);
+        let mut okm = [0u8; 42];
+        prk.expand(&[&hex!("f0f1f2f3f4"), &hex!("f5f6f7f8f9")], &mut okm).unwrap();
+        assert_eq!(&okm[..],
+                   &hex!("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf
+                          34007208d5b887185865")[..]);
+
+        // Test case 2
+        let ikm = (0x00..0x50).collect::<Vec<u8>>();
+        let salt = (0x60..0xb0).collect::<Vec<u8>>();
+        let info = (0xb0..=0xff).collect::<Vec<u8>>();
+        let prk = Prk::extract(hmac, &salt, &ikm);
+        let mut okm = [0u8; 82];
+        prk.expand(&[&info], &mut okm).unwrap();
+        assert_eq!(&okm[..],
+                   &hex!("b11e398dc80327a1c8e7f78c596a49344f012eda2d4efad8a050cc4c19afa97c
+                          59045a99cac7827271cb41c65e590e09da3275600c2f09b8367793a9aca3db71
+                          cc30c58179ec3e87c14c01d5c1f3434f1d87")[..]);
+
+        let mut too_long = vec![0u8; 255 * 32 + 1];
+        assert!(prk.expand(&[], &mut too_long).is_err());
+    }
+
     #[test]
     fn test_vectors() {
         /* These test vectors generated with OpenSSL. */
@@ -330,8 +389,7 @@ mod test {
             0x0d, 0xb2, 0x8f, 0x98, 0x85, 0x86, 0xa1, 0xb7, 0xe4, 0xd5, 0xc6, 0x9c
         ];
 
-        let hkdf = hkdf::HKDF_SHA256;
-        let mut ks = KeySchedule::new_with_empty_secret(hkdf);
+        let mut ks = KeySchedule::new_with_empty_secret(&RING, &TLS13_AES_128_GCM_SHA256).unwrap();
         ks.input_secret(&ecdhe_secret);
 
         assert_traffic_secret(
@@ -387,10 +445,10 @@ mod test {
         let traffic_secret = ks.derive_logged_secret(kind, &hash, &log, &[0; 32]);
 
         // Since we can't test key equality, we test the output of sealing with the key instead.
-        let aead_alg = &aead::AES_128_GCM;
+        let aead_alg = RING.aead(BulkAlgorithm::AES_128_GCM).unwrap();
         let key = derive_traffic_key(&traffic_secret, aead_alg);
         let seal_output = seal_zeroes(key);
-        let expected_key = aead::UnboundKey::new(aead_alg, expected_key).unwrap();
+        let expected_key = aead_alg.new_key(expected_key);
         let expected_seal_output = seal_zeroes(expected_key);
         assert_eq!(seal_output, expected_seal_output);
         assert!(seal_output.len() >= 48); // Sanity check.
@@ -399,12 +457,11 @@ mod test {
         assert_eq!(iv.value(), expected_iv);
     }
 
-    fn seal_zeroes(key: aead::UnboundKey) -> Vec<u8> {
-        let key = aead::LessSafeKey::new(key);
+    fn seal_zeroes(key: Box<dyn AeadKey>) -> Vec<u8> {
         let mut seal_output = vec![0; 32];
         key.seal_in_place_append_tag(
-            aead::Nonce::assume_unique_for_key([0; aead::NONCE_LEN]),
-            aead::Aad::empty(),
+            [0; NONCE_LEN],
+            &[],
             &mut seal_output)
             .unwrap();
         seal_output
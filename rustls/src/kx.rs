@@ -0,0 +1,623 @@
+use crate::msgs::enums::NamedGroup;
+use crate::msgs::handshake::{ClientECDHParams, ServerECDHParams};
+use crate::msgs::handshake::{ClientDHParams, ServerDHParams};
+use crate::msgs::codec::{Reader, Codec};
+
+#[cfg(feature = "pq_kx")]
+use crate::mlkem;
+
+use ring;
+use std::fmt;
+
+/// A key exchange group: something which can generate an
+/// ephemeral key pair and agree a shared secret with a peer.
+///
+/// Implement this to use groups rustls doesn't support itself,
+/// and list them in `ClientConfig::kx_groups` or
+/// `ServerConfig::kx_groups`.
+pub trait SupportedKxGroup : Send + Sync + fmt::Debug {
+    /// The TLS name of this group.
+    fn name(&self) -> NamedGroup;
+
+    /// Generate a fresh ephemeral key pair, to start a key
+    /// exchange in this group.  Returns None on failure.
+    fn start(&self) -> Option<Box<dyn ActiveKeyExchange>>;
+
+    /// Complete a key exchange started by the peer, whose public
+    /// key is `peer`.  This is used by TLS1.3 servers.
+    ///
+    /// The default implementation generates a key pair with
+    /// `start`; KEM-based groups override it to encapsulate
+    /// to `peer` instead.
+    fn respond(&self, peer: &[u8]) -> Option<KeyExchangeResult> {
+        let active = self.start()?;
+        let pubkey = active.pub_key().to_vec();
+        let premaster_secret = active.complete(peer)?;
+
+        Some(KeyExchangeResult {
+            pubkey,
+            premaster_secret,
+        })
+    }
+
+    /// Whether this group can be used in TLS1.2.  Groups whose
+    /// public keys are too large for TLS1.2's ECDHE encoding
+    /// should return false.
+    fn usable_for_tls12(&self) -> bool {
+        true
+    }
+
+    /// If this is a finite field Diffie-Hellman group, its
+    /// parameters.  TLS1.2 sends these explicitly, and this
+    /// marks the group as usable with DHE ciphersuites rather
+    /// than ECDHE ones.
+    fn ffdhe_params(&self) -> Option<FfdheParams> {
+        None
+    }
+
+    /// Whether a TLS1.3 server which prefers this group should send
+    /// a HelloRetryRequest for it, rather than accept a client's
+    /// key share in a less preferred group.
+    fn worth_hello_retry_request(&self) -> bool {
+        false
+    }
+}
+
+/// A key exchange in progress: an ephemeral key pair which is
+/// waiting for the peer's public key.
+pub trait ActiveKeyExchange : Send + Sync {
+    /// Our public key, as sent to the peer.
+    fn pub_key(&self) -> &[u8];
+
+    /// Complete the key exchange with the peer's public key
+    /// `peer`, consuming our private key.  Returns the shared
+    /// secret, or None if `peer` is invalid.
+    fn complete(self: Box<Self>, peer: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// The parameters of a finite field Diffie-Hellman group.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FfdheParams {
+    /// The prime modulus, big-endian.
+    pub p: &'static [u8],
+    /// The generator, big-endian.
+    pub g: &'static [u8],
+}
+
+/// A key exchange group implemented using *ring*.
+///
+/// Both the key agreement and the private key's random material
+/// come from *ring*, not from the configured `CryptoProvider`.
+pub struct RingKxGroup {
+    name: NamedGroup,
+    agreement_algorithm: &'static ring::agreement::Algorithm,
+}
+
+impl fmt::Debug for RingKxGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RingKxGroup")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl SupportedKxGroup for RingKxGroup {
+    fn name(&self) -> NamedGroup {
+        self.name
+    }
+
+    fn start(&self) -> Option<Box<dyn ActiveKeyExchange>> {
+        let rng = ring::rand::SystemRandom::new();
+        let privkey = ring::agreement::EphemeralPrivateKey::generate(self.agreement_algorithm, &rng)
+            .ok()?;
+        let pubkey = privkey.compute_public_key()
+            .ok()?;
+
+        Some(Box::new(RingKeyExchange {
+            agreement_algorithm: self.agreement_algorithm,
+            privkey,
+            pubkey,
+        }))
+    }
+}
+
+struct RingKeyExchange {
+    agreement_algorithm: &'static ring::agreement::Algorithm,
+    privkey: ring::agreement::EphemeralPrivateKey,
+    pubkey: ring::agreement::PublicKey,
+}
+
+impl ActiveKeyExchange for RingKeyExchange {
+    fn pub_key(&self) -> &[u8] {
+        self.pubkey.as_ref()
+    }
+
+    fn complete(self: Box<Self>, peer: &[u8]) -> Option<Vec<u8>> {
+        let peer_key = ring::agreement::UnparsedPublicKey::new(self.agreement_algorithm, peer);
+        ring::agreement::agree_ephemeral(self.privkey,
+                                         &peer_key,
+                                         (),
+                                         |v| Ok(v.to_vec()))
+            .ok()
+    }
+}
+
+/// The X25519 key exchange group.
+pub static X25519: RingKxGroup = RingKxGroup {
+    name: NamedGroup::X25519,
+    agreement_algorithm: &ring::agreement::X25519,
+};
+
+/// The NIST P-256 key exchange group.
+pub static SECP256R1: RingKxGroup = RingKxGroup {
+    name: NamedGroup::secp256r1,
+    agreement_algorithm: &ring::agreement::ECDH_P256,
+};
+
+/// The NIST P-384 key exchange group.
+pub static SECP384R1: RingKxGroup = RingKxGroup {
+    name: NamedGroup::secp384r1,
+    agreement_algorithm: &ring::agreement::ECDH_P384,
+};
+
+/// All the key exchange groups supported by rustls, in our
+/// default preference order.
+pub static ALL_KX_GROUPS: [&dyn SupportedKxGroup; 3] = [
+    &X25519,
+    &SECP384R1,
+    &SECP256R1,
+];
+
+/// Find the group called `name` in `groups`.
+pub fn find_kx_group(name: NamedGroup,
+                     groups: &[&'static dyn SupportedKxGroup])
+                     -> Option<&'static dyn SupportedKxGroup> {
+    groups.iter()
+        .find(|skxg| skxg.name() == name)
+        .cloned()
+}
+
+/// The names of `groups`, preserving their order.
+pub fn kx_group_names(groups: &[&'static dyn SupportedKxGroup]) -> Vec<NamedGroup> {
+    groups.iter()
+        .map(|skxg| skxg.name())
+        .collect()
+}
+
+/// The result of a key exchange.  This has our public key,
+/// and the agreed premaster secret.
+pub struct KeyExchangeResult {
+    /// Our public key, as sent to the peer.
+    pub pubkey: Vec<u8>,
+    /// The shared secret.
+    pub premaster_secret: Vec<u8>,
+}
+
+/// An in-progress key exchange.  This has the group,
+/// our private key, and our public key.
+pub struct KeyExchange {
+    pub group: NamedGroup,
+    pub pubkey: Vec<u8>,
+    ffdhe: Option<FfdheParams>,
+    active: Box<dyn ActiveKeyExchange>,
+}
+
+/// `bytes` without any leading zeros.
+fn trim_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter()
+        .position(|b| *b != 0)
+        .unwrap_or_else(|| bytes.len());
+    &bytes[start..]
+}
+
+/// TLS1.2 DHE public values may be sent without leading zeros:
+/// restore them, so `value` is `len` bytes.
+fn left_pad(value: &[u8], len: usize) -> Option<Vec<u8>> {
+    if value.len() > len {
+        return None;
+    }
+
+    let mut out = vec![0u8; len - value.len()];
+    out.extend_from_slice(value);
+    Some(out)
+}
+
+impl KeyExchange {
+    pub fn client_ecdhe(kx_params: &[u8],
+                        groups: &[&'static dyn SupportedKxGroup]) -> Option<KeyExchangeResult> {
+        let mut rd = Reader::init(kx_params);
+        let ecdh_params = ServerECDHParams::read(&mut rd)?;
+        let skxg = find_kx_group(ecdh_params.curve_params.named_group, groups)
+            .filter(|skxg| skxg.usable_for_tls12() && skxg.ffdhe_params().is_none())?;
+
+        KeyExchange::start(skxg)?
+            .complete(&ecdh_params.public.0)
+    }
+
+    /// The server chooses arbitrary DHE parameters in TLS1.2.  We
+    /// only accept those of a group in `groups`, and so never need
+    /// to check the parameters themselves.
+    pub fn client_dhe(kx_params: &[u8],
+                      groups: &[&'static dyn SupportedKxGroup]) -> Option<KeyExchangeResult> {
+        let mut rd = Reader::init(kx_params);
+        let dh_params = ServerDHParams::read(&mut rd)?;
+        let p = trim_leading_zeros(&dh_params.dh_p.0);
+        let g = trim_leading_zeros(&dh_params.dh_g.0);
+
+        let skxg = groups.iter()
+            .find(|skxg| skxg.ffdhe_params()
+                  .map(|params| params.p == p && params.g == g)
+                  .unwrap_or(false))?;
+
+        let peer = left_pad(&dh_params.dh_ys.0, p.len())?;
+        KeyExchange::start(*skxg)?
+            .complete_tls12(&peer)
+    }
+
+    pub fn start(skxg: &dyn SupportedKxGroup) -> Option<KeyExchange> {
+        let active = skxg.start()?;
+        let pubkey = active.pub_key().to_vec();
+
+        Some(KeyExchange {
+            group: skxg.name(),
+            pubkey,
+            ffdhe: skxg.ffdhe_params(),
+            active,
+        })
+    }
+
+    pub fn check_client_params(&self, kx_params: &[u8]) -> bool {
+        self.decode_client_params(kx_params).is_some()
+    }
+
+    fn decode_client_params(&self, kx_params: &[u8]) -> Option<Vec<u8>> {
+        let mut rd = Reader::init(kx_params);
+        let public = match self.ffdhe {
+            Some(params) => left_pad(&ClientDHParams::read(&mut rd)?.public.0, params.p.len())?,
+            None => ClientECDHParams::read(&mut rd)?.public.0,
+        };
+
+        if rd.any_left() {
+            None
+        } else {
+            Some(public)
+        }
+    }
+
+    pub fn server_complete(self, kx_params: &[u8]) -> Option<KeyExchangeResult> {
+        self.decode_client_params(kx_params)
+            .and_then(|public| self.complete_tls12(&public))
+    }
+
+    /// Complete a TLS1.2 key exchange.  For DHE, leading zeros are
+    /// stripped from the premaster secret (RFC 5246 section 8.1.2);
+    /// TLS1.3 keeps them.
+    fn complete_tls12(self, peer: &[u8]) -> Option<KeyExchangeResult> {
+        let is_dhe = self.ffdhe.is_some();
+        let mut result = self.complete(peer)?;
+
+        if is_dhe {
+            result.premaster_secret = trim_leading_zeros(&result.premaster_secret).to_vec();
+        }
+
+        Some(result)
+    }
+
+    pub fn complete(self, peer: &[u8]) -> Option<KeyExchangeResult> {
+        let premaster_secret = self.active.complete(peer)?;
+
+        Some(KeyExchangeResult {
+            pubkey: self.pubkey,
+            premaster_secret,
+        })
+    }
+}
+
+/// A hybrid key exchange group, which combines X25519 with a
+/// post-quantum KEM.  The shares and shared secrets of both
+/// are concatenated.
+#[cfg(feature = "pq_kx")]
+pub struct HybridKxGroup {
+    name: NamedGroup,
+    kem: mlkem::Variant,
+    x25519_first: bool,
+}
+
+#[cfg(feature = "pq_kx")]
+impl fmt::Debug for HybridKxGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HybridKxGroup")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+#[cfg(feature = "pq_kx")]
+const X25519_LEN: usize = 32;
+
+#[cfg(feature = "pq_kx")]
+fn hybrid_concat(x25519_first: bool, x25519: &[u8], kem: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(x25519.len() + kem.len());
+    if x25519_first {
+        out.extend_from_slice(x25519);
+        out.extend_from_slice(kem);
+    } else {
+        out.extend_from_slice(kem);
+        out.extend_from_slice(x25519);
+    }
+    out
+}
+
+/// Split `share` into its X25519 and KEM parts, checking the
+/// KEM part is `kem_len` long.
+#[cfg(feature = "pq_kx")]
+fn hybrid_split(x25519_first: bool, share: &[u8], kem_len: usize) -> Option<(&[u8], &[u8])> {
+    if share.len() != X25519_LEN + kem_len {
+        return None;
+    }
+
+    if x25519_first {
+        let (x25519, kem) = share.split_at(X25519_LEN);
+        Some((x25519, kem))
+    } else {
+        let (kem, x25519) = share.split_at(kem_len);
+        Some((x25519, kem))
+    }
+}
+
+#[cfg(feature = "pq_kx")]
+impl SupportedKxGroup for HybridKxGroup {
+    fn name(&self) -> NamedGroup {
+        self.name
+    }
+
+    fn start(&self) -> Option<Box<dyn ActiveKeyExchange>> {
+        let x25519 = X25519.start()?;
+        let (ek, dk) = mlkem::generate_key_pair(self.kem);
+        let pubkey = hybrid_concat(self.x25519_first, x25519.pub_key(), &ek);
+
+        Some(Box::new(HybridKeyExchange {
+            x25519_first: self.x25519_first,
+            x25519,
+            dk,
+            pubkey,
+        }))
+    }
+
+    fn respond(&self, peer: &[u8]) -> Option<KeyExchangeResult> {
+        let (peer_x25519, peer_ek) = hybrid_split(self.x25519_first, peer, mlkem::ENCAPSULATION_KEY_LEN)?;
+        let (ct, kem_secret) = mlkem::encapsulate(self.kem, peer_ek)?;
+        let x25519 = X25519.respond(peer_x25519)?;
+
+        Some(KeyExchangeResult {
+            pubkey: hybrid_concat(self.x25519_first, &x25519.pubkey, &ct),
+            premaster_secret: hybrid_concat(self.x25519_first, &x25519.premaster_secret, &kem_secret),
+        })
+    }
+
+    fn usable_for_tls12(&self) -> bool {
+        false
+    }
+
+    fn worth_hello_retry_request(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "pq_kx")]
+struct HybridKeyExchange {
+    x25519_first: bool,
+    x25519: Box<dyn ActiveKeyExchange>,
+    dk: mlkem::DecapsulationKey,
+    pubkey: Vec<u8>,
+}
+
+#[cfg(feature = "pq_kx")]
+impl ActiveKeyExchange for HybridKeyExchange {
+    fn pub_key(&self) -> &[u8] {
+        &self.pubkey
+    }
+
+    fn complete(self: Box<Self>, peer: &[u8]) -> Option<Vec<u8>> {
+        let (peer_x25519, ct) = hybrid_split(self.x25519_first, peer, mlkem::CIPHERTEXT_LEN)?;
+        let kem_secret = self.dk.decapsulate(ct)?;
+        let x25519_secret = self.x25519.complete(peer_x25519)?;
+        Some(hybrid_concat(self.x25519_first, &x25519_secret, &kem_secret))
+    }
+}
+
+/// The X25519Kyber768Draft00 hybrid key exchange group.
+#[cfg(feature = "pq_kx")]
+pub static X25519KYBER768DRAFT00: HybridKxGroup = HybridKxGroup {
+    name: NamedGroup::X25519Kyber768Draft00,
+    kem: mlkem::Variant::Kyber,
+    x25519_first: true,
+};
+
+/// The X25519MLKEM768 hybrid key exchange group.
+#[cfg(feature = "pq_kx")]
+pub static X25519MLKEM768: HybridKxGroup = HybridKxGroup {
+    name: NamedGroup::X25519MLKEM768,
+    kem: mlkem::Variant::MlKem,
+    x25519_first: false,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_groups_agree() {
+        for skxg in ALL_KX_GROUPS.iter() {
+            let ours = KeyExchange::start(*skxg).unwrap();
+            let theirs = KeyExchange::start(*skxg).unwrap();
+            assert_eq!(ours.group, skxg.name());
+
+            let their_pubkey = theirs.pubkey.clone();
+            let ours = ours.complete(&their_pubkey).unwrap();
+            let theirs = theirs.complete(&ours.pubkey).unwrap();
+            assert_eq!(ours.premaster_secret, theirs.premaster_secret);
+        }
+    }
+
+    #[test]
+    fn test_find_kx_group() {
+        assert_eq!(find_kx_group(NamedGroup::secp256r1, &ALL_KX_GROUPS).unwrap().name(),
+                   NamedGroup::secp256r1);
+        assert!(find_kx_group(NamedGroup::X448, &ALL_KX_GROUPS).is_none());
+        assert!(find_kx_group(NamedGroup::X25519, &[ &SECP256R1 ]).is_none());
+        assert_eq!(kx_group_names(&ALL_KX_GROUPS),
+                   vec![ NamedGroup::X25519, NamedGroup::secp384r1, NamedGroup::secp256r1 ]);
+    }
+
+    #[cfg(feature = "pq_kx")]
+    #[test]
+    fn test_hybrid_groups_agree() {
+        for skxg in &[ &X25519KYBER768DRAFT00, &X25519MLKEM768 ] {
+            assert!(!skxg.usable_for_tls12());
+            assert!(skxg.worth_hello_retry_request());
+
+            let client = skxg.start().unwrap();
+            assert_eq!(client.pub_key().len(), 32 + 1184);
+
+            let server = skxg.respond(client.pub_key()).unwrap();
+            assert_eq!(server.pubkey.len(), 32 + 1088);
+            assert_eq!(server.premaster_secret.len(), 64);
+
+            let client_secret = client.complete(&server.pubkey).unwrap();
+            assert_eq!(client_secret, server.premaster_secret);
+        }
+    }
+
+    #[cfg(feature = "pq_kx")]
+    #[test]
+    fn test_hybrid_groups_reject_bad_shares() {
+        let client = X25519MLKEM768.start().unwrap();
+        assert!(X25519MLKEM768.respond(&client.pub_key()[1..]).is_none());
+        assert!(X25519MLKEM768.respond(&[0u8; 32]).is_none());
+
+        let server = X25519MLKEM768.respond(client.pub_key()).unwrap();
+        assert!(client.complete(&server.pubkey[..100]).is_none());
+    }
+
+    /// X25519 with a fixed private key.
+    #[cfg(feature = "pq_kx")]
+    struct FixedX25519 {
+        scalar: [u8; X25519_LEN],
+        pubkey: [u8; X25519_LEN],
+    }
+
+    #[cfg(feature = "pq_kx")]
+    impl FixedX25519 {
+        fn start(scalar: [u8; X25519_LEN]) -> Box<dyn ActiveKeyExchange> {
+            Box::new(FixedX25519 {
+                scalar,
+                pubkey: crate::x25519::x25519_base(&scalar),
+            })
+        }
+    }
+
+    #[cfg(feature = "pq_kx")]
+    impl ActiveKeyExchange for FixedX25519 {
+        fn pub_key(&self) -> &[u8] {
+            &self.pubkey
+        }
+
+        fn complete(self: Box<Self>, peer: &[u8]) -> Option<Vec<u8>> {
+            crate::x25519::agree(&self.scalar, peer).map(|s| s.to_vec())
+        }
+    }
+
+    /// Check `skxg` against a known answer.  The client's KEM key
+    /// comes from the seeds `d` and `z`, and the server encapsulates
+    /// with `m`; see the KATs in `mlkem`.  The X25519 keys are those
+    /// of RFC 7748 section 6.1.
+    #[cfg(feature = "pq_kx")]
+    fn check_hybrid_kat(skxg: &HybridKxGroup,
+                        (d, z, m): (&[u8; 32], &[u8; 32], &[u8; 32]),
+                        client_share_hash: &[u8],
+                        server_share_hash: &[u8],
+                        secret: &[u8]) {
+        let alice = hex!("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob = hex!("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+        let sha3_256 = |data: &[u8]| {
+            use tiny_keccak::{Hasher, Sha3};
+            let mut h = Sha3::v256();
+            h.update(data);
+            let mut out = [0u8; 32];
+            h.finalize(&mut out);
+            out
+        };
+
+        let (ek, dk) = mlkem::key_pair_for_test(skxg.kem, d, z);
+        let x25519 = FixedX25519::start(alice);
+        let alice_pubkey = x25519.pub_key().to_vec();
+        let pubkey = hybrid_concat(skxg.x25519_first, x25519.pub_key(), &ek);
+        assert_eq!(&sha3_256(&pubkey), client_share_hash);
+        let client = Box::new(HybridKeyExchange {
+            x25519_first: skxg.x25519_first,
+            x25519,
+            dk,
+            pubkey,
+        });
+
+        let (ct, kem_secret) = mlkem::encapsulate_with_seed(skxg.kem, &ek, m).unwrap();
+        let server_x25519 = FixedX25519::start(bob);
+        let server_share = hybrid_concat(skxg.x25519_first, server_x25519.pub_key(), &ct);
+        assert_eq!(&sha3_256(&server_share), server_share_hash);
+        let server_secret = hybrid_concat(skxg.x25519_first,
+                                          &server_x25519.complete(&alice_pubkey).unwrap(),
+                                          &kem_secret);
+        assert_eq!(&server_secret[..], secret);
+
+        assert_eq!(&client.complete(&server_share).unwrap()[..], secret);
+    }
+
+    #[cfg(feature = "pq_kx")]
+    #[test]
+    fn test_x25519mlkem768_kat() {
+        // The ML-KEM-768 seeds d = 00..1f, z = 20..3f and m = 40..5f.
+        check_hybrid_kat(&X25519MLKEM768,
+                         (&hex!("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"),
+                          &hex!("202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f"),
+                          &hex!("404142434445464748494a4b4c4d4e4f505152535455565758595a5b5c5d5e5f")),
+                         &hex!("ea5150f31ce6990eb38ead9b019e9c56d141d544ed50990cccd90a25f05bc5fb"),
+                         &hex!("0038a71a6e6f6019a7f08a1a4da536f0be8f97aab0a8008bd8bb7b57139039f0"),
+                         &hex!("9cddd089ffe70e3996e76f7c8d06746df34d07e8657bc0fcf2bb0e1c3084aea1
+                                4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742"));
+    }
+
+    #[cfg(feature = "pq_kx")]
+    #[test]
+    fn test_x25519kyber768draft00_kat() {
+        // The seeds of count = 0 in the Kyber768 round 3 KAT file.
+        check_hybrid_kat(&X25519KYBER768DRAFT00,
+                         (&hex!("7c9935a0b07694aa0c6d10e4db6b1add2fd81a25ccb148032dcd739936737f2d"),
+                          &hex!("8626ed79d451140800e03b59b956f8210e556067407d13dc90fa9e8b872bfb8f"),
+                          &hex!("147c03f7a5bebba406c8fae1874d7f13c80efe79a3a9a874cc09fe76f6997615")),
+                         &hex!("fc5ca19610f2db490af28ba1d637e1ce3107eadd4f7b337929e855f010ca8f64"),
+                         &hex!("08966f70c78b725e1ae6313b7ec01a4f9199621ad2b32ab0e48eea973b6967f3"),
+                         &hex!("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742
+                                914cb67fe5c38e73bf74181c0ac50428dedf7750a98058f7d536708774535b29"));
+    }
+
+    #[cfg(feature = "pq_kx")]
+    #[test]
+    fn test_hybrid_share_order() {
+        let client = X25519MLKEM768.start().unwrap();
+        let server = X25519.respond(&client.pub_key()[1184..]).unwrap();
+        assert_eq!(server.premaster_secret.len(), 32);
+
+        let client = X25519KYBER768DRAFT00.start().unwrap();
+        let server = X25519.respond(&client.pub_key()[..32]).unwrap();
+        assert_eq!(server.premaster_secret.len(), 32);
+    }
+
+    #[test]
+    fn test_trim_and_pad() {
+        assert_eq!(trim_leading_zeros(&[ 0, 0, 1, 0 ]), &[ 1, 0 ]);
+        assert_eq!(trim_leading_zeros(&[ 0, 0 ]), &[] as &[u8]);
+        assert_eq!(left_pad(&[ 1, 2 ], 4).unwrap(), vec![ 0, 0, 1, 2 ]);
+        assert!(left_pad(&[ 1, 2, 3 ], 2).is_none());
+    }
+}
@@ -6,11 +6,13 @@
 //! ## Current features
 //!
 //! * TLS1.2 and TLS1.3.
-//! * ECDSA or RSA server authentication by clients.
-//! * ECDSA or RSA server authentication by servers.
+//! * ECDSA, Ed25519 or RSA server authentication by clients.
+//! * ECDSA, Ed25519 or RSA server authentication by servers.
 //! * Forward secrecy using ECDHE; with curve25519, nistp256 or nistp384 curves.
 //! * AES128-GCM and AES256-GCM bulk encryption, with safe nonces.
 //! * Chacha20Poly1305 bulk encryption.
+//! * Optional TLS1.3 AES128-CCM and AES128-CCM-8 bulk encryption, for constrained
+//!   peers: see the `aes_ccm` feature.
 //! * ALPN support.
 //! * SNI support.
 //! * Tunable MTU to make TLS messages match size of underlying transport.
@@ -26,6 +28,7 @@
 //! * OCSP stapling by servers.
 //! * SCT stapling by servers.
 //! * SCT verification by clients.
+//! * Pluggable cryptography, with *ring* as the default.
 //!
 //! ## Possible future features
 //!
@@ -100,16 +103,15 @@
 //! config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
 //! ```
 //!
-//! Now we can make a session.  You need to provide the server's hostname so we
-//! know what to expect to find in the server's certificate.
+//! Now we can make a session.  You need to provide the server's hostname (or
+//! IP address) so we know what to expect to find in the server's certificate.
 //!
 //! ```no_run
 //! # use rustls;
-//! # use webpki;
 //! # use std::sync::Arc;
 //! # let mut config = rustls::ClientConfig::new();
 //! let rc_config = Arc::new(config);
-//! let example_com = webpki::DNSNameRef::try_from_ascii_str("example.com").unwrap();
+//! let example_com = rustls::ServerName::try_from_ascii_str("example.com").unwrap();
 //! let mut client = rustls::ClientSession::new(&rc_config, example_com);
 //! ```
 //!
@@ -182,6 +184,29 @@
 //!   details of these.  You will only need this if you're writing a QUIC
 //!   implementation.
 //!
+//! - `esni`: this feature enables support for the obsolete draft
+//!   Encrypted SNI extension, via the `esni` module.  New deployments
+//!   should use Encrypted Client Hello (see the `ech` module) instead.
+//!
+//! - `pq_kx`: this feature adds the hybrid post-quantum key exchange
+//!   groups `kx_group::X25519MLKEM768` and `kx_group::X25519KYBER768DRAFT00`.
+//!   These are not enabled by default: add them to `kx_groups` in your
+//!   `ClientConfig` or `ServerConfig` to use them.  They are TLS1.3-only.
+//!   *ring* has no ML-KEM, so the KEM half is rustls's own implementation:
+//!   it is written to be constant time, but has not been audited.
+//!
+//! - `ffdhe`: this feature adds the finite field Diffie-Hellman groups
+//!   from RFC 7919, `kx_group::FFDHE2048` to `kx_group::FFDHE8192`, and the
+//!   TLS1.2 DHE ciphersuites in `FFDHE_CIPHERSUITES`.  These are not enabled
+//!   by default, and are only for peers which don't support elliptic curves.
+//!   *ring* has no finite field arithmetic, so these use `crypto-bigint`'s
+//!   constant time modular exponentiation.
+//!
+//! - `aes_ccm`: this feature adds the TLS1.3 AES-128-CCM and AES-128-CCM-8
+//!   ciphersuites in `TLS13_CCM_CIPHERSUITES`, for constrained peers which
+//!   lack AES-GCM and ChaCha20Poly1305.  *ring* has no AES-CCM, so these use
+//!   the RustCrypto `ccm` and `aes` crates instead.
+//!
 
 // Require docs for public APIs, deny unsafe code, etc.
 #![forbid(unsafe_code,
@@ -225,21 +250,52 @@ mod session;
 mod stream;
 mod pemfile;
 mod x509;
+mod x25519;
+#[cfg(feature = "aes_ccm")]
+mod ccm;
+mod hpke;
 mod anchors;
+mod ocsp;
+mod crl;
+mod pinning;
+mod identity;
 mod verify;
+mod ct;
 #[cfg(test)]
 mod verifybench;
 mod handshake;
 mod suites;
+mod kx;
+#[cfg(feature = "pq_kx")]
+mod mlkem;
+#[cfg(feature = "ffdhe")]
+mod ffdhe;
 mod ticketer;
 mod server;
 mod client;
 mod key;
 mod bs_debug;
 mod keylog;
+mod server_name;
+mod time_provider;
+
+/// Encrypted Client Hello (ECH) related functions
+///
+/// ECH's HPKE encryption uses *ring* (and, for servers' long-lived
+/// keys, the `x25519-dalek` crate), whatever `CryptoProvider` is
+/// configured.
+pub mod ech;
 
-/// ESNI related functions
+/// ESNI related functions.  ESNI is superseded by ECH, and is only
+/// here for compatibility.
+///
+/// ESNI's key exchange and encryption use *ring* (and, for
+/// secp256r1 keys, the `p256` crate), whatever `CryptoProvider` is
+/// configured.
+#[cfg(feature = "esni")]
 pub mod esni;
+#[cfg(feature = "esni")]
+mod p256;
 
 /// Internal classes which may be useful outside the library.
 /// The contents of this section DO NOT form part of the stable interface.
@@ -247,6 +303,9 @@ pub mod internal {
     /// Functions for parsing PEM files containing certificates/keys.
     pub mod pemfile {
         pub use crate::pemfile::{certs, rsa_private_keys, pkcs8_private_keys};
+        pub use crate::pemfile::{ec_private_keys, encrypted_pkcs8_private_keys, crls};
+        pub use crate::pemfile::{read_all, decrypt_pkcs8_private_key};
+        pub use crate::pemfile::{Reader, Item, PemError};
     }
 
     /// Low-level TLS message parsing and encoding functions.
@@ -265,24 +324,62 @@ pub use crate::stream::{Stream, StreamOwned};
 pub use crate::anchors::{DistinguishedNames, RootCertStore};
 pub use crate::client::StoresClientSessions;
 pub use crate::client::handy::{NoClientSessionStorage, ClientSessionMemoryCache};
+pub use crate::client::handy::ClientSessionFileCache;
 pub use crate::client::{ClientConfig, ClientSession, WriteEarlyData};
 pub use crate::client::ResolvesClientCert;
+pub use crate::server_name::ServerName;
+pub use crate::time_provider::{TimeProvider, SystemTimeProvider};
+pub use crate::ct::CTPolicy;
 pub use crate::server::StoresServerSessions;
 pub use crate::server::handy::{NoServerSessionStorage, ServerSessionMemoryCache};
 pub use crate::server::{ServerConfig, ServerSession};
 pub use crate::server::handy::ResolvesServerCertUsingSNI;
+pub use crate::server::handy::{OCSPStaplingResolver, FetchesOCSPResponses,
+                               DEFAULT_OCSP_FETCH_RETRY_INTERVAL};
 pub use crate::server::{ResolvesServerCert,ProducesTickets,ClientHello};
 pub use crate::ticketer::Ticketer;
 pub use crate::verify::{NoClientAuth, AllowAnyAuthenticatedClient,
                  AllowAnyAnonymousOrAuthenticatedClient};
+pub use crate::ocsp::OCSPPolicy;
+pub use crate::crl::{CertRevocationList, CRLStore, RevocationPolicy};
+pub use crate::crl::{RevocationCheckDepth, UnknownStatusPolicy};
+pub use crate::verify::{RevocationCheckingClientVerifier, RevocationCheckingServerVerifier};
+pub use crate::pinning::{PinSet, SPKIHash};
+pub use crate::verify::PinningVerifier;
+pub use crate::identity::{PeerIdentity, SubjectAltName, ClientIdentityPolicy};
+pub use crate::verify::PolicyCheckingClientVerifier;
+pub use crate::verify::TrustedRawPublicKeys;
 pub use crate::suites::{ALL_CIPHERSUITES, BulkAlgorithm, SupportedCipherSuite};
+pub use crate::cipher::{MessageEncrypter, MessageDecrypter, MessageCipherFactory};
+#[cfg(feature = "ffdhe")]
+pub use crate::suites::FFDHE_CIPHERSUITES;
+#[cfg(feature = "aes_ccm")]
+pub use crate::suites::TLS13_CCM_CIPHERSUITES;
+pub use crate::kx::{ALL_KX_GROUPS, SupportedKxGroup, ActiveKeyExchange, KeyExchangeResult};
+pub use crate::kx::FfdheParams;
 pub use crate::key::{Certificate, PrivateKey};
 pub use crate::keylog::{KeyLog, NoKeyLog, KeyLogFile};
 pub use crate::vecbuf::{WriteV, WriteVAdapter};
 
 /// Message signing interfaces and implementations.
+///
+/// The implementations here use *ring*, whatever `CryptoProvider`
+/// is configured.
 pub mod sign;
 
+/// Pluggable cryptography: the `CryptoProvider` trait, and the
+/// default implementation of it using *ring*.
+pub mod crypto;
+
+/// The key exchange groups supported by rustls.
+pub mod kx_group {
+    pub use crate::kx::{X25519, SECP256R1, SECP384R1, RingKxGroup};
+    #[cfg(feature = "pq_kx")]
+    pub use crate::kx::{X25519MLKEM768, X25519KYBER768DRAFT00, HybridKxGroup};
+    #[cfg(feature = "ffdhe")]
+    pub use crate::ffdhe::{FFDHE2048, FFDHE3072, FFDHE4096, FFDHE6144, FFDHE8192, FfdheKxGroup};
+}
+
 #[cfg(feature = "quic")]
 /// APIs for implementing QUIC TLS
 pub mod quic;
@@ -300,6 +397,8 @@ mod quic {
 pub use crate::verify::{ServerCertVerifier, ServerCertVerified,
     ClientCertVerifier, ClientCertVerified};
 #[cfg(feature = "dangerous_configuration")]
+pub use crate::verify::{ServerRawPublicKeyVerifier, ClientRawPublicKeyVerifier};
+#[cfg(feature = "dangerous_configuration")]
 pub use crate::client::danger::DangerousClientConfig;
 
 #[cfg(test)]
@@ -0,0 +1,843 @@
+//! A pure Rust implementation of the 768 parameter set of ML-KEM
+//! (FIPS 203), and of its predecessor Kyber768 (round 3), for use in
+//! hybrid key exchange groups.
+//!
+//! Secret values don't affect branches, memory accesses or the
+//! operands of divisions: reduction mod q multiplies rather than
+//! divides (see `barrett_reduce`), and decapsulation chooses between
+//! the real and the implicit rejection secrets with `subtle`.  This
+//! code hasn't been independently reviewed, though.
+//!
+//! Seeds come from *ring*'s random number generator, not from the
+//! configured `CryptoProvider`.
+
+use crate::rand;
+use crate::crypto::ring::RING;
+
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+use tiny_keccak::{Hasher, Xof, Sha3, Shake};
+
+const N: usize = 256;
+const Q: u32 = 3329;
+const K: usize = 3;
+const ETA1: usize = 2;
+const ETA2: usize = 2;
+const DU: usize = 10;
+const DV: usize = 4;
+
+/// Length of an encapsulation (public) key.
+pub const ENCAPSULATION_KEY_LEN: usize = 384 * K + 32;
+
+/// Length of a decapsulation (private) key.
+const DECAPSULATION_KEY_LEN: usize = 768 * K + 96;
+
+/// Length of a ciphertext.
+pub const CIPHERTEXT_LEN: usize = 32 * (DU * K + DV);
+
+/// Length of a shared secret.
+pub const SHARED_SECRET_LEN: usize = 32;
+
+/// Which flavour of the KEM to use.  These share the same
+/// underlying public key encryption scheme, but differ in how it
+/// is seeded and how the shared secret is derived.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+    /// Kyber768, as submitted to round 3 of the NIST process.
+    Kyber,
+    /// ML-KEM-768, as standardised in FIPS 203.
+    MlKem,
+}
+
+type Poly = [u16; N];
+type PolyVec = [Poly; K];
+
+fn bitrev7(x: usize) -> usize {
+    let mut r = 0;
+    for i in 0..7 {
+        r |= ((x >> i) & 1) << (6 - i);
+    }
+    r
+}
+
+fn pow_mod(base: u32, mut exp: usize) -> u16 {
+    let mut acc = 1u32;
+    let mut base = base % Q;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            acc = acc * base % Q;
+        }
+        base = base * base % Q;
+        exp >>= 1;
+    }
+    acc as u16
+}
+
+/// 17 is a primitive 256th root of unity modulo q.
+fn zetas() -> [u16; 128] {
+    let mut z = [0u16; 128];
+    for (i, zeta) in z.iter_mut().enumerate() {
+        *zeta = pow_mod(17, bitrev7(i));
+    }
+    z
+}
+
+fn gammas() -> [u16; 128] {
+    let mut g = [0u16; 128];
+    for (i, gamma) in g.iter_mut().enumerate() {
+        *gamma = pow_mod(17, 2 * bitrev7(i) + 1);
+    }
+    g
+}
+
+/// Reduce `x` < 2q to [0, q), without branching on `x`.
+fn reduce_once(x: u32) -> u16 {
+    // The top bit of t is set iff x < q, in which case q goes back on.
+    let t = x.wrapping_sub(Q);
+    t.wrapping_add(Q & 0u32.wrapping_sub(t >> 31)) as u16
+}
+
+const BARRETT_SHIFT: u32 = 36;
+
+/// floor(2^BARRETT_SHIFT / q).
+const BARRETT_MULTIPLIER: u64 = (1 << BARRETT_SHIFT) / Q as u64;
+
+/// Reduce `x` < q^2 mod q.  `%` would do, except that it can compile
+/// to a division instruction whose timing depends on `x`, so instead
+/// this estimates x / q with a multiplication, which is at most one
+/// too small.
+fn barrett_reduce(x: u32) -> u16 {
+    let quotient = ((u64::from(x) * BARRETT_MULTIPLIER) >> BARRETT_SHIFT) as u32;
+    reduce_once(x - quotient * Q)
+}
+
+fn add(a: u16, b: u16) -> u16 {
+    reduce_once(u32::from(a) + u32::from(b))
+}
+
+fn sub(a: u16, b: u16) -> u16 {
+    reduce_once(u32::from(a) + Q - u32::from(b))
+}
+
+fn mul(a: u16, b: u16) -> u16 {
+    barrett_reduce(u32::from(a) * u32::from(b))
+}
+
+fn ntt(f: &mut Poly, zetas: &[u16; 128]) {
+    let mut i = 1;
+    let mut len = 128;
+    while len >= 2 {
+        for start in (0..N).step_by(2 * len) {
+            let zeta = zetas[i];
+            i += 1;
+            for j in start..start + len {
+                let t = mul(zeta, f[j + len]);
+                f[j + len] = sub(f[j], t);
+                f[j] = add(f[j], t);
+            }
+        }
+        len /= 2;
+    }
+}
+
+fn inv_ntt(f: &mut Poly, zetas: &[u16; 128]) {
+    let mut i = 127;
+    let mut len = 2;
+    while len <= 128 {
+        for start in (0..N).step_by(2 * len) {
+            let zeta = zetas[i];
+            i -= 1;
+            for j in start..start + len {
+                let t = f[j];
+                f[j] = add(t, f[j + len]);
+                f[j + len] = mul(zeta, sub(f[j + len], t));
+            }
+        }
+        len *= 2;
+    }
+
+    // 3303 is 128^-1 mod q.
+    for c in f.iter_mut() {
+        *c = mul(*c, 3303);
+    }
+}
+
+/// Multiply `f` and `g` (both in the NTT domain), accumulating
+/// into `acc`.
+fn mul_acc_ntt(acc: &mut Poly, f: &Poly, g: &Poly, gammas: &[u16; 128]) {
+    for i in 0..128 {
+        let (a0, a1) = (f[2 * i], f[2 * i + 1]);
+        let (b0, b1) = (g[2 * i], g[2 * i + 1]);
+        let c0 = add(mul(a0, b0), mul(mul(a1, b1), gammas[i]));
+        let c1 = add(mul(a0, b1), mul(a1, b0));
+        acc[2 * i] = add(acc[2 * i], c0);
+        acc[2 * i + 1] = add(acc[2 * i + 1], c1);
+    }
+}
+
+fn sha3_256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut h = Sha3::v256();
+    for p in parts {
+        h.update(p);
+    }
+    let mut out = [0u8; 32];
+    h.finalize(&mut out);
+    out
+}
+
+fn sha3_512(parts: &[&[u8]]) -> ([u8; 32], [u8; 32]) {
+    let mut h = Sha3::v512();
+    for p in parts {
+        h.update(p);
+    }
+    let mut out = [0u8; 64];
+    h.finalize(&mut out);
+
+    let mut a = [0u8; 32];
+    let mut b = [0u8; 32];
+    a.copy_from_slice(&out[..32]);
+    b.copy_from_slice(&out[32..]);
+    (a, b)
+}
+
+fn shake256(parts: &[&[u8]], out: &mut [u8]) {
+    let mut h = Shake::v256();
+    for p in parts {
+        h.update(p);
+    }
+    h.finalize(out);
+}
+
+/// Uniformly sample a polynomial in the NTT domain from `rho`
+/// and the indices `i` and `j`.
+fn sample_ntt(rho: &[u8], i: u8, j: u8) -> Poly {
+    let mut xof = Shake::v128();
+    xof.update(rho);
+    xof.update(&[i, j]);
+
+    let mut f = [0u16; N];
+    let mut n = 0;
+    let mut buf = [0u8; 168];
+
+    while n < N {
+        xof.squeeze(&mut buf);
+
+        for c in buf.chunks(3) {
+            let d1 = u32::from(c[0]) | (u32::from(c[1] & 0x0f) << 8);
+            let d2 = u32::from(c[1] >> 4) | (u32::from(c[2]) << 4);
+
+            if d1 < Q && n < N {
+                f[n] = d1 as u16;
+                n += 1;
+            }
+
+            if d2 < Q && n < N {
+                f[n] = d2 as u16;
+                n += 1;
+            }
+        }
+    }
+
+    f
+}
+
+/// Sample the matrix A (or its transpose) from `rho`.
+fn sample_matrix(rho: &[u8], transpose: bool) -> [PolyVec; K] {
+    let mut a = [[[0u16; N]; K]; K];
+    for (i, row) in a.iter_mut().enumerate() {
+        for (j, entry) in row.iter_mut().enumerate() {
+            *entry = if transpose {
+                sample_ntt(rho, i as u8, j as u8)
+            } else {
+                sample_ntt(rho, j as u8, i as u8)
+            };
+        }
+    }
+    a
+}
+
+/// Sample a polynomial from the centred binomial distribution,
+/// using the output of PRF(`sigma`, `nonce`).
+fn sample_cbd(sigma: &[u8], nonce: u8, eta: usize) -> Poly {
+    let mut buf = vec![0u8; 64 * eta];
+    shake256(&[sigma, &[nonce]], &mut buf);
+
+    let bit = |idx: usize| u16::from((buf[idx / 8] >> (idx % 8)) & 1);
+
+    let mut f = [0u16; N];
+    for (i, c) in f.iter_mut().enumerate() {
+        let mut x = 0;
+        let mut y = 0;
+        for j in 0..eta {
+            x += bit(2 * i * eta + j);
+            y += bit(2 * i * eta + eta + j);
+        }
+        *c = sub(x, y);
+    }
+    f
+}
+
+fn byte_encode(f: &Poly, d: usize, out: &mut Vec<u8>) {
+    let mut acc = 0u32;
+    let mut acc_bits = 0;
+
+    for c in f.iter() {
+        acc |= u32::from(*c) << acc_bits;
+        acc_bits += d;
+
+        while acc_bits >= 8 {
+            out.push(acc as u8);
+            acc >>= 8;
+            acc_bits -= 8;
+        }
+    }
+}
+
+fn byte_decode(bytes: &[u8], d: usize) -> Poly {
+    debug_assert_eq!(bytes.len(), 32 * d);
+    let mask = (1u32 << d) - 1;
+    let mut acc = 0u32;
+    let mut acc_bits = 0;
+    let mut bytes = bytes.iter();
+
+    let mut f = [0u16; N];
+    for c in f.iter_mut() {
+        while acc_bits < d {
+            acc |= u32::from(*bytes.next().unwrap()) << acc_bits;
+            acc_bits += 8;
+        }
+
+        *c = (acc & mask) as u16;
+        acc >>= d;
+        acc_bits -= d;
+    }
+    f
+}
+
+const DIV_2Q_SHIFT: u32 = 40;
+
+/// ceil(2^DIV_2Q_SHIFT / 2q).
+const DIV_2Q_MULTIPLIER: u64 = ((1 << DIV_2Q_SHIFT) + 2 * Q as u64 - 1) / (2 * Q as u64);
+
+/// floor(`n` / 2q) for `n` < 2^23, by multiplication for the same
+/// reason as `barrett_reduce`.  The multiplier's error is too small
+/// to reach the next integer in that range.
+fn div_2q(n: u32) -> u32 {
+    ((u64::from(n) * DIV_2Q_MULTIPLIER) >> DIV_2Q_SHIFT) as u32
+}
+
+fn compress(f: &mut Poly, d: usize) {
+    let mask = (1u32 << d) - 1;
+    for c in f.iter_mut() {
+        // round(2^d * c / q), computed without division of a fraction.
+        *c = (div_2q((u32::from(*c) << (d + 1)) + Q) & mask) as u16;
+    }
+}
+
+fn decompress(f: &mut Poly, d: usize) {
+    for c in f.iter_mut() {
+        *c = ((u32::from(*c) * Q + (1 << (d - 1))) >> d) as u16;
+    }
+}
+
+struct Tables {
+    zetas: [u16; 128],
+    gammas: [u16; 128],
+}
+
+impl Tables {
+    fn new() -> Tables {
+        Tables {
+            zetas: zetas(),
+            gammas: gammas(),
+        }
+    }
+}
+
+/// K-PKE.KeyGen: returns the encoded encryption and decryption keys.
+fn pke_keygen(variant: Variant, d: &[u8; 32], t: &Tables) -> (Vec<u8>, Vec<u8>) {
+    let (rho, sigma) = match variant {
+        Variant::Kyber => sha3_512(&[d]),
+        Variant::MlKem => sha3_512(&[d, &[K as u8]]),
+    };
+
+    let a = sample_matrix(&rho, false);
+
+    let mut nonce = 0;
+    let mut s = [[0u16; N]; K];
+    for si in s.iter_mut() {
+        *si = sample_cbd(&sigma, nonce, ETA1);
+        ntt(si, &t.zetas);
+        nonce += 1;
+    }
+
+    let mut e = [[0u16; N]; K];
+    for ei in e.iter_mut() {
+        *ei = sample_cbd(&sigma, nonce, ETA1);
+        ntt(ei, &t.zetas);
+        nonce += 1;
+    }
+
+    let mut ek = Vec::with_capacity(ENCAPSULATION_KEY_LEN);
+    for i in 0..K {
+        let mut ti = e[i];
+        for j in 0..K {
+            mul_acc_ntt(&mut ti, &a[i][j], &s[j], &t.gammas);
+        }
+        byte_encode(&ti, 12, &mut ek);
+    }
+    ek.extend_from_slice(&rho);
+
+    let mut dk = Vec::with_capacity(384 * K);
+    for si in s.iter() {
+        byte_encode(si, 12, &mut dk);
+    }
+
+    (ek, dk)
+}
+
+/// K-PKE.Encrypt of message `m` under `ek`, using randomness `r`.
+fn pke_encrypt(ek: &[u8], m: &[u8; 32], r: &[u8; 32], t: &Tables) -> Vec<u8> {
+    let mut t_hat = [[0u16; N]; K];
+    for (i, ti) in t_hat.iter_mut().enumerate() {
+        *ti = byte_decode(&ek[384 * i..384 * (i + 1)], 12);
+    }
+    let rho = &ek[384 * K..];
+    let a_t = sample_matrix(rho, true);
+
+    let mut nonce = 0;
+    let mut y = [[0u16; N]; K];
+    for yi in y.iter_mut() {
+        *yi = sample_cbd(r, nonce, ETA1);
+        ntt(yi, &t.zetas);
+        nonce += 1;
+    }
+
+    let mut ct = Vec::with_capacity(CIPHERTEXT_LEN);
+    for i in 0..K {
+        let mut ui = [0u16; N];
+        for j in 0..K {
+            mul_acc_ntt(&mut ui, &a_t[i][j], &y[j], &t.gammas);
+        }
+        inv_ntt(&mut ui, &t.zetas);
+
+        let e1 = sample_cbd(r, nonce, ETA2);
+        nonce += 1;
+        for (c, e) in ui.iter_mut().zip(e1.iter()) {
+            *c = add(*c, *e);
+        }
+
+        compress(&mut ui, DU);
+        byte_encode(&ui, DU, &mut ct);
+    }
+
+    let mut v = [0u16; N];
+    for j in 0..K {
+        mul_acc_ntt(&mut v, &t_hat[j], &y[j], &t.gammas);
+    }
+    inv_ntt(&mut v, &t.zetas);
+
+    let e2 = sample_cbd(r, nonce, ETA2);
+    let mut mu = byte_decode(m, 1);
+    decompress(&mut mu, 1);
+    for i in 0..N {
+        v[i] = add(add(v[i], e2[i]), mu[i]);
+    }
+
+    compress(&mut v, DV);
+    byte_encode(&v, DV, &mut ct);
+    ct
+}
+
+/// K-PKE.Decrypt of `ct` under the encoded decryption key `dk`.
+fn pke_decrypt(dk: &[u8], ct: &[u8], t: &Tables) -> [u8; 32] {
+    let du_len = 32 * DU;
+
+    let mut w = byte_decode(&ct[du_len * K..], DV);
+    decompress(&mut w, DV);
+
+    let mut su = [0u16; N];
+    for i in 0..K {
+        let mut ui = byte_decode(&ct[du_len * i..du_len * (i + 1)], DU);
+        decompress(&mut ui, DU);
+        ntt(&mut ui, &t.zetas);
+
+        let si = byte_decode(&dk[384 * i..384 * (i + 1)], 12);
+        mul_acc_ntt(&mut su, &si, &ui, &t.gammas);
+    }
+    inv_ntt(&mut su, &t.zetas);
+
+    for (c, s) in w.iter_mut().zip(su.iter()) {
+        *c = sub(*c, *s);
+    }
+    compress(&mut w, 1);
+
+    let mut m = Vec::with_capacity(32);
+    byte_encode(&w, 1, &mut m);
+
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&m);
+    out
+}
+
+/// Check `ek` is of the right length, and that its coefficients
+/// are all reduced.
+fn check_encapsulation_key(ek: &[u8]) -> bool {
+    if ek.len() != ENCAPSULATION_KEY_LEN {
+        return false;
+    }
+
+    let mut reencoded = Vec::with_capacity(384 * K);
+    for i in 0..K {
+        let ti = byte_decode(&ek[384 * i..384 * (i + 1)], 12);
+        if ti.iter().any(|c| u32::from(*c) >= Q) {
+            return false;
+        }
+        byte_encode(&ti, 12, &mut reencoded);
+    }
+
+    reencoded[..] == ek[..384 * K]
+}
+
+/// A decapsulation key, which we hold while waiting for a ciphertext.
+pub struct DecapsulationKey {
+    variant: Variant,
+    dk: Vec<u8>,
+}
+
+impl DecapsulationKey {
+    fn pke_dk(&self) -> &[u8] {
+        &self.dk[..384 * K]
+    }
+
+    fn ek(&self) -> &[u8] {
+        &self.dk[384 * K..384 * K + ENCAPSULATION_KEY_LEN]
+    }
+
+    fn ek_hash(&self) -> &[u8] {
+        &self.dk[DECAPSULATION_KEY_LEN - 64..DECAPSULATION_KEY_LEN - 32]
+    }
+
+    fn z(&self) -> &[u8] {
+        &self.dk[DECAPSULATION_KEY_LEN - 32..]
+    }
+
+    /// Recover the shared secret from the ciphertext `ct`.  Returns
+    /// None if `ct` is the wrong length; otherwise an invalid `ct`
+    /// yields an unpredictable secret, which later fails to agree.
+    pub fn decapsulate(&self, ct: &[u8]) -> Option<[u8; SHARED_SECRET_LEN]> {
+        if ct.len() != CIPHERTEXT_LEN {
+            return None;
+        }
+
+        let t = Tables::new();
+        let m = pke_decrypt(self.pke_dk(), ct, &t);
+        let (k, r) = sha3_512(&[&m, self.ek_hash()]);
+        let ct2 = pke_encrypt(self.ek(), &m, &r, &t);
+
+        // Whether `ct` is valid is secret: both outcomes are computed,
+        // and one picked without branching.
+        let valid = ct.ct_eq(&ct2[..]);
+
+        let mut out = [0u8; SHARED_SECRET_LEN];
+        match self.variant {
+            Variant::Kyber => {
+                let k_bar = select(valid, &k, self.z());
+                shake256(&[&k_bar, &sha3_256(&[ct])], &mut out);
+            }
+            Variant::MlKem => {
+                let mut rejected = [0u8; SHARED_SECRET_LEN];
+                shake256(&[self.z(), ct], &mut rejected);
+                out = select(valid, &k, &rejected);
+            }
+        }
+        Some(out)
+    }
+}
+
+/// `a` if `choice` is set, else `b`.
+fn select(choice: Choice, a: &[u8], b: &[u8]) -> [u8; SHARED_SECRET_LEN] {
+    let mut out = [0u8; SHARED_SECRET_LEN];
+    for ((o, a), b) in out.iter_mut().zip(a.iter()).zip(b.iter()) {
+        *o = u8::conditional_select(b, a, choice);
+    }
+    out
+}
+
+fn keygen_from_seeds(variant: Variant, d: &[u8; 32], z: &[u8; 32]) -> (Vec<u8>, DecapsulationKey) {
+    let t = Tables::new();
+    let (ek, mut dk) = pke_keygen(variant, d, &t);
+
+    dk.extend_from_slice(&ek);
+    dk.extend_from_slice(&sha3_256(&[&ek]));
+    dk.extend_from_slice(z);
+
+    (ek, DecapsulationKey { variant, dk })
+}
+
+/// The key pair for `variant` made from the seeds `d` and `z`.
+#[cfg(test)]
+pub(crate) fn key_pair_for_test(variant: Variant, d: &[u8; 32], z: &[u8; 32])
+                                -> (Vec<u8>, DecapsulationKey) {
+    keygen_from_seeds(variant, d, z)
+}
+
+/// Generate a new key pair, returning the encoded encapsulation key
+/// and the decapsulation key.
+pub fn generate_key_pair(variant: Variant) -> (Vec<u8>, DecapsulationKey) {
+    let mut d = [0u8; 32];
+    let mut z = [0u8; 32];
+    rand::fill_random(&RING, &mut d);
+    rand::fill_random(&RING, &mut z);
+    keygen_from_seeds(variant, &d, &z)
+}
+
+pub(crate) fn encapsulate_with_seed(variant: Variant, ek: &[u8], seed: &[u8; 32])
+                                    -> Option<(Vec<u8>, [u8; SHARED_SECRET_LEN])> {
+    if !check_encapsulation_key(ek) {
+        return None;
+    }
+
+    let t = Tables::new();
+    let ek_hash = sha3_256(&[ek]);
+
+    match variant {
+        Variant::Kyber => {
+            let m = sha3_256(&[seed]);
+            let (k, r) = sha3_512(&[&m, &ek_hash]);
+            let ct = pke_encrypt(ek, &m, &r, &t);
+
+            let mut out = [0u8; SHARED_SECRET_LEN];
+            shake256(&[&k, &sha3_256(&[&ct])], &mut out);
+            Some((ct, out))
+        }
+        Variant::MlKem => {
+            let (k, r) = sha3_512(&[seed, &ek_hash]);
+            let ct = pke_encrypt(ek, seed, &r, &t);
+            Some((ct, k))
+        }
+    }
+}
+
+/// Generate a shared secret and encapsulate it to `ek`.  Returns the
+/// ciphertext and the shared secret, or None if `ek` is invalid.
+pub fn encapsulate(variant: Variant, ek: &[u8]) -> Option<(Vec<u8>, [u8; SHARED_SECRET_LEN])> {
+    let mut seed = [0u8; 32];
+    rand::fill_random(&RING, &mut seed);
+    encapsulate_with_seed(variant, ek, &seed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tables() {
+        let t = Tables::new();
+        assert_eq!(&t.zetas[..8], &[ 1, 1729, 2580, 3289, 2642, 630, 1897, 848 ]);
+        assert_eq!(&t.gammas[..4], &[ 17, 3312, 2761, 568 ]);
+    }
+
+    #[test]
+    fn test_reduction() {
+        for a in 0..Q {
+            assert_eq!(u32::from(reduce_once(a)), a);
+            assert_eq!(u32::from(reduce_once(a + Q)), a);
+        }
+
+        for x in (0..Q * Q).step_by(7).chain(Q * Q - 7..Q * Q) {
+            assert_eq!(u32::from(barrett_reduce(x)), x % Q);
+        }
+
+        for a in (0..Q as u16).step_by(13) {
+            for b in (0..Q as u16).step_by(17) {
+                assert_eq!(u32::from(add(a, b)), (u32::from(a) + u32::from(b)) % Q);
+                assert_eq!(u32::from(sub(a, b)), (u32::from(a) + Q - u32::from(b)) % Q);
+                assert_eq!(u32::from(mul(a, b)), u32::from(a) * u32::from(b) % Q);
+            }
+        }
+    }
+
+    #[test]
+    fn test_div_2q() {
+        // compress passes at most ((q - 1) << (DU + 1)) + q.
+        let max = ((Q - 1) << (DU + 1)) + Q;
+        assert!(max < 1 << 23);
+        for n in 0..1 << 23 {
+            assert_eq!(div_2q(n), n / (2 * Q));
+        }
+    }
+
+    #[test]
+    fn test_ntt_roundtrip() {
+        let t = Tables::new();
+        let mut f = [0u16; N];
+        for (i, c) in f.iter_mut().enumerate() {
+            *c = (i as u16 * 13) % 3329;
+        }
+        let orig = f;
+        ntt(&mut f, &t.zetas);
+        assert_ne!(&f[..], &orig[..]);
+        inv_ntt(&mut f, &t.zetas);
+        assert_eq!(&f[..], &orig[..]);
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let mut f = [0u16; N];
+        for (i, c) in f.iter_mut().enumerate() {
+            *c = (i as u16 * 7) % 1024;
+        }
+        let mut enc = Vec::new();
+        byte_encode(&f, 10, &mut enc);
+        assert_eq!(enc.len(), 320);
+        assert_eq!(&byte_decode(&enc, 10)[..], &f[..]);
+    }
+
+    #[test]
+    fn test_kem_agrees() {
+        for variant in &[ Variant::Kyber, Variant::MlKem ] {
+            let (ek, dk) = generate_key_pair(*variant);
+            assert_eq!(ek.len(), ENCAPSULATION_KEY_LEN);
+
+            let (ct, ss) = encapsulate(*variant, &ek).unwrap();
+            assert_eq!(ct.len(), CIPHERTEXT_LEN);
+            assert_eq!(dk.decapsulate(&ct).unwrap(), ss);
+        }
+    }
+
+    #[test]
+    fn test_kem_implicit_rejection() {
+        for variant in &[ Variant::Kyber, Variant::MlKem ] {
+            let (ek, dk) = generate_key_pair(*variant);
+            let (mut ct, ss) = encapsulate(*variant, &ek).unwrap();
+            ct[0] ^= 1;
+            assert_ne!(dk.decapsulate(&ct).unwrap(), ss);
+            assert!(dk.decapsulate(&ct[1..]).is_none());
+        }
+    }
+
+    #[test]
+    fn test_kem_rejects_bad_encapsulation_key() {
+        let (mut ek, _) = generate_key_pair(Variant::MlKem);
+        assert!(encapsulate(Variant::MlKem, &ek[1..]).is_none());
+
+        // set the first coefficient to q, which is unreduced.
+        ek[0] = 0x01;
+        ek[1] = (ek[1] & 0xf0) | 0x0d;
+        assert!(encapsulate(Variant::MlKem, &ek).is_none());
+    }
+
+    #[test]
+    fn test_kem_is_deterministic() {
+        let d = [1u8; 32];
+        let z = [2u8; 32];
+        let seed = [3u8; 32];
+
+        for variant in &[ Variant::Kyber, Variant::MlKem ] {
+            let (ek1, dk) = keygen_from_seeds(*variant, &d, &z);
+            let (ek2, _) = keygen_from_seeds(*variant, &d, &z);
+            assert_eq!(ek1, ek2);
+
+            let (ct1, ss1) = encapsulate_with_seed(*variant, &ek1, &seed).unwrap();
+            let (ct2, ss2) = encapsulate_with_seed(*variant, &ek1, &seed).unwrap();
+            assert_eq!(ct1, ct2);
+            assert_eq!(ss1, ss2);
+            assert_eq!(dk.decapsulate(&ct1).unwrap(), ss1);
+        }
+
+        // The variants differ in seeding, so must produce different keys.
+        let (kyber_ek, _) = keygen_from_seeds(Variant::Kyber, &d, &z);
+        let (mlkem_ek, _) = keygen_from_seeds(Variant::MlKem, &d, &z);
+        assert_ne!(kyber_ek, mlkem_ek);
+    }
+
+    // The ML-KEM-768 known answers below were produced with OpenSSL
+    // 3.5's ML-KEM-768, from the seeds d = 00..1f, z = 20..3f and (for
+    // encapsulation) m = 40..5f.  Long values are given by their
+    // SHA3-256 hash.
+
+    fn counting_seed(start: u8) -> [u8; 32] {
+        let mut seed = [0u8; 32];
+        for (i, b) in seed.iter_mut().enumerate() {
+            *b = start + i as u8;
+        }
+        seed
+    }
+
+    fn corrupt(ct: &[u8]) -> Vec<u8> {
+        let mut ct = ct.to_vec();
+        *ct.last_mut().unwrap() ^= 1;
+        ct
+    }
+
+    #[test]
+    fn test_mlkem768_keygen_kat() {
+        let (ek, dk) = keygen_from_seeds(Variant::MlKem, &counting_seed(0x00),
+                                         &counting_seed(0x20));
+        assert_eq!(ek.len(), ENCAPSULATION_KEY_LEN);
+        assert_eq!(dk.dk.len(), DECAPSULATION_KEY_LEN);
+        assert_eq!(sha3_256(&[&ek]),
+                   hex!("a24e16d8f8f9383a95b77050f4d9fd2f5733eec1d63ef3c23ebf9918173669a7"));
+        assert_eq!(sha3_256(&[&dk.dk]),
+                   hex!("1149f17c3c4ac6ab1e3e2d9d8bd0171355ac0fa31bb8855c48ceade874c0864b"));
+    }
+
+    #[test]
+    fn test_mlkem768_encaps_decaps_kat() {
+        let (ek, dk) = keygen_from_seeds(Variant::MlKem, &counting_seed(0x00),
+                                         &counting_seed(0x20));
+        let (ct, ss) = encapsulate_with_seed(Variant::MlKem, &ek, &counting_seed(0x40)).unwrap();
+        assert_eq!(sha3_256(&[&ct]),
+                   hex!("b4cfbd24cef67afd3764276c6980e0f88f8e9ca57f59b7f12fe1a9c1e72f4710"));
+        assert_eq!(ss,
+                   hex!("9cddd089ffe70e3996e76f7c8d06746df34d07e8657bc0fcf2bb0e1c3084aea1"));
+
+        assert_eq!(dk.decapsulate(&ct).unwrap(), ss);
+        assert_eq!(dk.decapsulate(&corrupt(&ct)).unwrap(),
+                   hex!("1f39ae51991196b33dbc7c6031f9f35fd3347d577ebb4dea93028bcd9ab5dabe"));
+    }
+
+    /// The seeds d, z and m used by count = 0 of the Kyber768 round 3
+    /// KAT file, PQCkemKAT_2400.rsp: the first three outputs of NIST's
+    /// KAT DRBG, seeded with
+    /// 061550234d158c5ec95595fe04ef7a25767f2e24cc2bc479d09d86dc9abcfde7
+    /// 056a8c266f9ef97ed08541dbd2e1ffa1.
+    fn kyber768_kat_seeds() -> ([u8; 32], [u8; 32], [u8; 32]) {
+        (hex!("7c9935a0b07694aa0c6d10e4db6b1add2fd81a25ccb148032dcd739936737f2d"),
+         hex!("8626ed79d451140800e03b59b956f8210e556067407d13dc90fa9e8b872bfb8f"),
+         hex!("147c03f7a5bebba406c8fae1874d7f13c80efe79a3a9a874cc09fe76f6997615"))
+    }
+
+    // PQCkemKAT_2400.rsp, count = 0.  pk, sk and ct are given by
+    // their SHA3-256 hash.
+    #[test]
+    fn test_kyber768_round3_kat() {
+        let (d, z, m) = kyber768_kat_seeds();
+        let (ek, dk) = keygen_from_seeds(Variant::Kyber, &d, &z);
+        assert_eq!(sha3_256(&[&ek]),
+                   hex!("d4ec143b50f01423b177895edee22bb739f647ecf85f50bc25ef7b5a725dee86"));
+        assert_eq!(sha3_256(&[&dk.dk]),
+                   hex!("245bc1d8cdd4893e4c471e8fccfa7019df0fd10f2d5375f36b4af5f4222aca6a"));
+
+        let (ct, ss) = encapsulate_with_seed(Variant::Kyber, &ek, &m).unwrap();
+        assert_eq!(sha3_256(&[&ct]),
+                   hex!("962242140e9b3492476c62847a250a5e425a41ceec123ce0158d601e7af4139e"));
+        assert_eq!(ss,
+                   hex!("914cb67fe5c38e73bf74181c0ac50428dedf7750a98058f7d536708774535b29"));
+
+        assert_eq!(dk.decapsulate(&ct).unwrap(), ss);
+    }
+
+    // Implicit rejection isn't covered by the KAT file: this answer
+    // comes from the round 3 reference implementation, decapsulating
+    // the KAT's ciphertext with its first bit flipped.
+    #[test]
+    fn test_kyber768_implicit_rejection_kat() {
+        let (d, z, m) = kyber768_kat_seeds();
+        let (ek, dk) = keygen_from_seeds(Variant::Kyber, &d, &z);
+        let (mut ct, _) = encapsulate_with_seed(Variant::Kyber, &ek, &m).unwrap();
+        ct[0] ^= 1;
+        assert_eq!(dk.decapsulate(&ct).unwrap(),
+                   hex!("c25ff0d9bc75b80594e9c2ad7eb07d2cba09204d5c7492ec5299f5a992b180f8"));
+    }
+}
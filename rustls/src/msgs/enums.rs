@@ -45,7 +45,9 @@ enum_builder! {
         Anonymous => 0x00,
         RSA => 0x01,
         DSA => 0x02,
-        ECDSA => 0x03
+        ECDSA => 0x03,
+        ED25519 => 0x07,
+        ED448 => 0x08
     }
 }
 
@@ -69,6 +71,19 @@ enum_builder! {
     }
 }
 
+enum_builder! {
+    /// The `CertificateType` TLS protocol enum.  Values in this enum are taken
+    /// from the various RFCs covering TLS, and are listed by IANA.
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U8
+    EnumName: CertificateType;
+    EnumVal{
+        X509 => 0x00,
+        OpenPGP => 0x01,
+        RawPublicKey => 0x02
+    }
+}
+
 enum_builder! {
     /// The `Compression` TLS protocol enum.  Values in this enum are taken
     /// from the various RFCs covering TLS, and are listed by IANA.
@@ -177,7 +192,8 @@ enum_builder! {
         BadCertificateHashValue => 0x72,
         UnknownPSKIdentity => 0x73,
         CertificateRequired => 0x74,
-        NoApplicationProtocol => 0x78
+        NoApplicationProtocol => 0x78,
+        ECHRequired => 0x79
     }
 }
 
@@ -218,6 +234,8 @@ enum_builder! {
         Heartbeat => 0x000f,
         ALProtocolNegotiation => 0x0010,
         SCT => 0x0012,
+        ClientCertificateType => 0x0013,
+        ServerCertificateType => 0x0014,
         Padding => 0x0015,
         ExtendedMasterSecret => 0x0017,
         SessionTicket => 0x0023,
@@ -236,7 +254,8 @@ enum_builder! {
         ChannelId => 0x754f,
         RenegotiationInfo => 0xff01,
         TransportParameters => 0xffa5,
-        EncryptedServerName => 0xffce
+        EncryptedServerName => 0xffce,
+        EncryptedClientHello => 0xfe0d
     }
 }
 
@@ -309,7 +328,9 @@ enum_builder! {
         FFDHE3072 => 0x0101,
         FFDHE4096 => 0x0102,
         FFDHE6144 => 0x0103,
-        FFDHE8192 => 0x0104
+        FFDHE8192 => 0x0104,
+        X25519MLKEM768 => 0x11ec,
+        X25519Kyber768Draft00 => 0x6399
     }
 }
 
@@ -799,3 +820,61 @@ enum_builder! {
         V1 => 0xff01
     }
 }
+
+enum_builder! {
+    /// The `ECH` protocol version.
+    @U16
+    EnumName: ECHVersion;
+    EnumVal{
+        V1 => 0xfe0d
+    }
+}
+
+enum_builder! {
+    /// The type of an `ECH` extension in a ClientHello.
+    @U8
+    EnumName: ECHClientHelloType;
+    EnumVal{
+        ClientHelloOuter => 0x00,
+        ClientHelloInner => 0x01
+    }
+}
+
+enum_builder! {
+    /// The `HPKE` KEM identifiers.  Values in this enum are taken
+    /// from RFC9180, and are listed by IANA.
+    @U16
+    EnumName: HpkeKem;
+    EnumVal{
+        DHKEM_P256_HKDF_SHA256 => 0x0010,
+        DHKEM_P384_HKDF_SHA384 => 0x0011,
+        DHKEM_P521_HKDF_SHA512 => 0x0012,
+        DHKEM_X25519_HKDF_SHA256 => 0x0020,
+        DHKEM_X448_HKDF_SHA512 => 0x0021
+    }
+}
+
+enum_builder! {
+    /// The `HPKE` KDF identifiers.  Values in this enum are taken
+    /// from RFC9180, and are listed by IANA.
+    @U16
+    EnumName: HpkeKdf;
+    EnumVal{
+        HKDF_SHA256 => 0x0001,
+        HKDF_SHA384 => 0x0002,
+        HKDF_SHA512 => 0x0003
+    }
+}
+
+enum_builder! {
+    /// The `HPKE` AEAD identifiers.  Values in this enum are taken
+    /// from RFC9180, and are listed by IANA.
+    @U16
+    EnumName: HpkeAead;
+    EnumVal{
+        AES_128_GCM => 0x0001,
+        AES_256_GCM => 0x0002,
+        CHACHA20_POLY_1305 => 0x0003,
+        EXPORT_ONLY => 0xffff
+    }
+}
@@ -48,8 +48,9 @@ fn test_enum8<T: Codec>(first: T, last: T) {
 fn test_enums() {
     test_enum16::<ProtocolVersion>(ProtocolVersion::SSLv2, ProtocolVersion::TLSv1_3);
     test_enum8::<HashAlgorithm>(HashAlgorithm::NONE, HashAlgorithm::SHA512);
-    test_enum8::<SignatureAlgorithm>(SignatureAlgorithm::Anonymous, SignatureAlgorithm::ECDSA);
+    test_enum8::<SignatureAlgorithm>(SignatureAlgorithm::Anonymous, SignatureAlgorithm::ED448);
     test_enum8::<ClientCertificateType>(ClientCertificateType::RSASign, ClientCertificateType::ECDSAFixedECDH);
+    test_enum8::<CertificateType>(CertificateType::X509, CertificateType::RawPublicKey);
     test_enum8::<Compression>(Compression::Null, Compression::LSZ);
     test_enum8::<ContentType>(ContentType::ChangeCipherSpec, ContentType::Heartbeat);
     test_enum8::<HandshakeType>(HandshakeType::HelloRequest, HandshakeType::MessageHash);
@@ -68,4 +69,7 @@ fn test_enums() {
     test_enum8::<PSKKeyExchangeMode>(PSKKeyExchangeMode::PSK_KE, PSKKeyExchangeMode::PSK_DHE_KE);
     test_enum8::<KeyUpdateRequest>(KeyUpdateRequest::UpdateNotRequested, KeyUpdateRequest::UpdateRequested);
     test_enum8::<CertificateStatusType>(CertificateStatusType::OCSP, CertificateStatusType::OCSP);
+    test_enum8::<ECHClientHelloType>(ECHClientHelloType::ClientHelloOuter,
+                                     ECHClientHelloType::ClientHelloInner);
+    test_enum16::<HpkeKdf>(HpkeKdf::HKDF_SHA256, HpkeKdf::HKDF_SHA512);
 }
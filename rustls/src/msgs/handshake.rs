@@ -3,14 +3,17 @@ use crate::msgs::enums::{CipherSuite, Compression, ExtensionType, ECPointFormat}
 use crate::msgs::enums::{HashAlgorithm, SignatureAlgorithm, ServerNameType};
 use crate::msgs::enums::{SignatureScheme, KeyUpdateRequest, NamedGroup};
 use crate::msgs::enums::{ClientCertificateType, CertificateStatusType};
-use crate::msgs::enums::ESNIVersion;
+use crate::msgs::enums::CertificateType;
+use crate::msgs::enums::{ESNIVersion, ECHVersion, ECHClientHelloType};
+use crate::msgs::enums::{HpkeKem, HpkeKdf, HpkeAead};
 use crate::msgs::enums::ECCurveType;
 use crate::msgs::enums::PSKKeyExchangeMode;
 use crate::msgs::base::{Payload, PayloadU8, PayloadU16, PayloadU24};
 use crate::msgs::codec;
 use crate::msgs::codec::{Codec, Reader};
 use crate::key;
-use crate::esni::*;
+#[cfg(feature = "esni")]
+use crate::esni::{ESNIHandshakeData, ESNIError, compute_esni};
 
 #[cfg(feature = "logging")]
 use crate::log::warn;
@@ -21,6 +24,7 @@ use std::collections;
 use std::mem;
 use ring::digest;
 use webpki;
+#[cfg(feature = "esni")]
 use crate::session::SessionRandoms;
 
 macro_rules! declare_u8_vec(
@@ -211,6 +215,8 @@ impl SupportedPointFormats for ECPointFormatList {
     }
 }
 
+declare_u8_vec!(CertificateTypes, CertificateType);
+
 declare_u16_vec!(NamedGroups, NamedGroup);
 
 declare_u16_vec!(SupportedSignatureSchemes, SignatureScheme);
@@ -233,6 +239,8 @@ impl DecomposedSignatureScheme for SignatureScheme {
             SignatureScheme::ECDSA_NISTP256_SHA256 |
                 SignatureScheme::ECDSA_NISTP384_SHA384 |
                 SignatureScheme::ECDSA_NISTP521_SHA512 => SignatureAlgorithm::ECDSA,
+            SignatureScheme::ED25519 => SignatureAlgorithm::ED25519,
+            SignatureScheme::ED448 => SignatureAlgorithm::ED448,
             _ => SignatureAlgorithm::Unknown(0),
         }
     }
@@ -508,7 +516,7 @@ impl Codec for PaddedServerNameList {
 
     fn read(r: &mut Reader) -> Option<PaddedServerNameList> {
         let count = r.left();
-        let sni = ServerName::read(r)?;
+        let sni = ServerNameRequest::read(r)?;
         let sni_length = count - r.left();
         let mut padding = Vec::with_capacity(r.left());
         padding.extend_from_slice(r.rest());
@@ -520,7 +528,7 @@ impl Codec for PaddedServerNameList {
         }
 
         Some(PaddedServerNameList {
-            sni: vec![sni],
+            sni,
             zeros: padding,
             padded_length: (sni_length + len) as u16,
         })
@@ -550,6 +558,213 @@ impl Codec for ClientESNIInner {
     }
 }
 
+// --- TLS 1.3 Encrypted Client Hello
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HpkeSymmetricCipherSuite {
+    pub kdf: HpkeKdf,
+    pub aead: HpkeAead,
+}
+
+impl Codec for HpkeSymmetricCipherSuite {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.kdf.encode(bytes);
+        self.aead.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<HpkeSymmetricCipherSuite> {
+        Some(HpkeSymmetricCipherSuite {
+            kdf: HpkeKdf::read(r)?,
+            aead: HpkeAead::read(r)?,
+        })
+    }
+}
+
+declare_u16_vec!(HpkeSymmetricCipherSuites, HpkeSymmetricCipherSuite);
+
+#[derive(Clone, Debug)]
+pub struct HpkeKeyConfig {
+    pub config_id: u8,
+    pub kem: HpkeKem,
+    pub public_key: PayloadU16,
+    pub cipher_suites: HpkeSymmetricCipherSuites,
+}
+
+impl Codec for HpkeKeyConfig {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.config_id.encode(bytes);
+        self.kem.encode(bytes);
+        self.public_key.encode(bytes);
+        self.cipher_suites.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<HpkeKeyConfig> {
+        Some(HpkeKeyConfig {
+            config_id: u8::read(r)?,
+            kem: HpkeKem::read(r)?,
+            public_key: PayloadU16::read(r)?,
+            cipher_suites: HpkeSymmetricCipherSuites::read(r)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ECHConfigContents {
+    pub key_config: HpkeKeyConfig,
+    pub maximum_name_length: u8,
+    pub public_name: PayloadU8,
+    pub extensions: PayloadU16,
+}
+
+impl ECHConfigContents {
+    /// Returns true if this config carries an extension we don't
+    /// understand, but must.  Such configs must be skipped.
+    pub fn has_mandatory_extension(&self) -> bool {
+        let mut rd = Reader::init(&self.extensions.0);
+
+        while rd.any_left() {
+            let typ = match u16::read(&mut rd) {
+                Some(typ) => typ,
+                None => return true,
+            };
+
+            if typ & 0x8000 != 0 || PayloadU16::read(&mut rd).is_none() {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+impl Codec for ECHConfigContents {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.key_config.encode(bytes);
+        self.maximum_name_length.encode(bytes);
+        self.public_name.encode(bytes);
+        self.extensions.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<ECHConfigContents> {
+        Some(ECHConfigContents {
+            key_config: HpkeKeyConfig::read(r)?,
+            maximum_name_length: u8::read(r)?,
+            public_name: PayloadU8::read(r)?,
+            extensions: PayloadU16::read(r)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ECHConfig {
+    V1(ECHConfigContents),
+    Unknown(ECHVersion, Payload),
+}
+
+impl ECHConfig {
+    pub fn get_version(&self) -> ECHVersion {
+        match *self {
+            ECHConfig::V1(_) => ECHVersion::V1,
+            ECHConfig::Unknown(version, _) => version,
+        }
+    }
+}
+
+impl Codec for ECHConfig {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.get_version().encode(bytes);
+
+        let mut sub: Vec<u8> = Vec::new();
+        match *self {
+            ECHConfig::V1(ref r) => r.encode(&mut sub),
+            ECHConfig::Unknown(_, ref r) => r.encode(&mut sub),
+        }
+
+        (sub.len() as u16).encode(bytes);
+        bytes.append(&mut sub);
+    }
+
+    fn read(r: &mut Reader) -> Option<ECHConfig> {
+        let version = ECHVersion::read(r)?;
+        let len = u16::read(r)? as usize;
+        let mut sub = r.sub(len)?;
+
+        Some(match version {
+            ECHVersion::V1 => {
+                let contents = ECHConfigContents::read(&mut sub)?;
+                if sub.any_left() {
+                    return None;
+                }
+                ECHConfig::V1(contents)
+            }
+            _ => ECHConfig::Unknown(version, Payload::read(&mut sub)?),
+        })
+    }
+}
+
+declare_u16_vec!(ECHConfigList, ECHConfig);
+
+#[derive(Clone, Debug)]
+pub struct ECHClientHelloOuter {
+    pub cipher_suite: HpkeSymmetricCipherSuite,
+    pub config_id: u8,
+    pub enc: PayloadU16,
+    pub payload: PayloadU16,
+}
+
+impl Codec for ECHClientHelloOuter {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.cipher_suite.encode(bytes);
+        self.config_id.encode(bytes);
+        self.enc.encode(bytes);
+        self.payload.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<ECHClientHelloOuter> {
+        Some(ECHClientHelloOuter {
+            cipher_suite: HpkeSymmetricCipherSuite::read(r)?,
+            config_id: u8::read(r)?,
+            enc: PayloadU16::read(r)?,
+            payload: PayloadU16::read(r)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ECHClientHello {
+    Outer(ECHClientHelloOuter),
+    Inner,
+}
+
+impl ECHClientHello {
+    pub fn get_type(&self) -> ECHClientHelloType {
+        match *self {
+            ECHClientHello::Outer(_) => ECHClientHelloType::ClientHelloOuter,
+            ECHClientHello::Inner => ECHClientHelloType::ClientHelloInner,
+        }
+    }
+}
+
+impl Codec for ECHClientHello {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.get_type().encode(bytes);
+
+        if let ECHClientHello::Outer(ref outer) = *self {
+            outer.encode(bytes);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Option<ECHClientHello> {
+        match ECHClientHelloType::read(r)? {
+            ECHClientHelloType::ClientHelloOuter => {
+                Some(ECHClientHello::Outer(ECHClientHelloOuter::read(r)?))
+            }
+            ECHClientHelloType::ClientHelloInner => Some(ECHClientHello::Inner),
+            _ => None,
+        }
+    }
+}
+
 pub type ProtocolNameList = VecU16OfPayloadU8;
 
 pub trait ConvertProtocolNameList {
@@ -779,6 +994,9 @@ pub enum ClientExtension {
     TransportParameters(Vec<u8>),
     EarlyData,
     EncryptedServerName(ClientEncryptedSNI),
+    EncryptedClientHello(ECHClientHello),
+    ClientCertificateTypes(CertificateTypes),
+    ServerCertificateTypes(CertificateTypes),
     Unknown(UnknownExtension),
 }
 
@@ -803,6 +1021,9 @@ impl ClientExtension {
             ClientExtension::TransportParameters(_) => ExtensionType::TransportParameters,
             ClientExtension::EarlyData => ExtensionType::EarlyData,
             ClientExtension::EncryptedServerName(_) => ExtensionType::EncryptedServerName,
+            ClientExtension::EncryptedClientHello(_) => ExtensionType::EncryptedClientHello,
+            ClientExtension::ClientCertificateTypes(_) => ExtensionType::ClientCertificateType,
+            ClientExtension::ServerCertificateTypes(_) => ExtensionType::ServerCertificateType,
             ClientExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -832,6 +1053,9 @@ impl Codec for ClientExtension {
             ClientExtension::CertificateStatusRequest(ref r) => r.encode(&mut sub),
             ClientExtension::TransportParameters(ref r) => sub.extend_from_slice(r),
             ClientExtension::EncryptedServerName(ref r) => r.encode(&mut sub),
+            ClientExtension::EncryptedClientHello(ref r) => r.encode(&mut sub),
+            ClientExtension::ClientCertificateTypes(ref r) |
+                ClientExtension::ServerCertificateTypes(ref r) => r.encode(&mut sub),
             ClientExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -861,6 +1085,9 @@ impl Codec for ClientExtension {
             ExtensionType::EncryptedServerName => {
                 ClientExtension::EncryptedServerName(ClientEncryptedSNI::read(&mut sub)?)
             }
+            ExtensionType::EncryptedClientHello => {
+                ClientExtension::EncryptedClientHello(ECHClientHello::read(&mut sub)?)
+            }
             ExtensionType::SessionTicket => {
                 if sub.any_left() {
                     ClientExtension::SessionTicketOffer(Payload::read(&mut sub)?)
@@ -900,6 +1127,12 @@ impl Codec for ClientExtension {
             ExtensionType::EarlyData if !sub.any_left() => {
                 ClientExtension::EarlyData
             }
+            ExtensionType::ClientCertificateType => {
+                ClientExtension::ClientCertificateTypes(CertificateTypes::read(&mut sub)?)
+            }
+            ExtensionType::ServerCertificateType => {
+                ClientExtension::ServerCertificateTypes(CertificateTypes::read(&mut sub)?)
+            }
             _ => ClientExtension::Unknown(UnknownExtension::read(typ, &mut sub)?),
         })
     }
@@ -917,12 +1150,14 @@ impl ClientExtension {
     }
 
     /// Make an ESNI request, encrypting `hostname` with the ESNIRecord
+    #[cfg(feature = "esni")]
     pub fn make_esni(dns_name: webpki::DNSNameRef,
                      hs_data: &ESNIHandshakeData,
                      key_share_bytes: Vec<u8>,
-                     randoms: &SessionRandoms) -> Option<ClientExtension> {
-        let esni = compute_esni(dns_name, hs_data, key_share_bytes, randoms)?;
-        Some(ClientExtension::EncryptedServerName(esni))
+                     randoms: &SessionRandoms,
+                     nonce: [u8; 16]) -> Result<ClientExtension, ESNIError> {
+        let esni = compute_esni(dns_name, hs_data, key_share_bytes, randoms, nonce)?;
+        Ok(ClientExtension::EncryptedServerName(esni))
     }
 }
 
@@ -941,6 +1176,10 @@ pub enum ServerExtension {
     SupportedVersions(ProtocolVersion),
     TransportParameters(Vec<u8>),
     EarlyData,
+    EncryptedServerName([u8; 16]),
+    EncryptedClientHello(ECHConfigList),
+    ClientCertificateType(CertificateType),
+    ServerCertificateType(CertificateType),
     Unknown(UnknownExtension),
 }
 
@@ -960,6 +1199,10 @@ impl ServerExtension {
             ServerExtension::SupportedVersions(_) => ExtensionType::SupportedVersions,
             ServerExtension::TransportParameters(_) => ExtensionType::TransportParameters,
             ServerExtension::EarlyData => ExtensionType::EarlyData,
+            ServerExtension::EncryptedServerName(_) => ExtensionType::EncryptedServerName,
+            ServerExtension::EncryptedClientHello(_) => ExtensionType::EncryptedClientHello,
+            ServerExtension::ClientCertificateType(_) => ExtensionType::ClientCertificateType,
+            ServerExtension::ServerCertificateType(_) => ExtensionType::ServerCertificateType,
             ServerExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -984,6 +1227,10 @@ impl Codec for ServerExtension {
             ServerExtension::SignedCertificateTimestamp(ref r) => r.encode(&mut sub),
             ServerExtension::SupportedVersions(ref r) => r.encode(&mut sub),
             ServerExtension::TransportParameters(ref r) => sub.extend_from_slice(r),
+            ServerExtension::EncryptedServerName(ref r) => sub.extend_from_slice(r),
+            ServerExtension::EncryptedClientHello(ref r) => r.encode(&mut sub),
+            ServerExtension::ClientCertificateType(ref r) |
+                ServerExtension::ServerCertificateType(ref r) => r.encode(&mut sub),
             ServerExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -1027,6 +1274,20 @@ impl Codec for ServerExtension {
                 ServerExtension::TransportParameters(sub.rest().to_vec())
             }
             ExtensionType::EarlyData => ServerExtension::EarlyData,
+            ExtensionType::EncryptedServerName => {
+                let mut nonce = [0u8; 16];
+                nonce.clone_from_slice(sub.take(16)?);
+                ServerExtension::EncryptedServerName(nonce)
+            }
+            ExtensionType::EncryptedClientHello => {
+                ServerExtension::EncryptedClientHello(ECHConfigList::read(&mut sub)?)
+            }
+            ExtensionType::ClientCertificateType => {
+                ServerExtension::ClientCertificateType(CertificateType::read(&mut sub)?)
+            }
+            ExtensionType::ServerCertificateType => {
+                ServerExtension::ServerCertificateType(CertificateType::read(&mut sub)?)
+            }
             _ => ServerExtension::Unknown(UnknownExtension::read(typ, &mut sub)?),
         })
     }
@@ -1049,7 +1310,7 @@ impl ServerExtension {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ClientHelloPayload {
     pub client_version: ProtocolVersion,
     pub random: Random,
@@ -1120,6 +1381,22 @@ impl ClientHelloPayload {
         }
     }
 
+    pub fn get_esni_extension(&self) -> Option<&ClientEncryptedSNI> {
+        let ext = self.find_extension(ExtensionType::EncryptedServerName)?;
+        match *ext {
+            ClientExtension::EncryptedServerName(ref esni) => Some(esni),
+            _ => None,
+        }
+    }
+
+    pub fn get_ech_extension(&self) -> Option<&ECHClientHello> {
+        let ext = self.find_extension(ExtensionType::EncryptedClientHello)?;
+        match *ext {
+            ClientExtension::EncryptedClientHello(ref ech) => Some(ech),
+            _ => None,
+        }
+    }
+
     pub fn get_sigalgs_extension(&self) -> Option<&SupportedSignatureSchemes> {
         let ext = self.find_extension(ExtensionType::SignatureAlgorithms)?;
         match *ext {
@@ -1152,6 +1429,22 @@ impl ClientHelloPayload {
         }
     }
 
+    pub fn get_client_certificate_types(&self) -> Option<&CertificateTypes> {
+        let ext = self.find_extension(ExtensionType::ClientCertificateType)?;
+        match *ext {
+            ClientExtension::ClientCertificateTypes(ref types) => Some(types),
+            _ => None,
+        }
+    }
+
+    pub fn get_server_certificate_types(&self) -> Option<&CertificateTypes> {
+        let ext = self.find_extension(ExtensionType::ServerCertificateType)?;
+        match *ext {
+            ClientExtension::ServerCertificateTypes(ref types) => Some(types),
+            _ => None,
+        }
+    }
+
     pub fn get_quic_params_extension(&self) -> Option<Vec<u8>> {
         let ext = self.find_extension(ExtensionType::TransportParameters)?;
         match *ext {
@@ -1253,6 +1546,7 @@ pub enum HelloRetryExtension {
     KeyShare(NamedGroup),
     Cookie(PayloadU16),
     SupportedVersions(ProtocolVersion),
+    EncryptedClientHello([u8; 8]),
     Unknown(UnknownExtension),
 }
 
@@ -1262,6 +1556,7 @@ impl HelloRetryExtension {
             HelloRetryExtension::KeyShare(_) => ExtensionType::KeyShare,
             HelloRetryExtension::Cookie(_) => ExtensionType::Cookie,
             HelloRetryExtension::SupportedVersions(_) => ExtensionType::SupportedVersions,
+            HelloRetryExtension::EncryptedClientHello(_) => ExtensionType::EncryptedClientHello,
             HelloRetryExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -1276,6 +1571,7 @@ impl Codec for HelloRetryExtension {
             HelloRetryExtension::KeyShare(ref r) => r.encode(&mut sub),
             HelloRetryExtension::Cookie(ref r) => r.encode(&mut sub),
             HelloRetryExtension::SupportedVersions(ref r) => r.encode(&mut sub),
+            HelloRetryExtension::EncryptedClientHello(ref r) => sub.extend_from_slice(r),
             HelloRetryExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -1298,6 +1594,11 @@ impl Codec for HelloRetryExtension {
             ExtensionType::SupportedVersions => {
                 HelloRetryExtension::SupportedVersions(ProtocolVersion::read(&mut sub)?)
             }
+            ExtensionType::EncryptedClientHello => {
+                let mut confirmation = [0u8; 8];
+                confirmation.clone_from_slice(sub.take(8)?);
+                HelloRetryExtension::EncryptedClientHello(confirmation)
+            }
             _ => HelloRetryExtension::Unknown(UnknownExtension::read(typ, &mut sub)?),
         })
     }
@@ -1363,7 +1664,8 @@ impl HelloRetryRequest {
             .any(|ext| {
                  ext.get_type() != ExtensionType::KeyShare &&
                  ext.get_type() != ExtensionType::SupportedVersions &&
-                 ext.get_type() != ExtensionType::Cookie
+                 ext.get_type() != ExtensionType::Cookie &&
+                 ext.get_type() != ExtensionType::EncryptedClientHello
                  })
     }
 
@@ -1394,6 +1696,14 @@ impl HelloRetryRequest {
             _ => None,
         }
     }
+
+    pub fn get_ech_confirmation(&self) -> Option<&[u8; 8]> {
+        let ext = self.find_extension(ExtensionType::EncryptedClientHello)?;
+        match *ext {
+            HelloRetryExtension::EncryptedClientHello(ref confirmation) => Some(confirmation),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -1733,7 +2043,7 @@ impl CertificatePayloadTLS13 {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum KeyExchangeAlgorithm {
     BulkOnly,
     DH,
@@ -1857,6 +2167,82 @@ impl Codec for ServerECDHParams {
     }
 }
 
+#[derive(Debug)]
+pub struct ClientDHParams {
+    pub public: PayloadU16,
+}
+
+impl Codec for ClientDHParams {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.public.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<ClientDHParams> {
+        let pb = PayloadU16::read(r)?;
+        Some(ClientDHParams { public: pb })
+    }
+}
+
+#[derive(Debug)]
+pub struct ServerDHParams {
+    pub dh_p: PayloadU16,
+    pub dh_g: PayloadU16,
+    pub dh_ys: PayloadU16,
+}
+
+impl ServerDHParams {
+    pub fn new(p: &[u8], g: &[u8], pubkey: &[u8]) -> ServerDHParams {
+        ServerDHParams {
+            dh_p: PayloadU16::new(p.to_vec()),
+            dh_g: PayloadU16::new(g.to_vec()),
+            dh_ys: PayloadU16::new(pubkey.to_vec()),
+        }
+    }
+}
+
+impl Codec for ServerDHParams {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.dh_p.encode(bytes);
+        self.dh_g.encode(bytes);
+        self.dh_ys.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<ServerDHParams> {
+        let dh_p = PayloadU16::read(r)?;
+        let dh_g = PayloadU16::read(r)?;
+        let dh_ys = PayloadU16::read(r)?;
+
+        Some(ServerDHParams {
+            dh_p,
+            dh_g,
+            dh_ys,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct DHEServerKeyExchange {
+    pub params: ServerDHParams,
+    pub dss: DigitallySignedStruct,
+}
+
+impl Codec for DHEServerKeyExchange {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.params.encode(bytes);
+        self.dss.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<DHEServerKeyExchange> {
+        let params = ServerDHParams::read(r)?;
+        let dss = DigitallySignedStruct::read(r)?;
+
+        Some(DHEServerKeyExchange {
+            params,
+            dss,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct ECDHEServerKeyExchange {
     pub params: ServerECDHParams,
@@ -1883,6 +2269,7 @@ impl Codec for ECDHEServerKeyExchange {
 #[derive(Debug)]
 pub enum ServerKeyExchangePayload {
     ECDHE(ECDHEServerKeyExchange),
+    DHE(DHEServerKeyExchange),
     Unknown(Payload),
 }
 
@@ -1890,6 +2277,7 @@ impl Codec for ServerKeyExchangePayload {
     fn encode(&self, bytes: &mut Vec<u8>) {
         match *self {
             ServerKeyExchangePayload::ECDHE(ref x) => x.encode(bytes),
+            ServerKeyExchangePayload::DHE(ref x) => x.encode(bytes),
             ServerKeyExchangePayload::Unknown(ref x) => x.encode(bytes),
         }
     }
@@ -1911,6 +2299,10 @@ impl ServerKeyExchangePayload {
                     ECDHEServerKeyExchange::read(&mut rd)
                         .and_then(|x| Some(ServerKeyExchangePayload::ECDHE(x)))
                 }
+                KeyExchangeAlgorithm::DHE => {
+                    DHEServerKeyExchange::read(&mut rd)
+                        .and_then(|x| Some(ServerKeyExchangePayload::DHE(x)))
+                }
                 _ => None,
             };
 
@@ -1925,14 +2317,17 @@ impl ServerKeyExchangePayload {
     pub fn encode_params(&self, bytes: &mut Vec<u8>) {
         bytes.clear();
 
-        if let ServerKeyExchangePayload::ECDHE(ref x) = *self {
-            x.params.encode(bytes);
+        match *self {
+            ServerKeyExchangePayload::ECDHE(ref x) => x.params.encode(bytes),
+            ServerKeyExchangePayload::DHE(ref x) => x.params.encode(bytes),
+            _ => {}
         }
     }
 
     pub fn get_sig(&self) -> Option<DigitallySignedStruct> {
         match *self {
             ServerKeyExchangePayload::ECDHE(ref x) => Some(x.dss.clone()),
+            ServerKeyExchangePayload::DHE(ref x) => Some(x.dss.clone()),
             _ => None,
         }
     }
@@ -1984,6 +2379,39 @@ pub trait HasServerExtensions {
     fn early_data_extension_offered(&self) -> bool {
         self.find_extension(ExtensionType::EarlyData).is_some()
     }
+
+    fn get_client_certificate_type(&self) -> Option<CertificateType> {
+        let ext = self.find_extension(ExtensionType::ClientCertificateType)?;
+        match *ext {
+            ServerExtension::ClientCertificateType(typ) => Some(typ),
+            _ => None,
+        }
+    }
+
+    fn get_server_certificate_type(&self) -> Option<CertificateType> {
+        let ext = self.find_extension(ExtensionType::ServerCertificateType)?;
+        match *ext {
+            ServerExtension::ServerCertificateType(typ) => Some(typ),
+            _ => None,
+        }
+    }
+
+    fn get_ech_retry_configs(&self) -> Option<&ECHConfigList> {
+        let ext = self.find_extension(ExtensionType::EncryptedClientHello)?;
+        match *ext {
+            ServerExtension::EncryptedClientHello(ref configs) => Some(configs),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "esni")]
+    fn get_esni_nonce(&self) -> Option<&[u8; 16]> {
+        let ext = self.find_extension(ExtensionType::EncryptedServerName)?;
+        match *ext {
+            ServerExtension::EncryptedServerName(ref nonce) => Some(nonce),
+            _ => None,
+        }
+    }
 }
 
 impl HasServerExtensions for EncryptedExtensions {
@@ -2313,6 +2741,7 @@ pub enum HandshakePayload {
     HelloRetryRequest(HelloRetryRequest),
     Certificate(CertificatePayload),
     CertificateTLS13(CertificatePayloadTLS13),
+    RawPublicKey(PayloadU24),
     ServerKeyExchange(ServerKeyExchangePayload),
     CertificateRequest(CertificateRequestPayload),
     CertificateRequestTLS13(CertificateRequestPayloadTLS13),
@@ -2343,6 +2772,7 @@ impl HandshakePayload {
             HandshakePayload::HelloRetryRequest(ref x) => x.encode(bytes),
             HandshakePayload::Certificate(ref x) => x.encode(bytes),
             HandshakePayload::CertificateTLS13(ref x) => x.encode(bytes),
+            HandshakePayload::RawPublicKey(ref x) => x.encode(bytes),
             HandshakePayload::ServerKeyExchange(ref x) => x.encode(bytes),
             HandshakePayload::ClientKeyExchange(ref x) => x.encode(bytes),
             HandshakePayload::CertificateRequest(ref x) => x.encode(bytes),
@@ -2360,6 +2790,30 @@ impl HandshakePayload {
     }
 }
 
+/// Read the body of a TLS1.2 Certificate message.  If raw public
+/// keys (RFC7250) were negotiated, this is a single SubjectPublicKeyInfo
+/// rather than a list of certificates.
+///
+/// We can tell which without knowing what was negotiated: the DER
+/// SEQUENCE header at the start of a SubjectPublicKeyInfo, read as the
+/// length of the first certificate in a list, is always too long.
+fn read_certificate_tls12(body: &[u8]) -> Option<HandshakePayload> {
+    let mut rd = Reader::init(body);
+    if let Some(chain) = CertificatePayload::read(&mut rd) {
+        if !rd.any_left() {
+            return Some(HandshakePayload::Certificate(chain));
+        }
+    }
+
+    let mut rd = Reader::init(body);
+    let spki = PayloadU24::read(&mut rd)?;
+    if spki.0.is_empty() || rd.any_left() {
+        return None;
+    }
+
+    Some(HandshakePayload::RawPublicKey(spki))
+}
+
 #[derive(Debug)]
 pub struct HandshakeMessagePayload {
     pub typ: HandshakeType,
@@ -2423,9 +2877,7 @@ impl HandshakeMessagePayload {
                 let p = CertificatePayloadTLS13::read(&mut sub)?;
                 HandshakePayload::CertificateTLS13(p)
             }
-            HandshakeType::Certificate => {
-                HandshakePayload::Certificate(CertificatePayload::read(&mut sub)?)
-            }
+            HandshakeType::Certificate => read_certificate_tls12(sub.rest())?,
             HandshakeType::ServerKeyExchange => {
                 let p = ServerKeyExchangePayload::read(&mut sub)?;
                 HandshakePayload::ServerKeyExchange(p)
@@ -2517,6 +2969,40 @@ impl HandshakeMessagePayload {
         ret
     }
 
+    /// Encoding of a ServerHello or HelloRetryRequest with the ECH
+    /// acceptance confirmation replaced by zeros.
+    pub fn get_encoding_for_ech_confirmation(&self) -> Vec<u8> {
+        let mut ret = self.get_encoding();
+
+        let start = match self.payload {
+            // Handshake header, legacy_version, then the first 24 bytes
+            // of the random.
+            HandshakePayload::ServerHello(_) => 4 + 2 + 24,
+            HandshakePayload::HelloRetryRequest(ref hrr) => {
+                let mut exts = Vec::new();
+                let mut offset = None;
+
+                for ext in &hrr.extensions {
+                    if ext.get_type() == ExtensionType::EncryptedClientHello {
+                        offset = Some(exts.len() + 4);
+                    }
+                    ext.encode(&mut exts);
+                }
+
+                match offset {
+                    Some(offset) => ret.len() - exts.len() + offset,
+                    None => return ret,
+                }
+            }
+            _ => return ret,
+        };
+
+        for byte in &mut ret[start..start + 8] {
+            *byte = 0;
+        }
+        ret
+    }
+
     pub fn build_handshake_hash(hash: &[u8]) -> HandshakeMessagePayload {
         HandshakeMessagePayload {
             typ: HandshakeType::MessageHash,
@@ -377,6 +377,18 @@ fn get_sample_clienthellopayload() -> ClientHelloPayload {
             ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()),
             ClientExtension::SignedCertificateTimestampRequest,
             ClientExtension::TransportParameters(vec![ 1, 2, 3 ]),
+            ClientExtension::ClientCertificateTypes(vec![ CertificateType::RawPublicKey ]),
+            ClientExtension::ServerCertificateTypes(vec![ CertificateType::RawPublicKey,
+                                                          CertificateType::X509 ]),
+            ClientExtension::EncryptedClientHello(ECHClientHello::Outer(ECHClientHelloOuter {
+                cipher_suite: HpkeSymmetricCipherSuite {
+                    kdf: HpkeKdf::HKDF_SHA256,
+                    aead: HpkeAead::AES_128_GCM,
+                },
+                config_id: 1,
+                enc: PayloadU16(vec![ 1, 2, 3 ]),
+                payload: PayloadU16(vec![ 4, 5, 6 ]),
+            })),
             ClientExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![ 1, 2, 3 ])
@@ -486,6 +498,12 @@ fn client_get_psk_modes() {
                                  |chp| chp.get_psk_modes().is_some());
 }
 
+#[test]
+fn client_get_ech_extension() {
+    test_client_extension_getter(ExtensionType::EncryptedClientHello,
+                                 |chp| chp.get_ech_extension().is_some());
+}
+
 fn test_helloretry_extension_getter(typ: ExtensionType, getter: fn(&HelloRetryRequest) -> bool) {
     let mut hrr = get_sample_helloretryrequest();
     let mut exts = mem::replace(&mut hrr.extensions, vec![]);
@@ -523,6 +541,12 @@ fn helloretry_get_supported_versions() {
                                      |hrr| hrr.get_supported_versions().is_some());
 }
 
+#[test]
+fn helloretry_get_ech_confirmation() {
+    test_helloretry_extension_getter(ExtensionType::EncryptedClientHello,
+                                     |hrr| hrr.get_ech_confirmation().is_some());
+}
+
 fn test_server_extension_getter(typ: ExtensionType, getter: fn(&ServerHelloPayload) -> bool) {
     let mut shp = get_sample_serverhellopayload();
     let ext = shp.find_extension(typ).unwrap().clone();
@@ -572,6 +596,12 @@ fn server_get_supported_versions() {
                                  |shp| shp.get_supported_versions().is_some());
 }
 
+#[test]
+fn server_get_ech_retry_configs() {
+    test_server_extension_getter(ExtensionType::EncryptedClientHello,
+                                 |shp| shp.get_ech_retry_configs().is_some());
+}
+
 fn test_cert_extension_getter(typ: ExtensionType, getter: fn(&CertificateEntry) -> bool) {
     let mut ce = get_sample_certificatepayloadtls13().entries.remove(0);
     let mut exts = mem::replace(&mut ce.exts, vec![]);
@@ -623,6 +653,10 @@ fn get_sample_serverhellopayload() -> ServerHelloPayload {
             ServerExtension::SignedCertificateTimestamp(vec![ PayloadU16(vec![0]) ]),
             ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_2),
             ServerExtension::TransportParameters(vec![ 1, 2, 3 ]),
+            ServerExtension::EncryptedServerName([ 4; 16 ]),
+            ServerExtension::EncryptedClientHello(get_sample_echconfiglist()),
+            ServerExtension::ClientCertificateType(CertificateType::RawPublicKey),
+            ServerExtension::ServerCertificateType(CertificateType::X509),
             ServerExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![ 1, 2, 3 ])
@@ -650,6 +684,7 @@ fn get_sample_helloretryrequest() -> HelloRetryRequest {
             HelloRetryExtension::KeyShare(NamedGroup::X25519),
             HelloRetryExtension::Cookie(PayloadU16(vec![ 0 ])),
             HelloRetryExtension::SupportedVersions(ProtocolVersion::TLSv1_2),
+            HelloRetryExtension::EncryptedClientHello([ 7; 8 ]),
             HelloRetryExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![ 1, 2, 3 ])
@@ -695,6 +730,20 @@ fn get_sample_serverkeyexchangepayload_ecdhe() -> ServerKeyExchangePayload {
     })
 }
 
+fn get_sample_serverkeyexchangepayload_dhe() -> ServerKeyExchangePayload {
+    ServerKeyExchangePayload::DHE(DHEServerKeyExchange {
+        params: ServerDHParams {
+            dh_p: PayloadU16(vec![ 1, 2, 3 ]),
+            dh_g: PayloadU16(vec![ 2 ]),
+            dh_ys: PayloadU16(vec![ 4, 5, 6 ])
+        },
+        dss: DigitallySignedStruct {
+            scheme: SignatureScheme::RSA_PSS_SHA256,
+            sig: PayloadU16(vec![ 1, 2, 3 ])
+        }
+    })
+}
+
 fn get_sample_serverkeyexchangepayload_unknown() -> ServerKeyExchangePayload {
     ServerKeyExchangePayload::Unknown(Payload(vec![ 1, 2, 3 ]))
 }
@@ -776,10 +825,18 @@ fn can_roundtrip_all_tls12_handshake_payloads() {
             typ: HandshakeType::Certificate,
             payload: HandshakePayload::Certificate(vec![ Certificate(vec![ 1, 2, 3 ]) ]),
         },
+        HandshakeMessagePayload {
+            typ: HandshakeType::Certificate,
+            payload: HandshakePayload::RawPublicKey(PayloadU24(vec![ 0x30, 0x03, 1, 2, 3 ])),
+        },
         HandshakeMessagePayload {
             typ: HandshakeType::ServerKeyExchange,
             payload: HandshakePayload::ServerKeyExchange(get_sample_serverkeyexchangepayload_ecdhe()),
         },
+        HandshakeMessagePayload {
+            typ: HandshakeType::ServerKeyExchange,
+            payload: HandshakePayload::ServerKeyExchange(get_sample_serverkeyexchangepayload_dhe()),
+        },
         HandshakeMessagePayload {
             typ: HandshakeType::ServerKeyExchange,
             payload: HandshakePayload::ServerKeyExchange(get_sample_serverkeyexchangepayload_unknown()),
@@ -939,3 +996,91 @@ fn test_esni() {
     record.encode(&mut output);
     assert_eq!(base64_esni, base64::encode(&output));
 }
+
+#[test]
+fn test_client_esni_inner_roundtrip() {
+    let dns_name = DNSNameRef::try_from_ascii_str("example.com").unwrap();
+    let sni = vec![ ServerName {
+        typ: ServerNameType::HostName,
+        payload: ServerNamePayload::HostName(dns_name.into()),
+    } ];
+    let inner = ClientESNIInner {
+        nonce: [ 1; 16 ],
+        real_sni: PaddedServerNameList::new(sni, 260),
+    };
+
+    let bytes = inner.get_encoding();
+    assert_eq!(bytes.len(), 16 + 260);
+
+    let other = ClientESNIInner::read(&mut Reader::init(&bytes)).unwrap();
+    assert_eq!(other.nonce, [ 1; 16 ]);
+    assert_eq!(other.real_sni.padded_length, 260);
+    let name: &str = other.real_sni.sni.get_hostname().unwrap().into();
+    assert_eq!(name, "example.com");
+    assert_eq!(other.get_encoding(), bytes);
+}
+
+fn get_sample_echconfiglist() -> ECHConfigList {
+    vec![
+        ECHConfig::V1(ECHConfigContents {
+            key_config: HpkeKeyConfig {
+                config_id: 1,
+                kem: HpkeKem::DHKEM_X25519_HKDF_SHA256,
+                public_key: PayloadU16(vec![ 2; 32 ]),
+                cipher_suites: vec![
+                    HpkeSymmetricCipherSuite {
+                        kdf: HpkeKdf::HKDF_SHA256,
+                        aead: HpkeAead::AES_128_GCM,
+                    },
+                ],
+            },
+            maximum_name_length: 64,
+            public_name: PayloadU8(b"example.com".to_vec()),
+            extensions: PayloadU16(vec![]),
+        }),
+        ECHConfig::Unknown(ECHVersion::Unknown(0xfe0c), Payload(vec![ 1, 2, 3 ])),
+    ]
+}
+
+#[test]
+fn test_ech_config_list_roundtrip() {
+    let list = get_sample_echconfiglist();
+    let bytes = list.get_encoding();
+
+    let other = ECHConfigList::read(&mut Reader::init(&bytes)).unwrap();
+    assert_eq!(other.len(), 2);
+    assert_eq!(other[0].get_version(), ECHVersion::V1);
+    assert_eq!(other[1].get_version(), ECHVersion::Unknown(0xfe0c));
+    assert_eq!(other.get_encoding(), bytes);
+}
+
+#[test]
+fn test_ech_client_hello_inner_roundtrip() {
+    let ext = ClientExtension::EncryptedClientHello(ECHClientHello::Inner);
+    let bytes = ext.get_encoding();
+    assert_eq!(bytes, vec![ 0xfe, 0x0d, 0x00, 0x01, 0x01 ]);
+
+    let other = ClientExtension::read(&mut Reader::init(&bytes)).unwrap();
+    assert_eq!(other.get_encoding(), bytes);
+}
+
+#[test]
+fn server_key_exchange_is_decoded_given_kxa() {
+    for (sample, kxa, other_kxa) in vec![
+        (get_sample_serverkeyexchangepayload_ecdhe(), KeyExchangeAlgorithm::ECDHE, KeyExchangeAlgorithm::DHE),
+        (get_sample_serverkeyexchangepayload_dhe(), KeyExchangeAlgorithm::DHE, KeyExchangeAlgorithm::ECDHE),
+    ] {
+        let bytes = sample.get_encoding();
+        let opaque = ServerKeyExchangePayload::read_bytes(&bytes).unwrap();
+
+        let decoded = opaque.unwrap_given_kxa(&kxa).unwrap();
+        assert_eq!(decoded.get_encoding(), bytes);
+        assert!(decoded.get_sig().is_some());
+
+        let mut params = Vec::new();
+        decoded.encode_params(&mut params);
+        assert_eq!(params, bytes[..bytes.len() - 7].to_vec());
+
+        assert!(opaque.unwrap_given_kxa(&other_kxa).is_none());
+    }
+}
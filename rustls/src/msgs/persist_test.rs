@@ -2,15 +2,24 @@ use super::persist::*;
 use super::handshake::*;
 use super::enums::*;
 use super::codec::{Reader, Codec};
-use webpki::DNSNameRef;
+use crate::server_name::ServerName;
 
 #[test]
 fn clientsessionkey_is_debug() {
-    let name = DNSNameRef::try_from_ascii_str("hello").unwrap();
-    let csk = ClientSessionKey::session_for_dns_name(name);
+    let name = ServerName::try_from_ascii_str("hello").unwrap();
+    let csk = ClientSessionKey::session_for_server_name(&name);
     println!("{:?}", csk);
 }
 
+#[test]
+fn clientsessionkey_distinguishes_kind_and_name() {
+    let name = ServerName::try_from_ascii_str("hello").unwrap();
+    let ip = ServerName::try_from_ascii_str("10.0.0.5").unwrap();
+    let session = ClientSessionKey::session_for_server_name(&name).get_encoding();
+    assert_ne!(session, ClientSessionKey::hint_for_server_name(&name).get_encoding());
+    assert_ne!(session, ClientSessionKey::session_for_server_name(&ip).get_encoding());
+}
+
 #[test]
 fn clientsessionkey_cannot_be_read() {
     let bytes = [0; 1];
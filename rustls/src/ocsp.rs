@@ -0,0 +1,499 @@
+// Verification of stapled OCSP responses (RFC 6960).
+//
+// webpki doesn't deal with OCSP, so we parse responses ourselves
+// using the DER helpers in x509.rs.  We only accept what RFC 6960
+// calls the id-pkix-ocsp-basic response type, which is all anyone
+// sends in practice.
+
+use ring::digest;
+use ring::io::der;
+use webpki;
+
+use crate::error::TLSError;
+use crate::x509::{self, CertInfo};
+
+/// What to do about stapled OCSP responses when verifying a server's
+/// certificate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OCSPPolicy {
+    /// Don't look at stapled responses at all.
+    Ignore,
+
+    /// Fail if a valid stapled response says the certificate is
+    /// revoked.  Missing, invalid or stale responses, and ones
+    /// which don't know about the certificate, are logged and then
+    /// ignored -- unless the certificate has the TLS feature
+    /// extension requesting stapling (RFC 7633), in which case this
+    /// acts like `Require`.
+    SoftFail,
+
+    /// Require a valid, fresh stapled response which says the
+    /// certificate is good.  This is sometimes called "must-staple".
+    Require,
+}
+
+/// What a verified OCSP response says about a certificate.
+#[derive(Debug, PartialEq)]
+pub enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+/// How far apart we allow our clock and the responder's to be.
+const ALLOWED_CLOCK_SKEW: u64 = 5 * 60;
+
+const TAG_ENUMERATED: u8 = 0x0a;
+const TAG_INTEGER: u8 = der::Tag::Integer as u8;
+const TAG_OCTET_STRING: u8 = der::Tag::OctetString as u8;
+const TAG_OID: u8 = der::Tag::OID as u8;
+const TAG_SEQUENCE: u8 = der::Tag::Sequence as u8;
+const TAG_CONTEXT_0: u8 = der::Tag::ContextSpecificConstructed0 as u8;
+const TAG_CONTEXT_1: u8 = der::Tag::ContextSpecificConstructed1 as u8;
+const TAG_CONTEXT_2: u8 = 0xa2;
+
+const STATUS_GOOD: u8 = 0x80;
+const STATUS_REVOKED: u8 = 0xa1;
+const STATUS_UNKNOWN: u8 = 0x82;
+
+const OID_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+const OID_OCSP_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
+const OID_TLS_FEATURE: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x18];
+const OID_SCT_LIST: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x05];
+
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const OID_SHA384: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02];
+const OID_SHA512: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03];
+
+/// The TLS feature `status_request` value, from RFC 7633.
+const TLS_FEATURE_STATUS_REQUEST: &[u8] = &[0x05];
+
+/// Does `cert` ask for its OCSP status to always be stapled?
+pub fn must_staple(cert: &CertInfo) -> bool {
+    let mut features = match cert.extension(OID_TLS_FEATURE)
+        .and_then(|mut ext| x509::read_der(&mut ext, TAG_SEQUENCE)) {
+        Some(features) => features,
+        None => return false,
+    };
+
+    while let Some(feature) = x509::read_der(&mut features, TAG_INTEGER) {
+        if feature == TLS_FEATURE_STATUS_REQUEST {
+            return true;
+        }
+    }
+
+    false
+}
+
+fn invalid(why: &str) -> TLSError {
+    TLSError::InvalidOCSPResponse(why.to_string())
+}
+
+fn malformed() -> TLSError {
+    invalid("malformed response")
+}
+
+fn time(secs: u64) -> webpki::Time {
+    webpki::Time::from_seconds_since_unix_epoch(secs)
+}
+
+/// The parts of a `BasicOCSPResponse` we need.
+struct BasicResponse<'a> {
+    tbs: &'a [u8],
+    signature_alg: &'a [u8],
+    signature: &'a [u8],
+    certs: &'a [u8],
+    responder_id: (u8, &'a [u8]),
+    responses: &'a [u8],
+}
+
+/// The parts of a `SingleResponse` we need.
+struct SingleResponse<'a> {
+    cert_id: &'a [u8],
+    status: CertStatus,
+    this_update: u64,
+    next_update: Option<u64>,
+    extensions: &'a [u8],
+}
+
+fn parse_single(mut single: &[u8]) -> Option<SingleResponse> {
+    let cert_id = x509::read_der(&mut single, TAG_SEQUENCE)?;
+
+    let status = if x509::read_der(&mut single, STATUS_GOOD).is_some() {
+        CertStatus::Good
+    } else if x509::read_der(&mut single, STATUS_REVOKED).is_some() {
+        CertStatus::Revoked
+    } else if x509::read_der(&mut single, STATUS_UNKNOWN).is_some() {
+        CertStatus::Unknown
+    } else {
+        return None;
+    };
+
+    let this_update = x509::read_time(&mut single)?;
+    let next_update = match x509::read_der(&mut single, TAG_CONTEXT_0) {
+        Some(mut next_update) => Some(x509::read_time(&mut next_update)?),
+        None => None,
+    };
+    let extensions = match x509::read_der(&mut single, TAG_CONTEXT_1) {
+        Some(mut extensions) => x509::read_der(&mut extensions, TAG_SEQUENCE)?,
+        None => &[],
+    };
+
+    Some(SingleResponse {
+        cert_id,
+        status,
+        this_update,
+        next_update,
+        extensions,
+    })
+}
+
+fn parse_response(response: &[u8]) -> Option<Result<BasicResponse, TLSError>> {
+    let mut input = response;
+    let mut outer = x509::read_der(&mut input, TAG_SEQUENCE)?;
+    if !input.is_empty() {
+        return None;
+    }
+
+    match x509::read_der(&mut outer, TAG_ENUMERATED)? {
+        [0] => {}
+        _ => return Some(Err(invalid("unsuccessful response status"))),
+    }
+
+    let mut bytes = x509::read_der(&mut outer, TAG_CONTEXT_0)?;
+    let mut bytes = x509::read_der(&mut bytes, TAG_SEQUENCE)?;
+    if x509::read_der(&mut bytes, TAG_OID)? != OID_OCSP_BASIC {
+        return Some(Err(invalid("unsupported response type")));
+    }
+    let mut basic = x509::read_der(&mut bytes, TAG_OCTET_STRING)?;
+    let mut basic = x509::read_der(&mut basic, TAG_SEQUENCE)?;
+
+    let tbs = x509::read_der_value(&mut basic, TAG_SEQUENCE)?;
+    let signature_alg = x509::read_der(&mut basic, TAG_SEQUENCE)?;
+    let signature = x509::read_bit_string(&mut basic)?;
+    let certs = match x509::read_der(&mut basic, TAG_CONTEXT_0) {
+        Some(mut certs) => x509::read_der(&mut certs, TAG_SEQUENCE)?,
+        None => &[],
+    };
+
+    let mut data = &tbs[..];
+    let mut data = x509::read_der(&mut data, TAG_SEQUENCE)?;
+    if let Some(version) = x509::read_der(&mut data, TAG_CONTEXT_0) {
+        if version != [TAG_INTEGER, 0x01, 0x00] {
+            return None;
+        }
+    }
+
+    let responder_id = if let Some(mut by_name) = x509::read_der(&mut data, TAG_CONTEXT_1) {
+        (TAG_CONTEXT_1, x509::read_der_value(&mut by_name, TAG_SEQUENCE)?)
+    } else {
+        let mut by_key = x509::read_der(&mut data, TAG_CONTEXT_2)?;
+        (TAG_CONTEXT_2, x509::read_der(&mut by_key, TAG_OCTET_STRING)?)
+    };
+
+    x509::read_time(&mut data)?;
+    let responses = x509::read_der(&mut data, TAG_SEQUENCE)?;
+
+    Some(Ok(BasicResponse {
+        tbs,
+        signature_alg,
+        signature,
+        certs,
+        responder_id,
+        responses,
+    }))
+}
+
+/// Does the `ResponderID` identify the holder of `name` and `spki`?
+fn is_responder(responder_id: (u8, &[u8]), name: &[u8], spki: &[u8]) -> bool {
+    match responder_id {
+        (TAG_CONTEXT_1, by_name) => by_name == name,
+        (_, by_key) => x509::spki_public_key(spki)
+            .map(|key| digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, key))
+            .map_or(false, |hash| hash.as_ref() == by_key),
+    }
+}
+
+/// Find who signed `response`: either the issuer itself, or
+/// a responder certificate it issued for the purpose.  Returns
+/// the signer's DER-encoded SubjectPublicKeyInfo.
+fn find_signer<'a>(response: &BasicResponse<'a>,
+                   issuer_name: &'a [u8],
+                   issuer_spki: &'a [u8],
+                   now: webpki::Time) -> Result<&'a [u8], TLSError> {
+    if is_responder(response.responder_id, issuer_name, issuer_spki) {
+        return Ok(issuer_spki);
+    }
+
+    let mut certs = response.certs;
+    while let Some(der) = x509::read_der_value(&mut certs, TAG_SEQUENCE) {
+        let cert = CertInfo::parse(der)
+            .ok_or_else(malformed)?;
+
+        if !is_responder(response.responder_id, cert.subject, cert.spki) {
+            continue;
+        }
+
+        if cert.issuer != issuer_name ||
+            x509::verify_signed_data(issuer_spki, cert.signature_alg,
+                                     cert.tbs, cert.signature).is_err() {
+            return Err(invalid("responder certificate not issued by issuer"));
+        }
+
+        if !cert.has_eku(OID_OCSP_SIGNING) {
+            return Err(invalid("responder certificate not authorized for OCSP signing"));
+        }
+
+        if now < time(cert.not_before) || now > time(cert.not_after) {
+            return Err(invalid("responder certificate expired or not yet valid"));
+        }
+
+        return Ok(cert.spki);
+    }
+
+    Err(invalid("unknown responder"))
+}
+
+/// Does `cert_id` identify `cert`, issued by the holder of
+/// `issuer_name` and `issuer_spki`?
+fn cert_id_matches(mut cert_id: &[u8],
+                   cert: &CertInfo,
+                   issuer_name: &[u8],
+                   issuer_spki: &[u8]) -> Option<bool> {
+    let mut alg = x509::read_der(&mut cert_id, TAG_SEQUENCE)?;
+    let name_hash = x509::read_der(&mut cert_id, TAG_OCTET_STRING)?;
+    let key_hash = x509::read_der(&mut cert_id, TAG_OCTET_STRING)?;
+    let serial = x509::read_der(&mut cert_id, TAG_INTEGER)?;
+
+    let hash = match x509::read_der(&mut alg, TAG_OID)? {
+        OID_SHA1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        OID_SHA256 => &digest::SHA256,
+        OID_SHA384 => &digest::SHA384,
+        OID_SHA512 => &digest::SHA512,
+        _ => return Some(false),
+    };
+
+    let issuer_key = x509::spki_public_key(issuer_spki)?;
+    Some(serial == cert.serial &&
+         digest::digest(hash, issuer_name).as_ref() == name_hash &&
+         digest::digest(hash, issuer_key).as_ref() == key_hash)
+}
+
+/// Verify `response` is a fresh, authentic OCSP response about
+/// `cert`, and return what it says.
+///
+/// `issuer_name` and `issuer_spki` are the DER-encoded subject and
+/// SubjectPublicKeyInfo of `cert`'s issuer.  `cert` must already
+/// have been validated.
+pub fn check_response(response: &[u8],
+                      cert: &CertInfo,
+                      issuer_name: &[u8],
+                      issuer_spki: &[u8],
+                      now: webpki::Time) -> Result<CertStatus, TLSError> {
+    let response = parse_response(response)
+        .ok_or_else(malformed)??;
+
+    let signer = find_signer(&response, issuer_name, issuer_spki, now)?;
+    x509::verify_signed_data(signer, response.signature_alg,
+                             response.tbs, response.signature)
+        .map_err(|_| invalid("bad signature"))?;
+
+    let mut responses = response.responses;
+    while let Some(single) = x509::read_der(&mut responses, TAG_SEQUENCE) {
+        let single = parse_single(single).ok_or_else(malformed)?;
+        if !cert_id_matches(single.cert_id, cert, issuer_name, issuer_spki)
+            .ok_or_else(malformed)? {
+            continue;
+        }
+
+        if now < time(single.this_update.saturating_sub(ALLOWED_CLOCK_SKEW)) {
+            return Err(invalid("response is not yet valid"));
+        }
+
+        // Responses without a nextUpdate claim that newer information
+        // is always available, so we can't know how long they last.
+        // Treat them as stale.
+        match single.next_update {
+            Some(next_update) if now <= time(next_update + ALLOWED_CLOCK_SKEW) => {}
+            _ => return Err(invalid("response is stale")),
+        }
+
+        return Ok(single.status);
+    }
+
+    Err(invalid("response does not cover certificate"))
+}
+
+/// Return the time, in seconds since the Unix epoch, after which
+/// `response` should no longer be stapled: the earliest nextUpdate
+/// of the `SingleResponse`s it contains.
+///
+/// This is for servers, and doesn't check the response's signature;
+/// that's the client's job.
+pub fn next_update(response: &[u8]) -> Result<u64, TLSError> {
+    let response = parse_response(response)
+        .ok_or_else(malformed)??;
+
+    let mut earliest = None;
+    let mut responses = response.responses;
+    while let Some(single) = x509::read_der(&mut responses, TAG_SEQUENCE) {
+        let single = parse_single(single).ok_or_else(malformed)?;
+        let next_update = single.next_update
+            .ok_or_else(|| invalid("response has no nextUpdate"))?;
+        earliest = Some(earliest.map_or(next_update, |e: u64| e.min(next_update)));
+    }
+
+    earliest.ok_or_else(malformed)
+}
+
+/// Return the SignedCertificateTimestampLists (RFC 6962) in
+/// `response`'s `SingleResponse`s, still TLS-encoded.
+///
+/// The response's signature isn't checked: SCTs are signed by
+/// their logs, so it doesn't matter who delivers them.
+pub fn sct_lists(response: &[u8]) -> Vec<&[u8]> {
+    let mut lists = Vec::new();
+
+    let response = match parse_response(response) {
+        Some(Ok(response)) => response,
+        _ => return lists,
+    };
+
+    let mut responses = response.responses;
+    while let Some(single) = x509::read_der(&mut responses, TAG_SEQUENCE) {
+        if let Some(mut list) = parse_single(single)
+            .and_then(|single| x509::find_extension(single.extensions, OID_SCT_LIST)) {
+            if let Some(list) = x509::read_der(&mut list, TAG_OCTET_STRING) {
+                lists.push(list);
+            }
+        }
+    }
+
+    lists
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CA: &[u8] = include_bytes!("testdata/ocsp-ca.der");
+    const END: &[u8] = include_bytes!("testdata/ocsp-end.der");
+    const MUST_STAPLE: &[u8] = include_bytes!("testdata/ocsp-must-staple.der");
+
+    // All the responses have a thisUpdate of 2020-06-01, and a
+    // nextUpdate a week later.
+    const JUNE_2ND: u64 = 1_591_056_000;
+
+    fn check_at(response: &[u8], cert: &[u8], now: u64) -> Result<CertStatus, TLSError> {
+        let ca = CertInfo::parse(CA).unwrap();
+        let cert = CertInfo::parse(cert).unwrap();
+        check_response(response, &cert, ca.subject, ca.spki, time(now))
+    }
+
+    fn check(response: &[u8]) -> Result<CertStatus, TLSError> {
+        check_at(response, END, JUNE_2ND)
+    }
+
+    fn expect_invalid(result: Result<CertStatus, TLSError>, why: &str) {
+        assert_eq!(result, Err(TLSError::InvalidOCSPResponse(why.to_string())));
+    }
+
+    #[test]
+    fn test_statuses() {
+        assert_eq!(check(include_bytes!("testdata/ocsp-good.der")), Ok(CertStatus::Good));
+        assert_eq!(check(include_bytes!("testdata/ocsp-revoked.der")), Ok(CertStatus::Revoked));
+        assert_eq!(check(include_bytes!("testdata/ocsp-unknown.der")), Ok(CertStatus::Unknown));
+        assert_eq!(check(include_bytes!("testdata/ocsp-good-sha256-certid.der")),
+                   Ok(CertStatus::Good));
+    }
+
+    #[test]
+    fn test_freshness() {
+        let good = include_bytes!("testdata/ocsp-good.der");
+        let day = 24 * 60 * 60;
+        assert_eq!(check_at(good, END, JUNE_2ND + 6 * day), Ok(CertStatus::Good));
+        expect_invalid(check_at(good, END, JUNE_2ND + 7 * day), "response is stale");
+        expect_invalid(check_at(good, END, JUNE_2ND - 2 * day), "response is not yet valid");
+    }
+
+    #[test]
+    fn test_delegated_responder() {
+        assert_eq!(check(include_bytes!("testdata/ocsp-good-delegated.der")),
+                   Ok(CertStatus::Good));
+        expect_invalid(check(include_bytes!("testdata/ocsp-good-not-responder.der")),
+                       "responder certificate not authorized for OCSP signing");
+        expect_invalid(check(include_bytes!("testdata/ocsp-good-rogue.der")),
+                       "responder certificate not issued by issuer");
+    }
+
+    #[test]
+    fn test_bad_responses() {
+        let mut corrupt = include_bytes!("testdata/ocsp-good.der").to_vec();
+        let len = corrupt.len();
+        corrupt[len - 10] ^= 1;
+        expect_invalid(check(&corrupt), "bad signature");
+
+        expect_invalid(check(include_bytes!("testdata/ocsp-good-other-cert.der")),
+                       "response does not cover certificate");
+        expect_invalid(check(include_bytes!("testdata/ocsp-try-later.der")),
+                       "unsuccessful response status");
+        expect_invalid(check(b"\x30\x03\x0a\x01"), "malformed response");
+        expect_invalid(check(b""), "malformed response");
+    }
+
+    #[test]
+    fn test_next_update() {
+        let june_8th = JUNE_2ND + 6 * 24 * 60 * 60;
+        assert_eq!(next_update(include_bytes!("testdata/ocsp-good.der")), Ok(june_8th));
+        assert_eq!(next_update(include_bytes!("testdata/ocsp-revoked.der")), Ok(june_8th));
+        assert_eq!(next_update(include_bytes!("testdata/ocsp-try-later.der")),
+                   Err(invalid("unsuccessful response status")));
+        assert_eq!(next_update(b""), Err(malformed()));
+    }
+
+    fn der(tag: u8, parts: &[&[u8]]) -> Vec<u8> {
+        let mut value = parts.concat();
+        x509::wrap_in_tag(&mut value, tag);
+        value
+    }
+
+    #[test]
+    fn test_sct_lists() {
+        let sct_list = [0x00, 0x04, 0x00, 0x02, 0xaa, 0xbb];
+        let update = der(0x18, &[b"20200601000000Z"]);
+        let extension = der(TAG_SEQUENCE, &[
+            &der(TAG_OID, &[OID_SCT_LIST]),
+            &der(TAG_OCTET_STRING, &[&der(TAG_OCTET_STRING, &[&sct_list])]),
+        ]);
+        let single = der(TAG_SEQUENCE, &[
+            &der(TAG_SEQUENCE, &[]),
+            &der(STATUS_GOOD, &[]),
+            &update,
+            &der(TAG_CONTEXT_1, &[&der(TAG_SEQUENCE, &[&extension])]),
+        ]);
+        let tbs = der(TAG_SEQUENCE, &[
+            &der(TAG_CONTEXT_2, &[&der(TAG_OCTET_STRING, &[])]),
+            &update,
+            &der(TAG_SEQUENCE, &[&single]),
+        ]);
+        let basic = der(TAG_SEQUENCE, &[&tbs, &der(TAG_SEQUENCE, &[]), &[0x03, 0x01, 0x00]]);
+        let response = der(TAG_SEQUENCE, &[
+            &der(TAG_ENUMERATED, &[&[0]]),
+            &der(TAG_CONTEXT_0, &[&der(TAG_SEQUENCE, &[
+                &der(TAG_OID, &[OID_OCSP_BASIC]),
+                &der(TAG_OCTET_STRING, &[&basic]),
+            ])]),
+        ]);
+
+        assert_eq!(sct_lists(&response), vec![&sct_list[..]]);
+        assert!(sct_lists(include_bytes!("testdata/ocsp-good.der")).is_empty());
+        assert!(sct_lists(b"").is_empty());
+    }
+
+    #[test]
+    fn test_must_staple() {
+        assert!(!must_staple(&CertInfo::parse(END).unwrap()));
+        assert!(must_staple(&CertInfo::parse(MUST_STAPLE).unwrap()));
+    }
+}
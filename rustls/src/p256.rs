@@ -0,0 +1,184 @@
+// NIST P-256 key agreement with long-lived private keys.
+//
+// Like `x25519`, this exists because ring only offers ephemeral key
+// agreement, and ESNI servers need to hold the same key for the
+// lifetime of a published record.  The arithmetic is the RustCrypto
+// `p256` crate's.
+
+use ::p256::{PublicKey, SecretKey};
+use ::p256::ecdh::diffie_hellman;
+use ::p256::elliptic_curve::sec1::ToEncodedPoint;
+
+/// Length of P-256 scalars, field elements and shared secrets.
+pub const P256_SCALAR_LEN: usize = 32;
+
+/// Length of an uncompressed P-256 point.
+pub const P256_POINT_LEN: usize = 1 + 2 * P256_SCALAR_LEN;
+
+/// Is `scalar` a valid private key, ie. in [1, n)?
+pub fn is_valid_scalar(scalar: &[u8; P256_SCALAR_LEN]) -> bool {
+    SecretKey::from_slice(scalar).is_ok()
+}
+
+/// Compute the uncompressed public key for the private key `scalar`.
+/// Returns None if `scalar` isn't valid.
+pub fn public_key(scalar: &[u8; P256_SCALAR_LEN]) -> Option<Vec<u8>> {
+    let secret = SecretKey::from_slice(scalar).ok()?;
+    Some(secret.public_key()
+         .to_encoded_point(false)
+         .as_bytes()
+         .to_vec())
+}
+
+/// Agree a shared secret (the x coordinate) between our private key
+/// `scalar` and the peer's uncompressed public key `peer`.  Returns
+/// None if either is invalid.
+pub fn agree(scalar: &[u8; P256_SCALAR_LEN], peer: &[u8]) -> Option<[u8; P256_SCALAR_LEN]> {
+    // TLS only uses uncompressed points.
+    if peer.len() != P256_POINT_LEN || peer[0] != 0x04 {
+        return None;
+    }
+
+    let secret = SecretKey::from_slice(scalar).ok()?;
+    let peer = PublicKey::from_sec1_bytes(peer).ok()?;
+    let shared = diffie_hellman(secret.to_nonzero_scalar(), peer.as_affine());
+
+    let mut out = [0u8; P256_SCALAR_LEN];
+    out.copy_from_slice(shared.raw_secret_bytes());
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn arr(v: &[u8]) -> [u8; P256_SCALAR_LEN] {
+        let mut r = [0u8; P256_SCALAR_LEN];
+        r.copy_from_slice(v);
+        r
+    }
+
+    fn point(x: &[u8], y: &[u8]) -> Vec<u8> {
+        let mut r = vec![ 0x04 ];
+        r.extend_from_slice(x);
+        r.extend_from_slice(y);
+        r
+    }
+
+    #[test]
+    fn base_point() {
+        let mut one = [0u8; P256_SCALAR_LEN];
+        one[31] = 1;
+        assert_eq!(public_key(&one).unwrap(),
+                   hex!("04
+                         6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296
+                         4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5").to_vec());
+    }
+
+    #[test]
+    fn rfc5903_diffie_hellman() {
+        let i = hex!("c88f01f510d9ac3f70a292daa2316de544e9aab8afe84049c62a9c57862d1433");
+        let r = hex!("c6ef9c5d78ae012a011164acb397ce2088685d8f06bf9be0b283ab46476bee53");
+        let gi = public_key(&arr(&i)).unwrap();
+        let gr = public_key(&arr(&r)).unwrap();
+        assert_eq!(gi,
+                   hex!("04
+                         dad0b65394221cf9b051e1feca5787d098dfe637fc90b9ef945d0c3772581180
+                         5271a0461cdb8252d61f1c456fa3e59ab1f45b33accf5f58389e0577b8990bb3").to_vec());
+        assert_eq!(gr,
+                   hex!("04
+                         d12dfb5289c8d4f81208b70270398c342296970a0bccb74c736fc7554494bf63
+                         56fbf3ca366cc23e8157854c13c58d6aac23f046ada30f8353e74f33039872ab").to_vec());
+
+        let shared = hex!("d6840f6b42f6edafd13116e0e12565202fef8e9ece7dce03812464d04b9442de");
+        assert_eq!(agree(&arr(&i), &gr).unwrap(), shared);
+        assert_eq!(agree(&arr(&r), &gi).unwrap(), shared);
+    }
+
+    // NIST CAVS 14.1 ECC CDH primitive tests (KAS_ECC_CDH_PrimitiveTest.txt),
+    // P-256 COUNT = 0 and 1.
+    #[test]
+    fn nist_cdh_primitive() {
+        let vectors = [
+            (hex!("700c48f77f56584c5cc632ca65640db91b6bacce3a4df6b42ce7cc838833d287"),
+             hex!("db71e509e3fd9b060ddb20ba5c51dcc5948d46fbf640dfe0441782cab85fa4ac"),
+             hex!("7d7dc5f71eb29ddaf80d6214632eeae03d9058af1fb6d22ed80badb62bc1a534"),
+             hex!("ead218590119e8876b29146ff89ca61770c4edbbf97d38ce385ed281d8a6b230"),
+             hex!("28af61281fd35e2fa7002523acc85a429cb06ee6648325389f59edfce1405141"),
+             hex!("46fc62106420ff012e54a434fbdd2d25ccc5852060561e68040dd7778997bd7b")),
+            (hex!("809f04289c64348c01515eb03d5ce7ac1a8cb9498f5caa50197e58d43a86a7ae"),
+             hex!("b29d84e811197f25eba8f5194092cb6ff440e26d4421011372461f579271cda3"),
+             hex!("38f65d6dce47676044d58ce5139582d568f64bb16098d179dbab07741dd5caf5"),
+             hex!("119f2f047902782ab0c9e27a54aff5eb9b964829ca99c06b02ddba95b0a3f6d0"),
+             hex!("8f52b726664cac366fc98ac7a012b2682cbd962e5acb544671d41b9445704d1d"),
+             hex!("057d636096cb80b67a8c038c890e887d1adfa4195e9b3ce241c8a778c59cda67")),
+        ];
+
+        for (qcavs_x, qcavs_y, d_iut, qiut_x, qiut_y, z_iut) in vectors.iter() {
+            assert_eq!(public_key(d_iut).unwrap(), point(qiut_x, qiut_y));
+            assert_eq!(&agree(d_iut, &point(qcavs_x, qcavs_y)).unwrap(), z_iut);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_scalars() {
+        let n = hex!("ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551");
+        assert!(public_key(&[0u8; P256_SCALAR_LEN]).is_none());
+        assert!(public_key(&n).is_none());
+        assert!(public_key(&[0xffu8; P256_SCALAR_LEN]).is_none());
+
+        let mut n_minus_1 = n;
+        n_minus_1[31] -= 1;
+        assert!(public_key(&n_minus_1).is_some());
+    }
+
+    // Invalid public keys, as in Wycheproof's ecdh_secp256r1_ecpoint_test.json.
+    #[test]
+    fn rejects_bad_points() {
+        let p = hex!("ffffffff00000001000000000000000000000000ffffffffffffffffffffffff");
+        let k = [0x42u8; P256_SCALAR_LEN];
+        let good = public_key(&k).unwrap();
+        assert!(agree(&k, &good).is_some());
+        assert!(agree(&k, &good[1..]).is_none());
+        assert!(agree(&k, &good[..64]).is_none());
+
+        // The point at infinity.
+        assert!(agree(&k, &[ 0x00 ]).is_none());
+        assert!(agree(&k, &[0u8; P256_POINT_LEN]).is_none());
+        assert!(agree(&k, &point(&[0u8; 32], &[0u8; 32])).is_none());
+
+        // Not on the curve.
+        let mut off_curve = good.clone();
+        off_curve[64] ^= 1;
+        assert!(agree(&k, &off_curve).is_none());
+
+        // Compressed and hybrid points aren't accepted.
+        let mut compressed = vec![ 0x02 | (good[64] & 1) ];
+        compressed.extend_from_slice(&good[1..33]);
+        assert!(agree(&k, &compressed).is_none());
+        let mut hybrid = good.clone();
+        hybrid[0] = 0x06 | (good[64] & 1);
+        assert!(agree(&k, &hybrid).is_none());
+
+        // Nor are unreduced coordinates (x = p, or y = p).
+        assert!(agree(&k, &point(&p, &[0u8; 32])).is_none());
+        assert!(agree(&k, &point(&good[1..33], &p)).is_none());
+    }
+
+    #[test]
+    fn agrees_with_ring() {
+        let ours = [0x42u8; P256_SCALAR_LEN];
+        let our_pub = public_key(&ours).unwrap();
+
+        let rng = ring::rand::SystemRandom::new();
+        let theirs = ring::agreement::EphemeralPrivateKey::generate(&ring::agreement::ECDH_P256, &rng)
+            .unwrap();
+        let their_pub = theirs.compute_public_key().unwrap();
+
+        let peer = ring::agreement::UnparsedPublicKey::new(&ring::agreement::ECDH_P256, &our_pub[..]);
+        let ring_secret = ring::agreement::agree_ephemeral(theirs, &peer, (), |s| Ok(s.to_vec()))
+            .unwrap();
+
+        assert_eq!(agree(&ours, their_pub.as_ref()).unwrap().to_vec(), ring_secret);
+    }
+}
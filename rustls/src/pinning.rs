@@ -0,0 +1,120 @@
+// Public key pinning.
+//
+// A pin is the SHA-256 hash of a DER-encoded SubjectPublicKeyInfo,
+// as in HPKP (RFC 7469).  Pinning keys rather than certificates
+// means pins survive certificate renewal, as long as the key is
+// kept.
+
+use ring::digest;
+
+use crate::error::TLSError;
+use crate::key::Certificate;
+use crate::x509;
+#[cfg(feature = "logging")]
+use crate::log::debug;
+
+/// The SHA-256 hash of a DER-encoded SubjectPublicKeyInfo.
+pub type SPKIHash = [u8; 32];
+
+/// Hash `spki`, which is a DER-encoded SubjectPublicKeyInfo.
+pub(crate) fn hash_spki(spki: &[u8]) -> SPKIHash {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(digest::digest(&digest::SHA256, spki).as_ref());
+    hash
+}
+
+/// The public keys one hostname is pinned to.
+///
+/// A certificate chain matches if any certificate in it -- the
+/// end-entity, an intermediate, or the trust anchor -- has one of
+/// these keys.
+///
+/// Backup pins are for keys which aren't in use yet, but which you
+/// intend to move to; they are accepted just like other pins.  Always
+/// have at least one, so losing a key doesn't lock out your clients.
+#[derive(Debug, Clone, Default)]
+pub struct PinSet {
+    pins: Vec<SPKIHash>,
+    backup_pins: Vec<SPKIHash>,
+}
+
+impl PinSet {
+    /// Make a new, empty `PinSet`.
+    pub fn new() -> PinSet {
+        PinSet::default()
+    }
+
+    /// Compute the pin for the public key in `cert`.
+    pub fn spki_hash(cert: &Certificate) -> Result<SPKIHash, TLSError> {
+        x509::CertInfo::parse(&cert.0)
+            .map(|cert| hash_spki(cert.spki))
+            .ok_or(TLSError::WebPKIError(webpki::Error::BadDER))
+    }
+
+    /// Pin a key in use now.
+    pub fn add_pin(&mut self, pin: SPKIHash) -> &mut PinSet {
+        self.pins.push(pin);
+        self
+    }
+
+    /// Pin a key which isn't in use yet.
+    pub fn add_backup_pin(&mut self, pin: SPKIHash) -> &mut PinSet {
+        self.backup_pins.push(pin);
+        self
+    }
+
+    /// Return true if there are no pins at all.
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty() && self.backup_pins.is_empty()
+    }
+
+    /// Does the key with SubjectPublicKeyInfo `spki` match this set?
+    pub(crate) fn matches(&self, spki: &[u8]) -> bool {
+        let hash = hash_spki(spki);
+        if self.pins.contains(&hash) {
+            return true;
+        }
+
+        if self.backup_pins.contains(&hash) {
+            debug!("Certificate chain matched a backup pin");
+            return true;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_spki_hash() {
+        let cert = Certificate(include_bytes!("testdata/cert-github.0.der").to_vec());
+        let info = x509::CertInfo::parse(&cert.0).unwrap();
+        let pin = PinSet::spki_hash(&cert).unwrap();
+        assert_eq!(&pin[..], digest::digest(&digest::SHA256, info.spki).as_ref());
+
+        assert!(PinSet::spki_hash(&Certificate(vec![0x30, 0x00])).is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let cert = include_bytes!("testdata/cert-github.0.der");
+        let other = include_bytes!("testdata/cert-github.1.der");
+        let spki = x509::CertInfo::parse(cert).unwrap().spki;
+        let other_spki = x509::CertInfo::parse(other).unwrap().spki;
+
+        let mut pins = PinSet::new();
+        assert!(pins.is_empty());
+        assert!(!pins.matches(spki));
+
+        pins.add_pin(hash_spki(other_spki));
+        assert!(!pins.is_empty());
+        assert!(!pins.matches(spki));
+        assert!(pins.matches(other_spki));
+
+        pins.add_backup_pin(hash_spki(spki));
+        assert!(pins.matches(spki));
+    }
+}
@@ -1,42 +1,22 @@
-use ring::digest;
-use ring::hmac;
+use crate::crypto::Hmac;
 
 use std::io::Write;
 
-fn convert_digest_to_hmac_alg(hash: &'static digest::Algorithm) -> hmac::Algorithm {
-    if hash == &digest::SHA256 {
-        hmac::HMAC_SHA256
-    } else if hash == &digest::SHA384 {
-        hmac::HMAC_SHA384
-    } else if hash == &digest::SHA512 {
-        hmac::HMAC_SHA512
-    } else {
-        panic!("bad digest for prf");
-    }
-}
-
-fn concat_sign(key: &hmac::Key, a: &[u8], b: &[u8]) -> hmac::Tag {
-    let mut ctx = hmac::Context::with_key(key);
-    ctx.update(a);
-    ctx.update(b);
-    ctx.sign()
-}
-
-fn p(out: &mut [u8], hashalg: &'static digest::Algorithm, secret: &[u8], seed: &[u8]) {
-    let hmac_key = hmac::Key::new(convert_digest_to_hmac_alg(hashalg), secret);
+fn p(out: &mut [u8], hmac: &dyn Hmac, secret: &[u8], seed: &[u8]) {
+    let hmac_key = hmac.with_key(secret);
 
     // A(1)
-    let mut current_a = hmac::sign(&hmac_key, seed);
+    let mut current_a = hmac_key.sign(&[seed]);
 
     let mut offs = 0;
 
     while offs < out.len() {
         // P_hash[i] = HMAC_hash(secret, A(i) + seed)
-        let p_term = concat_sign(&hmac_key, current_a.as_ref(), seed);
-        offs += out[offs..].as_mut().write(p_term.as_ref()).unwrap();
+        let p_term = hmac_key.sign(&[&current_a, seed]);
+        offs += out[offs..].as_mut().write(&p_term).unwrap();
 
         // A(i+1) = HMAC_hash(secret, A(i))
-        current_a = hmac::sign(&hmac_key, current_a.as_ref());
+        current_a = hmac_key.sign(&[&current_a]);
     }
 }
 
@@ -48,17 +28,18 @@ fn concat(a: &[u8], b: &[u8]) -> Vec<u8> {
 }
 
 pub fn prf(out: &mut [u8],
-           hashalg: &'static digest::Algorithm,
+           hmac: &dyn Hmac,
            secret: &[u8],
            label: &[u8],
            seed: &[u8]) {
     let joined_seed = concat(label, seed);
-    p(out, hashalg, secret, &joined_seed);
+    p(out, hmac, secret, &joined_seed);
 }
 
 #[cfg(test)]
 mod tests {
-    use ring::digest::{SHA256, SHA512};
+    use crate::crypto::{CryptoProvider, HashAlgorithm};
+    use crate::crypto::ring::RING;
 
     #[test]
     fn check_sha256() {
@@ -68,7 +49,7 @@ mod tests {
         let expect = include_bytes!("testdata/prf-result.1.bin");
         let mut output = [0u8; 100];
 
-        super::prf(&mut output, &SHA256, secret, label, seed);
+        super::prf(&mut output, RING.hmac(HashAlgorithm::SHA256).unwrap(), secret, label, seed);
         assert_eq!(expect.len(), output.len());
         assert_eq!(expect.to_vec(), output.to_vec());
     }
@@ -81,7 +62,7 @@ mod tests {
         let expect = include_bytes!("testdata/prf-result.2.bin");
         let mut output = [0u8; 196];
 
-        super::prf(&mut output, &SHA512, secret, label, seed);
+        super::prf(&mut output, RING.hmac(HashAlgorithm::SHA512).unwrap(), secret, label, seed);
         assert_eq!(expect.len(), output.len());
         assert_eq!(expect.to_vec(), output.to_vec());
     }
@@ -5,12 +5,14 @@ use crate::msgs::handshake::{ClientExtension, ServerExtension};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::server::{ServerConfig, ServerSession, ServerSessionImpl};
 use crate::error::TLSError;
-use crate::key_schedule;
+use crate::server_name::ServerName;
+use crate::key_schedule::Prk;
+use crate::msgs::enums::HashAlgorithm;
 use crate::session::{SessionCommon, Protocol};
+use crate::suites::SupportedCipherSuite;
 
 use std::sync::Arc;
 use ring::hkdf;
-use webpki;
 
 /// Secrets used to encrypt/decrypt traffic
 #[derive(Clone, Debug)]
@@ -21,6 +23,42 @@ pub struct Secrets {
     pub server: hkdf::Prk,
 }
 
+impl Secrets {
+    pub(crate) fn new(suite: &SupportedCipherSuite, client: &Prk, server: &Prk) -> Secrets {
+        Secrets {
+            client: ring_prk(suite, client),
+            server: ring_prk(suite, server),
+        }
+    }
+}
+
+/// The QUIC API deals in *ring* secrets, whichever `CryptoProvider`
+/// made them.
+pub(crate) fn ring_prk(suite: &SupportedCipherSuite, secret: &Prk) -> hkdf::Prk {
+    hkdf::Prk::new_less_safe(hkdf_algorithm(suite), secret.as_bytes())
+}
+
+fn hkdf_algorithm(suite: &SupportedCipherSuite) -> hkdf::Algorithm {
+    match suite.hash {
+        HashAlgorithm::SHA256 => hkdf::HKDF_SHA256,
+        HashAlgorithm::SHA384 => hkdf::HKDF_SHA384,
+        HashAlgorithm::SHA512 => hkdf::HKDF_SHA512,
+        _ => unreachable!("no TLS1.3 suite uses {:?}", suite.hash),
+    }
+}
+
+/// HKDF-Expand-Label from RFC8446, producing a new secret.
+fn hkdf_expand_prk(secret: &hkdf::Prk, alg: hkdf::Algorithm, label: &[u8]) -> hkdf::Prk {
+    const LABEL_PREFIX: &[u8] = b"tls13 ";
+
+    let output_len = u16::to_be_bytes(hkdf::KeyType::len(&alg) as u16);
+    let label_len = u8::to_be_bytes((LABEL_PREFIX.len() + label.len()) as u8);
+    let context_len = [0u8];
+
+    let info = &[&output_len[..], &label_len[..], LABEL_PREFIX, label, &context_len[..]];
+    secret.expand(info, alg).unwrap().into()
+}
+
 /// Generic methods for QUIC sessions
 pub trait QuicExt {
     /// Return the TLS-encoded transport parameters for the session's peer.
@@ -124,17 +162,9 @@ fn write_hs(this: &mut SessionCommon, buf: &mut Vec<u8>) -> Option<Secrets> {
 }
 
 fn update_secrets(this: &SessionCommon, client: &hkdf::Prk, server: &hkdf::Prk) -> Secrets {
-    let hkdf_alg= this.get_suite_assert().hkdf_algorithm;
-    let client = key_schedule::hkdf_expand(
-        client,
-        hkdf_alg,
-        b"traffic upd",
-        &[]);
-    let server = key_schedule::hkdf_expand(
-        server,
-        hkdf_alg,
-        b"traffic upd",
-        &[]);
+    let hkdf_alg = hkdf_algorithm(this.get_suite_assert());
+    let client = hkdf_expand_prk(client, hkdf_alg, b"traffic upd");
+    let server = hkdf_expand_prk(server, hkdf_alg, b"traffic upd");
 
     Secrets {
         client,
@@ -147,12 +177,12 @@ pub trait ClientQuicExt {
     /// Make a new QUIC ClientSession. This differs from `ClientSession::new()`
     /// in that it takes an extra argument, `params`, which contains the
     /// TLS-encoded transport parameters to send.
-    fn new_quic(config: &Arc<ClientConfig>, hostname: webpki::DNSNameRef, params: Vec<u8>)
+    fn new_quic(config: &Arc<ClientConfig>, server_name: ServerName, params: Vec<u8>)
                 -> ClientSession {
         assert!(config.versions.iter().all(|x| x.get_u16() >= ProtocolVersion::TLSv1_3.get_u16()), "QUIC requires TLS version >= 1.3");
         let mut imp = ClientSessionImpl::new(config);
         imp.common.protocol = Protocol::Quic;
-        imp.start_handshake(hostname.into(), None, vec![
+        imp.start_handshake(server_name, None, vec![
             ClientExtension::TransportParameters(params),
         ]);
         ClientSession { imp }
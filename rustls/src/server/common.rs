@@ -1,8 +1,10 @@
 use crate::session::SessionRandoms;
+use crate::crypto::CryptoProvider;
 use crate::msgs::handshake::{ServerExtension, SessionID};
-use crate::suites;
+use crate::kx;
 use crate::key;
 use crate::hash_hs;
+use crate::hpke::HpkeContext;
 
 use std::mem;
 
@@ -12,34 +14,43 @@ pub struct HandshakeDetails {
     pub session_id: SessionID,
     pub randoms: SessionRandoms,
     pub using_ems: bool,
+    pub ech_context: Option<HpkeContext>,
+    pub send_ech_retry_configs: bool,
+    #[cfg(feature = "esni")]
+    pub esni_nonce: Option<[u8; 16]>,
     pub extra_exts: Vec<ServerExtension>,
 }
 
 impl HandshakeDetails {
-    pub fn new(extra_exts: Vec<ServerExtension>) -> HandshakeDetails {
+    pub fn new(provider: &dyn CryptoProvider,
+               extra_exts: Vec<ServerExtension>) -> HandshakeDetails {
         HandshakeDetails {
             transcript: hash_hs::HandshakeHash::new(),
             hash_at_server_fin: Vec::new(),
             session_id: SessionID::empty(),
-            randoms: SessionRandoms::for_server(),
+            randoms: SessionRandoms::for_server(provider),
             using_ems: false,
+            ech_context: None,
+            send_ech_retry_configs: false,
+            #[cfg(feature = "esni")]
+            esni_nonce: None,
             extra_exts,
         }
     }
 }
 
 pub struct ServerKXDetails {
-    pub kx: Option<suites::KeyExchange>,
+    pub kx: Option<kx::KeyExchange>,
 }
 
 impl ServerKXDetails {
-    pub fn new(kx: suites::KeyExchange) -> ServerKXDetails {
+    pub fn new(kx: kx::KeyExchange) -> ServerKXDetails {
         ServerKXDetails {
             kx: Some(kx),
         }
     }
 
-    pub fn take_kx(&mut self) -> suites::KeyExchange {
+    pub fn take_kx(&mut self) -> kx::KeyExchange {
         self.kx.take().unwrap()
     }
 }
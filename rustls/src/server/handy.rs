@@ -4,9 +4,12 @@ use webpki;
 use crate::server;
 use crate::error::TLSError;
 use crate::server::ClientHello;
+use crate::ocsp;
+#[cfg(feature = "logging")]
+use crate::log::warn;
 
 use std::collections;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 
 /// Something which never stores sessions.
 pub struct NoServerSessionStorage {}
@@ -113,6 +116,23 @@ impl AlwaysResolvesChain {
         Ok(AlwaysResolvesChain(sign::CertifiedKey::new(chain, Arc::new(key))))
     }
 
+    /// Creates an `AlwaysResolvesChain` which presents `spki` as a raw
+    /// public key, auto-detecting the underlying private key type and
+    /// encoding.
+    pub fn new_raw_public_key(spki: Vec<u8>,
+                              priv_key: &key::PrivateKey) -> Result<AlwaysResolvesChain, TLSError> {
+        let key = sign::any_supported_type(priv_key)
+            .map_err(|_| TLSError::General("invalid private key".into()))?;
+        Ok(AlwaysResolvesChain(sign::CertifiedKey::new_raw_public_key(spki, Arc::new(key))))
+    }
+
+    /// Creates an `AlwaysResolvesChain` using an existing `SigningKey`,
+    /// such as one whose private key is held in an HSM.
+    pub fn new_with_signing_key(chain: Vec<key::Certificate>,
+                                key: Box<dyn sign::SigningKey>) -> AlwaysResolvesChain {
+        AlwaysResolvesChain(sign::CertifiedKey::new(chain, Arc::new(key)))
+    }
+
     /// Creates an `AlwaysResolvesChain`, auto-detecting the underlying private
     /// key type and encoding.
     ///
@@ -177,6 +197,188 @@ impl server::ResolvesServerCert for ResolvesServerCertUsingSNI {
     }
 }
 
+/// Something which can obtain fresh OCSP responses, for use
+/// with `OCSPStaplingResolver`.
+pub trait FetchesOCSPResponses : Send + Sync {
+    /// Return a DER-encoded OCSP response for the end-entity
+    /// certificate at the start of `chain`, or `None` if one
+    /// isn't available right now.
+    ///
+    /// This is called during the handshake, so it must not block:
+    /// an implementation that talks to the OCSP responder should do
+    /// so in the background, and return whatever it has so far.
+    ///
+    /// If this returns `None`, or a response that is unusable or
+    /// already stale, `OCSPStaplingResolver` won't call it again for
+    /// that certificate until its retry interval has passed.
+    fn fetch(&self, chain: &[key::Certificate]) -> Option<Vec<u8>>;
+}
+
+struct StapledResponse {
+    response: Vec<u8>,
+    next_update: u64,
+}
+
+/// How long `OCSPStaplingResolver` waits before asking its
+/// `FetchesOCSPResponses` again after a failed fetch, by default.
+pub const DEFAULT_OCSP_FETCH_RETRY_INTERVAL: u64 = 5 * 60;
+
+/// A `ResolvesServerCert` which wraps another, and staples the
+/// freshest OCSP response it knows for the resolved certificate.
+///
+/// Responses can be pushed in at any time with `set_ocsp_response`,
+/// and take effect for the next handshake.  A response is stapled
+/// until its nextUpdate time passes; after that, the `FetchesOCSPResponses`
+/// hook (if any) is asked for a new one.  Failing that, nothing is
+/// stapled -- an expired response is no use to anyone.
+///
+/// A fetch that fails, or produces a stale response, is remembered:
+/// the hook isn't asked again for that certificate until the retry
+/// interval (see `set_fetch_retry_interval`) has passed, so a down
+/// OCSP responder doesn't cost every handshake a fetch.
+///
+/// Any OCSP response the inner resolver attaches is used if
+/// there's no fresher one.
+///
+/// Freshness is judged by `ClientHello::now`, which comes from the
+/// `ServerConfig`'s `time_provider`.  While that doesn't know the
+/// time, nothing is stapled.
+pub struct OCSPStaplingResolver {
+    inner: Arc<dyn server::ResolvesServerCert>,
+    fetcher: Option<Box<dyn FetchesOCSPResponses>>,
+    responses: RwLock<collections::HashMap<Vec<u8>, StapledResponse>>,
+    failed_fetches: RwLock<collections::HashMap<Vec<u8>, u64>>,
+    retry_interval: u64,
+}
+
+impl OCSPStaplingResolver {
+    /// Make a new `OCSPStaplingResolver`, which knows no OCSP responses
+    /// and has no way to fetch them.
+    pub fn new(inner: Arc<dyn server::ResolvesServerCert>) -> OCSPStaplingResolver {
+        OCSPStaplingResolver {
+            inner,
+            fetcher: None,
+            responses: RwLock::new(collections::HashMap::new()),
+            failed_fetches: RwLock::new(collections::HashMap::new()),
+            retry_interval: DEFAULT_OCSP_FETCH_RETRY_INTERVAL,
+        }
+    }
+
+    /// Make a new `OCSPStaplingResolver` which asks `fetcher` for
+    /// a response when it doesn't have a fresh one.
+    pub fn new_with_fetcher(inner: Arc<dyn server::ResolvesServerCert>,
+                            fetcher: Box<dyn FetchesOCSPResponses>) -> OCSPStaplingResolver {
+        OCSPStaplingResolver {
+            fetcher: Some(fetcher),
+            ..OCSPStaplingResolver::new(inner)
+        }
+    }
+
+    /// After a failed fetch, wait `secs` seconds before asking the
+    /// `FetchesOCSPResponses` hook again for the same certificate.
+    /// The default is `DEFAULT_OCSP_FETCH_RETRY_INTERVAL`.
+    pub fn set_fetch_retry_interval(&mut self, secs: u64) {
+        self.retry_interval = secs;
+    }
+
+    /// Staple `response` for the end-entity certificate `cert` from
+    /// now on, replacing any previous response.
+    ///
+    /// This fails if `response` isn't a successful OCSP response
+    /// with a nextUpdate time.  Its signature is not checked.
+    pub fn set_ocsp_response(&self, cert: &key::Certificate,
+                             response: Vec<u8>) -> Result<(), TLSError> {
+        let next_update = ocsp::next_update(&response)?;
+        self.responses.write()
+            .unwrap()
+            .insert(cert.0.clone(), StapledResponse { response, next_update });
+        Ok(())
+    }
+
+    /// Stop stapling a response for `cert`.
+    pub fn clear_ocsp_response(&self, cert: &key::Certificate) {
+        self.responses.write()
+            .unwrap()
+            .remove(&cert.0);
+    }
+
+    fn current_response(&self, chain: &[key::Certificate],
+                        attached: Option<Vec<u8>>,
+                        now: u64) -> Option<Vec<u8>> {
+        let end_entity = chain.get(0)?;
+
+        if let Some(stapled) = self.responses.read().unwrap().get(&end_entity.0) {
+            if now < stapled.next_update {
+                return Some(stapled.response.clone());
+            }
+        }
+
+        if let Some(response) = attached {
+            match ocsp::next_update(&response) {
+                Ok(next_update) if now < next_update => return Some(response),
+                _ => {}
+            }
+        }
+
+        let fetcher = self.fetcher.as_ref()?;
+
+        if let Some(retry_after) = self.failed_fetches.read().unwrap().get(&end_entity.0) {
+            if now < *retry_after {
+                return None;
+            }
+        }
+
+        let response = match fetcher.fetch(chain) {
+            Some(response) => response,
+            None => {
+                self.fetch_failed(end_entity, now);
+                return None;
+            }
+        };
+
+        match ocsp::next_update(&response) {
+            Ok(next_update) if now < next_update => {
+                self.failed_fetches.write()
+                    .unwrap()
+                    .remove(&end_entity.0);
+                self.responses.write()
+                    .unwrap()
+                    .insert(end_entity.0.clone(),
+                            StapledResponse { response: response.clone(), next_update });
+                Some(response)
+            }
+            Ok(_) => {
+                warn!("fetched OCSP response is already stale");
+                self.fetch_failed(end_entity, now);
+                None
+            }
+            #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+            Err(err) => {
+                warn!("fetched OCSP response is unusable: {:?}", err);
+                self.fetch_failed(end_entity, now);
+                None
+            }
+        }
+    }
+
+    fn fetch_failed(&self, end_entity: &key::Certificate, now: u64) {
+        self.failed_fetches.write()
+            .unwrap()
+            .insert(end_entity.0.clone(), now.saturating_add(self.retry_interval));
+    }
+}
+
+impl server::ResolvesServerCert for OCSPStaplingResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<sign::CertifiedKey> {
+        let now = client_hello.now();
+        let mut ck = self.inner.resolve(client_hello)?;
+        let attached = ck.take_ocsp();
+        ck.ocsp = now
+            .and_then(|now| self.current_response(&ck.cert, attached, now));
+        Some(ck)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -237,4 +439,130 @@ mod test {
 
         assert_eq!(count, 4);
     }
+
+    const END: &[u8] = include_bytes!("../testdata/ocsp-end.der");
+    const GOOD: &[u8] = include_bytes!("../testdata/ocsp-good.der");
+    const REVOKED: &[u8] = include_bytes!("../testdata/ocsp-revoked.der");
+
+    // The responses are valid from 2020-06-01 to 2020-06-08.
+    const JUNE_2ND: u64 = 1_591_056_000;
+    const JUNE_9TH: u64 = JUNE_2ND + 7 * 24 * 60 * 60;
+
+    struct CountingFetcher {
+        response: Option<Vec<u8>>,
+        calls: Arc<Mutex<usize>>,
+    }
+
+    impl FetchesOCSPResponses for CountingFetcher {
+        fn fetch(&self, chain: &[key::Certificate]) -> Option<Vec<u8>> {
+            assert_eq!(chain[0].0, END);
+            *self.calls.lock().unwrap() += 1;
+            self.response.clone()
+        }
+    }
+
+    fn stapling_resolver(fetched: Option<&[u8]>) -> (OCSPStaplingResolver, Arc<Mutex<usize>>) {
+        let calls = Arc::new(Mutex::new(0));
+        let fetcher = CountingFetcher {
+            response: fetched.map(|r| r.to_vec()),
+            calls: calls.clone(),
+        };
+        let resolver = OCSPStaplingResolver::new_with_fetcher(Arc::new(FailResolveChain {}),
+                                                              Box::new(fetcher));
+        (resolver, calls)
+    }
+
+    #[test]
+    fn test_ocspstaplingresolver_uses_latest_response() {
+        let chain = vec![key::Certificate(END.to_vec())];
+        let r = OCSPStaplingResolver::new(Arc::new(FailResolveChain {}));
+        assert_eq!(r.current_response(&chain, None, JUNE_2ND), None);
+
+        r.set_ocsp_response(&chain[0], GOOD.to_vec()).unwrap();
+        assert_eq!(r.current_response(&chain, None, JUNE_2ND), Some(GOOD.to_vec()));
+
+        r.set_ocsp_response(&chain[0], REVOKED.to_vec()).unwrap();
+        assert_eq!(r.current_response(&chain, Some(GOOD.to_vec()), JUNE_2ND),
+                   Some(REVOKED.to_vec()));
+
+        r.clear_ocsp_response(&chain[0]);
+        assert_eq!(r.current_response(&chain, Some(GOOD.to_vec()), JUNE_2ND),
+                   Some(GOOD.to_vec()));
+    }
+
+    #[test]
+    fn test_ocspstaplingresolver_drops_stale_response() {
+        let chain = vec![key::Certificate(END.to_vec())];
+        let r = OCSPStaplingResolver::new(Arc::new(FailResolveChain {}));
+        r.set_ocsp_response(&chain[0], GOOD.to_vec()).unwrap();
+        assert_eq!(r.current_response(&chain, None, JUNE_9TH), None);
+        assert_eq!(r.current_response(&chain, Some(GOOD.to_vec()), JUNE_9TH), None);
+    }
+
+    #[test]
+    fn test_ocspstaplingresolver_rejects_bad_response() {
+        let cert = key::Certificate(END.to_vec());
+        let r = OCSPStaplingResolver::new(Arc::new(FailResolveChain {}));
+        assert!(r.set_ocsp_response(&cert, vec![0x30, 0x00]).is_err());
+        assert!(r.set_ocsp_response(&cert,
+                                    include_bytes!("../testdata/ocsp-try-later.der").to_vec())
+                .is_err());
+    }
+
+    #[test]
+    fn test_ocspstaplingresolver_fetches_when_stale() {
+        let chain = vec![key::Certificate(END.to_vec())];
+        let (r, calls) = stapling_resolver(Some(GOOD));
+        assert_eq!(r.current_response(&chain, None, JUNE_2ND), Some(GOOD.to_vec()));
+        assert_eq!(*calls.lock().unwrap(), 1);
+
+        // The fetched response is remembered until it goes stale.
+        assert_eq!(r.current_response(&chain, None, JUNE_2ND), Some(GOOD.to_vec()));
+        assert_eq!(*calls.lock().unwrap(), 1);
+
+        assert_eq!(r.current_response(&chain, None, JUNE_9TH), None);
+        assert_eq!(*calls.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_ocspstaplingresolver_ignores_bad_fetch() {
+        let chain = vec![key::Certificate(END.to_vec())];
+        let (r, calls) = stapling_resolver(Some(b"garbage"));
+        assert_eq!(r.current_response(&chain, None, JUNE_2ND), None);
+        assert_eq!(*calls.lock().unwrap(), 1);
+
+        let (r, _) = stapling_resolver(None);
+        assert_eq!(r.current_response(&chain, None, JUNE_2ND), None);
+    }
+
+    #[test]
+    fn test_ocspstaplingresolver_waits_to_retry_failed_fetch() {
+        let chain = vec![key::Certificate(END.to_vec())];
+        for fetched in &[None, Some(&b"garbage"[..]), Some(GOOD)] {
+            let (mut r, calls) = stapling_resolver(*fetched);
+            r.set_fetch_retry_interval(60);
+
+            // GOOD is stale by the 9th; the others are never any use.
+            assert_eq!(r.current_response(&chain, None, JUNE_9TH), None);
+            assert_eq!(*calls.lock().unwrap(), 1);
+
+            assert_eq!(r.current_response(&chain, None, JUNE_9TH + 59), None);
+            assert_eq!(*calls.lock().unwrap(), 1);
+
+            assert_eq!(r.current_response(&chain, None, JUNE_9TH + 60), None);
+            assert_eq!(*calls.lock().unwrap(), 2);
+        }
+    }
+
+    #[test]
+    fn test_ocspstaplingresolver_uses_pushed_response_after_failed_fetch() {
+        let chain = vec![key::Certificate(END.to_vec())];
+        let (r, calls) = stapling_resolver(None);
+        assert_eq!(r.current_response(&chain, None, JUNE_2ND), None);
+        assert_eq!(*calls.lock().unwrap(), 1);
+
+        r.set_ocsp_response(&chain[0], GOOD.to_vec()).unwrap();
+        assert_eq!(r.current_response(&chain, None, JUNE_2ND), Some(GOOD.to_vec()));
+        assert_eq!(*calls.lock().unwrap(), 1);
+    }
 }
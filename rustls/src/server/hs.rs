@@ -1,7 +1,7 @@
 use crate::msgs::enums::{ContentType, HandshakeType, ProtocolVersion};
-use crate::msgs::enums::{Compression, NamedGroup, ECPointFormat, CipherSuite};
+use crate::msgs::enums::{Compression, ECPointFormat, CipherSuite};
 use crate::msgs::enums::{ExtensionType, AlertDescription};
-use crate::msgs::enums::{ClientCertificateType, SignatureScheme};
+use crate::msgs::enums::{ClientCertificateType, SignatureScheme, CertificateType};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::handshake::{HandshakePayload, SupportedSignatureSchemes};
 use crate::msgs::handshake::{HandshakeMessagePayload, ServerHelloPayload, Random};
@@ -11,20 +11,30 @@ use crate::msgs::handshake::ClientExtension;
 use crate::msgs::handshake::{ECPointFormatList, SupportedPointFormats};
 use crate::msgs::handshake::{ServerECDHParams, DigitallySignedStruct};
 use crate::msgs::handshake::{ServerKeyExchangePayload, ECDHEServerKeyExchange};
+use crate::msgs::handshake::{ServerDHParams, DHEServerKeyExchange, KeyExchangeAlgorithm};
 use crate::msgs::handshake::CertificateRequestPayload;
 use crate::msgs::handshake::CertificateStatus;
 use crate::msgs::codec::Codec;
+use crate::msgs::base::PayloadU24;
+#[cfg(feature = "esni")]
+use crate::msgs::codec::encode_vec_u16;
 use crate::msgs::persist;
 use crate::session::SessionSecrets;
 use crate::server::{ServerSessionImpl, ServerConfig, ClientHello};
 use crate::suites;
-use crate::verify;
+use crate::kx::{self, SupportedKxGroup};
 use crate::rand;
 use crate::sign;
+use crate::key;
+use crate::verify;
+use crate::time_provider::webpki_now;
 #[cfg(feature = "logging")]
 use crate::log::{trace, debug};
 use crate::error::TLSError;
-use crate::handshake::check_handshake_message;
+use crate::handshake::{check_handshake_message, check_message};
+use crate::ech;
+#[cfg(feature = "esni")]
+use crate::esni;
 use webpki;
 #[cfg(feature = "quic")]
 use crate::session::Protocol;
@@ -51,6 +61,74 @@ pub type NextStateOrError = Result<NextState, TLSError>;
 pub trait State {
     fn check_message(&self, m: &Message) -> CheckResult;
     fn handle(self: Box<Self>, sess: &mut ServerSessionImpl, m: Message) -> NextStateOrError;
+
+    /// The signature this state is waiting for, if any.  No messages
+    /// are processed until it arrives.
+    fn pending_signature(&self) -> Option<&sign::PendingSignature> {
+        None
+    }
+
+    /// Continue with `sig`, made for `pending_signature()`.
+    fn resume_with_signature(self: Box<Self>,
+                             _sess: &mut ServerSessionImpl,
+                             _sig: Vec<u8>) -> NextStateOrError {
+        unreachable!("no signature pending")
+    }
+}
+
+type ResumeWithSignature =
+    Box<dyn FnOnce(&mut ServerSessionImpl, DigitallySignedStruct) -> NextStateOrError + Send + Sync>;
+
+/// The handshake is paused until the application supplies a
+/// signature: see `sign::Signer::start_sign`.
+pub struct ExpectSignature {
+    pending: sign::PendingSignature,
+    resume: ResumeWithSignature,
+}
+
+impl ExpectSignature {
+    /// Sign `message` with `signer`, then continue the handshake
+    /// with `resume`: either now, or once the signature arrives.
+    pub fn sign<F>(sess: &mut ServerSessionImpl,
+                   signer: &dyn sign::Signer,
+                   message: Vec<u8>,
+                   resume: F) -> NextStateOrError
+        where F: FnOnce(&mut ServerSessionImpl, DigitallySignedStruct) -> NextStateOrError + Send + Sync + 'static
+    {
+        let scheme = signer.get_scheme();
+
+        match signer.start_sign(&message)? {
+            Some(sig) => resume(sess, DigitallySignedStruct::new(scheme, sig)),
+            None => {
+                debug!("Waiting for {:?} signature", scheme);
+                Ok(Box::new(ExpectSignature {
+                    pending: sign::PendingSignature::new(message, scheme),
+                    resume: Box::new(resume),
+                }))
+            }
+        }
+    }
+}
+
+impl State for ExpectSignature {
+    fn check_message(&self, m: &Message) -> CheckResult {
+        check_message(m, &[], &[])
+    }
+
+    fn handle(self: Box<Self>, _sess: &mut ServerSessionImpl, _m: Message) -> NextStateOrError {
+        unreachable!("no messages are processed while a signature is pending")
+    }
+
+    fn pending_signature(&self) -> Option<&sign::PendingSignature> {
+        Some(&self.pending)
+    }
+
+    fn resume_with_signature(self: Box<Self>,
+                             sess: &mut ServerSessionImpl,
+                             sig: Vec<u8>) -> NextStateOrError {
+        let dss = DigitallySignedStruct::new(self.pending.scheme(), sig);
+        (self.resume)(sess, dss)
+    }
 }
 
 pub fn incompatible(sess: &mut ServerSessionImpl, why: &str) -> TLSError {
@@ -58,6 +136,48 @@ pub fn incompatible(sess: &mut ServerSessionImpl, why: &str) -> TLSError {
     TLSError::PeerIncompatibleError(why.to_string())
 }
 
+/// Whether we ask clients to authenticate, with either a
+/// certificate chain or a raw public key.
+pub fn offer_client_auth(config: &ServerConfig) -> bool {
+    config.raw_public_key_verifier.is_some() ||
+        config.verifier.offer_client_auth()
+}
+
+/// Whether clients must authenticate.  We only ask for raw
+/// public keys if we need them.
+pub fn client_auth_mandatory(config: &ServerConfig) -> bool {
+    config.raw_public_key_verifier.is_some() ||
+        config.verifier.client_auth_mandatory()
+}
+
+/// The kind of client credentials we negotiated.  We've always
+/// refused clients which can't send raw public keys if we want them.
+pub fn client_cert_type(config: &ServerConfig) -> CertificateType {
+    if config.raw_public_key_verifier.is_some() {
+        CertificateType::RawPublicKey
+    } else {
+        CertificateType::X509
+    }
+}
+
+/// Authenticate the client's certificate chain, or its raw public
+/// key if that's what we negotiated.
+pub fn verify_client_cert(sess: &ServerSessionImpl,
+                          cert_chain: &[key::Certificate])
+                          -> Result<verify::ClientCertVerified, TLSError> {
+    match sess.config.raw_public_key_verifier {
+        Some(ref verifier) => {
+            let spki = cert_chain.get(0).ok_or(TLSError::NoCertificatesPresented)?;
+            verifier.verify_client_raw_public_key(&spki.0)
+        }
+        None => {
+            let sni = sess.get_sni().map(webpki::DNSName::as_ref);
+            let now = webpki_now(&*sess.config.time_provider)?;
+            sess.config.verifier.verify_client_cert_for_sni(cert_chain, sni, now)
+        }
+    }
+}
+
 fn bad_version(sess: &mut ServerSessionImpl, why: &str) -> TLSError {
     sess.common.send_fatal_alert(AlertDescription::ProtocolVersion);
     TLSError::PeerIncompatibleError(why.to_string())
@@ -223,6 +343,12 @@ impl ExtensionProcessing {
             }
         }
 
+        if !for_resume {
+            let raw_server_key = server_key.as_ref()
+                .map_or(false, |key| key.raw_public_key);
+            self.process_certificate_types(sess, hello, raw_server_key)?;
+        }
+
         if !for_resume &&
            hello.find_extension(ExtensionType::SCT).is_some() &&
            server_key.is_some() &&
@@ -238,6 +364,20 @@ impl ExtensionProcessing {
             }
         }
 
+        // ESNI: echo the nonce from the client's decrypted ESNI.
+        #[cfg(feature = "esni")] {
+            if sess.common.is_tls13() {
+                if let Some(nonce) = handshake.esni_nonce {
+                    self.exts.push(ServerExtension::EncryptedServerName(nonce));
+                }
+            }
+        }
+
+        if sess.common.is_tls13() && handshake.send_ech_retry_configs {
+            let retry_configs = ech::make_retry_configs(&sess.config.ech_keys);
+            self.exts.push(ServerExtension::EncryptedClientHello(retry_configs));
+        }
+
         if !sess.common.is_tls13() {
         }
 
@@ -246,6 +386,50 @@ impl ExtensionProcessing {
         Ok(())
     }
 
+    /// Choose certificate types (RFC7250).  If we have a raw public
+    /// key, or want one from the client, the client must support that;
+    /// otherwise we use X.509 certificates as usual.
+    fn process_certificate_types(&mut self,
+                                 sess: &mut ServerSessionImpl,
+                                 hello: &ClientHelloPayload,
+                                 raw_server_key: bool) -> Result<(), TLSError> {
+        let offered = |types: Option<&Vec<CertificateType>>, typ| {
+            types.map_or(typ == CertificateType::X509, |types| types.contains(&typ))
+        };
+
+        let server_cert_type = if raw_server_key {
+            CertificateType::RawPublicKey
+        } else {
+            CertificateType::X509
+        };
+
+        if !offered(hello.get_server_certificate_types(), server_cert_type) {
+            sess.common.send_fatal_alert(AlertDescription::UnsupportedCertificate);
+            return Err(TLSError::PeerIncompatibleError("client does not support our certificate type"
+                .to_string()));
+        }
+
+        if hello.get_server_certificate_types().is_some() {
+            self.exts.push(ServerExtension::ServerCertificateType(server_cert_type));
+        }
+
+        if sess.config.raw_public_key_verifier.is_some() {
+            if !offered(hello.get_client_certificate_types(), CertificateType::RawPublicKey) {
+                sess.common.send_fatal_alert(AlertDescription::UnsupportedCertificate);
+                return Err(TLSError::PeerIncompatibleError("client does not support raw public keys"
+                    .to_string()));
+            }
+
+            self.exts.push(ServerExtension::ClientCertificateType(CertificateType::RawPublicKey));
+        } else if sess.config.verifier.offer_client_auth() &&
+                  hello.get_client_certificate_types()
+                      .map_or(false, |types| types.contains(&CertificateType::X509)) {
+            self.exts.push(ServerExtension::ClientCertificateType(CertificateType::X509));
+        }
+
+        Ok(())
+    }
+
     fn process_tls12(&mut self, sess: &ServerSessionImpl, hello: &ClientHelloPayload,
                      handshake: &HandshakeDetails) {
         // Renegotiation.
@@ -274,6 +458,37 @@ impl ExtensionProcessing {
     }
 }
 
+/// Our ServerKeyExchange params, before they're signed.
+enum ServerKXParams {
+    ECDHE(ServerECDHParams),
+    DHE(ServerDHParams),
+}
+
+impl ServerKXParams {
+    fn new(skxg: &dyn SupportedKxGroup, kx: &kx::KeyExchange) -> ServerKXParams {
+        match skxg.ffdhe_params() {
+            Some(ffdhe) => ServerKXParams::DHE(ServerDHParams::new(ffdhe.p, ffdhe.g, &kx.pubkey)),
+            None => ServerKXParams::ECDHE(ServerECDHParams::new(skxg.name(), &kx.pubkey)),
+        }
+    }
+
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match *self {
+            ServerKXParams::ECDHE(ref params) => params.encode(bytes),
+            ServerKXParams::DHE(ref params) => params.encode(bytes),
+        }
+    }
+
+    fn into_payload(self, dss: DigitallySignedStruct) -> ServerKeyExchangePayload {
+        match self {
+            ServerKXParams::ECDHE(params) =>
+                ServerKeyExchangePayload::ECDHE(ECDHEServerKeyExchange { params, dss }),
+            ServerKXParams::DHE(params) =>
+                ServerKeyExchangePayload::DHE(DHEServerKeyExchange { params, dss }),
+        }
+    }
+}
+
 pub struct ExpectClientHello {
     pub handshake: HandshakeDetails,
     pub done_retry: bool,
@@ -285,20 +500,129 @@ pub struct ExpectClientHello {
 impl ExpectClientHello {
     pub fn new(server_config: &ServerConfig, extra_exts: Vec<ServerExtension>) -> ExpectClientHello {
         let mut ech = ExpectClientHello {
-            handshake: HandshakeDetails::new(extra_exts),
+            handshake: HandshakeDetails::new(server_config.crypto_provider, extra_exts),
             done_retry: false,
             send_cert_status: false,
             send_sct: false,
             send_ticket: false,
         };
 
-        if server_config.verifier.offer_client_auth() {
+        if offer_client_auth(server_config) {
             ech.handshake.transcript.set_client_auth_enabled();
         }
 
         ech
     }
 
+    /// Decrypt the client's ClientHelloInner, if it sent one we have
+    /// keys for, and return that in place of `m`.  Otherwise we carry
+    /// on with `m`, the ClientHelloOuter, and offer retry configs.
+    fn process_ech(&mut self,
+                   sess: &mut ServerSessionImpl,
+                   m: Message) -> Result<Message, TLSError> {
+        if sess.config.ech_keys.is_empty() {
+            return Ok(m);
+        }
+
+        let decrypted = {
+            let outer = extract_handshake!(m, HandshakePayload::ClientHello).unwrap();
+            let accepted_in_hrr = self.handshake.ech_context.is_some();
+
+            if outer.get_ech_extension().is_none() {
+                if accepted_in_hrr {
+                    return Err(illegal_param(sess, "client dropped ECH after HelloRetryRequest"));
+                }
+                return Ok(m);
+            }
+
+            // We rejected ECH in the first ClientHello, so ignore it now.
+            if self.done_retry && !accepted_in_hrr {
+                return Ok(m);
+            }
+
+            ech::decrypt_client_hello(&sess.config.ech_keys,
+                                      outer,
+                                      self.handshake.ech_context.take())
+        };
+
+        match decrypted {
+            Ok(Some((inner, context))) => {
+                debug!("accepted ECH");
+                self.handshake.ech_context = Some(context);
+                Ok(Message {
+                    typ: m.typ,
+                    version: m.version,
+                    payload: MessagePayload::Handshake(HandshakeMessagePayload {
+                        typ: HandshakeType::ClientHello,
+                        payload: HandshakePayload::ClientHello(inner),
+                    }),
+                })
+            }
+            Ok(None) => {
+                debug!("rejecting ECH for unknown config");
+                self.handshake.send_ech_retry_configs = true;
+                Ok(m)
+            }
+            Err(TLSError::DecryptError) => {
+                sess.common.send_fatal_alert(AlertDescription::DecryptError);
+                Err(TLSError::DecryptError)
+            }
+            Err(err) => {
+                sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+                Err(err)
+            }
+        }
+    }
+
+    /// Decrypt the client's encrypted SNI, if it sent one we have
+    /// keys for.  Returns the real SNI, which takes precedence over
+    /// any cleartext one.
+    #[cfg(feature = "esni")]
+    fn process_esni(&mut self,
+                    sess: &mut ServerSessionImpl,
+                    client_hello: &ClientHelloPayload) -> Result<Option<webpki::DNSName>, TLSError> {
+        self.handshake.esni_nonce = None;
+
+        let esni = match client_hello.get_esni_extension() {
+            Some(esni) if !sess.config.esni_keys.is_empty() => esni,
+            _ => return Ok(None),
+        };
+
+        // The ClientHello's key shares are the AAD.
+        let key_shares = client_hello.get_keyshare_extension()
+            .ok_or_else(|| incompatible(sess, "client sent ESNI without key shares"))?;
+        let mut key_share_bytes = Vec::new();
+        encode_vec_u16(&mut key_share_bytes, key_shares);
+
+        let mut client_random = [0u8; 32];
+        client_hello.random.write_slice(&mut client_random);
+
+        let inner = match esni::decrypt_esni(&sess.config.esni_keys,
+                                             esni,
+                                             &key_share_bytes,
+                                             &client_random) {
+            Ok(Some(inner)) => inner,
+            Ok(None) => {
+                debug!("ignoring ESNI for unknown record");
+                return Ok(None);
+            }
+            Err(TLSError::DecryptError) => {
+                sess.common.send_fatal_alert(AlertDescription::DecryptError);
+                return Err(TLSError::DecryptError);
+            }
+            Err(err) => {
+                sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+                return Err(err);
+            }
+        };
+
+        let sni = inner.real_sni.sni.get_hostname()
+            .ok_or_else(|| illegal_param(sess, "ClientHello ESNI did not contain a hostname."))?;
+
+        self.handshake.esni_nonce = Some(inner.nonce);
+        Ok(Some(sni.into()))
+    }
+
     fn into_expect_tls12_ccs(self) -> NextState {
         Box::new(tls12::ExpectCCS {
             handshake: self.handshake,
@@ -317,7 +641,7 @@ impl ExpectClientHello {
         }
     }
 
-    fn into_expect_tls12_certificate(self, kx: suites::KeyExchange) -> NextState {
+    fn into_expect_tls12_certificate(self, kx: kx::KeyExchange) -> NextState {
         Box::new(tls12::ExpectCertificate {
             handshake: self.handshake,
             server_kx: ServerKXDetails::new(kx),
@@ -325,7 +649,7 @@ impl ExpectClientHello {
         })
     }
 
-    fn into_expect_tls12_client_kx(self, kx: suites::KeyExchange) -> NextState {
+    fn into_expect_tls12_client_kx(self, kx: kx::KeyExchange) -> NextState {
         Box::new(tls12::ExpectClientKX {
             handshake: self.handshake,
             server_kx: ServerKXDetails::new(kx),
@@ -373,14 +697,20 @@ impl ExpectClientHello {
     fn emit_certificate(&mut self,
                         sess: &mut ServerSessionImpl,
                         server_certkey: &mut sign::CertifiedKey) {
-        let cert_chain = server_certkey.take_cert();
+        let mut cert_chain = server_certkey.take_cert();
+
+        let payload = if server_certkey.raw_public_key {
+            HandshakePayload::RawPublicKey(PayloadU24::new(cert_chain.remove(0).0))
+        } else {
+            HandshakePayload::Certificate(cert_chain)
+        };
 
         let c = Message {
             typ: ContentType::Handshake,
             version: ProtocolVersion::TLSv1_2,
             payload: MessagePayload::Handshake(HandshakeMessagePayload {
                 typ: HandshakeType::Certificate,
-                payload: HandshakePayload::Certificate(cert_chain),
+                payload,
             }),
         };
 
@@ -412,60 +742,63 @@ impl ExpectClientHello {
         sess.common.send_msg(c, false);
     }
 
-    fn emit_server_kx(&mut self,
-                      sess: &mut ServerSessionImpl,
-                      sigschemes: Vec<SignatureScheme>,
-                      group: NamedGroup,
-                      server_certkey: &mut sign::CertifiedKey)
-                      -> Result<suites::KeyExchange, TLSError> {
+    /// Start our key exchange in `skxg`, and sign its params with
+    /// `server_certkey`.  `emit_server_kx` then sends them.
+    fn start_server_kx(&self,
+                       sess: &mut ServerSessionImpl,
+                       sigschemes: &[SignatureScheme],
+                       skxg: &'static dyn SupportedKxGroup,
+                       server_certkey: &sign::CertifiedKey)
+                       -> Result<(kx::KeyExchange, ServerKXParams, Box<dyn sign::Signer>, Vec<u8>), TLSError> {
         let kx = sess.common.get_suite_assert()
-            .start_server_kx(group)
+            .start_server_kx(skxg)
             .ok_or_else(|| TLSError::PeerMisbehavedError("key exchange failed".to_string()))?;
-        let secdh = ServerECDHParams::new(group, kx.pubkey.as_ref());
+        let params = ServerKXParams::new(skxg, &kx);
 
+        // We sign ClientHello.random || ServerHello.random || params.
         let mut msg = Vec::new();
         msg.extend(&self.handshake.randoms.client);
         msg.extend(&self.handshake.randoms.server);
-        secdh.encode(&mut msg);
+        params.encode(&mut msg);
 
-        let signing_key = &server_certkey.key;
-        let signer = signing_key.choose_scheme(&sigschemes)
+        let signer = server_certkey.key.choose_scheme(sigschemes)
             .ok_or_else(|| TLSError::General("incompatible signing key".to_string()))?;
-        let sigscheme = signer.get_scheme();
-        let sig = signer.sign(&msg)?;
-
-        let skx = ServerKeyExchangePayload::ECDHE(ECDHEServerKeyExchange {
-            params: secdh,
-            dss: DigitallySignedStruct::new(sigscheme, sig),
-        });
+        Ok((kx, params, signer, msg))
+    }
 
+    fn emit_server_kx(&mut self,
+                      sess: &mut ServerSessionImpl,
+                      params: ServerKXParams,
+                      dss: DigitallySignedStruct) {
         let m = Message {
             typ: ContentType::Handshake,
             version: ProtocolVersion::TLSv1_2,
             payload: MessagePayload::Handshake(HandshakeMessagePayload {
                 typ: HandshakeType::ServerKeyExchange,
-                payload: HandshakePayload::ServerKeyExchange(skx),
+                payload: HandshakePayload::ServerKeyExchange(params.into_payload(dss)),
             }),
         };
 
         self.handshake.transcript.add_message(&m);
         sess.common.send_msg(m, false);
-        Ok(kx)
     }
 
     fn emit_certificate_req(&mut self, sess: &mut ServerSessionImpl) -> bool {
-        let client_auth = &sess.config.verifier;
-
-        if !client_auth.offer_client_auth() {
+        if !offer_client_auth(&sess.config) {
             return false;
         }
 
-        let names = client_auth.client_auth_root_subjects();
+        // Raw public keys aren't issued by anyone.
+        let names = if sess.config.raw_public_key_verifier.is_some() {
+            Vec::new()
+        } else {
+            sess.config.verifier.client_auth_root_subjects()
+        };
 
         let cr = CertificateRequestPayload {
             certtypes: vec![ ClientCertificateType::RSASign,
                          ClientCertificateType::ECDSASign ],
-            sigschemes: verify::supported_verify_schemes().to_vec(),
+            sigschemes: sess.config.crypto_provider.supported_verify_schemes(),
             canames: names,
         };
 
@@ -514,14 +847,14 @@ impl ExpectClientHello {
         self.handshake.session_id = *id;
         self.emit_server_hello(sess, None, client_hello, Some(&resumedata))?;
 
-        let hashalg = sess.common.get_suite_assert().get_hash();
+        let hmac = sess.config.crypto_provider.suite_hmac(sess.common.get_suite_assert())?;
         let secrets = SessionSecrets::new_resume(&self.handshake.randoms,
-                                                 hashalg,
+                                                 hmac,
                                                  &resumedata.master_secret.0);
         sess.config.key_log.log("CLIENT_RANDOM",
                                 &secrets.randoms.client,
                                 &secrets.master_secret);
-        sess.common.start_encryption_tls12(secrets);
+        sess.common.start_encryption_tls12(secrets)?;
         sess.client_cert_chain = resumedata.client_cert_chain;
 
         if self.send_ticket {
@@ -543,6 +876,7 @@ impl State for ExpectClientHello {
     }
 
     fn handle(mut self: Box<Self>, sess: &mut ServerSessionImpl, m: Message) -> NextStateOrError {
+        let m = self.process_ech(sess, m)?;
         let client_hello = extract_handshake!(m, HandshakePayload::ClientHello).unwrap();
         let tls13_enabled = sess.config.supports_version(ProtocolVersion::TLSv1_3);
         let tls12_enabled = sess.config.supports_version(ProtocolVersion::TLSv1_2);
@@ -583,7 +917,17 @@ impl State for ExpectClientHello {
         // send an Illegal Parameter alert instead of the Internal Error alert
         // (or whatever) that we'd send if this were checked later or in a
         // different way.
+        #[cfg(feature = "esni")]
+        let esni = if sess.common.is_tls13() {
+            self.process_esni(sess, client_hello)?
+        } else {
+            None
+        };
+        #[cfg(not(feature = "esni"))]
+        let esni: Option<webpki::DNSName> = None;
+
         let sni: Option<webpki::DNSName> = match client_hello.get_sni_extension() {
+            _ if esni.is_some() => esni,
             Some(sni) => {
                 match sni.get_hostname() {
                     Some(sni) => Some(sni.into()),
@@ -601,8 +945,10 @@ impl State for ExpectClientHello {
         // orthogonally to offered ciphersuites (even though, in TLS1.2 it is not).
         // So: reduce the offered sigschemes to those compatible with the
         // intersection of ciphersuites.
+        let provider = sess.config.crypto_provider;
         let mut common_suites = sess.config.ciphersuites.clone();
-        common_suites.retain(|scs| client_hello.cipher_suites.contains(&scs.suite));
+        common_suites.retain(|scs| client_hello.cipher_suites.contains(&scs.suite) &&
+                             provider.supports_suite(scs));
 
         let mut sigschemes_ext = client_hello.get_sigalgs_extension()
             .cloned()
@@ -624,7 +970,8 @@ impl State for ExpectClientHello {
                 None => None,
             };
 
-            let client_hello = ClientHello::new(sni_ref, &sigschemes_ext, alpn_slices);
+            let client_hello = ClientHello::new(sni_ref, &sigschemes_ext, alpn_slices,
+                                                sess.config.time_provider.now());
 
             let certkey = sess.config.cert_resolver.resolve(client_hello);
             certkey.ok_or_else(|| {
@@ -635,13 +982,21 @@ impl State for ExpectClientHello {
 
         // Reduce our supported ciphersuites by the certificate.
         // (no-op for TLS1.3)
-        let suitable_suites = suites::reduce_given_sigalg(&sess.config.ciphersuites,
+        let suitable_suites = suites::reduce_given_sigalg(&common_suites,
                                                           certkey.key.algorithm());
 
         // And version
         let protocol_version = sess.common.negotiated_version.unwrap();
         let suitable_suites = suites::reduce_given_version(&suitable_suites, protocol_version);
 
+        // And key exchange groups
+        let client_groups = client_hello.get_namedgroups_extension()
+            .map(|groups| groups.as_slice())
+            .unwrap_or(&[]);
+        let suitable_suites = suites::reduce_given_kx_groups(&suitable_suites,
+                                                             &sess.config.kx_groups,
+                                                             client_groups);
+
         let maybe_ciphersuite = if sess.config.ignore_client_order {
             suites::choose_ciphersuite_preferring_server(&client_hello.cipher_suites, &suitable_suites)
         } else {
@@ -656,7 +1011,7 @@ impl State for ExpectClientHello {
         sess.common.set_suite(maybe_ciphersuite.unwrap());
 
         // Start handshake hash.
-        let starting_hash = sess.common.get_suite_assert().get_hash();
+        let starting_hash = provider.suite_hash(sess.common.get_suite_assert())?;
         if !self.handshake.transcript.start_hash(starting_hash) {
             sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
             return Err(TLSError::PeerIncompatibleError("hash differed on retry"
@@ -681,16 +1036,23 @@ impl State for ExpectClientHello {
 
         let groups_ext = client_hello.get_namedgroups_extension()
             .ok_or_else(|| incompatible(sess, "client didn't describe groups"))?;
-        let ecpoints_ext = client_hello.get_ecpoints_extension()
-            .ok_or_else(|| incompatible(sess, "client didn't describe ec points"))?;
+        let ecpoints_ext = client_hello.get_ecpoints_extension();
 
         trace!("namedgroups {:?}", groups_ext);
         trace!("ecpoints {:?}", ecpoints_ext);
 
-        if !ecpoints_ext.contains(&ECPointFormat::Uncompressed) {
-            sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
-            return Err(TLSError::PeerIncompatibleError("client didn't support uncompressed ec points"
-                .to_string()));
+        // Peers which only do DHE need not describe ec points.
+        let using_ecdhe = sess.common.get_suite_assert().kx == KeyExchangeAlgorithm::ECDHE;
+
+        if using_ecdhe {
+            let ecpoints_ext = ecpoints_ext
+                .ok_or_else(|| incompatible(sess, "client didn't describe ec points"))?;
+
+            if !ecpoints_ext.contains(&ECPointFormat::Uncompressed) {
+                sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+                return Err(TLSError::PeerIncompatibleError("client didn't support uncompressed ec points"
+                    .to_string()));
+            }
         }
 
         // -- If TLS1.3 is enabled, signal the downgrade in the server random
@@ -719,7 +1081,7 @@ impl State for ExpectClientHello {
 
                 let maybe_resume = sess.config
                     .ticketer
-                    .decrypt(&ticket.0)
+                    .decrypt_at(&ticket.0, sess.config.time_provider.now())
                     .and_then(|plain| persist::ServerSessionValue::read_bytes(&plain));
 
                 if can_resume(sess, &self.handshake, &maybe_resume) {
@@ -737,7 +1099,7 @@ impl State for ExpectClientHello {
         // allocate a session ID.
         if self.handshake.session_id.is_empty() && !ticket_received {
             let mut bytes = [0u8; 32];
-            rand::fill_random(&mut bytes);
+            rand::fill_random(sess.config.crypto_provider, &mut bytes);
             self.handshake.session_id = SessionID::new(&bytes);
         }
 
@@ -764,33 +1126,41 @@ impl State for ExpectClientHello {
             return Err(incompatible(sess, "no supported sig scheme"));
         }
 
-        let group = suites::KeyExchange::supported_groups()
+        let group = sess.config.kx_groups
             .iter()
-            .filter(|group| groups_ext.contains(group))
+            .filter(|skxg| skxg.usable_for_tls12() && groups_ext.contains(&skxg.name()))
+            .filter(|skxg| skxg.ffdhe_params().is_none() == using_ecdhe)
             .nth(0)
             .cloned()
             .ok_or_else(|| incompatible(sess, "no supported group"))?;
 
-        let ecpoint = ECPointFormatList::supported()
-            .iter()
-            .filter(|format| ecpoints_ext.contains(format))
-            .nth(0)
-            .cloned()
-            .ok_or_else(|| incompatible(sess, "no supported point format"))?;
+        if using_ecdhe {
+            let ecpoint = ECPointFormatList::supported()
+                .iter()
+                .filter(|format| ecpoints_ext.map_or(false, |ext| ext.contains(format)))
+                .nth(0)
+                .cloned()
+                .ok_or_else(|| incompatible(sess, "no supported point format"))?;
 
-        debug_assert_eq!(ecpoint, ECPointFormat::Uncompressed);
+            debug_assert_eq!(ecpoint, ECPointFormat::Uncompressed);
+        }
 
         self.emit_server_hello(sess, Some(&mut certkey), client_hello, None)?;
         self.emit_certificate(sess, &mut certkey);
         self.emit_cert_status(sess, &mut certkey);
-        let kx = self.emit_server_kx(sess, sigschemes, group, &mut certkey)?;
-        let doing_client_auth = self.emit_certificate_req(sess);
-        self.emit_server_hello_done(sess);
+        let (kx, params, signer, msg) = self.start_server_kx(sess, &sigschemes, group, &certkey)?;
 
-        if doing_client_auth {
-            Ok(self.into_expect_tls12_certificate(kx))
-        } else {
-            Ok(self.into_expect_tls12_client_kx(kx))
-        }
+        ExpectSignature::sign(sess, &*signer, msg, move |sess, dss| {
+            let mut st = self;
+            st.emit_server_kx(sess, params, dss);
+            let doing_client_auth = st.emit_certificate_req(sess);
+            st.emit_server_hello_done(sess);
+
+            if doing_client_auth {
+                Ok(st.into_expect_tls12_certificate(kx))
+            } else {
+                Ok(st.into_expect_tls12_client_kx(kx))
+            }
+        })
     }
 }
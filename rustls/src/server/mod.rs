@@ -1,6 +1,9 @@
 use crate::session::{Session, SessionCommon};
 use crate::keylog::{KeyLog, NoKeyLog};
+use crate::time_provider::{TimeProvider, SystemTimeProvider};
 use crate::suites::{SupportedCipherSuite, ALL_CIPHERSUITES};
+use crate::kx::SupportedKxGroup;
+use crate::crypto::{self, CryptoProvider};
 use crate::msgs::enums::ContentType;
 use crate::msgs::enums::SignatureScheme;
 use crate::msgs::enums::{AlertDescription, HandshakeType, ProtocolVersion};
@@ -10,6 +13,10 @@ use crate::error::TLSError;
 use crate::sign;
 use crate::verify;
 use crate::key;
+use crate::identity::PeerIdentity;
+use crate::ech::ECHServerKeys;
+#[cfg(feature = "esni")]
+use crate::esni::ESNIServerKeys;
 use crate::vecbuf::WriteV;
 #[cfg(feature = "logging")]
 use crate::log::trace;
@@ -91,6 +98,22 @@ pub trait ProducesTickets : Send + Sync {
     /// panic-proof, and otherwise bullet-proof.  If the decryption
     /// fails, return None.
     fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>>;
+
+    /// Like `encrypt`, but `now` is the current time from the
+    /// `ServerConfig`'s `time_provider`, or None if it doesn't know
+    /// the time.  This is what the server calls.  The default ignores
+    /// `now`; implementations that roll keys should roll them by it.
+    fn encrypt_at(&self, plain: &[u8], now: Option<u64>) -> Option<Vec<u8>> {
+        let _ = now;
+        self.encrypt(plain)
+    }
+
+    /// Like `decrypt`, but `now` is the current time as for
+    /// `encrypt_at`.
+    fn decrypt_at(&self, cipher: &[u8], now: Option<u64>) -> Option<Vec<u8>> {
+        let _ = now;
+        self.decrypt(cipher)
+    }
 }
 
 /// How to choose a certificate chain and signing key for use
@@ -108,13 +131,14 @@ pub struct ClientHello<'a> {
     server_name: Option<webpki::DNSNameRef<'a>>,
     sigschemes: &'a [SignatureScheme],
     alpn: Option<&'a[&'a[u8]]>,
+    now: Option<u64>,
 }
 
 impl<'a> ClientHello<'a> {
     /// Creates a new ClientHello
     fn new(server_name: Option<webpki::DNSNameRef<'a>>, sigschemes:  &'a [SignatureScheme],
-    alpn: Option<&'a[&'a[u8]]>)->Self {
-        ClientHello {server_name, sigschemes, alpn}
+    alpn: Option<&'a[&'a[u8]]>, now: Option<u64>)->Self {
+        ClientHello {server_name, sigschemes, alpn, now}
     }
 
     /// Get the server name indicator.
@@ -137,6 +161,14 @@ impl<'a> ClientHello<'a> {
     pub fn alpn(&self) -> Option<&'a[&'a[u8]]> {
         self.alpn
     }
+
+    /// Get the current time from the server's `time_provider`, in
+    /// seconds since the UNIX epoch.
+    ///
+    /// Returns `None` if it doesn't know the time.
+    pub fn now(&self) -> Option<u64> {
+        self.now
+    }
 }
 
 /// Common configuration for a set of server sessions.
@@ -148,6 +180,17 @@ pub struct ServerConfig {
     /// List of ciphersuites, in preference order.
     pub ciphersuites: Vec<&'static SupportedCipherSuite>,
 
+    /// List of supported key exchange groups, in preference order.
+    ///
+    /// The default is the `default_kx_groups()` of `crypto_provider`.
+    pub kx_groups: Vec<&'static dyn SupportedKxGroup>,
+
+    /// Where to get our cryptography.  Ciphersuites this doesn't
+    /// support are not chosen.
+    ///
+    /// The default is `crypto::ring::RING`.
+    pub crypto_provider: &'static dyn CryptoProvider,
+
     /// Ignore the client's ciphersuite order. Instead,
     /// choose the top ciphersuite in the server list
     /// which is supported by the client.
@@ -176,10 +219,33 @@ pub struct ServerConfig {
     /// How to verify client certificates.
     verifier: Arc<dyn verify::ClientCertVerifier>,
 
+    /// If set, clients must authenticate with a raw public key
+    /// (RFC7250), which we verify with this, rather than a
+    /// certificate chain.
+    raw_public_key_verifier: Option<Arc<dyn verify::ClientRawPublicKeyVerifier>>,
+
     /// How to output key material for debugging.  The default
     /// does nothing.
     pub key_log: Arc<dyn KeyLog>,
 
+    /// Where to get the current time, for verifying client
+    /// certificates.  `ticketer` is given this time for rolling
+    /// keys, and `cert_resolver` sees it as `ClientHello::now`.
+    ///
+    /// The default is `SystemTimeProvider`.
+    pub time_provider: Arc<dyn TimeProvider>,
+
+    /// Keys for decrypting encrypted ClientHellos, one per published
+    /// ECHConfig.  If empty (the default), ECH is ignored.  If not,
+    /// clients we can't decrypt for are sent all of these configs to
+    /// retry with.
+    pub ech_keys: Vec<ECHServerKeys>,
+
+    /// Keys for decrypting encrypted SNI, one per published
+    /// ESNI record.  If empty (the default), ESNI is ignored.
+    #[cfg(feature = "esni")]
+    pub esni_keys: Vec<ESNIServerKeys>,
+
     /// Amount of early data to accept; 0 to disable.
     #[cfg(feature = "quic")]    // TLS support unimplemented
     #[doc(hidden)]
@@ -201,8 +267,22 @@ impl ServerConfig {
     /// default, requiring client authentication, requires additional
     /// configuration that we cannot provide reasonable defaults for.
     pub fn new(client_cert_verifier: Arc<dyn verify::ClientCertVerifier>) -> ServerConfig {
+        ServerConfig::with_crypto_provider(client_cert_verifier, &crypto::ring::RING)
+    }
+
+    /// Make a `ServerConfig` like `new()`, but which gets its
+    /// cryptography from `provider`.  The ciphersuites are those
+    /// `provider` supports, and the key exchange groups are its
+    /// default ones.
+    pub fn with_crypto_provider(client_cert_verifier: Arc<dyn verify::ClientCertVerifier>,
+                                provider: &'static dyn CryptoProvider) -> ServerConfig {
         ServerConfig {
-            ciphersuites: ALL_CIPHERSUITES.to_vec(),
+            ciphersuites: ALL_CIPHERSUITES.iter()
+                .filter(|scs| provider.supports_suite(scs))
+                .cloned()
+                .collect(),
+            kx_groups: provider.default_kx_groups(),
+            crypto_provider: provider,
             ignore_client_order: false,
             mtu: None,
             session_storage: handy::ServerSessionMemoryCache::new(256),
@@ -211,7 +291,12 @@ impl ServerConfig {
             cert_resolver: Arc::new(handy::FailResolveChain {}),
             versions: vec![ ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2 ],
             verifier: client_cert_verifier,
+            raw_public_key_verifier: None,
             key_log: Arc::new(NoKeyLog {}),
+            time_provider: Arc::new(SystemTimeProvider),
+            ech_keys: Vec::new(),
+            #[cfg(feature = "esni")]
+            esni_keys: Vec::new(),
             #[cfg(feature = "quic")]
             max_early_data_size: 0,
         }
@@ -256,6 +341,18 @@ impl ServerConfig {
         Ok(())
     }
 
+    /// Sets a single certificate chain and a `SigningKey` for its
+    /// private key.  This is like `set_single_cert`, for keys which
+    /// rustls can't load itself: for example, ones held in an HSM.
+    ///
+    /// `cert_chain` is a vector of DER-encoded certificates.
+    pub fn set_single_cert_with_signing_key(&mut self,
+                                            cert_chain: Vec<key::Certificate>,
+                                            key: Box<dyn sign::SigningKey>) {
+        let resolver = handy::AlwaysResolvesChain::new_with_signing_key(cert_chain, key);
+        self.cert_resolver = Arc::new(resolver);
+    }
+
     /// Sets a single certificate chain, matching private key and OCSP
     /// response.  This certificate and key is used for all subsequent
     /// connections, irrespective of things like SNI hostname.
@@ -280,6 +377,22 @@ impl ServerConfig {
         Ok(())
     }
 
+    /// Sets a single raw public key (RFC7250) and matching private
+    /// key.  This is used for all subsequent connections, and clients
+    /// which can't accept raw public keys are refused.
+    ///
+    /// `spki` is the DER-encoded SubjectPublicKeyInfo of `key_der`,
+    /// a DER-encoded RSA, ECDSA or Ed25519 private key.
+    ///
+    /// This function fails if `key_der` is invalid.
+    pub fn set_single_raw_public_key(&mut self,
+                                     spki: Vec<u8>,
+                                     key_der: key::PrivateKey) -> Result<(), TLSError> {
+        let resolver = handy::AlwaysResolvesChain::new_raw_public_key(spki, &key_der)?;
+        self.cert_resolver = Arc::new(resolver);
+        Ok(())
+    }
+
     /// Set the ALPN protocol list to the given protocol names.
     /// Overwrites any existing configured protocols.
     ///
@@ -294,6 +407,15 @@ impl ServerConfig {
     pub fn set_client_certificate_verifier(&mut self, verifier: Arc<dyn verify::ClientCertVerifier>) {
         self.verifier = verifier;
     }
+
+    /// Require clients to authenticate with a raw public key (RFC7250)
+    /// rather than a certificate chain, and verify their keys with
+    /// `verifier`.  This takes the place of the `ClientCertVerifier`
+    /// for clients which offer raw public keys; others are refused.
+    pub fn set_client_raw_public_key_verifier(&mut self,
+                                              verifier: Arc<dyn verify::ClientRawPublicKeyVerifier>) {
+        self.raw_public_key_verifier = Some(verifier);
+    }
 }
 
 pub struct ServerSessionImpl {
@@ -322,7 +444,7 @@ impl ServerSessionImpl {
                -> ServerSessionImpl {
         ServerSessionImpl {
             config: server_config.clone(),
-            common: SessionCommon::new(server_config.mtu, false),
+            common: SessionCommon::new(server_config.mtu, false, server_config.crypto_provider),
             sni: None,
             alpn_protocol: None,
             quic_params: None,
@@ -394,8 +516,11 @@ impl ServerSessionImpl {
     }
 
     pub fn process_new_handshake_messages(&mut self) -> Result<(), TLSError> {
-        while let Some(msg) = self.common.handshake_joiner.frames.pop_front() {
-            self.process_main_protocol(msg)?;
+        while self.get_pending_signature().is_none() {
+            match self.common.handshake_joiner.frames.pop_front() {
+                Some(msg) => self.process_main_protocol(msg)?,
+                None => break,
+            }
         }
 
         Ok(())
@@ -430,7 +555,12 @@ impl ServerSessionImpl {
             return Err(TLSError::CorruptMessage);
         }
 
-        while let Some(msg) = self.common.message_deframer.frames.pop_front() {
+        while self.get_pending_signature().is_none() {
+            let msg = match self.common.message_deframer.frames.pop_front() {
+                Some(msg) => msg,
+                None => break,
+            };
+
             match self.process_msg(msg) {
                 Ok(_) => {}
                 Err(err) => {
@@ -444,6 +574,35 @@ impl ServerSessionImpl {
         Ok(())
     }
 
+    pub fn get_pending_signature(&self) -> Option<&sign::PendingSignature> {
+        self.state.as_ref()
+            .and_then(|st| st.pending_signature())
+    }
+
+    pub fn complete_signature(&mut self, signature: Vec<u8>) -> Result<(), TLSError> {
+        if let Some(ref err) = self.error {
+            return Err(err.clone());
+        }
+
+        if self.get_pending_signature().is_none() {
+            return Err(TLSError::General("no signature is pending".to_string()));
+        }
+
+        let st = self.state.take().unwrap();
+        let result = st.resume_with_signature(self, signature)
+            .and_then(|st| {
+                self.state = Some(st);
+                self.process_new_handshake_messages()
+            });
+
+        if let Err(err) = result {
+            self.error = Some(err.clone());
+            return Err(err);
+        }
+
+        self.process_new_packets()
+    }
+
     pub fn get_peer_certificates(&self) -> Option<Vec<key::Certificate>> {
         let certs = self.client_cert_chain.as_ref()?;
         let mut r = Vec::new();
@@ -515,6 +674,17 @@ impl ServerSession {
         self.imp.get_sni().map(|s| s.as_ref().into())
     }
 
+    /// Retrieves the identity in the client's certificate, once it's
+    /// been verified.
+    ///
+    /// This is `None` if the client didn't authenticate, or did so
+    /// with a raw public key.
+    pub fn get_peer_identity(&self) -> Option<PeerIdentity> {
+        self.imp.client_cert_chain.as_ref()?
+            .first()
+            .and_then(|cert| PeerIdentity::from_certificate(cert).ok())
+    }
+
     /// Application-controlled portion of the resumption ticket supplied by the client, if any.
     ///
     /// Recovered from the prior session's `set_resumption_data`. Integrity is guaranteed by rustls.
@@ -608,6 +778,14 @@ impl Session for ServerSession {
     fn get_negotiated_ciphersuite(&self) -> Option<&'static SupportedCipherSuite> {
         self.imp.get_negotiated_ciphersuite()
     }
+
+    fn get_pending_signature(&self) -> Option<&sign::PendingSignature> {
+        self.imp.get_pending_signature()
+    }
+
+    fn complete_signature(&mut self, signature: Vec<u8>) -> Result<(), TLSError> {
+        self.imp.complete_signature(signature)
+    }
 }
 
 impl io::Read for ServerSession {
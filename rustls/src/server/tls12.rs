@@ -1,5 +1,5 @@
 use crate::msgs::enums::{ContentType, HandshakeType, ProtocolVersion};
-use crate::msgs::enums::AlertDescription;
+use crate::msgs::enums::{AlertDescription, CertificateType};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::base::Payload;
 use crate::msgs::handshake::HandshakePayload;
@@ -10,6 +10,7 @@ use crate::msgs::codec::Codec;
 use crate::msgs::persist;
 use crate::session::SessionSecrets;
 use crate::server::ServerSessionImpl;
+use crate::key;
 use crate::verify;
 #[cfg(feature = "logging")]
 use crate::log::{warn, trace, debug};
@@ -45,11 +46,28 @@ impl hs::State for ExpectCertificate {
     }
 
     fn handle(mut self: Box<Self>, sess: &mut ServerSessionImpl, m: Message) -> hs::NextStateOrError {
-        let cert_chain = extract_handshake!(m, HandshakePayload::Certificate).unwrap();
+        // Clients without a raw public key send an empty certificate
+        // list instead.
+        let cert_chain = match extract_handshake!(m, HandshakePayload::RawPublicKey) {
+            Some(spki) if hs::client_cert_type(&sess.config) == CertificateType::RawPublicKey => {
+                Some(vec![ key::Certificate(spki.0.clone()) ])
+            }
+            _ => extract_handshake!(m, HandshakePayload::Certificate)
+                .filter(|chain| chain.is_empty() ||
+                        hs::client_cert_type(&sess.config) == CertificateType::X509)
+                .cloned(),
+        };
+
+        if cert_chain.is_none() {
+            sess.common.send_fatal_alert(AlertDescription::DecodeError);
+            return Err(TLSError::CorruptMessagePayload(ContentType::Handshake));
+        }
+
+        let cert_chain = cert_chain.unwrap();
         self.handshake.transcript.add_message(&m);
 
         if cert_chain.is_empty() &&
-           !sess.config.verifier.client_auth_mandatory() {
+           !hs::client_auth_mandatory(&sess.config) {
             debug!("client auth requested but no certificate supplied");
             self.handshake.transcript.abandon_client_auth();
             return Ok(self.into_expect_tls12_client_kx(None));
@@ -57,13 +75,13 @@ impl hs::State for ExpectCertificate {
 
         trace!("certs {:?}", cert_chain);
 
-        sess.config.verifier.verify_client_cert(cert_chain)
+        hs::verify_client_cert(sess, &cert_chain)
             .or_else(|err| {
                      hs::incompatible(sess, "certificate invalid");
                      Err(err)
                      })?;
 
-        let cert = ClientCertDetails::new(cert_chain.clone());
+        let cert = ClientCertDetails::new(cert_chain);
         Ok(self.into_expect_tls12_client_kx(Some(cert)))
     }
 }
@@ -115,22 +133,22 @@ impl hs::State for ExpectClientKX {
             .ok_or_else(|| TLSError::PeerMisbehavedError("key exchange completion failed"
                                                          .to_string()))?;
 
-        let hashalg = sess.common.get_suite_assert().get_hash();
+        let hmac = sess.config.crypto_provider.suite_hmac(sess.common.get_suite_assert())?;
         let secrets = if self.handshake.using_ems {
             let handshake_hash = self.handshake.transcript.get_current_hash();
             SessionSecrets::new_ems(&self.handshake.randoms,
                                     &handshake_hash,
-                                    hashalg,
+                                    hmac,
                                     &kxd.premaster_secret)
         } else {
             SessionSecrets::new(&self.handshake.randoms,
-                                hashalg,
+                                hmac,
                                 &kxd.premaster_secret)
         };
         sess.config.key_log.log("CLIENT_RANDOM",
                                 &secrets.randoms.client,
                                 &secrets.master_secret);
-        sess.common.start_encryption_tls12(secrets);
+        sess.common.start_encryption_tls12(secrets)?;
 
         if self.client_cert.is_some() {
             Ok(self.into_expect_tls12_certificate_verify())
@@ -168,7 +186,9 @@ impl hs::State for ExpectCertificateVerify {
             let handshake_msgs = self.handshake.transcript.take_handshake_buf();
             let certs = &self.client_cert.cert_chain;
 
-            verify::verify_signed_struct(&handshake_msgs, &certs[0], sig)
+            verify::verify_signed_struct(sess.config.crypto_provider,
+                                         &handshake_msgs, &certs[0],
+                                         hs::client_cert_type(&sess.config), sig)
         };
 
         if let Err(e) = rc {
@@ -255,7 +275,7 @@ pub fn emit_ticket(handshake: &mut HandshakeDetails,
         .get_encoding();
     let ticket = sess.config
         .ticketer
-        .encrypt(&plain)
+        .encrypt_at(&plain, sess.config.time_provider.now())
         .unwrap_or_else(Vec::new);
     let ticket_lifetime = sess.config.ticketer.get_lifetime();
 
@@ -1,5 +1,5 @@
 use crate::msgs::enums::{ContentType, HandshakeType, ProtocolVersion};
-use crate::msgs::enums::{AlertDescription, SignatureScheme, NamedGroup};
+use crate::msgs::enums::{AlertDescription, NamedGroup};
 use crate::msgs::enums::{Compression, PSKKeyExchangeMode};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::handshake::HandshakePayload;
@@ -27,10 +27,11 @@ use crate::msgs::persist;
 use crate::server::ServerSessionImpl;
 use crate::key_schedule::{KeySchedule, SecretKind};
 use crate::cipher;
+use crate::ech;
 use crate::verify;
 use crate::rand;
 use crate::sign;
-use crate::suites;
+use crate::kx::SupportedKxGroup;
 #[cfg(feature = "logging")]
 use crate::log::{warn, trace, debug};
 use crate::error::TLSError;
@@ -61,21 +62,21 @@ impl CompleteClientHelloHandling {
                     client_hello: &Message,
                     psk: &[u8],
                     binder: &[u8])
-                    -> bool {
+                    -> Result<bool, TLSError> {
         let binder_plaintext = match client_hello.payload {
             MessagePayload::Handshake(ref hmp) => hmp.get_encoding_for_binder_signing(),
             _ => unreachable!(),
         };
 
         let suite = sess.common.get_suite_assert();
-        let suite_hash = suite.get_hash();
+        let suite_hash = sess.config.crypto_provider.suite_hash(suite)?;
         let handshake_hash = self.handshake.transcript.get_hash_given(suite_hash, &binder_plaintext);
 
-        let key_schedule = KeySchedule::new(suite.hkdf_algorithm, &psk);
+        let key_schedule = KeySchedule::new(sess.config.crypto_provider, suite, &psk)?;
         let base_key = key_schedule.derive_for_empty_hash(SecretKind::ResumptionPSKBinderKey);
         let real_binder = key_schedule.sign_verify_data(&base_key, &handshake_hash);
 
-        constant_time::verify_slices_are_equal(&real_binder, binder).is_ok()
+        Ok(constant_time::verify_slices_are_equal(&real_binder, binder).is_ok())
     }
 
     fn into_expect_retried_client_hello(self) -> hs::NextState {
@@ -106,17 +107,17 @@ impl CompleteClientHelloHandling {
                          sess: &mut ServerSessionImpl,
                          session_id: &SessionID,
                          share: &KeyShareEntry,
+                         skxg: &'static dyn SupportedKxGroup,
                          chosen_psk_idx: Option<usize>,
                          resuming_psk: Option<&[u8]>)
                            -> Result<(), TLSError> {
         let mut extensions = Vec::new();
 
         // Do key exchange
-        let kxr = suites::KeyExchange::start_ecdhe(share.group)
-            .and_then(|kx| kx.complete(&share.payload.0))
+        let kxr = skxg.respond(&share.payload.0)
             .ok_or_else(|| TLSError::PeerMisbehavedError("key exchange failed".to_string()))?;
 
-        let kse = KeyShareEntry::new(share.group, kxr.pubkey.as_ref());
+        let kse = KeyShareEntry::new(share.group, &kxr.pubkey);
         extensions.push(ServerExtension::KeyShare(kse));
         extensions.push(ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_3));
 
@@ -124,27 +125,40 @@ impl CompleteClientHelloHandling {
             extensions.push(ServerExtension::PresharedKey(psk_idx as u16));
         }
 
+        let mut shp = HandshakeMessagePayload {
+            typ: HandshakeType::ServerHello,
+            payload: HandshakePayload::ServerHello(ServerHelloPayload {
+                legacy_version: ProtocolVersion::TLSv1_2,
+                random: Random::from_slice(&self.handshake.randoms.server),
+                session_id: *session_id,
+                cipher_suite: sess.common.get_suite_assert().suite,
+                compression_method: Compression::Null,
+                extensions,
+            }),
+        };
+
+        // Tell the client we used its ClientHelloInner, in the last
+        // 8 bytes of our random.
+        if self.handshake.ech_context.is_some() {
+            let confirmation = self.ech_confirmation(sess, &shp, ech::ACCEPT_CONFIRMATION_LABEL)?;
+            self.handshake.randoms.server[24..].copy_from_slice(&confirmation);
+
+            if let HandshakePayload::ServerHello(ref mut sh) = shp.payload {
+                sh.random = Random::from_slice(&self.handshake.randoms.server);
+            }
+        }
+
         let sh = Message {
             typ: ContentType::Handshake,
             version: ProtocolVersion::TLSv1_2,
-            payload: MessagePayload::Handshake(HandshakeMessagePayload {
-                typ: HandshakeType::ServerHello,
-                payload: HandshakePayload::ServerHello(ServerHelloPayload {
-                    legacy_version: ProtocolVersion::TLSv1_2,
-                    random: Random::from_slice(&self.handshake.randoms.server),
-                    session_id: *session_id,
-                    cipher_suite: sess.common.get_suite_assert().suite,
-                    compression_method: Compression::Null,
-                    extensions,
-                }),
-            }),
+            payload: MessagePayload::Handshake(shp),
         };
 
         hs::check_aligned_handshake(sess)?;
 
         #[cfg(feature = "quic")]
         let client_hello_hash = self.handshake.transcript
-            .get_hash_given(sess.common.get_suite_assert().get_hash(), &[]);
+            .get_hash_given(sess.config.crypto_provider.suite_hash(sess.common.get_suite_assert())?, &[]);
 
         trace!("sending server hello {:?}", sh);
         self.handshake.transcript.add_message(&sh);
@@ -154,7 +168,7 @@ impl CompleteClientHelloHandling {
         let suite = sess.common.get_suite_assert();
         let mut key_schedule;
         if let Some(psk) = resuming_psk {
-            key_schedule = KeySchedule::new(suite.hkdf_algorithm, psk);
+            key_schedule = KeySchedule::new(sess.config.crypto_provider, suite, psk)?;
 
             #[cfg(feature = "quic")] {
                 if sess.common.protocol == Protocol::Quic {
@@ -166,11 +180,11 @@ impl CompleteClientHelloHandling {
                             &self.handshake.randoms.client);
                     // If 0-RTT should be rejected, this will be clobbered by ExtensionProcessing
                     // before the application can see.
-                    sess.common.quic.early_secret = Some(client_early_traffic_secret);
+                    sess.common.quic.early_secret = Some(quic::ring_prk(suite, &client_early_traffic_secret));
                 }
             }
         } else {
-            key_schedule = KeySchedule::new_with_empty_secret(suite.hkdf_algorithm);
+            key_schedule = KeySchedule::new_with_empty_secret(sess.config.crypto_provider, suite)?;
         }
         key_schedule.input_secret(&kxr.premaster_secret);
 
@@ -180,20 +194,17 @@ impl CompleteClientHelloHandling {
             &handshake_hash,
             &*sess.config.key_log,
             &self.handshake.randoms.client);
-        sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+        sess.common.set_message_encrypter(cipher::new_tls13_write(sess.config.crypto_provider, suite, &write_key)?);
 
         let read_key = key_schedule.derive_logged_secret(
             SecretKind::ClientHandshakeTrafficSecret,
             &handshake_hash,
             &*sess.config.key_log,
             &self.handshake.randoms.client);
-        sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
+        sess.common.set_message_decrypter(cipher::new_tls13_read(sess.config.crypto_provider, suite, &read_key)?);
 
         #[cfg(feature = "quic")] {
-            sess.common.quic.hs_secrets = Some(quic::Secrets {
-                client: read_key.clone(),
-                server: write_key.clone(),
-            });
+            sess.common.quic.hs_secrets = Some(quic::Secrets::new(suite, &read_key, &write_key));
         }
 
         key_schedule.current_client_traffic_secret = Some(read_key);
@@ -216,9 +227,23 @@ impl CompleteClientHelloHandling {
         sess.common.send_msg(m, false);
     }
 
+    /// The ECH acceptance confirmation for the ServerHello or
+    /// HelloRetryRequest `hmp`, which comes next in the transcript.
+    fn ech_confirmation(&self,
+                        sess: &ServerSessionImpl,
+                        hmp: &HandshakeMessagePayload,
+                        label: &[u8]) -> Result<[u8; 8], TLSError> {
+        let suite = sess.common.get_suite_assert();
+        let transcript_hash = self.handshake.transcript
+            .get_hash_given(sess.config.crypto_provider.suite_hash(suite)?,
+                            &hmp.get_encoding_for_ech_confirmation());
+        ech::compute_confirmation(sess.config.crypto_provider, suite,
+                                  &self.handshake.randoms.client, &transcript_hash, label)
+    }
+
     fn emit_hello_retry_request(&mut self,
                                 sess: &mut ServerSessionImpl,
-                                group: NamedGroup) {
+                                group: NamedGroup) -> Result<(), TLSError> {
         let mut req = HelloRetryRequest {
             legacy_version: ProtocolVersion::TLSv1_2,
             session_id: SessionID::empty(),
@@ -229,19 +254,37 @@ impl CompleteClientHelloHandling {
         req.extensions.push(HelloRetryExtension::KeyShare(group));
         req.extensions.push(HelloRetryExtension::SupportedVersions(ProtocolVersion::TLSv1_3));
 
+        let accepted_ech = self.handshake.ech_context.is_some();
+        if accepted_ech {
+            req.extensions.push(HelloRetryExtension::EncryptedClientHello([0u8; 8]));
+        }
+
+        let mut hrrp = HandshakeMessagePayload {
+            typ: HandshakeType::HelloRetryRequest,
+            payload: HandshakePayload::HelloRetryRequest(req),
+        };
+
+        self.handshake.transcript.rollup_for_hrr();
+
+        if accepted_ech {
+            let confirmation = self.ech_confirmation(sess, &hrrp, ech::HRR_ACCEPT_CONFIRMATION_LABEL)?;
+
+            if let HandshakePayload::HelloRetryRequest(ref mut req) = hrrp.payload {
+                req.extensions.pop();
+                req.extensions.push(HelloRetryExtension::EncryptedClientHello(confirmation));
+            }
+        }
+
         let m = Message {
             typ: ContentType::Handshake,
             version: ProtocolVersion::TLSv1_2,
-            payload: MessagePayload::Handshake(HandshakeMessagePayload {
-                typ: HandshakeType::HelloRetryRequest,
-                payload: HandshakePayload::HelloRetryRequest(req),
-            }),
+            payload: MessagePayload::Handshake(hrrp),
         };
 
         trace!("Requesting retry {:?}", m);
-        self.handshake.transcript.rollup_for_hrr();
         self.handshake.transcript.add_message(&m);
         sess.common.send_msg(m, false);
+        Ok(())
     }
 
     fn emit_encrypted_extensions(&mut self,
@@ -272,7 +315,7 @@ impl CompleteClientHelloHandling {
     }
 
     fn emit_certificate_req_tls13(&mut self, sess: &mut ServerSessionImpl) -> bool {
-        if !sess.config.verifier.offer_client_auth() {
+        if !hs::offer_client_auth(&sess.config) {
             return false;
         }
 
@@ -281,10 +324,14 @@ impl CompleteClientHelloHandling {
             extensions: Vec::new(),
         };
 
-        let schemes = verify::supported_verify_schemes();
-        cr.extensions.push(CertReqExtension::SignatureAlgorithms(schemes.to_vec()));
+        let schemes = sess.config.crypto_provider.supported_verify_schemes();
+        cr.extensions.push(CertReqExtension::SignatureAlgorithms(schemes));
 
-        let names = sess.config.verifier.client_auth_root_subjects();
+        let names = if sess.config.raw_public_key_verifier.is_some() {
+            Vec::new()
+        } else {
+            sess.config.verifier.client_auth_root_subjects()
+        };
         if !names.is_empty() {
             cr.extensions.push(CertReqExtension::AuthorityNames(names));
         }
@@ -350,25 +397,18 @@ impl CompleteClientHelloHandling {
         sess.common.send_msg(c, true);
     }
 
-    fn emit_certificate_verify_tls13(&mut self,
-                                     sess: &mut ServerSessionImpl,
-                                     server_key: &mut sign::CertifiedKey,
-                                     schemes: &[SignatureScheme])
-                                     -> Result<(), TLSError> {
+    /// The message we sign in our CertificateVerify.
+    fn certificate_verify_message(&self) -> Vec<u8> {
         let mut message = Vec::new();
         message.resize(64, 0x20u8);
         message.extend_from_slice(b"TLS 1.3, server CertificateVerify\x00");
         message.extend_from_slice(&self.handshake.transcript.get_current_hash());
+        message
+    }
 
-        let signing_key = &server_key.key;
-        let signer = signing_key.choose_scheme(schemes)
-            .ok_or_else(|| hs::incompatible(sess, "no overlapping sigschemes"))?;
-
-        let scheme = signer.get_scheme();
-        let sig = signer.sign(&message)?;
-
-        let cv = DigitallySignedStruct::new(scheme, sig);
-
+    fn emit_certificate_verify_tls13(&mut self,
+                                     sess: &mut ServerSessionImpl,
+                                     cv: DigitallySignedStruct) {
         let m = Message {
             typ: ContentType::Handshake,
             version: ProtocolVersion::TLSv1_3,
@@ -381,10 +421,9 @@ impl CompleteClientHelloHandling {
         trace!("sending certificate-verify {:?}", m);
         self.handshake.transcript.add_message(&m);
         sess.common.send_msg(m, true);
-        Ok(())
     }
 
-    fn emit_finished_tls13(&mut self, sess: &mut ServerSessionImpl) {
+    fn emit_finished_tls13(&mut self, sess: &mut ServerSessionImpl) -> Result<(), TLSError> {
         let handshake_hash = self.handshake.transcript.get_current_hash();
         let verify_data = sess.common
             .get_key_schedule()
@@ -414,17 +453,13 @@ impl CompleteClientHelloHandling {
                                   &*sess.config.key_log,
                                   &self.handshake.randoms.client);
         let suite = sess.common.get_suite_assert();
-        sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+        sess.common.set_message_encrypter(cipher::new_tls13_write(sess.config.crypto_provider, suite, &write_key)?);
 
         #[cfg(feature = "quic")] {
             let read_key = sess.common.get_key_schedule()
-                .derive(sess.common.get_key_schedule().algorithm(),
-                        SecretKind::ClientApplicationTrafficSecret,
+                .derive(SecretKind::ClientApplicationTrafficSecret,
                         &self.handshake.hash_at_server_fin);
-            sess.common.quic.traffic_secrets = Some(quic::Secrets {
-                client: read_key,
-                server: write_key.clone(),
-            });
+            sess.common.quic.traffic_secrets = Some(quic::Secrets::new(suite, &read_key, &write_key));
         }
 
         sess.common.get_mut_key_schedule()
@@ -438,6 +473,7 @@ impl CompleteClientHelloHandling {
         sess.common
             .get_mut_key_schedule()
             .current_exporter_secret = Some(exporter_secret);
+        Ok(())
     }
 
     fn attempt_tls13_ticket_decryption(&mut self,
@@ -446,7 +482,7 @@ impl CompleteClientHelloHandling {
         if sess.config.ticketer.enabled() {
             sess.config
                 .ticketer
-                .decrypt(ticket)
+                .decrypt_at(ticket, sess.config.time_provider.now())
                 .and_then(|plain| persist::ServerSessionValue::read_bytes(&plain))
         } else {
             sess.config
@@ -488,21 +524,35 @@ impl CompleteClientHelloHandling {
             .map(|share| share.group)
             .collect();
 
-        let supported_groups = suites::KeyExchange::supported_groups();
-        let chosen_group = supported_groups
+        let supported_groups = &sess.config.kx_groups;
+        let mut chosen_group = supported_groups
             .iter()
-            .filter(|group| share_groups.contains(group))
+            .filter(|skxg| share_groups.contains(&skxg.name()))
             .nth(0)
             .cloned();
 
+        // If our most preferred group in common with the client is worth
+        // a retry, insist on it even if the client sent other shares.
+        let preferred_group = supported_groups
+            .iter()
+            .find(|skxg| groups_ext.contains(&skxg.name()));
+
+        if let Some(preferred_group) = preferred_group {
+            if preferred_group.worth_hello_retry_request() &&
+                !share_groups.contains(&preferred_group.name()) &&
+                !self.done_retry {
+                chosen_group = None;
+            }
+        }
+
         if chosen_group.is_none() {
             // We don't have a suitable key share.  Choose a suitable group and
             // send a HelloRetryRequest.
             let retry_group_maybe = supported_groups
                 .iter()
+                .map(|skxg| skxg.name())
                 .filter(|group| groups_ext.contains(group))
-                .nth(0)
-                .cloned();
+                .nth(0);
             self.handshake.transcript.add_message(chm);
 
             if let Some(group) = retry_group_maybe {
@@ -510,7 +560,7 @@ impl CompleteClientHelloHandling {
                     return Err(hs::illegal_param(sess, "did not follow retry request"));
                 }
 
-                self.emit_hello_retry_request(sess, group);
+                self.emit_hello_retry_request(sess, group)?;
                 self.emit_fake_ccs(sess);
                 return Ok(self.into_expect_retried_client_hello());
             }
@@ -522,7 +572,7 @@ impl CompleteClientHelloHandling {
 
         let chosen_group = chosen_group.unwrap();
         let chosen_share = shares_ext.iter()
-            .find(|share| share.group == chosen_group)
+            .find(|share| share.group == chosen_group.name())
             .unwrap();
 
         let mut chosen_psk_index = None;
@@ -549,7 +599,7 @@ impl CompleteClientHelloHandling {
 
                 let resume = maybe_resume.unwrap();
 
-                if !self.check_binder(sess, chm, &resume.master_secret.0, &psk_offer.binders[i].0) {
+                if !self.check_binder(sess, chm, &resume.master_secret.0, &psk_offer.binders[i].0)? {
                     sess.common.send_fatal_alert(AlertDescription::DecryptError);
                     return Err(TLSError::PeerMisbehavedError("client sent wrong binder".to_string()));
                 }
@@ -576,24 +626,37 @@ impl CompleteClientHelloHandling {
         let full_handshake = resumedata.is_none();
         self.handshake.transcript.add_message(chm);
         self.emit_server_hello(sess, &client_hello.session_id,
-                               chosen_share, chosen_psk_index,
+                               chosen_share, chosen_group, chosen_psk_index,
                                resumedata.as_ref().map(|x| &x.master_secret.0[..]))?;
         if !self.done_retry {
             self.emit_fake_ccs(sess);
         }
         self.emit_encrypted_extensions(sess, &mut server_key, client_hello, resumedata.as_ref())?;
 
-        let doing_client_auth = if full_handshake {
-            let client_auth = self.emit_certificate_req_tls13(sess);
-            self.emit_certificate_tls13(sess, &mut server_key);
-            self.emit_certificate_verify_tls13(sess, &mut server_key, &sigschemes_ext)?;
-            client_auth
-        } else {
-            false
-        };
+        if !full_handshake {
+            hs::check_aligned_handshake(sess)?;
+            return self.finish(sess, false);
+        }
 
+        let doing_client_auth = self.emit_certificate_req_tls13(sess);
+        self.emit_certificate_tls13(sess, &mut server_key);
         hs::check_aligned_handshake(sess)?;
-        self.emit_finished_tls13(sess);
+
+        let message = self.certificate_verify_message();
+        let signer = server_key.key.choose_scheme(&sigschemes_ext)
+            .ok_or_else(|| hs::incompatible(sess, "no overlapping sigschemes"))?;
+
+        hs::ExpectSignature::sign(sess, &*signer, message, move |sess, cv| {
+            let mut st = self;
+            st.emit_certificate_verify_tls13(sess, cv);
+            st.finish(sess, doing_client_auth)
+        })
+    }
+
+    fn finish(mut self,
+              sess: &mut ServerSessionImpl,
+              doing_client_auth: bool) -> hs::NextStateOrError {
+        self.emit_finished_tls13(sess)?;
 
         if doing_client_auth {
             Ok(self.into_expect_certificate())
@@ -645,7 +708,7 @@ impl hs::State for ExpectCertificate {
         let cert_chain = certp.convert();
 
         if cert_chain.is_empty() {
-            if !sess.config.verifier.client_auth_mandatory() {
+            if !hs::client_auth_mandatory(&sess.config) {
                 debug!("client auth requested but no certificate supplied");
                 self.handshake.transcript.abandon_client_auth();
                 return Ok(self.into_expect_finished());
@@ -655,7 +718,7 @@ impl hs::State for ExpectCertificate {
             return Err(TLSError::NoCertificatesPresented);
         }
 
-        sess.config.get_verifier().verify_client_cert(&cert_chain)
+        hs::verify_client_cert(sess, &cert_chain)
             .or_else(|err| {
                      hs::incompatible(sess, "certificate invalid");
                      Err(err)
@@ -693,7 +756,9 @@ impl hs::State for ExpectCertificateVerify {
             self.handshake.transcript.abandon_client_auth();
             let certs = &self.client_cert.cert_chain;
 
-            verify::verify_tls13(&certs[0],
+            verify::verify_tls13(sess.config.crypto_provider,
+                                 &certs[0],
+                                 hs::client_cert_type(&sess.config),
                                  sig,
                                  &handshake_hash,
                                  b"TLS 1.3, client CertificateVerify\x00")
@@ -724,8 +789,7 @@ fn get_server_session_value(handshake: &mut HandshakeDetails,
         .get_current_hash();
     let key_schedule = sess.common.get_key_schedule();
     let resumption_master_secret =
-        key_schedule.derive(key_schedule.algorithm(),
-                            SecretKind::ResumptionMasterSecret,
+        key_schedule.derive(SecretKind::ResumptionMasterSecret,
                             &handshake_hash);
     let secret = sess.common
         .get_key_schedule()
@@ -754,13 +818,13 @@ impl ExpectFinished {
 
     fn emit_stateless_ticket(&mut self, sess: &mut ServerSessionImpl) {
         debug_assert!(self.send_ticket);
-        let nonce = rand::random_vec(32);
+        let nonce = rand::random_vec(sess.config.crypto_provider, 32);
         let plain = get_server_session_value(&mut self.handshake,
                                              sess, &nonce)
             .get_encoding();
         let maybe_ticket = sess.config
             .ticketer
-            .encrypt(&plain);
+            .encrypt_at(&plain, sess.config.time_provider.now());
         let ticket_lifetime = sess.config.ticketer.get_lifetime();
 
         if maybe_ticket.is_none() {
@@ -768,7 +832,7 @@ impl ExpectFinished {
         }
 
         let ticket = maybe_ticket.unwrap();
-        let age_add = rand::random_u32(); // nb, we don't do 0-RTT data, so whatever
+        let age_add = rand::random_u32(sess.config.crypto_provider); // nb, we don't do 0-RTT data, so whatever
         #[allow(unused_mut)]
         let mut payload = NewSessionTicketPayloadTLS13::new(ticket_lifetime, age_add, nonce, ticket);
         #[cfg(feature = "quic")] {
@@ -792,15 +856,15 @@ impl ExpectFinished {
 
     fn emit_stateful_ticket(&mut self, sess: &mut ServerSessionImpl) {
         debug_assert!(self.send_ticket);
-        let nonce = rand::random_vec(32);
-        let id = rand::random_vec(32);
+        let nonce = rand::random_vec(sess.config.crypto_provider, 32);
+        let id = rand::random_vec(sess.config.crypto_provider, 32);
         let plain = get_server_session_value(&mut self.handshake,
                                              sess, &nonce)
             .get_encoding();
 
         if sess.config.session_storage.put(id.clone(), plain) {
             let stateful_lifetime = 24 * 60 * 60; // this is a bit of a punt
-            let age_add = rand::random_u32();
+            let age_add = rand::random_u32(sess.config.crypto_provider);
             #[allow(unused_mut)]
             let mut payload = NewSessionTicketPayloadTLS13::new(stateful_lifetime, age_add, nonce, id);
             #[cfg(feature = "quic")] {
@@ -862,7 +926,7 @@ impl hs::State for ExpectFinished {
 
         let suite = sess.common.get_suite_assert();
         hs::check_aligned_handshake(sess)?;
-        sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
+        sess.common.set_message_decrypter(cipher::new_tls13_read(sess.config.crypto_provider, suite, &read_key)?);
         sess.common
             .get_mut_key_schedule()
             .current_client_traffic_secret = Some(read_key);
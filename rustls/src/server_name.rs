@@ -0,0 +1,96 @@
+use std::net::IpAddr;
+
+use webpki;
+
+use crate::error::TLSError;
+
+/// The name of the server a client wants to talk to: either a
+/// DNS name, or an IP address.
+///
+/// The certificate presented by the server must be valid for this
+/// name.  IP addresses are matched against iPAddress subjectAltNames
+/// only, and aren't sent in the SNI extension (RFC 6066 forbids it).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerName {
+    /// A DNS name.
+    DNSName(webpki::DNSName),
+
+    /// An IPv4 or IPv6 address.
+    IPAddress(IpAddr),
+}
+
+impl ServerName {
+    /// Parse `name`, which is either an IP address literal or a
+    /// DNS name.  IPv6 addresses are written without brackets.
+    pub fn try_from_ascii_str(name: &str) -> Result<ServerName, TLSError> {
+        if let Ok(ip) = name.parse::<IpAddr>() {
+            return Ok(ServerName::IPAddress(ip));
+        }
+
+        webpki::DNSNameRef::try_from_ascii_str(name)
+            .map(|dns_name| ServerName::DNSName(dns_name.to_owned()))
+            .map_err(|_| TLSError::InvalidDNSName(name.to_string()))
+    }
+
+    /// Return the DNS name, if this is one.
+    pub fn dns_name(&self) -> Option<webpki::DNSNameRef> {
+        match *self {
+            ServerName::DNSName(ref dns_name) => Some(dns_name.as_ref()),
+            ServerName::IPAddress(_) => None,
+        }
+    }
+
+    /// The name in text form, for use as a key in session storage.
+    /// DNS names can't be all-numeric, so these don't collide.
+    pub(crate) fn to_key(&self) -> Vec<u8> {
+        match *self {
+            ServerName::DNSName(ref dns_name) => {
+                let dns_name: &str = dns_name.as_ref().into();
+                dns_name.as_bytes().to_vec()
+            }
+            ServerName::IPAddress(ref ip) => ip.to_string().into_bytes(),
+        }
+    }
+}
+
+impl<'a> From<webpki::DNSNameRef<'a>> for ServerName {
+    fn from(dns_name: webpki::DNSNameRef<'a>) -> ServerName {
+        ServerName::DNSName(dns_name.to_owned())
+    }
+}
+
+impl From<webpki::DNSName> for ServerName {
+    fn from(dns_name: webpki::DNSName) -> ServerName {
+        ServerName::DNSName(dns_name)
+    }
+}
+
+impl From<IpAddr> for ServerName {
+    fn from(ip: IpAddr) -> ServerName {
+        ServerName::IPAddress(ip)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_try_from_ascii_str() {
+        let name = ServerName::try_from_ascii_str("example.com").unwrap();
+        assert!(name.dns_name().is_some());
+        assert_eq!(name.to_key(), b"example.com".to_vec());
+
+        let name = ServerName::try_from_ascii_str("10.0.0.5").unwrap();
+        assert_eq!(name, ServerName::IPAddress("10.0.0.5".parse().unwrap()));
+        assert!(name.dns_name().is_none());
+        assert_eq!(name.to_key(), b"10.0.0.5".to_vec());
+
+        let name = ServerName::try_from_ascii_str("2001:db8::1").unwrap();
+        assert_eq!(name, ServerName::IPAddress("2001:db8::1".parse().unwrap()));
+
+        assert_eq!(ServerName::try_from_ascii_str("[2001:db8::1]"),
+                   Err(TLSError::InvalidDNSName("[2001:db8::1]".to_string())));
+        assert!(ServerName::try_from_ascii_str("not a name").is_err());
+    }
+}
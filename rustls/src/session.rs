@@ -1,4 +1,3 @@
-use ring;
 use std::io::{Read, Write};
 use crate::msgs::message::{BorrowMessage, Message, MessagePayload};
 use crate::msgs::deframer::MessageDeframer;
@@ -13,7 +12,9 @@ use crate::suites::SupportedCipherSuite;
 use crate::cipher::{MessageDecrypter, MessageEncrypter, self};
 use crate::vecbuf::{ChunkVecBuffer, WriteV};
 use crate::key;
-use crate::key_schedule::{SecretKind, KeySchedule};
+use crate::sign::PendingSignature;
+use crate::key_schedule::{SecretKind, KeySchedule, Prk};
+use crate::crypto::{CryptoProvider, Hmac};
 use crate::prf;
 use crate::rand;
 use crate::quic;
@@ -138,6 +139,21 @@ pub trait Session: quic::QuicExt + Read + Write + Send + Sync {
     /// This returns None until the ciphersuite is agreed.
     fn get_negotiated_ciphersuite(&self) -> Option<&'static SupportedCipherSuite>;
 
+    /// Retrieves the signature the handshake is waiting for.
+    ///
+    /// This is `Some` once our `sign::Signer` returns `Ok(None)`
+    /// from `start_sign`.  Until the signature is supplied with
+    /// `complete_signature`, no further TLS messages are processed.
+    fn get_pending_signature(&self) -> Option<&PendingSignature>;
+
+    /// Supplies the signature of `get_pending_signature().message()`,
+    /// and continues the handshake.
+    ///
+    /// Any TLS messages received meanwhile are then processed, as if
+    /// by `process_new_packets`, and errors are reported in the same
+    /// way.  It is an error to call this when no signature is pending.
+    fn complete_signature(&mut self, signature: Vec<u8>) -> Result<(), TLSError>;
+
     /// This function uses `io` to complete any outstanding IO for
     /// this session.
     ///
@@ -153,6 +169,9 @@ pub trait Session: quic::QuicExt + Read + Write + Send + Sync {
     /// - Otherwise, if `wants_read` is true, `read_tls` is invoked
     ///   once.
     ///
+    /// This returns early if the handshake is waiting for a signature:
+    /// see `get_pending_signature()`.
+    ///
     /// The return value is the number of bytes read from and written
     /// to `io`, respectively.
     ///
@@ -196,6 +215,10 @@ pub trait Session: quic::QuicExt + Read + Write + Send + Sync {
                 },
             };
 
+            if self.get_pending_signature().is_some() {
+                return Ok((rdlen, wrlen));
+            }
+
             match (eof, until_handshaked, self.is_handshaking()) {
                 (_, true, false) => return Ok((rdlen, wrlen)),
                 (_, false, _) => return Ok((rdlen, wrlen)),
@@ -223,25 +246,25 @@ pub struct SessionRandoms {
 static TLS12_DOWNGRADE_SENTINEL: &[u8] = &[0x44, 0x4f, 0x57, 0x4e, 0x47, 0x52, 0x44, 0x01];
 
 impl SessionRandoms {
-    pub fn for_server() -> SessionRandoms {
+    pub fn for_server(provider: &dyn CryptoProvider) -> SessionRandoms {
         let mut ret = SessionRandoms {
             we_are_client: false,
             client: [0u8; 32],
             server: [0u8; 32],
         };
 
-        rand::fill_random(&mut ret.server);
+        rand::fill_random(provider, &mut ret.server);
         ret
     }
 
-    pub fn for_client() -> SessionRandoms {
+    pub fn for_client(provider: &dyn CryptoProvider) -> SessionRandoms {
         let mut ret = SessionRandoms {
             we_are_client: true,
             client: [0u8; 32],
             server: [0u8; 32],
         };
 
-        rand::fill_random(&mut ret.client);
+        rand::fill_random(provider, &mut ret.client);
         ret
     }
 
@@ -270,24 +293,24 @@ fn join_randoms(first: &[u8], second: &[u8]) -> [u8; 64] {
 
 pub struct SessionSecrets {
     pub randoms: SessionRandoms,
-    hash: &'static ring::digest::Algorithm,
+    hmac: &'static dyn Hmac,
     pub master_secret: [u8; 48],
 }
 
 impl SessionSecrets {
     pub fn new(randoms: &SessionRandoms,
-               hashalg: &'static ring::digest::Algorithm,
+               hmac: &'static dyn Hmac,
                pms: &[u8])
                -> SessionSecrets {
         let mut ret = SessionSecrets {
             randoms: randoms.clone(),
-            hash: hashalg,
+            hmac,
             master_secret: [0u8; 48],
         };
 
         let randoms = join_randoms(&ret.randoms.client, &ret.randoms.server);
         prf::prf(&mut ret.master_secret,
-                 ret.hash,
+                 ret.hmac,
                  pms,
                  b"master secret",
                  &randoms);
@@ -296,16 +319,16 @@ impl SessionSecrets {
 
     pub fn new_ems(randoms: &SessionRandoms,
                    hs_hash: &[u8],
-                   hashalg: &'static ring::digest::Algorithm,
+                   hmac: &'static dyn Hmac,
                    pms: &[u8]) -> SessionSecrets {
         let mut ret = SessionSecrets {
             randoms: randoms.clone(),
-            hash: hashalg,
+            hmac,
             master_secret: [0u8; 48]
         };
 
         prf::prf(&mut ret.master_secret,
-                 ret.hash,
+                 ret.hmac,
                  pms,
                  b"extended master secret",
                  hs_hash);
@@ -313,12 +336,12 @@ impl SessionSecrets {
     }
 
     pub fn new_resume(randoms: &SessionRandoms,
-                      hashalg: &'static ring::digest::Algorithm,
+                      hmac: &'static dyn Hmac,
                       master_secret: &[u8])
                       -> SessionSecrets {
         let mut ret = SessionSecrets {
             randoms: randoms.clone(),
-            hash: hashalg,
+            hmac,
             master_secret: [0u8; 48],
         };
         ret.master_secret.as_mut().write_all(master_secret).unwrap();
@@ -333,7 +356,7 @@ impl SessionSecrets {
         // Don't design security protocols on drugs, kids.
         let randoms = join_randoms(&self.randoms.server, &self.randoms.client);
         prf::prf(&mut out,
-                 self.hash,
+                 self.hmac,
                  &self.master_secret,
                  b"key expansion",
                  &randoms);
@@ -352,7 +375,7 @@ impl SessionSecrets {
         out.resize(12, 0u8);
 
         prf::prf(&mut out,
-                 self.hash,
+                 self.hmac,
                  &self.master_secret,
                  label,
                  handshake_hash);
@@ -381,7 +404,7 @@ impl SessionSecrets {
         }
 
         prf::prf(output,
-                 self.hash,
+                 self.hmac,
                  &self.master_secret,
                  label,
                  &randoms)
@@ -412,7 +435,9 @@ pub struct SessionCommon {
     pub we_encrypting: bool,
     pub traffic: bool,
     pub early_traffic: bool,
-    pub want_write_key_update: bool,
+    /// The traffic secret and encrypter to switch to after we next
+    /// send a KeyUpdate, if the peer asked for one.
+    pending_write_key_update: Option<(Prk, Box<dyn MessageEncrypter>)>,
     pub message_deframer: MessageDeframer,
     pub handshake_joiner: HandshakeJoiner,
     pub message_fragmenter: MessageFragmenter,
@@ -421,12 +446,16 @@ pub struct SessionCommon {
     pub sendable_tls: ChunkVecBuffer,
     /// Protocol whose key schedule should be used. Unused for TLS < 1.3.
     pub protocol: Protocol,
+    /// Where our cryptography comes from.
+    pub crypto_provider: &'static dyn CryptoProvider,
     #[cfg(feature = "quic")]
     pub(crate) quic: Quic,
 }
 
 impl SessionCommon {
-    pub fn new(mtu: Option<usize>, client: bool) -> SessionCommon {
+    pub fn new(mtu: Option<usize>,
+               client: bool,
+               crypto_provider: &'static dyn CryptoProvider) -> SessionCommon {
         SessionCommon {
             negotiated_version: None,
             is_client: client,
@@ -442,7 +471,7 @@ impl SessionCommon {
             we_encrypting: false,
             traffic: false,
             early_traffic: false,
-            want_write_key_update: false,
+            pending_write_key_update: None,
             message_deframer: MessageDeframer::new(),
             handshake_joiner: HandshakeJoiner::new(),
             message_fragmenter: MessageFragmenter::new(mtu.unwrap_or(MAX_FRAGMENT_LEN)),
@@ -450,6 +479,7 @@ impl SessionCommon {
             sendable_plaintext: ChunkVecBuffer::new(),
             sendable_tls: ChunkVecBuffer::new(),
             protocol: Protocol::Tls13,
+            crypto_provider,
             #[cfg(feature = "quic")]
             quic: Quic::new(),
         }
@@ -578,18 +608,13 @@ impl SessionCommon {
     fn do_write_key_update(&mut self) {
         // TLS1.3 putting key update triggering here breaks layering
         // between the handshake and record layer.
-        let kind = if self.is_client {
-            SecretKind::ClientApplicationTrafficSecret
-        } else {
-            SecretKind::ServerApplicationTrafficSecret
+        let (write_key, encrypter) = match self.pending_write_key_update.take() {
+            Some(pending) => pending,
+            None => return,
         };
 
-        self.want_write_key_update = false;
         self.send_msg_encrypt(Message::build_key_update_notify());
-
-        let write_key = self.get_key_schedule().derive_next(kind);
-        let scs = self.get_suite_assert();
-        self.set_message_encrypter(cipher::new_tls13_write(scs, &write_key));
+        self.set_message_encrypter(encrypter);
 
         if self.is_client {
             self.get_mut_key_schedule().current_client_traffic_secret = Some(write_key);
@@ -601,7 +626,7 @@ impl SessionCommon {
     /// Fragment `m`, encrypt the fragments, and then queue
     /// the encrypted fragments for sending.
     pub fn send_msg_encrypt(&mut self, m: Message) {
-        if self.want_write_key_update {
+        if self.pending_write_key_update.is_some() {
             self.do_write_key_update();
         }
 
@@ -617,7 +642,7 @@ impl SessionCommon {
     fn send_appdata_encrypt(&mut self,
                             payload: &[u8],
                             limit: Limit) -> usize {
-        if self.want_write_key_update {
+        if self.pending_write_key_update.is_some() {
             self.do_write_key_update();
         }
 
@@ -791,11 +816,12 @@ impl SessionCommon {
         Ok(len)
     }
 
-    pub fn start_encryption_tls12(&mut self, secrets: SessionSecrets) {
-        let (dec, enc) = cipher::new_tls12(self.get_suite_assert(), &secrets);
+    pub fn start_encryption_tls12(&mut self, secrets: SessionSecrets) -> Result<(), TLSError> {
+        let (dec, enc) = cipher::new_tls12(self.crypto_provider, self.get_suite_assert(), &secrets)?;
         self.message_encrypter = enc;
         self.message_decrypter = dec;
         self.secrets = Some(secrets);
+        Ok(())
     }
 
     pub fn peer_now_encrypting(&mut self) {
@@ -844,10 +870,20 @@ impl SessionCommon {
             return Err(TLSError::PeerMisbehavedError(msg));
         }
 
+        let suite = self.get_suite_assert();
         match kur {
             KeyUpdateRequest::UpdateNotRequested => {}
             KeyUpdateRequest::UpdateRequested => {
-                self.want_write_key_update = true;
+                // Make our next write-side keys now, while we can
+                // still report an error.
+                let write_kind = if self.is_client {
+                    SecretKind::ClientApplicationTrafficSecret
+                } else {
+                    SecretKind::ServerApplicationTrafficSecret
+                };
+                let write_key = self.get_key_schedule().derive_next(write_kind);
+                let encrypter = cipher::new_tls13_write(self.crypto_provider, suite, &write_key)?;
+                self.pending_write_key_update = Some((write_key, encrypter));
             }
             _ => {
                 self.send_fatal_alert(AlertDescription::IllegalParameter);
@@ -858,8 +894,7 @@ impl SessionCommon {
         // Update our read-side keys.
         let new_read_key = self.get_key_schedule()
             .derive_next(read_kind);
-        let suite = self.get_suite_assert();
-        self.set_message_decrypter(cipher::new_tls13_read(suite, &new_read_key));
+        self.set_message_decrypter(cipher::new_tls13_read(self.crypto_provider, suite, &new_read_key)?);
 
         if read_kind == SecretKind::ServerApplicationTrafficSecret {
             self.get_mut_key_schedule().current_server_traffic_secret = Some(new_read_key);
@@ -1,8 +1,9 @@
 use crate::msgs::enums::{SignatureAlgorithm, SignatureScheme};
 use crate::key;
 use crate::error::TLSError;
+use crate::x509;
 
-use ring::{self, signature::{self, EcdsaKeyPair, RsaKeyPair}};
+use ring::{self, signature::{self, EcdsaKeyPair, Ed25519KeyPair, RsaKeyPair}};
 use webpki;
 
 use std::sync::Arc;
@@ -27,6 +28,42 @@ pub trait Signer : Send + Sync {
 
     /// Reveals which scheme will be used when you call `sign()`.
     fn get_scheme(&self) -> SignatureScheme;
+
+    /// Starts signing `message` during a handshake.
+    ///
+    /// By default this calls `sign()`.  Return `Ok(None)` instead to
+    /// make the signature elsewhere, such as in a remote KMS or HSM,
+    /// without blocking the handshake: it then pauses, and the session's
+    /// `get_pending_signature()` says what to sign until the signature
+    /// is given to its `complete_signature()`.
+    fn start_sign(&self, message: &[u8]) -> Result<Option<Vec<u8>>, TLSError> {
+        self.sign(message).map(Some)
+    }
+}
+
+/// A signature which a paused handshake is waiting for.
+///
+/// See `Signer::start_sign`.
+#[derive(Debug)]
+pub struct PendingSignature {
+    message: Vec<u8>,
+    scheme: SignatureScheme,
+}
+
+impl PendingSignature {
+    pub(crate) fn new(message: Vec<u8>, scheme: SignatureScheme) -> PendingSignature {
+        PendingSignature { message, scheme }
+    }
+
+    /// The message to sign.
+    pub fn message(&self) -> &[u8] {
+        &self.message
+    }
+
+    /// The scheme to sign it with, which is the `Signer`'s `get_scheme()`.
+    pub fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
 }
 
 /// A packaged-together certificate chain, matching `SigningKey` and
@@ -47,6 +84,11 @@ pub struct CertifiedKey {
     /// certificate is included on those logs.  This must be
     /// a `SignedCertificateTimestampList` encoding; see RFC6962.
     pub sct_list: Option<Vec<u8>>,
+
+    /// If true, `cert` holds a single DER-encoded SubjectPublicKeyInfo
+    /// for `key`, which is presented as a raw public key (RFC7250)
+    /// rather than a certificate.
+    pub raw_public_key: bool,
 }
 
 impl CertifiedKey {
@@ -60,6 +102,16 @@ impl CertifiedKey {
             key,
             ocsp: None,
             sct_list: None,
+            raw_public_key: false,
+        }
+    }
+
+    /// Make a new CertifiedKey which presents `spki`, the
+    /// DER-encoded SubjectPublicKeyInfo of `key`, as a raw public key.
+    pub fn new_raw_public_key(spki: Vec<u8>, key: Arc<Box<dyn SigningKey>>) -> CertifiedKey {
+        CertifiedKey {
+            raw_public_key: true,
+            ..CertifiedKey::new(vec![ key::Certificate(spki) ], key)
         }
     }
 
@@ -99,6 +151,9 @@ impl CertifiedKey {
     /// - the first certificate should quote the given server name
     ///   (if provided)
     ///
+    /// A raw public key need only be a key we understand: it doesn't
+    /// name anyone.
+    ///
     /// These checks are not security-sensitive.  They are the
     /// *server* attempting to detect accidental misconfiguration.
     pub fn cross_check_end_entity_cert(&self, name: Option<webpki::DNSNameRef>) -> Result<(), TLSError> {
@@ -107,6 +162,12 @@ impl CertifiedKey {
             TLSError::General("No end-entity certificate in certificate chain".to_string())
         })?;
 
+        if self.raw_public_key {
+            return x509::parse_spki(&end_entity_cert.0)
+                .map(|_| ())
+                .ok_or_else(|| TLSError::General("Raw public key is syntactically invalid".to_string()));
+        }
+
         // Reject syntactically-invalid end-entity certificates.
         let end_entity_cert = webpki::EndEntityCert::from(end_entity_cert.as_ref()).map_err(|_| {
                 TLSError::General("End-entity certificate in certificate \
@@ -137,6 +198,7 @@ pub fn any_supported_type(der: &key::PrivateKey) -> Result<Box<dyn SigningKey>,
     }
 
     any_ecdsa_type(der)
+        .or_else(|_| any_eddsa_type(der))
 }
 
 /// Parse `der` as any ECDSA key type, returning the first which works.
@@ -156,6 +218,16 @@ pub fn any_ecdsa_type(der: &key::PrivateKey) -> Result<Box<dyn SigningKey>, ()>
     Err(())
 }
 
+/// Parse `der` as any EdDSA key type, returning the first which works.
+pub fn any_eddsa_type(der: &key::PrivateKey) -> Result<Box<dyn SigningKey>, ()> {
+    if let Ok(ed25519) = Ed25519SigningKey::new(der) {
+        return Ok(Box::new(ed25519));
+    }
+
+    // nb. ring doesn't support Ed448.
+    Err(())
+}
+
 /// A `SigningKey` for RSA-PKCS1 or RSA-PSS
 pub struct RSASigningKey {
     key: Arc<RsaKeyPair>,
@@ -297,6 +369,50 @@ impl Signer for SingleSchemeSigner {
     }
 }
 
+/// A `SigningKey` for Ed25519.
+pub struct Ed25519SigningKey {
+    key: Arc<Ed25519KeyPair>,
+}
+
+impl Ed25519SigningKey {
+    /// Make a new `Ed25519SigningKey` from a DER encoding in PKCS#8
+    /// format.  Both v1 and v2 encodings are accepted; a v2 encoding's
+    /// public key is not checked against the private key.
+    pub fn new(der: &key::PrivateKey) -> Result<Ed25519SigningKey, ()> {
+        Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der.0)
+            .map(|kp| Ed25519SigningKey { key: Arc::new(kp) })
+            .map_err(|_| ())
+    }
+}
+
+impl SigningKey for Ed25519SigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        if offered.contains(&SignatureScheme::ED25519) {
+            Some(Box::new(Ed25519Signer { key: self.key.clone() }))
+        } else {
+            None
+        }
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::ED25519
+    }
+}
+
+struct Ed25519Signer {
+    key: Arc<Ed25519KeyPair>,
+}
+
+impl Signer for Ed25519Signer {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, TLSError> {
+        Ok(self.key.sign(message).as_ref().into())
+    }
+
+    fn get_scheme(&self) -> SignatureScheme {
+        SignatureScheme::ED25519
+    }
+}
+
 /// The set of schemes we support for signatures and
 /// that are allowed for TLS1.3.
 pub fn supported_sign_tls13() -> &'static [SignatureScheme] {
@@ -304,6 +420,8 @@ pub fn supported_sign_tls13() -> &'static [SignatureScheme] {
         SignatureScheme::ECDSA_NISTP384_SHA384,
         SignatureScheme::ECDSA_NISTP256_SHA256,
 
+        SignatureScheme::ED25519,
+
         SignatureScheme::RSA_PSS_SHA512,
         SignatureScheme::RSA_PSS_SHA384,
         SignatureScheme::RSA_PSS_SHA256,
@@ -1,15 +1,18 @@
 use crate::msgs::enums::{CipherSuite, HashAlgorithm, SignatureAlgorithm, SignatureScheme};
-use crate::msgs::enums::{NamedGroup, ProtocolVersion};
+use crate::msgs::enums::{ProtocolVersion, NamedGroup};
 use crate::msgs::handshake::KeyExchangeAlgorithm;
 use crate::msgs::handshake::DecomposedSignatureScheme;
-use crate::msgs::handshake::{ClientECDHParams, ServerECDHParams};
-use crate::msgs::codec::{Reader, Codec};
-
-use ring;
+use crate::kx::{KeyExchange, KeyExchangeResult, SupportedKxGroup};
+use crate::cipher::MessageCipherFactory;
+#[cfg(feature = "aes_ccm")]
+use crate::cipher;
 
 /// Bulk symmetric encryption scheme used by a cipher suite.
+///
+/// More variants may be added, so this is non-exhaustive.
 #[allow(non_camel_case_types)]
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum BulkAlgorithm {
     /// AES with 128-bit keys in Galois counter mode.
     AES_128_GCM,
@@ -19,112 +22,18 @@ pub enum BulkAlgorithm {
 
     /// Chacha20 for confidentiality with poly1305 for authenticity.
     CHACHA20_POLY1305,
-}
-
-/// The result of a key exchange.  This has our public key,
-/// and the agreed premaster secret.
-pub struct KeyExchangeResult {
-    pub pubkey: ring::agreement::PublicKey,
-    pub premaster_secret: Vec<u8>,
-}
-
-/// An in-progress key exchange.  This has the algorithm,
-/// our private key, and our public key.
-pub struct KeyExchange {
-    pub group: NamedGroup,
-    alg: &'static ring::agreement::Algorithm,
-    privkey: ring::agreement::EphemeralPrivateKey,
-    pub pubkey: ring::agreement::PublicKey,
-}
-
-impl KeyExchange {
-    pub fn named_group_to_ecdh_alg(group: NamedGroup)
-                                   -> Option<&'static ring::agreement::Algorithm> {
-        match group {
-            NamedGroup::X25519 => Some(&ring::agreement::X25519),
-            NamedGroup::secp256r1 => Some(&ring::agreement::ECDH_P256),
-            NamedGroup::secp384r1 => Some(&ring::agreement::ECDH_P384),
-            _ => None,
-        }
-    }
-
-    pub fn supported_groups() -> &'static [NamedGroup] {
-        // in preference order
-        &[
-            NamedGroup::X25519,
-            NamedGroup::secp384r1,
-            NamedGroup::secp256r1
-        ]
-    }
-
-    pub fn client_ecdhe(kx_params: &[u8]) -> Option<KeyExchangeResult> {
-        let mut rd = Reader::init(kx_params);
-        let ecdh_params = ServerECDHParams::read(&mut rd)?;
-
-        KeyExchange::start_ecdhe(ecdh_params.curve_params.named_group)?
-            .complete(&ecdh_params.public.0)
-    }
-
-    pub fn start_ecdhe(named_group: NamedGroup) -> Option<KeyExchange> {
-        let alg = KeyExchange::named_group_to_ecdh_alg(named_group)?;
-        let rng = ring::rand::SystemRandom::new();
-        let ours = ring::agreement::EphemeralPrivateKey::generate(alg, &rng).unwrap();
-
-        let pubkey = ours.compute_public_key().unwrap();
-
-        Some(KeyExchange {
-            group: named_group,
-            alg,
-            privkey: ours,
-            pubkey,
-        })
-    }
-
-    pub fn check_client_params(&self, kx_params: &[u8]) -> bool {
-        self.decode_client_params(kx_params).is_some()
-    }
-
-    fn decode_client_params(&self, kx_params: &[u8]) -> Option<ClientECDHParams> {
-        let mut rd = Reader::init(kx_params);
-        let ecdh_params = ClientECDHParams::read(&mut rd).unwrap();
-        if rd.any_left() {
-            None
-        } else {
-            Some(ecdh_params)
-        }
-    }
-
-    pub fn server_complete(self, kx_params: &[u8]) -> Option<KeyExchangeResult> {
-        self.decode_client_params(kx_params)
-            .and_then(|ecdh| self.complete(&ecdh.public.0))
-    }
-
-    pub fn complete(self, peer: &[u8]) -> Option<KeyExchangeResult> {
-        let peer_key = ring::agreement::UnparsedPublicKey::new(self.alg, peer);
-        let secret = ring::agreement::agree_ephemeral(self.privkey,
-                                                      &peer_key,
-                                                      (),
-                                                      |v| {
-                                                          let mut r = Vec::new();
-                                                          r.extend_from_slice(v);
-                                                          Ok(r)
-                                                      });
-
-        if secret.is_err() {
-            return None;
-        }
 
-        Some(KeyExchangeResult {
-            pubkey: self.pubkey,
-            premaster_secret: secret.unwrap(),
-        })
-    }
+    /// Something else, implemented by the suite's `cipher_factory`.
+    /// Without a `cipher_factory`, a suite with this is only
+    /// negotiated if the `CryptoProvider` has an AEAD for it, and
+    /// fails if that's in TLS1.2.
+    Custom,
 }
 
 /// A cipher suite supported by rustls.
 ///
-/// All possible instances of this class are provided by the library in
-/// the `ALL_CIPHERSUITES` array.
+/// The suites rustls implements itself are in the `ALL_CIPHERSUITES`
+/// array.  Others can be defined by giving them a `cipher_factory`.
 #[derive(Debug)]
 pub struct SupportedCipherSuite {
     /// The TLS enumeration naming this cipher suite.
@@ -157,7 +66,10 @@ pub struct SupportedCipherSuite {
     /// chacha20poly1305 works this way by design.
     pub explicit_nonce_len: usize,
 
-    pub(crate) hkdf_algorithm: ring::hkdf::Algorithm,
+    /// Makes the record encrypters and decrypters for this suite,
+    /// instead of the `CryptoProvider`'s implementation of `bulk`.
+    /// This is `None` for the suites in `ALL_CIPHERSUITES`.
+    pub cipher_factory: Option<&'static dyn MessageCipherFactory>,
 }
 
 impl PartialEq for SupportedCipherSuite {
@@ -167,26 +79,25 @@ impl PartialEq for SupportedCipherSuite {
 }
 
 impl SupportedCipherSuite {
-    /// Which hash function to use with this suite.
-    pub fn get_hash(&self) -> &'static ring::digest::Algorithm {
-        self.hkdf_algorithm.hmac_algorithm().digest_algorithm()
-    }
-
     /// We have parameters and a verified public key in `kx_params`.
-    /// Generate an ephemeral key, generate the shared secret, and
-    /// return it and the public half in a `KeyExchangeResult`.
-    pub fn do_client_kx(&self, kx_params: &[u8]) -> Option<KeyExchangeResult> {
+    /// Generate an ephemeral key in its group, which must be one of
+    /// `groups`, generate the shared secret, and return it and the
+    /// public half in a `KeyExchangeResult`.
+    pub fn do_client_kx(&self,
+                        kx_params: &[u8],
+                        groups: &[&'static dyn SupportedKxGroup]) -> Option<KeyExchangeResult> {
         match self.kx {
-            KeyExchangeAlgorithm::ECDHE => KeyExchange::client_ecdhe(kx_params),
+            KeyExchangeAlgorithm::ECDHE => KeyExchange::client_ecdhe(kx_params, groups),
+            KeyExchangeAlgorithm::DHE => KeyExchange::client_dhe(kx_params, groups),
             _ => None,
         }
     }
 
     /// Start the KX process with the given group.  This generates
     /// the server's share, but we don't yet have the client's share.
-    pub fn start_server_kx(&self, named_group: NamedGroup) -> Option<KeyExchange> {
+    pub fn start_server_kx(&self, skxg: &dyn SupportedKxGroup) -> Option<KeyExchange> {
         match self.kx {
-            KeyExchangeAlgorithm::ECDHE => KeyExchange::start_ecdhe(named_group),
+            KeyExchangeAlgorithm::ECDHE | KeyExchangeAlgorithm::DHE => KeyExchange::start(skxg),
             _ => None,
         }
     }
@@ -216,25 +127,34 @@ impl SupportedCipherSuite {
             our_preference.push(SignatureScheme::RSA_PSS_SHA256);
         }
 
+        // And for ECDSA, EdDSA: see `usable_for_sigalg`.
+        if self.sign == SignatureAlgorithm::ECDSA {
+            our_preference.push(SignatureScheme::ED25519);
+        }
+
         our_preference.retain(|pref| offered.contains(pref));
         our_preference
     }
 
-    /// Which AEAD algorithm to use for this suite.
-    pub fn get_aead_alg(&self) -> &'static ring::aead::Algorithm {
-        match self.bulk {
-            BulkAlgorithm::AES_128_GCM => &ring::aead::AES_128_GCM,
-            BulkAlgorithm::AES_256_GCM => &ring::aead::AES_256_GCM,
-            BulkAlgorithm::CHACHA20_POLY1305 => &ring::aead::CHACHA20_POLY1305,
-        }
-    }
-
     /// Length of key block that needs to be output by the key
     /// derivation phase for this suite.
     pub fn key_block_len(&self) -> usize {
         (self.enc_key_len + self.fixed_iv_len) * 2 + self.explicit_nonce_len
     }
 
+    /// Return true if this suite can be used with a key of type `sigalg`.
+    ///
+    /// RFC8422 uses the ECDHE_ECDSA suites for EdDSA keys too.
+    pub fn usable_for_sigalg(&self, sigalg: SignatureAlgorithm) -> bool {
+        match self.sign {
+            SignatureAlgorithm::Anonymous => true,
+            SignatureAlgorithm::ECDSA => sigalg == SignatureAlgorithm::ECDSA ||
+                sigalg == SignatureAlgorithm::ED25519 ||
+                sigalg == SignatureAlgorithm::ED448,
+            sign => sign == sigalg,
+        }
+    }
+
     /// Return true if this suite is usable for TLS `version`.
     pub fn usable_for_version(&self, version: ProtocolVersion) -> bool {
         match version {
@@ -273,7 +193,7 @@ pub static TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
         enc_key_len: 32,
         fixed_iv_len: 12,
         explicit_nonce_len: 0,
-        hkdf_algorithm: ring::hkdf::HKDF_SHA256,
+        cipher_factory: None,
     };
 
 pub static TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
@@ -286,7 +206,7 @@ pub static TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
         enc_key_len: 32,
         fixed_iv_len: 12,
         explicit_nonce_len: 0,
-        hkdf_algorithm: ring::hkdf::HKDF_SHA256,
+        cipher_factory: None,
     };
 
 pub static TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite {
@@ -298,7 +218,7 @@ pub static TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite = Support
     enc_key_len: 16,
     fixed_iv_len: 4,
     explicit_nonce_len: 8,
-    hkdf_algorithm: ring::hkdf::HKDF_SHA256,
+    cipher_factory: None,
 };
 
 pub static TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite = SupportedCipherSuite {
@@ -310,7 +230,7 @@ pub static TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite = Support
     enc_key_len: 32,
     fixed_iv_len: 4,
     explicit_nonce_len: 8,
-    hkdf_algorithm: ring::hkdf::HKDF_SHA384,
+    cipher_factory: None,
 };
 
 pub static TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite {
@@ -322,7 +242,7 @@ pub static TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite = Suppo
     enc_key_len: 16,
     fixed_iv_len: 4,
     explicit_nonce_len: 8,
-    hkdf_algorithm: ring::hkdf::HKDF_SHA256,
+    cipher_factory: None,
 };
 
 pub static TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite = SupportedCipherSuite {
@@ -334,7 +254,47 @@ pub static TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite = Suppo
     enc_key_len: 32,
     fixed_iv_len: 4,
     explicit_nonce_len: 8,
-    hkdf_algorithm: ring::hkdf::HKDF_SHA384,
+    cipher_factory: None,
+};
+
+#[cfg(feature = "ffdhe")]
+pub static TLS_DHE_RSA_WITH_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
+    SupportedCipherSuite {
+        suite: CipherSuite::TLS_DHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+        kx: KeyExchangeAlgorithm::DHE,
+        sign: SignatureAlgorithm::RSA,
+        bulk: BulkAlgorithm::CHACHA20_POLY1305,
+        hash: HashAlgorithm::SHA256,
+        enc_key_len: 32,
+        fixed_iv_len: 12,
+        explicit_nonce_len: 0,
+        cipher_factory: None,
+    };
+
+#[cfg(feature = "ffdhe")]
+pub static TLS_DHE_RSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite {
+    suite: CipherSuite::TLS_DHE_RSA_WITH_AES_128_GCM_SHA256,
+    kx: KeyExchangeAlgorithm::DHE,
+    sign: SignatureAlgorithm::RSA,
+    bulk: BulkAlgorithm::AES_128_GCM,
+    hash: HashAlgorithm::SHA256,
+    enc_key_len: 16,
+    fixed_iv_len: 4,
+    explicit_nonce_len: 8,
+    cipher_factory: None,
+};
+
+#[cfg(feature = "ffdhe")]
+pub static TLS_DHE_RSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite = SupportedCipherSuite {
+    suite: CipherSuite::TLS_DHE_RSA_WITH_AES_256_GCM_SHA384,
+    kx: KeyExchangeAlgorithm::DHE,
+    sign: SignatureAlgorithm::RSA,
+    bulk: BulkAlgorithm::AES_256_GCM,
+    hash: HashAlgorithm::SHA384,
+    enc_key_len: 32,
+    fixed_iv_len: 4,
+    explicit_nonce_len: 8,
+    cipher_factory: None,
 };
 
 pub static TLS13_CHACHA20_POLY1305_SHA256: SupportedCipherSuite = SupportedCipherSuite {
@@ -346,7 +306,7 @@ pub static TLS13_CHACHA20_POLY1305_SHA256: SupportedCipherSuite = SupportedCiphe
     enc_key_len: 32,
     fixed_iv_len: 12,
     explicit_nonce_len: 0,
-    hkdf_algorithm: ring::hkdf::HKDF_SHA256,
+    cipher_factory: None,
 };
 
 pub static TLS13_AES_256_GCM_SHA384: SupportedCipherSuite = SupportedCipherSuite {
@@ -358,7 +318,7 @@ pub static TLS13_AES_256_GCM_SHA384: SupportedCipherSuite = SupportedCipherSuite
     enc_key_len: 32,
     fixed_iv_len: 12,
     explicit_nonce_len: 0,
-    hkdf_algorithm: ring::hkdf::HKDF_SHA384,
+    cipher_factory: None,
 };
 
 pub static TLS13_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite {
@@ -370,9 +330,36 @@ pub static TLS13_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite
     enc_key_len: 16,
     fixed_iv_len: 12,
     explicit_nonce_len: 0,
-    hkdf_algorithm: ring::hkdf::HKDF_SHA256,
+    cipher_factory: None,
 };
 
+#[cfg(feature = "aes_ccm")]
+pub static TLS13_AES_128_CCM_SHA256: SupportedCipherSuite = SupportedCipherSuite {
+    suite: CipherSuite::TLS13_AES_128_CCM_SHA256,
+    kx: KeyExchangeAlgorithm::BulkOnly,
+    sign: SignatureAlgorithm::Anonymous,
+    bulk: BulkAlgorithm::Custom,
+    hash: HashAlgorithm::SHA256,
+    enc_key_len: 16,
+    fixed_iv_len: 12,
+    explicit_nonce_len: 0,
+    cipher_factory: Some(&cipher::AES_128_CCM),
+};
+
+#[cfg(feature = "aes_ccm")]
+pub static TLS13_AES_128_CCM_8_SHA256: SupportedCipherSuite = SupportedCipherSuite {
+    suite: CipherSuite::TLS13_AES_128_CCM_8_SHA256,
+    kx: KeyExchangeAlgorithm::BulkOnly,
+    sign: SignatureAlgorithm::Anonymous,
+    bulk: BulkAlgorithm::Custom,
+    hash: HashAlgorithm::SHA256,
+    enc_key_len: 16,
+    fixed_iv_len: 12,
+    explicit_nonce_len: 0,
+    cipher_factory: Some(&cipher::AES_128_CCM_8),
+};
+
+#[cfg(feature = "esni")]
 pub static TLS13_CIPHERSUITES: [&'static SupportedCipherSuite; 3] =
     [&TLS13_CHACHA20_POLY1305_SHA256,
      &TLS13_AES_256_GCM_SHA384,
@@ -393,6 +380,31 @@ pub static ALL_CIPHERSUITES: [&'static SupportedCipherSuite; 9] =
      &TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
      &TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256];
 
+/// The TLS1.2 cipher suites which use finite field Diffie-Hellman
+/// key exchange.  These are not in `ALL_CIPHERSUITES`: add them to
+/// `ciphersuites` in your config, along with some FFDHE groups in
+/// `kx_groups`, to use them.
+#[cfg(feature = "ffdhe")]
+pub static FFDHE_CIPHERSUITES: [&'static SupportedCipherSuite; 3] =
+    [&TLS_DHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+     &TLS_DHE_RSA_WITH_AES_256_GCM_SHA384,
+     &TLS_DHE_RSA_WITH_AES_128_GCM_SHA256];
+
+/// The TLS1.3 AES-CCM cipher suites, for constrained peers which
+/// lack AES-GCM and ChaCha20Poly1305.  These are not in `ALL_CIPHERSUITES`,
+/// and are much slower than the suites which are: add them to `ciphersuites`
+/// in your config to use them.
+///
+/// AES-128-CCM-8 has a shorter authentication tag, so only use it
+/// when a peer needs it.
+///
+/// These need the `aes_ccm` feature: see the crate documentation
+/// for why.
+#[cfg(feature = "aes_ccm")]
+pub static TLS13_CCM_CIPHERSUITES: [&'static SupportedCipherSuite; 2] =
+    [&TLS13_AES_128_CCM_SHA256,
+     &TLS13_AES_128_CCM_8_SHA256];
+
 // These both O(N^2)!
 pub fn choose_ciphersuite_preferring_client(client_suites: &[CipherSuite],
                                             server_suites: &[&'static SupportedCipherSuite])
@@ -422,7 +434,7 @@ pub fn reduce_given_sigalg(all: &[&'static SupportedCipherSuite],
                            sigalg: SignatureAlgorithm)
                            -> Vec<&'static SupportedCipherSuite> {
     all.iter()
-        .filter(|&&suite| suite.sign == SignatureAlgorithm::Anonymous || suite.sign == sigalg)
+        .filter(|&&suite| suite.usable_for_sigalg(sigalg))
         .cloned()
         .collect()
 }
@@ -438,13 +450,38 @@ pub fn reduce_given_version(all: &[&'static SupportedCipherSuite],
         .collect()
 }
 
+/// Return a list of the ciphersuites in `all` for which we have
+/// a key exchange group in `our_groups` that the client also
+/// supports: DHE suites need a finite field group, and ECDHE
+/// suites an elliptic curve one.
+pub fn reduce_given_kx_groups(all: &[&'static SupportedCipherSuite],
+                              our_groups: &[&'static dyn SupportedKxGroup],
+                              client_groups: &[NamedGroup])
+                              -> Vec<&'static SupportedCipherSuite> {
+    let have_group = |ffdhe: bool| our_groups.iter()
+        .any(|skxg| skxg.usable_for_tls12() &&
+             skxg.ffdhe_params().is_some() == ffdhe &&
+             client_groups.contains(&skxg.name()));
+    let have_ecdhe_group = have_group(false);
+    let have_ffdhe_group = have_group(true);
+
+    all.iter()
+        .filter(|&&suite| match suite.kx {
+            KeyExchangeAlgorithm::ECDHE => have_ecdhe_group,
+            KeyExchangeAlgorithm::DHE => have_ffdhe_group,
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
+
 /// Return true if `sigscheme` is usable by any of the given suites.
 pub fn compatible_sigscheme_for_suites(sigscheme: SignatureScheme,
                                        common_suites: &[&'static SupportedCipherSuite]) -> bool {
     let sigalg = sigscheme.sign();
 
     common_suites.iter()
-        .any(|&suite| suite.sign == SignatureAlgorithm::Anonymous || suite.sign == sigalg)
+        .any(|&suite| suite.usable_for_sigalg(sigalg))
 }
 
 #[cfg(test)]
@@ -1,25 +1,30 @@
 
 use crate::server::ProducesTickets;
 use crate::rand;
+use crate::crypto::ring::RING;
+use crate::time_provider::{TimeProvider, SystemTimeProvider};
 
 use std::mem;
 use std::sync::{Mutex, Arc};
-use std::time;
 use ring::aead;
 
 /// The timebase for expiring and rolling tickets and ticketing
 /// keys.  This is UNIX wall time in seconds.
+#[deprecated(note = "the server now uses `ServerConfig::time_provider`")]
+#[allow(dead_code)]
 pub fn timebase() -> u64 {
-    time::SystemTime::now()
-        .duration_since(time::UNIX_EPOCH)
+    SystemTimeProvider.now()
         .unwrap()
-        .as_secs()
 }
 
 /// This is a `ProducesTickets` implementation which uses
 /// any *ring* `aead::Algorithm` to encrypt and authentication
 /// the ticket payload.  It does not enforce any lifetime
 /// constraint.
+///
+/// Keys and nonces come from *ring*'s random number generator:
+/// ticket encryption doesn't use the `ServerConfig`'s
+/// `CryptoProvider`.
 pub struct AEADTicketer {
     alg: &'static aead::Algorithm,
     key: aead::LessSafeKey,
@@ -47,7 +52,7 @@ impl AEADTicketer {
     /// Make a ticketer with recommended configuration and a random key.
     pub fn new() -> AEADTicketer {
         let mut key = [0u8; 32];
-        rand::fill_random(&mut key);
+        rand::fill_random(&RING, &mut key);
         AEADTicketer::new_custom(&aead::CHACHA20_POLY1305, &key, 60 * 60 * 12)
     }
 }
@@ -64,7 +69,7 @@ impl ProducesTickets for AEADTicketer {
     fn encrypt(&self, message: &[u8]) -> Option<Vec<u8>> {
         // Random nonce, because a counter is a privacy leak.
         let mut nonce_buf = [0u8; 12];
-        rand::fill_random(&mut nonce_buf);
+        rand::fill_random(&RING, &mut nonce_buf);
         let nonce = ring::aead::Nonce::assume_unique_for_key(nonce_buf);
         let aad = ring::aead::Aad::empty();
 
@@ -108,7 +113,7 @@ impl ProducesTickets for AEADTicketer {
 struct TicketSwitcherState {
     current: Box<dyn ProducesTickets>,
     previous: Option<Box<dyn ProducesTickets>>,
-    next_switch_time: u64,
+    next_switch_time: Option<u64>,
 }
 
 /// A ticketer that has a 'current' sub-ticketer and a single
@@ -125,6 +130,11 @@ impl TicketSwitcher {
     /// is used to generate new tickets.  Tickets are accepted for no
     /// longer than twice this duration.  `generator` produces a new
     /// `ProducesTickets` implementation.
+    ///
+    /// Ticketers are rolled according to the time the server passes
+    /// to `encrypt_at`/`decrypt_at`, which comes from its
+    /// `time_provider`.  While that doesn't know the time, they
+    /// aren't rolled at all.
     pub fn new(lifetime: u32,
                generator: fn() -> Box<dyn ProducesTickets>)
                -> TicketSwitcher {
@@ -134,7 +144,7 @@ impl TicketSwitcher {
             state: Mutex::new(TicketSwitcherState {
                 current: generator(),
                 previous: None,
-                next_switch_time: timebase() + u64::from(lifetime),
+                next_switch_time: None,
             }),
         }
     }
@@ -145,13 +155,31 @@ impl TicketSwitcher {
     ///
     /// Calling this regularly will ensure timely key erasure.  Otherwise,
     /// key erasure will be delayed until the next encrypt/decrypt call.
+    ///
+    /// This uses the system time; if your `ServerConfig` has a different
+    /// `time_provider`, use `maybe_roll_at` with its time instead.
     pub fn maybe_roll(&self) {
+        self.maybe_roll_at(SystemTimeProvider.now());
+    }
+
+    /// Like `maybe_roll`, but `now` is the current time in seconds
+    /// since the UNIX epoch, or None if it isn't known.
+    pub fn maybe_roll_at(&self, now: Option<u64>) {
         let mut state = self.state.lock().unwrap();
-        let now = timebase();
+        let now = match now {
+            Some(now) => now,
+            None => return,
+        };
 
-        if now > state.next_switch_time {
-            state.previous = Some(mem::replace(&mut state.current, (self.generator)()));
-            state.next_switch_time = now + u64::from(self.lifetime);
+        match state.next_switch_time {
+            Some(switch_time) if now > switch_time => {
+                state.previous = Some(mem::replace(&mut state.current, (self.generator)()));
+                state.next_switch_time = Some(now + u64::from(self.lifetime));
+            }
+            Some(_) => {}
+            None => {
+                state.next_switch_time = Some(now + u64::from(self.lifetime));
+            }
         }
     }
 }
@@ -167,6 +195,16 @@ impl ProducesTickets for TicketSwitcher {
 
     fn encrypt(&self, message: &[u8]) -> Option<Vec<u8>> {
         self.maybe_roll();
+        self.encrypt_at(message, None)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        self.maybe_roll();
+        self.decrypt_at(ciphertext, None)
+    }
+
+    fn encrypt_at(&self, message: &[u8], now: Option<u64>) -> Option<Vec<u8>> {
+        self.maybe_roll_at(now);
 
         self.state
             .lock()
@@ -175,8 +213,8 @@ impl ProducesTickets for TicketSwitcher {
             .encrypt(message)
     }
 
-    fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
-        self.maybe_roll();
+    fn decrypt_at(&self, ciphertext: &[u8], now: Option<u64>) -> Option<Vec<u8>> {
+        self.maybe_roll_at(now);
 
         let state = self.state.lock().unwrap();
         let rc = state.current.decrypt(ciphertext);
@@ -206,6 +244,26 @@ impl Ticketer {
     }
 }
 
+#[test]
+fn ticketswitcher_rolls_at_provided_time() {
+    let t = TicketSwitcher::new(100, generate_inner);
+    let cipher = t.encrypt_at(b"hello world", Some(1000)).unwrap();
+
+    // Not yet time to roll.
+    t.maybe_roll_at(Some(1100));
+    assert_eq!(t.state.lock().unwrap().previous.is_none(), true);
+
+    // Without a time, nothing rolls.
+    assert_eq!(t.decrypt_at(&cipher, None).unwrap(), b"hello world");
+    assert_eq!(t.state.lock().unwrap().previous.is_none(), true);
+
+    // Rolled once: the old ticketer can still decrypt.
+    assert_eq!(t.decrypt_at(&cipher, Some(1101)).unwrap(), b"hello world");
+
+    // Rolled twice: it's gone.
+    assert_eq!(t.decrypt_at(&cipher, Some(1202)), None);
+}
+
 #[test]
 fn basic_pairwise_test() {
     let t = Ticketer::new();
@@ -0,0 +1,35 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::TLSError;
+
+/// Something that knows the current time.
+///
+/// This is asked whenever the time matters: when checking
+/// certificates, CRLs, OCSP responses and SCTs, and ticket
+/// lifetimes and ages.  Supplying your own lets tests and
+/// simulations run at a fixed time, or fast-forward.
+pub trait TimeProvider : Send + Sync {
+    /// The current time in seconds since the UNIX epoch, or
+    /// None if it can't be determined.
+    fn now(&self) -> Option<u64>;
+}
+
+/// A `TimeProvider` which uses the system time.  This is the
+/// default.
+pub struct SystemTimeProvider;
+
+impl TimeProvider for SystemTimeProvider {
+    fn now(&self) -> Option<u64> {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|since_the_epoch| since_the_epoch.as_secs())
+    }
+}
+
+/// The current time according to `time_provider`, for webpki.
+pub(crate) fn webpki_now(time_provider: &dyn TimeProvider) -> Result<webpki::Time, TLSError> {
+    time_provider.now()
+        .map(webpki::Time::from_seconds_since_unix_epoch)
+        .ok_or(TLSError::FailedToGetCurrentTime)
+}
@@ -1,15 +1,25 @@
 use webpki;
 use sct;
 use std;
+use std::collections;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use crate::key::Certificate;
+use crate::msgs::enums::CertificateType;
 use crate::msgs::handshake::DigitallySignedStruct;
 use crate::msgs::handshake::SCTList;
-use crate::msgs::enums::SignatureScheme;
 use crate::error::TLSError;
 use crate::anchors::{DistinguishedNames, RootCertStore};
 use crate::anchors::OwnedTrustAnchor;
+use crate::crypto::CryptoProvider;
+use crate::ocsp::{self, OCSPPolicy, CertStatus};
+use crate::crl::{CRLStore, RevocationPolicy, RevocationCheckDepth, UnknownStatusPolicy};
+use crate::pinning::PinSet;
+use crate::identity::ClientIdentityPolicy;
+use crate::server_name::ServerName;
+use crate::time_provider::TimeProvider;
+use crate::x509;
 #[cfg(feature = "logging")]
 use crate::log::{warn, debug};
 
@@ -21,6 +31,7 @@ static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA256,
                                                    &webpki::ECDSA_P256_SHA384,
                                                    &webpki::ECDSA_P384_SHA256,
                                                    &webpki::ECDSA_P384_SHA384,
+                                                   &webpki::ED25519,
                                                    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
                                                    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
                                                    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
@@ -61,13 +72,15 @@ impl ClientCertVerified {
 /// Something that can verify a server certificate chain
 pub trait ServerCertVerifier : Send + Sync {
     /// Verify a the certificate chain `presented_certs` against the roots
-    /// configured in `roots`.  Make sure that `dns_name` is quoted by
-    /// the top certificate in the chain.
+    /// configured in `roots`.  Make sure that `server_name` is quoted by
+    /// the top certificate in the chain.  `now` is the current time,
+    /// from the `ClientConfig`'s `time_provider`.
     fn verify_server_cert(&self,
                           roots: &RootCertStore,
                           presented_certs: &[Certificate],
-                          dns_name: webpki::DNSNameRef,
-                          ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError>;
+                          server_name: &ServerName,
+                          ocsp_response: &[u8],
+                          now: webpki::Time) -> Result<ServerCertVerified, TLSError>;
 }
 
 /// Something that can verify a client certificate chain
@@ -85,42 +98,357 @@ pub trait ClientCertVerifier : Send + Sync {
     fn client_auth_root_subjects(&self) -> DistinguishedNames;
 
     /// Verify a certificate chain `presented_certs` is rooted in `roots`.
-    /// Does no further checking of the certificate.
+    /// Does no further checking of the certificate.  `now` is the
+    /// current time, from the `ServerConfig`'s `time_provider`.
     fn verify_client_cert(&self,
-                          presented_certs: &[Certificate]) -> Result<ClientCertVerified, TLSError>;
+                          presented_certs: &[Certificate],
+                          now: webpki::Time) -> Result<ClientCertVerified, TLSError>;
+
+    /// Like `verify_client_cert`, also given the name the client asked
+    /// for with SNI, if any.  The default ignores it.
+    fn verify_client_cert_for_sni(&self,
+                                  presented_certs: &[Certificate],
+                                  _sni: Option<webpki::DNSNameRef>,
+                                  now: webpki::Time) -> Result<ClientCertVerified, TLSError> {
+        self.verify_client_cert(presented_certs, now)
+    }
+}
+
+/// Something that can verify a server's raw public key (RFC7250),
+/// which the server presents instead of a certificate chain.
+pub trait ServerRawPublicKeyVerifier : Send + Sync {
+    /// Decide whether `spki`, a DER-encoded SubjectPublicKeyInfo, is
+    /// a key `server_name` may use.  Nothing vouches for the key, so
+    /// this has to be known in advance.
+    fn verify_server_raw_public_key(&self,
+                                    spki: &[u8],
+                                    server_name: &ServerName) -> Result<ServerCertVerified, TLSError>;
+}
+
+/// Something that can verify a client's raw public key (RFC7250),
+/// which the client presents instead of a certificate chain.
+pub trait ClientRawPublicKeyVerifier : Send + Sync {
+    /// Decide whether `spki`, a DER-encoded SubjectPublicKeyInfo, is
+    /// the key of a client we know.
+    fn verify_client_raw_public_key(&self,
+                                    spki: &[u8]) -> Result<ClientCertVerified, TLSError>;
 }
 
+/// A set of raw public keys, any of which we accept from a peer.
+///
+/// This is both a `ServerRawPublicKeyVerifier`, which accepts the
+/// keys for any server name, and a `ClientRawPublicKeyVerifier`.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedRawPublicKeys {
+    keys: Vec<Vec<u8>>,
+}
+
+impl TrustedRawPublicKeys {
+    /// Make a new, empty `TrustedRawPublicKeys`.
+    pub fn new() -> TrustedRawPublicKeys {
+        TrustedRawPublicKeys::default()
+    }
+
+    /// Trust `spki`, a DER-encoded SubjectPublicKeyInfo.  This
+    /// fails if it isn't one of the key types we support.
+    pub fn add(&mut self, spki: Vec<u8>) -> Result<(), TLSError> {
+        x509::parse_spki(&spki)
+            .ok_or(TLSError::WebPKIError(webpki::Error::BadDER))?;
+        self.keys.push(spki);
+        Ok(())
+    }
+
+    /// Return true if no keys are trusted.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Extract the SubjectPublicKeyInfo from `cert`, for use as
+    /// a raw public key.
+    pub fn spki(cert: &Certificate) -> Result<Vec<u8>, TLSError> {
+        x509::CertInfo::parse(&cert.0)
+            .map(|cert| cert.spki.to_vec())
+            .ok_or(TLSError::WebPKIError(webpki::Error::BadDER))
+    }
+
+    fn check(&self, spki: &[u8]) -> Result<(), TLSError> {
+        if self.keys.iter().any(|key| key.as_slice() == spki) {
+            Ok(())
+        } else {
+            Err(TLSError::UnknownRawPublicKey)
+        }
+    }
+}
+
+impl ServerRawPublicKeyVerifier for TrustedRawPublicKeys {
+    fn verify_server_raw_public_key(&self,
+                                    spki: &[u8],
+                                    _server_name: &ServerName) -> Result<ServerCertVerified, TLSError> {
+        self.check(spki)
+            .map(|_| ServerCertVerified::assertion())
+    }
+}
+
+impl ClientRawPublicKeyVerifier for TrustedRawPublicKeys {
+    fn verify_client_raw_public_key(&self,
+                                    spki: &[u8]) -> Result<ClientCertVerified, TLSError> {
+        self.check(spki)
+            .map(|_| ClientCertVerified::assertion())
+    }
+}
+
+/// The default `ServerCertVerifier`, which validates certificates
+/// with webpki and checks stapled OCSP responses.
 pub struct WebPKIVerifier {
-    pub time: fn() -> Result<webpki::Time, TLSError>,
+    /// What to do about stapled OCSP responses.
+    pub ocsp_policy: OCSPPolicy,
 }
 
 impl ServerCertVerifier for WebPKIVerifier {
     fn verify_server_cert(&self,
                           roots: &RootCertStore,
                           presented_certs: &[Certificate],
-                          dns_name: webpki::DNSNameRef,
-                          ocsp_response: &[u8]) -> Result<ServerCertVerified, TLSError> {
+                          server_name: &ServerName,
+                          ocsp_response: &[u8],
+                          now: webpki::Time) -> Result<ServerCertVerified, TLSError> {
         let (cert, chain, trustroots) = prepare(roots, presented_certs)?;
-        let now = (self.time)()?;
         let cert = cert.verify_is_valid_tls_server_cert(SUPPORTED_SIG_ALGS,
                 &webpki::TLSServerTrustAnchors(&trustroots), &chain, now)
             .map_err(TLSError::WebPKIError)
             .map(|_| cert)?;
 
-        if !ocsp_response.is_empty() {
-            debug!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
+        match *server_name {
+            ServerName::DNSName(ref dns_name) => {
+                cert.verify_is_valid_for_dns_name(dns_name.as_ref())
+                    .map_err(TLSError::WebPKIError)?;
+            }
+            ServerName::IPAddress(ip) => verify_is_valid_for_ip_address(&presented_certs[0], ip)?,
         }
 
-        cert.verify_is_valid_for_dns_name(dns_name)
-            .map_err(TLSError::WebPKIError)
+        check_ocsp(self.ocsp_policy, roots, presented_certs, ocsp_response, now)
             .map(|_| ServerCertVerified::assertion())
     }
 }
 
 impl WebPKIVerifier {
     pub fn new() -> WebPKIVerifier {
-        WebPKIVerifier {
-            time: try_now,
+        WebPKIVerifier::new_with_ocsp_policy(OCSPPolicy::SoftFail)
+    }
+
+    pub fn new_with_ocsp_policy(ocsp_policy: OCSPPolicy) -> WebPKIVerifier {
+        WebPKIVerifier { ocsp_policy }
+    }
+}
+
+/// webpki only knows about DNS names, so we check iPAddress
+/// subjectAltNames ourselves.  `cert` must already have been
+/// validated.
+fn verify_is_valid_for_ip_address(cert: &Certificate, ip: IpAddr) -> Result<(), TLSError> {
+    let cert = x509::CertInfo::parse(&cert.0)
+        .ok_or(TLSError::WebPKIError(webpki::Error::BadDER))?;
+
+    let matched = match ip {
+        IpAddr::V4(ip) => cert.has_ip_address(&ip.octets()),
+        IpAddr::V6(ip) => cert.has_ip_address(&ip.octets()),
+    };
+
+    if matched {
+        Ok(())
+    } else {
+        Err(TLSError::WebPKIError(webpki::Error::CertNotValidForName))
+    }
+}
+
+/// A `ServerCertVerifier` which verifies server certificates with
+/// a `WebPKIVerifier`, and then requires that the keys of pinned
+/// hostnames match their pins.
+///
+/// Hostnames without pins are verified as normal.
+pub struct PinningVerifier {
+    inner: WebPKIVerifier,
+    pins: collections::HashMap<Vec<u8>, PinSet>,
+}
+
+impl PinningVerifier {
+    /// Make a new `PinningVerifier`, with no pins, which otherwise
+    /// verifies certificates like `WebPKIVerifier::new()`.
+    pub fn new() -> PinningVerifier {
+        PinningVerifier::new_with_verifier(WebPKIVerifier::new())
+    }
+
+    /// Make a new `PinningVerifier`, with no pins, which otherwise
+    /// verifies certificates with `inner`.
+    pub fn new_with_verifier(inner: WebPKIVerifier) -> PinningVerifier {
+        PinningVerifier {
+            inner,
+            pins: collections::HashMap::new(),
+        }
+    }
+
+    /// Pin `server_name` to the keys in `pins`, replacing any existing
+    /// pins for it.  `server_name` is a DNS name or an IP address.
+    ///
+    /// This fails if `server_name` is not a valid name, or if `pins`
+    /// is empty.
+    pub fn add_pins(&mut self, server_name: &str, pins: PinSet) -> Result<(), TLSError> {
+        let server_name = ServerName::try_from_ascii_str(server_name)?;
+
+        if pins.is_empty() {
+            return Err(TLSError::General("Empty pin set".into()));
+        }
+
+        self.pins.insert(server_name.to_key().to_ascii_lowercase(), pins);
+        Ok(())
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(&self,
+                          roots: &RootCertStore,
+                          presented_certs: &[Certificate],
+                          server_name: &ServerName,
+                          ocsp_response: &[u8],
+                          now: webpki::Time) -> Result<ServerCertVerified, TLSError> {
+        let verified = self.inner.verify_server_cert(roots, presented_certs,
+                                                     server_name, ocsp_response, now)?;
+
+        let pins = match self.pins.get(&server_name.to_key().to_ascii_lowercase()) {
+            Some(pins) => pins,
+            None => return Ok(verified),
+        };
+
+        if chain_spkis(presented_certs, roots)?
+            .iter()
+            .any(|spki| pins.matches(spki)) {
+            Ok(verified)
+        } else {
+            Err(TLSError::CertificatePinMismatch)
+        }
+    }
+}
+
+/// Return the DER-encoded SubjectPublicKeyInfos of the certificates
+/// on the path from `presented_certs[0]` to one of `roots`, in that
+/// order.  `presented_certs` must already have been validated.
+///
+/// The peer can send extra certificates, so only those which
+/// actually issued the one before count.
+fn chain_spkis(presented_certs: &[Certificate],
+               roots: &RootCertStore) -> Result<Vec<Vec<u8>>, TLSError> {
+    let bad_der = || TLSError::WebPKIError(webpki::Error::BadDER);
+    let end_entity = x509::CertInfo::parse(&presented_certs[0].0)
+        .ok_or_else(bad_der)?;
+    let intermediates = presented_certs[1..]
+        .iter()
+        .map(|der| x509::CertInfo::parse(&der.0).ok_or_else(bad_der))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let issued = |issuer_spki: &[u8], cert: &x509::CertInfo| {
+        x509::verify_signed_data(issuer_spki, cert.signature_alg, cert.tbs, cert.signature)
+            .is_ok()
+    };
+
+    let mut spkis = vec![end_entity.spki.to_vec()];
+    let mut cert = &end_entity;
+
+    // Each intermediate can appear on the path at most once.
+    for _ in 0..=intermediates.len() {
+        let anchor = roots.roots
+            .iter()
+            .map(anchor_subject_and_spki)
+            .find(|(subject, spki)| subject[..] == cert.issuer[..] && issued(spki, cert));
+        if let Some((_, spki)) = anchor {
+            spkis.push(spki);
+            break;
+        }
+
+        match intermediates.iter().find(|c| c.subject == cert.issuer && issued(c.spki, cert)) {
+            Some(issuer) => {
+                spkis.push(issuer.spki.to_vec());
+                cert = issuer;
+            }
+            None => break,
+        }
+    }
+
+    Ok(spkis)
+}
+
+/// Find the DER-encoded subject and SubjectPublicKeyInfo of the
+/// issuer of `cert`, among `intermediates` and then `roots`.
+pub(crate) fn find_issuer(cert: &x509::CertInfo,
+                          intermediates: &[Certificate],
+                          roots: &RootCertStore) -> Option<(Vec<u8>, Vec<u8>)> {
+    let signed = |spki: &[u8]| {
+        x509::verify_signed_data(spki, cert.signature_alg, cert.tbs, cert.signature)
+            .is_ok()
+    };
+
+    for der in intermediates {
+        if let Some(candidate) = x509::CertInfo::parse(&der.0) {
+            if candidate.subject == cert.issuer && signed(candidate.spki) {
+                return Some((candidate.subject.to_vec(), candidate.spki.to_vec()));
+            }
+        }
+    }
+
+    roots.roots
+        .iter()
+        .map(anchor_subject_and_spki)
+        .find(|(subject, spki)| subject[..] == cert.issuer[..] && signed(spki))
+}
+
+/// Return the DER-encoded subject and SubjectPublicKeyInfo of `root`.
+/// webpki stores these without their outer SEQUENCE.
+fn anchor_subject_and_spki(root: &OwnedTrustAnchor) -> (Vec<u8>, Vec<u8>) {
+    let anchor = root.to_trust_anchor();
+    let mut subject = anchor.subject.to_vec();
+    x509::wrap_in_sequence(&mut subject);
+    let mut spki = anchor.spki.to_vec();
+    x509::wrap_in_sequence(&mut spki);
+    (subject, spki)
+}
+
+/// Check `ocsp_response` for the end-entity certificate in the
+/// (already validated) `presented_certs`, according to `policy`.
+fn check_ocsp(policy: OCSPPolicy,
+              roots: &RootCertStore,
+              presented_certs: &[Certificate],
+              ocsp_response: &[u8],
+              now: webpki::Time) -> Result<(), TLSError> {
+    if policy == OCSPPolicy::Ignore {
+        return Ok(());
+    }
+
+    let cert = x509::CertInfo::parse(&presented_certs[0].0)
+        .ok_or(TLSError::WebPKIError(webpki::Error::BadDER))?;
+
+    let policy = if policy == OCSPPolicy::SoftFail && ocsp::must_staple(&cert) {
+        OCSPPolicy::Require
+    } else {
+        policy
+    };
+
+    let status = if ocsp_response.is_empty() {
+        Err(TLSError::InvalidOCSPResponse("no response stapled".to_string()))
+    } else {
+        match find_issuer(&cert, &presented_certs[1..], roots) {
+            Some((name, spki)) => ocsp::check_response(ocsp_response, &cert, &name, &spki, now),
+            None => Err(TLSError::InvalidOCSPResponse("cannot find issuer".to_string())),
+        }
+    };
+
+    match status {
+        Ok(CertStatus::Good) => Ok(()),
+        Ok(CertStatus::Revoked) => Err(TLSError::CertificateRevoked),
+        Ok(CertStatus::Unknown) if policy == OCSPPolicy::Require => {
+            Err(TLSError::InvalidOCSPResponse("certificate status unknown".to_string()))
+        }
+        Err(err) if policy == OCSPPolicy::Require => Err(err),
+        #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+        other => {
+            debug!("Ignoring OCSP result {:?}", other);
+            Ok(())
         }
     }
 }
@@ -150,11 +478,6 @@ fn prepare<'a, 'b>(roots: &'b RootCertStore, presented_certs: &'a [Certificate])
     Ok((cert, chain, trustroots))
 }
 
-fn try_now() -> Result<webpki::Time, TLSError> {
-    webpki::Time::try_from(std::time::SystemTime::now())
-        .map_err( |_ | TLSError::FailedToGetCurrentTime)
-}
-
 /// A `ClientCertVerifier` that will ensure that every client provides a trusted
 /// certificate, without any name checking.
 pub struct AllowAnyAuthenticatedClient {
@@ -179,10 +502,9 @@ impl ClientCertVerifier for AllowAnyAuthenticatedClient {
         self.roots.get_subjects()
     }
 
-    fn verify_client_cert(&self, presented_certs: &[Certificate])
+    fn verify_client_cert(&self, presented_certs: &[Certificate], now: webpki::Time)
                           -> Result<ClientCertVerified, TLSError> {
         let (cert, chain, trustroots) = prepare(&self.roots, presented_certs)?;
-        let now = try_now()?;
         cert.verify_is_valid_tls_client_cert(
                 SUPPORTED_SIG_ALGS, &webpki::TLSClientTrustAnchors(&trustroots),
                 &chain, now)
@@ -221,153 +543,293 @@ impl ClientCertVerifier for AllowAnyAnonymousOrAuthenticatedClient {
         self.inner.client_auth_root_subjects()
     }
 
-    fn verify_client_cert(&self, presented_certs: &[Certificate])
+    fn verify_client_cert(&self, presented_certs: &[Certificate], now: webpki::Time)
             -> Result<ClientCertVerified, TLSError> {
-        self.inner.verify_client_cert(presented_certs)
+        self.inner.verify_client_cert(presented_certs, now)
+    }
+
+    fn verify_client_cert_for_sni(&self,
+                                  presented_certs: &[Certificate],
+                                  sni: Option<webpki::DNSNameRef>,
+                                  now: webpki::Time) -> Result<ClientCertVerified, TLSError> {
+        self.inner.verify_client_cert_for_sni(presented_certs, sni, now)
     }
 }
 
-/// Turns off client authentication.
-pub struct NoClientAuth;
+/// A `ClientCertVerifier` which verifies client certificates with
+/// another verifier, and then checks they haven't been revoked.
+pub struct RevocationCheckingClientVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+    roots: RootCertStore,
+    crls: CRLStore,
+    policy: RevocationPolicy,
+}
 
-impl NoClientAuth {
-    /// Constructs a `NoClientAuth` and wraps it in an `Arc`.
-    pub fn new() -> Arc<dyn ClientCertVerifier> { Arc::new(NoClientAuth) }
+impl RevocationCheckingClientVerifier {
+    /// Construct a new `RevocationCheckingClientVerifier`.
+    ///
+    /// `inner` does the rest of the verification; `roots` should be
+    /// the trust anchors it uses.  Certificates are checked against
+    /// `crls` according to `policy`.
+    pub fn new(inner: Arc<dyn ClientCertVerifier>,
+               roots: RootCertStore,
+               crls: CRLStore,
+               policy: RevocationPolicy) -> Arc<dyn ClientCertVerifier> {
+        Arc::new(RevocationCheckingClientVerifier { inner, roots, crls, policy })
+    }
 }
 
-impl ClientCertVerifier for NoClientAuth {
-    fn offer_client_auth(&self) -> bool { false }
+impl ClientCertVerifier for RevocationCheckingClientVerifier {
+    fn offer_client_auth(&self) -> bool { self.inner.offer_client_auth() }
+
+    fn client_auth_mandatory(&self) -> bool { self.inner.client_auth_mandatory() }
 
     fn client_auth_root_subjects(&self) -> DistinguishedNames {
-        unimplemented!();
+        self.inner.client_auth_root_subjects()
     }
 
-    fn verify_client_cert(&self, _presented_certs: &[Certificate])
+    fn verify_client_cert(&self, presented_certs: &[Certificate], now: webpki::Time)
                           -> Result<ClientCertVerified, TLSError> {
-        unimplemented!();
+        self.verify_client_cert_for_sni(presented_certs, None, now)
+    }
+
+    fn verify_client_cert_for_sni(&self,
+                                  presented_certs: &[Certificate],
+                                  sni: Option<webpki::DNSNameRef>,
+                                  now: webpki::Time) -> Result<ClientCertVerified, TLSError> {
+        let verified = self.inner.verify_client_cert_for_sni(presented_certs, sni, now)?;
+        check_revocation(&self.crls, &self.policy, &self.roots, presented_certs, now)?;
+        Ok(verified)
     }
 }
 
-static ECDSA_SHA256: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA256,
-                                             &webpki::ECDSA_P384_SHA256];
-static ECDSA_SHA384: SignatureAlgorithms = &[&webpki::ECDSA_P256_SHA384,
-                                             &webpki::ECDSA_P384_SHA384];
+/// A `ClientCertVerifier` which verifies client certificates with
+/// another verifier, and then checks who they identify against a
+/// `ClientIdentityPolicy`.
+///
+/// The policy can depend on the name the client asked for with SNI,
+/// so one server can accept different clients for each of its names.
+pub struct PolicyCheckingClientVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+    policy: ClientIdentityPolicy,
+    sni_policies: collections::HashMap<String, ClientIdentityPolicy>,
+}
 
-static RSA_SHA256: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA256];
-static RSA_SHA384: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA384];
-static RSA_SHA512: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA512];
-static RSA_PSS_SHA256: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY];
-static RSA_PSS_SHA384: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY];
-static RSA_PSS_SHA512: SignatureAlgorithms = &[&webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY];
+impl PolicyCheckingClientVerifier {
+    /// Construct a new `PolicyCheckingClientVerifier`.
+    ///
+    /// `inner` does the rest of the verification, and `policy` is
+    /// used for clients which didn't send SNI, or sent a name with
+    /// no policy of its own.
+    pub fn new(inner: Arc<dyn ClientCertVerifier>,
+               policy: ClientIdentityPolicy) -> PolicyCheckingClientVerifier {
+        PolicyCheckingClientVerifier {
+            inner,
+            policy,
+            sni_policies: collections::HashMap::new(),
+        }
+    }
 
-fn convert_scheme(scheme: SignatureScheme) -> Result<SignatureAlgorithms, TLSError> {
-    match scheme {
-        // nb. for TLS1.2 the curve is not fixed by SignatureScheme.
-        SignatureScheme::ECDSA_NISTP256_SHA256 => Ok(ECDSA_SHA256),
-        SignatureScheme::ECDSA_NISTP384_SHA384 => Ok(ECDSA_SHA384),
+    /// Use `policy` for clients which asked for `sni`, replacing any
+    /// existing policy for it.
+    ///
+    /// This fails if `sni` is not a valid DNS name.
+    pub fn add_sni_policy(&mut self, sni: &str, policy: ClientIdentityPolicy) -> Result<(), TLSError> {
+        let sni = webpki::DNSNameRef::try_from_ascii_str(sni)
+            .map_err(|_| TLSError::General("Bad DNS name".into()))?;
+        let sni: &str = sni.into();
+        self.sni_policies.insert(sni.to_ascii_lowercase(), policy);
+        Ok(())
+    }
+}
 
-        SignatureScheme::RSA_PKCS1_SHA256 => Ok(RSA_SHA256),
-        SignatureScheme::RSA_PKCS1_SHA384 => Ok(RSA_SHA384),
-        SignatureScheme::RSA_PKCS1_SHA512 => Ok(RSA_SHA512),
+impl ClientCertVerifier for PolicyCheckingClientVerifier {
+    fn offer_client_auth(&self) -> bool { self.inner.offer_client_auth() }
 
-        SignatureScheme::RSA_PSS_SHA256 => Ok(RSA_PSS_SHA256),
-        SignatureScheme::RSA_PSS_SHA384 => Ok(RSA_PSS_SHA384),
-        SignatureScheme::RSA_PSS_SHA512 => Ok(RSA_PSS_SHA512),
+    fn client_auth_mandatory(&self) -> bool { self.inner.client_auth_mandatory() }
 
-        _ => {
-            let error_msg = format!("received unadvertised sig scheme {:?}", scheme);
-            Err(TLSError::PeerMisbehavedError(error_msg))
-        }
+    fn client_auth_root_subjects(&self) -> DistinguishedNames {
+        self.inner.client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(&self, presented_certs: &[Certificate], now: webpki::Time)
+                          -> Result<ClientCertVerified, TLSError> {
+        self.verify_client_cert_for_sni(presented_certs, None, now)
+    }
+
+    fn verify_client_cert_for_sni(&self,
+                                  presented_certs: &[Certificate],
+                                  sni: Option<webpki::DNSNameRef>,
+                                  now: webpki::Time) -> Result<ClientCertVerified, TLSError> {
+        let verified = self.inner.verify_client_cert_for_sni(presented_certs, sni, now)?;
+
+        let policy = sni
+            .and_then(|sni| {
+                let sni: &str = sni.into();
+                self.sni_policies.get(&sni.to_ascii_lowercase())
+            })
+            .unwrap_or(&self.policy);
+        policy.check(&presented_certs[0])?;
+        Ok(verified)
     }
 }
 
-fn verify_sig_using_any_alg(cert: &webpki::EndEntityCert,
-                            algs: SignatureAlgorithms,
-                            message: &[u8],
-                            sig: &[u8])
-                            -> Result<(), webpki::Error> {
-    // TLS doesn't itself give us enough info to map to a single webpki::SignatureAlgorithm.
-    // Therefore, convert_algs maps to several and we try them all.
-    for alg in algs {
-        match cert.verify_signature(alg, message, sig) {
-            Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey) => continue,
-            res => return res,
+/// A `ServerCertVerifier` which verifies server certificates with
+/// another verifier, and then checks they haven't been revoked.
+pub struct RevocationCheckingServerVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    crls: CRLStore,
+    policy: RevocationPolicy,
+}
+
+impl RevocationCheckingServerVerifier {
+    /// Construct a new `RevocationCheckingServerVerifier`, which
+    /// verifies certificates with `inner` and then checks them
+    /// against `crls` according to `policy`.
+    pub fn new(inner: Arc<dyn ServerCertVerifier>,
+               crls: CRLStore,
+               policy: RevocationPolicy) -> RevocationCheckingServerVerifier {
+        RevocationCheckingServerVerifier { inner, crls, policy }
+    }
+}
+
+impl ServerCertVerifier for RevocationCheckingServerVerifier {
+    fn verify_server_cert(&self,
+                          roots: &RootCertStore,
+                          presented_certs: &[Certificate],
+                          server_name: &ServerName,
+                          ocsp_response: &[u8],
+                          now: webpki::Time) -> Result<ServerCertVerified, TLSError> {
+        let verified = self.inner.verify_server_cert(roots, presented_certs,
+                                                     server_name, ocsp_response, now)?;
+        check_revocation(&self.crls, &self.policy, roots, presented_certs, now)?;
+        Ok(verified)
+    }
+}
+
+/// Is `cert` one of `roots`?
+fn is_trust_anchor(cert: &x509::CertInfo, roots: &RootCertStore) -> bool {
+    roots.roots
+        .iter()
+        .map(anchor_subject_and_spki)
+        .any(|(subject, spki)| subject == cert.subject && spki == cert.spki)
+}
+
+/// Check the certificates in `presented_certs`, which must already
+/// have been validated, against `crls` according to `policy`.
+pub(crate) fn check_revocation(crls: &CRLStore,
+                               policy: &RevocationPolicy,
+                               roots: &RootCertStore,
+                               presented_certs: &[Certificate],
+                               now: webpki::Time) -> Result<(), TLSError> {
+    let depth = match policy.depth {
+        RevocationCheckDepth::EndEntity => 1,
+        RevocationCheckDepth::Chain => presented_certs.len(),
+    };
+
+    for (i, der) in presented_certs.iter().enumerate().take(depth) {
+        let cert = x509::CertInfo::parse(&der.0)
+            .ok_or(TLSError::WebPKIError(webpki::Error::BadDER))?;
+
+        // Peers sometimes send the root too.
+        if i > 0 && is_trust_anchor(&cert, roots) {
+            break;
         }
+
+        let status = match find_issuer(&cert, &presented_certs[i + 1..], roots) {
+            Some((name, spki)) => crls.status(&cert, &name, &spki, now,
+                                              policy.allow_expired_crls),
+            None => CertStatus::Unknown,
+        };
+
+        match status {
+            CertStatus::Good => {}
+            CertStatus::Revoked => return Err(TLSError::CertificateRevoked),
+            CertStatus::Unknown if policy.unknown_status == UnknownStatusPolicy::Deny => {
+                return Err(TLSError::UnknownRevocationStatus);
+            }
+            CertStatus::Unknown => debug!("No CRL for certificate {}", i),
+        }
+    }
+
+    Ok(())
+}
+
+/// Turns off client authentication.
+pub struct NoClientAuth;
+
+impl NoClientAuth {
+    /// Constructs a `NoClientAuth` and wraps it in an `Arc`.
+    pub fn new() -> Arc<dyn ClientCertVerifier> { Arc::new(NoClientAuth) }
+}
+
+impl ClientCertVerifier for NoClientAuth {
+    fn offer_client_auth(&self) -> bool { false }
+
+    fn client_auth_root_subjects(&self) -> DistinguishedNames {
+        unimplemented!();
     }
 
-    Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey)
+    fn verify_client_cert(&self, _presented_certs: &[Certificate], _now: webpki::Time)
+                          -> Result<ClientCertVerified, TLSError> {
+        unimplemented!();
+    }
 }
 
 /// Verify the signed `message` using the public key quoted in
-/// `cert` and algorithm and signature in `dss`.
+/// `cert` and algorithm and signature in `dss`.  If `cert_type` is
+/// `RawPublicKey`, `cert` is a SubjectPublicKeyInfo.
 ///
 /// `cert` MUST have been authenticated before using this function,
 /// typically using `verify_cert`.
-pub fn verify_signed_struct(message: &[u8],
+pub fn verify_signed_struct(provider: &dyn CryptoProvider,
+                            message: &[u8],
                             cert: &Certificate,
+                            cert_type: CertificateType,
                             dss: &DigitallySignedStruct)
                             -> Result<HandshakeSignatureValid, TLSError> {
+    let result = if cert_type == CertificateType::RawPublicKey {
+        provider.verify_tls12_raw_signature(message, &cert.0, dss.scheme, &dss.sig.0)
+    } else {
+        provider.verify_tls12_signature(message, cert, dss.scheme, &dss.sig.0)
+    };
 
-    let possible_algs = convert_scheme(dss.scheme)?;
-    let cert = webpki::EndEntityCert::from(&cert.0)
-        .map_err(TLSError::WebPKIError)?;
-
-    verify_sig_using_any_alg(&cert, possible_algs, message, &dss.sig.0)
-        .map_err(TLSError::WebPKIError)
-        .map(|_| HandshakeSignatureValid::assertion())
+    result.map(|_| HandshakeSignatureValid::assertion())
 }
 
-fn convert_alg_tls13(scheme: SignatureScheme)
-                     -> Result<&'static webpki::SignatureAlgorithm, TLSError> {
-    use crate::msgs::enums::SignatureScheme::*;
-
-    match scheme {
-        ECDSA_NISTP256_SHA256 => Ok(&webpki::ECDSA_P256_SHA256),
-        ECDSA_NISTP384_SHA384 => Ok(&webpki::ECDSA_P384_SHA384),
-        RSA_PSS_SHA256 => Ok(&webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY),
-        RSA_PSS_SHA384 => Ok(&webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY),
-        RSA_PSS_SHA512 => Ok(&webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY),
-        _ => {
-            let error_msg = format!("received unsupported sig scheme {:?}", scheme);
-            Err(TLSError::PeerMisbehavedError(error_msg))
-        }
-    }
-}
-
-pub fn verify_tls13(cert: &Certificate,
+pub fn verify_tls13(provider: &dyn CryptoProvider,
+                    cert: &Certificate,
+                    cert_type: CertificateType,
                     dss: &DigitallySignedStruct,
                     handshake_hash: &[u8],
                     context_string_with_0: &[u8])
                     -> Result<HandshakeSignatureValid, TLSError> {
-    let alg = convert_alg_tls13(dss.scheme)?;
-
     let mut msg = Vec::new();
     msg.resize(64, 0x20u8);
     msg.extend_from_slice(context_string_with_0);
     msg.extend_from_slice(handshake_hash);
 
-    let cert = webpki::EndEntityCert::from(&cert.0)
-        .map_err(TLSError::WebPKIError)?;
+    let result = if cert_type == CertificateType::RawPublicKey {
+        provider.verify_tls13_raw_signature(&msg, &cert.0, dss.scheme, &dss.sig.0)
+    } else {
+        provider.verify_tls13_signature(&msg, cert, dss.scheme, &dss.sig.0)
+    };
 
-    cert.verify_signature(alg, &msg, &dss.sig.0)
-        .map_err(TLSError::WebPKIError)
-        .map(|_| HandshakeSignatureValid::assertion())
+    result.map(|_| HandshakeSignatureValid::assertion())
 }
 
-fn unix_time_millis() -> Result<u64, TLSError> {
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|dur| dur.as_secs())
-        .map_err(|_| TLSError::FailedToGetCurrentTime)
-        .and_then(|secs| secs.checked_mul(1000)
-                  .ok_or(TLSError::FailedToGetCurrentTime))
+pub(crate) fn unix_time_millis(time_provider: &dyn TimeProvider) -> Result<u64, TLSError> {
+    time_provider.now()
+        .and_then(|secs| secs.checked_mul(1000))
+        .ok_or(TLSError::FailedToGetCurrentTime)
 }
 
 pub fn verify_scts(cert: &Certificate,
                    scts: &SCTList,
-                   logs: &[&sct::Log]) -> Result<(), TLSError> {
+                   logs: &[&sct::Log],
+                   time_provider: &dyn TimeProvider) -> Result<(), TLSError> {
     let mut valid_scts = 0;
-    let now = unix_time_millis()?;
+    let now = unix_time_millis(time_provider)?;
     let mut last_sct_error = None;
 
     for sct in scts {
@@ -397,18 +859,3 @@ pub fn verify_scts(cert: &Certificate,
 
     Ok(())
 }
-
-pub fn supported_verify_schemes() -> &'static [SignatureScheme] {
-    &[
-        SignatureScheme::ECDSA_NISTP384_SHA384,
-        SignatureScheme::ECDSA_NISTP256_SHA256,
-
-        SignatureScheme::RSA_PSS_SHA512,
-        SignatureScheme::RSA_PSS_SHA384,
-        SignatureScheme::RSA_PSS_SHA256,
-
-        SignatureScheme::RSA_PKCS1_SHA512,
-        SignatureScheme::RSA_PKCS1_SHA384,
-        SignatureScheme::RSA_PKCS1_SHA256,
-    ]
-}
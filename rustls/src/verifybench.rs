@@ -7,9 +7,9 @@
 use std::time::{Duration, Instant};
 
 use crate::anchors;
-use crate::error::TLSError;
 use crate::verify;
 use crate::verify::ServerCertVerifier;
+use crate::ocsp::OCSPPolicy;
 use crate::key;
 use webpki;
 
@@ -37,12 +37,12 @@ fn bench<Fsetup, Ftest, S>(count: usize, name: &'static str, f_setup: Fsetup, f_
              times.iter().min().unwrap() / 1000);
 }
 
-fn fixed_time() -> Result<webpki::Time, TLSError> {
-    Ok(webpki::Time::from_seconds_since_unix_epoch(1500000000))
+fn fixed_time() -> webpki::Time {
+    webpki::Time::from_seconds_since_unix_epoch(1500000000)
 }
 
 static V: &'static verify::WebPKIVerifier = &verify::WebPKIVerifier {
-    time: fixed_time,
+    ocsp_policy: OCSPPolicy::SoftFail,
 };
 
 #[test]
@@ -57,7 +57,7 @@ fn test_reddit_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("reddit.com")
           .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[], fixed_time()).unwrap();
     });
 }
 
@@ -73,7 +73,7 @@ fn test_github_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("github.com")
           .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[], fixed_time()).unwrap();
     });
 }
 
@@ -90,7 +90,7 @@ fn test_arstechnica_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("arstechnica.com")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[], fixed_time()).unwrap();
     });
 }
 
@@ -107,7 +107,7 @@ fn test_servo_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("servo.org")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[], fixed_time()).unwrap();
     });
 }
 
@@ -123,7 +123,7 @@ fn test_twitter_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("twitter.com")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap(); });
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[], fixed_time()).unwrap(); });
 }
 
 #[test]
@@ -138,7 +138,7 @@ fn test_wikipedia_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("wikipedia.org")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[], fixed_time()).unwrap();
     });
 }
 
@@ -155,7 +155,7 @@ fn test_google_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("www.google.com")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[], fixed_time()).unwrap();
     });
 }
 
@@ -172,7 +172,7 @@ fn test_hn_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("news.ycombinator.com")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[], fixed_time()).unwrap();
     });
 }
 
@@ -188,7 +188,7 @@ fn test_stackoverflow_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("stackoverflow.com")
           .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[], fixed_time()).unwrap();
     });
 }
 
@@ -204,7 +204,7 @@ fn test_duckduckgo_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("duckduckgo.com")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[], fixed_time()).unwrap();
     });
 }
 
@@ -221,7 +221,7 @@ fn test_rustlang_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("www.rust-lang.org")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[], fixed_time()).unwrap();
     });
 }
 
@@ -238,7 +238,7 @@ fn test_wapo_cert() {
           |_| {
         let dns_name = webpki::DNSNameRef::try_from_ascii_str("www.washingtonpost.com")
             .unwrap();
-        V.verify_server_cert(&anchors, &chain[..], dns_name, &[]).unwrap();
+        V.verify_server_cert(&anchors, &chain[..], &dns_name.into(), &[], fixed_time()).unwrap();
     });
 }
 
@@ -0,0 +1,165 @@
+// X25519 (RFC7748) with long-lived private keys.
+//
+// ring only offers ephemeral key agreement: a private key is consumed
+// by its first use and cannot be imported.  Things like ESNI and ECH
+// need a server to hold the same X25519 key for the lifetime of a
+// published record, so for that case we use x25519-dalek's
+// `StaticSecret`.  Everything else continues to use ring.
+
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Length of X25519 scalars, u-coordinates and shared secrets.
+pub const X25519_LEN: usize = 32;
+
+/// Compute the public key for the private key `scalar`.
+pub fn x25519_base(scalar: &[u8; X25519_LEN]) -> [u8; X25519_LEN] {
+    PublicKey::from(&StaticSecret::from(*scalar)).to_bytes()
+}
+
+/// Agree a shared secret between our private key `scalar` and
+/// the peer's public key `peer`.  Returns None if `peer` is
+/// malformed or the result is all-zero (RFC7748 section 6.1).
+pub fn agree(scalar: &[u8; X25519_LEN], peer: &[u8]) -> Option<[u8; X25519_LEN]> {
+    if peer.len() != X25519_LEN {
+        return None;
+    }
+
+    let mut u = [0u8; X25519_LEN];
+    u.copy_from_slice(peer);
+    let shared = StaticSecret::from(*scalar)
+        .diffie_hellman(&PublicKey::from(u));
+
+    if shared.was_contributory() {
+        Some(shared.to_bytes())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use x25519_dalek::x25519;
+
+    fn arr(v: &[u8]) -> [u8; X25519_LEN] {
+        let mut r = [0u8; X25519_LEN];
+        r.copy_from_slice(v);
+        r
+    }
+
+    #[test]
+    fn rfc7748_vector_1() {
+        let k = hex!("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4");
+        let u = hex!("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c");
+        let expect = hex!("c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552");
+        assert_eq!(agree(&arr(&k), &u).unwrap(), expect);
+    }
+
+    #[test]
+    fn rfc7748_vector_2() {
+        let k = hex!("4b66e9d4d1b4673c5ad22691957d6af5c11b6421e0ea01d42ca4169e7918ba0d");
+        let u = hex!("e5210f12786811d3f4b7959d0538ae2c31dbe7106fc03c3efc4cd549c715a493");
+        let expect = hex!("95cbde9476e8907d7aade45cb4b873f88b595a68799fa152e6f8f7647aac7957");
+        assert_eq!(agree(&arr(&k), &u).unwrap(), expect);
+    }
+
+    // RFC7748 section 5.2: k and u both start as 9, then each
+    // round k becomes X25519(k, u) and u the old k.
+    fn iterate(rounds: usize) -> [u8; X25519_LEN] {
+        let mut k = [0u8; X25519_LEN];
+        k[0] = 9;
+        let mut u = k;
+
+        for _ in 0..rounds {
+            let r = x25519(k, u);
+            u = k;
+            k = r;
+        }
+
+        k
+    }
+
+    #[test]
+    fn rfc7748_iterated_once() {
+        assert_eq!(iterate(1),
+                   hex!("422c8e7a6227d7bca1350b3e2bb7279f7897b87bb6854b783c60e80311ae3079"));
+    }
+
+    #[test]
+    fn rfc7748_iterated_1000() {
+        assert_eq!(iterate(1000),
+                   hex!("684cf59ba83309552800ef566f2f4d3c1c3887c49360e3875f2eb94d99532c51"));
+    }
+
+    #[test]
+    #[ignore]
+    fn rfc7748_iterated_1000000() {
+        assert_eq!(iterate(1_000_000),
+                   hex!("7c3911e0ab2586fd864497297e575e6f3bc601c0883c30df5f4dd2d24f131654"));
+    }
+
+    #[test]
+    fn rfc7748_diffie_hellman() {
+        let alice = hex!("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob = hex!("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+        let alice_pub = x25519_base(&arr(&alice));
+        let bob_pub = x25519_base(&arr(&bob));
+        assert_eq!(alice_pub,
+                   hex!("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"));
+        assert_eq!(bob_pub,
+                   hex!("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f"));
+
+        let shared = hex!("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+        assert_eq!(agree(&arr(&alice), &bob_pub).unwrap(), shared);
+        assert_eq!(agree(&arr(&bob), &alice_pub).unwrap(), shared);
+    }
+
+    #[test]
+    fn agrees_with_ring() {
+        let ours = [0x42u8; X25519_LEN];
+        let our_pub = x25519_base(&ours);
+
+        let rng = ring::rand::SystemRandom::new();
+        let theirs = ring::agreement::EphemeralPrivateKey::generate(&ring::agreement::X25519, &rng)
+            .unwrap();
+        let their_pub = theirs.compute_public_key().unwrap();
+
+        let peer = ring::agreement::UnparsedPublicKey::new(&ring::agreement::X25519, &our_pub[..]);
+        let ring_secret = ring::agreement::agree_ephemeral(theirs, &peer, (), |s| Ok(s.to_vec()))
+            .unwrap();
+
+        assert_eq!(agree(&ours, their_pub.as_ref()).unwrap().to_vec(), ring_secret);
+    }
+
+    #[test]
+    fn rejects_bad_peers() {
+        let ours = [0x42u8; X25519_LEN];
+        assert!(agree(&ours, &[0u8; 31]).is_none());
+        assert!(agree(&ours, &[0u8; 33]).is_none());
+    }
+
+    // The low-order points from Wycheproof's x25519_test.json, which
+    // give an all-zero shared secret whatever our key is: 0, 1, the
+    // two points of order 8, p - 1, p and p + 1, and 0 and 1 again
+    // with the (ignored) top bit set.
+    #[test]
+    fn rejects_low_order_points() {
+        let points = [
+            hex!("0000000000000000000000000000000000000000000000000000000000000000"),
+            hex!("0100000000000000000000000000000000000000000000000000000000000000"),
+            hex!("e0eb7a7c3b41b8ae1656e3faf19fc46ada098deb9c32b1fd866205165f49b800"),
+            hex!("5f9c95bca3508c24b1d0b1559c83ef5b04445cc4581c8e86d8224eddd09f1157"),
+            hex!("ecffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f"),
+            hex!("edffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f"),
+            hex!("eeffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f"),
+            hex!("0000000000000000000000000000000000000000000000000000000000000080"),
+            hex!("0100000000000000000000000000000000000000000000000000000000000080"),
+        ];
+
+        for scalar in &[[0x42u8; X25519_LEN], x25519_base(&[0x17u8; X25519_LEN])] {
+            for point in points.iter() {
+                assert!(agree(scalar, point).is_none(), "accepted {:?}", point);
+            }
+        }
+    }
+}
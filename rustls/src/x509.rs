@@ -17,10 +17,439 @@ fn wrap_in_asn1_len(bytes: &mut Vec<u8>) {
     }
 }
 
+/// Prepend stuff to `bytes` to put it in a DER value with `tag`.
+pub fn wrap_in_tag(bytes: &mut Vec<u8>, tag: u8) {
+    wrap_in_asn1_len(bytes);
+    bytes.insert(0, tag);
+}
+
 /// Prepend stuff to `bytes` to put it in a DER SEQUENCE.
 pub fn wrap_in_sequence(bytes: &mut Vec<u8>) {
-    wrap_in_asn1_len(bytes);
-    bytes.insert(0, der::Tag::Sequence as u8);
+    wrap_in_tag(bytes, der::Tag::Sequence as u8);
+}
+
+/// Read a DER value with `tag` from the front of `input`, returning
+/// its contents and advancing `input` past it.
+///
+/// Returns None (and leaves `input` alone) if the next value doesn't
+/// have `tag`, or is truncated.
+pub fn read_der<'a>(input: &mut &'a [u8], tag: u8) -> Option<&'a [u8]> {
+    let bytes = *input;
+    if bytes.len() < 2 || bytes[0] != tag {
+        return None;
+    }
+
+    let (len, header) = match bytes[1] {
+        short @ 0x00..=0x7f => (short as usize, 2),
+        long @ 0x81..=0x83 => {
+            let octets = (long & 0x7f) as usize;
+            let len_bytes = bytes.get(2..2 + octets)?;
+            if len_bytes[0] == 0 {
+                return None;
+            }
+            let len = len_bytes.iter()
+                .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+            (len, 2 + octets)
+        }
+        _ => return None,
+    };
+
+    let body = bytes.get(header..header + len)?;
+    *input = &bytes[header + len..];
+    Some(body)
+}
+
+/// Read a whole DER value with `tag` from the front of `input`,
+/// returning it including its tag and length.
+pub fn read_der_value<'a>(input: &mut &'a [u8], tag: u8) -> Option<&'a [u8]> {
+    let start = *input;
+    read_der(input, tag)?;
+    Some(&start[..start.len() - input.len()])
+}
+
+/// Read a BIT STRING with no unused bits, returning its contents.
+pub fn read_bit_string<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    match read_der(input, der::Tag::BitString as u8)? {
+        [0, bits @ ..] => Some(bits),
+        _ => None,
+    }
+}
+
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+
+/// Days from 1970-01-01 to the given date in the proleptic
+/// Gregorian calendar.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Read a UTCTime or GeneralizedTime, returning it in seconds
+/// since the Unix epoch.  Only the `Z`-terminated forms DER allows
+/// are accepted, though we tolerate fractional seconds.
+pub fn read_time(input: &mut &[u8]) -> Option<u64> {
+    let (text, year_digits) = if let Some(text) = read_der(input, TAG_UTC_TIME) {
+        (text, 2)
+    } else {
+        (read_der(input, TAG_GENERALIZED_TIME)?, 4)
+    };
+
+    let fixed = year_digits + 10;
+    if text.len() < fixed + 1 || text[text.len() - 1] != b'Z' {
+        return None;
+    }
+
+    let fraction = &text[fixed..text.len() - 1];
+    if !fraction.is_empty() &&
+        (year_digits != 4 || fraction[0] != b'.' || fraction.len() < 2 ||
+         !fraction[1..].iter().all(u8::is_ascii_digit)) {
+        return None;
+    }
+
+    let digits = &text[..fixed];
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| {
+        digits[range].iter().fold(0u64, |acc, d| acc * 10 + (d - b'0') as u64)
+    };
+
+    let mut year = number(0..year_digits);
+    if year_digits == 2 {
+        year += if year < 50 { 2000 } else { 1900 };
+    }
+    let month = number(year_digits..year_digits + 2);
+    let day = number(year_digits + 2..year_digits + 4);
+    let hour = number(year_digits + 4..year_digits + 6);
+    let minute = number(year_digits + 6..year_digits + 8);
+    let second = number(year_digits + 8..year_digits + 10);
+
+    if year < 1970 || month < 1 || month > 12 || day < 1 || day > 31 ||
+        hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    Some(((days_from_civil(year, month, day) * 24 + hour) * 60 + minute) * 60 + second)
+}
+
+/// Read a time if the next element is one.  Returns `Some(None)` if
+/// it isn't, and None if it is but it's malformed.
+pub fn read_optional_time(input: &mut &[u8]) -> Option<Option<u64>> {
+    match input.first() {
+        Some(&TAG_UTC_TIME) | Some(&TAG_GENERALIZED_TIME) => read_time(input).map(Some),
+        _ => Some(None),
+    }
+}
+
+/// The parts of an X.509 certificate we sometimes need to look at
+/// ourselves.  webpki is responsible for validating certificates:
+/// this does no more checking than is needed to find these fields.
+pub struct CertInfo<'a> {
+    /// The signed `tbsCertificate`, including its tag and length.
+    pub tbs: &'a [u8],
+
+    /// The contents of the outer `signatureAlgorithm`.
+    pub signature_alg: &'a [u8],
+
+    /// The signature value.
+    pub signature: &'a [u8],
+
+    /// The contents of the `serialNumber` INTEGER.
+    pub serial: &'a [u8],
+
+    /// The `issuer` Name, including its tag and length.
+    pub issuer: &'a [u8],
+
+    /// The start of the `validity` period, in seconds since the
+    /// Unix epoch.
+    pub not_before: u64,
+
+    /// The end of the `validity` period.
+    pub not_after: u64,
+
+    /// The `subject` Name, including its tag and length.
+    pub subject: &'a [u8],
+
+    /// The `subjectPublicKeyInfo`, including its tag and length.
+    pub spki: &'a [u8],
+
+    /// The contents of the `extensions` SEQUENCE, or nothing.
+    pub extensions: &'a [u8],
+}
+
+impl<'a> CertInfo<'a> {
+    pub fn parse(cert: &'a [u8]) -> Option<CertInfo<'a>> {
+        let mut input = cert;
+        let mut outer = read_der(&mut input, der::Tag::Sequence as u8)?;
+        if !input.is_empty() {
+            return None;
+        }
+
+        let tbs = read_der_value(&mut outer, der::Tag::Sequence as u8)?;
+        let signature_alg = read_der(&mut outer, der::Tag::Sequence as u8)?;
+        let signature = read_bit_string(&mut outer)?;
+
+        let mut fields = &tbs[..];
+        let mut fields = read_der(&mut fields, der::Tag::Sequence as u8)?;
+        read_der(&mut fields, der::Tag::ContextSpecificConstructed0 as u8);
+        let serial = read_der(&mut fields, der::Tag::Integer as u8)?;
+        read_der(&mut fields, der::Tag::Sequence as u8)?;
+        let issuer = read_der_value(&mut fields, der::Tag::Sequence as u8)?;
+        let mut validity = read_der(&mut fields, der::Tag::Sequence as u8)?;
+        let not_before = read_time(&mut validity)?;
+        let not_after = read_time(&mut validity)?;
+        let subject = read_der_value(&mut fields, der::Tag::Sequence as u8)?;
+        let spki = read_der_value(&mut fields, der::Tag::Sequence as u8)?;
+
+        // Skip issuerUniqueID and subjectUniqueID.
+        read_der(&mut fields, 0x81);
+        read_der(&mut fields, 0x82);
+
+        let extensions = match read_der(&mut fields, der::Tag::ContextSpecificConstructed3 as u8) {
+            Some(mut wrapped) => read_der(&mut wrapped, der::Tag::Sequence as u8)?,
+            None => &[],
+        };
+
+        Some(CertInfo {
+            tbs,
+            signature_alg,
+            signature,
+            serial,
+            issuer,
+            not_before,
+            not_after,
+            subject,
+            spki,
+            extensions,
+        })
+    }
+
+    /// Return the contents of the `extnValue` of the extension
+    /// with `oid`, if there is one.
+    pub fn extension(&self, oid: &[u8]) -> Option<&'a [u8]> {
+        find_extension(self.extensions, oid)
+    }
+
+    /// Return the `tbsCertificate`, re-encoded without the extension
+    /// with `oid`.  This is how a precertificate is recovered from a
+    /// certificate, for checking embedded SCTs.
+    pub fn tbs_without_extension(&self, oid: &[u8]) -> Option<Vec<u8>> {
+        let mut tbs = self.tbs;
+        let fields = read_der(&mut tbs, der::Tag::Sequence as u8)?;
+
+        // Everything before the extensions is kept as it is.
+        let mut rest = fields;
+        while let Some(&tag) = rest.first() {
+            if tag == der::Tag::ContextSpecificConstructed3 as u8 {
+                break;
+            }
+            read_der(&mut rest, tag)?;
+        }
+
+        let mut extensions = Vec::new();
+        let mut input = self.extensions;
+        while !input.is_empty() {
+            let extension = read_der_value(&mut input, der::Tag::Sequence as u8)?;
+            let mut contents = extension;
+            let mut contents = read_der(&mut contents, der::Tag::Sequence as u8)?;
+            if read_der(&mut contents, der::Tag::OID as u8)? != oid {
+                extensions.extend_from_slice(extension);
+            }
+        }
+        wrap_in_sequence(&mut extensions);
+        wrap_in_tag(&mut extensions, der::Tag::ContextSpecificConstructed3 as u8);
+
+        let mut result = fields[..fields.len() - rest.len()].to_vec();
+        result.extend_from_slice(&extensions);
+        wrap_in_sequence(&mut result);
+        Some(result)
+    }
+
+    /// Does this certificate carry `eku` in its extended key usage?
+    pub fn has_eku(&self, eku: &[u8]) -> bool {
+        let mut ekus = match self.extension(OID_EXTENDED_KEY_USAGE)
+            .and_then(|mut ext| read_der(&mut ext, der::Tag::Sequence as u8)) {
+            Some(ekus) => ekus,
+            None => return false,
+        };
+
+        while let Some(id) = read_der(&mut ekus, der::Tag::OID as u8) {
+            if id == eku {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Return this certificate's subjectAltNames, as the tag and
+    /// contents of each `GeneralName`.  We stop at the first one
+    /// that doesn't parse.
+    pub fn subject_alt_names(&self) -> Vec<(u8, &'a [u8])> {
+        let mut names = match self.extension(OID_SUBJECT_ALT_NAME)
+            .and_then(|mut ext| read_der(&mut ext, der::Tag::Sequence as u8)) {
+            Some(names) => names,
+            None => return Vec::new(),
+        };
+
+        let mut ret = Vec::new();
+        while let Some(&tag) = names.first() {
+            match read_der(&mut names, tag) {
+                Some(name) => ret.push((tag, name)),
+                None => break,
+            }
+        }
+
+        ret
+    }
+
+    /// Does this certificate have an iPAddress subjectAltName equal
+    /// to `ip`?  That's 4 bytes for IPv4, and 16 for IPv6.
+    pub fn has_ip_address(&self, ip: &[u8]) -> bool {
+        self.subject_alt_names()
+            .iter()
+            .any(|&(tag, name)| tag == TAG_IP_ADDRESS && name == ip)
+    }
+
+    /// Return the contents of the first commonName in the subject,
+    /// whatever string type it is.
+    pub fn common_name(&self) -> Option<&'a [u8]> {
+        let mut subject = self.subject;
+        let mut rdns = read_der(&mut subject, der::Tag::Sequence as u8)?;
+
+        while let Some(mut rdn) = read_der(&mut rdns, TAG_SET) {
+            while let Some(mut attr) = read_der(&mut rdn, der::Tag::Sequence as u8) {
+                let id = read_der(&mut attr, der::Tag::OID as u8)?;
+                let &tag = attr.first()?;
+                let value = read_der(&mut attr, tag)?;
+                if id == OID_COMMON_NAME {
+                    return Some(value);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Return the contents of the `extnValue` of the extension with
+/// `oid` in `extensions`, the contents of an `Extensions` SEQUENCE.
+pub fn find_extension<'a>(mut extensions: &'a [u8], oid: &[u8]) -> Option<&'a [u8]> {
+    while let Some(mut extension) = read_der(&mut extensions, der::Tag::Sequence as u8) {
+        let id = read_der(&mut extension, der::Tag::OID as u8)?;
+        read_der(&mut extension, der::Tag::Boolean as u8);
+        let value = read_der(&mut extension, der::Tag::OctetString as u8)?;
+        if id == oid {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+pub const OID_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const TAG_SET: u8 = 0x31;
+
+/// The `GeneralName` CHOICE tags we understand.
+pub const TAG_RFC822_NAME: u8 = 0x81;
+pub const TAG_DNS_NAME: u8 = 0x82;
+pub const TAG_URI: u8 = 0x86;
+pub const TAG_IP_ADDRESS: u8 = 0x87;
+
+/// Return the contents of the `subjectPublicKey` BIT STRING in
+/// the DER-encoded `spki`.
+pub fn spki_public_key(spki: &[u8]) -> Option<&[u8]> {
+    let mut input = spki;
+    let mut spki = read_der(&mut input, der::Tag::Sequence as u8)?;
+    read_der(&mut spki, der::Tag::Sequence as u8)?;
+    read_bit_string(&mut spki)
+}
+
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_SECP256R1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_SECP384R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+
+const OID_SHA256_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+const OID_SHA384_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+const OID_SHA512_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+const OID_ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_ECDSA_WITH_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+
+/// The kinds of public key we can verify signatures with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PublicKeyType {
+    RSA,
+    ECDSAP256,
+    ECDSAP384,
+    Ed25519,
+}
+
+/// Return the type of the key in the DER-encoded `spki`, and the
+/// contents of its `subjectPublicKey` BIT STRING.  Keys of other
+/// types give `None`.
+pub fn parse_spki(spki: &[u8]) -> Option<(PublicKeyType, &[u8])> {
+    let mut input = spki;
+    let mut spki = read_der(&mut input, der::Tag::Sequence as u8)?;
+    let mut key_alg = read_der(&mut spki, der::Tag::Sequence as u8)?;
+    let key_type = read_der(&mut key_alg, der::Tag::OID as u8)?;
+    let curve = read_der(&mut key_alg, der::Tag::OID as u8);
+    let public_key = read_bit_string(&mut spki)?;
+
+    let key_type = match (key_type, curve) {
+        (OID_RSA_ENCRYPTION, _) => PublicKeyType::RSA,
+        (OID_EC_PUBLIC_KEY, Some(OID_SECP256R1)) => PublicKeyType::ECDSAP256,
+        (OID_EC_PUBLIC_KEY, Some(OID_SECP384R1)) => PublicKeyType::ECDSAP384,
+        (OID_ED25519, _) => PublicKeyType::Ed25519,
+        _ => return None,
+    };
+
+    Some((key_type, public_key))
+}
+
+/// Verify `signature` over `message`, made with the algorithm
+/// identified by `signature_alg` (the contents of an
+/// AlgorithmIdentifier) by the key in the DER-encoded `spki`.
+///
+/// This is for signatures in PKI structures that webpki doesn't
+/// deal with, like OCSP responses and CRLs.  It supports the same
+/// algorithms webpki does, other than RSA-PSS.
+pub fn verify_signed_data(spki: &[u8],
+                          signature_alg: &[u8],
+                          message: &[u8],
+                          signature: &[u8]) -> Result<(), ()> {
+    use ring::signature;
+
+    let (key_type, public_key) = parse_spki(spki).ok_or(())?;
+
+    let mut signature_alg = signature_alg;
+    let signature_alg = read_der(&mut signature_alg, der::Tag::OID as u8).ok_or(())?;
+
+    let alg: &'static dyn signature::VerificationAlgorithm = match (signature_alg, key_type) {
+        (OID_SHA256_WITH_RSA, PublicKeyType::RSA) => &signature::RSA_PKCS1_2048_8192_SHA256,
+        (OID_SHA384_WITH_RSA, PublicKeyType::RSA) => &signature::RSA_PKCS1_2048_8192_SHA384,
+        (OID_SHA512_WITH_RSA, PublicKeyType::RSA) => &signature::RSA_PKCS1_2048_8192_SHA512,
+        (OID_ECDSA_WITH_SHA256, PublicKeyType::ECDSAP256) => &signature::ECDSA_P256_SHA256_ASN1,
+        (OID_ECDSA_WITH_SHA256, PublicKeyType::ECDSAP384) => &signature::ECDSA_P384_SHA256_ASN1,
+        (OID_ECDSA_WITH_SHA384, PublicKeyType::ECDSAP256) => &signature::ECDSA_P256_SHA384_ASN1,
+        (OID_ECDSA_WITH_SHA384, PublicKeyType::ECDSAP384) => &signature::ECDSA_P384_SHA384_ASN1,
+        (OID_ED25519, PublicKeyType::Ed25519) => &signature::ED25519,
+        _ => return Err(()),
+    };
+
+    signature::UnparsedPublicKey::new(alg, public_key)
+        .verify(message, signature)
+        .map_err(|_| ())
 }
 
 #[test]
@@ -52,6 +481,126 @@ fn test_medium() {
                val[..6].to_vec());
 }
 
+#[test]
+fn test_read_der() {
+    let mut val = vec![0x12; 300];
+    wrap_in_tag(&mut val, 0x04);
+    val.extend_from_slice(&[0x30, 0x01, 0x99, 0x02]);
+
+    let mut input = &val[..];
+    assert_eq!(read_der(&mut input, 0x30), None);
+    assert_eq!(read_der(&mut input, 0x04), Some(&[0x12; 300][..]));
+    assert_eq!(read_der(&mut input, 0x30), Some(&[0x99][..]));
+    assert_eq!(read_der(&mut input, 0x02), None);
+    assert_eq!(input, &[0x02]);
+}
+
+#[test]
+fn test_read_time() {
+    let mut utc = &b"\x17\x0d491231235959Z\x17\x0d500101000000Z"[..];
+    assert_eq!(read_time(&mut utc), Some(2_524_607_999));
+    assert_eq!(read_time(&mut utc), None);
+
+    let mut generalized = &b"\x18\x0f20200601000000Z\x18\x1220200601000000.25Z"[..];
+    assert_eq!(read_time(&mut generalized), Some(1_590_969_600));
+    assert_eq!(read_time(&mut generalized), Some(1_590_969_600));
+
+    let mut bad = &b"\x18\x0f20201301000000Z"[..];
+    assert_eq!(read_time(&mut bad), None);
+    let mut local = &b"\x18\x0e20200601000000"[..];
+    assert_eq!(read_time(&mut local), None);
+}
+
+#[test]
+fn test_read_optional_time() {
+    let mut input = &b"\x17\x0d491231235959Z\x30\x00"[..];
+    assert_eq!(read_optional_time(&mut input), Some(Some(2_524_607_999)));
+    assert_eq!(read_optional_time(&mut input), Some(None));
+    assert_eq!(input, b"\x30\x00");
+
+    let mut bad = &b"\x17\x0d491231235959X"[..];
+    assert_eq!(read_optional_time(&mut bad), None);
+    let mut empty = &b""[..];
+    assert_eq!(read_optional_time(&mut empty), Some(None));
+}
+
+#[test]
+fn test_cert_info() {
+    let ca = include_bytes!("testdata/ocsp-ca.der");
+    let end = include_bytes!("testdata/ocsp-end.der");
+    let ca = CertInfo::parse(ca).unwrap();
+    let end = CertInfo::parse(end).unwrap();
+
+    assert_eq!(end.serial, &[0x12, 0x34]);
+    assert_eq!(end.issuer, ca.subject);
+    assert_eq!(end.not_before, 1_577_836_800);
+    assert_eq!(end.not_after, 2_208_988_800);
+    assert_eq!(end.extension(&[0x55, 0x1d, 0x13]), Some(&[0x30, 0x00][..]));
+    assert_eq!(end.extension(OID_EXTENDED_KEY_USAGE), None);
+    assert!(!end.has_eku(&[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09]));
+    assert_eq!(spki_public_key(end.spki).map(|key| key.len()), Some(65));
+    assert_eq!(parse_spki(end.spki).map(|(typ, key)| (typ, key.len())),
+               Some((PublicKeyType::ECDSAP256, 65)));
+    assert_eq!(parse_spki(&[0x30, 0x00]), None);
+
+    assert_eq!(verify_signed_data(ca.spki, end.signature_alg, end.tbs, end.signature),
+               Ok(()));
+    assert_eq!(verify_signed_data(end.spki, end.signature_alg, end.tbs, end.signature),
+               Err(()));
+}
+
+#[test]
+fn test_has_ip_address() {
+    let cert = CertInfo::parse(include_bytes!("testdata/cert-ip-san.der")).unwrap();
+    assert!(cert.has_ip_address(&[198, 51, 100, 1]));
+    assert!(cert.has_ip_address(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0,
+                                  0, 0, 0, 0, 0, 0, 0, 0x01]));
+    assert!(!cert.has_ip_address(&[198, 51, 100, 2]));
+    assert!(!cert.has_ip_address(b"example.com"));
+
+    let end = CertInfo::parse(include_bytes!("testdata/ocsp-end.der")).unwrap();
+    assert!(!end.has_ip_address(&[198, 51, 100, 1]));
+}
+
+#[test]
+fn test_names() {
+    let cert = CertInfo::parse(include_bytes!("testdata/cert-ip-san.der")).unwrap();
+    assert_eq!(cert.common_name(), Some(&b"198.51.100.1"[..]));
+    assert_eq!(cert.subject_alt_names(),
+               vec![ (TAG_DNS_NAME, &b"example.com"[..]),
+                     (TAG_IP_ADDRESS, &[198, 51, 100, 1][..]),
+                     (TAG_IP_ADDRESS, &[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0,
+                                        0, 0, 0, 0, 0, 0, 0, 0x01][..]),
+                     (TAG_RFC822_NAME, &b"a@example.com"[..]) ]);
+
+    // The commonName comes last here, after lots of other attributes.
+    let github = CertInfo::parse(include_bytes!("testdata/cert-github.0.der")).unwrap();
+    assert_eq!(github.common_name(), Some(&b"github.com"[..]));
+    assert_eq!(github.subject_alt_names().len(), 2);
+}
+
+#[test]
+fn test_tbs_without_extension() {
+    let github = CertInfo::parse(include_bytes!("testdata/cert-github.0.der")).unwrap();
+    assert_eq!(github.tbs_without_extension(&[0x55, 0x1d, 0xff]).unwrap(), github.tbs);
+
+    let tbs = github.tbs_without_extension(OID_EXTENDED_KEY_USAGE).unwrap();
+    assert!(tbs.len() < github.tbs.len());
+
+    let mut input = &tbs[..];
+    let mut fields = read_der(&mut input, der::Tag::Sequence as u8).unwrap();
+    assert!(input.is_empty());
+    while fields[0] != der::Tag::ContextSpecificConstructed3 as u8 {
+        let tag = fields[0];
+        read_der(&mut fields, tag).unwrap();
+    }
+    let mut extensions = read_der(&mut fields, der::Tag::ContextSpecificConstructed3 as u8).unwrap();
+    let extensions = read_der(&mut extensions, der::Tag::Sequence as u8).unwrap();
+    assert_eq!(find_extension(extensions, OID_EXTENDED_KEY_USAGE), None);
+    assert_eq!(find_extension(extensions, OID_SUBJECT_ALT_NAME),
+               github.extension(OID_SUBJECT_ALT_NAME));
+}
+
 #[test]
 fn test_large() {
     let mut val = Vec::new();
@@ -5,25 +5,51 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::mem;
 use std::fmt;
 use std::env;
+use std::fs;
 use std::error::Error;
 use std::io::{self, Write, Read};
 
 use rustls;
 
-use rustls::{ClientConfig, ClientSession, ResolvesClientCert};
+use rustls::{ClientConfig, ClientSession, ResolvesClientCert, ClientSessionFileCache};
 use rustls::{ServerConfig, ServerSession, ResolvesServerCert};
 use rustls::Session;
 use rustls::{Stream, StreamOwned};
 use rustls::{ProtocolVersion, SignatureScheme, CipherSuite};
 use rustls::TLSError;
+use rustls::{OCSPPolicy, OCSPStaplingResolver};
+use rustls::{CRLStore, RevocationPolicy, UnknownStatusPolicy};
+use rustls::{RootCertStore, AllowAnyAuthenticatedClient, RevocationCheckingClientVerifier};
+use rustls::{PinningVerifier, PinSet};
+use rustls::TrustedRawPublicKeys;
+use rustls::{TimeProvider, SystemTimeProvider};
+use rustls::CTPolicy;
+use rustls::{ClientIdentityPolicy, PolicyCheckingClientVerifier, PeerIdentity};
 use rustls::sign;
-use rustls::{ALL_CIPHERSUITES, SupportedCipherSuite};
+use rustls::{ALL_CIPHERSUITES, BulkAlgorithm, SupportedCipherSuite};
+use rustls::Certificate;
+use rustls::crypto::{self, CryptoProvider, GetRandomFailed, HashAlgorithm, Hash, Hmac, Aead};
+use rustls::{SupportedKxGroup, ActiveKeyExchange, KeyExchangeResult, FfdheParams, kx_group};
+#[cfg(feature = "ffdhe")]
+use rustls::FFDHE_CIPHERSUITES;
+#[cfg(feature = "aes_ccm")]
+use rustls::TLS13_CCM_CIPHERSUITES;
+use rustls::internal::msgs::enums::{NamedGroup, ContentType, SignatureAlgorithm};
+use rustls::internal::msgs::message::{Message, MessagePayload, BorrowMessage};
+use rustls::internal::msgs::codec::{self, Codec};
+use rustls::internal::msgs::handshake::KeyExchangeAlgorithm;
+use rustls::{MessageEncrypter, MessageDecrypter, MessageCipherFactory};
+use rustls::crypto::AeadKey;
 use rustls::KeyLog;
 use rustls::ClientHello;
+use rustls::ech;
+#[cfg(feature = "esni")]
+use rustls::esni;
 #[cfg(feature = "quic")]
 use rustls::quic::{self, QuicExt, ClientQuicExt, ServerQuicExt};
 #[cfg(feature = "quic")]
 use ring::hkdf;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
 
 use webpki;
 
@@ -360,7 +386,7 @@ fn server_cert_resolve_with_sni() {
             ..Default::default()
         });
 
-        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("the-value-from-sni"));
+        let mut client = ClientSession::new(&Arc::new(client_config), server_name("the-value-from-sni"));
         let mut server = ServerSession::new(&Arc::new(server_config));
 
         let err = do_handshake_until_error(&mut client, &mut server);
@@ -380,7 +406,7 @@ fn server_cert_resolve_with_alpn() {
             ..Default::default()
         });
 
-        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("sni-value"));
+        let mut client = ClientSession::new(&Arc::new(client_config), server_name("sni-value"));
         let mut server = ServerSession::new(&Arc::new(server_config));
 
         let err = do_handshake_until_error(&mut client, &mut server);
@@ -401,7 +427,7 @@ fn check_sigalgs_reduced_by_ciphersuite(kt: KeyType, suite: CipherSuite,
         ..Default::default()
     });
 
-    let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+    let mut client = ClientSession::new(&Arc::new(client_config), server_name("localhost"));
     let mut server = ServerSession::new(&Arc::new(server_config));
 
     let err = do_handshake_until_error(&mut client, &mut server);
@@ -432,10 +458,45 @@ fn server_cert_resolve_reduces_sigalgs_for_ecdsa_ciphersuite() {
         vec![
             SignatureScheme::ECDSA_NISTP384_SHA384,
             SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ED25519,
         ]
     );
 }
 
+#[test]
+fn ed25519_uses_ecdsa_ciphersuites_in_tls12() {
+    let mut client_config = make_client_config_with_auth(KeyType::ED25519);
+    client_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+    let server_config = make_server_config_with_mandatory_client_auth(KeyType::ED25519);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.get_negotiated_ciphersuite().unwrap().suite,
+               CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256);
+    assert!(server.get_peer_certificates().is_some());
+}
+
+#[test]
+fn ed25519_keys_are_loaded() {
+    use rustls::sign::SigningKey;
+
+    let key = KeyType::ED25519.get_key();
+    let signing_key = sign::Ed25519SigningKey::new(&key).unwrap();
+    assert_eq!(signing_key.algorithm(), SignatureAlgorithm::ED25519);
+    assert!(signing_key.choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256]).is_none());
+
+    let signer = signing_key.choose_scheme(&[SignatureScheme::ED25519]).unwrap();
+    assert_eq!(signer.get_scheme(), SignatureScheme::ED25519);
+    assert_eq!(signer.sign(b"hello").unwrap().len(), 64);
+
+    assert_eq!(sign::any_supported_type(&key).unwrap().algorithm(),
+               SignatureAlgorithm::ED25519);
+    assert_eq!(sign::any_eddsa_type(&key).unwrap().algorithm(),
+               SignatureAlgorithm::ED25519);
+    assert!(sign::any_ecdsa_type(&key).is_err());
+    assert!(sign::any_eddsa_type(&KeyType::ECDSA.get_key()).is_err());
+}
+
 struct ServerCheckNoSNI {}
 
 impl ResolvesServerCert for ServerCheckNoSNI {
@@ -457,7 +518,7 @@ fn client_with_sni_disabled_does_not_send_sni() {
         let server_config = Arc::new(server_config);
 
         for client_config in AllClientVersions::new(client_config) {
-            let mut client = ClientSession::new(&Arc::new(client_config), dns_name("value-not-sent"));
+            let mut client = ClientSession::new(&Arc::new(client_config), server_name("value-not-sent"));
             let mut server = ServerSession::new(&server_config);
 
             let err = do_handshake_until_error(&mut client, &mut server);
@@ -466,6 +527,49 @@ fn client_with_sni_disabled_does_not_send_sni() {
     }
 }
 
+#[test]
+fn client_does_not_send_sni_for_ip_address() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_client_config(*kt);
+
+        let mut server_config = make_server_config(*kt);
+        server_config.cert_resolver = Arc::new(ServerCheckNoSNI {});
+        let server_config = Arc::new(server_config);
+
+        for client_config in AllClientVersions::new(client_config) {
+            for name in &["198.51.100.1", "2001:db8::1"] {
+                let mut client = ClientSession::new(&Arc::new(client_config.clone()),
+                                                    server_name(name));
+                let mut server = ServerSession::new(&server_config);
+
+                let err = do_handshake_until_error(&mut client, &mut server);
+                assert_eq!(err.is_err(), true);
+            }
+        }
+    }
+}
+
+#[test]
+fn client_checks_server_certificate_with_given_ip_address() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_client_config(*kt);
+        let server_config = Arc::new(make_server_config(*kt));
+
+        // The test certificates only have DNS names; in particular
+        // "localhost" doesn't cover 127.0.0.1.
+        for client_config in AllClientVersions::new(client_config) {
+            let mut client = ClientSession::new(&Arc::new(client_config),
+                                                server_name("127.0.0.1"));
+            let mut server = ServerSession::new(&server_config);
+
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(err,
+                       Err(TLSErrorFromPeer::Client(
+                               TLSError::WebPKIError(webpki::Error::CertNotValidForName))));
+        }
+    }
+}
+
 #[test]
 fn client_checks_server_certificate_with_given_name() {
     for kt in ALL_KEY_TYPES.iter() {
@@ -474,7 +578,7 @@ fn client_checks_server_certificate_with_given_name() {
 
         for client_config in AllClientVersions::new(client_config) {
             let mut client = ClientSession::new(&Arc::new(client_config),
-                                                dns_name("not-the-right-hostname.com"));
+                                                server_name("not-the-right-hostname.com"));
             let mut server = ServerSession::new(&server_config);
 
             let err = do_handshake_until_error(&mut client, &mut server);
@@ -487,6 +591,389 @@ fn client_checks_server_certificate_with_given_name() {
     }
 }
 
+#[test]
+fn client_can_require_stapled_ocsp_response() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut client_config = make_client_config(*kt);
+        client_config.set_ocsp_policy(OCSPPolicy::Require);
+        let server_config = Arc::new(make_server_config(*kt));
+
+        for client_config in AllClientVersions::new(client_config) {
+            let mut client = ClientSession::new(&Arc::new(client_config), server_name("localhost"));
+            let mut server = ServerSession::new(&server_config);
+
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(err,
+                       Err(TLSErrorFromPeer::Client(
+                               TLSError::InvalidOCSPResponse("no response stapled".to_string()))));
+
+            transfer(&mut client, &mut server);
+            assert_eq!(server.process_new_packets(),
+                       Err(TLSError::AlertReceived(
+                               rustls::internal::msgs::enums::AlertDescription::BadCertificateStatusResponse)));
+        }
+    }
+}
+
+fn pinned_client_config(kt: KeyType, dns_name: &str, pins: PinSet) -> Arc<ClientConfig> {
+    let mut verifier = PinningVerifier::new();
+    verifier.add_pins(dns_name, pins).unwrap();
+    let mut client_config = make_client_config(kt);
+    client_config.set_pinning_verifier(verifier);
+    Arc::new(client_config)
+}
+
+#[test]
+fn client_accepts_pinned_keys() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let chain = kt.get_chain();
+        let server_config = Arc::new(make_server_config(*kt));
+
+        // Any key in the chain will do, including the root's.
+        for cert in &chain {
+            let mut pins = PinSet::new();
+            pins.add_pin(PinSet::spki_hash(cert).unwrap());
+            let client_config = pinned_client_config(*kt, "localhost", pins);
+
+            let mut client = ClientSession::new(&client_config, server_name("localhost"));
+            let mut server = ServerSession::new(&server_config);
+            do_handshake(&mut client, &mut server);
+        }
+
+        let mut pins = PinSet::new();
+        pins.add_pin([0u8; 32])
+            .add_backup_pin(PinSet::spki_hash(&chain[0]).unwrap());
+        let client_config = pinned_client_config(*kt, "LOCALHOST", pins);
+
+        let mut client = ClientSession::new(&client_config, server_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn client_rejects_unpinned_keys() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut pins = PinSet::new();
+        pins.add_pin([0u8; 32]);
+        let client_config = pinned_client_config(*kt, "localhost", pins);
+        let server_config = Arc::new(make_server_config(*kt));
+
+        let mut client = ClientSession::new(&client_config, server_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        let err = do_handshake_until_error(&mut client, &mut server);
+        assert_eq!(err,
+                   Err(TLSErrorFromPeer::Client(TLSError::CertificatePinMismatch)));
+
+        // Pins for other names don't matter.
+        let mut pins = PinSet::new();
+        pins.add_pin([0u8; 32]);
+        let client_config = pinned_client_config(*kt, "example.com", pins);
+
+        let mut client = ClientSession::new(&client_config, server_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn pinning_verifier_rejects_bad_pins() {
+    let mut verifier = PinningVerifier::new();
+    assert!(verifier.add_pins("localhost", PinSet::new()).is_err());
+
+    let mut pins = PinSet::new();
+    pins.add_pin([0u8; 32]);
+    assert!(verifier.add_pins("not a name", pins).is_err());
+}
+
+fn trusted_raw_public_keys(chain: &[Certificate]) -> Arc<TrustedRawPublicKeys> {
+    let mut keys = TrustedRawPublicKeys::new();
+    keys.add(TrustedRawPublicKeys::spki(&chain[0]).unwrap()).unwrap();
+    Arc::new(keys)
+}
+
+fn make_raw_public_key_configs(kt: KeyType) -> (ClientConfig, ServerConfig) {
+    let server_spki = TrustedRawPublicKeys::spki(&kt.get_chain()[0]).unwrap();
+    let mut server_config = ServerConfig::new(rustls::NoClientAuth::new());
+    server_config.set_single_raw_public_key(server_spki, kt.get_key()).unwrap();
+    server_config.set_client_raw_public_key_verifier(trusted_raw_public_keys(&kt.get_client_chain()));
+
+    let client_spki = TrustedRawPublicKeys::spki(&kt.get_client_chain()[0]).unwrap();
+    let mut client_config = ClientConfig::new();
+    client_config.set_server_raw_public_key_verifier(trusted_raw_public_keys(&kt.get_chain()));
+    client_config.set_single_client_raw_public_key(client_spki, kt.get_client_key());
+
+    (client_config, server_config)
+}
+
+#[test]
+fn raw_public_keys_work() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (client_config, server_config) = make_raw_public_key_configs(*kt);
+        let server_config = Arc::new(server_config);
+        let server_spki = TrustedRawPublicKeys::spki(&kt.get_chain()[0]).unwrap();
+        let client_spki = TrustedRawPublicKeys::spki(&kt.get_client_chain()[0]).unwrap();
+
+        for client_config in AllClientVersions::new(client_config) {
+            let mut client = ClientSession::new(&Arc::new(client_config), server_name("localhost"));
+            let mut server = ServerSession::new(&server_config);
+            do_handshake(&mut client, &mut server);
+
+            assert_eq!(client.get_peer_certificates(), Some(vec![ Certificate(server_spki.clone()) ]));
+            assert_eq!(server.get_peer_certificates(), Some(vec![ Certificate(client_spki.clone()) ]));
+        }
+    }
+}
+
+#[test]
+fn client_rejects_untrusted_raw_public_key() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client_config, server_config) = make_raw_public_key_configs(*kt);
+        client_config.set_server_raw_public_key_verifier(trusted_raw_public_keys(&kt.get_client_chain()));
+        let server_config = Arc::new(server_config);
+
+        for client_config in AllClientVersions::new(client_config) {
+            let mut client = ClientSession::new(&Arc::new(client_config), server_name("localhost"));
+            let mut server = ServerSession::new(&server_config);
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(err,
+                       Err(TLSErrorFromPeer::Client(TLSError::UnknownRawPublicKey)));
+        }
+    }
+}
+
+#[test]
+fn server_rejects_untrusted_raw_public_key() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (client_config, mut server_config) = make_raw_public_key_configs(*kt);
+        server_config.set_client_raw_public_key_verifier(trusted_raw_public_keys(&kt.get_chain()));
+        let server_config = Arc::new(server_config);
+
+        for client_config in AllClientVersions::new(client_config) {
+            let mut client = ClientSession::new(&Arc::new(client_config), server_name("localhost"));
+            let mut server = ServerSession::new(&server_config);
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(err,
+                       Err(TLSErrorFromPeer::Server(TLSError::UnknownRawPublicKey)));
+        }
+    }
+}
+
+#[test]
+fn raw_public_keys_are_not_forced_on_peers() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (_, server_config) = make_raw_public_key_configs(*kt);
+
+        // A server with a raw public key can't serve ordinary clients.
+        let (mut client, mut server) = make_pair_for_configs(make_client_config(*kt),
+                                                             server_config);
+        let err = do_handshake_until_error(&mut client, &mut server);
+        assert_eq!(err,
+                   Err(TLSErrorFromPeer::Server(TLSError::PeerIncompatibleError(
+                               "client does not support our certificate type".to_string()))));
+
+        // And one which wants raw public keys from clients needs them
+        // to have one.
+        let mut server_config = make_server_config(*kt);
+        server_config.set_client_raw_public_key_verifier(trusted_raw_public_keys(&kt.get_client_chain()));
+        let (mut client, mut server) = make_pair_for_configs(make_client_config(*kt),
+                                                             server_config);
+        let err = do_handshake_until_error(&mut client, &mut server);
+        assert_eq!(err,
+                   Err(TLSErrorFromPeer::Server(TLSError::PeerIncompatibleError(
+                               "client does not support raw public keys".to_string()))));
+
+        // A client offering a raw public key still works with
+        // servers that don't ask for one.
+        let mut client_config = make_client_config(*kt);
+        client_config.client_auth_raw_public_key = true;
+        let (mut client, mut server) = make_pair_for_configs(client_config,
+                                                             make_server_config(*kt));
+        do_handshake(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn server_only_staples_usable_ocsp_responses() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut server_config = make_server_config(*kt);
+        server_config.set_single_cert_with_ocsp_and_sct(kt.get_chain(),
+                                                        kt.get_key(),
+                                                        b"bogus".to_vec(),
+                                                        vec![])
+            .unwrap();
+        let resolver = OCSPStaplingResolver::new(server_config.cert_resolver.clone());
+        assert!(resolver.set_ocsp_response(&kt.get_chain()[0], b"bogus".to_vec()).is_err());
+        server_config.cert_resolver = Arc::new(resolver);
+        let server_config = Arc::new(server_config);
+
+        let mut client_config = make_client_config(*kt);
+        client_config.set_ocsp_policy(OCSPPolicy::Require);
+
+        for client_config in AllClientVersions::new(client_config) {
+            let mut client = ClientSession::new(&Arc::new(client_config), server_name("localhost"));
+            let mut server = ServerSession::new(&server_config);
+
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(err,
+                       Err(TLSErrorFromPeer::Client(
+                               TLSError::InvalidOCSPResponse("no response stapled".to_string()))));
+        }
+    }
+}
+
+#[test]
+fn client_soft_fails_on_bogus_ocsp_response() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut server_config = make_server_config(*kt);
+        server_config.set_single_cert_with_ocsp_and_sct(kt.get_chain(),
+                                                        kt.get_key(),
+                                                        b"bogus".to_vec(),
+                                                        vec![])
+            .unwrap();
+        let server_config = Arc::new(server_config);
+
+        for client_config in AllClientVersions::new(make_client_config(*kt)) {
+            let mut client = ClientSession::new(&Arc::new(client_config), server_name("localhost"));
+            let mut server = ServerSession::new(&server_config);
+            do_handshake(&mut client, &mut server);
+        }
+
+        let mut client_config = make_client_config(*kt);
+        client_config.set_ocsp_policy(OCSPPolicy::Require);
+        let mut client = ClientSession::new(&Arc::new(client_config), server_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        assert_eq!(do_handshake_until_error(&mut client, &mut server),
+                   Err(TLSErrorFromPeer::Client(
+                           TLSError::InvalidOCSPResponse("malformed response".to_string()))));
+    }
+}
+
+#[test]
+fn client_checks_server_certificate_revocation() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config(*kt));
+
+        let mut client_config = make_client_config(*kt);
+        client_config.set_crls(CRLStore::empty(), RevocationPolicy::new());
+        let mut client = ClientSession::new(&Arc::new(client_config), server_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        assert_eq!(do_handshake_until_error(&mut client, &mut server),
+                   Err(TLSErrorFromPeer::Client(TLSError::UnknownRevocationStatus)));
+
+        let mut policy = RevocationPolicy::new();
+        policy.unknown_status = UnknownStatusPolicy::Allow;
+        let mut client_config = make_client_config(*kt);
+        client_config.set_crls(CRLStore::empty(), policy);
+        let mut client = ClientSession::new(&Arc::new(client_config), server_name("localhost"));
+        let mut server = ServerSession::new(&server_config);
+        do_handshake(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn server_checks_client_certificate_revocation() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = Arc::new(make_client_config_with_auth(*kt));
+
+        for unknown_status in &[UnknownStatusPolicy::Deny, UnknownStatusPolicy::Allow] {
+            let mut roots = RootCertStore::empty();
+            for root in kt.get_chain() {
+                roots.add(&root).unwrap();
+            }
+
+            let mut policy = RevocationPolicy::new();
+            policy.unknown_status = *unknown_status;
+            let verifier = RevocationCheckingClientVerifier::new(
+                AllowAnyAuthenticatedClient::new(roots.clone()),
+                roots,
+                CRLStore::empty(),
+                policy);
+
+            let mut server_config = make_server_config(*kt);
+            server_config.set_client_certificate_verifier(verifier);
+
+            let mut client = ClientSession::new(&client_config, server_name("localhost"));
+            let mut server = ServerSession::new(&Arc::new(server_config));
+            let result = do_handshake_until_error(&mut client, &mut server);
+
+            if *unknown_status == UnknownStatusPolicy::Deny {
+                assert_eq!(result,
+                           Err(TLSErrorFromPeer::Server(TLSError::UnknownRevocationStatus)));
+            } else {
+                assert_eq!(result, Ok(()));
+            }
+        }
+    }
+}
+
+fn policy_checking_server_config(kt: KeyType) -> ServerConfig {
+    let mut roots = RootCertStore::empty();
+    for root in kt.get_chain() {
+        roots.add(&root).unwrap();
+    }
+
+    let mut policy = ClientIdentityPolicy::new();
+    policy.require_client_auth_eku()
+        .allow_name("ponytown client");
+    let mut verifier = PolicyCheckingClientVerifier::new(
+        AllowAnyAuthenticatedClient::new(roots),
+        policy);
+
+    let mut localhost_policy = ClientIdentityPolicy::new();
+    localhost_policy.allow_name("someone else");
+    verifier.add_sni_policy("LOCALHOST", localhost_policy).unwrap();
+    assert!(verifier.add_sni_policy("not a name", ClientIdentityPolicy::new()).is_err());
+
+    let mut server_config = make_server_config(kt);
+    server_config.set_client_certificate_verifier(Arc::new(verifier));
+    server_config
+}
+
+#[test]
+fn server_checks_client_identity_policy() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(policy_checking_server_config(*kt));
+
+        for client_config in AllClientVersions::new(make_client_config_with_auth(*kt)) {
+            let client_config = Arc::new(client_config);
+
+            let mut client = ClientSession::new(&client_config, server_name("testserver.com"));
+            let mut server = ServerSession::new(&server_config);
+            do_handshake(&mut client, &mut server);
+
+            let identity = server.get_peer_identity().unwrap();
+            let client_cert = &kt.get_client_chain()[0];
+            assert_eq!(identity, PeerIdentity::from_certificate(client_cert).unwrap());
+            assert_eq!(identity.common_name, Some("ponytown client".to_string()));
+            assert_eq!(identity.spki_hash, PinSet::spki_hash(client_cert).unwrap());
+
+            // Clients of localhost have their own policy.
+            let mut client = ClientSession::new(&client_config, server_name("localhost"));
+            let mut server = ServerSession::new(&server_config);
+            assert_eq!(do_handshake_until_error(&mut client, &mut server),
+                       Err(TLSErrorFromPeer::Server(TLSError::ClientIdentityNotAllowed)));
+        }
+
+        // The server's certificate works for client auth, but has no
+        // clientAuth extended key usage.
+        let mut client_config = make_client_config(*kt);
+        client_config.set_single_client_cert(kt.get_chain(), kt.get_key());
+        let mut client = ClientSession::new(&Arc::new(client_config), server_name("testserver.com"));
+        let mut server = ServerSession::new(&server_config);
+        assert_eq!(do_handshake_until_error(&mut client, &mut server),
+                   Err(TLSErrorFromPeer::Server(
+                           TLSError::WebPKIError(webpki::Error::RequiredEKUNotFound))));
+    }
+}
+
+#[test]
+fn server_has_no_peer_identity_without_client_auth() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.get_peer_identity(), None);
+}
+
 struct ClientCheckCertResolve {
     query_count: AtomicUsize,
     expect_queries: usize
@@ -564,6 +1051,178 @@ fn client_auth_works() {
     }
 }
 
+#[test]
+fn configs_accept_signing_keys() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut client_config = make_client_config(*kt);
+        client_config.set_single_client_cert_with_signing_key(
+            kt.get_client_chain(),
+            sign::any_supported_type(&kt.get_client_key()).unwrap());
+
+        let mut server_config = make_server_config_with_mandatory_client_auth(*kt);
+        server_config.set_single_cert_with_signing_key(
+            kt.get_chain(),
+            sign::any_supported_type(&kt.get_key()).unwrap());
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert!(server.get_peer_certificates().is_some());
+    }
+}
+
+/// Signs with the wrapped key, but only when the test supplies the
+/// signature with `complete_signature`.
+struct DeferredSigningKey(Box<dyn sign::SigningKey>);
+
+struct DeferredSigner(Box<dyn sign::Signer>);
+
+impl sign::SigningKey for DeferredSigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
+        self.0.choose_scheme(offered)
+            .map(|signer| Box::new(DeferredSigner(signer)) as Box<dyn sign::Signer>)
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        self.0.algorithm()
+    }
+}
+
+impl sign::Signer for DeferredSigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, TLSError> {
+        self.0.sign(message)
+    }
+
+    fn get_scheme(&self) -> SignatureScheme {
+        self.0.get_scheme()
+    }
+
+    fn start_sign(&self, _message: &[u8]) -> Result<Option<Vec<u8>>, TLSError> {
+        Ok(None)
+    }
+}
+
+struct ResolvesDeferredCert(sign::CertifiedKey);
+
+impl ResolvesDeferredCert {
+    fn new(chain: Vec<Certificate>, key: &rustls::PrivateKey) -> ResolvesDeferredCert {
+        let key = DeferredSigningKey(sign::any_supported_type(key).unwrap());
+        ResolvesDeferredCert(sign::CertifiedKey::new(chain, Arc::new(Box::new(key))))
+    }
+}
+
+impl ResolvesServerCert for ResolvesDeferredCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<sign::CertifiedKey> {
+        Some(self.0.clone())
+    }
+}
+
+impl ResolvesClientCert for ResolvesDeferredCert {
+    fn resolve(&self,
+               _acceptable_issuers: &[&[u8]],
+               _sigschemes: &[SignatureScheme])
+        -> Option<sign::CertifiedKey> {
+        Some(self.0.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+fn complete_deferred_signature(sess: &mut dyn Session, key: &rustls::PrivateKey) {
+    let (message, scheme) = {
+        let pending = sess.get_pending_signature()
+            .expect("signature unexpectedly not pending");
+        (pending.message().to_vec(), pending.scheme())
+    };
+
+    let signer = sign::any_supported_type(key).unwrap()
+        .choose_scheme(&[scheme])
+        .unwrap();
+    sess.complete_signature(signer.sign(&message).unwrap()).unwrap();
+    assert!(sess.get_pending_signature().is_none());
+}
+
+#[test]
+fn server_signature_can_be_deferred() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut server_config = make_server_config(*kt);
+        server_config.cert_resolver = Arc::new(ResolvesDeferredCert::new(kt.get_chain(),
+                                                                         &kt.get_key()));
+        let server_config = Arc::new(server_config);
+
+        for client_config in AllClientVersions::new(make_client_config(*kt)) {
+            let (mut client, mut server) = make_pair_for_arc_configs(&Arc::new(client_config),
+                                                                     &server_config);
+            transfer(&mut client, &mut server);
+            server.process_new_packets().unwrap();
+            assert!(server.is_handshaking());
+
+            // nothing happens until the signature arrives.
+            server.process_new_packets().unwrap();
+            assert!(server.get_pending_signature().is_some());
+            complete_deferred_signature(&mut server, &kt.get_key());
+            do_handshake(&mut client, &mut server);
+        }
+    }
+}
+
+#[test]
+fn client_signature_can_be_deferred() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut client_config = make_client_config(*kt);
+        client_config.client_auth_cert_resolver =
+            Arc::new(ResolvesDeferredCert::new(kt.get_client_chain(), &kt.get_client_key()));
+        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(*kt));
+
+        for client_config in AllClientVersions::new(client_config) {
+            let (mut client, mut server) = make_pair_for_arc_configs(&Arc::new(client_config),
+                                                                     &server_config);
+            transfer(&mut client, &mut server);
+            server.process_new_packets().unwrap();
+            transfer(&mut server, &mut client);
+            client.process_new_packets().unwrap();
+
+            complete_deferred_signature(&mut client, &kt.get_client_key());
+            do_handshake(&mut client, &mut server);
+            assert!(server.get_peer_certificates().is_some());
+        }
+    }
+}
+
+#[test]
+fn bad_deferred_signature_is_rejected_by_peer() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut server_config = make_server_config(*kt);
+        server_config.cert_resolver = Arc::new(ResolvesDeferredCert::new(kt.get_chain(),
+                                                                         &kt.get_key()));
+        let server_config = Arc::new(server_config);
+
+        for client_config in AllClientVersions::new(make_client_config(*kt)) {
+            let (mut client, mut server) = make_pair_for_arc_configs(&Arc::new(client_config),
+                                                                     &server_config);
+            transfer(&mut client, &mut server);
+            server.process_new_packets().unwrap();
+            server.complete_signature(vec![0u8; 64]).unwrap();
+
+            transfer(&mut server, &mut client);
+            assert!(client.process_new_packets().is_err());
+        }
+    }
+}
+
+#[test]
+fn complete_signature_requires_pending_signature() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+    assert!(client.get_pending_signature().is_none());
+    assert_eq!(client.complete_signature(vec![]),
+               Err(TLSError::General("no signature is pending".to_string())));
+    assert!(server.get_pending_signature().is_none());
+    assert_eq!(server.complete_signature(vec![]),
+               Err(TLSError::General("no signature is pending".to_string())));
+    do_handshake(&mut client, &mut server);
+}
+
 #[test]
 fn client_error_is_sticky() {
     let (mut client, _) = make_pair(KeyType::RSA);
@@ -1074,106 +1733,471 @@ fn client_stream_handshake_error() {
 }
 
 #[test]
-fn client_streamowned_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (client, mut server) = make_pair_for_configs(client_config, server_config);
+fn client_streamowned_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    let pipe = OtherSession::new_fails(&mut server);
+    let mut client_stream = StreamOwned::new(client, pipe);
+    let rc = client_stream.write(b"hello");
+    assert!(rc.is_err());
+    assert_eq!(format!("{:?}", rc),
+               "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })");
+    let rc = client_stream.write(b"hello");
+    assert!(rc.is_err());
+    assert_eq!(format!("{:?}", rc),
+               "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })");
+}
+
+#[test]
+fn server_stream_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    client.write(b"world").unwrap();
+
+    {
+        let mut pipe = OtherSession::new_fails(&mut client);
+        let mut server_stream = Stream::new(&mut server, &mut pipe);
+        let mut bytes = [0u8; 5];
+        let rc = server_stream.read(&mut bytes);
+        assert!(rc.is_err());
+        assert_eq!(format!("{:?}", rc),
+                   "Err(Custom { kind: InvalidData, error: PeerIncompatibleError(\"no ciphersuites in common\") })");
+    }
+}
+
+#[test]
+fn server_streamowned_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, server) = make_pair_for_configs(client_config, server_config);
+
+    client.write(b"world").unwrap();
+
+    let pipe = OtherSession::new_fails(&mut client);
+    let mut server_stream = StreamOwned::new(server, pipe);
+    let mut bytes = [0u8; 5];
+    let rc = server_stream.read(&mut bytes);
+    assert!(rc.is_err());
+    assert_eq!(format!("{:?}", rc),
+               "Err(Custom { kind: InvalidData, error: PeerIncompatibleError(\"no ciphersuites in common\") })");
+}
+
+#[test]
+fn server_config_is_clone() {
+    let _ = make_server_config(KeyType::RSA).clone();
+}
+
+#[test]
+fn client_config_is_clone() {
+    let _ = make_client_config(KeyType::RSA).clone();
+}
+
+#[test]
+fn client_session_is_debug() {
+    let (client, _) = make_pair(KeyType::RSA);
+    println!("{:?}", client);
+}
+
+#[test]
+fn server_session_is_debug() {
+    let (_, server) = make_pair(KeyType::RSA);
+    println!("{:?}", server);
+}
+
+#[test]
+fn server_complete_io_for_handshake_ending_with_alert() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    assert_eq!(true, server.is_handshaking());
+
+    let mut pipe = OtherSession::new_fails(&mut client);
+    let rc = server.complete_io(&mut pipe);
+    assert!(rc.is_err(),
+            "server io failed due to handshake failure");
+    assert!(!server.wants_write(),
+            "but server did send its alert");
+    assert_eq!(format!("{:?}", pipe.last_error),
+               "Some(AlertReceived(HandshakeFailure))",
+               "which was received by client");
+}
+
+#[test]
+fn server_exposes_offered_sni() {
+    let kt = KeyType::RSA;
+    let mut client = ClientSession::new(&Arc::new(make_client_config(kt)),
+                                        server_name("second.testserver.com"));
+    let mut server = ServerSession::new(&Arc::new(make_server_config(kt)));
+
+    assert_eq!(None, server.get_sni_hostname());
+    do_handshake(&mut client, &mut server);
+    assert_eq!(Some("second.testserver.com"), server.get_sni_hostname());
+}
+
+// The ESNI tests below only run with the `esni` feature, which
+// isn't a default one: use `cargo test --all-features` (as CI does).
+
+/// Resolves certificates with `inner`, remembering the SNI
+/// each ClientHello had.
+#[cfg(feature = "esni")]
+struct ServerRecordSNI {
+    inner: Arc<dyn ResolvesServerCert>,
+    sni: Mutex<Option<String>>,
+}
+
+#[cfg(feature = "esni")]
+impl ServerRecordSNI {
+    fn new(inner: Arc<dyn ResolvesServerCert>) -> ServerRecordSNI {
+        ServerRecordSNI {
+            inner,
+            sni: Mutex::new(None),
+        }
+    }
+
+    fn sni(&self) -> Option<String> {
+        self.sni.lock().unwrap().clone()
+    }
+}
+
+#[cfg(feature = "esni")]
+impl ResolvesServerCert for ServerRecordSNI {
+    fn resolve(&self, client_hello: ClientHello) -> Option<sign::CertifiedKey> {
+        *self.sni.lock().unwrap() = client_hello.server_name()
+            .map(|name| { let name: &str = name.into(); name.to_string() });
+        self.inner.resolve(client_hello)
+    }
+}
+
+#[cfg(feature = "esni")]
+fn make_esni_client_config() -> ClientConfig {
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.versions = vec![ ProtocolVersion::TLSv1_3 ];
+    client_config.encrypt_sni = true;
+    client_config
+}
+
+#[cfg(feature = "esni")]
+fn make_esni_pair(server_config: ServerConfig, record: &Vec<u8>) -> (ClientSession, ServerSession) {
+    make_esni_pair_for_arc_configs(&Arc::new(make_esni_client_config()),
+                                   &Arc::new(server_config),
+                                   record)
+}
+
+#[cfg(feature = "esni")]
+fn make_esni_pair_for_arc_configs(client_config: &Arc<ClientConfig>,
+                                  server_config: &Arc<ServerConfig>,
+                                  record: &Vec<u8>) -> (ClientSession, ServerSession) {
+    let esni_data = esni::create_esni_handshake(record).unwrap();
+    let client = ClientSession::new_with_esni(client_config,
+                                              dns_name("localhost"),
+                                              esni_data);
+    let server = ServerSession::new(server_config);
+    (client, server)
+}
+
+#[cfg(feature = "esni")]
+#[test]
+fn server_decrypts_esni() {
+    let key = esni::ESNIPrivateKey::new_x25519(&[ 0x42; 32 ]).unwrap();
+    let record = make_esni_record(&key);
+
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.esni_keys = vec![ esni::ESNIServerKeys::new(&record, vec![ key ]).unwrap() ];
+
+    let (mut client, mut server) = make_esni_pair(server_config, &record);
+    assert_eq!(None, server.get_sni_hostname());
+    do_handshake(&mut client, &mut server);
+    assert_eq!(Some("localhost"), server.get_sni_hostname());
+}
+
+#[cfg(feature = "esni")]
+#[test]
+fn server_decrypts_esni_with_generated_keys() {
+    for group in &[ NamedGroup::X25519, NamedGroup::secp256r1 ] {
+        let keys = esni::ESNIKeysBuilder::new(0, u64::max_value())
+            .groups(&[ *group ])
+            .build()
+            .unwrap();
+        let keys = esni::ESNIKeys::from_bytes(&keys.to_bytes()).unwrap();
+
+        let mut server_config = make_server_config(KeyType::RSA);
+        server_config.esni_keys = vec![ keys.to_server_keys().unwrap() ];
+
+        let (mut client, mut server) = make_esni_pair(server_config,
+                                                      &keys.get_record_bytes().to_vec());
+        do_handshake(&mut client, &mut server);
+        assert_eq!(Some("localhost"), server.get_sni_hostname());
+    }
+}
+
+#[cfg(feature = "esni")]
+fn assert_esni_unavailable(mut client: ClientSession) {
+    // Nothing is sent: in particular, not our SNI in the clear.  Like
+    // other errors, this is only reported by `process_new_packets`.
+    assert!(!client.wants_write());
+    assert!(client.wants_read());
+    match client.process_new_packets() {
+        Err(TLSError::ESNIUnavailable(_)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    let err = client.complete_io(&mut io::Cursor::new(Vec::new())).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[cfg(feature = "esni")]
+#[test]
+fn client_fails_without_esni_record() {
+    let client_config = Arc::new(make_esni_client_config());
+    assert_esni_unavailable(ClientSession::new(&client_config, server_name("localhost")));
+}
+
+#[cfg(feature = "esni")]
+#[test]
+fn client_fails_when_esni_needs_tls13() {
+    let key = esni::ESNIPrivateKey::new_x25519(&[ 0x42; 32 ]).unwrap();
+    let record = make_esni_record(&key);
+
+    let mut client_config = make_esni_client_config();
+    client_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+    let client = ClientSession::new_with_esni(&Arc::new(client_config),
+                                              dns_name("localhost"),
+                                              esni::create_esni_handshake(&record).unwrap());
+    assert_esni_unavailable(client);
+}
+
+#[cfg(feature = "esni")]
+#[test]
+fn server_resolves_cert_with_esni() {
+    let key = esni::ESNIPrivateKey::new_x25519(&[ 0x42; 32 ]).unwrap();
+    let record = make_esni_record(&key);
+
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.esni_keys = vec![ esni::ESNIServerKeys::new(&record, vec![ key ]).unwrap() ];
+    let resolver = Arc::new(ServerRecordSNI::new(server_config.cert_resolver.clone()));
+    server_config.cert_resolver = resolver.clone();
+
+    let (mut client, mut server) = make_esni_pair(server_config, &record);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(Some("localhost".to_string()), resolver.sni());
+    assert_eq!(Some("localhost"), server.get_sni_hostname());
+}
+
+#[cfg(feature = "esni")]
+#[test]
+fn server_ignores_esni_without_keys() {
+    let key = esni::ESNIPrivateKey::new_x25519(&[ 0x42; 32 ]).unwrap();
+    let record = make_esni_record(&key);
+
+    let (mut client, mut server) = make_esni_pair(make_server_config(KeyType::RSA), &record);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Client(TLSError::ESNINonceMissing)));
+    assert_eq!(None, server.get_sni_hostname());
+}
+
+#[cfg(feature = "esni")]
+#[test]
+fn client_rejects_server_dropping_esni_nonce() {
+    let key = esni::ESNIPrivateKey::new_x25519(&[ 0x42; 32 ]).unwrap();
+    let record = make_esni_record(&key);
+
+    // This server doesn't support ESNI, so doesn't echo the nonce.
+    let (mut client, mut server) = make_esni_pair(make_server_config(KeyType::RSA), &record);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Client(TLSError::ESNINonceMissing)));
+
+    transfer(&mut client, &mut server);
+    assert_eq!(server.process_new_packets(),
+               Err(TLSError::AlertReceived(rustls::internal::msgs::enums::AlertDescription::MissingExtension)));
+}
+
+#[cfg(feature = "esni")]
+#[test]
+fn server_ignores_esni_for_unknown_record() {
+    let key = esni::ESNIPrivateKey::new_x25519(&[ 0x42; 32 ]).unwrap();
+    let record = make_esni_record(&key);
+    let other_key = esni::ESNIPrivateKey::new_x25519(&[ 0x43; 32 ]).unwrap();
+    let other_record = make_esni_record(&other_key);
 
-    let pipe = OtherSession::new_fails(&mut server);
-    let mut client_stream = StreamOwned::new(client, pipe);
-    let rc = client_stream.write(b"hello");
-    assert!(rc.is_err());
-    assert_eq!(format!("{:?}", rc),
-               "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })");
-    let rc = client_stream.write(b"hello");
-    assert!(rc.is_err());
-    assert_eq!(format!("{:?}", rc),
-               "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })");
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.esni_keys = vec![ esni::ESNIServerKeys::new(&other_record, vec![ other_key ]).unwrap() ];
+
+    let (mut client, mut server) = make_esni_pair(server_config, &record);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Client(TLSError::ESNINonceMissing)));
+    assert_eq!(None, server.get_sni_hostname());
 }
 
+#[cfg(feature = "esni")]
 #[test]
-fn server_stream_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+fn server_decrypts_esni_after_hello_retry_request() {
+    let key = esni::ESNIPrivateKey::new_x25519(&[ 0x42; 32 ]).unwrap();
+    let record = make_esni_record(&key);
 
-    client.write(b"world").unwrap();
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.esni_keys = vec![ esni::ESNIServerKeys::new(&record, vec![ key ]).unwrap() ];
 
-    {
-        let mut pipe = OtherSession::new_fails(&mut client);
-        let mut server_stream = Stream::new(&mut server, &mut pipe);
-        let mut bytes = [0u8; 5];
-        let rc = server_stream.read(&mut bytes);
-        assert!(rc.is_err());
-        assert_eq!(format!("{:?}", rc),
-                   "Err(Custom { kind: InvalidData, error: PeerIncompatibleError(\"no ciphersuites in common\") })");
-    }
+    // The client is misled into offering a key share the server
+    // doesn't support, forcing a HelloRetryRequest.
+    let mut client_config = make_esni_client_config();
+    client_config.set_persistence(Arc::new(ClientKxHint(vec![ 0x00, 0x1e ])));
+
+    let (mut client, mut server) = make_esni_pair_for_arc_configs(&Arc::new(client_config),
+                                                                  &Arc::new(server_config),
+                                                                  &record);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(Some("localhost"), server.get_sni_hostname());
 }
 
+#[cfg(feature = "esni")]
 #[test]
-fn server_streamowned_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, server) = make_pair_for_configs(client_config, server_config);
+fn server_decrypts_esni_when_resuming() {
+    let key = esni::ESNIPrivateKey::new_x25519(&[ 0x42; 32 ]).unwrap();
+    let record = make_esni_record(&key);
 
-    client.write(b"world").unwrap();
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.esni_keys = vec![ esni::ESNIServerKeys::new(&record, vec![ key ]).unwrap() ];
+    let server_config = Arc::new(server_config);
+    let client_config = Arc::new(make_esni_client_config());
 
-    let pipe = OtherSession::new_fails(&mut client);
-    let mut server_stream = StreamOwned::new(server, pipe);
-    let mut bytes = [0u8; 5];
-    let rc = server_stream.read(&mut bytes);
-    assert!(rc.is_err());
-    assert_eq!(format!("{:?}", rc),
-               "Err(Custom { kind: InvalidData, error: PeerIncompatibleError(\"no ciphersuites in common\") })");
+    let (mut client, mut server) = make_esni_pair_for_arc_configs(&client_config,
+                                                                  &server_config,
+                                                                  &record);
+    server.set_resumption_data(b"hello");
+    do_handshake(&mut client, &mut server);
+    assert_eq!(None, server.received_resumption_data());
+
+    let (mut client, mut server) = make_esni_pair_for_arc_configs(&client_config,
+                                                                  &server_config,
+                                                                  &record);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(Some(&b"hello"[..]), server.received_resumption_data());
+    assert_eq!(Some("localhost"), server.get_sni_hostname());
 }
 
+#[cfg(feature = "esni")]
 #[test]
-fn server_config_is_clone() {
-    let _ = make_server_config(KeyType::RSA).clone();
+fn esni_server_keys_must_match_record() {
+    let key = esni::ESNIPrivateKey::new_x25519(&[ 0x42; 32 ]).unwrap();
+    let other_key = esni::ESNIPrivateKey::new_x25519(&[ 0x43; 32 ]).unwrap();
+    let record = make_esni_record(&key);
+
+    assert!(esni::ESNIServerKeys::new(&record, vec![ other_key ]).is_err());
+    assert!(esni::ESNIServerKeys::new(&record, vec![]).is_err());
+    assert!(esni::ESNIServerKeys::new(&record[1..], vec![ key ]).is_err());
+}
+
+/// Session storage which answers every query with the same value:
+/// useful for planting a key share hint.
+struct ClientKxHint(Vec<u8>);
+
+impl rustls::StoresClientSessions for ClientKxHint {
+    fn put(&self, _key: Vec<u8>, _value: Vec<u8>) -> bool {
+        false
+    }
+
+    fn get(&self, _key: &[u8]) -> Option<Vec<u8>> {
+        Some(self.0.clone())
+    }
+}
+
+fn make_ech_pair(server_config: ServerConfig, config_list: &[u8]) -> (ClientSession, ServerSession) {
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.versions = vec![ ProtocolVersion::TLSv1_3 ];
+    make_ech_pair_for_configs(client_config, server_config, config_list)
+}
+
+fn make_ech_pair_for_configs(client_config: ClientConfig,
+                             server_config: ServerConfig,
+                             config_list: &[u8]) -> (ClientSession, ServerSession) {
+    let ech_data = ech::create_ech_handshake(config_list).unwrap();
+    let client = ClientSession::new_with_ech(&Arc::new(client_config),
+                                             dns_name("localhost"),
+                                             ech_data);
+    let server = ServerSession::new(&Arc::new(server_config));
+    (client, server)
 }
 
 #[test]
-fn client_config_is_clone() {
-    let _ = make_client_config(KeyType::RSA).clone();
+fn server_accepts_ech() {
+    let keys = ech::ECHServerKeys::generate(1, "testserver.com").unwrap();
+    let config_list = ech::make_config_list(&[ keys.clone() ]);
+
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.ech_keys = vec![ keys ];
+
+    let (mut client, mut server) = make_ech_pair(server_config, &config_list);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(Some("localhost"), server.get_sni_hostname());
 }
 
 #[test]
-fn client_session_is_debug() {
-    let (client, _) = make_pair(KeyType::RSA);
-    println!("{:?}", client);
+fn server_accepts_ech_after_hello_retry_request() {
+    let keys = ech::ECHServerKeys::generate(1, "testserver.com").unwrap();
+    let config_list = ech::make_config_list(&[ keys.clone() ]);
+
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.ech_keys = vec![ keys ];
+
+    // The client is misled into offering a key share the server
+    // doesn't support, forcing a HelloRetryRequest.
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.versions = vec![ ProtocolVersion::TLSv1_3 ];
+    client_config.set_persistence(Arc::new(ClientKxHint(vec![ 0x00, 0x1e ])));
+
+    let (mut client, mut server) = make_ech_pair_for_configs(client_config,
+                                                             server_config,
+                                                             &config_list);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(Some("localhost"), server.get_sni_hostname());
 }
 
 #[test]
-fn server_session_is_debug() {
-    let (_, server) = make_pair(KeyType::RSA);
-    println!("{:?}", server);
+fn client_reports_ech_rejection_with_retry_configs() {
+    let keys = ech::ECHServerKeys::generate(1, "testserver.com").unwrap();
+    let other_keys = ech::ECHServerKeys::generate(2, "testserver.com").unwrap();
+    let retry_configs = ech::make_config_list(&[ keys.clone() ]);
+
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.ech_keys = vec![ keys ];
+
+    let (mut client, mut server) = make_ech_pair(server_config,
+                                                 &ech::make_config_list(&[ other_keys ]));
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert_eq!(err,
+               Err(TLSErrorFromPeer::Client(TLSError::ECHRejected(Some(retry_configs)))));
+
+    transfer(&mut client, &mut server);
+    assert_eq!(server.process_new_packets(),
+               Err(TLSError::AlertReceived(rustls::internal::msgs::enums::AlertDescription::ECHRequired)));
 }
 
 #[test]
-fn server_complete_io_for_handshake_ending_with_alert() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+fn client_rejects_ech_with_tls12_server() {
+    let keys = ech::ECHServerKeys::generate(1, "testserver.com").unwrap();
+    let config_list = ech::make_config_list(&[ keys ]);
 
-    assert_eq!(true, server.is_handshaking());
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.versions = vec![ ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2 ];
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
 
-    let mut pipe = OtherSession::new_fails(&mut client);
-    let rc = server.complete_io(&mut pipe);
-    assert!(rc.is_err(),
-            "server io failed due to handshake failure");
-    assert!(!server.wants_write(),
-            "but server did send its alert");
-    assert_eq!(format!("{:?}", pipe.last_error),
-               "Some(AlertReceived(HandshakeFailure))",
-               "which was received by client");
+    let (mut client, mut server) = make_ech_pair_for_configs(client_config,
+                                                             server_config,
+                                                             &config_list);
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert_eq!(err, Err(TLSErrorFromPeer::Client(TLSError::ECHRejected(None))));
 }
 
 #[test]
-fn server_exposes_offered_sni() {
-    let kt = KeyType::RSA;
-    let mut client = ClientSession::new(&Arc::new(make_client_config(kt)),
-                                        dns_name("second.testserver.com"));
-    let mut server = ServerSession::new(&Arc::new(make_server_config(kt)));
+fn ech_rejects_unusable_config_lists() {
+    assert!(ech::create_ech_handshake(&[]).is_none());
+    assert!(ech::create_ech_handshake(&[ 0x00, 0x00 ]).is_none());
 
-    assert_eq!(None, server.get_sni_hostname());
-    do_handshake(&mut client, &mut server);
-    assert_eq!(Some("second.testserver.com"), server.get_sni_hostname());
+    let keys = ech::ECHServerKeys::generate(1, "testserver.com").unwrap();
+    let config_list = ech::make_config_list(&[ keys ]);
+    assert!(ech::create_ech_handshake(&config_list[1..]).is_none());
 }
 
 #[test]
@@ -1192,12 +2216,12 @@ fn sni_resolver_works() {
     let server_config = Arc::new(server_config);
 
     let mut server1 = ServerSession::new(&server_config);
-    let mut client1 = ClientSession::new(&Arc::new(make_client_config(kt)), dns_name("localhost"));
+    let mut client1 = ClientSession::new(&Arc::new(make_client_config(kt)), server_name("localhost"));
     let err = do_handshake_until_error(&mut client1, &mut server1);
     assert_eq!(err, Ok(()));
 
     let mut server2 = ServerSession::new(&server_config);
-    let mut client2 = ClientSession::new(&Arc::new(make_client_config(kt)), dns_name("notlocalhost"));
+    let mut client2 = ClientSession::new(&Arc::new(make_client_config(kt)), server_name("notlocalhost"));
     let err = do_handshake_until_error(&mut client2, &mut server2);
     assert_eq!(err,
                Err(TLSErrorFromPeer::Server(
@@ -1394,13 +2418,374 @@ fn negotiated_ciphersuite_server() {
         server_config.ciphersuites = vec![scs];
         server_config.versions = vec![version];
 
-        do_suite_test(make_client_config(kt),
-                      server_config,
-                      scs,
-                      version);
+        do_suite_test(make_client_config(kt),
+                      server_config,
+                      scs,
+                      version);
+    }
+}
+
+/// A key exchange group which counts how often it is used.
+#[derive(Debug)]
+struct CountingKxGroup {
+    inner: &'static dyn SupportedKxGroup,
+    starts: AtomicUsize,
+}
+
+impl SupportedKxGroup for CountingKxGroup {
+    fn name(&self) -> NamedGroup {
+        self.inner.name()
+    }
+
+    fn start(&self) -> Option<Box<dyn ActiveKeyExchange>> {
+        self.starts.fetch_add(1, Ordering::SeqCst);
+        self.inner.start()
+    }
+
+    fn respond(&self, peer: &[u8]) -> Option<KeyExchangeResult> {
+        self.starts.fetch_add(1, Ordering::SeqCst);
+        self.inner.respond(peer)
+    }
+
+    fn usable_for_tls12(&self) -> bool {
+        self.inner.usable_for_tls12()
+    }
+
+    fn worth_hello_retry_request(&self) -> bool {
+        self.inner.worth_hello_retry_request()
+    }
+
+    fn ffdhe_params(&self) -> Option<FfdheParams> {
+        self.inner.ffdhe_params()
+    }
+}
+
+fn leak_counting_kx_group(inner: &'static dyn SupportedKxGroup) -> &'static CountingKxGroup {
+    Box::leak(Box::new(CountingKxGroup { inner, starts: AtomicUsize::new(0) }))
+}
+
+#[test]
+fn negotiated_kx_group_restricted() {
+    for version in &[ ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3 ] {
+        let client_group = leak_counting_kx_group(&kx_group::SECP256R1);
+        let server_group = leak_counting_kx_group(&kx_group::SECP256R1);
+
+        let mut client_config = make_client_config(KeyType::RSA);
+        client_config.versions = vec![ *version ];
+        client_config.kx_groups = vec![ client_group ];
+        let mut server_config = make_server_config(KeyType::RSA);
+        server_config.kx_groups = vec![ server_group ];
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client_group.starts.load(Ordering::SeqCst), 1);
+        assert_eq!(server_group.starts.load(Ordering::SeqCst), 1);
+    }
+}
+
+#[test]
+fn server_kx_group_preference_causes_hello_retry_request() {
+    let server_group = leak_counting_kx_group(&kx_group::SECP256R1);
+
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.versions = vec![ ProtocolVersion::TLSv1_3 ];
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.kx_groups = vec![ server_group ];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server_group.starts.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn no_shared_kx_group() {
+    for version in &[ ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3 ] {
+        let mut client_config = make_client_config(KeyType::RSA);
+        client_config.versions = vec![ *version ];
+        client_config.kx_groups = vec![ &kx_group::X25519 ];
+        let mut server_config = make_server_config(KeyType::RSA);
+        server_config.kx_groups = vec![ &kx_group::SECP256R1 ];
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        match do_handshake_until_error(&mut client, &mut server) {
+            Err(TLSErrorFromPeer::Server(TLSError::PeerIncompatibleError(_))) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
+
+#[cfg(feature = "pq_kx")]
+static PQ_KX_GROUPS: [&dyn SupportedKxGroup; 2] = [
+    &kx_group::X25519MLKEM768,
+    &kx_group::X25519KYBER768DRAFT00,
+];
+
+#[cfg(feature = "pq_kx")]
+#[test]
+fn pq_kx_group_handshake() {
+    for pq_group in PQ_KX_GROUPS.iter() {
+        let client_group = leak_counting_kx_group(*pq_group);
+        let server_group = leak_counting_kx_group(*pq_group);
+
+        let mut client_config = make_client_config(KeyType::RSA);
+        client_config.kx_groups = vec![ client_group, &kx_group::X25519 ];
+        let mut server_config = make_server_config(KeyType::RSA);
+        server_config.kx_groups = vec![ server_group, &kx_group::X25519 ];
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.get_protocol_version(), Some(ProtocolVersion::TLSv1_3));
+        assert_eq!(client_group.starts.load(Ordering::SeqCst), 1);
+        assert_eq!(server_group.starts.load(Ordering::SeqCst), 1);
+    }
+}
+
+#[cfg(feature = "pq_kx")]
+#[test]
+fn pq_kx_group_unpredicted_by_client_causes_hello_retry_request() {
+    for pq_group in PQ_KX_GROUPS.iter() {
+        let client_group = leak_counting_kx_group(*pq_group);
+        let server_group = leak_counting_kx_group(*pq_group);
+
+        let mut client_config = make_client_config(KeyType::RSA);
+        client_config.kx_groups = vec![ &kx_group::X25519, client_group ];
+        let mut server_config = make_server_config(KeyType::RSA);
+        server_config.kx_groups = vec![ server_group, &kx_group::X25519 ];
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client_group.starts.load(Ordering::SeqCst), 1);
+        assert_eq!(server_group.starts.load(Ordering::SeqCst), 1);
+    }
+}
+
+#[cfg(feature = "pq_kx")]
+#[test]
+fn pq_kx_group_not_used_for_tls12() {
+    let client_group = leak_counting_kx_group(&kx_group::X25519MLKEM768);
+    let server_group = leak_counting_kx_group(&kx_group::X25519MLKEM768);
+
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+    client_config.kx_groups = vec![ client_group, &kx_group::X25519 ];
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.kx_groups = vec![ server_group, &kx_group::X25519 ];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client_group.starts.load(Ordering::SeqCst), 0);
+    assert_eq!(server_group.starts.load(Ordering::SeqCst), 0);
+}
+
+#[cfg(feature = "pq_kx")]
+#[test]
+fn pq_kx_group_only_for_tls12_fails() {
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+    client_config.kx_groups = vec![ &kx_group::X25519MLKEM768 ];
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.kx_groups = vec![ &kx_group::X25519MLKEM768 ];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::PeerIncompatibleError("no ciphersuites in common".to_string()))));
+}
+
+#[cfg(feature = "ffdhe")]
+fn make_ffdhe_configs(version: ProtocolVersion,
+                      client_group: &'static dyn SupportedKxGroup,
+                      server_group: &'static dyn SupportedKxGroup) -> (ClientConfig, ServerConfig) {
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.versions = vec![ version ];
+    client_config.ciphersuites.extend_from_slice(&FFDHE_CIPHERSUITES);
+    client_config.kx_groups = vec![ client_group ];
+
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.ciphersuites.extend_from_slice(&FFDHE_CIPHERSUITES);
+    server_config.kx_groups = vec![ server_group ];
+
+    (client_config, server_config)
+}
+
+#[cfg(feature = "ffdhe")]
+#[test]
+fn ffdhe_tls12_handshake() {
+    for suite in FFDHE_CIPHERSUITES.iter() {
+        let client_group = leak_counting_kx_group(&kx_group::FFDHE2048);
+        let server_group = leak_counting_kx_group(&kx_group::FFDHE2048);
+        let (mut client_config, server_config) =
+            make_ffdhe_configs(ProtocolVersion::TLSv1_2, client_group, server_group);
+        client_config.ciphersuites = vec![ *suite ];
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.get_negotiated_ciphersuite().unwrap().suite, suite.suite);
+        assert_eq!(server.get_negotiated_ciphersuite().unwrap().suite, suite.suite);
+        assert_eq!(client_group.starts.load(Ordering::SeqCst), 1);
+        assert_eq!(server_group.starts.load(Ordering::SeqCst), 1);
+
+        client.write_all(b"hello").unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        check_read(&mut server, b"hello");
+    }
+}
+
+#[cfg(feature = "ffdhe")]
+#[test]
+fn ffdhe_tls13_handshake() {
+    let client_group = leak_counting_kx_group(&kx_group::FFDHE2048);
+    let server_group = leak_counting_kx_group(&kx_group::FFDHE2048);
+    let (client_config, server_config) =
+        make_ffdhe_configs(ProtocolVersion::TLSv1_3, client_group, server_group);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.get_protocol_version(), Some(ProtocolVersion::TLSv1_3));
+    assert_eq!(client_group.starts.load(Ordering::SeqCst), 1);
+    assert_eq!(server_group.starts.load(Ordering::SeqCst), 1);
+}
+
+#[cfg(feature = "ffdhe")]
+#[test]
+fn ffdhe_is_opt_in() {
+    let client_config = make_client_config(KeyType::RSA);
+    let server_config = make_server_config(KeyType::RSA);
+
+    for groups in &[&rustls::ALL_KX_GROUPS[..], &client_config.kx_groups, &server_config.kx_groups] {
+        assert!(groups.iter().all(|skxg| skxg.ffdhe_params().is_none()));
+    }
+
+    for suite in FFDHE_CIPHERSUITES.iter() {
+        assert!(!ALL_CIPHERSUITES.contains(suite));
+        assert!(!client_config.ciphersuites.contains(suite));
+        assert!(!server_config.ciphersuites.contains(suite));
+    }
+}
+
+#[cfg(feature = "ffdhe")]
+#[test]
+fn ffdhe_suites_not_chosen_without_ffdhe_group() {
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+    client_config.ciphersuites = FFDHE_CIPHERSUITES.to_vec();
+    client_config.ciphersuites.extend_from_slice(&ALL_CIPHERSUITES);
+    client_config.kx_groups.push(&kx_group::FFDHE2048);
+
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.ciphersuites = FFDHE_CIPHERSUITES.to_vec();
+    server_config.ciphersuites.extend_from_slice(&ALL_CIPHERSUITES);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.get_negotiated_ciphersuite().unwrap().suite,
+               CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256);
+}
+
+#[cfg(feature = "aes_ccm")]
+#[test]
+fn ccm_tls13_handshake() {
+    for suite in TLS13_CCM_CIPHERSUITES.iter() {
+        for kt in ALL_KEY_TYPES.iter() {
+            let mut client_config = make_client_config(*kt);
+            client_config.versions = vec![ ProtocolVersion::TLSv1_3 ];
+            client_config.ciphersuites = vec![ *suite ];
+
+            let mut server_config = make_server_config(*kt);
+            server_config.ciphersuites = vec![ *suite ];
+
+            let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+            do_handshake(&mut client, &mut server);
+            assert_eq!(client.get_negotiated_ciphersuite().unwrap().suite, suite.suite);
+            assert_eq!(server.get_negotiated_ciphersuite().unwrap().suite, suite.suite);
+
+            client.write_all(b"hello").unwrap();
+            transfer(&mut client, &mut server);
+            server.process_new_packets().unwrap();
+            check_read(&mut server, b"hello");
+
+            server.write_all(b"world").unwrap();
+            transfer(&mut server, &mut client);
+            client.process_new_packets().unwrap();
+            check_read(&mut client, b"world");
+        }
+    }
+}
+
+#[cfg(feature = "aes_ccm")]
+#[test]
+fn ccm_suites_are_opt_in() {
+    for suite in TLS13_CCM_CIPHERSUITES.iter() {
+        assert!(!ALL_CIPHERSUITES.contains(suite));
+        assert!(!make_client_config(KeyType::RSA).ciphersuites.contains(suite));
+        assert!(!make_server_config(KeyType::RSA).ciphersuites.contains(suite));
+    }
+
+    // A client preferring CCM still gets a default suite from a
+    // server which didn't opt in.
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.ciphersuites = TLS13_CCM_CIPHERSUITES.to_vec();
+    client_config.ciphersuites.extend_from_slice(&ALL_CIPHERSUITES);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config,
+                                                         make_server_config(KeyType::RSA));
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.get_negotiated_ciphersuite().unwrap().suite,
+               CipherSuite::TLS13_CHACHA20_POLY1305_SHA256);
+
+    // And with nothing else, no suite at all.
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.ciphersuites = TLS13_CCM_CIPHERSUITES.to_vec();
+
+    let (mut client, mut server) = make_pair_for_configs(client_config,
+                                                         make_server_config(KeyType::RSA));
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert_eq!(err, Err(TLSErrorFromPeer::Server(TLSError::PeerIncompatibleError(
+        "no ciphersuites in common".to_string()))));
+}
+
+/// A key exchange group which claims to be `name`, but uses
+/// the parameters of `inner`.
+#[cfg(feature = "ffdhe")]
+#[derive(Debug)]
+struct MislabelledKxGroup {
+    name: NamedGroup,
+    inner: &'static dyn SupportedKxGroup,
+}
+
+#[cfg(feature = "ffdhe")]
+impl SupportedKxGroup for MislabelledKxGroup {
+    fn name(&self) -> NamedGroup {
+        self.name
+    }
+
+    fn start(&self) -> Option<Box<dyn ActiveKeyExchange>> {
+        self.inner.start()
+    }
+
+    fn ffdhe_params(&self) -> Option<FfdheParams> {
+        self.inner.ffdhe_params()
     }
 }
 
+#[cfg(feature = "ffdhe")]
+#[test]
+fn ffdhe_client_rejects_unexpected_group() {
+    static MISLABELLED: MislabelledKxGroup = MislabelledKxGroup {
+        name: NamedGroup::FFDHE3072,
+        inner: &kx_group::FFDHE2048,
+    };
+
+    let (client_config, server_config) =
+        make_ffdhe_configs(ProtocolVersion::TLSv1_2, &kx_group::FFDHE3072, &MISLABELLED);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Client(
+                   TLSError::PeerMisbehavedError("key exchange failed".to_string()))));
+}
+
 #[derive(Debug, PartialEq)]
 struct KeyLogItem {
     label: String,
@@ -1772,6 +3157,267 @@ fn tls13_stateless_resumption() {
     assert_eq!(storage.takes(), 0);
 }
 
+#[test]
+fn client_resumes_from_session_file_in_new_process() {
+    let kt = KeyType::RSA;
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("sessions");
+    let server_config = Arc::new(make_server_config(kt));
+
+    for version in &[ ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3 ] {
+        // Each process has its own config and cache, sharing the file.
+        let new_process = || {
+            let mut client_config = make_client_config(kt);
+            client_config.versions = vec![ *version ];
+            client_config.session_persistence = ClientSessionFileCache::new(&path, 32);
+            Arc::new(client_config)
+        };
+
+        // full handshake
+        let (mut client, mut server) = make_pair_for_arc_configs(&new_process(), &server_config);
+        let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
+
+        // resumed
+        let (mut client, mut server) = make_pair_for_arc_configs(&new_process(), &server_config);
+        let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
+        assert!(resume_s2c < full_s2c);
+        if *version == ProtocolVersion::TLSv1_3 {
+            assert!(resume_c2s > full_c2s);
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+/// A `TimeProvider` which only moves when it's told to.
+struct FixedTime(Mutex<Option<u64>>);
+
+impl FixedTime {
+    fn new(now: Option<u64>) -> Arc<FixedTime> {
+        Arc::new(FixedTime(Mutex::new(now)))
+    }
+
+    fn advance(&self, secs: u64) {
+        let mut now = self.0.lock().unwrap();
+        *now = now.map(|now| now + secs);
+    }
+}
+
+impl TimeProvider for FixedTime {
+    fn now(&self) -> Option<u64> {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[test]
+fn peers_check_certificates_at_provided_time() {
+    let kt = KeyType::RSA;
+    let year_3000 = 32503680000;
+
+    for (now, err) in vec![
+        (Some(0), TLSError::WebPKIError(webpki::Error::CertNotValidYet)),
+        (Some(year_3000), TLSError::WebPKIError(webpki::Error::CertExpired)),
+        (None, TLSError::FailedToGetCurrentTime),
+    ] {
+        let mut client_config = make_client_config(kt);
+        client_config.time_provider = FixedTime::new(now);
+        let (mut client, mut server) = make_pair_for_configs(client_config,
+                                                             make_server_config(kt));
+        assert_eq!(do_handshake_until_error(&mut client, &mut server),
+                   Err(TLSErrorFromPeer::Client(err.clone())));
+
+        let mut server_config = make_server_config_with_mandatory_client_auth(kt);
+        server_config.time_provider = FixedTime::new(now);
+        let (mut client, mut server) = make_pair_for_configs(make_client_config_with_auth(kt),
+                                                             server_config);
+        assert_eq!(do_handshake_until_error(&mut client, &mut server),
+                   Err(TLSErrorFromPeer::Server(err)));
+    }
+}
+
+#[test]
+fn client_expires_tickets_at_provided_time() {
+    let kt = KeyType::RSA;
+    let time = FixedTime::new(SystemTimeProvider.now());
+
+    let mut client_config = make_client_config(kt);
+    client_config.versions = vec![ ProtocolVersion::TLSv1_3 ];
+    client_config.time_provider = time.clone();
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    server_config.ticketer = rustls::Ticketer::new();
+    server_config.time_provider = time.clone();
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
+
+    // resumed, an hour later
+    time.advance(60 * 60);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
+    assert!(resume_c2s > full_c2s);
+    assert!(resume_s2c < full_s2c);
+
+    // the ticket from that lasts 12 hours, so a day later this is
+    // a full handshake again
+    time.advance(24 * 60 * 60);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(do_handshake(&mut client, &mut server), (full_c2s, full_s2c));
+}
+
+#[test]
+fn server_rolls_ticket_keys_at_provided_time() {
+    let kt = KeyType::RSA;
+    let time = FixedTime::new(SystemTimeProvider.now());
+
+    let mut client_config = make_client_config(kt);
+    client_config.versions = vec![ ProtocolVersion::TLSv1_2 ];
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    server_config.ticketer = rustls::Ticketer::new();
+    server_config.time_provider = time.clone();
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (_, full_s2c) = do_handshake(&mut client, &mut server);
+
+    // resumed, an hour later
+    time.advance(60 * 60);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (_, resume_s2c) = do_handshake(&mut client, &mut server);
+    assert!(resume_s2c < full_s2c);
+
+    // other clients' handshakes roll the ticket keys twice by the
+    // server's clock; the client's clock hasn't moved, so it still
+    // offers its ticket, but now gets a full handshake
+    for _ in 0..2 {
+        time.advance(7 * 60 * 60);
+        let other_config = Arc::new(make_client_config(kt));
+        let (mut client, mut server) = make_pair_for_arc_configs(&other_config, &server_config);
+        do_handshake(&mut client, &mut server);
+    }
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (_, s2c) = do_handshake(&mut client, &mut server);
+    assert!(s2c > resume_s2c);
+}
+
+/// Make a CT log run by `operator`, with a fresh key.
+fn make_ct_log(id: u8, operator: &'static str) -> (&'static sct::Log<'static>, EcdsaKeyPair) {
+    let rng = ring::rand::SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+    let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()).unwrap();
+    let log = sct::Log {
+        description: "test log",
+        url: "ct.example.com",
+        operated_by: operator,
+        key: Box::leak(key.public_key().as_ref().to_vec().into_boxed_slice()),
+        id: [id; 32],
+        max_merge_delay: 86400,
+    };
+    (Box::leak(Box::new(log)), key)
+}
+
+/// Make an SCT for `cert`, signed by `log`.
+fn make_sct(log: &(&sct::Log, EcdsaKeyPair), cert: &Certificate) -> Vec<u8> {
+    let timestamp = 1_560_000_000_000u64;
+
+    let mut signed = vec![0u8, 0u8];
+    timestamp.encode(&mut signed);
+    0u16.encode(&mut signed); // x509_entry
+    codec::u24(cert.0.len() as u32).encode(&mut signed);
+    signed.extend_from_slice(&cert.0);
+    0u16.encode(&mut signed); // no extensions
+    let rng = ring::rand::SystemRandom::new();
+    let signature = log.1.sign(&rng, &signed).unwrap();
+
+    let mut sct = vec![0u8];
+    sct.extend_from_slice(&log.0.id);
+    timestamp.encode(&mut sct);
+    0u16.encode(&mut sct);
+    SignatureScheme::ECDSA_NISTP256_SHA256.encode(&mut sct);
+    (signature.as_ref().len() as u16).encode(&mut sct);
+    sct.extend_from_slice(signature.as_ref());
+    sct
+}
+
+fn make_sct_list(scts: &[Vec<u8>]) -> Vec<u8> {
+    let mut list = Vec::new();
+    for sct in scts {
+        (sct.len() as u16).encode(&mut list);
+        list.extend_from_slice(sct);
+    }
+    let mut ret = Vec::new();
+    (list.len() as u16).encode(&mut ret);
+    ret.extend_from_slice(&list);
+    ret
+}
+
+#[test]
+fn client_enforces_ct_policy() {
+    let kt = KeyType::RSA;
+    let alice = make_ct_log(1, "Alice");
+    let bob = make_ct_log(2, "Bob");
+    let alice2 = make_ct_log(3, "Alice");
+    let logs: &'static [&'static sct::Log<'static>] =
+        Box::leak(vec![ alice.0, bob.0, alice2.0 ].into_boxed_slice());
+
+    let end_entity = &kt.get_chain()[0];
+    let run = |signers: &[&(&sct::Log, EcdsaKeyPair)], policy: CTPolicy| {
+        let scts = signers.iter()
+            .map(|log| make_sct(log, end_entity))
+            .collect::<Vec<_>>();
+        let mut server_config = make_server_config(kt);
+        server_config.set_single_cert_with_ocsp_and_sct(kt.get_chain(),
+                                                        kt.get_key(),
+                                                        vec![],
+                                                        make_sct_list(&scts))
+            .unwrap();
+        let server_config = Arc::new(server_config);
+
+        let mut client_config = make_client_config(kt);
+        client_config.set_ct_policy(logs, policy);
+
+        let mut results = Vec::new();
+        for client_config in AllClientVersions::new(client_config) {
+            let mut client = ClientSession::new(&Arc::new(client_config), server_name("localhost"));
+            let mut server = ServerSession::new(&server_config);
+            results.push(do_handshake_until_error(&mut client, &mut server));
+        }
+        results
+    };
+    let not_met = |why: &str| {
+        Err(TLSErrorFromPeer::Client(TLSError::CTPolicyNotMet(why.to_string())))
+    };
+
+    let mut policy = CTPolicy::new();
+    policy.scts_by_lifetime = vec![ (u64::max_value(), 2) ];
+    for result in run(&[&alice, &bob], policy.clone()) {
+        assert_eq!(result, Ok(()));
+    }
+    for result in run(&[&alice, &alice2], policy.clone()) {
+        assert_eq!(result, not_met("2 SCTs from 1 log operators, but 2 from 2 are required"));
+    }
+    for result in run(&[&alice, &alice, &bob], policy.clone()) {
+        assert_eq!(result, Ok(()));
+    }
+
+    // test-ca's certificates are valid for over five years.
+    for result in run(&[&alice, &bob, &alice2], CTPolicy::new()) {
+        assert_eq!(result, not_met("3 SCTs from 2 log operators, but 5 from 2 are required"));
+    }
+
+    policy.accept_tls_extension = false;
+    for result in run(&[&alice, &bob], policy.clone()) {
+        assert_eq!(result, not_met("0 SCTs from 0 log operators, but 2 from 2 are required"));
+    }
+}
+
 #[cfg(feature = "quic")]
 mod test_quic {
     use super::*;
@@ -1827,7 +3473,7 @@ mod test_quic {
 
         // full handshake
         let mut client =
-            ClientSession::new_quic(&client_config, dns_name("localhost"), client_params.into());
+            ClientSession::new_quic(&client_config, server_name("localhost"), client_params.into());
         let mut server = ServerSession::new_quic(&server_config, server_params.into());
         let client_initial = step(&mut client, &mut server).unwrap();
         assert!(client_initial.is_none());
@@ -1882,7 +3528,7 @@ mod test_quic {
 
         // 0-RTT handshake
         let mut client =
-            ClientSession::new_quic(&client_config, dns_name("localhost"), client_params.into());
+            ClientSession::new_quic(&client_config, server_name("localhost"), client_params.into());
         assert!(client.get_negotiated_ciphersuite().is_some());
         let mut server = ServerSession::new_quic(&server_config, server_params.into());
         step(&mut client, &mut server).unwrap();
@@ -1902,7 +3548,7 @@ mod test_quic {
             let mut client_config = (*client_config).clone();
             client_config.alpn_protocols = vec!["foo".into()];
             let mut client =
-                ClientSession::new_quic(&Arc::new(client_config), dns_name("localhost"), client_params.into());
+                ClientSession::new_quic(&Arc::new(client_config), server_name("localhost"), client_params.into());
             let mut server = ServerSession::new_quic(&server_config, server_params.into());
             step(&mut client, &mut server).unwrap();
             assert_eq!(client.get_quic_transport_parameters(), Some(server_params));
@@ -1914,10 +3560,42 @@ mod test_quic {
             assert!(!client.is_early_data_accepted());
         }
 
+        // 0-RTT handshake, from a new process with the session in a file
+        {
+            let dir = tempfile::TempDir::new().unwrap();
+            let path = dir.path().join("sessions");
+            let new_process = || {
+                let mut client_config = (*client_config).clone();
+                client_config.session_persistence = ClientSessionFileCache::new(&path, 32);
+                Arc::new(client_config)
+            };
+
+            let mut client =
+                ClientSession::new_quic(&new_process(), server_name("localhost"), client_params.into());
+            let mut server = ServerSession::new_quic(&server_config, server_params.into());
+            step(&mut client, &mut server).unwrap();
+            assert!(client.get_early_secret().is_none());
+            step(&mut server, &mut client).unwrap().unwrap();
+            step(&mut client, &mut server).unwrap().unwrap();
+            step(&mut server, &mut client).unwrap().unwrap();
+            step(&mut client, &mut server).unwrap().unwrap();
+            assert!(step(&mut server, &mut client).unwrap().is_none());
+
+            let mut client =
+                ClientSession::new_quic(&new_process(), server_name("localhost"), client_params.into());
+            let mut server = ServerSession::new_quic(&server_config, server_params.into());
+            step(&mut client, &mut server).unwrap();
+            assert!(client.get_early_secret().is_some());
+            step(&mut server, &mut client).unwrap().unwrap();
+            step(&mut client, &mut server).unwrap().unwrap();
+            step(&mut server, &mut client).unwrap().unwrap();
+            assert!(client.is_early_data_accepted());
+        }
+
         // failed handshake
         let mut client = ClientSession::new_quic(
             &client_config,
-            dns_name("example.com"),
+            server_name("example.com"),
             client_params.into(),
         );
         let mut server = ServerSession::new_quic(&server_config, server_params.into());
@@ -1947,7 +3625,7 @@ mod test_quic {
             let server_config = Arc::new(server_config);
 
             let mut client = ClientSession::new_quic(&client_config,
-                                                     dns_name("localhost"),
+                                                     server_name("localhost"),
                                                      client_params.into());
             let mut server = ServerSession::new_quic(&server_config,
                                                      server_params.into());
@@ -2019,7 +3697,7 @@ fn test_client_mtu_reduction() {
         let mut client_config = make_client_config(*kt);
         client_config.set_mtu(&Some(64));
 
-        let mut client = ClientSession::new(&Arc::new(client_config), dns_name("localhost"));
+        let mut client = ClientSession::new(&Arc::new(client_config), server_name("localhost"));
         let writes = collect_write_lengths(&mut client);
         assert!(writes.iter().all(|x| *x <= 64));
         assert!(writes.len() > 1);
@@ -2066,6 +3744,398 @@ fn exercise_key_log_file_for_server() {
     }
 }
 
+/// A crypto provider which defers to *ring*, except that it
+/// lacks ChaCha20-Poly1305.  It counts the random bytes it makes.
+///
+/// If `claims_chacha` is set, it says it supports every suite
+/// anyway.
+#[derive(Debug)]
+struct NoChaChaProvider {
+    random_bytes: AtomicUsize,
+    claims_chacha: bool,
+}
+
+impl CryptoProvider for NoChaChaProvider {
+    fn fill_random(&self, bytes: &mut [u8]) -> Result<(), GetRandomFailed> {
+        self.random_bytes.fetch_add(bytes.len(), Ordering::SeqCst);
+        crypto::ring::RING.fill_random(bytes)
+    }
+
+    fn hash(&self, alg: HashAlgorithm) -> Option<&'static dyn Hash> {
+        crypto::ring::RING.hash(alg)
+    }
+
+    fn hmac(&self, alg: HashAlgorithm) -> Option<&'static dyn Hmac> {
+        crypto::ring::RING.hmac(alg)
+    }
+
+    fn aead(&self, alg: BulkAlgorithm) -> Option<&'static dyn Aead> {
+        match alg {
+            BulkAlgorithm::CHACHA20_POLY1305 => None,
+            _ => crypto::ring::RING.aead(alg),
+        }
+    }
+
+    fn default_kx_groups(&self) -> Vec<&'static dyn SupportedKxGroup> {
+        crypto::ring::RING.default_kx_groups()
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        crypto::ring::RING.supported_verify_schemes()
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &Certificate,
+                              scheme: SignatureScheme, sig: &[u8]) -> Result<(), TLSError> {
+        crypto::ring::RING.verify_tls12_signature(message, cert, scheme, sig)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &Certificate,
+                              scheme: SignatureScheme, sig: &[u8]) -> Result<(), TLSError> {
+        crypto::ring::RING.verify_tls13_signature(message, cert, scheme, sig)
+    }
+
+    fn supports_suite(&self, suite: &SupportedCipherSuite) -> bool {
+        self.claims_chacha || suite.bulk != BulkAlgorithm::CHACHA20_POLY1305
+    }
+}
+
+fn leak_no_chacha_provider() -> &'static NoChaChaProvider {
+    Box::leak(Box::new(NoChaChaProvider { random_bytes: AtomicUsize::new(0), claims_chacha: false }))
+}
+
+#[test]
+fn config_ciphersuites_follow_crypto_provider() {
+    let provider = leak_no_chacha_provider();
+    let is_chacha = |scs: &&SupportedCipherSuite| scs.bulk == BulkAlgorithm::CHACHA20_POLY1305;
+
+    let client_config = ClientConfig::with_crypto_provider(provider);
+    assert!(!client_config.ciphersuites.is_empty());
+    assert!(!client_config.ciphersuites.iter().any(is_chacha));
+
+    let server_config = ServerConfig::with_crypto_provider(rustls::NoClientAuth::new(), provider);
+    assert!(!server_config.ciphersuites.is_empty());
+    assert!(!server_config.ciphersuites.iter().any(is_chacha));
+
+    assert!(ClientConfig::new().ciphersuites.iter().any(is_chacha));
+}
+
+#[test]
+fn handshake_uses_crypto_provider() {
+    for version in &[ ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3 ] {
+        let client_provider = leak_no_chacha_provider();
+        let server_provider = leak_no_chacha_provider();
+
+        let mut client_config = make_client_config(KeyType::RSA);
+        client_config.versions = vec![ *version ];
+        client_config.crypto_provider = client_provider;
+        let mut server_config = make_server_config(KeyType::RSA);
+        server_config.crypto_provider = server_provider;
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+
+        let suite = client.get_negotiated_ciphersuite().unwrap();
+        assert_ne!(suite.bulk, BulkAlgorithm::CHACHA20_POLY1305);
+        assert_eq!(server.get_negotiated_ciphersuite().unwrap().suite, suite.suite);
+        assert!(client_provider.random_bytes.load(Ordering::SeqCst) >= 32);
+        assert!(server_provider.random_bytes.load(Ordering::SeqCst) >= 32);
+
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+    }
+}
+
+#[test]
+fn suites_unsupported_by_crypto_provider_are_not_negotiated() {
+    let chacha_suites = ALL_CIPHERSUITES.iter()
+        .filter(|scs| scs.bulk == BulkAlgorithm::CHACHA20_POLY1305)
+        .cloned()
+        .collect();
+
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.ciphersuites = chacha_suites;
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.crypto_provider = leak_no_chacha_provider();
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::PeerIncompatibleError("no ciphersuites in common".to_string()))));
+}
+
+#[test]
+fn crypto_provider_lacking_negotiated_suite_is_an_error() {
+    for version in &[ ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3 ] {
+        let chacha_suites = ALL_CIPHERSUITES.iter()
+            .filter(|scs| scs.bulk == BulkAlgorithm::CHACHA20_POLY1305)
+            .cloned()
+            .collect();
+
+        let mut client_config = make_client_config(KeyType::RSA);
+        client_config.versions = vec![ *version ];
+        client_config.ciphersuites = chacha_suites;
+        client_config.crypto_provider =
+            Box::leak(Box::new(NoChaChaProvider { random_bytes: AtomicUsize::new(0),
+                                                  claims_chacha: true }));
+
+        let (mut client, mut server) = make_pair_for_configs(client_config,
+                                                             make_server_config(KeyType::RSA));
+        assert_eq!(do_handshake_until_error(&mut client, &mut server),
+                   Err(TLSErrorFromPeer::Client(
+                       TLSError::General("ciphersuite not supported by crypto provider".to_string()))));
+    }
+}
+
+/// AES-128-GCM record protection implemented outside rustls, as a
+/// hardware AEAD would be.  It counts the records it protects.
+#[derive(Debug)]
+struct ExternalAesGcm {
+    records: AtomicUsize,
+}
+
+static EXTERNAL_AES_GCM: ExternalAesGcm = ExternalAesGcm { records: AtomicUsize::new(0) };
+
+struct ExternalAesGcmCipher {
+    key: Box<dyn AeadKey>,
+    iv: [u8; 12],
+    tls13: bool,
+}
+
+impl ExternalAesGcmCipher {
+    fn new(key: &[u8], iv: &[u8], tls13: bool) -> ExternalAesGcmCipher {
+        let mut ret = ExternalAesGcmCipher {
+            key: crypto::ring::RING.aead(BulkAlgorithm::AES_128_GCM).unwrap().new_key(key),
+            iv: [0u8; 12],
+            tls13,
+        };
+        ret.iv[..iv.len()].copy_from_slice(iv);
+        ret
+    }
+
+    fn nonce(&self, seq: u64) -> [u8; 12] {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[4..].iter_mut().zip(seq.to_be_bytes().iter()) {
+            *n ^= s;
+        }
+        nonce
+    }
+
+    fn tls12_aad(seq: u64, typ: ContentType, vers: ProtocolVersion, len: usize) -> [u8; 13] {
+        let mut aad = [0u8; 13];
+        codec::put_u64(seq, &mut aad[..8]);
+        aad[8] = typ.get_u8();
+        codec::put_u16(vers.get_u16(), &mut aad[9..11]);
+        codec::put_u16(len as u16, &mut aad[11..]);
+        aad
+    }
+
+    fn tls13_aad(len: usize) -> [u8; 5] {
+        let mut aad = [0x17, 0x03, 0x03, 0, 0];
+        codec::put_u16(len as u16, &mut aad[3..]);
+        aad
+    }
+}
+
+impl MessageEncrypter for ExternalAesGcmCipher {
+    fn encrypt(&self, msg: BorrowMessage, seq: u64) -> Result<Message, TLSError> {
+        EXTERNAL_AES_GCM.records.fetch_add(1, Ordering::SeqCst);
+        let nonce = self.nonce(seq);
+
+        if self.tls13 {
+            let mut payload = msg.payload.to_vec();
+            payload.push(msg.typ.get_u8());
+            let aad = Self::tls13_aad(payload.len() + 16);
+            let tag = self.key.seal_in_place_separate_tag(nonce, &aad, &mut payload)?;
+            payload.extend_from_slice(&tag);
+
+            Ok(Message {
+                typ: ContentType::ApplicationData,
+                version: ProtocolVersion::TLSv1_2,
+                payload: MessagePayload::new_opaque(payload),
+            })
+        } else {
+            let aad = Self::tls12_aad(seq, msg.typ, msg.version, msg.payload.len());
+            let mut payload = nonce[4..].to_vec();
+            payload.extend_from_slice(msg.payload);
+            let tag = self.key.seal_in_place_separate_tag(nonce, &aad, &mut payload[8..])?;
+            payload.extend_from_slice(&tag);
+
+            Ok(Message {
+                typ: msg.typ,
+                version: msg.version,
+                payload: MessagePayload::new_opaque(payload),
+            })
+        }
+    }
+}
+
+impl MessageDecrypter for ExternalAesGcmCipher {
+    fn decrypt(&self, mut msg: Message, seq: u64) -> Result<Message, TLSError> {
+        EXTERNAL_AES_GCM.records.fetch_add(1, Ordering::SeqCst);
+        let mut payload = msg.take_opaque_payload().ok_or(TLSError::DecryptError)?.0;
+
+        if self.tls13 {
+            let aad = Self::tls13_aad(payload.len());
+            let len = self.key.open_in_place(self.nonce(seq), &aad, &mut payload)?;
+            payload.truncate(len);
+            while payload.last() == Some(&0) {
+                payload.pop();
+            }
+            let typ = ContentType::read_bytes(&payload[payload.len() - 1..])
+                .ok_or(TLSError::DecryptError)?;
+            payload.pop();
+
+            Ok(Message {
+                typ,
+                version: ProtocolVersion::TLSv1_3,
+                payload: MessagePayload::new_opaque(payload),
+            })
+        } else {
+            if payload.len() < 24 {
+                return Err(TLSError::DecryptError);
+            }
+            let mut nonce = self.iv;
+            nonce[4..].copy_from_slice(&payload[..8]);
+            let aad = Self::tls12_aad(seq, msg.typ, msg.version, payload.len() - 24);
+            let len = self.key.open_in_place(nonce, &aad, &mut payload[8..])?;
+            payload.drain(..8);
+            payload.truncate(len);
+
+            Ok(Message {
+                typ: msg.typ,
+                version: msg.version,
+                payload: MessagePayload::new_opaque(payload),
+            })
+        }
+    }
+}
+
+impl MessageCipherFactory for ExternalAesGcm {
+    fn new_tls12_decrypter(&self, key: &[u8], iv: &[u8]) -> Box<dyn MessageDecrypter> {
+        Box::new(ExternalAesGcmCipher::new(key, iv, false))
+    }
+
+    fn new_tls12_encrypter(&self, key: &[u8], iv: &[u8], extra: &[u8]) -> Box<dyn MessageEncrypter> {
+        let mut full_iv = iv.to_vec();
+        full_iv.extend_from_slice(extra);
+        Box::new(ExternalAesGcmCipher::new(key, &full_iv, false))
+    }
+
+    fn new_tls13_decrypter(&self, key: &[u8], iv: &[u8]) -> Box<dyn MessageDecrypter> {
+        Box::new(ExternalAesGcmCipher::new(key, iv, true))
+    }
+
+    fn new_tls13_encrypter(&self, key: &[u8], iv: &[u8]) -> Box<dyn MessageEncrypter> {
+        Box::new(ExternalAesGcmCipher::new(key, iv, true))
+    }
+}
+
+static EXTERNAL_TLS13_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite {
+    suite: CipherSuite::TLS13_AES_128_GCM_SHA256,
+    kx: KeyExchangeAlgorithm::BulkOnly,
+    sign: SignatureAlgorithm::Anonymous,
+    bulk: BulkAlgorithm::Custom,
+    hash: HashAlgorithm::SHA256,
+    enc_key_len: 16,
+    fixed_iv_len: 12,
+    explicit_nonce_len: 0,
+    cipher_factory: Some(&EXTERNAL_AES_GCM),
+};
+
+static EXTERNAL_TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite {
+    suite: CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+    kx: KeyExchangeAlgorithm::ECDHE,
+    sign: SignatureAlgorithm::RSA,
+    bulk: BulkAlgorithm::Custom,
+    hash: HashAlgorithm::SHA256,
+    enc_key_len: 16,
+    fixed_iv_len: 4,
+    explicit_nonce_len: 8,
+    cipher_factory: Some(&EXTERNAL_AES_GCM),
+};
+
+#[test]
+fn external_ciphersuite_interoperates_with_builtin() {
+    let external_suites = [
+        (&EXTERNAL_TLS13_AES_128_GCM_SHA256, ProtocolVersion::TLSv1_3),
+        (&EXTERNAL_TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256, ProtocolVersion::TLSv1_2),
+    ];
+
+    for &(suite, version) in external_suites.iter() {
+        for &external_client in &[ true, false ] {
+            let mut client_config = make_client_config(KeyType::RSA);
+            client_config.versions = vec![ version ];
+            let mut server_config = make_server_config(KeyType::RSA);
+            if external_client {
+                client_config.ciphersuites = vec![ suite ];
+            } else {
+                server_config.ciphersuites = vec![ suite ];
+            }
+
+            let before = EXTERNAL_AES_GCM.records.load(Ordering::SeqCst);
+            let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+            do_handshake(&mut client, &mut server);
+            assert_eq!(client.get_negotiated_ciphersuite().unwrap().suite, suite.suite);
+            assert_eq!(server.get_negotiated_ciphersuite().unwrap().suite, suite.suite);
+
+            client.write_all(b"hello").unwrap();
+            transfer(&mut client, &mut server);
+            server.process_new_packets().unwrap();
+            check_read(&mut server, b"hello");
+
+            server.write_all(b"world").unwrap();
+            transfer(&mut server, &mut client);
+            client.process_new_packets().unwrap();
+            check_read(&mut client, b"world");
+
+            assert!(EXTERNAL_AES_GCM.records.load(Ordering::SeqCst) > before);
+        }
+    }
+}
+
+#[test]
+fn external_ciphersuite_is_negotiated_like_builtins() {
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.ciphersuites = vec![ &EXTERNAL_TLS13_AES_128_GCM_SHA256 ];
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.ciphersuites = vec![ &EXTERNAL_TLS13_AES_128_GCM_SHA256 ];
+
+    // Not supported by the crypto provider's AEADs, but still usable.
+    assert!(crypto::ring::RING.aead(BulkAlgorithm::Custom).is_none());
+    assert!(crypto::ring::RING.supports_suite(&EXTERNAL_TLS13_AES_128_GCM_SHA256));
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.get_negotiated_ciphersuite(), Some(&EXTERNAL_TLS13_AES_128_GCM_SHA256));
+}
+
+static NO_FACTORY_TLS13_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite {
+    suite: CipherSuite::TLS13_AES_128_GCM_SHA256,
+    kx: KeyExchangeAlgorithm::BulkOnly,
+    sign: SignatureAlgorithm::Anonymous,
+    bulk: BulkAlgorithm::Custom,
+    hash: HashAlgorithm::SHA256,
+    enc_key_len: 16,
+    fixed_iv_len: 12,
+    explicit_nonce_len: 0,
+    cipher_factory: None,
+};
+
+#[test]
+fn custom_ciphersuite_without_factory_is_not_negotiated() {
+    assert!(!crypto::ring::RING.supports_suite(&NO_FACTORY_TLS13_AES_128_GCM_SHA256));
+
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.ciphersuites = vec![ &NO_FACTORY_TLS13_AES_128_GCM_SHA256 ];
+    let mut server_config = make_server_config(KeyType::RSA);
+    server_config.ciphersuites = vec![ &NO_FACTORY_TLS13_AES_128_GCM_SHA256 ];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server),
+               Err(TLSErrorFromPeer::Server(
+                   TLSError::PeerIncompatibleError("no ciphersuites in common".to_string()))));
+}
+
 fn assert_lt(left: usize, right: usize) {
     if left >= right {
         panic!("expected {} < {}", left, right);
@@ -12,9 +12,12 @@ use rustls::{ServerConfig, ServerSession};
 use rustls::Session;
 use rustls::ProtocolVersion;
 use rustls::TLSError;
+use rustls::ServerName;
 use rustls::{Certificate, PrivateKey};
 use rustls::internal::pemfile;
 use rustls::{RootCertStore, NoClientAuth, AllowAnyAuthenticatedClient};
+#[cfg(feature = "esni")]
+use rustls::esni::ESNIPrivateKey;
 
 use webpki;
 
@@ -42,6 +45,7 @@ macro_rules! embed_files {
             let dir = tempfile::TempDir::new().unwrap();
 
             fs::create_dir(dir.path().join("ecdsa")).unwrap();
+            fs::create_dir(dir.path().join("eddsa")).unwrap();
             fs::create_dir(dir.path().join("rsa")).unwrap();
 
             $(
@@ -74,6 +78,23 @@ embed_files! {
     (ECDSA_NISTP256_PEM, "ecdsa", "nistp256.pem");
     (ECDSA_NISTP384_PEM, "ecdsa", "nistp384.pem");
 
+    (EDDSA_CA_CERT, "eddsa", "ca.cert");
+    (EDDSA_CA_DER, "eddsa", "ca.der");
+    (EDDSA_CA_KEY, "eddsa", "ca.key");
+    (EDDSA_CLIENT_CERT, "eddsa", "client.cert");
+    (EDDSA_CLIENT_CHAIN, "eddsa", "client.chain");
+    (EDDSA_CLIENT_FULLCHAIN, "eddsa", "client.fullchain");
+    (EDDSA_CLIENT_KEY, "eddsa", "client.key");
+    (EDDSA_CLIENT_REQ, "eddsa", "client.req");
+    (EDDSA_END_CERT, "eddsa", "end.cert");
+    (EDDSA_END_CHAIN, "eddsa", "end.chain");
+    (EDDSA_END_FULLCHAIN, "eddsa", "end.fullchain");
+    (EDDSA_END_KEY, "eddsa", "end.key");
+    (EDDSA_END_REQ, "eddsa", "end.req");
+    (EDDSA_INTER_CERT, "eddsa", "inter.cert");
+    (EDDSA_INTER_KEY, "eddsa", "inter.key");
+    (EDDSA_INTER_REQ, "eddsa", "inter.req");
+
     (RSA_CA_CERT, "rsa", "ca.cert");
     (RSA_CA_DER, "rsa", "ca.der");
     (RSA_CA_KEY, "rsa", "ca.key");
@@ -124,16 +145,18 @@ pub fn transfer(left: &mut dyn Session, right: &mut dyn Session) -> usize {
 #[derive(Clone, Copy)]
 pub enum KeyType {
     RSA,
-    ECDSA
+    ECDSA,
+    ED25519
 }
 
-pub static ALL_KEY_TYPES: [KeyType; 2] = [ KeyType::RSA, KeyType::ECDSA ];
+pub static ALL_KEY_TYPES: [KeyType; 3] = [ KeyType::RSA, KeyType::ECDSA, KeyType::ED25519 ];
 
 impl KeyType {
     fn bytes_for(&self, part: &str) -> &'static [u8] {
         match self {
             KeyType::RSA => bytes_for("rsa", part),
             KeyType::ECDSA => bytes_for("ecdsa", part),
+            KeyType::ED25519 => bytes_for("eddsa", part),
         }
     }
 
@@ -148,12 +171,12 @@ impl KeyType {
             .clone()
     }
 
-    fn get_client_chain(&self) -> Vec<Certificate> {
+    pub fn get_client_chain(&self) -> Vec<Certificate> {
         pemfile::certs(&mut io::BufReader::new(self.bytes_for("client.fullchain")))
             .unwrap()
     }
 
-    fn get_client_key(&self) -> PrivateKey {
+    pub fn get_client_key(&self) -> PrivateKey {
         pemfile::pkcs8_private_keys(&mut io::BufReader::new(self.bytes_for("client.key")))
                 .unwrap()[0]
             .clone()
@@ -210,7 +233,7 @@ pub fn make_pair_for_configs(client_config: ClientConfig,
 pub fn make_pair_for_arc_configs(client_config: &Arc<ClientConfig>,
                                  server_config: &Arc<ServerConfig>) -> (ClientSession, ServerSession) {
     (
-        ClientSession::new(client_config, dns_name("localhost")),
+        ClientSession::new(client_config, server_name("localhost")),
         ServerSession::new(server_config)
     )
 }
@@ -280,6 +303,32 @@ pub fn dns_name(name: &'static str) -> webpki::DNSNameRef<'_> {
     webpki::DNSNameRef::try_from_ascii_str(name).unwrap()
 }
 
+pub fn server_name(name: &'static str) -> ServerName {
+    ServerName::try_from_ascii_str(name).unwrap()
+}
+
+/// Make the bytes of an ESNIKeys record for `key`, valid forever,
+/// offering TLS13_AES_128_GCM_SHA256 and padding names to 260 bytes.
+#[cfg(feature = "esni")]
+pub fn make_esni_record(key: &ESNIPrivateKey) -> Vec<u8> {
+    let public_key = key.get_public_key();
+
+    let mut record = vec![ 0xff, 0x01, 0x00, 0x00, 0x00, 0x00 ];
+    record.extend_from_slice(&((public_key.len() + 4) as u16).to_be_bytes());
+    record.extend_from_slice(&key.get_group().get_u16().to_be_bytes());
+    record.extend_from_slice(&(public_key.len() as u16).to_be_bytes());
+    record.extend_from_slice(&public_key);
+    record.extend_from_slice(&[ 0x00, 0x02, 0x13, 0x01 ]);
+    record.extend_from_slice(&260u16.to_be_bytes());
+    record.extend_from_slice(&0u64.to_be_bytes());
+    record.extend_from_slice(&u64::max_value().to_be_bytes());
+    record.extend_from_slice(&[ 0x00, 0x00 ]);
+
+    let checksum = ring::digest::digest(&ring::digest::SHA256, &record);
+    record[2..6].copy_from_slice(&checksum.as_ref()[..4]);
+    record
+}
+
 pub struct FailsReads {
     errkind: io::ErrorKind
 }
@@ -2,8 +2,12 @@
 
 set -xe
 
-rm -rf rsa/ ecdsa/
-mkdir -p rsa/ ecdsa/
+# All three PKIs are made together, so they share validity dates.
+# OpenSSL 3 writes PKCS#8 unless asked, hence -traditional for the
+# PKCS#1 *.rsa files.
+
+rm -rf rsa/ ecdsa/ eddsa/
+mkdir -p rsa/ ecdsa/ eddsa/
 
 openssl req -nodes \
           -x509 \
@@ -32,6 +36,7 @@ openssl req -nodes \
           -subj "/CN=testserver.com"
 
 openssl rsa \
+          -traditional \
           -in rsa/end.key \
           -out rsa/end.rsa
 
@@ -44,6 +49,7 @@ openssl req -nodes \
           -subj "/CN=ponytown client"
 
 openssl rsa \
+          -traditional \
           -in rsa/client.key \
           -out rsa/client.rsa
 
@@ -88,7 +94,41 @@ openssl req -nodes \
           -days 2000 \
           -subj "/CN=ponytown client"
 
-for kt in rsa ecdsa ; do
+# eddsa
+openssl req -nodes \
+          -x509 \
+          -newkey ed25519 \
+          -keyout eddsa/ca.key \
+          -out eddsa/ca.cert \
+          -batch \
+          -days 3650 \
+          -subj "/CN=ponytown EdDSA CA"
+
+openssl req -nodes \
+          -newkey ed25519 \
+          -keyout eddsa/inter.key \
+          -out eddsa/inter.req \
+          -batch \
+          -days 3000 \
+          -subj "/CN=ponytown EdDSA level 2 intermediate"
+
+openssl req -nodes \
+          -newkey ed25519 \
+          -keyout eddsa/end.key \
+          -out eddsa/end.req \
+          -batch \
+          -days 2000 \
+          -subj "/CN=testserver.com"
+
+openssl req -nodes \
+          -newkey ed25519 \
+          -keyout eddsa/client.key \
+          -out eddsa/client.req \
+          -batch \
+          -days 2000 \
+          -subj "/CN=ponytown client"
+
+for kt in rsa ecdsa eddsa ; do
   openssl x509 -req \
             -in $kt/inter.req \
             -out $kt/inter.cert \